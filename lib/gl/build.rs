@@ -7,7 +7,9 @@ fn main() {
     let out_dir = env::var("OUT_DIR").unwrap();
     let mut file = File::create(&Path::new(&out_dir).join("bindings.rs")).unwrap();
 
-    Registry::new(Api::Gl, (4, 6), Profile::Core, Fallbacks::All, [])
+    // S3TC isn't core, but every desktop driver has it
+    let extensions = ["GL_EXT_texture_compression_s3tc", "GL_EXT_texture_sRGB"];
+    Registry::new(Api::Gl, (4, 6), Profile::Core, Fallbacks::All, extensions)
         .write_bindings(GlobalGenerator, &mut file)
         .expect("Couldn't generate GL bindings");
 }
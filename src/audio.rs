@@ -0,0 +1,193 @@
+//! Ambient sound emitters placed on the map. Every emitter loops its sound and
+//! gets quieter away from its center, silent past its radius. The editor only
+//! plays them while previewing.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader};
+
+use glam::{Vec2, Vec3, Vec3Swizzles};
+use rodio::decoder::DecoderError;
+use rodio::{Decoder, OutputStream, OutputStreamHandle, PlayError, Sink, Source, StreamError};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum AudioError {
+    #[error("No audio output: {0}")]
+    Stream(#[from] StreamError),
+    #[error("Couldn't play: {0}")]
+    Play(#[from] PlayError),
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Unsupported sound: {0}")]
+    Decoder(#[from] DecoderError),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EmitterKind {
+    Wind,
+    River,
+    Birds,
+}
+
+impl EmitterKind {
+    pub const ALL: [EmitterKind; 3] = [EmitterKind::Wind, EmitterKind::River, EmitterKind::Birds];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            EmitterKind::Wind => "Wind",
+            EmitterKind::River => "River",
+            EmitterKind::Birds => "Birds",
+        }
+    }
+
+    pub fn default_sound(&self) -> &'static str {
+        match self {
+            EmitterKind::Wind => "sounds/wind.ogg",
+            EmitterKind::River => "sounds/river.ogg",
+            EmitterKind::Birds => "sounds/birds.ogg",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Emitter {
+    /// Stays the same when other emitters are removed
+    id: u32,
+    pub kind: EmitterKind,
+    pub sound_path: String,
+    pub position: Vec3,
+    /// Meters, silent beyond
+    pub radius: f32,
+    pub volume: f32,
+}
+
+impl Emitter {
+    /// How loud it is heard at `listener`, 1 at the center fading to 0 at the radius
+    fn attenuation(&self, listener: Vec3) -> f32 {
+        let t = (self.position.distance(listener) / self.radius.max(0.01)).min(1.0);
+        (1.0 - t) * (1.0 - t)
+    }
+}
+
+pub struct AudioSettings {
+    pub emitters: Vec<Emitter>,
+    /// The emitter the sliders edit
+    pub selected: Option<usize>,
+    /// What the next placed emitter will be
+    pub kind: EmitterKind,
+    pub sound_path: String,
+    pub preview: bool,
+    pub master_volume: f32,
+    next_id: u32,
+}
+
+impl Default for AudioSettings {
+    fn default() -> Self {
+        AudioSettings {
+            emitters: vec![],
+            selected: None,
+            kind: EmitterKind::Wind,
+            sound_path: EmitterKind::Wind.default_sound().to_owned(),
+            preview: false,
+            master_volume: 1.0,
+            next_id: 0,
+        }
+    }
+}
+
+impl AudioSettings {
+    pub fn add_emitter(&mut self, position: Vec3, radius: f32) {
+        self.emitters.push(Emitter {
+            id: self.next_id,
+            kind: self.kind,
+            sound_path: self.sound_path.clone(),
+            position,
+            radius,
+            volume: 1.0,
+        });
+        self.next_id += 1;
+        self.selected = Some(self.emitters.len() - 1);
+    }
+
+    /// Removes the emitter closest to `point` on the ground plane if it's within its radius
+    pub fn remove_emitter_at(&mut self, point: Vec2) {
+        let distance = |emitter: &Emitter| emitter.position.xz().distance(point);
+        let closest = self
+            .emitters
+            .iter()
+            .enumerate()
+            .filter(|(_, emitter)| distance(emitter) <= emitter.radius)
+            .min_by(|(_, a), (_, b)| distance(a).total_cmp(&distance(b)))
+            .map(|(i, _)| i);
+        if let Some(index) = closest {
+            self.remove_emitter(index);
+        }
+    }
+
+    pub fn remove_emitter(&mut self, index: usize) {
+        self.emitters.remove(index);
+        self.selected = None;
+    }
+}
+
+/// Sound path and the sink playing it, None if it couldn't be loaded
+type Voice = (String, Option<Sink>);
+
+pub struct Audio {
+    // Has to stay alive for anything to play
+    _stream: OutputStream,
+    handle: OutputStreamHandle,
+    voices: HashMap<u32, Voice>,
+}
+
+impl Audio {
+    pub fn new() -> Result<Self, AudioError> {
+        let (stream, handle) = OutputStream::try_default()?;
+        Ok(Audio {
+            _stream: stream,
+            handle,
+            voices: HashMap::new(),
+        })
+    }
+
+    /// Starts and stops voices to match the emitters and sets their volume for
+    /// where the listener is. Sounds that fail to load are reported once.
+    pub fn update(&mut self, settings: &AudioSettings, listener: Vec3) {
+        self.voices
+            .retain(|id, _| settings.emitters.iter().any(|emitter| emitter.id == *id));
+        for emitter in &settings.emitters {
+            let reload = match self.voices.get(&emitter.id) {
+                Some((path, _)) => *path != emitter.sound_path,
+                None => true,
+            };
+            if reload {
+                let sink = match self.play_looped(&emitter.sound_path) {
+                    Ok(sink) => Some(sink),
+                    Err(error) => {
+                        eprintln!("Couldn't play {}: {}", emitter.sound_path, error);
+                        None
+                    }
+                };
+                self.voices
+                    .insert(emitter.id, (emitter.sound_path.clone(), sink));
+            }
+            if let Some((_, Some(sink))) = self.voices.get(&emitter.id) {
+                if settings.preview {
+                    let volume = emitter.attenuation(listener) * emitter.volume;
+                    sink.set_volume(volume * settings.master_volume);
+                    sink.play();
+                } else {
+                    sink.pause();
+                }
+            }
+        }
+    }
+
+    fn play_looped(&self, path: &str) -> Result<Sink, AudioError> {
+        let sink = Sink::try_new(&self.handle)?;
+        let source = Decoder::new(BufReader::new(File::open(path)?))?;
+        sink.append(source.repeat_infinite());
+        Ok(sink)
+    }
+}
@@ -0,0 +1,99 @@
+//! Basis Universal textures (`.basis`, ETC1S or UASTC), which are made once and then
+//! transcoded to whatever the GPU takes: BC7 where there's BPTC, BC3 elsewhere.
+//! Transcoding takes a while, so it's done on the IO thread and the result is kept in
+//! the derived data cache as KTX2 for the next time the same texture is imported.
+//!
+//! The transcoder is C++ and behind the `basis` feature.
+
+use std::fs;
+use std::path::Path;
+
+use crate::block_texture::{self, BlockFormat, CompressedImage};
+use crate::derived_cache::{self, ContentHash};
+use crate::opengl::caps;
+
+const CACHE_KIND: &str = "basis";
+/// Goes up whenever transcoding changes, so older results aren't read back
+const CACHE_VERSION: u64 = 1;
+
+/// What Basis textures are transcoded to on this GPU, asked on the GL thread
+pub fn target_format() -> BlockFormat {
+    if caps::get().texture_bptc {
+        BlockFormat::Bc7
+    } else {
+        BlockFormat::Bc3
+    }
+}
+
+/// From the cache if it's been transcoded to `format` before
+pub fn load(path: &Path, format: BlockFormat) -> Result<CompressedImage, String> {
+    let bytes = fs::read(path).map_err(|e| e.to_string())?;
+    let hash = ContentHash::new()
+        .with_u64(CACHE_VERSION)
+        .with_bytes(format.name().as_bytes())
+        .with_bytes(&bytes);
+    if let Some(image) =
+        derived_cache::get(CACHE_KIND, hash).and_then(|ktx2| block_texture::from_ktx2(&ktx2).ok())
+    {
+        return Ok(image);
+    }
+    let image = transcode(&bytes, format)?;
+    derived_cache::put(CACHE_KIND, hash, &block_texture::to_ktx2(&image));
+    Ok(image)
+}
+
+#[cfg(feature = "basis")]
+fn transcode(bytes: &[u8], format: BlockFormat) -> Result<CompressedImage, String> {
+    use basis_universal::{TranscodeParameters, Transcoder, TranscoderTextureFormat};
+
+    basis_universal::transcoder_init();
+    let mut transcoder = Transcoder::new();
+    if !transcoder.validate_header(bytes) {
+        return Err("Not a Basis Universal file".to_owned());
+    }
+    let target = match format {
+        BlockFormat::Bc1 => TranscoderTextureFormat::BC1_RGB,
+        BlockFormat::Bc3 => TranscoderTextureFormat::BC3_RGBA,
+        BlockFormat::Bc4 => TranscoderTextureFormat::BC4_R,
+        BlockFormat::Bc5 => TranscoderTextureFormat::BC5_RG,
+        BlockFormat::Bc7 => TranscoderTextureFormat::BC7_RGBA,
+    };
+    let description = transcoder
+        .image_level_description(bytes, 0, 0)
+        .ok_or("The file has no images")?;
+    transcoder
+        .prepare_transcoding(bytes)
+        .map_err(|_| "The file is broken".to_owned())?;
+    // Only the first image is used, a texture array or a cubemap isn't expected
+    let levels = (0..transcoder.image_level_count(bytes, 0))
+        .map(|level_index| {
+            let parameters = TranscodeParameters {
+                image_index: 0,
+                level_index,
+                decode_flags: None,
+                output_row_pitch_in_blocks_or_pixels: None,
+                output_rows_in_pixels: None,
+            };
+            transcoder
+                .transcode_image_level(bytes, target, parameters)
+                .map_err(|e| format!("Mip level {} didn't transcode: {:?}", level_index, e))
+        })
+        .collect::<Result<_, String>>();
+    transcoder.end_transcoding();
+    Ok(CompressedImage {
+        format,
+        // Ground textures are color
+        srgb: matches!(
+            format,
+            BlockFormat::Bc1 | BlockFormat::Bc3 | BlockFormat::Bc7
+        ),
+        width: description.original_width as usize,
+        height: description.original_height as usize,
+        levels: levels?,
+    })
+}
+
+#[cfg(not(feature = "basis"))]
+fn transcode(_bytes: &[u8], _format: BlockFormat) -> Result<CompressedImage, String> {
+    Err("This build can't read Basis Universal, it needs the `basis` feature".to_owned())
+}
@@ -0,0 +1,264 @@
+//! `--benchmark <file>` builds the same procedural scene every time, flies the camera
+//! once around it and writes how long frames and each render pass took to a JSON
+//! file, so that performance can be compared between commits. The scene only lives
+//! in memory, nothing of the project is touched, and the editor quits when it's done.
+//!
+//! Pass times come from GPU timestamps taken between the passes, frame times are the
+//! wall clock between frames. Vsync and the FPS cap are off while it runs.
+
+use std::collections::BTreeMap;
+use std::f32::consts::TAU;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use glam::{Vec2, Vec3};
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::heightfield::HeightField;
+use crate::noise;
+use crate::opengl::objects::Query;
+use crate::terrain::TerrainParams;
+
+/// Drawn before timing starts, while shaders compile and caches fill up
+const WARM_UP_FRAMES: usize = 60;
+/// Timed, the camera goes around once in this many
+const FRAMES: usize = 1200;
+/// Timestamps are read this many frames after they're taken, so the CPU doesn't
+/// wait for the GPU
+const QUERY_SETS: usize = 3;
+/// Instances per 100 square meters of the scene's scatter type
+pub const SCATTER_DENSITY: f32 = 0.5;
+
+#[derive(Debug, Error)]
+pub enum BenchmarkError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Couldn't write the report: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// What's timed separately. Each one ends where the next begins, a pass drawn in
+/// every viewport counts them all.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub enum Pass {
+    /// The brush and everything else that runs on the GPU before drawing
+    Editing,
+    Shadows,
+    Terrain,
+    /// Game objects and scattered instances
+    Objects,
+    /// Skybox and lens flare
+    Sky,
+    PostProcess,
+    Gui,
+}
+
+pub fn scene_params() -> TerrainParams {
+    TerrainParams {
+        world_size: 2048.0,
+        resolution: 1024,
+        max_height: 400.0,
+        seed: 1,
+    }
+}
+
+/// Rolling hills with a range of mountains across the middle
+pub fn scene_heights(params: &TerrainParams) -> HeightField {
+    let size = params.resolution as usize;
+    let seed = params.seed;
+    let mut data = Vec::with_capacity(size * size);
+    for y in 0..size {
+        for x in 0..size {
+            let (u, v) = (x as f32 / size as f32, y as f32 / size as f32);
+            let hills = noise::fbm(seed, u * 8.0, v * 8.0, 6);
+            let range = 1.0 - (v - 0.5).abs() * 2.0;
+            let mountains = noise::fbm(seed.wrapping_add(100), u * 3.0, v * 3.0, 6);
+            data.push((hills * 0.3 + mountains * range * range * 0.7).clamp(0.0, 1.0));
+        }
+    }
+    HeightField::from_data(size, data)
+}
+
+/// Position and direction at `t` between 0 and 1 on a circle around the middle,
+/// looking ahead and down over the mountains
+fn camera_at(params: &TerrainParams, t: f32) -> (Vec3, Vec3) {
+    let radius = params.world_size * 0.3;
+    let angle = t * TAU;
+    let around = Vec2::new(angle.cos(), angle.sin()) * radius;
+    let position = Vec3::new(around.x, params.max_height * 0.9, around.y);
+    let ahead = Vec2::new(-angle.sin(), angle.cos()) - around / radius * 0.5;
+    let direction = Vec3::new(ahead.x, -0.35, ahead.y).normalize();
+    (position, direction)
+}
+
+/// Timestamps of one frame, the first taken when it starts
+#[derive(Default)]
+struct QuerySet {
+    queries: Vec<Query>,
+    passes: Vec<Pass>,
+    frame: usize,
+}
+
+impl QuerySet {
+    fn timestamp(&mut self, index: usize) {
+        if self.queries.len() <= index {
+            self.queries
+                .push(Query::new(gl::TIMESTAMP, "Benchmark timestamp"));
+        }
+        unsafe {
+            gl::QueryCounter(self.queries[index].id(), gl::TIMESTAMP);
+        }
+    }
+
+    /// Milliseconds spent in each pass, waiting for the GPU if it isn't done yet
+    fn read(&mut self) -> BTreeMap<Pass, f32> {
+        let mut times = BTreeMap::new();
+        let mut previous = 0;
+        for (index, query) in self.queries[..=self.passes.len()].iter().enumerate() {
+            let mut nanoseconds: u64 = 0;
+            unsafe {
+                gl::GetQueryObjectui64v(query.id(), gl::QUERY_RESULT, &mut nanoseconds);
+            }
+            if index > 0 {
+                let pass = self.passes[index - 1];
+                let elapsed = nanoseconds.saturating_sub(previous) as f32 / 1_000_000.0;
+                *times.entry(pass).or_insert(0.0) += elapsed;
+            }
+            previous = nanoseconds;
+        }
+        self.passes.clear();
+        times
+    }
+}
+
+#[derive(Serialize)]
+struct Timing {
+    average_ms: f32,
+    /// Average of the slowest 1% of the frames
+    worst_1_percent_ms: f32,
+}
+
+impl Timing {
+    fn of(times: &[f32]) -> Self {
+        let mut sorted = times.to_vec();
+        sorted.sort_by(|a, b| b.total_cmp(a));
+        let worst = &sorted[..(sorted.len() / 100).max(1)];
+        let average = |times: &[f32]| times.iter().sum::<f32>() / times.len().max(1) as f32;
+        Timing {
+            average_ms: average(&sorted),
+            worst_1_percent_ms: average(worst),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Report {
+    window_size: (u32, u32),
+    frames: usize,
+    instances: usize,
+    frame: Timing,
+    passes: BTreeMap<Pass, Timing>,
+}
+
+pub struct Benchmark {
+    path: PathBuf,
+    params: TerrainParams,
+    frame: usize,
+    frame_start: Option<Instant>,
+    frame_times: Vec<f32>,
+    pass_times: BTreeMap<Pass, Vec<f32>>,
+    query_sets: Vec<QuerySet>,
+}
+
+impl Benchmark {
+    /// The scene has to be built from `params` already
+    pub fn new(path: PathBuf, params: TerrainParams) -> Self {
+        Benchmark {
+            path,
+            params,
+            frame: 0,
+            frame_start: None,
+            frame_times: vec![],
+            pass_times: BTreeMap::new(),
+            query_sets: (0..QUERY_SETS).map(|_| QuerySet::default()).collect(),
+        }
+    }
+
+    /// Where the camera goes this frame, None once every frame has been timed
+    pub fn begin_frame(&mut self) -> Option<(Vec3, Vec3)> {
+        let now = Instant::now();
+        if let (Some(start), true) = (self.frame_start, self.frame > WARM_UP_FRAMES) {
+            self.frame_times
+                .push(now.duration_since(start).as_secs_f32() * 1000.0);
+        }
+        self.frame_start = Some(now);
+
+        let set = self.frame % QUERY_SETS;
+        self.collect(set);
+        if self.frame >= WARM_UP_FRAMES + FRAMES {
+            for set in 0..QUERY_SETS {
+                self.collect(set);
+            }
+            return None;
+        }
+        let query_set = &mut self.query_sets[set];
+        query_set.frame = self.frame;
+        query_set.timestamp(0);
+
+        let t = self.frame.saturating_sub(WARM_UP_FRAMES) as f32 / FRAMES as f32;
+        self.frame += 1;
+        Some(camera_at(&self.params, t))
+    }
+
+    /// Ends `pass` and starts timing the next one
+    pub fn mark(&mut self, pass: Pass) {
+        let query_set = &mut self.query_sets[(self.frame + QUERY_SETS - 1) % QUERY_SETS];
+        query_set.passes.push(pass);
+        query_set.timestamp(query_set.passes.len());
+    }
+
+    fn collect(&mut self, set: usize) {
+        let query_set = &mut self.query_sets[set];
+        if query_set.passes.is_empty() {
+            return;
+        }
+        let frame = query_set.frame;
+        let times = query_set.read();
+        if frame >= WARM_UP_FRAMES {
+            for (pass, time) in times {
+                self.pass_times.entry(pass).or_default().push(time);
+            }
+        }
+    }
+
+    /// Writes the report, where the window size and instance count go for reference
+    pub fn save(&self, window_size: (u32, u32), instances: usize) -> Result<(), BenchmarkError> {
+        let report = Report {
+            window_size,
+            frames: self.frame_times.len(),
+            instances,
+            frame: Timing::of(&self.frame_times),
+            passes: self
+                .pass_times
+                .iter()
+                .map(|(&pass, times)| (pass, Timing::of(times)))
+                .collect(),
+        };
+        fs::write(&self.path, serde_json::to_string_pretty(&report)?)?;
+        Ok(())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Marks the end of `pass` when there's a benchmark running
+pub fn mark(benchmark: &mut Option<Benchmark>, pass: Pass) {
+    if let Some(benchmark) = benchmark {
+        benchmark.mark(pass);
+    }
+}
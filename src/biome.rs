@@ -0,0 +1,221 @@
+//! Biomes are named bundles of texturing rules, scatter rules and a color tint.
+//! Their weights are painted into a map of their own, the same way texture layers
+//! are, and auto-texturing and auto-scattering follow those weights, so where two
+//! biomes meet their rules blend into each other.
+
+use glam::Vec2;
+
+use crate::noise::{self, smoothstep};
+use crate::scatter_types::{ScatterLayer, ScatterType};
+use crate::splat::{PaintBlendMode, PaintSettings, NUM_LAYERS};
+use crate::terrain::Terrain;
+
+/// The biome map is a splat map, so there's one biome per channel
+pub const MAX_BIOMES: usize = NUM_LAYERS;
+
+/// Meters and degrees over which a texturing rule fades in and out
+const RULE_FEATHER: (f32, f32) = (5.0, 5.0);
+
+/// Hash stream deciding which instances stay, apart from the ones used for placement
+const KEEP_SEED: u32 = 17;
+
+/// Puts a texture layer where the height and slope are in range
+#[derive(Debug, Clone)]
+pub struct SplatRule {
+    pub layer: usize,
+    /// Meters
+    pub min_height: f32,
+    pub max_height: f32,
+    /// Degrees from horizontal
+    pub min_slope: f32,
+    pub max_slope: f32,
+}
+
+impl SplatRule {
+    /// How strongly the rule applies, 0 outside of its ranges
+    fn weight(&self, height: f32, slope: f32) -> f32 {
+        let in_range = |value: f32, min: f32, max: f32, feather: f32| {
+            smoothstep((value - min) / feather + 1.0) * (1.0 - smoothstep((value - max) / feather))
+        };
+        in_range(height, self.min_height, self.max_height, RULE_FEATHER.0)
+            * in_range(slope, self.min_slope, self.max_slope, RULE_FEATHER.1)
+    }
+}
+
+/// Scatters one scatter type over the biome
+#[derive(Debug, Clone)]
+pub struct ScatterRule {
+    pub scatter_type: usize,
+    /// Instances per 100 square meters where the biome is at full weight
+    pub density: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Biome {
+    pub name: String,
+    /// Shown over the terrain when the regions are visible
+    pub color: [f32; 3],
+    /// Multiplies the terrain color
+    pub tint: [f32; 3],
+    pub splat_rules: Vec<SplatRule>,
+    pub scatter_rules: Vec<ScatterRule>,
+}
+
+impl Biome {
+    fn new(name: &str, color: [f32; 3], splat_rules: Vec<SplatRule>) -> Self {
+        Biome {
+            name: name.to_owned(),
+            color,
+            tint: [1.0; 3],
+            splat_rules,
+            scatter_rules: vec![],
+        }
+    }
+
+    /// Layer weights from the rules, None when no rule applies
+    fn layer_weights(&self, height: f32, slope: f32) -> Option<[f32; NUM_LAYERS]> {
+        let mut weights = [0.0; NUM_LAYERS];
+        for rule in &self.splat_rules {
+            weights[rule.layer] += rule.weight(height, slope);
+        }
+        let sum: f32 = weights.iter().sum();
+        if sum <= 0.0 {
+            return None;
+        }
+        for w in weights.iter_mut() {
+            *w /= sum;
+        }
+        Some(weights)
+    }
+}
+
+pub struct BiomeSettings {
+    pub biomes: [Biome; MAX_BIOMES],
+    /// `layer` is the biome being painted
+    pub paint: PaintSettings,
+    /// Colors the terrain by biome
+    pub show_regions: bool,
+}
+
+impl Default for BiomeSettings {
+    fn default() -> Self {
+        let rule = |layer, (min_height, max_height), (min_slope, max_slope)| SplatRule {
+            layer,
+            min_height,
+            max_height,
+            min_slope,
+            max_slope,
+        };
+        // Layers are grass, dirt, rock and snow
+        BiomeSettings {
+            biomes: [
+                Biome::new(
+                    "Meadow",
+                    [0.3, 0.8, 0.2],
+                    vec![
+                        rule(0, (-1000.0, 1000.0), (0.0, 25.0)),
+                        rule(1, (-1000.0, 1000.0), (25.0, 40.0)),
+                        rule(2, (-1000.0, 1000.0), (40.0, 90.0)),
+                    ],
+                ),
+                Biome::new(
+                    "Forest",
+                    [0.1, 0.45, 0.15],
+                    vec![
+                        rule(1, (-1000.0, 1000.0), (0.0, 35.0)),
+                        rule(2, (-1000.0, 1000.0), (35.0, 90.0)),
+                    ],
+                ),
+                Biome::new(
+                    "Alpine",
+                    [0.85, 0.9, 1.0],
+                    vec![
+                        rule(2, (-1000.0, 1000.0), (0.0, 90.0)),
+                        rule(3, (150.0, 1000.0), (0.0, 35.0)),
+                    ],
+                ),
+                Biome::new(
+                    "Desert",
+                    [0.95, 0.75, 0.3],
+                    vec![
+                        rule(1, (-1000.0, 1000.0), (0.0, 30.0)),
+                        rule(2, (-1000.0, 1000.0), (30.0, 90.0)),
+                    ],
+                ),
+            ],
+            paint: PaintSettings {
+                layer: 1,
+                blend_mode: PaintBlendMode::Add,
+                ..PaintSettings::default()
+            },
+            show_regions: false,
+        }
+    }
+}
+
+impl BiomeSettings {
+    /// New splat weights for a texel. The biomes whose rules apply replace the old
+    /// weights in proportion to their own weight.
+    pub fn texel_weights(
+        &self,
+        biome_weights: &[f32; MAX_BIOMES],
+        height: f32,
+        slope: f32,
+        old: [f32; NUM_LAYERS],
+    ) -> [f32; NUM_LAYERS] {
+        let mut weights = [0.0; NUM_LAYERS];
+        let mut covered = 0.0;
+        for (biome, &biome_weight) in self.biomes.iter().zip(biome_weights) {
+            if biome_weight <= 0.0 {
+                continue;
+            }
+            if let Some(layers) = biome.layer_weights(height, slope) {
+                for (w, layer) in weights.iter_mut().zip(layers) {
+                    *w += layer * biome_weight;
+                }
+                covered += biome_weight;
+            }
+        }
+        for (w, old) in weights.iter_mut().zip(old) {
+            *w += old * (1.0 - covered).max(0.0);
+        }
+        weights
+    }
+
+    /// Keeps rules pointing at the right types after one is removed
+    pub fn forget_scatter_type(&mut self, index: usize) {
+        for biome in &mut self.biomes {
+            biome
+                .scatter_rules
+                .retain(|rule| rule.scatter_type != index);
+            for rule in &mut biome.scatter_rules {
+                if rule.scatter_type > index {
+                    rule.scatter_type -= 1;
+                }
+            }
+        }
+    }
+
+    /// Refills every layer some rule scatters. An instance stays with the probability
+    /// of its biome's weight, so biomes thin out towards their edges.
+    pub fn auto_scatter(
+        &self,
+        terrain: &Terrain,
+        types: &[ScatterType],
+        layers: &mut [ScatterLayer],
+    ) {
+        for rule in self.biomes.iter().flat_map(|biome| &biome.scatter_rules) {
+            layers[rule.scatter_type].clear();
+        }
+        for (biome_index, biome) in self.biomes.iter().enumerate() {
+            for rule in &biome.scatter_rules {
+                let scatter_type = &types[rule.scatter_type];
+                let keep = |position: Vec2, (x, y): (i32, i32)| {
+                    let weight = terrain.biome_weights_at(position)[biome_index];
+                    noise::hash(scatter_type.seed.wrapping_add(KEEP_SEED), x, y) < weight
+                };
+                layers[rule.scatter_type].fill(terrain, scatter_type, rule.density, keep);
+            }
+        }
+    }
+}
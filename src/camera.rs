@@ -23,6 +23,21 @@ pub enum Movement {
     Right,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    Perspective,
+    /// Looking straight down, `half_height` is half of the visible world extent vertically
+    OrthographicTop {
+        half_height: f32,
+    },
+}
+
+impl Default for Projection {
+    fn default() -> Self {
+        Projection::Perspective
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct Camera {
     pub position: Vec3,
@@ -40,6 +55,7 @@ pub struct Camera {
     aspect_ratio: f32,
     v_fov: f32,
     locked: bool, // whether to allow flying
+    projection: Projection,
 
     pub speed_boost: bool,
 }
@@ -89,12 +105,66 @@ impl Camera {
             screen_dimensions,
             aspect_ratio,
             locked: false,
+            projection: Projection::Perspective,
             pitch,
             yaw,
             direction,
         }
     }
 
+    /// An orthographic camera looking straight down at `center`.
+    /// `extent` is how much of the world is visible vertically on screen.
+    pub fn new_top_down(center: Vec3, extent: f32, screen_width: u32, screen_height: u32) -> Self {
+        let screen_dimensions = Vec2::new(screen_width as f32, screen_height as f32);
+
+        Camera {
+            position: center,
+            direction: -TRUE_UP,
+            // Can't derive those from TRUE_UP since we're looking along it
+            up: Vec3::new(0.0, 0.0, -1.0),
+            right: Vec3::new(1.0, 0.0, 0.0),
+            movement_speed: 10.0,
+            speed_boost: false,
+            sensitivity: 0.0015,
+            zoom: ZOOM_DEFAULT,
+            v_fov: Camera::calculate_vert_fov(ZOOM_DEFAULT),
+            screen_dimensions,
+            aspect_ratio: screen_dimensions.x / screen_dimensions.y,
+            locked: true,
+            projection: Projection::OrthographicTop {
+                half_height: extent / 2.0,
+            },
+            pitch: -PI / 2.0,
+            yaw: 0.0,
+        }
+    }
+
+    /// Moves a perspective camera and points it along `direction`
+    pub fn place(&mut self, position: Vec3, direction: Vec3) {
+        let placed = Camera::new(
+            position,
+            position + direction,
+            self.screen_dimensions.x as u32,
+            self.screen_dimensions.y as u32,
+        );
+        self.position = placed.position;
+        self.direction = placed.direction;
+        self.up = placed.up;
+        self.right = placed.right;
+        self.pitch = placed.pitch;
+        self.yaw = placed.yaw;
+    }
+
+    pub fn projection(&self) -> Projection {
+        self.projection
+    }
+
+    /// Called when the camera gets a new area of the screen to render to
+    pub fn set_screen_dimensions(&mut self, screen_width: u32, screen_height: u32) {
+        self.screen_dimensions = Vec2::new(screen_width as f32, screen_height as f32);
+        self.aspect_ratio = self.screen_dimensions.x / self.screen_dimensions.y;
+    }
+
     /// Move the camera
     pub fn go(&mut self, direction: Movement, delta_time: f32) {
         let speed = if self.speed_boost {
@@ -104,6 +174,17 @@ impl Camera {
         };
         let speed = speed * delta_time;
 
+        if let Projection::OrthographicTop { half_height } = &mut self.projection {
+            // Forward/backward zoom the top view in and out, left/right pan it
+            match direction {
+                Movement::Forward => *half_height = (*half_height - speed).max(1.0),
+                Movement::Backward => *half_height += speed,
+                Movement::Left => self.position -= speed * self.right,
+                Movement::Right => self.position += speed * self.right,
+            }
+            return;
+        }
+
         let projected_direction = if self.locked {
             Vec3::new(self.direction.x, 0.0, self.direction.z)
         } else {
@@ -127,6 +208,14 @@ impl Camera {
     }
 
     pub fn rotate(&mut self, yaw_delta: f32, pitch_delta: f32) {
+        if let Projection::OrthographicTop { half_height } = self.projection {
+            // Top view can't rotate, so drag the map around instead
+            let world_per_pixel = 2.0 * half_height / self.screen_dimensions.y;
+            self.position -= self.right * yaw_delta * world_per_pixel;
+            self.position += self.up * pitch_delta * world_per_pixel;
+            return;
+        }
+
         // Adjust Euler angles
         self.pitch -= pitch_delta * self.sensitivity;
         self.pitch = self.pitch.clamp(PITCH_MIN, PITCH_MAX);
@@ -150,6 +239,15 @@ impl Camera {
 
     /// pixel has coordinates relative to the top left corner
     pub fn get_ray_through_pixel(&self, pixel: Vec2) -> Ray {
+        if let Projection::OrthographicTop { half_height } = self.projection {
+            // All rays are parallel, only the origin changes
+            let half_width = half_height * self.aspect_ratio;
+            let offset = pixel / self.screen_dimensions * 2.0 - Vec2::ONE;
+            let origin = self.position + self.right * offset.x * half_width
+                - self.up * offset.y * half_height;
+            return Ray::new(origin, self.direction);
+        }
+
         let half_height = (self.v_fov / 2.0).tan();
         let half_width = half_height * self.aspect_ratio;
         let top_left_corner =
@@ -165,6 +263,10 @@ impl Camera {
     }
 
     pub fn get_view_matrix(&self) -> Mat4 {
+        if let Projection::OrthographicTop { .. } = self.projection {
+            // Looking along true up, so have to use our own
+            return Mat4::look_at_rh(self.position, self.position + self.direction, self.up);
+        }
         // Camera never turns upside down so true up is fixed
         Mat4::look_at_rh(self.position, self.position + self.direction, TRUE_UP)
     }
@@ -178,6 +280,18 @@ impl Camera {
 
     // For OpenGL:
     pub fn get_projection_matrix(&self) -> Mat4 {
+        if let Projection::OrthographicTop { half_height } = self.projection {
+            let half_width = half_height * self.aspect_ratio;
+            // The camera sits above the terrain so everything below it is visible
+            return Mat4::orthographic_rh_gl(
+                -half_width,
+                half_width,
+                -half_height,
+                half_height,
+                0.5,
+                5000.0,
+            );
+        }
         // Mat4::perspective_rh(self.v_fov, self.aspect_ratio, 0.5, 2000.0)
         // @explore: try setting different clip planes every frame based on z-buffer (glReadPixels)?
         Mat4::perspective_infinite_rh(self.v_fov, self.aspect_ratio, 0.5)
@@ -0,0 +1,37 @@
+//! Rocks scattered over the steep faces of the terrain. A heightfield can't have
+//! overhangs, so near-vertical walls end up with badly stretched textures; a layer
+//! of rocks oriented to the slope hides most of it.
+
+use glam::Mat4;
+
+use crate::instances::{placement_transform, InstancedModel};
+use crate::scatter::SlopeScatter;
+use crate::terrain::Terrain;
+use crate::Result;
+
+pub struct CliffSettings {
+    /// glTF model used for every rock
+    pub model_path: String,
+    pub scatter: SlopeScatter,
+}
+
+impl Default for CliffSettings {
+    fn default() -> Self {
+        CliffSettings {
+            model_path: "models/box/box.gltf".to_owned(),
+            scatter: SlopeScatter::default(),
+        }
+    }
+}
+
+/// Rocks on every face steeper than the settings allow
+pub fn scatter(terrain: &Terrain, settings: &CliffSettings) -> Result<InstancedModel> {
+    let mut rocks = InstancedModel::load(&settings.model_path)?;
+    let transforms: Vec<Mat4> = terrain
+        .scatter_on_slopes(&settings.scatter)
+        .iter()
+        .map(|placement| placement_transform(placement, 1.0))
+        .collect();
+    rocks.set_transforms(&transforms);
+    Ok(rocks)
+}
@@ -0,0 +1,86 @@
+//! A/B comparison with another heightmap, e.g. the last saved one. The difference
+//! is drawn as a heat overlay, and regions can be blended back towards the other map.
+
+use image::GenericImageView;
+
+use crate::heightfield::HeightField;
+use crate::opengl::objects::Texture;
+use crate::resources::{self, texture_bytes, MemoryHandle, ResourceKind};
+
+pub struct CompareSettings {
+    /// Heightmap image to compare with
+    pub path: String,
+    pub overlay_opacity: f32,
+    /// Height difference in meters that shows at full heat
+    pub range: f32,
+    /// How far "Blend" goes towards the reference, and how fast the revert brush does
+    pub blend: f32,
+}
+
+impl CompareSettings {
+    pub fn new(path: &str) -> Self {
+        CompareSettings {
+            path: path.to_owned(),
+            overlay_opacity: 0.6,
+            range: 10.0,
+            blend: 0.5,
+        }
+    }
+}
+
+/// The other side of the comparison, resampled to the terrain resolution
+pub struct Reference {
+    pub texture: Texture,
+    heights: HeightField,
+    _memory: MemoryHandle,
+}
+
+impl Reference {
+    pub fn new(heights: &HeightField, resolution: usize) -> Self {
+        let heights = heights.resampled(resolution);
+
+        let texture = Texture::new(gl::TEXTURE_2D, "Reference heightmap");
+        texture.set_wrap_and_filter(gl::CLAMP_TO_EDGE, gl::LINEAR, gl::LINEAR);
+        let size = resolution as i32;
+        unsafe {
+            gl::TextureStorage2D(texture.id(), 1, gl::R16, size, size);
+            gl::TextureSubImage2D(
+                texture.id(),
+                0,
+                0,
+                0,
+                size,
+                size,
+                gl::RED,
+                gl::UNSIGNED_SHORT,
+                heights.to_u16().as_ptr() as *const _,
+            );
+        }
+
+        Reference {
+            texture,
+            heights,
+            _memory: resources::track(
+                ResourceKind::Heightmap,
+                texture_bytes(gl::R16, resolution, resolution, 1),
+            ),
+        }
+    }
+
+    pub fn heights(&self) -> &HeightField {
+        &self.heights
+    }
+}
+
+/// Decodes a heightmap image on any thread. Any square size will do.
+pub fn load_heights(path: &str) -> Result<HeightField, String> {
+    let img = image::open(path).map_err(|e| e.to_string())?;
+    let (width, height) = img.dimensions();
+    if width != height {
+        return Err(format!("{}x{} isn't square", width, height));
+    }
+    Ok(HeightField::from_u16(
+        width as usize,
+        &img.into_luma16().into_raw(),
+    ))
+}
@@ -2,35 +2,171 @@ use std::fs;
 
 use glam::Vec3;
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use thiserror::Error;
 
+use crate::export_hooks::ExportHooks;
+use crate::georef::Georeference;
+use crate::terrain::TerrainParams;
+use crate::tool_memory::ToolMemory;
 use crate::Result;
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Bumped whenever the saved format changes in a way old files can't just be read as.
+/// Add the migration from the previous version to `MIGRATIONS` at the same time.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// `MIGRATIONS[i]` turns a version `i` file into a version `i + 1` one
+const MIGRATIONS: [fn(&mut Map<String, Value>); FORMAT_VERSION as usize] = [
+    // Version 0 files only lack the version field, everything added since has a default
+    |_| {},
+];
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("Saved by a newer editor (format {found}, this one reads up to {supported})")]
+    TooNew { found: u32, supported: u32 },
+    #[error("Not a valid config: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Config {
     pub heightmap_path: String,
     pub start_with_flat_terrain: bool,
+    #[serde(default)]
+    pub terrain: TerrainParams,
     pub camera_position: Option<Vec3>,
     pub camera_direction: Option<Vec3>,
+    /// On top of the OS scale factor, which is way off on some mixed-DPI setups
+    #[serde(default = "default_ui_scale")]
+    pub ui_scale: f32,
+    /// Brush sizes and settings each tool was last used with
+    #[serde(default)]
+    pub tools: ToolMemory,
+    /// Where the terrain is on the ground, known when it came from a GeoTIFF DEM
+    #[serde(default)]
+    pub georeference: Option<Georeference>,
+    /// The first-run tutorial has been finished or dismissed
+    #[serde(default)]
+    pub tutorial_seen: bool,
+    /// Append a summary of every session to a local file on exit
+    #[serde(default)]
+    pub session_stats: bool,
+    /// Commands run after each kind of export
+    #[serde(default)]
+    pub export_hooks: ExportHooks,
 }
 
 impl Config {
     pub fn load_or_default() -> Result<Self> {
         let config = if let Ok(config) = fs::read_to_string("config.json") {
-            serde_json::from_str(&config)?
+            Config::from_json(&config)?
         } else {
             Config {
                 heightmap_path: "textures/heightmaps/heightmap.png".to_owned(),
                 start_with_flat_terrain: true,
+                terrain: TerrainParams::default(),
                 camera_position: None,
                 camera_direction: None,
+                ui_scale: default_ui_scale(),
+                tools: ToolMemory::default(),
+                georeference: None,
+                tutorial_seen: false,
+                session_stats: false,
+                export_hooks: ExportHooks::default(),
             }
         };
         Ok(config)
     }
 
     pub fn save(&self) {
-        let string = serde_json::to_string(self).unwrap();
+        fs::write("config.json", self.to_json()).unwrap();
+    }
+
+    /// Reads any version up to the current one, migrating older files step by step
+    pub fn from_json(json: &str) -> std::result::Result<Self, ConfigError> {
+        let mut fields = match serde_json::from_str(json)? {
+            Value::Object(fields) => fields,
+            other => return Ok(serde_json::from_value(other)?),
+        };
+        // Files from before versioning don't have the field at all
+        let version = fields
+            .remove("version")
+            .and_then(|v| v.as_u64())
+            .unwrap_or(0) as u32;
+        if version > FORMAT_VERSION {
+            return Err(ConfigError::TooNew {
+                found: version,
+                supported: FORMAT_VERSION,
+            });
+        }
+        for migrate in &MIGRATIONS[version as usize..] {
+            migrate(&mut fields);
+        }
+        Ok(serde_json::from_value(Value::Object(fields))?)
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut value = serde_json::to_value(self).unwrap();
+        value["version"] = FORMAT_VERSION.into();
+        serde_json::to_string(&value).unwrap()
+    }
+}
 
-        fs::write("config.json", string).unwrap();
+fn default_ui_scale() -> f32 {
+    1.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const V0: &str = include_str!("../tests/fixtures/config_v0.json");
+
+    #[test]
+    fn version_0_files_load() {
+        let config = Config::from_json(V0).unwrap();
+        assert_eq!(config.heightmap_path, "textures/heightmaps/alps.png");
+        assert!(!config.start_with_flat_terrain);
+        assert_eq!(config.terrain.world_size, 2048.0);
+        assert_eq!(config.terrain.resolution, 2048);
+        assert_eq!(config.terrain.max_height, 350.0);
+        assert_eq!(config.terrain.seed, 0);
+        assert_eq!(config.camera_position, Some(Vec3::new(512.0, 180.0, -64.0)));
+        assert_eq!(config.ui_scale, 1.0);
+        assert!(!config.tutorial_seen);
+    }
+
+    #[test]
+    fn version_0_files_save_as_the_current_version() {
+        let json = Config::from_json(V0).unwrap().to_json();
+        let value: Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["version"], FORMAT_VERSION);
+    }
+
+    #[test]
+    fn saved_config_loads_back_the_same() {
+        let mut config = Config::from_json(V0).unwrap();
+        config.terrain.seed = 77;
+        config.ui_scale = 1.5;
+        config.tutorial_seen = true;
+        config.session_stats = true;
+        let loaded = Config::from_json(&config.to_json()).unwrap();
+        assert_eq!(loaded.to_json(), config.to_json());
+        assert_eq!(loaded.terrain, config.terrain);
+        assert_eq!(loaded.ui_scale, 1.5);
+    }
+
+    #[test]
+    fn newer_files_are_refused() {
+        let mut value: Value = serde_json::from_str(V0).unwrap();
+        value["version"] = (FORMAT_VERSION + 1).into();
+        match Config::from_json(&value.to_string()) {
+            Err(ConfigError::TooNew { found, supported }) => {
+                assert_eq!(found, FORMAT_VERSION + 1);
+                assert_eq!(supported, FORMAT_VERSION);
+            }
+            other => panic!("expected TooNew, got {:?}", other),
+        }
     }
 }
@@ -0,0 +1,141 @@
+//! Time of day and the sunlight that goes with it. The sun rises in the east
+//! at 6, is highest at noon and sets in the west at 18, on a path tilted
+//! towards +Z. Playing the cycle runs through a whole day in a few seconds.
+//! The moon follows the same path, behind the sun by its phase, and the sky fades
+//! from the day sky to stars as the sun goes down.
+
+use std::f32::consts::TAU;
+
+use glam::Vec3;
+
+use crate::noise::smoothstep;
+
+/// Degrees the sun's path leans from straight overhead, noon matches the old fixed sun
+const PATH_TILT: f32 = 68.2;
+
+/// Days from one new moon to the next
+const LUNAR_MONTH: f32 = 29.5;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Sunlight {
+    /// Towards the sun
+    pub direction: Vec3,
+    /// Linear RGB, black when the sun is down
+    pub color: Vec3,
+    /// Fraction of the surface color that's lit regardless of the sun
+    pub ambient: f32,
+    /// Angular diameter in radians, softens shadows. Zero for hard shadows.
+    pub size: f32,
+}
+
+/// What the skybox needs to draw the sky at some time of day
+#[derive(Debug, Clone, Copy)]
+pub struct Sky {
+    /// 1 for the day sky, 0 for the night sky
+    pub day: f32,
+    pub moon_direction: Vec3,
+    /// Angular, radians
+    pub moon_radius: f32,
+    /// 0 to 1
+    pub star_density: f32,
+}
+
+impl Default for Sunlight {
+    /// Noon
+    fn default() -> Self {
+        DayCycle::default().sunlight()
+    }
+}
+
+pub struct DayCycle {
+    /// 0 to 24
+    pub hour: f32,
+    pub playing: bool,
+    /// How long a whole day takes when playing
+    pub cycle_seconds: f32,
+    /// 0 is the new moon, 0.5 the full moon
+    pub moon_phase: f32,
+    /// Degrees across
+    pub moon_size: f32,
+    pub star_density: f32,
+}
+
+impl Default for DayCycle {
+    fn default() -> Self {
+        DayCycle {
+            hour: 12.0,
+            playing: false,
+            cycle_seconds: 30.0,
+            moon_phase: 0.5,
+            moon_size: 2.0,
+            star_density: 0.5,
+        }
+    }
+}
+
+impl DayCycle {
+    pub fn advance(&mut self, delta_time: f32) {
+        if self.playing {
+            let hours = 24.0 * delta_time / self.cycle_seconds.max(0.1);
+            self.hour = (self.hour + hours).rem_euclid(24.0);
+            self.moon_phase = (self.moon_phase + hours / 24.0 / LUNAR_MONTH).rem_euclid(1.0);
+        }
+    }
+
+    pub fn sunlight(&self) -> Sunlight {
+        let direction = path_direction(self.hour);
+        // Below the horizon the sun fades out, close to it it's orange
+        let elevation = direction.y;
+        let daylight = smoothstep((elevation + 0.05) / 0.15);
+        let warmth = smoothstep(elevation / 0.3);
+        let color = Vec3::new(1.0, 0.55, 0.3).lerp(Vec3::ONE, warmth) * daylight;
+        Sunlight {
+            direction,
+            color,
+            ambient: 0.08 + 0.27 * daylight,
+            size: 0.0,
+        }
+    }
+
+    pub fn sky(&self) -> Sky {
+        Sky {
+            day: smoothstep((path_direction(self.hour).y + 0.1) / 0.3),
+            // A full moon rises as the sun sets
+            moon_direction: path_direction(self.hour - self.moon_phase * 24.0),
+            moon_radius: self.moon_size.to_radians() / 2.0,
+            star_density: self.star_density,
+        }
+    }
+
+    pub fn moon_phase_name(&self) -> &'static str {
+        const NAMES: [&str; 8] = [
+            "New moon",
+            "Waxing crescent",
+            "First quarter",
+            "Waxing gibbous",
+            "Full moon",
+            "Waning gibbous",
+            "Last quarter",
+            "Waning crescent",
+        ];
+        NAMES[((self.moon_phase * 8.0).round() as usize) % 8]
+    }
+}
+
+/// Towards the sun at `samples` evenly spaced times over a whole day
+pub fn sun_path(samples: usize) -> Vec<Vec3> {
+    (0..samples)
+        .map(|i| path_direction((i as f32 + 0.5) * 24.0 / samples as f32))
+        .collect()
+}
+
+/// Towards the sun at `hour`
+fn path_direction(hour: f32) -> Vec3 {
+    let angle = (hour - 6.0) / 24.0 * TAU;
+    let tilt = PATH_TILT.to_radians();
+    Vec3::new(
+        angle.cos(),
+        angle.sin() * tilt.cos(),
+        angle.sin() * tilt.sin(),
+    )
+}
@@ -0,0 +1,163 @@
+//! Elevation data with holes in it. DEMs like SRTM mark cells without data (radar
+//! shadows, water, steep slopes) with a nodata value, which would come in as deep pits
+//! or spikes. Those cells are found here and filled from the data around them.
+
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use tiff::decoder::{Decoder, DecodingResult};
+use tiff::tags::Tag;
+use tiff::ColorType;
+
+use crate::compare;
+use crate::heightfield::HeightField;
+use crate::voids::{self, VoidFill};
+
+/// What SRTM uses for voids when the file doesn't say
+const SRTM_NODATA: f64 = -32768.0;
+
+/// Voids are shown at full heat
+pub const VOID_OVERLAY_OPACITY: f32 = 0.6;
+
+pub struct VoidRepairSettings {
+    /// Fill the voids on import
+    pub enabled: bool,
+    pub method: VoidFill,
+    /// Show the texels that had no data over the terrain
+    pub show: bool,
+}
+
+impl Default for VoidRepairSettings {
+    fn default() -> Self {
+        VoidRepairSettings {
+            enabled: true,
+            method: VoidFill::Laplacian,
+            show: true,
+        }
+    }
+}
+
+pub struct Dem {
+    pub heights: HeightField,
+    /// 1 where there was no data, none if every texel had some
+    pub voids: Option<HeightField>,
+}
+
+/// Decodes a heightmap on any thread, filling its voids with `fill` if it has any.
+/// Voids are only found in GeoTIFFs; other images load as they are.
+pub fn load(path: &str, fill: Option<VoidFill>) -> Result<Dem, String> {
+    let is_tiff = Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("tif") || e.eq_ignore_ascii_case("tiff"));
+    // Colour TIFFs aren't DEMs, the image crate knows better what to do with them
+    let tiff = if is_tiff { read_tiff(path)? } else { None };
+    let (heights, void_texels) = match tiff {
+        Some(tiff) => tiff,
+        None => {
+            let heights = compare::load_heights(path)?;
+            return Ok(Dem {
+                heights,
+                voids: None,
+            });
+        }
+    };
+    if !void_texels.contains(&true) {
+        return Ok(Dem {
+            heights,
+            voids: None,
+        });
+    }
+    let heights = match fill {
+        Some(method) => voids::fill_voids(&heights, &void_texels, method),
+        None => heights,
+    };
+    Ok(Dem {
+        heights,
+        voids: Some(voids::void_mask(&void_texels)),
+    })
+}
+
+/// Single channel only. Unsigned samples map to 0..1 like a grayscale image would,
+/// signed and float ones are elevations and get stretched between their lowest and
+/// highest. Voids are at 0.
+fn read_tiff(path: &str) -> Result<Option<(HeightField, Vec<bool>)>, String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    let mut decoder = Decoder::new(BufReader::new(file)).map_err(|e| e.to_string())?;
+    match decoder.colortype().map_err(|e| e.to_string())? {
+        ColorType::Gray(_) => {}
+        _ => return Ok(None),
+    }
+    let (width, height) = decoder.dimensions().map_err(|e| e.to_string())?;
+    if width != height {
+        return Err(format!("{}x{} isn't square", width, height));
+    }
+    let nodata = decoder
+        .get_tag_ascii_string(Tag::GdalNodata)
+        .ok()
+        .and_then(|s| s.trim().trim_end_matches('\0').parse::<f64>().ok());
+
+    let (samples, max): (Vec<f64>, Option<f64>) = match decoder
+        .read_image()
+        .map_err(|e| e.to_string())?
+    {
+        DecodingResult::U8(v) => (to_f64(&v), Some(u8::MAX as f64)),
+        DecodingResult::U16(v) => (to_f64(&v), Some(u16::MAX as f64)),
+        DecodingResult::U32(v) => (to_f64(&v), Some(u32::MAX as f64)),
+        DecodingResult::U64(v) => (v.iter().map(|&s| s as f64).collect(), Some(u64::MAX as f64)),
+        DecodingResult::F16(v) => (v.iter().map(|s| s.to_f64()).collect(), None),
+        DecodingResult::F32(v) => (to_f64(&v), None),
+        DecodingResult::F64(v) => (v, None),
+        DecodingResult::I8(v) => (to_f64(&v), None),
+        DecodingResult::I16(v) => (to_f64(&v), None),
+        DecodingResult::I32(v) => (to_f64(&v), None),
+        DecodingResult::I64(v) => (v.iter().map(|&s| s as f64).collect(), None),
+    };
+    let nodata = match (nodata, max) {
+        (Some(nodata), _) => Some(nodata),
+        // Unsigned data without a nodata tag is most likely a plain heightmap
+        (None, Some(_)) => None,
+        (None, None) => Some(SRTM_NODATA),
+    };
+    let void_texels: Vec<bool> = samples
+        .iter()
+        .map(|&s| !s.is_finite() || Some(s) == nodata)
+        .collect();
+
+    let (low, high) = match max {
+        Some(max) => (0.0, max),
+        None => samples
+            .iter()
+            .zip(&void_texels)
+            .filter(|(_, &void)| !void)
+            .fold((f64::MAX, f64::MIN), |(low, high), (&s, _)| {
+                (low.min(s), high.max(s))
+            }),
+    };
+    let range = (high - low).max(f64::EPSILON);
+    let data = samples
+        .iter()
+        .zip(&void_texels)
+        .map(|(&s, &void)| {
+            if void {
+                0.0
+            } else {
+                ((s - low) / range) as f32
+            }
+        })
+        .collect();
+    Ok(Some((
+        HeightField::from_data(width as usize, data),
+        void_texels,
+    )))
+}
+
+/// Share of the texels without data
+pub fn void_fraction(voids: &HeightField) -> f32 {
+    voids.data().iter().sum::<f32>() / voids.data().len() as f32
+}
+
+fn to_f64<T: Copy + Into<f64>>(samples: &[T]) -> Vec<f64> {
+    samples.iter().map(|&s| s.into()).collect()
+}
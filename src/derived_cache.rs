@@ -0,0 +1,221 @@
+//! Results that take a while to work out but only depend on what they're worked out
+//! from: horizons baked from the heights, the prefiltered sky light, compressed and
+//! transcoded textures. They're kept on disk under a hash of everything that went
+//! into them, so opening a project again reads them back instead of redoing minutes
+//! of work.
+//!
+//! Nothing is ever invalidated, different inputs are just a different hash. Whatever
+//! changes the way a result is worked out has to go into its hash as well, usually as
+//! a version number. Once the cache is over `MAX_BYTES` the entries used longest ago
+//! are deleted.
+
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use xxhash_rust::xxh3::{xxh3_64_with_seed, Xxh3};
+
+const CACHE_DIRECTORY: &str = "derived_cache";
+const MAX_BYTES: u64 = 2 * 1024 * 1024 * 1024;
+
+/// Bytes in the cache, None until the directory is first looked at
+static DISK_BYTES: Mutex<Option<u64>> = Mutex::new(None);
+
+/// xxh3 of every piece seeded with the hash of everything before it. Stays the same
+/// between builds unlike the std hasher, mixes every bit into every other unlike a
+/// word at a time FNV, and keeps up with heightmaps of hundreds of megabytes.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ContentHash(u64);
+
+impl ContentHash {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn piece(self, bytes: &[u8]) -> Self {
+        ContentHash(xxh3_64_with_seed(bytes, self.0))
+    }
+
+    pub fn with_u64(self, value: u64) -> Self {
+        self.piece(&value.to_le_bytes())
+    }
+
+    pub fn with_f32(self, value: f32) -> Self {
+        self.piece(&value.to_bits().to_le_bytes())
+    }
+
+    /// The length goes in too, so slices that only differ in how they're split don't
+    /// hash the same
+    pub fn with_bytes(self, bytes: &[u8]) -> Self {
+        self.with_u64(bytes.len() as u64).piece(bytes)
+    }
+
+    /// Streamed through a small buffer rather than copied to bytes all at once
+    pub fn with_floats(self, values: &[f32]) -> Self {
+        let seed = self.with_u64(values.len() as u64).0;
+        let mut hasher = Xxh3::with_seed(seed);
+        let mut buffer = [0u8; 4096];
+        for floats in values.chunks(buffer.len() / 4) {
+            for (bytes, value) in buffer.chunks_exact_mut(4).zip(floats) {
+                bytes.copy_from_slice(&value.to_le_bytes());
+            }
+            hasher.update(&buffer[..floats.len() * 4]);
+        }
+        ContentHash(hasher.digest())
+    }
+}
+
+impl fmt::LowerHex for ContentHash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::LowerHex::fmt(&self.0, f)
+    }
+}
+
+fn entry_path(kind: &str, hash: ContentHash) -> PathBuf {
+    Path::new(CACHE_DIRECTORY).join(format!("{}_{:016x}", kind, hash))
+}
+
+/// None if it's never been stored or can't be read. The entry counts as just used.
+pub fn get(kind: &str, hash: ContentHash) -> Option<Vec<u8>> {
+    let path = entry_path(kind, hash);
+    let bytes = fs::read(&path).ok()?;
+    // Only decides what gets deleted first, it's fine if it fails
+    if let Ok(file) = fs::File::options().write(true).open(&path) {
+        file.set_modified(SystemTime::now()).ok();
+    }
+    Some(bytes)
+}
+
+/// A failure is only reported, the result is worked out again next time
+pub fn put(kind: &str, hash: ContentHash, bytes: &[u8]) {
+    let path = entry_path(kind, hash);
+    let replaced = fs::metadata(&path).map_or(0, |metadata| metadata.len());
+    let before = disk_bytes().saturating_sub(replaced);
+    // Written aside and renamed, so a half written entry is never read
+    let partial = path.with_extension(format!("{}.partial", std::process::id()));
+    let result = fs::create_dir_all(CACHE_DIRECTORY)
+        .and_then(|_| fs::write(&partial, bytes))
+        .and_then(|_| fs::rename(&partial, &path));
+    if let Err(e) = result {
+        eprintln!("Couldn't cache {}: {}", path.display(), e);
+        fs::remove_file(&partial).ok();
+        return;
+    }
+    let total = before + bytes.len() as u64;
+    *DISK_BYTES.lock().unwrap() = Some(total);
+    if total > MAX_BYTES {
+        trim();
+    }
+}
+
+/// Every entry with the time it was last used and its size
+fn entries() -> Vec<(PathBuf, SystemTime, u64)> {
+    let directory = match fs::read_dir(CACHE_DIRECTORY) {
+        Ok(directory) => directory,
+        Err(_) => return vec![],
+    };
+    directory
+        .flatten()
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok()?;
+            let used = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            metadata
+                .is_file()
+                .then(|| (entry.path(), used, metadata.len()))
+        })
+        .collect()
+}
+
+/// Deletes the entries used longest ago until the cache fits in `MAX_BYTES` again
+fn trim() {
+    let mut entries = entries();
+    entries.sort_by_key(|&(_, used, _)| used);
+    let mut total: u64 = entries.iter().map(|&(_, _, bytes)| bytes).sum();
+    for (path, _, bytes) in entries {
+        if total <= MAX_BYTES {
+            break;
+        }
+        if fs::remove_file(path).is_ok() {
+            total -= bytes;
+        }
+    }
+    *DISK_BYTES.lock().unwrap() = Some(total);
+}
+
+/// How much the cache takes on disk
+pub fn disk_bytes() -> u64 {
+    let mut disk_bytes = DISK_BYTES.lock().unwrap();
+    *disk_bytes.get_or_insert_with(|| entries().iter().map(|&(_, _, bytes)| bytes).sum())
+}
+
+/// Everything is worked out again when it's next needed
+pub fn clear() -> std::io::Result<()> {
+    let result = match fs::remove_dir_all(CACHE_DIRECTORY) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    };
+    // Whatever failed to be deleted is counted again
+    *DISK_BYTES.lock().unwrap() = None;
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn heightmap_hash(texels: &[u16]) -> ContentHash {
+        let bytes: Vec<u8> = texels.iter().flat_map(|t| t.to_le_bytes()).collect();
+        ContentHash::new().with_u64(1).with_bytes(&bytes)
+    }
+
+    #[test]
+    fn high_bits_in_consecutive_words_change_the_hash() {
+        // Flipping bit 63 of two consecutive words cancelled out with FNV a word at a time
+        let texels = vec![1000u16; 64];
+        let mut changed = texels.clone();
+        changed[3] ^= 0x8000;
+        changed[7] ^= 0x8000;
+        assert_ne!(heightmap_hash(&texels), heightmap_hash(&changed));
+
+        let words = |a: u64, b: u64| ContentHash::new().with_u64(a).with_u64(b);
+        assert_ne!(words(0, 0), words(1 << 63, 1 << 63));
+    }
+
+    #[test]
+    fn every_single_bit_changes_the_hash() {
+        let bytes = vec![0x5au8; 40];
+        let hash = ContentHash::new().with_bytes(&bytes);
+        for bit in 0..bytes.len() * 8 {
+            let mut flipped = bytes.clone();
+            flipped[bit / 8] ^= 1 << (bit % 8);
+            assert_ne!(ContentHash::new().with_bytes(&flipped), hash, "bit {}", bit);
+        }
+    }
+
+    #[test]
+    fn lengths_are_part_of_the_hash() {
+        let split = ContentHash::new().with_bytes(b"ab").with_bytes(b"c");
+        let joined = ContentHash::new().with_bytes(b"a").with_bytes(b"bc");
+        assert_ne!(split, joined);
+        assert_ne!(
+            ContentHash::new().with_floats(&[]),
+            ContentHash::new().with_floats(&[0.0])
+        );
+    }
+
+    #[test]
+    fn floats_hash_the_same_across_buffer_boundaries() {
+        let values: Vec<f32> = (0..5000).map(|i| i as f32 * 0.37).collect();
+        let bytes: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+        let streamed = ContentHash::new().with_floats(&values);
+        let whole = ContentHash::new()
+            .with_u64(values.len() as u64)
+            .piece(&bytes);
+        assert_eq!(streamed, whole);
+        let mut changed = values.clone();
+        changed[4999] = -0.0;
+        assert_ne!(ContentHash::new().with_floats(&changed), streamed);
+    }
+}
@@ -0,0 +1,139 @@
+//! The sparse detail layer on the GPU. Every carved tile is a layer of a texture
+//! array, found through an index with one texel per tile that holds its layer plus
+//! one. The shaders add it to the heightmap in `sample_height`.
+
+use std::collections::HashMap;
+
+use terrain_core::detail::{DetailLayer, TileKey, MAX_TILES, TILE_SIZE};
+
+use crate::opengl::objects::Texture;
+use crate::resources::{self, texture_bytes, MemoryHandle, ResourceKind};
+
+/// Where the terrain shaders read the tiles and the index from
+pub const TILES_UNIT: i32 = 14;
+pub const INDEX_UNIT: i32 = 15;
+
+/// Brushes at most this many heightmap texels wide can carve detail
+pub const MAX_BRUSH_TEXELS: f32 = 2.0;
+
+#[derive(Default)]
+pub struct DetailSettings {
+    /// Small brushes carve into the detail layer instead of the heightmap
+    pub enabled: bool,
+}
+
+pub struct DetailMap {
+    pub tiles: Texture,
+    pub index: Texture,
+    tiles_per_side: usize,
+    /// Layer of `tiles` by tile
+    layers: HashMap<TileKey, usize>,
+    _memory: MemoryHandle,
+}
+
+impl DetailMap {
+    pub fn new(tiles_per_side: usize) -> Self {
+        let tiles = Texture::new(gl::TEXTURE_2D_ARRAY, "Detail tiles");
+        tiles.set_wrap_and_filter(gl::CLAMP_TO_EDGE, gl::LINEAR, gl::LINEAR);
+        let index = Texture::new(gl::TEXTURE_2D, "Detail index");
+        index.set_wrap_and_filter(gl::CLAMP_TO_EDGE, gl::NEAREST, gl::NEAREST);
+        let side = tiles_per_side as i32;
+        unsafe {
+            let tile_size = TILE_SIZE as i32;
+            gl::TextureStorage3D(
+                tiles.id(),
+                1,
+                gl::R16F,
+                tile_size,
+                tile_size,
+                MAX_TILES as i32,
+            );
+            gl::TextureStorage2D(index.id(), 1, gl::R32F, side, side);
+        }
+
+        let mut detail_map = DetailMap {
+            tiles,
+            index,
+            tiles_per_side,
+            layers: HashMap::new(),
+            _memory: resources::track(
+                ResourceKind::Heightmap,
+                texture_bytes(gl::R16F, TILE_SIZE, TILE_SIZE, 1) * MAX_TILES
+                    + texture_bytes(gl::R32F, tiles_per_side, tiles_per_side, 1),
+            ),
+        };
+        detail_map.clear();
+        detail_map
+    }
+
+    /// Everything in `layer` again, e.g. after a new map was made for it
+    pub fn upload_all(&mut self, layer: &DetailLayer) {
+        let keys: Vec<TileKey> = layer.tiles().map(|(&key, _)| key).collect();
+        self.upload(layer, &keys);
+    }
+
+    /// Sends the given tiles of `layer`, new ones get a layer of the array
+    pub fn upload(&mut self, layer: &DetailLayer, keys: &[TileKey]) {
+        debug_assert_eq!(layer.tiles_per_side(), self.tiles_per_side);
+        for &key in keys {
+            let tile = match layer.tile(key) {
+                Some(tile) => tile,
+                None => continue,
+            };
+            let next = self.layers.len();
+            let index = *self.layers.entry(key).or_insert(next);
+            let tile_size = TILE_SIZE as i32;
+            unsafe {
+                gl::TextureSubImage3D(
+                    self.tiles.id(),
+                    0,
+                    0,
+                    0,
+                    index as i32,
+                    tile_size,
+                    tile_size,
+                    1,
+                    gl::RED,
+                    gl::FLOAT,
+                    tile.data().as_ptr() as *const _,
+                );
+            }
+            if index == next {
+                let value = (index + 1) as f32;
+                unsafe {
+                    gl::TextureSubImage2D(
+                        self.index.id(),
+                        0,
+                        key.0 as i32,
+                        key.1 as i32,
+                        1,
+                        1,
+                        gl::RED,
+                        gl::FLOAT,
+                        &value as *const f32 as *const _,
+                    );
+                }
+            }
+        }
+    }
+
+    /// No tiles, the layers are handed out again from the first one
+    pub fn clear(&mut self) {
+        self.layers.clear();
+        let side = self.tiles_per_side;
+        let zeros = vec![0.0f32; side * side];
+        unsafe {
+            gl::TextureSubImage2D(
+                self.index.id(),
+                0,
+                0,
+                0,
+                side as i32,
+                side as i32,
+                gl::RED,
+                gl::FLOAT,
+                zeros.as_ptr() as *const _,
+            );
+        }
+    }
+}
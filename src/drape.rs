@@ -0,0 +1,94 @@
+//! A photo draped over the terrain as its base color, e.g. satellite imagery of the
+//! area a DEM came from. When both are georeferenced the photo is lined up by their
+//! extents, otherwise it's stretched over the whole terrain.
+
+use glam::Vec2;
+use image::RgbaImage;
+
+use crate::georef::GeoExtent;
+use crate::opengl::objects::Texture;
+use crate::resources::{self, texture_bytes, MemoryHandle, ResourceKind};
+use crate::texture::{calculate_mip_levels, get_max_anisotropy};
+
+/// Bigger photos are scaled down while loading, GL can't take arbitrarily large textures
+pub const MAX_DRAPE_SIZE: u32 = 8192;
+
+pub struct DrapeSettings {
+    /// Image to drape, ideally a GeoTIFF
+    pub path: String,
+    /// How much of the painted layers the photo covers
+    pub opacity: f32,
+}
+
+impl Default for DrapeSettings {
+    fn default() -> Self {
+        DrapeSettings {
+            path: String::new(),
+            opacity: 1.0,
+        }
+    }
+}
+
+pub struct Drape {
+    pub texture: Texture,
+    _memory: MemoryHandle,
+    /// Terrain uv times the scale plus the offset is the uv in the photo
+    pub uv_scale: Vec2,
+    pub uv_offset: Vec2,
+}
+
+impl Drape {
+    /// `photo_extent` and `terrain_extent` are where the photo and the terrain are
+    /// on the ground, if they're known
+    pub fn new(
+        photo: &RgbaImage,
+        photo_extent: Option<GeoExtent>,
+        terrain_extent: Option<GeoExtent>,
+    ) -> Self {
+        let (width, height) = (photo.width() as usize, photo.height() as usize);
+        let levels = calculate_mip_levels(width, height);
+        let texture = Texture::new(gl::TEXTURE_2D, "Draped photo");
+        texture.set_wrap_and_filter(gl::CLAMP_TO_EDGE, gl::LINEAR_MIPMAP_LINEAR, gl::LINEAR);
+        unsafe {
+            gl::TextureParameterf(
+                texture.id(),
+                gl::TEXTURE_MAX_ANISOTROPY,
+                get_max_anisotropy(),
+            );
+            // Photos are in sRGB, sampling gives linear colors like the rest of the terrain
+            gl::TextureStorage2D(
+                texture.id(),
+                levels,
+                gl::SRGB8_ALPHA8,
+                width as i32,
+                height as i32,
+            );
+            gl::TextureSubImage2D(
+                texture.id(),
+                0,
+                0,
+                0,
+                width as i32,
+                height as i32,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                photo.as_raw().as_ptr() as *const _,
+            );
+            gl::GenerateTextureMipmap(texture.id());
+        }
+
+        let (uv_scale, uv_offset) = match (photo_extent, terrain_extent) {
+            (Some(photo), Some(terrain)) => photo.uv_transform(&terrain),
+            _ => (Vec2::ONE, Vec2::ZERO),
+        };
+        Drape {
+            texture,
+            _memory: resources::track(
+                ResourceKind::Texture,
+                texture_bytes(gl::SRGB8_ALPHA8, width, height, levels),
+            ),
+            uv_scale,
+            uv_offset,
+        }
+    }
+}
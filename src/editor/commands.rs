@@ -0,0 +1,258 @@
+//! Every editor command in one place, with its name and key binding. The command
+//! palette lists them and the key bindings look them up here, so a command that's
+//! added once shows up in both.
+
+use egui::{InputState, Key};
+
+use crate::editor::gui::{Action, TOOLS};
+use crate::filters::HeightFilter;
+use crate::terrain::{BoundsChange, TerrainParams};
+use crate::viewport::ViewportLayout;
+use crate::{EditorState, TerrainTool};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    QuickOpen,
+    CommandPalette,
+    SaveTerrain,
+    SaveNormalMap,
+    SaveSplatMap,
+    SaveAmbientOcclusionMap,
+    SaveCamera,
+    TakeScreenshot,
+    NewProject,
+    CropExtend,
+    ExportArchive,
+    ImportArchive,
+    Erode,
+    Blur,
+    Terrace,
+    Despeckle,
+    FillSinks,
+    Weather,
+    ToggleSnap,
+    TogglePhotoMode,
+    ToggleReflections,
+    ToggleDayCycle,
+    ToggleWindArrows,
+    ToggleHypsometric,
+    SaveHypsometricMap,
+    ShowTutorial,
+    Layout(ViewportLayout),
+    Tool(TerrainTool),
+    Quit,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct KeyBinding {
+    pub ctrl: bool,
+    pub shift: bool,
+    pub key: Key,
+}
+
+impl KeyBinding {
+    const fn key(key: Key) -> Self {
+        KeyBinding {
+            ctrl: false,
+            shift: false,
+            key,
+        }
+    }
+
+    const fn ctrl(key: Key) -> Self {
+        KeyBinding {
+            ctrl: true,
+            shift: false,
+            key,
+        }
+    }
+
+    const fn ctrl_shift(key: Key) -> Self {
+        KeyBinding {
+            ctrl: true,
+            shift: true,
+            key,
+        }
+    }
+
+    /// On release, so that key repeat doesn't run the command over and over
+    pub fn released(&self, input: &InputState) -> bool {
+        input.modifiers.ctrl == self.ctrl
+            && input.modifiers.shift == self.shift
+            && input.key_released(self.key)
+    }
+
+    pub fn name(&self) -> String {
+        let key = format!("{:?}", self.key);
+        // Num1 reads better as 1
+        let key = key.strip_prefix("Num").unwrap_or(&key);
+        match (self.ctrl, self.shift) {
+            (true, true) => format!("Ctrl+Shift+{}", key),
+            (true, false) => format!("Ctrl+{}", key),
+            (false, true) => format!("Shift+{}", key),
+            (false, false) => key.to_owned(),
+        }
+    }
+}
+
+pub struct CommandInfo {
+    pub command: Command,
+    pub name: String,
+    pub binding: Option<KeyBinding>,
+}
+
+impl CommandInfo {
+    fn new(command: Command, name: &str, binding: Option<KeyBinding>) -> Self {
+        CommandInfo {
+            command,
+            name: name.to_owned(),
+            binding,
+        }
+    }
+}
+
+/// Number keys pick the tools in the order of their buttons
+const TOOL_KEYS: [Key; TOOLS.len()] = [
+    Key::Num1,
+    Key::Num2,
+    Key::Num3,
+    Key::Num4,
+    Key::Num5,
+    Key::Num6,
+    Key::Num7,
+    Key::Num8,
+    Key::Num9,
+    Key::Num0,
+];
+
+pub fn all() -> Vec<CommandInfo> {
+    use Command::*;
+
+    let mut commands = vec![
+        CommandInfo::new(QuickOpen, "Quick open", Some(KeyBinding::ctrl(Key::P))),
+        CommandInfo::new(
+            CommandPalette,
+            "Command palette",
+            Some(KeyBinding::ctrl_shift(Key::P)),
+        ),
+        CommandInfo::new(SaveTerrain, "Save terrain", Some(KeyBinding::ctrl(Key::S))),
+        CommandInfo::new(SaveNormalMap, "Save normal map", None),
+        CommandInfo::new(SaveSplatMap, "Save splat map", None),
+        CommandInfo::new(SaveAmbientOcclusionMap, "Save AO map", None),
+        CommandInfo::new(SaveCamera, "Save camera position", None),
+        CommandInfo::new(TakeScreenshot, "Save screenshot", None),
+        CommandInfo::new(NewProject, "New project...", Some(KeyBinding::ctrl(Key::N))),
+        CommandInfo::new(CropExtend, "Crop / extend...", None),
+        CommandInfo::new(
+            ExportArchive,
+            "Export archive",
+            Some(KeyBinding::ctrl(Key::E)),
+        ),
+        CommandInfo::new(ImportArchive, "Import archive", None),
+        CommandInfo::new(Erode, "Erode", None),
+        CommandInfo::new(Blur, "Filter: blur", None),
+        CommandInfo::new(Terrace, "Filter: terrace", None),
+        CommandInfo::new(Despeckle, "Filter: despeckle", None),
+        CommandInfo::new(FillSinks, "Filter: fill sinks", None),
+        CommandInfo::new(Weather, "Filter: weathering", None),
+        CommandInfo::new(ToggleSnap, "Toggle snapping", Some(KeyBinding::key(Key::G))),
+        CommandInfo::new(TogglePhotoMode, "Toggle photo mode", None),
+        CommandInfo::new(ToggleReflections, "Toggle screen-space reflections", None),
+        CommandInfo::new(ToggleDayCycle, "Play / pause the day cycle", None),
+        CommandInfo::new(ToggleWindArrows, "Toggle wind arrows", None),
+        CommandInfo::new(ToggleHypsometric, "Toggle elevation tints", None),
+        CommandInfo::new(SaveHypsometricMap, "Save elevation tint map", None),
+        CommandInfo::new(ShowTutorial, "Help: Show tutorial", None),
+    ];
+    for layout in ViewportLayout::ALL {
+        let name = format!("Viewports: {}", layout.name());
+        commands.push(CommandInfo::new(Layout(layout), &name, None));
+    }
+    for ((tool, name), key) in TOOLS.iter().zip(TOOL_KEYS) {
+        let name = format!("Tool: {}", name);
+        commands.push(CommandInfo::new(
+            Tool(*tool),
+            &name,
+            Some(KeyBinding::key(key)),
+        ));
+    }
+    commands.push(CommandInfo::new(
+        Tool(TerrainTool::SmoothAlong),
+        "Tool: Smooth along stroke",
+        None,
+    ));
+    commands.push(CommandInfo::new(
+        Tool(TerrainTool::ErodeUnderBrush),
+        "Tool: Erosion brush",
+        None,
+    ));
+    commands.push(CommandInfo::new(
+        Tool(TerrainTool::FindPath),
+        "Tool: Walkable path",
+        None,
+    ));
+    commands.push(CommandInfo::new(
+        Tool(TerrainTool::Viewshed),
+        "Tool: Line of sight",
+        None,
+    ));
+    commands.push(CommandInfo::new(
+        Quit,
+        "Quit",
+        Some(KeyBinding::ctrl(Key::Q)),
+    ));
+    commands
+}
+
+/// Everything but opening the palettes, which the GUI does itself
+pub fn run(command: Command, editor_state: &mut EditorState, actions: &mut Vec<Action>) {
+    match command {
+        Command::QuickOpen | Command::CommandPalette => {}
+        Command::SaveTerrain => actions.push(Action::SaveTerrain),
+        Command::SaveNormalMap => actions.push(Action::SaveNormalMap),
+        Command::SaveSplatMap => actions.push(Action::SaveSplatMap),
+        Command::SaveAmbientOcclusionMap => actions.push(Action::SaveAmbientOcclusionMap),
+        Command::SaveCamera => actions.push(Action::SaveCamera),
+        Command::TakeScreenshot => actions.push(Action::TakeScreenshot),
+        Command::NewProject => editor_state.new_project = Some(TerrainParams::default()),
+        Command::CropExtend => editor_state.bounds_change = Some(BoundsChange::default()),
+        Command::ExportArchive => {
+            actions.push(Action::ExportArchive(editor_state.archive_path.clone()))
+        }
+        Command::ImportArchive => {
+            actions.push(Action::ImportArchive(editor_state.archive_path.clone()))
+        }
+        Command::Erode => actions.push(Action::Erode(editor_state.erosion)),
+        Command::Blur => actions.push(Action::ApplyFilter(HeightFilter::Blur {
+            radius: editor_state.filters.blur_radius,
+            edges: editor_state.edges,
+        })),
+        Command::Terrace => actions.push(Action::ApplyFilter(HeightFilter::Terrace {
+            steps: editor_state.filters.terrace_steps,
+            sharpness: editor_state.filters.terrace_sharpness,
+        })),
+        Command::Despeckle => actions.push(Action::ApplyFilter(HeightFilter::Despeckle {
+            radius: editor_state.filters.despeckle_radius,
+            threshold: editor_state.filters.despeckle_threshold,
+            edges: editor_state.edges,
+        })),
+        Command::FillSinks => actions.push(Action::ApplyFilter(HeightFilter::FillSinks)),
+        Command::Weather => actions.push(Action::Weather(editor_state.weathering)),
+        Command::ToggleSnap => actions.push(Action::ToggleSnap),
+        Command::TogglePhotoMode => editor_state.photo.enabled = !editor_state.photo.enabled,
+        Command::ToggleReflections => {
+            editor_state.reflections.enabled = !editor_state.reflections.enabled
+        }
+        Command::ToggleDayCycle => editor_state.day.playing = !editor_state.day.playing,
+        Command::ToggleWindArrows => editor_state.wind.show_arrows = !editor_state.wind.show_arrows,
+        Command::ToggleHypsometric => {
+            let hypsometric = &mut editor_state.hypsometric;
+            hypsometric.enabled = !hypsometric.enabled;
+        }
+        Command::SaveHypsometricMap => actions.push(Action::SaveHypsometricMap),
+        Command::ShowTutorial => actions.push(Action::ShowTutorial),
+        Command::Layout(layout) => actions.push(Action::SetViewportLayout(layout)),
+        Command::Tool(tool) => actions.push(Action::SelectTool(tool)),
+        Command::Quit => actions.push(Action::Quit),
+    }
+}
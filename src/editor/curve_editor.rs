@@ -0,0 +1,99 @@
+//! A curves widget for remapping heights: drag the points to reshape the curve,
+//! click somewhere else to add one and right click a point to remove it.
+
+use egui::{Color32, Pos2, Sense, Shape, Stroke, Vec2};
+
+use crate::filters::HeightCurve;
+
+/// Pixels around a point that still grab it
+const GRAB_RADIUS: f32 = 8.0;
+
+/// Line segments the curve is drawn with
+const CURVE_SEGMENTS: usize = 64;
+
+#[derive(Default)]
+pub struct CurveEditor {
+    /// Index of the point being dragged
+    dragging: Option<usize>,
+}
+
+impl CurveEditor {
+    /// True if the curve changed
+    pub fn show(&mut self, ui: &mut egui::Ui, curve: &mut HeightCurve) -> bool {
+        let size = Vec2::splat(ui.available_width().min(240.0));
+        let (rect, response) = ui.allocate_exact_size(size, Sense::click_and_drag());
+        // Heights in go left to right, heights out bottom to top
+        let to_screen = |(x, y): (f32, f32)| {
+            Pos2::new(
+                rect.left() + x * rect.width(),
+                rect.bottom() - y * rect.height(),
+            )
+        };
+        let from_screen = |pos: Pos2| {
+            (
+                (pos.x - rect.left()) / rect.width(),
+                (rect.bottom() - pos.y) / rect.height(),
+            )
+        };
+        let closest = |curve: &HeightCurve, pos: Pos2| {
+            curve
+                .points()
+                .iter()
+                .position(|&point| to_screen(point).distance(pos) < GRAB_RADIUS)
+        };
+
+        let mut changed = false;
+        if let Some(pos) = response.interact_pointer_pos() {
+            let (x, y) = from_screen(pos);
+            if response.drag_started() {
+                self.dragging = closest(curve, pos).or_else(|| curve.insert(x, y));
+            }
+            if response.dragged() {
+                if let Some(index) = self.dragging {
+                    curve.move_point(index, x, y);
+                    changed = true;
+                }
+            }
+            if response.clicked() && closest(curve, pos).is_none() {
+                changed |= curve.insert(x, y).is_some();
+            }
+            if response.secondary_clicked() {
+                if let Some(index) = closest(curve, pos) {
+                    curve.remove(index);
+                    changed = true;
+                }
+            }
+        }
+        if response.drag_released() {
+            self.dragging = None;
+        }
+
+        let painter = ui.painter_at(rect);
+        painter.rect_filled(rect, 0.0, Color32::from_gray(24));
+        let grid = Stroke::new(1.0, Color32::from_gray(48));
+        for i in 1..4 {
+            let t = i as f32 / 4.0;
+            painter.line_segment([to_screen((t, 0.0)), to_screen((t, 1.0))], grid);
+            painter.line_segment([to_screen((0.0, t)), to_screen((1.0, t))], grid);
+        }
+        // Where the curve would leave the terrain as it is
+        painter.line_segment([to_screen((0.0, 0.0)), to_screen((1.0, 1.0))], grid);
+
+        let line = (0..=CURVE_SEGMENTS)
+            .map(|i| {
+                let x = i as f32 / CURVE_SEGMENTS as f32;
+                to_screen((x, curve.evaluate(x)))
+            })
+            .collect();
+        painter.add(Shape::line(line, Stroke::new(2.0, Color32::WHITE)));
+        for (i, &point) in curve.points().iter().enumerate() {
+            let color = if self.dragging == Some(i) {
+                Color32::from_rgb(120, 200, 255)
+            } else {
+                Color32::WHITE
+            };
+            painter.circle_filled(to_screen(point), 4.0, color);
+        }
+        changed
+    }
+}
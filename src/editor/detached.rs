@@ -0,0 +1,161 @@
+//! GUI panels moved out of the main window into their own OS windows, so they can
+//! sit on another monitor. Every window has its own GL context sharing objects with
+//! the main one, and its own egui instance.
+
+use std::ops::Deref;
+
+use egui_winit::State as EguiState;
+use glam::Vec2;
+use glutin::dpi::LogicalSize;
+use glutin::event::WindowEvent;
+use glutin::event_loop::EventLoopWindowTarget;
+use glutin::window::{WindowBuilder, WindowId};
+use glutin::{Api, ContextBuilder, GlProfile, GlRequest, Robustness};
+use glutin::{PossiblyCurrent, WindowedContext};
+
+use crate::editor::gui::{Action, Gui, Panel, ViewInfo};
+use crate::opengl::caps;
+use crate::{EditorState, Result};
+
+pub struct DetachedPanel {
+    pub panel: Panel,
+    // Dropped before the context, see `close`
+    gui: Gui,
+    gui_state: EguiState,
+    context: ContextSlot,
+    ui_scale: f32,
+    /// The window was closed, the panel goes back into the main window
+    pub closed: bool,
+}
+
+impl DetachedPanel {
+    /// Leaves the new window's context current
+    pub fn open(
+        panel: Panel,
+        event_loop: &EventLoopWindowTarget<()>,
+        main_context: &WindowedContext<PossiblyCurrent>,
+        ui_scale: f32,
+    ) -> Result<Self> {
+        let window_builder = WindowBuilder::new()
+            .with_title(panel.name())
+            .with_inner_size(LogicalSize::new(420, 900))
+            // The GUI assumes the size never changes
+            .with_resizable(false);
+        let context = ContextBuilder::new()
+            .with_gl(GlRequest::Specific(Api::OpenGl, caps::get().version))
+            .with_gl_profile(GlProfile::Core)
+            // Has to be the same as the main context's to share with it
+            .with_gl_robustness(Robustness::TryRobustLoseContextOnReset)
+            .with_srgb(true)
+            .with_double_buffer(Some(true))
+            // Waiting for every window's vblank in turn would slow the main one down
+            .with_vsync(false)
+            .with_shared_lists(main_context.context())
+            .build_windowed(window_builder, event_loop)?;
+        let context = unsafe { context.make_current() }.map_err(|(_, error)| error)?;
+        unsafe {
+            gl::ClearColor(0.05, 0.05, 0.05, 1.0);
+            gl::Enable(gl::FRAMEBUFFER_SRGB);
+        }
+
+        let size = context.window().inner_size();
+        let gui = Gui::new(Vec2::new(size.width as f32, size.height as f32))?;
+        let gui_state = EguiState::new(context.window());
+        let mut detached = DetachedPanel {
+            panel,
+            gui,
+            gui_state,
+            context: ContextSlot::new(context),
+            ui_scale,
+            closed: false,
+        };
+        detached.set_ui_scale(ui_scale);
+        Ok(detached)
+    }
+
+    /// The window may be on a monitor with a different scale factor than the main one
+    pub fn set_ui_scale(&mut self, ui_scale: f32) {
+        self.ui_scale = ui_scale;
+        let scale_factor = self.context.window().scale_factor() as f32;
+        self.gui_state.set_pixels_per_point(scale_factor * ui_scale);
+    }
+
+    pub fn window_id(&self) -> WindowId {
+        self.context.window().id()
+    }
+
+    pub fn on_event(&mut self, event: &WindowEvent) {
+        self.gui_state.on_event(self.gui.ctx(), event);
+        match event {
+            WindowEvent::CloseRequested => self.closed = true,
+            // egui goes back to the OS scale
+            WindowEvent::ScaleFactorChanged { .. } => self.set_ui_scale(self.ui_scale),
+            _ => {}
+        }
+    }
+
+    /// Lays out and draws the panel, leaving this window's context current
+    pub fn update(
+        &mut self,
+        view: &ViewInfo,
+        editor_state: &mut EditorState,
+    ) -> Result<Vec<Action>> {
+        self.context.make_current()?;
+        let actions = self.gui.layout_panel(
+            &mut self.gui_state,
+            self.context.window(),
+            self.panel,
+            view,
+            editor_state,
+        );
+        let size = self.context.window().inner_size();
+        unsafe {
+            gl::Viewport(0, 0, size.width as i32, size.height as i32);
+            gl::Clear(gl::COLOR_BUFFER_BIT);
+        }
+        self.gui.draw();
+        self.context.swap_buffers()?;
+        Ok(actions)
+    }
+
+    /// Vertex arrays aren't shared between contexts, so the GUI has to be dropped
+    /// with its own context current. Leaves no context current.
+    pub fn close(mut self) -> Result<()> {
+        self.context.make_current()?;
+        drop(self.gui);
+        Ok(())
+    }
+}
+
+/// A context that lives in a struct field. glutin's make_current takes the context
+/// by value and gives it back either way, the slot is only empty in between.
+pub struct ContextSlot(Option<WindowedContext<PossiblyCurrent>>);
+
+impl ContextSlot {
+    pub fn new(context: WindowedContext<PossiblyCurrent>) -> Self {
+        ContextSlot(Some(context))
+    }
+
+    pub fn make_current(&mut self) -> Result<()> {
+        let context = self
+            .0
+            .take()
+            .expect("Context lost in an earlier make_current");
+        let (context, result) = match unsafe { context.make_current() } {
+            Ok(context) => (context, Ok(())),
+            Err((context, error)) => (context, Err(error)),
+        };
+        self.0 = Some(context);
+        Ok(result?)
+    }
+}
+
+impl Deref for ContextSlot {
+    type Target = WindowedContext<PossiblyCurrent>;
+
+    fn deref(&self) -> &Self::Target {
+        self.0
+            .as_ref()
+            .expect("Context lost in an earlier make_current")
+    }
+}
@@ -1,36 +1,268 @@
 use std::mem::size_of;
 
-use egui::{Align2, ClippedMesh, CtxRef, LayerId, Output};
+use egui::{Align2, ClippedMesh, CtxRef, LayerId, Output, Pos2, Rect, Stroke};
 use egui_gizmo::{Gizmo, GizmoMode, GizmoOrientation, GizmoVisuals};
 use egui_winit::State;
 use epaint::Color32;
 use gl::types::*;
-use glam::{Mat4, Vec2};
+use glam::{Mat4, Vec2, Vec3};
 use glutin::window::Window;
 use memoffset::offset_of;
 
+use crate::audio::EmitterKind;
+use crate::biome::{ScatterRule, SplatRule};
+use crate::codec::{self, LOSSLESS_BITS, MIN_BITS};
+use crate::derived_cache;
+use crate::editor::commands::{self, Command};
+use crate::editor::palette::{Palette, PaletteMode};
+use crate::editor::tutorial::Tutorial;
+use crate::erosion::ErosionSettings;
+use crate::export_hooks::{ExportProfile, PATH_PLACEHOLDER};
+use crate::external::ExternalTool;
+use crate::filters::{FilterKind, HeightCurve, HeightFilter};
+use crate::flood::FloodSource;
+use crate::gradient::{ColorGradient, MAX_GRADIENT_STOPS};
+use crate::heightfield::{EdgeFill, EdgeMode};
+use crate::opengl::caps;
+use crate::opengl::objects::{Buffer, Texture, VertexArray};
+use crate::postprocess::ReflectionQuality;
+use crate::prop_paint::GroundBlend;
+use crate::resources::{self, ResourceKind};
+use crate::season::{Season, SeasonalColor};
+use crate::session_stats::STATS_PATH;
+use crate::splat::{default_layers, PaintBlendMode};
+use crate::storage::StorageKind;
+use crate::terrain::{
+    BoundsChange, ErosionSplat, FitSettings, SnapTarget, TerrainMesh, TerrainParams, RESOLUTIONS,
+};
+use crate::viewport::ViewportLayout;
+use crate::voids::VoidFill;
+use crate::weathering::WeatheringSettings;
+use crate::wind::{Wind, MAX_WIND_ZONES};
 use crate::{opengl::shader::Program, texture::unit_to_gl_const, utils::size_of_slice, Result};
+use crate::{EditorState, TerrainTool};
+
+/// The tools there's a button for, with its label
+pub const TOOLS: [(TerrainTool, &str); 10] = [
+    (TerrainTool::Sculpt, "Sculpt"),
+    (TerrainTool::PaintTextures, "Paint"),
+    (TerrainTool::PaintVegetation, "Scatter"),
+    (TerrainTool::CaptureStamp, "Capture"),
+    (TerrainTool::CapturePrefab, "Group"),
+    (TerrainTool::PlacePrefab, "Place"),
+    (TerrainTool::PaintBiomes, "Biome"),
+    (TerrainTool::Wind, "Wind"),
+    (TerrainTool::PlaceSound, "Sound"),
+    (TerrainTool::PaintTint, "Tint"),
+];
+
+/// GUI windows that can be detached into their own OS windows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Panel {
+    Tools,
+    Stats,
+}
+
+impl Panel {
+    pub fn name(self) -> &'static str {
+        match self {
+            Panel::Tools => "Tools",
+            Panel::Stats => "Stats",
+        }
+    }
+}
 
 /// An action to take as a result of interacting with the GUI
 pub enum Action {
     SaveTerrain,
     SaveCamera,
+    SetViewportLayout(ViewportLayout),
+    TakeScreenshot,
+    LoadLut(String),
+    SelectTool(TerrainTool),
+    NewProject(TerrainParams),
+    ResampleTerrain(u32),
+    /// Sculpt on a copy of the heights downsampled to this resolution
+    BeginProxy(u32),
+    /// Bring what's been sculpted on the proxy back to full resolution
+    EndProxy,
+    /// Adds the detail layer to the heightmap and empties it
+    BakeDetail,
+    ClearDetail,
+    ChangeBounds(BoundsChange),
+    Erode(ErosionSettings),
+    Weather(WeatheringSettings),
+    ApplyFilter(HeightFilter),
+    /// Shows the filter on the terrain without applying it
+    PreviewFilter(HeightFilter),
+    EndFilterPreview,
+    UndoFilter,
+    UndoStroke,
+    /// PNG or KTX2, whichever `compressed_maps` says, as the splat and AO maps
+    SaveNormalMap,
+    SaveSplatMap,
+    SaveAmbientOcclusionMap,
+    SaveHypsometricMap,
+    ExportArchive(String),
+    ImportArchive(String),
+    /// Keeps the storage settings for the next session
+    SaveStorage,
+    /// Into the project's config
+    SaveExportHooks,
+    ClearDerivedCache,
+    LoadReference(String),
+    ClearReference,
+    LoadDrape(String),
+    LoadGroundTexture(String),
+    ClearDrape,
+    /// Stops showing where the imported heightmap had no data
+    DismissImportVoids,
+    /// Roads, water and forests from an OpenStreetMap extract
+    ImportOsm(String),
+    /// Replaces the terrain with a World Machine or Gaea build folder
+    ImportBuild(String, ExternalTool),
+    DismissImportFlow,
+    BlendWithReference(f32),
+    ScatterCliffRocks,
+    ClearCliffRocks,
+    /// Looks for the walkable path again between the same two points
+    FindPath,
+    /// Works out the viewshed again from the same point
+    UpdateViewshed,
+    ClearViewshed,
+    /// Works out the sun hours again if they're shown already
+    ShowSunHours,
+    HideSunHours,
+    /// Also after the settings or the terrain have changed
+    FindBuildable,
+    HideBuildable,
+    SaveBuildableMask,
+    /// Also after the settings or the terrain have changed
+    ShowFlood,
+    HideFlood,
+    AddScatterType,
+    RemoveScatterType(usize),
+    ClearScatterType(usize),
+    AutoTexture,
+    AutoScatter,
+    DrapeObject(f32),
+    FitTerrain(FitSettings),
+    BlendObjectIntoGround(GroundBlend),
+    ClearObjectPaint,
+    RestoreObjectShape,
+    ReplayJournal,
+    ClearJournal,
+    SaveJournal,
+    LoadJournal,
+    StartRecordingInput(String),
+    StopRecordingInput,
+    PlayInput(String),
+    StopPlayingInput,
+    HostSession,
+    JoinSession,
+    LeaveSession,
     Quit,
+    /// From the unsaved changes dialog
+    SaveAndQuit,
+    DiscardAndQuit,
+    ShowTutorial,
+    /// Finished or dismissed, so it doesn't come up on the next start
+    FinishTutorial,
+    DetachPanel(Panel),
+    AttachPanel(Panel),
+    SetUiScale(f32),
+    SetSessionStats(bool),
+    ToggleSnap,
+    ImportHeightmap(String, Option<VoidFill>),
+}
+
+/// What the GUI needs to know about the editor but doesn't change directly
+pub struct ViewInfo {
+    pub view_matrix: Mat4,
+    pub projection_matrix: Mat4,
+    /// The gizmo is only shown in the main view
+    pub gizmo_clip_rect: Rect,
+    pub viewport_layout: ViewportLayout,
+    pub tool: Option<TerrainTool>,
+    pub terrain: TerrainParams,
+    /// Saves and loads still running in the background
+    pub io_pending: usize,
+    /// Vsync can be turned off and on without a restart
+    pub vsync_supported: bool,
+    /// Panels shown in their own windows instead of the main one
+    pub detached_panels: Vec<Panel>,
+    /// Set while editing together with others
+    pub sync_status: Option<String>,
+    /// Operations recorded so far
+    pub journal_len: usize,
+    pub recording_input: bool,
+    pub playing_input: bool,
+    /// The heightmap on disk is behind the terrain
+    pub unsaved_changes: bool,
+    /// Edits made this session, every brush step counts
+    pub edits: u64,
+    /// What the terrain is showing instead of its actual heights
+    pub filter_preview: Option<HeightFilter>,
+    pub can_undo_filter: bool,
+    /// A brush stroke has ended and not been undone
+    pub can_undo_stroke: bool,
+    /// There's a heightmap to compare with
+    pub has_reference: bool,
+    /// There's a photo draped over the terrain
+    pub has_drape: bool,
+    /// Sculpting happens on a downsampled copy of this resolution
+    pub proxy_resolution: Option<usize>,
+    /// Carved detail tiles that haven't been baked into the heightmap
+    pub detail_tiles: usize,
+    /// The brush is small enough to carve detail
+    pub brush_fits_detail: bool,
+    /// Share of the imported heightmap that had no data, until it's dismissed
+    pub import_voids: Option<f32>,
+    /// The imported build had a flow map
+    pub import_flow: bool,
+    /// The terrain came from a GeoTIFF, so georeferenced photos can be lined up with it
+    pub georeferenced: bool,
+    /// World space point under the brush, on the terrain
+    pub cursor: Option<Vec3>,
+    /// The same in the terrain's projected coordinate system
+    pub cursor_geo: Option<(f64, f64)>,
+    /// Degrees north and east, if the coordinate system can be converted
+    pub cursor_lat_long: Option<(f64, f64)>,
+    /// How many rocks are scattered over the cliffs, if any
+    pub cliff_rocks: Option<usize>,
+    /// Painted instances of every scatter type
+    pub scatter_counts: Vec<usize>,
+    /// World space eyes of the observer the viewshed is for, and the share of the
+    /// terrain they can see
+    pub viewshed: Option<(Vec3, f32)>,
+    /// Regions that can be built on, their total area and the largest one's in square
+    /// meters, while they're shown
+    pub buildable: Option<(usize, f32, f32)>,
+    /// Share of the terrain under water and meters of the deepest water, while the
+    /// flood is shown
+    pub flood: Option<(f32, f32)>,
+    pub sun_hours_shown: bool,
+    /// Hours of sun the terrain under the brush gets over a day, while they're shown
+    pub sun_hours_at_cursor: Option<f32>,
+    /// World space start and end of the wind direction arrows
+    pub wind_arrows: Vec<(Vec3, Vec3)>,
 }
 
 pub struct Gui {
     screen_size: Vec2,
 
     ctx: CtxRef,
-    egui_texture: GLuint,
+    palette: Palette,
+    tutorial: Tutorial,
+    egui_texture: Option<Texture>,
     egui_texture_version: Option<u64>,
 
     shader: Program,
 
     // OpenGL buffers
-    vao: GLuint,
-    vbo: GLuint,
-    ebo: GLuint,
+    vao: VertexArray,
+    vbo: Buffer,
+    ebo: Buffer,
     vertex_buffer_size: usize,
     index_buffer_size: usize,
     index_count: i32,
@@ -39,34 +271,29 @@ pub struct Gui {
 impl Gui {
     // Note: assuming non-resizable window for now
     pub fn new(screen_size: Vec2) -> Result<Gui> {
-        let mut vao: GLuint = 0;
-        let mut vbo: GLuint = 0;
-        let mut ebo: GLuint = 0;
+        let vao = VertexArray::new("GUI");
+        let vbo = Buffer::new("GUI vertices");
+        let ebo = Buffer::new("GUI indices");
 
         // Initial size
         let vertex_buffer_size = 1024 * 1024;
         let index_buffer_size = 1024 * 1024;
 
         unsafe {
-            // Create objects
-            gl::CreateVertexArrays(1, &mut vao);
-            gl::CreateBuffers(1, &mut vbo);
-            gl::CreateBuffers(1, &mut ebo);
-
             // Attach buffers to vao
-            gl::VertexArrayVertexBuffer(vao, 0, vbo, 0, size_of::<Vertex>() as i32);
-            gl::VertexArrayElementBuffer(vao, ebo);
+            gl::VertexArrayVertexBuffer(vao.id(), 0, vbo.id(), 0, size_of::<Vertex>() as i32);
+            gl::VertexArrayElementBuffer(vao.id(), ebo.id());
 
             // Allocate some initial storage for the buffers with the hope that
             // it won't have to reallocate often
             gl::NamedBufferStorage(
-                vbo,
+                vbo.id(),
                 vertex_buffer_size as isize,
                 std::ptr::null(),
                 gl::DYNAMIC_STORAGE_BIT,
             );
             gl::NamedBufferStorage(
-                ebo,
+                ebo.id(),
                 index_buffer_size as isize,
                 std::ptr::null(),
                 gl::DYNAMIC_STORAGE_BIT,
@@ -74,7 +301,7 @@ impl Gui {
 
             // Position
             gl::VertexArrayAttribFormat(
-                vao,
+                vao.id(),
                 0,
                 2,
                 gl::FLOAT,
@@ -84,7 +311,7 @@ impl Gui {
 
             // UV
             gl::VertexArrayAttribFormat(
-                vao,
+                vao.id(),
                 1,
                 2,
                 gl::FLOAT,
@@ -94,7 +321,7 @@ impl Gui {
 
             // Color
             gl::VertexArrayAttribFormat(
-                vao,
+                vao.id(),
                 2,
                 4,
                 gl::UNSIGNED_BYTE,
@@ -102,13 +329,13 @@ impl Gui {
                 offset_of!(Vertex, srgba) as u32,
             );
 
-            gl::EnableVertexArrayAttrib(vao, 0);
-            gl::EnableVertexArrayAttrib(vao, 1);
-            gl::EnableVertexArrayAttrib(vao, 2);
+            gl::EnableVertexArrayAttrib(vao.id(), 0);
+            gl::EnableVertexArrayAttrib(vao.id(), 1);
+            gl::EnableVertexArrayAttrib(vao.id(), 2);
 
-            gl::VertexArrayAttribBinding(vao, 0, 0);
-            gl::VertexArrayAttribBinding(vao, 1, 0);
-            gl::VertexArrayAttribBinding(vao, 2, 0);
+            gl::VertexArrayAttribBinding(vao.id(), 0, 0);
+            gl::VertexArrayAttribBinding(vao.id(), 1, 0);
+            gl::VertexArrayAttribBinding(vao.id(), 2, 0);
         }
 
         let shader = Program::new()
@@ -120,7 +347,9 @@ impl Gui {
             screen_size,
 
             ctx: CtxRef::default(),
-            egui_texture: 0, // will be created before draw
+            palette: Palette::default(),
+            tutorial: Tutorial::default(),
+            egui_texture: None, // will be created before draw
             egui_texture_version: None,
 
             shader,
@@ -138,6 +367,10 @@ impl Gui {
         &self.ctx
     }
 
+    pub fn start_tutorial(&mut self) {
+        self.tutorial.start();
+    }
+
     pub fn wants_input(&self) -> bool {
         self.ctx.wants_pointer_input() || self.ctx.wants_keyboard_input()
     }
@@ -146,9 +379,9 @@ impl Gui {
         &mut self,
         state: &mut State,
         window: &Window,
-        view_matrix: &Mat4,
-        projection_matrix: &Mat4,
         model_matrix: &mut Mat4,
+        view: &ViewInfo,
+        editor_state: &mut EditorState,
     ) -> Vec<Action> {
         let input = state.take_egui_input(window);
         self.ctx.begin_frame(input);
@@ -156,30 +389,261 @@ impl Gui {
 
         // ================== GUI starts ========================
 
-        egui::Window::new("Tools")
-            .anchor(Align2::RIGHT_TOP, egui::Vec2::new(-10.0, 10.0))
-            .resizable(false)
-            .show(&self.ctx, |ui| {
-                if ui.button("Save terrain").clicked() {
-                    actions.push(Action::SaveTerrain);
-                }
+        let mut tools_rect = None;
+        if !view.detached_panels.contains(&Panel::Tools) {
+            tools_rect = egui::Window::new("Tools")
+                .anchor(Align2::RIGHT_TOP, egui::Vec2::new(-10.0, 10.0))
+                .resizable(false)
+                .show(&self.ctx, |ui| {
+                    if ui.small_button("Detach").clicked() {
+                        actions.push(Action::DetachPanel(Panel::Tools));
+                    }
+                    tools_panel(ui, view, editor_state, &mut actions);
+                })
+                .map(|window| window.response.rect);
+        }
 
-                if ui.button("Save camera position").clicked() {
-                    actions.push(Action::SaveCamera);
-                }
-            });
+        if !view.detached_panels.contains(&Panel::Stats) {
+            egui::Window::new("Stats")
+                .default_open(false)
+                .show(&self.ctx, |ui| {
+                    if ui.small_button("Detach").clicked() {
+                        actions.push(Action::DetachPanel(Panel::Stats));
+                    }
+                    stats_panel(ui, view, editor_state, &mut actions);
+                });
+        }
+
+        if let Some(params) = &mut editor_state.new_project {
+            let mut open = true;
+            let mut create = false;
+            egui::Window::new("New project")
+                .open(&mut open)
+                .resizable(false)
+                .collapsible(false)
+                .anchor(Align2::CENTER_CENTER, egui::Vec2::ZERO)
+                .show(&self.ctx, |ui| {
+                    ui.add(
+                        egui::DragValue::new(&mut params.world_size)
+                            .clamp_range(64.0..=16384.0)
+                            .speed(16.0)
+                            .prefix("World size: ")
+                            .suffix(" m"),
+                    );
+                    egui::ComboBox::from_label("Heightmap resolution")
+                        .selected_text(params.resolution.to_string())
+                        .show_ui(ui, |ui| {
+                            for resolution in RESOLUTIONS {
+                                ui.selectable_value(
+                                    &mut params.resolution,
+                                    resolution,
+                                    resolution.to_string(),
+                                );
+                            }
+                        });
+                    ui.add(
+                        egui::DragValue::new(&mut params.max_height)
+                            .clamp_range(1.0..=8000.0)
+                            .speed(1.0)
+                            .prefix("Max height: ")
+                            .suffix(" m"),
+                    );
+                    ui.horizontal(|ui| {
+                        ui.add(egui::DragValue::new(&mut params.seed).prefix("Seed: "));
+                        if ui.button("Random").clicked() {
+                            params.seed = random_seed();
+                        }
+                    });
+                    ui.label(format!(
+                        "{:.2} m per texel",
+                        params.world_size / params.resolution as f32
+                    ));
+                    ui.label("Unsaved changes to the current terrain will be lost");
+                    if ui
+                        .add_enabled(params.is_valid(), egui::Button::new("Create"))
+                        .clicked()
+                    {
+                        create = true;
+                    }
+                });
+            if create {
+                actions.push(Action::NewProject(*params));
+            }
+            if create || !open {
+                editor_state.new_project = None;
+            }
+        }
+
+        if let Some(path) = &editor_state.dropped_heightmap {
+            let mut open = true;
+            let mut import = false;
+            let repair = &mut editor_state.void_repair;
+            egui::Window::new("Import heightmap")
+                .open(&mut open)
+                .resizable(false)
+                .collapsible(false)
+                .anchor(Align2::CENTER_CENTER, egui::Vec2::ZERO)
+                .show(&self.ctx, |ui| {
+                    ui.label(path);
+                    ui.label("Replaces the terrain's heights, this can't be undone");
+                    ui.horizontal(|ui| {
+                        ui.checkbox(&mut repair.enabled, "Fill voids")
+                            .on_hover_text("Cells a DEM has no data for, e.g. SRTM's -32768");
+                        if !repair.enabled {
+                            return;
+                        }
+                        egui::ComboBox::from_id_source("Void fill")
+                            .selected_text(repair.method.name())
+                            .show_ui(ui, |ui| {
+                                for method in VoidFill::ALL {
+                                    ui.selectable_value(&mut repair.method, method, method.name());
+                                }
+                            });
+                    });
+                    if ui.button("Import").clicked() {
+                        import = true;
+                    }
+                });
+            if import {
+                let fill = Some(repair.method).filter(|_| repair.enabled);
+                actions.push(Action::ImportHeightmap(path.clone(), fill));
+            }
+            if import || !open {
+                editor_state.dropped_heightmap = None;
+            }
+        }
+
+        if editor_state.confirm_exit {
+            let mut close = false;
+            egui::Window::new("Unsaved changes")
+                .resizable(false)
+                .collapsible(false)
+                .anchor(Align2::CENTER_CENTER, egui::Vec2::ZERO)
+                .show(&self.ctx, |ui| {
+                    ui.label("The terrain has changes that aren't saved yet");
+                    ui.horizontal(|ui| {
+                        if ui.button("Save").clicked() {
+                            actions.push(Action::SaveAndQuit);
+                            close = true;
+                        }
+                        if ui.button("Discard").clicked() {
+                            actions.push(Action::DiscardAndQuit);
+                            close = true;
+                        }
+                        if ui.button("Cancel").clicked() {
+                            close = true;
+                        }
+                    });
+                });
+            if close {
+                editor_state.confirm_exit = false;
+            }
+        }
+
+        if !editor_state.errors.is_empty() {
+            let mut dismiss = false;
+            egui::Window::new("Something went wrong")
+                .resizable(false)
+                .collapsible(false)
+                .anchor(Align2::CENTER_CENTER, egui::Vec2::ZERO)
+                .show(&self.ctx, |ui| {
+                    for error in &editor_state.errors {
+                        ui.label(error);
+                    }
+                    ui.colored_label(Color32::GRAY, "Everything else still works");
+                    if ui.button("OK").clicked() {
+                        dismiss = true;
+                    }
+                });
+            if dismiss {
+                editor_state.errors.clear();
+            }
+        }
+
+        if let Some(change) = &mut editor_state.bounds_change {
+            let world_size = view.terrain.world_size;
+            let mut open = true;
+            let mut apply = false;
+            egui::Window::new("Crop / extend terrain")
+                .open(&mut open)
+                .resizable(false)
+                .collapsible(false)
+                .show(&self.ctx, |ui| {
+                    ui.label("Positive values add land, negative values crop");
+                    let max_crop = -world_size;
+                    for (value, side) in [
+                        (&mut change.neg_x, "-X: "),
+                        (&mut change.pos_x, "+X: "),
+                        (&mut change.neg_z, "-Z: "),
+                        (&mut change.pos_z, "+Z: "),
+                    ] {
+                        ui.add(
+                            egui::DragValue::new(value)
+                                .clamp_range(max_crop..=world_size * 4.0)
+                                .speed(8.0)
+                                .prefix(side)
+                                .suffix(" m"),
+                        );
+                    }
+
+                    let mut noise = matches!(change.fill, EdgeFill::Noise { .. });
+                    ui.horizontal(|ui| {
+                        ui.radio_value(&mut noise, false, "Extend edges");
+                        ui.radio_value(&mut noise, true, "Noise");
+                    });
+                    change.fill = match (noise, change.fill) {
+                        (false, _) => EdgeFill::Clamp,
+                        (true, fill @ EdgeFill::Noise { .. }) => fill,
+                        (true, EdgeFill::Clamp) => EdgeFill::Noise {
+                            amplitude: 0.1,
+                            seed: view.terrain.seed,
+                        },
+                    };
+                    if let EdgeFill::Noise { amplitude, .. } = &mut change.fill {
+                        ui.add(egui::Slider::new(amplitude, 0.0..=1.0).text("Noise amplitude"));
+                    }
+
+                    let new_size = change.new_size(world_size);
+                    if change.is_square() {
+                        ui.label(format!("New size: {:.0} m", new_size));
+                    } else {
+                        ui.colored_label(Color32::YELLOW, "The terrain must stay square");
+                    }
+                    if ui
+                        .add_enabled(change.is_valid(world_size), egui::Button::new("Apply"))
+                        .clicked()
+                    {
+                        apply = true;
+                    }
+                });
+            if apply {
+                actions.push(Action::ChangeBounds(*change));
+            }
+            if apply || !open {
+                editor_state.bounds_change = None;
+            }
+        }
+
+        if !editor_state.photo.enabled {
+            egui::TopBottomPanel::bottom("Status bar").show(&self.ctx, |ui| status_bar(ui, view));
+        }
 
         egui::Area::new("Viewport")
             .fixed_pos((0.0, 0.0))
             .show(&self.ctx, |ui| {
+                if editor_state.photo.enabled {
+                    return; // keep the picture clean
+                }
                 ui.with_layer_id(LayerId::background(), |ui| {
+                    // Gizmo is only shown in the main view
+                    ui.set_clip_rect(view.gizmo_clip_rect);
                     let visuals = GizmoVisuals {
                         gizmo_size: 100.0,
                         ..Default::default()
                     };
                     let gizmo = Gizmo::new("gizmo")
-                        .view_matrix(view_matrix.to_cols_array_2d())
-                        .projection_matrix(projection_matrix.to_cols_array_2d())
+                        .view_matrix(view.view_matrix.to_cols_array_2d())
+                        .projection_matrix(view.projection_matrix.to_cols_array_2d())
                         .model_matrix(model_matrix.to_cols_array_2d())
                         .mode(GizmoMode::Translate)
                         .orientation(GizmoOrientation::Global)
@@ -188,11 +652,101 @@ impl Gui {
                     if let Some(gizmo_result) = gizmo.interact(ui) {
                         *model_matrix = Mat4::from_cols_array_2d(&gizmo_result.transform);
                     }
+
+                    let view_projection = view.projection_matrix * view.view_matrix;
+                    let rect = view.gizmo_clip_rect;
+                    let to_screen = |point: Vec3| {
+                        let clip = view_projection * point.extend(1.0);
+                        if clip.w <= 0.0 {
+                            return None; // behind the camera
+                        }
+                        let ndc = clip.truncate() / clip.w;
+                        Some(Pos2::new(
+                            rect.min.x + (ndc.x + 1.0) / 2.0 * rect.width(),
+                            rect.min.y + (1.0 - ndc.y) / 2.0 * rect.height(),
+                        ))
+                    };
+                    let stroke = Stroke::new(3.0, Color32::from_rgb(120, 200, 255));
+                    for &(start, end) in &view.wind_arrows {
+                        if let (Some(start), Some(end)) = (to_screen(start), to_screen(end)) {
+                            ui.painter().arrow(start, end - start, stroke);
+                        }
+                    }
+                    let paths = &editor_state.paths;
+                    let color = Color32::from_rgb(255, 200, 60);
+                    for step in paths.line().windows(2) {
+                        if let (Some(start), Some(end)) = (to_screen(step[0]), to_screen(step[1])) {
+                            ui.painter()
+                                .line_segment([start, end], Stroke::new(3.0, color));
+                        }
+                    }
+                    if let Some(start) = paths.start.and_then(to_screen) {
+                        ui.painter().circle_filled(start, 5.0, color);
+                    }
+                    if let Some(eye) = view.viewshed.and_then(|(eye, _)| to_screen(eye)) {
+                        ui.painter().circle_filled(eye, 5.0, Color32::WHITE);
+                    }
                 });
             });
 
+        // Plain keys would go off while typing into a text field
+        let typing = self.ctx.wants_keyboard_input();
+        let pressed: Vec<Command> = commands::all()
+            .into_iter()
+            .filter(|info| match info.binding {
+                Some(binding) => (binding.ctrl || !typing) && binding.released(&self.ctx.input()),
+                None => false,
+            })
+            .map(|info| info.command)
+            .collect();
+        for command in pressed {
+            match command {
+                Command::QuickOpen => self.palette.toggle(PaletteMode::QuickOpen),
+                Command::CommandPalette => self.palette.toggle(PaletteMode::Commands),
+                command => commands::run(command, editor_state, &mut actions),
+            }
+        }
+        self.palette.show(&self.ctx, editor_state, &mut actions);
+        if !editor_state.photo.enabled {
+            self.tutorial
+                .show(&self.ctx, view, tools_rect, &mut actions);
+        }
+
         // ================== GUI ends ===========================
 
+        self.end_frame(state, window);
+        actions
+    }
+
+    /// The whole window is one panel, for panels detached into their own windows
+    pub fn layout_panel(
+        &mut self,
+        state: &mut State,
+        window: &Window,
+        panel: Panel,
+        view: &ViewInfo,
+        editor_state: &mut EditorState,
+    ) -> Vec<Action> {
+        let input = state.take_egui_input(window);
+        self.ctx.begin_frame(input);
+        let mut actions = vec![];
+
+        egui::CentralPanel::default().show(&self.ctx, |ui| {
+            if ui.small_button("Attach").clicked() {
+                actions.push(Action::AttachPanel(panel));
+            }
+            egui::ScrollArea::vertical().show(ui, |ui| match panel {
+                Panel::Tools => tools_panel(ui, view, editor_state, &mut actions),
+                Panel::Stats => stats_panel(ui, view, editor_state, &mut actions),
+            });
+        });
+
+        self.end_frame(state, window);
+        actions
+    }
+
+    /// Tessellates what was laid out and uploads it for `draw`
+    fn end_frame(&mut self, state: &mut State, window: &Window) {
         let (output, shapes) = self.ctx.end_frame();
 
         state.handle_output(window, &self.ctx, output);
@@ -221,11 +775,16 @@ impl Gui {
         let required_size = size_of_slice(&vertices);
         if self.vertex_buffer_size < required_size {
             unsafe {
-                gl::DeleteBuffers(1, &self.vbo);
-                gl::CreateBuffers(1, &mut self.vbo);
-                gl::VertexArrayVertexBuffer(self.vao, 0, self.vbo, 0, size_of::<Vertex>() as i32);
+                self.vbo = Buffer::new("GUI vertices");
+                gl::VertexArrayVertexBuffer(
+                    self.vao.id(),
+                    0,
+                    self.vbo.id(),
+                    0,
+                    size_of::<Vertex>() as i32,
+                );
                 gl::NamedBufferStorage(
-                    self.vbo,
+                    self.vbo.id(),
                     required_size as isize,
                     vertices.as_ptr() as *const _,
                     gl::DYNAMIC_STORAGE_BIT,
@@ -236,7 +795,7 @@ impl Gui {
         } else {
             unsafe {
                 gl::NamedBufferSubData(
-                    self.vbo,
+                    self.vbo.id(),
                     0,
                     required_size as isize,
                     vertices.as_ptr() as *const _,
@@ -248,11 +807,10 @@ impl Gui {
         let required_size = size_of_slice(&indices);
         if self.index_buffer_size < required_size {
             unsafe {
-                gl::DeleteBuffers(1, &self.ebo);
-                gl::CreateBuffers(1, &mut self.ebo);
-                gl::VertexArrayElementBuffer(self.vao, self.ebo);
+                self.ebo = Buffer::new("GUI indices");
+                gl::VertexArrayElementBuffer(self.vao.id(), self.ebo.id());
                 gl::NamedBufferStorage(
-                    self.ebo,
+                    self.ebo.id(),
                     required_size as isize,
                     indices.as_ptr() as *const _,
                     gl::DYNAMIC_STORAGE_BIT,
@@ -263,15 +821,13 @@ impl Gui {
         } else {
             unsafe {
                 gl::NamedBufferSubData(
-                    self.ebo,
+                    self.ebo.id(),
                     0,
                     required_size as isize,
                     indices.as_ptr() as *const _,
                 )
             }
         }
-
-        actions
     }
 
     pub fn draw(&mut self) {
@@ -284,9 +840,10 @@ impl Gui {
             .unwrap();
         unsafe {
             gl::ActiveTexture(unit_to_gl_const(0));
-            gl::BindTexture(gl::TEXTURE_2D, self.egui_texture);
+            let texture = self.egui_texture.as_ref().map_or(0, Texture::id);
+            gl::BindTexture(gl::TEXTURE_2D, texture);
 
-            gl::BindVertexArray(self.vao);
+            gl::BindVertexArray(self.vao.id());
             gl::Disable(gl::DEPTH_TEST);
             gl::Disable(gl::CULL_FACE);
             gl::Enable(gl::BLEND);
@@ -323,26 +880,19 @@ impl Gui {
             .map(|&a| Color32::from_white_alpha(a).to_tuple())
             .collect();
 
-        let mut new_texture: GLuint = 0;
+        // Replacing the old texture deletes it
+        let new_texture = Texture::new(gl::TEXTURE_2D, "GUI font");
+        new_texture.set_wrap_and_filter(gl::CLAMP_TO_EDGE, gl::LINEAR, gl::LINEAR);
         unsafe {
-            if self.egui_texture != 0 {
-                // Delete old texture
-                gl::DeleteTextures(1, &self.egui_texture);
-            }
-            gl::CreateTextures(gl::TEXTURE_2D, 1, &mut new_texture);
-            gl::TextureParameteri(new_texture, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
-            gl::TextureParameteri(new_texture, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
-            gl::TextureParameteri(new_texture, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
-            gl::TextureParameteri(new_texture, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
             gl::TextureStorage2D(
-                new_texture,
+                new_texture.id(),
                 1,
                 gl::SRGB8_ALPHA8,
                 texture.width as GLint,
                 texture.height as GLint,
             );
             gl::TextureSubImage2D(
-                new_texture,
+                new_texture.id(),
                 0,
                 0,
                 0,
@@ -353,19 +903,7 @@ impl Gui {
                 pixels.as_ptr() as *const _,
             );
         }
-        self.egui_texture = new_texture;
-    }
-}
-
-impl Drop for Gui {
-    fn drop(&mut self) {
-        unsafe {
-            let buffers = [self.vbo, self.ebo];
-            gl::DeleteBuffers(1, buffers.as_ptr());
-
-            let arrays = [self.vao];
-            gl::DeleteVertexArrays(1, arrays.as_ptr());
-        }
+        self.egui_texture = Some(new_texture);
     }
 }
 
@@ -376,3 +914,1885 @@ struct Vertex {
     uv: [f32; 2],
     srgba: [u8; 4],
 }
+
+fn tools_panel(
+    ui: &mut egui::Ui,
+    view: &ViewInfo,
+    editor_state: &mut EditorState,
+    actions: &mut Vec<Action>,
+) {
+    let tool = view.tool;
+    ui.horizontal(|ui| {
+        for (option, name) in TOOLS {
+            if ui.selectable_label(tool == Some(option), name).clicked() {
+                actions.push(Action::SelectTool(option));
+            }
+        }
+        // Not brushes, so they don't get number keys
+        for (option, name) in [
+            (TerrainTool::FindPath, "Path"),
+            (TerrainTool::Viewshed, "Sight"),
+        ] {
+            if ui.selectable_label(tool == Some(option), name).clicked() {
+                actions.push(Action::SelectTool(option));
+            }
+        }
+    });
+    ui.horizontal(|ui| {
+        let snap = &mut editor_state.snap;
+        ui.checkbox(&mut snap.enabled, "Snap (G)");
+        egui::ComboBox::from_id_source("Snap target")
+            .selected_text(snap.target.name())
+            .show_ui(ui, |ui| {
+                for target in SnapTarget::ALL {
+                    ui.selectable_value(&mut snap.target, target, target.name());
+                }
+            });
+    });
+    egui::ComboBox::from_label("Edges")
+        .selected_text(editor_state.edges.name())
+        .show_ui(ui, |ui| {
+            for edges in EdgeMode::ALL {
+                ui.selectable_value(&mut editor_state.edges, edges, edges.name());
+            }
+        })
+        .response
+        .on_hover_text("What brushes and filters find past the border, wrap for maps that tile");
+    if ui
+        .add_enabled(view.can_undo_stroke, egui::Button::new("Undo stroke"))
+        .on_hover_text("Puts back the heights from before the last stroke, painting stays")
+        .clicked()
+    {
+        actions.push(Action::UndoStroke);
+    }
+
+    if matches!(
+        tool,
+        Some(TerrainTool::Sculpt) | Some(TerrainTool::SmoothAlong)
+    ) {
+        let along = Some(TerrainTool::SmoothAlong);
+        if ui
+            .selectable_label(tool == along, "Smooth along stroke")
+            .on_hover_text("Evens out riverbeds and ridgelines without flattening across them")
+            .clicked()
+        {
+            let other = if tool == along {
+                TerrainTool::Sculpt
+            } else {
+                TerrainTool::SmoothAlong
+            };
+            actions.push(Action::SelectTool(other));
+        }
+        if tool == along {
+            let smoothing = &mut editor_state.along_stroke;
+            ui.add(egui::Slider::new(&mut smoothing.strength, 0.1..=10.0).text("Strength"));
+            ui.add(egui::Slider::new(&mut smoothing.reach, 1.0..=100.0).text("Reach, m"));
+        }
+    }
+
+    if tool == Some(TerrainTool::Sculpt) {
+        ui.checkbox(&mut editor_state.detail.enabled, "Carve detail")
+            .on_hover_text("Small brushes carve paths and trenches finer than the heightmap");
+        if editor_state.detail.enabled && !view.brush_fits_detail {
+            ui.colored_label(Color32::GRAY, "The brush is too big for detail");
+        }
+        if view.detail_tiles > 0 {
+            ui.horizontal(|ui| {
+                ui.label(format!("{} detail tiles", view.detail_tiles));
+                if ui.button("Bake").clicked() {
+                    actions.push(Action::BakeDetail);
+                }
+                if ui.button("Clear").clicked() {
+                    actions.push(Action::ClearDetail);
+                }
+            });
+        }
+    }
+
+    if tool == Some(TerrainTool::CaptureStamp) {
+        let stamps = &mut editor_state.stamps;
+        ui.horizontal(|ui| {
+            ui.label("Name");
+            ui.text_edit_singleline(&mut stamps.name);
+        });
+        ui.label("Click to save the terrain under the brush as a stamp");
+        if !stamps.library.is_empty() {
+            ui.collapsing(format!("Library ({})", stamps.library.len()), |ui| {
+                for name in &stamps.library {
+                    if ui.selectable_label(false, name).clicked() {
+                        // Capturing with the same name replaces it
+                        stamps.name = name.clone();
+                    }
+                }
+            });
+        }
+    }
+
+    let season = editor_state.seasons.season;
+    if tool == Some(TerrainTool::PaintVegetation) {
+        let scatter = &mut editor_state.scatter;
+        for (i, scatter_type) in scatter.types.iter().enumerate() {
+            let label = format!("{} ({})", scatter_type.name, view.scatter_counts[i]);
+            if ui
+                .selectable_label(scatter.selected == Some(i), label)
+                .on_hover_text(&scatter_type.model_path)
+                .clicked()
+            {
+                scatter.selected = Some(i);
+            }
+        }
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut scatter.model_path);
+            if ui.button("Add type").clicked() {
+                actions.push(Action::AddScatterType);
+            }
+        });
+        if let Some(index) = scatter.selected {
+            let scatter_type = &mut scatter.types[index];
+            ui.add(
+                egui::Slider::new(&mut scatter_type.density, 0.01..=100.0)
+                    .logarithmic(true)
+                    .text("Per 100 m²"),
+            );
+            ui.add(egui::Slider::new(&mut scatter_type.min_scale, 0.1..=10.0).text("Min scale"));
+            ui.add(egui::Slider::new(&mut scatter_type.max_scale, 0.1..=10.0).text("Max scale"));
+            scatter_type.max_scale = scatter_type.max_scale.max(scatter_type.min_scale);
+            ui.add(
+                egui::Slider::new(&mut scatter_type.alignment, 0.0..=1.0).text("Align to slope"),
+            );
+            ui.collapsing("Level of detail", |ui| {
+                let lod = &mut scatter_type.lod;
+                ui.add(
+                    egui::Slider::new(&mut lod.max_distance, 10.0..=5000.0)
+                        .logarithmic(true)
+                        .text("Draw distance, m"),
+                );
+                ui.add(
+                    egui::Slider::new(&mut lod.imposter_distance, 10.0..=5000.0)
+                        .logarithmic(true)
+                        .text("Imposter distance, m"),
+                )
+                .on_hover_text("Further away a flat quad is drawn instead");
+                ui.checkbox(&mut lod.cast_shadows, "Cast shadows");
+            });
+            if let Some(color) = scatter_type.seasons.get_mut(season) {
+                ui.horizontal(|ui| {
+                    ui.label(format!("In {}", season.name().to_lowercase()));
+                    seasonal_color_edit(ui, color);
+                });
+            }
+            ui.collapsing("Density heatmap", |ui| {
+                let heatmap = &mut scatter.heatmap;
+                ui.checkbox(&mut heatmap.enabled, "Show");
+                ui.add(
+                    egui::Slider::new(&mut heatmap.cell_size, 1.0..=100.0)
+                        .logarithmic(true)
+                        .text("Cell, m"),
+                );
+                ui.add(
+                    egui::Slider::new(&mut heatmap.range, 0.1..=200.0)
+                        .logarithmic(true)
+                        .text("Full heat per 100 m²"),
+                );
+                ui.add(egui::Slider::new(&mut heatmap.opacity, 0.0..=1.0).text("Opacity"));
+            });
+            ui.horizontal(|ui| {
+                if ui.button("Clear").clicked() {
+                    actions.push(Action::ClearScatterType(index));
+                }
+                if ui.button("Remove type").clicked() {
+                    actions.push(Action::RemoveScatterType(index));
+                }
+            });
+            ui.label("Hold Ctrl to erase");
+        }
+    }
+
+    if tool == Some(TerrainTool::PaintBiomes) {
+        let types = &editor_state.scatter.types;
+        let biomes = &mut editor_state.biomes;
+        ui.horizontal(|ui| {
+            for (i, biome) in biomes.biomes.iter().enumerate() {
+                ui.selectable_value(&mut biomes.paint.layer, i, &biome.name);
+            }
+        });
+        let paint = &mut biomes.paint;
+        ui.add(egui::Slider::new(&mut paint.strength, 0.1..=10.0).text("Strength"));
+        ui.checkbox(&mut biomes.show_regions, "Show regions");
+
+        let biome = &mut biomes.biomes[paint.layer];
+        ui.horizontal(|ui| {
+            ui.label("Name");
+            ui.text_edit_singleline(&mut biome.name);
+        });
+        ui.horizontal(|ui| {
+            ui.label("Region");
+            ui.color_edit_button_rgb(&mut biome.color);
+            ui.label("Tint");
+            ui.color_edit_button_rgb(&mut biome.tint);
+        });
+        ui.collapsing("Texturing rules", |ui| {
+            let layers = default_layers();
+            let mut remove = None;
+            for (i, rule) in biome.splat_rules.iter_mut().enumerate() {
+                ui.horizontal(|ui| {
+                    egui::ComboBox::from_id_source(("Rule layer", i))
+                        .selected_text(layers[rule.layer].name)
+                        .show_ui(ui, |ui| {
+                            for (layer, option) in layers.iter().enumerate() {
+                                ui.selectable_value(&mut rule.layer, layer, option.name);
+                            }
+                        });
+                    if ui.small_button("✖").clicked() {
+                        remove = Some(i);
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Height, m");
+                    ui.add(egui::DragValue::new(&mut rule.min_height));
+                    ui.add(egui::DragValue::new(&mut rule.max_height));
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Slope, °");
+                    ui.add(egui::DragValue::new(&mut rule.min_slope).clamp_range(0.0..=90.0));
+                    ui.add(egui::DragValue::new(&mut rule.max_slope).clamp_range(0.0..=90.0));
+                });
+            }
+            if let Some(i) = remove {
+                biome.splat_rules.remove(i);
+            }
+            if ui.button("Add rule").clicked() {
+                biome.splat_rules.push(SplatRule {
+                    layer: 0,
+                    min_height: -1000.0,
+                    max_height: 1000.0,
+                    min_slope: 0.0,
+                    max_slope: 90.0,
+                });
+            }
+        });
+        ui.collapsing("Scatter rules", |ui| {
+            let mut remove = None;
+            for (i, rule) in biome.scatter_rules.iter_mut().enumerate() {
+                ui.horizontal(|ui| {
+                    egui::ComboBox::from_id_source(("Rule type", i))
+                        .selected_text(&types[rule.scatter_type].name)
+                        .show_ui(ui, |ui| {
+                            for (index, option) in types.iter().enumerate() {
+                                ui.selectable_value(&mut rule.scatter_type, index, &option.name);
+                            }
+                        });
+                    ui.add(
+                        egui::Slider::new(&mut rule.density, 0.01..=100.0)
+                            .logarithmic(true)
+                            .text("Per 100 m²"),
+                    );
+                    if ui.small_button("✖").clicked() {
+                        remove = Some(i);
+                    }
+                });
+            }
+            if let Some(i) = remove {
+                biome.scatter_rules.remove(i);
+            }
+            if types.is_empty() {
+                ui.label("Add scatter types with the Scatter tool first");
+            } else if ui.button("Add rule").clicked() {
+                biome.scatter_rules.push(ScatterRule {
+                    scatter_type: 0,
+                    density: types[0].density,
+                });
+            }
+        });
+        ui.horizontal(|ui| {
+            if ui.button("Auto texture").clicked() {
+                actions.push(Action::AutoTexture);
+            }
+            if ui.button("Auto scatter").clicked() {
+                actions.push(Action::AutoScatter);
+            }
+        });
+    }
+
+    if tool == Some(TerrainTool::PlaceSound) {
+        let audio = &mut editor_state.audio;
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut audio.preview, "Preview");
+            ui.add(egui::Slider::new(&mut audio.master_volume, 0.0..=1.0).text("Volume"));
+        });
+        ui.horizontal(|ui| {
+            for kind in EmitterKind::ALL {
+                if ui
+                    .selectable_label(audio.kind == kind, kind.name())
+                    .clicked()
+                {
+                    audio.kind = kind;
+                    audio.sound_path = kind.default_sound().to_owned();
+                }
+            }
+        });
+        ui.text_edit_singleline(&mut audio.sound_path);
+        ui.separator();
+        for (i, emitter) in audio.emitters.iter().enumerate() {
+            let label = format!(
+                "{} {} ({:.0} m)",
+                emitter.kind.name(),
+                i + 1,
+                emitter.radius
+            );
+            if ui
+                .selectable_label(audio.selected == Some(i), label)
+                .on_hover_text(&emitter.sound_path)
+                .clicked()
+            {
+                audio.selected = Some(i);
+            }
+        }
+        if let Some(index) = audio.selected {
+            let emitter = &mut audio.emitters[index];
+            ui.add(
+                egui::Slider::new(&mut emitter.radius, 1.0..=1000.0)
+                    .logarithmic(true)
+                    .text("Radius, m"),
+            );
+            ui.add(egui::Slider::new(&mut emitter.volume, 0.0..=1.0).text("Volume"));
+            let (mut replace_sound, mut remove) = (false, false);
+            ui.horizontal(|ui| {
+                replace_sound = ui.button("Use sound above").clicked();
+                remove = ui.button("Remove").clicked();
+            });
+            if replace_sound {
+                emitter.sound_path = audio.sound_path.clone();
+            }
+            if remove {
+                audio.remove_emitter(index);
+            }
+        }
+        ui.label("Click to place a sound. Ctrl+click removes one");
+    }
+
+    if tool == Some(TerrainTool::Wind) {
+        let wind = &mut editor_state.wind;
+        ui.label("Global");
+        wind_sliders(ui, &mut wind.global);
+        ui.separator();
+        for (i, zone) in wind.zones.iter().enumerate() {
+            let label = format!("Zone {} ({:.0} m)", i + 1, zone.radius);
+            if ui
+                .selectable_label(wind.selected == Some(i), label)
+                .clicked()
+            {
+                wind.selected = Some(i);
+            }
+        }
+        if let Some(index) = wind.selected {
+            let zone = &mut wind.zones[index];
+            ui.add(
+                egui::Slider::new(&mut zone.radius, 1.0..=1000.0)
+                    .logarithmic(true)
+                    .text("Radius, m"),
+            );
+            wind_sliders(ui, &mut zone.wind);
+            if ui.button("Remove zone").clicked() {
+                wind.remove_zone(index);
+            }
+        }
+        ui.checkbox(&mut wind.show_arrows, "Show arrows");
+        ui.label(format!(
+            "Click to add a zone, up to {}. Ctrl+click removes one",
+            MAX_WIND_ZONES
+        ));
+    }
+
+    if tool == Some(TerrainTool::FindPath) {
+        let paths = &mut editor_state.paths;
+        let response = ui.add(
+            egui::Slider::new(&mut paths.settings.max_slope, 5.0..=60.0)
+                .text("Max slope")
+                .suffix("°"),
+        );
+        // Every change looks for the path again, which is too slow to do mid-drag
+        let mut changed = response.drag_released() || (response.changed() && !response.dragged());
+        egui::ComboBox::from_label("Grid")
+            .selected_text(paths.settings.resolution.to_string())
+            .show_ui(ui, |ui| {
+                for resolution in [256, 512, 1024] {
+                    let option = ui.selectable_value(
+                        &mut paths.settings.resolution,
+                        resolution,
+                        resolution.to_string(),
+                    );
+                    changed |= option.changed();
+                }
+            })
+            .response
+            .on_hover_text("Cells along a side of the terrain, finer finds narrower passes");
+        if changed && paths.ends.is_some() {
+            actions.push(Action::FindPath);
+        }
+        let status = match (&paths.start, &paths.ends, &paths.path) {
+            (Some(_), _, _) => "Click where the path ends".to_owned(),
+            (None, Some(_), Some(path)) => format!(
+                "{:.0} m long, {:.0}° at the steepest",
+                path.length, path.steepest
+            ),
+            (None, Some(_), None) => "No way through, it's too steep somewhere".to_owned(),
+            (None, None, _) => "Click where the path starts".to_owned(),
+        };
+        ui.label(status);
+        ui.horizontal(|ui| {
+            if ui
+                .add_enabled(paths.ends.is_some(), egui::Button::new("Find again"))
+                .on_hover_text("After the terrain has changed")
+                .clicked()
+            {
+                actions.push(Action::FindPath);
+            }
+            if ui.button("Clear").clicked() {
+                paths.clear();
+            }
+        });
+        ui.label("Ctrl+click clears the path");
+    }
+
+    if tool == Some(TerrainTool::Viewshed) {
+        let settings = &mut editor_state.viewshed;
+        let response = ui.add(
+            egui::Slider::new(&mut settings.observer_height, 0.0..=200.0)
+                .logarithmic(true)
+                .text("Eye height")
+                .suffix(" m"),
+        );
+        // Working it out again mid-drag would stall every frame
+        let mut changed = response.drag_released() || (response.changed() && !response.dragged());
+        egui::ComboBox::from_label("Grid")
+            .selected_text(settings.resolution.to_string())
+            .show_ui(ui, |ui| {
+                for resolution in [512, 1024, 2048] {
+                    let option = ui.selectable_value(
+                        &mut settings.resolution,
+                        resolution,
+                        resolution.to_string(),
+                    );
+                    changed |= option.changed();
+                }
+            })
+            .response
+            .on_hover_text("Cells along a side of the terrain, finer is sharper and slower");
+        if changed && view.viewshed.is_some() {
+            actions.push(Action::UpdateViewshed);
+        }
+        match view.viewshed {
+            Some((_, fraction)) => {
+                ui.label(format!(
+                    "{:.0}% of the terrain can be seen",
+                    fraction * 100.0
+                ));
+            }
+            None => {
+                ui.label("Click where the observer stands");
+            }
+        }
+        ui.horizontal(|ui| {
+            if ui
+                .add_enabled(view.viewshed.is_some(), egui::Button::new("Update"))
+                .on_hover_text("After the terrain has changed")
+                .clicked()
+            {
+                actions.push(Action::UpdateViewshed);
+            }
+            if ui
+                .add_enabled(view.viewshed.is_some(), egui::Button::new("Clear"))
+                .clicked()
+            {
+                actions.push(Action::ClearViewshed);
+            }
+        });
+        ui.label("Ctrl+click clears it too");
+    }
+
+    if tool == Some(TerrainTool::CapturePrefab) {
+        let prefabs = &mut editor_state.prefabs;
+        ui.horizontal(|ui| {
+            ui.label("Name");
+            ui.text_edit_singleline(&mut prefabs.name);
+        });
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut prefabs.include_heights, "Terrain");
+            ui.checkbox(&mut prefabs.include_splat, "Textures");
+        });
+        ui.label("Click to save the objects under the brush as a prefab");
+    }
+
+    if tool == Some(TerrainTool::PlacePrefab) {
+        let prefabs = &mut editor_state.prefabs;
+        if prefabs.library.is_empty() {
+            ui.label("No prefabs yet, make one with the Group tool");
+        }
+        for name in &prefabs.library {
+            let selected = prefabs.selected.as_ref() == Some(name);
+            if ui.selectable_label(selected, name).clicked() {
+                prefabs.selected = Some(name.clone());
+            }
+        }
+    }
+
+    if tool == Some(TerrainTool::PaintTextures) {
+        let paint = &mut editor_state.paint;
+        ui.horizontal(|ui| {
+            for (i, layer) in default_layers().iter().enumerate() {
+                ui.selectable_value(&mut paint.layer, i, layer.name);
+            }
+        });
+        ui.horizontal(|ui| {
+            for mode in PaintBlendMode::ALL {
+                ui.radio_value(&mut paint.blend_mode, mode, mode.name());
+            }
+        });
+        ui.add(
+            egui::Slider::new(&mut paint.layer_opacity[paint.layer], 0.0..=1.0)
+                .text("Layer opacity"),
+        );
+        ui.add(egui::Slider::new(&mut paint.strength, 0.1..=10.0).text("Strength"));
+        ui.checkbox(
+            &mut editor_state.prop_paint.with_brush,
+            "Paint placed objects",
+        )
+        .on_hover_text("Objects under the brush get the layer's color, Subtract takes it off");
+    }
+
+    if tool == Some(TerrainTool::PaintTint) {
+        let tint = &mut editor_state.tint;
+        ui.horizontal(|ui| {
+            ui.label("Color");
+            ui.color_edit_button_rgb(&mut tint.color);
+            if ui.button("White").clicked() {
+                tint.color = [1.0; 3];
+            }
+        });
+        ui.add(egui::Slider::new(&mut tint.strength, 0.1..=10.0).text("Strength"));
+        ui.label("Multiplies the terrain color, hold Ctrl to erase");
+    }
+
+    ui.collapsing("Time of day", |ui| {
+        let day = &mut editor_state.day;
+        ui.horizontal(|ui| {
+            let label = if day.playing { "Pause" } else { "Play cycle" };
+            if ui.button(label).clicked() {
+                day.playing = !day.playing;
+            }
+            let minutes = (day.hour.fract() * 60.0) as u32;
+            ui.label(format!("{:02}:{:02}", day.hour as u32, minutes));
+        });
+        ui.add(egui::Slider::new(&mut day.hour, 0.0..=24.0).text("Hour"));
+        ui.add(
+            egui::Slider::new(&mut day.cycle_seconds, 1.0..=600.0)
+                .logarithmic(true)
+                .text("Seconds per day"),
+        );
+        let phase_name = day.moon_phase_name();
+        ui.add(egui::Slider::new(&mut day.moon_phase, 0.0..=1.0).text(phase_name));
+        ui.add(egui::Slider::new(&mut day.moon_size, 0.5..=10.0).text("Moon size (deg)"));
+        ui.add(egui::Slider::new(&mut day.star_density, 0.0..=1.0).text("Stars"));
+        let sun = &mut editor_state.sun;
+        ui.checkbox(&mut sun.disk, "Sun disk");
+        ui.add_enabled(
+            sun.disk,
+            egui::Slider::new(&mut sun.disk_size, 0.2..=10.0).text("Size (deg)"),
+        );
+        ui.checkbox(&mut sun.lens_flare, "Lens flare");
+        ui.add_enabled(
+            sun.lens_flare,
+            egui::Slider::new(&mut sun.flare_intensity, 0.0..=2.0).text("Intensity"),
+        );
+        ui.checkbox(&mut sun.soft_shadows, "Soft shadows")
+            .on_hover_text("Needs the shadow map");
+        ui.add_enabled(
+            sun.soft_shadows,
+            egui::Slider::new(&mut sun.light_size, 0.1..=5.0).text("Light size (deg)"),
+        );
+        ui.separator();
+        ui.horizontal(|ui| {
+            if !view.sun_hours_shown {
+                if ui
+                    .button("Show sun hours")
+                    .on_hover_text("How long the sun shines on every part of the terrain in a day")
+                    .clicked()
+                {
+                    actions.push(Action::ShowSunHours);
+                }
+                return;
+            }
+            if ui
+                .button("Update")
+                .on_hover_text("After the terrain has changed")
+                .clicked()
+            {
+                actions.push(Action::ShowSunHours);
+            }
+            if ui.button("Hide sun hours").clicked() {
+                actions.push(Action::HideSunHours);
+            }
+        });
+        if let Some(hours) = view.sun_hours_at_cursor {
+            ui.label(format!("{:.1} h of sun under the brush", hours));
+        }
+    });
+    ui.collapsing("Season", |ui| {
+        let seasons = &mut editor_state.seasons;
+        ui.horizontal(|ui| {
+            for option in Season::ALL {
+                ui.radio_value(&mut seasons.season, option, option.name());
+            }
+        });
+        let season = seasons.season;
+        for (layer, variants) in default_layers().iter().zip(&mut seasons.layers) {
+            if let Some(color) = variants.get_mut(season) {
+                ui.horizontal(|ui| {
+                    ui.label(layer.name);
+                    seasonal_color_edit(ui, color);
+                });
+            }
+        }
+        if season == Season::Summer {
+            ui.label("Everything is authored in summer");
+        }
+    });
+    ui.collapsing("Buildable area", |ui| {
+        let settings = &mut editor_state.buildable;
+        ui.add(
+            egui::Slider::new(&mut settings.max_slope, 1.0..=45.0)
+                .text("Max slope")
+                .suffix("°"),
+        );
+        ui.add(
+            egui::Slider::new(&mut settings.min_area, 100.0..=1_000_000.0)
+                .logarithmic(true)
+                .text("Min area")
+                .suffix(" m²"),
+        )
+        .on_hover_text("Flat patches smaller than this don't count");
+        ui.horizontal(|ui| {
+            let label = if view.buildable.is_some() {
+                "Update"
+            } else {
+                "Find"
+            };
+            if ui.button(label).clicked() {
+                actions.push(Action::FindBuildable);
+            }
+            if ui
+                .add_enabled(view.buildable.is_some(), egui::Button::new("Hide"))
+                .clicked()
+            {
+                actions.push(Action::HideBuildable);
+            }
+            if ui
+                .add_enabled(view.buildable.is_some(), egui::Button::new("Save mask"))
+                .on_hover_text("White where it can be built on, next to the heightmap")
+                .clicked()
+            {
+                actions.push(Action::SaveBuildableMask);
+            }
+        });
+        if let Some((regions, total, largest)) = view.buildable {
+            let hectares = |area: f32| area / 10_000.0;
+            let share = total / (view.terrain.world_size * view.terrain.world_size);
+            ui.label(format!(
+                "{} regions, {:.1} ha in all ({:.0}% of the map)",
+                regions,
+                hectares(total),
+                share * 100.0
+            ));
+            ui.label(format!("The largest is {:.1} ha", hectares(largest)));
+        }
+    });
+    ui.collapsing("Flooding", |ui| {
+        let settings = &mut editor_state.flood;
+        let mut changed = false;
+        ui.horizontal(|ui| {
+            for source in FloodSource::ALL {
+                changed |= ui
+                    .radio_value(&mut settings.source, source, source.name())
+                    .changed();
+            }
+        });
+        let slider = match settings.source {
+            FloodSource::Sea => {
+                egui::Slider::new(&mut settings.sea_level, 0.0..=view.terrain.max_height)
+                    .text("Sea level")
+            }
+            FloodSource::Rain => egui::Slider::new(&mut settings.min_depth, 0.1..=10.0)
+                .logarithmic(true)
+                .text("Min depth"),
+        };
+        changed |= ui.add(slider.suffix(" m")).changed();
+        // Worked out coarsely enough to follow the slider as it's dragged
+        if changed && view.flood.is_some() {
+            actions.push(Action::ShowFlood);
+        }
+        ui.horizontal(|ui| {
+            let label = if view.flood.is_some() {
+                "Update"
+            } else {
+                "Show"
+            };
+            if ui
+                .button(label)
+                .on_hover_text("Only a preview, the water that's drawn is left alone")
+                .clicked()
+            {
+                actions.push(Action::ShowFlood);
+            }
+            if ui
+                .add_enabled(view.flood.is_some(), egui::Button::new("Hide"))
+                .clicked()
+            {
+                actions.push(Action::HideFlood);
+            }
+        });
+        if let Some((share, deepest)) = view.flood {
+            ui.label(format!(
+                "{:.0}% under water, {:.1} m at the deepest",
+                share * 100.0,
+                deepest
+            ));
+        }
+    });
+
+    ui.separator();
+    if ui.button("New project...").clicked() {
+        editor_state.new_project = Some(TerrainParams::default());
+    }
+    let mut resolution = view.terrain.resolution;
+    egui::ComboBox::from_label("Resolution")
+        .selected_text(resolution.to_string())
+        .show_ui(ui, |ui| {
+            for option in RESOLUTIONS {
+                ui.selectable_value(&mut resolution, option, option.to_string());
+            }
+        });
+    if resolution != view.terrain.resolution {
+        actions.push(Action::ResampleTerrain(resolution));
+    }
+    ui.collapsing("Proxy sculpting", |ui| {
+        proxy_panel(ui, view, editor_state, actions)
+    });
+    ui.collapsing("Erosion", |ui| {
+        let erosion = &mut editor_state.erosion;
+        ui.add(egui::Slider::new(&mut erosion.iterations, 1..=200).text("Iterations"));
+        ui.add(egui::Slider::new(&mut erosion.talus_angle, 5.0..=80.0).text("Talus angle"));
+        ui.add(egui::Slider::new(&mut erosion.strength, 0.0..=1.0).text("Strength"));
+        egui::ComboBox::from_label("Tile size")
+            .selected_text(erosion.tiles.tile_size.to_string())
+            .show_ui(ui, |ui| {
+                for option in [128, 256, 512, 1024, 2048] {
+                    ui.selectable_value(&mut erosion.tiles.tile_size, option, option.to_string());
+                }
+            });
+        ui.add(egui::Slider::new(&mut erosion.tiles.overlap, 0..=128).text("Tile overlap"));
+        if ui.button("Erode").clicked() {
+            actions.push(Action::Erode(*erosion));
+        }
+        ui.separator();
+        erosion_splat_panel(ui, &mut editor_state.erosion_splat);
+        ui.separator();
+        let brush = Some(TerrainTool::ErodeUnderBrush);
+        if ui
+            .selectable_label(view.tool == brush, "Erosion brush")
+            .on_hover_text("Hydraulic erosion only where you paint")
+            .clicked()
+        {
+            let tool = if view.tool == brush {
+                TerrainTool::Sculpt
+            } else {
+                TerrainTool::ErodeUnderBrush
+            };
+            actions.push(Action::SelectTool(tool));
+        }
+        let droplets = &mut editor_state.erosion_brush;
+        ui.add(egui::Slider::new(&mut droplets.rate, 100.0..=20000.0).text("Droplets per second"));
+        ui.add(egui::Slider::new(&mut droplets.lifetime, 5..=100).text("Droplet lifetime"));
+        ui.add(egui::Slider::new(&mut droplets.inertia, 0.0..=0.9).text("Inertia"));
+        ui.add(egui::Slider::new(&mut droplets.capacity, 0.1..=8.0).text("Sediment capacity"));
+        ui.add(egui::Slider::new(&mut droplets.erosion, 0.01..=1.0).text("Erosion"));
+        ui.add(egui::Slider::new(&mut droplets.deposition, 0.01..=1.0).text("Deposition"));
+        ui.add(egui::Slider::new(&mut droplets.evaporation, 0.0..=0.2).text("Evaporation"));
+    });
+    ui.collapsing("Filters", |ui| {
+        filters_panel(ui, view, editor_state, actions)
+    });
+    ui.collapsing("Weathering", |ui| {
+        let weathering = &mut editor_state.weathering;
+        ui.add(egui::Slider::new(&mut weathering.cavity_radius, 1..=32).text("Cavity radius"));
+        ui.add(
+            egui::Slider::new(&mut weathering.cavity_darkening, 0.0..=1.0).text("Darken crevices"),
+        );
+        ui.horizontal(|ui| {
+            ui.label("Sediment");
+            ui.color_edit_button_rgb(&mut weathering.sediment_color);
+        });
+        ui.add(egui::Slider::new(&mut weathering.sediment_amount, 0.0..=1.0).text("Amount"));
+        if ui.button("Weather").clicked() {
+            actions.push(Action::Weather(*weathering));
+        }
+    });
+    ui.collapsing("Cliff rocks", |ui| {
+        let cliffs = &mut editor_state.cliffs;
+        ui.horizontal(|ui| {
+            ui.label("Model");
+            ui.text_edit_singleline(&mut cliffs.model_path);
+        });
+        let scatter = &mut cliffs.scatter;
+        ui.add(egui::Slider::new(&mut scatter.min_slope, 20.0..=85.0).text("Min slope"));
+        ui.add(
+            egui::Slider::new(&mut scatter.density, 0.01..=10.0)
+                .logarithmic(true)
+                .text("Per 100 m²"),
+        );
+        ui.add(egui::Slider::new(&mut scatter.min_scale, 0.1..=10.0).text("Min scale"));
+        ui.add(egui::Slider::new(&mut scatter.max_scale, 0.1..=10.0).text("Max scale"));
+        scatter.max_scale = scatter.max_scale.max(scatter.min_scale);
+        ui.horizontal(|ui| {
+            if ui
+                .button("Scatter")
+                .on_hover_text("Rocks don't follow sculpting, scatter again after")
+                .clicked()
+            {
+                actions.push(Action::ScatterCliffRocks);
+            }
+            if let Some(count) = view.cliff_rocks {
+                if ui.button("Clear").clicked() {
+                    actions.push(Action::ClearCliffRocks);
+                }
+                ui.label(format!("{} rocks", count));
+            }
+        });
+    });
+    ui.collapsing("Selected object", |ui| {
+        ui.add(egui::Slider::new(&mut editor_state.drape_offset, -1.0..=5.0).text("Offset, m"));
+        ui.horizontal(|ui| {
+            if ui
+                .button("Drape onto terrain")
+                .on_hover_text("Moves the vertices down to the ground")
+                .clicked()
+            {
+                actions.push(Action::DrapeObject(editor_state.drape_offset));
+            }
+            if ui.button("Restore shape").clicked() {
+                actions.push(Action::RestoreObjectShape);
+            }
+        });
+        ui.separator();
+        let fit = &mut editor_state.fit;
+        ui.add(egui::Slider::new(&mut fit.feather, 0.0..=50.0).text("Feather, m"));
+        ui.add(egui::Slider::new(&mut fit.depth, 0.0..=5.0).text("Sink, m"));
+        if ui
+            .button("Fit terrain")
+            .on_hover_text("Flattens the ground under the object")
+            .clicked()
+        {
+            actions.push(Action::FitTerrain(*fit));
+        }
+        ui.separator();
+        let blend = &mut editor_state.prop_paint.ground_blend;
+        ui.add(egui::Slider::new(&mut blend.height, 0.1..=10.0).text("Blend height, m"));
+        ui.add(egui::Slider::new(&mut blend.strength, 0.0..=1.0).text("Blend strength"));
+        ui.horizontal(|ui| {
+            if ui
+                .button("Blend into ground")
+                .on_hover_text("Gives the bottom of the object the color of the ground")
+                .clicked()
+            {
+                actions.push(Action::BlendObjectIntoGround(*blend));
+            }
+            if ui.button("Clear paint").clicked() {
+                actions.push(Action::ClearObjectPaint);
+            }
+        });
+    });
+    ui.collapsing("Elevation tints", |ui| {
+        hypsometric_panel(ui, view, editor_state, actions)
+    });
+    ui.collapsing("Ground texture", |ui| {
+        let path = &mut editor_state.ground_texture_path;
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(path);
+            if ui.button("Load").clicked() {
+                actions.push(Action::LoadGroundTexture(path.clone()));
+            }
+        });
+        ui.label("Tiled over the terrain, KTX2 and DDS stay BC compressed");
+        ui.label("Basis Universal is transcoded for this GPU and cached");
+    });
+    ui.collapsing("Draped photo", |ui| {
+        let drape = &mut editor_state.drape;
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut drape.path);
+            if ui.button("Load").clicked() {
+                actions.push(Action::LoadDrape(drape.path.clone()));
+            }
+        });
+        if view.georeferenced {
+            ui.label("GeoTIFFs are lined up with the terrain");
+        } else {
+            ui.label("The terrain isn't georeferenced, photos cover all of it");
+        }
+        if !view.has_drape {
+            return;
+        }
+        ui.add(egui::Slider::new(&mut drape.opacity, 0.0..=1.0).text("Opacity"));
+        if ui.button("Close").clicked() {
+            actions.push(Action::ClearDrape);
+        }
+    });
+    ui.collapsing("World Machine / Gaea build", |ui| {
+        external_panel(ui, view, editor_state, actions)
+    });
+    ui.collapsing("OpenStreetMap", |ui| {
+        osm_panel(ui, view, editor_state, actions)
+    });
+    if let Some(fraction) = view.import_voids {
+        ui.collapsing("Import voids", |ui| {
+            ui.label(format!(
+                "{:.2}% of the heightmap had no data",
+                fraction * 100.0
+            ));
+            ui.checkbox(&mut editor_state.void_repair.show, "Show on terrain");
+            if ui.button("Dismiss").clicked() {
+                actions.push(Action::DismissImportVoids);
+            }
+        });
+    }
+    ui.collapsing("Compare", |ui| {
+        let compare = &mut editor_state.compare;
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut compare.path);
+            if ui.button("Load").clicked() {
+                actions.push(Action::LoadReference(compare.path.clone()));
+            }
+        });
+        if !view.has_reference {
+            return;
+        }
+        ui.add(egui::Slider::new(&mut compare.overlay_opacity, 0.0..=1.0).text("Overlay"));
+        ui.add(
+            egui::Slider::new(&mut compare.range, 0.1..=100.0)
+                .logarithmic(true)
+                .text("Range, m"),
+        );
+        ui.add(egui::Slider::new(&mut compare.blend, 0.0..=1.0).text("Blend"));
+        ui.horizontal(|ui| {
+            let revert = Some(TerrainTool::Revert);
+            if ui
+                .selectable_label(tool == revert, "Revert brush")
+                .clicked()
+            {
+                actions.push(Action::SelectTool(TerrainTool::Revert));
+            }
+            if ui.button("Blend all").clicked() {
+                actions.push(Action::BlendWithReference(compare.blend));
+            }
+            if ui.button("Close").clicked() {
+                actions.push(Action::ClearReference);
+            }
+        });
+    });
+    if ui.button("Crop / extend...").clicked() {
+        editor_state.bounds_change = Some(BoundsChange::default());
+    }
+    let save_label = if view.unsaved_changes {
+        "Save terrain*"
+    } else {
+        "Save terrain"
+    };
+    if ui.button(save_label).clicked() {
+        actions.push(Action::SaveTerrain);
+    }
+
+    ui.horizontal(|ui| {
+        ui.label("Save");
+        if ui.button("Normals").clicked() {
+            actions.push(Action::SaveNormalMap);
+        }
+        if ui.button("Splat").clicked() {
+            actions.push(Action::SaveSplatMap);
+        }
+        if ui
+            .button("AO")
+            .on_hover_text("How much of the sky the ground sees")
+            .clicked()
+        {
+            actions.push(Action::SaveAmbientOcclusionMap);
+        }
+    });
+    ui.checkbox(&mut editor_state.compressed_maps, "As KTX2")
+        .on_hover_text("BC compressed with mipmaps: BC5 normals, BC3 splat and BC4 AO");
+    ui.collapsing("Storage", |ui| storage_panel(ui, editor_state, actions));
+    ui.horizontal(|ui| {
+        ui.label("Archive");
+        ui.text_edit_singleline(&mut editor_state.archive_path);
+    });
+    archive_panel(ui, view, editor_state);
+    ui.horizontal(|ui| {
+        let path = &editor_state.archive_path;
+        if ui.button("Export archive").clicked() {
+            actions.push(Action::ExportArchive(path.clone()));
+        }
+        if ui
+            .button("Import archive")
+            .on_hover_text("Replaces the current project")
+            .clicked()
+        {
+            actions.push(Action::ImportArchive(path.clone()));
+        }
+    });
+    ui.collapsing("After export", |ui| {
+        export_hooks_panel(ui, editor_state, actions)
+    });
+    if !editor_state.recent_projects.is_empty() {
+        ui.collapsing("Recent projects", |ui| {
+            for path in &editor_state.recent_projects {
+                if ui
+                    .selectable_label(false, path)
+                    .on_hover_text("Replaces the current project")
+                    .clicked()
+                {
+                    editor_state.archive_path = path.clone();
+                    actions.push(Action::ImportArchive(path.clone()));
+                }
+            }
+        });
+    }
+    if view.io_pending > 0 {
+        ui.label(format!("Saving / loading {} file(s)...", view.io_pending));
+    }
+
+    if ui.button("Save camera position").clicked() {
+        actions.push(Action::SaveCamera);
+    }
+
+    ui.collapsing("Journal", |ui| {
+        ui.checkbox(&mut editor_state.record_journal, "Record");
+        ui.label(format!("{} operations", view.journal_len));
+        ui.horizontal(|ui| {
+            if ui
+                .button("Replay")
+                .on_hover_text(
+                    "Reloads the terrain the project starts from and replays \
+                     the journal on it",
+                )
+                .clicked()
+            {
+                actions.push(Action::ReplayJournal);
+            }
+            if ui.button("Clear").clicked() {
+                actions.push(Action::ClearJournal);
+            }
+            if ui.button("Save").clicked() {
+                actions.push(Action::SaveJournal);
+            }
+            if ui.button("Load").clicked() {
+                actions.push(Action::LoadJournal);
+            }
+        });
+    });
+    ui.collapsing("Input recording", |ui| {
+        ui.horizontal(|ui| {
+            ui.label("File:");
+            ui.text_edit_singleline(&mut editor_state.input_recording_path);
+        });
+        let path = &editor_state.input_recording_path;
+        ui.horizontal(|ui| {
+            if view.recording_input {
+                if ui.button("Stop recording").clicked() {
+                    actions.push(Action::StopRecordingInput);
+                }
+            } else if view.playing_input {
+                if ui.button("Stop playing").clicked() {
+                    actions.push(Action::StopPlayingInput);
+                }
+            } else {
+                if ui.button("Record").clicked() {
+                    actions.push(Action::StartRecordingInput(path.clone()));
+                }
+                if ui.button("Play back").clicked() {
+                    actions.push(Action::PlayInput(path.clone()));
+                }
+            }
+        });
+        ui.colored_label(
+            Color32::GRAY,
+            "Viewport input only. Play back on the project it was \
+             recorded on to check the terrain comes out the same.",
+        );
+    });
+
+    ui.collapsing("Shared editing (experimental)", |ui| {
+        if let Some(status) = &view.sync_status {
+            ui.label(status);
+            if ui.button("Leave").clicked() {
+                actions.push(Action::LeaveSession);
+            }
+        } else {
+            ui.text_edit_singleline(&mut editor_state.sync_address);
+            ui.horizontal(|ui| {
+                if ui.button("Host").clicked() {
+                    actions.push(Action::HostSession);
+                }
+                if ui
+                    .button("Join")
+                    .on_hover_text("Everyone has to start from the same project")
+                    .clicked()
+                {
+                    actions.push(Action::JoinSession);
+                }
+            });
+        }
+    });
+
+    ui.separator();
+    let mut layout = view.viewport_layout;
+    egui::ComboBox::from_label("Viewports")
+        .selected_text(layout.name())
+        .show_ui(ui, |ui| {
+            for option in ViewportLayout::ALL {
+                ui.selectable_value(&mut layout, option, option.name());
+            }
+        });
+    if layout != view.viewport_layout {
+        actions.push(Action::SetViewportLayout(layout));
+    }
+    let mesh = &mut editor_state.terrain_mesh;
+    egui::ComboBox::from_label("Terrain mesh")
+        .selected_text(mesh.name())
+        .show_ui(ui, |ui| {
+            for option in TerrainMesh::ALL {
+                ui.selectable_value(mesh, option, option.name());
+            }
+        })
+        .response
+        .on_hover_text("A clipmap has the most detail around the camera and less further out");
+    ui.checkbox(&mut editor_state.tiling_preview, "Tiling preview")
+        .on_hover_text("Surrounds the terrain with copies of itself to show seams");
+
+    ui.separator();
+    let resolution = &mut editor_state.resolution;
+    let mut percent = (resolution.scale * 100.0).round();
+    ui.add(
+        egui::Slider::new(&mut percent, 50.0..=200.0)
+            .text("Render scale")
+            .suffix("%"),
+    )
+    .on_hover_text("Below 100% draws fewer pixels and upscales them");
+    resolution.scale = percent / 100.0;
+    ui.add_enabled(
+        resolution.scale < 1.0,
+        egui::Slider::new(&mut resolution.sharpening, 0.0..=1.0).text("Sharpening"),
+    );
+    ui.collapsing("Sky lighting", |ui| {
+        let sky_light = &mut editor_state.sky_light;
+        ui.add(egui::Slider::new(&mut sky_light.diffuse, 0.0..=1.0).text("Sky ambient"))
+            .on_hover_text("0 is a flat ambient");
+        ui.add(egui::Slider::new(&mut sky_light.specular, 0.0..=1.0).text("Reflections"));
+    });
+    ui.collapsing("Shadows", |ui| {
+        let horizon = &mut editor_state.horizon;
+        ui.checkbox(&mut horizon.shadow_map, "Shadow map")
+            .on_hover_text("Lets models cast shadows, but redraws everything each frame");
+        ui.checkbox(&mut horizon.enabled, "Horizon shadows")
+            .on_hover_text("Terrain only, baked after every edit");
+        ui.add_enabled(
+            horizon.enabled,
+            egui::Slider::new(&mut horizon.sky_occlusion, 0.0..=1.0).text("Sky occlusion"),
+        );
+        ui.add_enabled(
+            horizon.enabled,
+            egui::Slider::new(&mut horizon.softness, 0.0..=0.3).text("Softness"),
+        );
+    });
+    ui.collapsing("Objects meeting the ground", |ui| {
+        let blend = &mut editor_state.intersection_blend;
+        ui.checkbox(&mut blend.enabled, "Blend into the terrain")
+            .on_hover_text("Fades the bottom of objects and rocks into the ground under them");
+        ui.add_enabled(
+            blend.enabled,
+            egui::Slider::new(&mut blend.height, 0.05..=3.0).text("Height, m"),
+        );
+        ui.add_enabled(
+            blend.enabled,
+            egui::Slider::new(&mut blend.shading, 0.0..=1.0).text("Ground shading"),
+        );
+    });
+    ui.collapsing("Screen-space reflections", |ui| {
+        let reflections = &mut editor_state.reflections;
+        ui.checkbox(&mut reflections.enabled, "Enabled");
+        egui::ComboBox::from_label("Quality")
+            .selected_text(reflections.quality.name())
+            .show_ui(ui, |ui| {
+                for option in ReflectionQuality::ALL {
+                    ui.selectable_value(&mut reflections.quality, option, option.name());
+                }
+            });
+        ui.add(egui::Slider::new(&mut reflections.wetness, 0.0..=1.0).text("Wet ground"));
+        ui.add(
+            egui::Slider::new(&mut reflections.max_distance, 10.0..=1000.0)
+                .logarithmic(true)
+                .text("Max distance (m)"),
+        );
+        ui.add(egui::Slider::new(&mut reflections.thickness, 0.1..=20.0).text("Thickness (m)"));
+    });
+    let photo = &mut editor_state.photo;
+    ui.checkbox(&mut photo.enabled, "Photo mode");
+    if photo.enabled {
+        ui.label("Click on the terrain to focus");
+        ui.checkbox(&mut photo.dof_enabled, "Depth of field");
+        ui.add(egui::Slider::new(&mut photo.aperture, 0.0..=2.0).text("Aperture"));
+        ui.add(egui::Slider::new(&mut photo.max_blur_radius, 1.0..=32.0).text("Max blur (px)"));
+        ui.checkbox(&mut photo.vignette_enabled, "Vignette");
+        ui.add(
+            egui::Slider::new(&mut photo.vignette_strength, 0.0..=1.0).text("Vignette strength"),
+        );
+        ui.add(egui::Slider::new(&mut photo.vignette_radius, 0.0..=1.0).text("Vignette radius"));
+    }
+    if ui.button("Save screenshot").clicked() {
+        actions.push(Action::TakeScreenshot);
+    }
+
+    ui.separator();
+    let grading = &mut editor_state.grading;
+    ui.checkbox(&mut grading.enabled, "Colour grading");
+    if grading.enabled {
+        let selected = grading.lut_path.clone();
+        let selected_name = selected
+            .as_deref()
+            .and_then(|path| std::path::Path::new(path).file_stem())
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "None".to_owned());
+        egui::ComboBox::from_label("LUT")
+            .selected_text(selected_name)
+            .show_ui(ui, |ui| {
+                for path in grading.available_luts.iter() {
+                    let name = std::path::Path::new(path)
+                        .file_stem()
+                        .map(|name| name.to_string_lossy().into_owned())
+                        .unwrap_or_default();
+                    ui.selectable_value(&mut grading.lut_path, Some(path.clone()), name);
+                }
+            });
+        if grading.lut_path != selected {
+            if let Some(path) = &grading.lut_path {
+                actions.push(Action::LoadLut(path.clone()));
+            }
+        }
+        ui.add(egui::Slider::new(&mut grading.intensity, 0.0..=1.0).text("Intensity"));
+        if ui.small_button("Rescan LUTs").clicked() {
+            grading.available_luts = crate::postprocess::list_luts();
+        }
+    }
+
+    ui.separator();
+    ui.collapsing("Help", |ui| {
+        ui.label("Right mouse + move: look around");
+        ui.label("Right mouse + W/A/S/D: fly, Shift to go faster");
+        ui.label("Left mouse: use the tool, Ctrl to do the opposite");
+        ui.label("Mouse wheel: brush size");
+        ui.label("Ctrl+Shift+P: find any command");
+        if ui.button("Show tutorial").clicked() {
+            actions.push(Action::ShowTutorial);
+        }
+    });
+}
+
+/// A build folder's heights, splat map and flow map in one go
+fn external_panel(
+    ui: &mut egui::Ui,
+    view: &ViewInfo,
+    editor_state: &mut EditorState,
+    actions: &mut Vec<Action>,
+) {
+    let external = &mut editor_state.external;
+    ui.horizontal(|ui| {
+        ui.label("Folder");
+        ui.text_edit_singleline(&mut external.path);
+    });
+    ui.horizontal(|ui| {
+        egui::ComboBox::from_id_source("External tool")
+            .selected_text(external.tool.name())
+            .show_ui(ui, |ui| {
+                for tool in ExternalTool::ALL {
+                    ui.selectable_value(&mut external.tool, tool, tool.name());
+                }
+            });
+        if ui
+            .button("Import")
+            .on_hover_text("Replaces the terrain's heights and texturing, this can't be undone")
+            .clicked()
+        {
+            actions.push(Action::ImportBuild(external.path.clone(), external.tool));
+        }
+    });
+    if view.import_flow {
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut external.show_flow, "Show flow on terrain");
+            if ui.button("Dismiss").clicked() {
+                actions.push(Action::DismissImportFlow);
+            }
+        });
+    }
+}
+
+/// Which layers and scatter type the map data goes into
+/// Shared by the erosion button and the erosion brush
+fn erosion_splat_panel(ui: &mut egui::Ui, splat: &mut ErosionSplat) {
+    let layers = default_layers();
+    let layer_name = |layer: Option<usize>| layer.map_or("Nothing", |l| layers[l].name);
+    for (label, layer) in [
+        ("Paint sediment with", &mut splat.sediment_layer),
+        ("Paint worn slopes with", &mut splat.rock_layer),
+    ] {
+        egui::ComboBox::from_label(label)
+            .selected_text(layer_name(*layer))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(layer, None, "Nothing");
+                for (i, option) in layers.iter().enumerate() {
+                    ui.selectable_value(layer, Some(i), option.name);
+                }
+            });
+    }
+    ui.add_enabled(
+        splat.sediment_layer.is_some() || splat.rock_layer.is_some(),
+        egui::Slider::new(&mut splat.depth, 0.1..=20.0).text("Full coverage at, m"),
+    );
+}
+
+fn osm_panel(
+    ui: &mut egui::Ui,
+    view: &ViewInfo,
+    editor_state: &mut EditorState,
+    actions: &mut Vec<Action>,
+) {
+    let types = &editor_state.scatter.types;
+    let osm = &mut editor_state.osm;
+    ui.horizontal(|ui| {
+        ui.label("File");
+        ui.text_edit_singleline(&mut osm.path);
+    });
+    if !view.georeferenced {
+        ui.colored_label(
+            Color32::GRAY,
+            "The terrain isn't georeferenced, the map will be stretched over it",
+        );
+    }
+    let layers = default_layers();
+    let layer_name = |layer: Option<usize>| layer.map_or("Nothing", |l| layers[l].name);
+    for (label, layer) in [
+        ("Roads", &mut osm.road_layer),
+        ("Water", &mut osm.water_layer),
+    ] {
+        egui::ComboBox::from_label(label)
+            .selected_text(layer_name(*layer))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(layer, None, "Nothing");
+                for (i, option) in layers.iter().enumerate() {
+                    ui.selectable_value(layer, Some(i), option.name);
+                }
+            });
+    }
+    ui.checkbox(&mut osm.flatten_roads, "Flatten the ground under roads");
+    let forest_name = osm
+        .forest_type
+        .and_then(|i| types.get(i))
+        .map_or("Nothing", |t| t.name.as_str());
+    egui::ComboBox::from_label("Forests")
+        .selected_text(forest_name)
+        .show_ui(ui, |ui| {
+            ui.selectable_value(&mut osm.forest_type, None, "Nothing");
+            for (i, scatter_type) in types.iter().enumerate() {
+                ui.selectable_value(&mut osm.forest_type, Some(i), &scatter_type.name);
+            }
+        });
+    if ui
+        .button("Import")
+        .on_hover_text("Changes the terrain, this can't be undone")
+        .clicked()
+    {
+        actions.push(Action::ImportOsm(osm.path.clone()));
+    }
+}
+
+/// Where archives are exported to and imported from
+fn storage_panel(ui: &mut egui::Ui, editor_state: &mut EditorState, actions: &mut Vec<Action>) {
+    let storage = &mut editor_state.storage;
+    egui::ComboBox::from_id_source("Storage kind")
+        .selected_text(storage.kind.name())
+        .show_ui(ui, |ui| {
+            for kind in StorageKind::ALL {
+                ui.selectable_value(&mut storage.kind, kind, kind.name());
+            }
+        });
+    match storage.kind {
+        StorageKind::Local => {
+            ui.colored_label(Color32::GRAY, "Archive paths are relative to the editor");
+        }
+        StorageKind::Folder => {
+            ui.horizontal(|ui| {
+                ui.label("Folder");
+                ui.text_edit_singleline(&mut storage.folder)
+                    .on_hover_text("A network share, or a bucket mounted as a drive");
+            });
+        }
+        StorageKind::WebDav => {
+            ui.horizontal(|ui| {
+                ui.label("URL");
+                ui.text_edit_singleline(&mut storage.url)
+                    .on_hover_text("http:// only, e.g. http://nas.local/dav/terrain/");
+            });
+            ui.horizontal(|ui| {
+                ui.label("User");
+                ui.text_edit_singleline(&mut storage.username);
+            });
+            ui.horizontal(|ui| {
+                ui.label("Password");
+                ui.add(egui::TextEdit::singleline(&mut storage.password).password(true));
+            });
+        }
+    }
+    if ui
+        .button("Remember")
+        .on_hover_text("Saved as plain text next to the editor")
+        .clicked()
+    {
+        actions.push(Action::SaveStorage);
+    }
+}
+
+/// Commands run once each kind of export is written
+fn export_hooks_panel(
+    ui: &mut egui::Ui,
+    editor_state: &mut EditorState,
+    actions: &mut Vec<Action>,
+) {
+    ui.colored_label(
+        Color32::GRAY,
+        format!("Shell commands, {} is what was exported", PATH_PLACEHOLDER),
+    );
+    egui::Grid::new("Export hooks").show(ui, |ui| {
+        for profile in ExportProfile::ALL {
+            ui.label(profile.name());
+            ui.text_edit_singleline(editor_state.export_hooks.command_mut(profile));
+            ui.end_row();
+        }
+    });
+    if ui
+        .button("Remember")
+        .on_hover_text("Saved in the project's config")
+        .clicked()
+    {
+        actions.push(Action::SaveExportHooks);
+    }
+}
+
+/// How the heightmap is packed into archives, and what the last one came to
+fn archive_panel(ui: &mut egui::Ui, view: &ViewInfo, editor_state: &mut EditorState) {
+    let bits = &mut editor_state.pack.heightmap_bits;
+    ui.add(egui::Slider::new(bits, MIN_BITS..=LOSSLESS_BITS).text("Heightmap bits"));
+    if *bits == LOSSLESS_BITS {
+        ui.label("Lossless");
+    } else {
+        let error = codec::max_error(*bits) * view.terrain.max_height;
+        ui.label(format!("Heights off by up to {:.2} m", error));
+    }
+    if let Some(report) = &editor_state.last_export {
+        let megabytes = |bytes: u64| bytes as f64 / (1024.0 * 1024.0);
+        let archive = megabytes(report.archive_bytes);
+        match report.heightmap_bytes {
+            Some(heightmap) => ui.colored_label(
+                Color32::GRAY,
+                format!(
+                    "Last export: {:.1} MB of heights and layers packed into {:.1} MB, \
+                     heights at {} bits",
+                    megabytes(heightmap + report.layer_bytes),
+                    archive,
+                    report.heightmap_bits
+                ),
+            ),
+            None => ui.colored_label(Color32::GRAY, format!("Last export: {:.1} MB", archive)),
+        };
+    }
+}
+
+/// Broad shaping on a downsampled copy of a big heightmap, which keeps sculpting
+/// interactive. The changes are upsampled onto the full heights when it's applied.
+fn proxy_panel(
+    ui: &mut egui::Ui,
+    view: &ViewInfo,
+    editor_state: &mut EditorState,
+    actions: &mut Vec<Action>,
+) {
+    if let Some(resolution) = view.proxy_resolution {
+        ui.label(format!(
+            "Sculpting on a {0}x{0} copy of the {1}x{1} heightmap",
+            resolution, view.terrain.resolution
+        ));
+        if ui.button("Apply to full resolution").clicked() {
+            actions.push(Action::EndProxy);
+        }
+        return;
+    }
+    let proxy = &mut editor_state.proxy_resolution;
+    egui::ComboBox::from_label("Proxy resolution")
+        .selected_text(proxy.to_string())
+        .show_ui(ui, |ui| {
+            for option in RESOLUTIONS {
+                if option < view.terrain.resolution {
+                    ui.selectable_value(proxy, option, option.to_string());
+                }
+            }
+        });
+    let syncing = view.sync_status.is_some();
+    let enabled = *proxy < view.terrain.resolution && !syncing;
+    if ui
+        .add_enabled(enabled, egui::Button::new("Sculpt on proxy"))
+        .clicked()
+    {
+        actions.push(Action::BeginProxy(*proxy));
+    }
+    if syncing {
+        ui.colored_label(Color32::GRAY, "Not while editing together with others");
+    }
+}
+
+/// Operations on the whole heightmap, one at a time with its own parameters
+fn filters_panel(
+    ui: &mut egui::Ui,
+    view: &ViewInfo,
+    editor_state: &mut EditorState,
+    actions: &mut Vec<Action>,
+) {
+    let edges = editor_state.edges;
+    let filters = &mut editor_state.filters;
+    egui::ComboBox::from_label("Operation")
+        .selected_text(filters.kind.name())
+        .show_ui(ui, |ui| {
+            for kind in FilterKind::ALL {
+                ui.selectable_value(&mut filters.kind, kind, kind.name());
+            }
+        });
+    // Amounts are fractions of the max height, the labels say what that is in meters
+    let meters = |fraction: f32| format!("{:.1} m", fraction * view.terrain.max_height);
+    match filters.kind {
+        FilterKind::Blur => {
+            ui.add(egui::Slider::new(&mut filters.blur_radius, 1..=32).text("Radius"));
+        }
+        FilterKind::Sharpen => {
+            ui.add(egui::Slider::new(&mut filters.blur_radius, 1..=32).text("Radius"));
+            ui.add(egui::Slider::new(&mut filters.sharpen_amount, 0.0..=4.0).text("Amount"));
+        }
+        FilterKind::Terrace => {
+            ui.add(egui::Slider::new(&mut filters.terrace_steps, 2..=32).text("Steps"));
+            ui.add(egui::Slider::new(&mut filters.terrace_sharpness, 0.0..=1.0).text("Sharpness"));
+        }
+        FilterKind::Add => {
+            ui.add(egui::Slider::new(&mut filters.add_amount, -1.0..=1.0).text("Amount"));
+            ui.label(meters(filters.add_amount));
+        }
+        FilterKind::Multiply => {
+            ui.add(egui::Slider::new(&mut filters.multiply_factor, 0.0..=4.0).text("Factor"));
+        }
+        FilterKind::Clamp | FilterKind::Normalize => {
+            ui.add(egui::Slider::new(&mut filters.min, 0.0..=1.0).text("Min"));
+            ui.add(egui::Slider::new(&mut filters.max, 0.0..=1.0).text("Max"));
+            ui.label(format!(
+                "{} to {}",
+                meters(filters.min),
+                meters(filters.max)
+            ));
+        }
+        FilterKind::Invert => {}
+        FilterKind::Curve => {
+            editor_state.curve_editor.show(ui, &mut filters.curve);
+            ui.horizontal(|ui| {
+                ui.label("Click to add a point, right click to remove one");
+                if ui.small_button("Reset").clicked() {
+                    filters.curve = HeightCurve::default();
+                }
+            });
+        }
+        FilterKind::Power => {
+            ui.add(
+                egui::Slider::new(&mut filters.exponent, 0.1..=8.0)
+                    .logarithmic(true)
+                    .text("Exponent"),
+            );
+        }
+        FilterKind::Despeckle => {
+            ui.add(egui::Slider::new(&mut filters.despeckle_radius, 1..=4).text("Radius"));
+            ui.add(
+                egui::Slider::new(&mut filters.despeckle_threshold, 0.0..=0.1)
+                    .logarithmic(true)
+                    .text("Threshold"),
+            );
+            ui.label(format!(
+                "Spikes and pits over {}",
+                meters(filters.despeckle_threshold)
+            ));
+        }
+        FilterKind::FillSinks => {
+            ui.label("Fills every pit up to its brim so water can flow off the terrain");
+        }
+        FilterKind::MakeTileable => {
+            let mut percent = filters.tileable_width * 100.0;
+            ui.add(
+                egui::Slider::new(&mut percent, 1.0..=50.0)
+                    .suffix("%")
+                    .text("Blend width"),
+            );
+            filters.tileable_width = percent / 100.0;
+            ui.label("Opposite edges meet, set edges to wrap to sculpt across them");
+        }
+    }
+
+    let filter = filters.filter(edges);
+    ui.horizontal(|ui| {
+        ui.checkbox(&mut filters.preview, "Preview");
+        if ui.button("Apply").clicked() {
+            actions.push(Action::ApplyFilter(filter));
+            // Would show the filter applied twice
+            filters.preview = false;
+        }
+        if ui
+            .add_enabled(view.can_undo_filter, egui::Button::new("Undo"))
+            .clicked()
+        {
+            actions.push(Action::UndoFilter);
+        }
+    });
+    if filters.preview && view.filter_preview != Some(filter) {
+        actions.push(Action::PreviewFilter(filter));
+    } else if !filters.preview && view.filter_preview.is_some() {
+        actions.push(Action::EndFilterPreview);
+    }
+}
+
+/// Elevation colors: the gradient as a bar, then one row per stop, lowest first
+fn hypsometric_panel(
+    ui: &mut egui::Ui,
+    view: &ViewInfo,
+    editor_state: &mut EditorState,
+    actions: &mut Vec<Action>,
+) {
+    let hypsometric = &mut editor_state.hypsometric;
+    ui.checkbox(&mut hypsometric.enabled, "Show");
+    ui.add(egui::Slider::new(&mut hypsometric.opacity, 0.0..=1.0).text("Opacity"));
+
+    let gradient = &mut hypsometric.gradient;
+    let size = egui::Vec2::new(ui.available_width().min(240.0), 16.0);
+    let (rect, _) = ui.allocate_exact_size(size, egui::Sense::hover());
+    const SLICES: usize = 64;
+    let slice_width = rect.width() / SLICES as f32;
+    for i in 0..SLICES {
+        let color = gradient.color_at((i as f32 + 0.5) / SLICES as f32);
+        let left = rect.left() + i as f32 * slice_width;
+        let slice = Rect::from_min_size(
+            Pos2::new(left, rect.top()),
+            egui::Vec2::new(slice_width + 0.5, rect.height()),
+        );
+        ui.painter()
+            .rect_filled(slice, 0.0, egui::Rgba::from_rgb(color.x, color.y, color.z));
+    }
+
+    let mut removed = None;
+    for (i, stop) in gradient.stops_mut().iter_mut().enumerate() {
+        ui.horizontal(|ui| {
+            ui.color_edit_button_rgb(&mut stop.color);
+            ui.add(egui::Slider::new(&mut stop.height, 0.0..=1.0).show_value(false));
+            ui.label(format!("{:.0} m", stop.height * view.terrain.max_height));
+            if ui.small_button("x").clicked() {
+                removed = Some(i);
+            }
+        });
+    }
+    if let Some(index) = removed {
+        gradient.remove_stop(index);
+    }
+    gradient.sort();
+
+    ui.horizontal(|ui| {
+        let has_room = gradient.stops().len() < MAX_GRADIENT_STOPS;
+        if ui
+            .add_enabled(has_room, egui::Button::new("Add stop"))
+            .clicked()
+        {
+            gradient.add_stop();
+        }
+        if ui.button("Reset").clicked() {
+            *gradient = ColorGradient::default();
+        }
+    });
+    if ui.button("Save as texture").clicked() {
+        actions.push(Action::SaveHypsometricMap);
+    }
+}
+
+/// Where the cursor is, in meters and on the ground when the terrain is georeferenced
+fn status_bar(ui: &mut egui::Ui, view: &ViewInfo) {
+    ui.horizontal(|ui| {
+        let cursor = match view.cursor {
+            Some(cursor) => cursor,
+            None => {
+                ui.label("Cursor is off the terrain");
+                return;
+            }
+        };
+        ui.label(format!(
+            "X {:.1} m  Z {:.1} m  Height {:.1} m",
+            cursor.x, cursor.z, cursor.y
+        ));
+        if let Some((x, y)) = view.cursor_geo {
+            ui.separator();
+            ui.label(format!("E {:.1}  N {:.1}", x, y));
+        }
+        if let Some((lat, long)) = view.cursor_lat_long {
+            ui.separator();
+            let north_south = if lat >= 0.0 { "N" } else { "S" };
+            let east_west = if long >= 0.0 { "E" } else { "W" };
+            ui.label(format!(
+                "{:.5}° {}  {:.5}° {}",
+                lat.abs(),
+                north_south,
+                long.abs(),
+                east_west
+            ));
+        }
+    });
+}
+
+fn stats_panel(
+    ui: &mut egui::Ui,
+    view: &ViewInfo,
+    editor_state: &mut EditorState,
+    actions: &mut Vec<Action>,
+) {
+    let capabilities = caps::get();
+    let (major, minor) = capabilities.version;
+    ui.label(format!("OpenGL {}.{}", major, minor));
+    for limitation in capabilities.limitations() {
+        ui.colored_label(Color32::GRAY, format!("Older GPU: {}", limitation));
+    }
+
+    ui.separator();
+    let usage = resources::usage();
+    ui.label("Estimated video memory");
+    egui::Grid::new("memory").show(ui, |ui| {
+        for kind in ResourceKind::ALL {
+            ui.label(kind.name());
+            ui.label(resources::format_bytes(usage.of(kind)));
+            ui.end_row();
+        }
+        ui.label("Total");
+        ui.label(resources::format_bytes(usage.total()));
+        ui.end_row();
+    });
+    let budget = &mut editor_state.memory_budget;
+    ui.add(
+        egui::DragValue::new(&mut budget.budget_mb)
+            .clamp_range(64..=32768)
+            .speed(16.0)
+            .prefix("Budget: ")
+            .suffix(" MB"),
+    );
+    ui.add(egui::Slider::new(&mut budget.max_downres, 0..=6).text("Max mip levels dropped"));
+    if usage.total() > budget.bytes() {
+        ui.colored_label(Color32::YELLOW, "Over budget");
+    }
+
+    ui.separator();
+    ui.horizontal(|ui| {
+        let disk_bytes = derived_cache::disk_bytes() as usize;
+        ui.label(format!(
+            "Derived data: {}",
+            resources::format_bytes(disk_bytes)
+        ));
+        if ui
+            .small_button("Clear")
+            .on_hover_text("Horizons, sky light and compressed textures are redone when needed")
+            .clicked()
+        {
+            actions.push(Action::ClearDerivedCache);
+        }
+    });
+
+    ui.separator();
+    let frames = &mut editor_state.frames;
+    if view.vsync_supported {
+        ui.checkbox(&mut frames.vsync, "Vsync");
+    } else {
+        ui.label("Vsync can't be changed on this platform");
+    }
+    ui.horizontal(|ui| {
+        ui.checkbox(&mut frames.fps_cap, "Cap FPS");
+        ui.add_enabled(
+            frames.fps_cap,
+            egui::DragValue::new(&mut frames.max_fps)
+                .clamp_range(10..=480)
+                .suffix(" FPS"),
+        );
+    });
+    ui.checkbox(&mut frames.save_power, "Save power when idle")
+        .on_hover_text("Stops drawing when there's no input and nothing is animating");
+
+    ui.separator();
+    let mut percent = (editor_state.ui_scale * 100.0).round();
+    let response = ui.add(
+        egui::Slider::new(&mut percent, 75.0..=200.0)
+            .text("UI scale")
+            .suffix("%"),
+    );
+    editor_state.ui_scale = percent / 100.0;
+    // Rescaling mid-drag would move the slider out from under the pointer
+    if response.drag_released() || (response.changed() && !response.dragged()) {
+        actions.push(Action::SetUiScale(editor_state.ui_scale));
+    }
+
+    let response = ui
+        .checkbox(&mut editor_state.session_stats, "Save session stats")
+        .on_hover_text(format!(
+            "Time per tool, undos and the longest stall, added to {} on exit. \
+             Nothing leaves this computer.",
+            STATS_PATH
+        ));
+    if response.changed() {
+        actions.push(Action::SetSessionStats(editor_state.session_stats));
+    }
+}
+
+/// Only for picking a new seed, nothing is generated from the clock
+fn random_seed() -> u32 {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0);
+    nanos.wrapping_mul(0x9e37_79b9)
+}
+
+fn wind_sliders(ui: &mut egui::Ui, wind: &mut Wind) {
+    ui.add(egui::Slider::new(&mut wind.direction, 0.0..=360.0).text("Direction, °"));
+    ui.add(egui::Slider::new(&mut wind.strength, 0.0..=3.0).text("Strength"));
+}
+
+/// Tint or new color, switching keeps the color
+fn seasonal_color_edit(ui: &mut egui::Ui, color: &mut SeasonalColor) {
+    let (mut swap, mut rgb) = match *color {
+        SeasonalColor::Tint(rgb) => (false, rgb),
+        SeasonalColor::Swap(rgb) => (true, rgb),
+    };
+    ui.radio_value(&mut swap, false, "Tint");
+    ui.radio_value(&mut swap, true, "Swap");
+    ui.color_edit_button_rgb(&mut rgb);
+    *color = if swap {
+        SeasonalColor::Swap(rgb)
+    } else {
+        SeasonalColor::Tint(rgb)
+    };
+}
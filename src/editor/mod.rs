@@ -1 +1,6 @@
+pub mod commands;
+pub mod curve_editor;
+pub mod detached;
 pub mod gui;
+pub mod palette;
+pub mod tutorial;
@@ -0,0 +1,214 @@
+//! Ctrl+P quick open and the Ctrl+Shift+P command palette: type a few letters of a
+//! prefab, scatter type, LUT or recent project, or of any editor command, and press
+//! Enter instead of looking for it in the panels.
+
+use egui::{Align2, Color32, CtxRef, Key};
+
+use crate::editor::commands::{self, Command};
+use crate::editor::gui::Action;
+use crate::{EditorState, TerrainTool};
+
+/// More than this and it's quicker to type another letter than to scroll
+const MAX_RESULTS: usize = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PaletteMode {
+    /// Assets and recent projects
+    QuickOpen,
+    Commands,
+}
+
+#[derive(Default)]
+pub struct Palette {
+    /// None when closed
+    mode: Option<PaletteMode>,
+    query: String,
+    selected: usize,
+}
+
+enum Entry {
+    Command(Command),
+    Prefab(String),
+    ScatterType(usize),
+    Lut(String),
+    RecentProject(String),
+}
+
+impl Palette {
+    /// Opening the other mode switches to it
+    pub fn toggle(&mut self, mode: PaletteMode) {
+        self.mode = if self.mode == Some(mode) {
+            None
+        } else {
+            Some(mode)
+        };
+        self.query.clear();
+        self.selected = 0;
+    }
+
+    pub fn show(
+        &mut self,
+        ctx: &CtxRef,
+        editor_state: &mut EditorState,
+        actions: &mut Vec<Action>,
+    ) {
+        let mode = match self.mode {
+            Some(mode) => mode,
+            None => return,
+        };
+        let entries = match mode {
+            PaletteMode::QuickOpen => assets(editor_state),
+            PaletteMode::Commands => commands(),
+        };
+        let mut matches: Vec<(i32, String, String, Entry)> = entries
+            .into_iter()
+            .filter_map(|(label, hint, entry)| {
+                fuzzy_score(&self.query, &label).map(|score| (score, label, hint, entry))
+            })
+            .collect();
+        // Stable, so equally good matches keep their order
+        matches.sort_by_key(|(score, ..)| -score);
+        matches.truncate(MAX_RESULTS);
+
+        let (up, down, enter, escape) = {
+            let input = ctx.input();
+            (
+                input.key_pressed(Key::ArrowUp),
+                input.key_pressed(Key::ArrowDown),
+                input.key_pressed(Key::Enter),
+                input.key_pressed(Key::Escape),
+            )
+        };
+        if down {
+            self.selected += 1;
+        }
+        if up {
+            self.selected = self.selected.saturating_sub(1);
+        }
+        self.selected = self.selected.min(matches.len().saturating_sub(1));
+
+        let mut chosen = if enter { Some(self.selected) } else { None };
+        let title = match mode {
+            PaletteMode::QuickOpen => "Quick open",
+            PaletteMode::Commands => "Command palette",
+        };
+        egui::Window::new(title)
+            .anchor(Align2::CENTER_TOP, egui::Vec2::new(0.0, 60.0))
+            .collapsible(false)
+            .resizable(false)
+            .title_bar(false)
+            .show(ctx, |ui| {
+                let response = ui.text_edit_singleline(&mut self.query);
+                response.request_focus();
+                if response.changed() {
+                    self.selected = 0;
+                }
+                for (i, (_, label, hint, _)) in matches.iter().enumerate() {
+                    ui.horizontal(|ui| {
+                        if ui.selectable_label(i == self.selected, label).clicked() {
+                            chosen = Some(i);
+                        }
+                        ui.colored_label(Color32::GRAY, hint);
+                    });
+                }
+                if matches.is_empty() {
+                    ui.label("Nothing matches");
+                }
+            });
+
+        if let Some(index) = chosen {
+            if index < matches.len() {
+                let (.., entry) = matches.swap_remove(index);
+                run(entry, editor_state, actions);
+            }
+            self.mode = None;
+        }
+        if escape {
+            self.mode = None;
+        }
+    }
+}
+
+/// Label, the key binding if there is one, and the command
+fn commands() -> Vec<(String, String, Entry)> {
+    commands::all()
+        .into_iter()
+        .filter(|info| !matches!(info.command, Command::QuickOpen | Command::CommandPalette))
+        .map(|info| {
+            let binding = info.binding.map(|b| b.name()).unwrap_or_default();
+            (info.name, binding, Entry::Command(info.command))
+        })
+        .collect()
+}
+
+/// Whatever there is to open right now, labels are what's searched
+fn assets(editor_state: &EditorState) -> Vec<(String, String, Entry)> {
+    let mut assets = vec![];
+    for name in &editor_state.prefabs.library {
+        assets.push((
+            name.clone(),
+            "Prefab".to_owned(),
+            Entry::Prefab(name.clone()),
+        ));
+    }
+    for (i, scatter_type) in editor_state.scatter.types.iter().enumerate() {
+        let name = scatter_type.name.clone();
+        assets.push((name, "Scatter type".to_owned(), Entry::ScatterType(i)));
+    }
+    for path in &editor_state.grading.available_luts {
+        assets.push((path.clone(), "LUT".to_owned(), Entry::Lut(path.clone())));
+    }
+    for path in &editor_state.recent_projects {
+        let entry = Entry::RecentProject(path.clone());
+        assets.push((path.clone(), "Recent project".to_owned(), entry));
+    }
+    assets
+}
+
+fn run(entry: Entry, editor_state: &mut EditorState, actions: &mut Vec<Action>) {
+    match entry {
+        Entry::Command(command) => commands::run(command, editor_state, actions),
+        Entry::Prefab(name) => {
+            editor_state.prefabs.selected = Some(name);
+            actions.push(Action::SelectTool(TerrainTool::PlacePrefab));
+        }
+        Entry::ScatterType(index) => {
+            editor_state.scatter.selected = Some(index);
+            actions.push(Action::SelectTool(TerrainTool::PaintVegetation));
+        }
+        Entry::Lut(path) => {
+            editor_state.grading.enabled = true;
+            editor_state.grading.lut_path = Some(path.clone());
+            actions.push(Action::LoadLut(path));
+        }
+        Entry::RecentProject(path) => {
+            editor_state.archive_path = path.clone();
+            actions.push(Action::ImportArchive(path));
+        }
+    }
+}
+
+/// None unless every character of `query` is in `label` in the same order.
+/// Higher is better: runs of consecutive characters and word starts count extra,
+/// so "sn" prefers "Save normal map" over "Toggle snapping".
+fn fuzzy_score(query: &str, label: &str) -> Option<i32> {
+    let label: Vec<char> = label.to_lowercase().chars().collect();
+    let mut score = 0;
+    let mut position = 0;
+    let mut previous_match = None;
+    for query_char in query.to_lowercase().chars().filter(|c| !c.is_whitespace()) {
+        let offset = label[position..].iter().position(|&c| c == query_char)?;
+        let index = position + offset;
+        score += 1;
+        if previous_match.is_some_and(|previous| previous + 1 == index) {
+            score += 5;
+        }
+        if index == 0 || !label[index - 1].is_alphanumeric() {
+            score += 3;
+        }
+        score -= offset as i32;
+        previous_match = Some(index);
+        position = index + 1;
+    }
+    Some(score)
+}
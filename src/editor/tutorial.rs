@@ -0,0 +1,183 @@
+//! A walk through the editor shown on the first run: looking around, then a quick
+//! sculpt, paint and scatter. Each step waits for the user to actually do it with
+//! the real tools, watching the view for it, so nothing here edits the terrain.
+
+use egui::{Align2, Color32, CtxRef, Id, LayerId, Order, Rect, Stroke};
+use glam::Mat4;
+
+use crate::editor::gui::{Action, ViewInfo};
+use crate::TerrainTool;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Step {
+    Welcome,
+    Camera,
+    Tools,
+    Sculpt,
+    Paint,
+    Scatter,
+    Done,
+}
+
+impl Step {
+    fn next(self) -> Option<Step> {
+        match self {
+            Step::Welcome => Some(Step::Camera),
+            Step::Camera => Some(Step::Tools),
+            Step::Tools => Some(Step::Sculpt),
+            Step::Sculpt => Some(Step::Paint),
+            Step::Paint => Some(Step::Scatter),
+            Step::Scatter => Some(Step::Done),
+            Step::Done => None,
+        }
+    }
+
+    fn text(self) -> &'static str {
+        match self {
+            Step::Welcome => {
+                "This takes a minute and shows the basics: moving around, then sculpting, \
+                 painting and scattering on the terrain in front of you."
+            }
+            Step::Camera => {
+                "Hold the right mouse button and move the mouse to look around. Keep holding \
+                 it and use W, A, S and D to fly, Shift to go faster."
+            }
+            Step::Tools => {
+                "The tools are in the highlighted window. Pick Sculpt, the number keys \
+                 pick them too."
+            }
+            Step::Sculpt => {
+                "Hold the left mouse button over the terrain to raise it, with Ctrl to lower \
+                 it. The mouse wheel changes the brush size."
+            }
+            Step::Paint => "Now pick Paint, choose a layer and paint over what you've sculpted.",
+            Step::Scatter => {
+                "Last, pick Scatter. Add a scatter type if there isn't one yet, then paint \
+                 some instances onto the terrain."
+            }
+            Step::Done => {
+                "That's it. Everything else is in the tools window, and Ctrl+Shift+P finds \
+                 any command by name. The tutorial can be shown again from Help."
+            }
+        }
+    }
+
+    /// The tools window is where every step but the first two and the last happens
+    fn highlights_tools(self) -> bool {
+        matches!(
+            self,
+            Step::Tools | Step::Sculpt | Step::Paint | Step::Scatter
+        )
+    }
+}
+
+/// What the view looked like when a step started, to tell when it's been done
+struct Baseline {
+    view_matrix: Mat4,
+    edits: u64,
+    scattered: usize,
+}
+
+impl Baseline {
+    fn new(view: &ViewInfo) -> Self {
+        Baseline {
+            view_matrix: view.view_matrix,
+            edits: view.edits,
+            scattered: view.scatter_counts.iter().sum(),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Tutorial {
+    /// None when not running
+    step: Option<Step>,
+    /// Taken on the first frame of a step
+    baseline: Option<Baseline>,
+}
+
+impl Tutorial {
+    pub fn start(&mut self) {
+        self.go_to(Some(Step::Welcome));
+    }
+
+    fn go_to(&mut self, step: Option<Step>) {
+        self.step = step;
+        self.baseline = None;
+    }
+
+    /// `tools_rect` is where the tools window is, unless it's been detached
+    pub fn show(
+        &mut self,
+        ctx: &CtxRef,
+        view: &ViewInfo,
+        tools_rect: Option<Rect>,
+        actions: &mut Vec<Action>,
+    ) {
+        let step = match self.step {
+            Some(step) => step,
+            None => return,
+        };
+        let baseline = self.baseline.get_or_insert_with(|| Baseline::new(view));
+        if done(step, view, baseline) {
+            self.go_to(step.next());
+            return;
+        }
+
+        if let (true, Some(rect)) = (step.highlights_tools(), tools_rect) {
+            let painter = ctx.layer_painter(LayerId::new(Order::Foreground, Id::new("Tutorial")));
+            painter.rect_stroke(rect.expand(4.0), 6.0, Stroke::new(3.0, Color32::YELLOW));
+        }
+
+        let mut next = false;
+        let mut close = false;
+        egui::Window::new("Getting started")
+            .resizable(false)
+            .collapsible(false)
+            .anchor(Align2::CENTER_BOTTOM, egui::Vec2::new(0.0, -40.0))
+            .show(ctx, |ui| {
+                ui.set_max_width(360.0);
+                ui.label(step.text());
+                if step.highlights_tools() && tools_rect.is_none() {
+                    ui.colored_label(Color32::GRAY, "The tools window is detached");
+                }
+                ui.horizontal(|ui| match step {
+                    Step::Welcome => {
+                        next = ui.button("Start").clicked();
+                        close = ui.button("Not now").clicked();
+                    }
+                    Step::Done => close = ui.button("Finish").clicked(),
+                    _ => {
+                        next = ui.button("Skip this step").clicked();
+                        close = ui.button("End the tutorial").clicked();
+                    }
+                });
+            });
+        if next {
+            self.go_to(step.next());
+        }
+        if close {
+            self.go_to(None);
+            actions.push(Action::FinishTutorial);
+        }
+    }
+}
+
+/// Whether the user has done what the step asks for since it started
+fn done(step: Step, view: &ViewInfo, baseline: &mut Baseline) -> bool {
+    // Only edits made with the tool the step is about count
+    let edited_with = |tool: TerrainTool, baseline: &mut Baseline| {
+        if view.tool != Some(tool) {
+            baseline.edits = view.edits;
+        }
+        view.edits > baseline.edits
+    };
+    match step {
+        Step::Welcome | Step::Done => false,
+        Step::Camera => view.view_matrix != baseline.view_matrix,
+        Step::Tools => view.tool == Some(TerrainTool::Sculpt),
+        Step::Sculpt => edited_with(TerrainTool::Sculpt, baseline),
+        Step::Paint => edited_with(TerrainTool::PaintTextures, baseline),
+        Step::Scatter => view.scatter_counts.iter().sum::<usize>() > baseline.scattered,
+    }
+}
@@ -0,0 +1,121 @@
+//! Commands run after an export is written, to hand it on: a texture compressor, a
+//! copy into a game project's assets and so on. Every kind of export has its own
+//! command, kept in the project's config so that it travels with the project.
+//!
+//! A command is a line for the system shell, run in the project directory. `{path}`
+//! in it is replaced with the quoted path of what was exported, which is also in the
+//! `TERRAIN_EXPORT_PATH` environment variable for scripts.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::process::Command;
+
+use serde::{Deserialize, Serialize};
+
+use crate::io::SavePurpose;
+
+pub const PATH_PLACEHOLDER: &str = "{path}";
+const PATH_VARIABLE: &str = "TERRAIN_EXPORT_PATH";
+
+/// Lines of a failed command's output shown with the error, the last ones
+const ERROR_LINES: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum ExportProfile {
+    Heightmap,
+    NormalMap,
+    SplatMap,
+    AmbientOcclusionMap,
+    HypsometricMap,
+    BuildableMask,
+    /// The path is the archive's name in the storage it went to
+    Archive,
+}
+
+impl ExportProfile {
+    pub const ALL: [ExportProfile; 7] = [
+        ExportProfile::Heightmap,
+        ExportProfile::NormalMap,
+        ExportProfile::SplatMap,
+        ExportProfile::AmbientOcclusionMap,
+        ExportProfile::HypsometricMap,
+        ExportProfile::BuildableMask,
+        ExportProfile::Archive,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ExportProfile::Heightmap => "Heightmap",
+            ExportProfile::NormalMap => "Normal map",
+            ExportProfile::SplatMap => "Splat map",
+            ExportProfile::AmbientOcclusionMap => "AO map",
+            ExportProfile::HypsometricMap => "Hypsometric map",
+            ExportProfile::BuildableMask => "Buildable mask",
+            ExportProfile::Archive => "Archive",
+        }
+    }
+
+    /// None for saves that aren't exports, like screenshots and stamps
+    pub fn of_save(purpose: SavePurpose) -> Option<ExportProfile> {
+        match purpose {
+            SavePurpose::Heightmap => Some(ExportProfile::Heightmap),
+            SavePurpose::NormalMap => Some(ExportProfile::NormalMap),
+            SavePurpose::SplatMap => Some(ExportProfile::SplatMap),
+            SavePurpose::AmbientOcclusionMap => Some(ExportProfile::AmbientOcclusionMap),
+            SavePurpose::HypsometricMap => Some(ExportProfile::HypsometricMap),
+            SavePurpose::BuildableMask => Some(ExportProfile::BuildableMask),
+            SavePurpose::Screenshot | SavePurpose::Stamp | SavePurpose::Prefab => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ExportHooks {
+    commands: BTreeMap<ExportProfile, String>,
+}
+
+impl ExportHooks {
+    /// None if nothing is run after exports of this kind
+    pub fn command(&self, profile: ExportProfile) -> Option<&str> {
+        self.commands
+            .get(&profile)
+            .map(|command| command.trim())
+            .filter(|command| !command.is_empty())
+    }
+
+    pub fn command_mut(&mut self, profile: ExportProfile) -> &mut String {
+        self.commands.entry(profile).or_default()
+    }
+}
+
+/// Runs `command` on `path` and waits for it. The error is the exit status and the
+/// end of what the command printed.
+pub fn run(command: &str, path: &Path) -> Result<(), String> {
+    let quoted = format!("\"{}\"", path.display());
+    let line = command.replace(PATH_PLACEHOLDER, &quoted);
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    let output = shell
+        .arg(&line)
+        .env(PATH_VARIABLE, path)
+        .output()
+        .map_err(|e| format!("Couldn't start it: {}", e))?;
+    if output.status.success() {
+        return Ok(());
+    }
+    let printed = if output.stderr.is_empty() {
+        String::from_utf8_lossy(&output.stdout)
+    } else {
+        String::from_utf8_lossy(&output.stderr)
+    };
+    let lines: Vec<&str> = printed.lines().collect();
+    let last = &lines[lines.len().saturating_sub(ERROR_LINES)..];
+    Err(format!("{}\n{}", output.status, last.join("\n")))
+}
@@ -0,0 +1,397 @@
+//! One-click import of what World Machine and Gaea build. Both write a folder with an
+//! image per output, named after it: the heights, a splat map, a flow map and so on.
+//! Tiled builds add the tile's column and row to every name, like World Machine's
+//! default `_x0_y0`; the other way round works too. Tiles can share their edge texels
+//! with their neighbours, which is found out from the data rather than asked for.
+//!
+//! Besides images, both tools write raw heights: `.r16`/`.raw` is 16-bit and `.r32` is
+//! 32-bit float, little endian, square.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::georef::Georeference;
+use crate::heightfield::HeightField;
+use crate::splat::NUM_LAYERS;
+
+/// Flow is shown at full heat where the most water runs
+pub const FLOW_OVERLAY_OPACITY: f32 = 0.7;
+
+/// Average difference below which two tile edges are taken to be the same texels
+const SHARED_EDGE_TOLERANCE: f32 = 1e-3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExternalTool {
+    WorldMachine,
+    Gaea,
+}
+
+impl ExternalTool {
+    pub const ALL: [ExternalTool; 2] = [ExternalTool::WorldMachine, ExternalTool::Gaea];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ExternalTool::WorldMachine => "World Machine",
+            ExternalTool::Gaea => "Gaea",
+        }
+    }
+
+    /// Words in the file names of each output, in the order they're checked.
+    /// The names are whatever the output nodes are called, these are the usual ones.
+    fn preset(&self) -> Preset {
+        match self {
+            ExternalTool::WorldMachine => Preset {
+                splat: &["splat", "texture"],
+                flow: &["flow", "wear"],
+                heights: &["height", "terrain", "elevation"],
+            },
+            ExternalTool::Gaea => Preset {
+                splat: &["splat", "rgba", "satmap"],
+                flow: &["flow", "water"],
+                heights: &["height", "terrain", "elevation", "output"],
+            },
+        }
+    }
+}
+
+struct Preset {
+    splat: &'static [&'static str],
+    flow: &'static [&'static str],
+    heights: &'static [&'static str],
+}
+
+pub struct ExternalImportSettings {
+    /// The build folder
+    pub path: String,
+    pub tool: ExternalTool,
+    /// Show the flow map over the terrain
+    pub show_flow: bool,
+}
+
+impl Default for ExternalImportSettings {
+    fn default() -> Self {
+        ExternalImportSettings {
+            path: String::new(),
+            tool: ExternalTool::WorldMachine,
+            show_flow: true,
+        }
+    }
+}
+
+pub struct ExternalBuild {
+    pub heights: HeightField,
+    /// Weights of every terrain layer, none if the build has no splat map
+    pub splat: Option<Vec<HeightField>>,
+    pub flow: Option<HeightField>,
+    /// Only known for a single GeoTIFF
+    pub georeference: Option<Georeference>,
+    /// Columns and rows the heights were put together from
+    pub tiles: (usize, usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Output {
+    Heights,
+    Splat,
+    Flow,
+}
+
+/// A tile of an output, or all of it, by column and row
+type Tiles = BTreeMap<(usize, usize), PathBuf>;
+
+/// Texels per side and the texels of every channel
+struct Raster {
+    size: usize,
+    channels: Vec<Vec<f32>>,
+}
+
+/// Reads a build folder on any thread
+pub fn load(folder: &str, tool: ExternalTool) -> Result<ExternalBuild, String> {
+    let outputs = find_outputs(Path::new(folder), &tool.preset())?;
+    let height_tiles = outputs
+        .get(&Output::Heights)
+        .ok_or_else(|| format!("No heightmap in {}", folder))?;
+
+    let stitched = stitch(height_tiles, 1)?;
+    let tiles = grid_size(height_tiles);
+    let georeference = match height_tiles.values().next() {
+        Some(path) if height_tiles.len() == 1 => Georeference::read(&path.to_string_lossy()),
+        _ => None,
+    };
+    let size = stitched.size;
+    let mut heights = stitched.channels.into_iter().next().unwrap_or_default();
+    normalise(&mut heights);
+
+    let splat = match outputs.get(&Output::Splat) {
+        Some(tiles) => Some(splat_layers(stitch(tiles, 4)?)),
+        None => None,
+    };
+    let flow = match outputs.get(&Output::Flow) {
+        Some(tiles) => {
+            let flow = stitch(tiles, 1)?;
+            let data = flow.channels.into_iter().next().unwrap_or_default();
+            Some(HeightField::from_data(flow.size, data))
+        }
+        None => None,
+    };
+    Ok(ExternalBuild {
+        heights: HeightField::from_data(size, heights),
+        splat,
+        flow,
+        georeference,
+        tiles,
+    })
+}
+
+/// Every file the preset knows, grouped by output. If several files could be the
+/// same output, e.g. Height and Height_Preview, the shortest name wins.
+fn find_outputs(folder: &Path, preset: &Preset) -> Result<BTreeMap<Output, Tiles>, String> {
+    let mut candidates: BTreeMap<(Output, String), Tiles> = BTreeMap::new();
+    for entry in fs::read_dir(folder).map_err(|e| e.to_string())? {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        let supported = path.extension().and_then(|e| e.to_str()).is_some_and(|e| {
+            matches!(
+                e.to_lowercase().as_str(),
+                "png" | "tif" | "tiff" | "exr" | "r16" | "raw" | "r32"
+            )
+        });
+        let stem = match path.file_stem().and_then(|s| s.to_str()) {
+            Some(stem) if supported && path.is_file() => stem.to_lowercase(),
+            _ => continue,
+        };
+        let (base, tile) = split_tile(&stem);
+        let matches = |words: &[&str]| words.iter().any(|w| base.contains(w));
+        let output = if matches(preset.splat) {
+            Output::Splat
+        } else if matches(preset.flow) {
+            Output::Flow
+        } else if matches(preset.heights) {
+            Output::Heights
+        } else {
+            continue;
+        };
+        candidates
+            .entry((output, base.to_owned()))
+            .or_default()
+            .insert(tile.unwrap_or((0, 0)), path);
+    }
+
+    let mut outputs: BTreeMap<Output, (usize, Tiles)> = BTreeMap::new();
+    for ((output, base), tiles) in candidates {
+        match outputs.get(&output) {
+            Some((length, _)) if *length <= base.len() => {}
+            _ => {
+                outputs.insert(output, (base.len(), tiles));
+            }
+        }
+    }
+    Ok(outputs
+        .into_iter()
+        .map(|(output, (_, tiles))| (output, tiles))
+        .collect())
+}
+
+/// `height_x1_y0` is tile (1, 0) of `height`, and so is `height_y0_x1`
+fn split_tile(stem: &str) -> (&str, Option<(usize, usize)>) {
+    let mut base = stem;
+    let (mut x, mut y) = (None, None);
+    for _ in 0..2 {
+        let (rest, last) = match base.rsplit_once('_') {
+            Some(split) => split,
+            None => break,
+        };
+        let index = |prefix: char| last.strip_prefix(prefix).and_then(|n| n.parse().ok());
+        match (index('x'), index('y')) {
+            (Some(i), _) if x.is_none() => x = Some(i),
+            (_, Some(i)) if y.is_none() => y = Some(i),
+            _ => break,
+        }
+        base = rest;
+    }
+    match (x, y) {
+        (Some(x), Some(y)) => (base, Some((x, y))),
+        _ => (stem, None),
+    }
+}
+
+fn grid_size(tiles: &Tiles) -> (usize, usize) {
+    let columns = tiles.keys().map(|&(x, _)| x + 1).max().unwrap_or(0);
+    let rows = tiles.keys().map(|&(_, y)| y + 1).max().unwrap_or(0);
+    (columns, rows)
+}
+
+/// Puts the tiles side by side, row 0 at the top. Where neighbours share their
+/// edge texels only one copy is kept.
+fn stitch(tiles: &Tiles, channels: usize) -> Result<Raster, String> {
+    let (columns, rows) = grid_size(tiles);
+    if columns != rows {
+        return Err(format!("{}x{} tiles don't make a square", columns, rows));
+    }
+    let mut loaded = BTreeMap::new();
+    let mut tile_size = None;
+    for y in 0..rows {
+        for x in 0..columns {
+            let path = tiles
+                .get(&(x, y))
+                .ok_or_else(|| format!("Tile x{} y{} is missing", x, y))?;
+            let raster = load_raster(path, channels)?;
+            if *tile_size.get_or_insert(raster.size) != raster.size {
+                return Err(format!(
+                    "{} isn't the size of the other tiles",
+                    path.display()
+                ));
+            }
+            loaded.insert((x, y), raster);
+        }
+    }
+    let tile_size = tile_size.unwrap_or(0);
+    if loaded.len() == 1 {
+        return Ok(loaded.remove(&(0, 0)).unwrap());
+    }
+
+    let overlap = if shares_edges(&loaded, tile_size) {
+        1
+    } else {
+        0
+    };
+    let step = tile_size - overlap;
+    let size = step * (columns - 1) + tile_size;
+    let mut stitched = vec![vec![0.0; size * size]; channels];
+    for (&(tx, ty), tile) in &loaded {
+        for (channel, texels) in tile.channels.iter().enumerate() {
+            for (row, line) in texels.chunks_exact(tile_size).enumerate() {
+                let start = (ty * step + row) * size + tx * step;
+                stitched[channel][start..start + tile_size].copy_from_slice(line);
+            }
+        }
+    }
+    Ok(Raster {
+        size,
+        channels: stitched,
+    })
+}
+
+/// Whether the last column of the first tile is the first column of the next
+fn shares_edges(tiles: &BTreeMap<(usize, usize), Raster>, tile_size: usize) -> bool {
+    let (left, right) = match (tiles.get(&(0, 0)), tiles.get(&(1, 0))) {
+        (Some(left), Some(right)) => (&left.channels[0], &right.channels[0]),
+        _ => return false,
+    };
+    let difference: f32 = (0..tile_size)
+        .map(|row| (left[row * tile_size + tile_size - 1] - right[row * tile_size]).abs())
+        .sum();
+    difference / (tile_size as f32) < SHARED_EDGE_TOLERANCE
+}
+
+/// 1 or 4 channels in [0, 1]. Grayscale images repeat in every channel and
+/// images without alpha get none, i.e. 0.
+fn load_raster(path: &Path, channels: usize) -> Result<Raster, String> {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default()
+        .to_lowercase();
+    let raw = match extension.as_str() {
+        "r16" | "raw" => Some(read_raw(path, 2, |b| {
+            u16::from_le_bytes([b[0], b[1]]) as f32 / u16::MAX as f32
+        })?),
+        "r32" => Some(read_raw(path, 4, |b| {
+            f32::from_le_bytes([b[0], b[1], b[2], b[3]])
+        })?),
+        _ => None,
+    };
+    if let Some((size, texels)) = raw {
+        return Ok(Raster {
+            size,
+            channels: vec![texels; channels],
+        });
+    }
+
+    let image = image::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    if width != height {
+        return Err(format!(
+            "{} is {}x{}, not square",
+            path.display(),
+            width,
+            height
+        ));
+    }
+    let channels = if channels == 1 {
+        let luma = image.into_luma16();
+        vec![luma.iter().map(|&p| p as f32 / u16::MAX as f32).collect()]
+    } else {
+        let has_alpha = image.color().has_alpha();
+        let rgba = image.into_rgba16();
+        (0..channels)
+            .map(|c| {
+                rgba.pixels()
+                    .map(|p| match c {
+                        3 if !has_alpha => 0.0,
+                        _ => p[c.min(3)] as f32 / u16::MAX as f32,
+                    })
+                    .collect()
+            })
+            .collect()
+    };
+    Ok(Raster {
+        size: width,
+        channels,
+    })
+}
+
+fn read_raw(
+    path: &Path,
+    bytes_per_texel: usize,
+    texel: impl Fn(&[u8]) -> f32,
+) -> Result<(usize, Vec<f32>), String> {
+    let bytes = fs::read(path).map_err(|e| e.to_string())?;
+    let texels = bytes.len() / bytes_per_texel;
+    let size = (texels as f64).sqrt() as usize;
+    if size * size * bytes_per_texel != bytes.len() {
+        return Err(format!("{} isn't a square raw heightmap", path.display()));
+    }
+    Ok((
+        size,
+        bytes.chunks_exact(bytes_per_texel).map(texel).collect(),
+    ))
+}
+
+/// Float heights can be in meters, they're stretched to [0, 1] if they're outside it
+fn normalise(heights: &mut [f32]) {
+    let (low, high) = heights
+        .iter()
+        .fold((f32::MAX, f32::MIN), |(low, high), &h| {
+            (low.min(h), high.max(h))
+        });
+    if low >= 0.0 && high <= 1.0 {
+        return;
+    }
+    let range = (high - low).max(f32::EPSILON);
+    for h in heights {
+        *h = (*h - low) / range;
+    }
+}
+
+/// Red, green, blue and alpha weigh the terrain layers in order. Without alpha, red,
+/// green and blue go to the other layers and the first one gets what's left over,
+/// being what everything starts out as.
+fn splat_layers(splat: Raster) -> Vec<HeightField> {
+    let size = splat.size;
+    let has_alpha = splat.channels[3].iter().any(|&a| a > 0.0);
+    let mut layers: Vec<Vec<f32>> = if has_alpha {
+        splat.channels
+    } else {
+        let rgb = &splat.channels[..3];
+        let rest = (0..size * size)
+            .map(|i| (1.0 - rgb.iter().map(|c| c[i]).sum::<f32>()).max(0.0))
+            .collect();
+        std::iter::once(rest).chain(rgb.iter().cloned()).collect()
+    };
+    layers.truncate(NUM_LAYERS);
+    layers
+        .into_iter()
+        .map(|data| HeightField::from_data(size, data))
+        .collect()
+}
@@ -0,0 +1,111 @@
+//! When to draw the next frame. Vsync and an FPS cap keep the editor from drawing
+//! frames nobody sees, and once there's been no input for a moment and nothing is
+//! animating the event loop goes to sleep until the next event arrives.
+
+use std::time::{Duration, Instant};
+
+use glutin::event_loop::ControlFlow;
+use glutin::{PossiblyCurrent, WindowedContext};
+
+/// Frames keep coming this long after the last input, so the UI can finish its animations
+const IDLE_DELAY: Duration = Duration::from_millis(500);
+
+pub struct FrameSettings {
+    pub vsync: bool,
+    pub fps_cap: bool,
+    pub max_fps: u32,
+    /// Stop drawing when nothing changes
+    pub save_power: bool,
+}
+
+impl Default for FrameSettings {
+    fn default() -> Self {
+        FrameSettings {
+            vsync: true,
+            fps_cap: false,
+            max_fps: 60,
+            save_power: true,
+        }
+    }
+}
+
+pub struct FramePacing {
+    last_frame: Instant,
+    last_input: Instant,
+    /// What the context was last set to, None if the platform can't change it
+    vsync: Option<bool>,
+}
+
+impl FramePacing {
+    /// The context is created with vsync on
+    pub fn new() -> Self {
+        let now = Instant::now();
+        FramePacing {
+            last_frame: now,
+            last_input: now,
+            vsync: Some(true),
+        }
+    }
+
+    pub fn input_received(&mut self) {
+        self.last_input = Instant::now();
+    }
+
+    /// False when the platform has no way to change vsync after the context is created
+    pub fn vsync_supported(&self) -> bool {
+        self.vsync.is_some()
+    }
+
+    pub fn apply_vsync(
+        &mut self,
+        settings: &FrameSettings,
+        context: &WindowedContext<PossiblyCurrent>,
+    ) {
+        if let Some(vsync) = self.vsync {
+            if vsync != settings.vsync {
+                let applied = set_swap_interval(context, settings.vsync as i32);
+                self.vsync = if applied { Some(settings.vsync) } else { None };
+            }
+        }
+    }
+
+    /// Whether it's time to draw. If not, `control_flow` is set to wake up when it is.
+    pub fn should_draw(
+        &mut self,
+        settings: &FrameSettings,
+        animating: bool,
+        control_flow: &mut ControlFlow,
+    ) -> bool {
+        let now = Instant::now();
+        if settings.save_power && !animating && now - self.last_input > IDLE_DELAY {
+            *control_flow = ControlFlow::Wait;
+            return false;
+        }
+        if settings.fps_cap {
+            let frame_time = Duration::from_secs_f32(1.0 / settings.max_fps.max(1) as f32);
+            let next_frame = self.last_frame + frame_time;
+            if now < next_frame {
+                *control_flow = ControlFlow::WaitUntil(next_frame);
+                return false;
+            }
+        }
+        *control_flow = ControlFlow::Poll;
+        self.last_frame = now;
+        true
+    }
+}
+
+/// glutin only sets vsync when the context is created, this goes through whichever
+/// swap interval extension the platform has. False if there's none.
+fn set_swap_interval(context: &WindowedContext<PossiblyCurrent>, interval: i32) -> bool {
+    for name in &["wglSwapIntervalEXT", "glXSwapIntervalMESA"] {
+        let address = context.get_proc_address(name);
+        if !address.is_null() {
+            let swap_interval: extern "system" fn(i32) -> i32 =
+                unsafe { std::mem::transmute(address) };
+            swap_interval(interval);
+            return true;
+        }
+    }
+    false
+}
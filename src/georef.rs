@@ -0,0 +1,327 @@
+//! Where a GeoTIFF lies on the ground, from its model tiepoint and pixel scale tags.
+//! That's enough to line up north-up rasters in the same coordinate system, e.g. a DEM
+//! and an orthophoto of the same area. Rotated rasters and reprojection aren't handled.
+//!
+//! The geo keys naming the coordinate system are kept as they are, so that a heightmap
+//! saved as a GeoTIFF says the same thing, and are only read far enough to tell
+//! latitude and longitude apart from UTM.
+
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+use glam::Vec2;
+use serde::{Deserialize, Serialize};
+use tiff::decoder::Decoder;
+use tiff::encoder::{colortype, TiffEncoder};
+use tiff::tags::Tag;
+
+use crate::terrain::BoundsChange;
+
+const MODEL_TYPE_KEY: u16 = 1024;
+const RASTER_TYPE_KEY: u16 = 1025;
+const PROJECTED_CS_KEY: u16 = 3072;
+
+const MODEL_TYPE_GEOGRAPHIC: u16 = 2;
+const RASTER_PIXEL_IS_POINT: u16 = 2;
+
+/// In the units of the raster's coordinate system, usually meters.
+/// Y grows northwards, the first row of the image is at `max_y`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct GeoExtent {
+    pub min_x: f64,
+    pub min_y: f64,
+    pub max_x: f64,
+    pub max_y: f64,
+}
+
+impl GeoExtent {
+    pub fn width(&self) -> f64 {
+        self.max_x - self.min_x
+    }
+
+    pub fn height(&self) -> f64 {
+        self.max_y - self.min_y
+    }
+
+    /// Follows the terrain being cropped or extended, `world_size` is what the
+    /// extent covered before
+    pub fn changed(&self, change: &BoundsChange, world_size: f32) -> GeoExtent {
+        let per_meter_x = self.width() / world_size as f64;
+        let per_meter_y = self.height() / world_size as f64;
+        GeoExtent {
+            min_x: self.min_x - change.neg_x as f64 * per_meter_x,
+            max_x: self.max_x + change.pos_x as f64 * per_meter_x,
+            // -Z is the top of the heightmap, i.e. north
+            min_y: self.min_y - change.pos_z as f64 * per_meter_y,
+            max_y: self.max_y + change.neg_z as f64 * per_meter_y,
+        }
+    }
+
+    /// Scale and offset that take uv within `inner` to uv within `self`,
+    /// both with v = 0 along the northern edge
+    pub fn uv_transform(&self, inner: &GeoExtent) -> (Vec2, Vec2) {
+        let scale = Vec2::new(
+            (inner.width() / self.width()) as f32,
+            (inner.height() / self.height()) as f32,
+        );
+        let offset = Vec2::new(
+            ((inner.min_x - self.min_x) / self.width()) as f32,
+            ((self.max_y - inner.max_y) / self.height()) as f32,
+        );
+        (scale, offset)
+    }
+
+    /// Coordinates of a point given as uv, v = 0 along the northern edge
+    pub fn at_uv(&self, uv: Vec2) -> (f64, f64) {
+        (
+            self.min_x + uv.x as f64 * self.width(),
+            self.max_y - uv.y as f64 * self.height(),
+        )
+    }
+}
+
+/// Everything a GeoTIFF says about where it is
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Georeference {
+    pub extent: GeoExtent,
+    /// GeoKeyDirectoryTag and the parameters its keys point into
+    key_directory: Vec<u16>,
+    double_params: Vec<f64>,
+    ascii_params: String,
+}
+
+impl Georeference {
+    /// None unless the file is a TIFF with a tiepoint and a pixel scale
+    pub fn read(path: &str) -> Option<Georeference> {
+        let file = File::open(path).ok()?;
+        let mut decoder = Decoder::new(BufReader::new(file)).ok()?;
+        let (width, height) = decoder.dimensions().ok()?;
+        let scale = decoder.get_tag_f64_vec(Tag::ModelPixelScaleTag).ok()?;
+        let tiepoint = decoder.get_tag_f64_vec(Tag::ModelTiepointTag).ok()?;
+        if scale.len() < 2 || tiepoint.len() < 6 {
+            return None;
+        }
+        let key_directory = decoder
+            .get_tag_u16_vec(Tag::GeoKeyDirectoryTag)
+            .unwrap_or_default();
+        let double_params = decoder
+            .get_tag_f64_vec(Tag::GeoDoubleParamsTag)
+            .unwrap_or_default();
+        let ascii_params = decoder
+            .get_tag_ascii_string(Tag::GeoAsciiParamsTag)
+            .unwrap_or_default();
+
+        // Raster (i, j) is at model (x, y)
+        let (i, j, x, y) = (tiepoint[0], tiepoint[1], tiepoint[3], tiepoint[4]);
+        let (scale_x, scale_y) = (scale[0], scale[1]);
+        let mut min_x = x - i * scale_x;
+        let mut max_y = y + j * scale_y;
+        if geo_key(&key_directory, RASTER_TYPE_KEY) == Some(RASTER_PIXEL_IS_POINT) {
+            // The tiepoint is the middle of the pixel rather than its corner
+            min_x -= scale_x / 2.0;
+            max_y += scale_y / 2.0;
+        }
+        Some(Georeference {
+            extent: GeoExtent {
+                min_x,
+                min_y: max_y - height as f64 * scale_y,
+                max_x: min_x + width as f64 * scale_x,
+                max_y,
+            },
+            key_directory,
+            double_params,
+            ascii_params,
+        })
+    }
+
+    /// Coordinates are longitude and latitude rather than meters
+    pub fn is_geographic(&self) -> bool {
+        geo_key(&self.key_directory, MODEL_TYPE_KEY) == Some(MODEL_TYPE_GEOGRAPHIC)
+    }
+
+    /// Degrees north and east, if the coordinate system is one that can be converted
+    pub fn lat_long(&self, x: f64, y: f64) -> Option<(f64, f64)> {
+        if self.is_geographic() {
+            return Some((y, x));
+        }
+        let (zone, north) = utm_zone(geo_key(&self.key_directory, PROJECTED_CS_KEY)?)?;
+        Some(utm_to_lat_long(x, y, zone, north))
+    }
+
+    /// The other way round from `lat_long`
+    pub fn from_lat_long(&self, lat: f64, long: f64) -> Option<(f64, f64)> {
+        if self.is_geographic() {
+            return Some((long, lat));
+        }
+        let (zone, north) = utm_zone(geo_key(&self.key_directory, PROJECTED_CS_KEY)?)?;
+        Some(lat_long_to_utm(lat, long, zone, north))
+    }
+
+    /// A 16-bit grayscale GeoTIFF covering the extent
+    pub fn write_geotiff(&self, path: &Path, pixels: &[u16], size: u32) -> Result<(), String> {
+        let file = File::create(path).map_err(|e| e.to_string())?;
+        let write = || -> tiff::TiffResult<()> {
+            let mut encoder = TiffEncoder::new(BufWriter::new(file))?;
+            let mut image = encoder.new_image::<colortype::Gray16>(size, size)?;
+            let extent = &self.extent;
+            let scale_x = extent.width() / size as f64;
+            let scale_y = extent.height() / size as f64;
+            let (mut x, mut y) = (extent.min_x, extent.max_y);
+            if geo_key(&self.key_directory, RASTER_TYPE_KEY) == Some(RASTER_PIXEL_IS_POINT) {
+                x += scale_x / 2.0;
+                y -= scale_y / 2.0;
+            }
+            let directory = image.encoder();
+            directory.write_tag(Tag::ModelPixelScaleTag, &[scale_x, scale_y, 0.0][..])?;
+            directory.write_tag(Tag::ModelTiepointTag, &[0.0, 0.0, 0.0, x, y, 0.0][..])?;
+            if !self.key_directory.is_empty() {
+                directory.write_tag(Tag::GeoKeyDirectoryTag, &self.key_directory[..])?;
+            }
+            if !self.double_params.is_empty() {
+                directory.write_tag(Tag::GeoDoubleParamsTag, &self.double_params[..])?;
+            }
+            if !self.ascii_params.is_empty() {
+                directory.write_tag(Tag::GeoAsciiParamsTag, self.ascii_params.as_str())?;
+            }
+            image.write_data(pixels)
+        };
+        write().map_err(|e| e.to_string())
+    }
+
+    /// An ESRI world file for formats that can't hold georeferencing themselves.
+    /// It only places the image, the coordinate system has to be known some other way.
+    pub fn write_world_file(&self, image_path: &Path, size: u32) -> Result<(), String> {
+        let extent = &self.extent;
+        let scale_x = extent.width() / size as f64;
+        let scale_y = extent.height() / size as f64;
+        // Pixel size, two rotation terms, then the middle of the top left pixel
+        let contents = format!(
+            "{}\n0\n0\n{}\n{}\n{}\n",
+            scale_x,
+            -scale_y,
+            extent.min_x + scale_x / 2.0,
+            extent.max_y - scale_y / 2.0,
+        );
+        fs::write(world_file_path(image_path), contents).map_err(|e| e.to_string())
+    }
+}
+
+/// heightmap.png goes with heightmap.pgw: the first and last letters of the
+/// extension and a "w"
+fn world_file_path(image_path: &Path) -> std::path::PathBuf {
+    let extension = image_path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default();
+    let mut letters = extension.chars();
+    let world_extension = match (letters.next(), letters.last()) {
+        (Some(first), Some(last)) => format!("{}{}w", first, last),
+        _ => "wld".to_owned(),
+    };
+    image_path.with_extension(world_extension)
+}
+
+/// Value of a key stored right in the directory, which is where the short ones live
+fn geo_key(directory: &[u16], key: u16) -> Option<u16> {
+    // A header of four, then four per key: id, where it's stored, count, value
+    directory
+        .get(4..)?
+        .chunks_exact(4)
+        .find(|entry| entry[0] == key && entry[1] == 0)
+        .map(|entry| entry[3])
+}
+
+/// WGS 84 and ETRS89 UTM zones, the two are close enough for a readout
+fn utm_zone(epsg: u16) -> Option<(u32, bool)> {
+    match epsg {
+        32601..=32660 => Some(((epsg - 32600) as u32, true)),
+        32701..=32760 => Some(((epsg - 32700) as u32, false)),
+        25828..=25838 => Some(((epsg - 25800) as u32, true)),
+        _ => None,
+    }
+}
+
+/// Transverse Mercator on the WGS 84 ellipsoid, from Snyder as well
+fn lat_long_to_utm(lat: f64, long: f64, zone: u32, north: bool) -> (f64, f64) {
+    const A: f64 = 6_378_137.0;
+    const F: f64 = 1.0 / 298.257_223_563;
+    const K0: f64 = 0.9996;
+    let e2 = F * (2.0 - F);
+    let ep2 = e2 / (1.0 - e2);
+
+    let phi = lat.to_radians();
+    let central_meridian = (zone as f64 * 6.0 - 183.0).to_radians();
+    let (sin, cos, tan) = (phi.sin(), phi.cos(), phi.tan());
+    let n = A / (1.0 - e2 * sin * sin).sqrt();
+    let t = tan * tan;
+    let c = ep2 * cos * cos;
+    let a = (long.to_radians() - central_meridian) * cos;
+    let m = A
+        * ((1.0 - e2 / 4.0 - 3.0 * e2 * e2 / 64.0 - 5.0 * e2.powi(3) / 256.0) * phi
+            - (3.0 * e2 / 8.0 + 3.0 * e2 * e2 / 32.0 + 45.0 * e2.powi(3) / 1024.0)
+                * (2.0 * phi).sin()
+            + (15.0 * e2 * e2 / 256.0 + 45.0 * e2.powi(3) / 1024.0) * (4.0 * phi).sin()
+            - (35.0 * e2.powi(3) / 3072.0) * (6.0 * phi).sin());
+
+    let x = K0
+        * n
+        * (a + (1.0 - t + c) * a.powi(3) / 6.0
+            + (5.0 - 18.0 * t + t * t + 72.0 * c - 58.0 * ep2) * a.powi(5) / 120.0);
+    let y = K0
+        * (m + n
+            * tan
+            * (a * a / 2.0
+                + (5.0 - t + 9.0 * c + 4.0 * c * c) * a.powi(4) / 24.0
+                + (61.0 - 58.0 * t + t * t + 600.0 * c - 330.0 * ep2) * a.powi(6) / 720.0));
+    let northing = if north { y } else { y + 10_000_000.0 };
+    (x + 500_000.0, northing)
+}
+
+/// Inverse transverse Mercator on the WGS 84 ellipsoid, from Snyder's
+/// "Map Projections: A Working Manual"
+fn utm_to_lat_long(easting: f64, northing: f64, zone: u32, north: bool) -> (f64, f64) {
+    const A: f64 = 6_378_137.0;
+    const F: f64 = 1.0 / 298.257_223_563;
+    const K0: f64 = 0.9996;
+    let e2 = F * (2.0 - F);
+    let ep2 = e2 / (1.0 - e2);
+
+    let x = easting - 500_000.0;
+    let y = if north {
+        northing
+    } else {
+        northing - 10_000_000.0
+    };
+    let m = y / K0;
+    let mu = m / (A * (1.0 - e2 / 4.0 - 3.0 * e2 * e2 / 64.0 - 5.0 * e2.powi(3) / 256.0));
+    let e1 = (1.0 - (1.0 - e2).sqrt()) / (1.0 + (1.0 - e2).sqrt());
+    let phi1 = mu
+        + (3.0 * e1 / 2.0 - 27.0 * e1.powi(3) / 32.0) * (2.0 * mu).sin()
+        + (21.0 * e1 * e1 / 16.0 - 55.0 * e1.powi(4) / 32.0) * (4.0 * mu).sin()
+        + (151.0 * e1.powi(3) / 96.0) * (6.0 * mu).sin()
+        + (1097.0 * e1.powi(4) / 512.0) * (8.0 * mu).sin();
+
+    let (sin, cos, tan) = (phi1.sin(), phi1.cos(), phi1.tan());
+    let c1 = ep2 * cos * cos;
+    let t1 = tan * tan;
+    let n1 = A / (1.0 - e2 * sin * sin).sqrt();
+    let r1 = A * (1.0 - e2) / (1.0 - e2 * sin * sin).powf(1.5);
+    let d = x / (n1 * K0);
+
+    let lat = phi1
+        - (n1 * tan / r1)
+            * (d * d / 2.0
+                - (5.0 + 3.0 * t1 + 10.0 * c1 - 4.0 * c1 * c1 - 9.0 * ep2) * d.powi(4) / 24.0
+                + (61.0 + 90.0 * t1 + 298.0 * c1 + 45.0 * t1 * t1 - 252.0 * ep2 - 3.0 * c1 * c1)
+                    * d.powi(6)
+                    / 720.0);
+    let central_meridian = (zone as f64 * 6.0 - 183.0).to_radians();
+    let long = central_meridian
+        + (d - (1.0 + 2.0 * t1 + c1) * d.powi(3) / 6.0
+            + (5.0 - 2.0 * c1 + 28.0 * t1 - 3.0 * c1 * c1 + 8.0 * ep2 + 24.0 * t1 * t1)
+                * d.powi(5)
+                / 120.0)
+            / cos;
+    (lat.to_degrees(), long.to_degrees())
+}
@@ -0,0 +1,126 @@
+//! The texture tiled over the whole terrain under the layer colors. KTX2 and DDS
+//! files are uploaded as they are, compressed and with the mipmaps they come with,
+//! Basis Universal ones are transcoded first (see `basis`) and anything else is read
+//! as an 8-bit sRGB image and gets its mipmaps made.
+//!
+//! Compressed files aren't flipped, which a texture that tiles doesn't mind.
+
+use std::path::Path;
+
+use gl::types::GLenum;
+use image::RgbImage;
+
+use crate::basis;
+use crate::block_texture::{self, BlockFormat, CompressedImage};
+use crate::opengl::objects::Texture;
+use crate::resources::{self, texture_bytes, MemoryHandle, ResourceKind};
+use crate::texture::{calculate_mip_levels, get_max_anisotropy};
+
+pub const DEFAULT_PATH: &str = "textures/checkerboard.png";
+
+pub enum GroundImage {
+    Compressed(CompressedImage),
+    /// Flipped already, the first row at the bottom
+    Rgb(RgbImage),
+}
+
+impl GroundImage {
+    /// Basis Universal textures are transcoded to `transcode_to`
+    pub fn load(path: &str, transcode_to: BlockFormat) -> Result<Self, String> {
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or_default()
+            .to_lowercase();
+        match extension.as_str() {
+            "ktx2" | "dds" => block_texture::load(Path::new(path)).map(GroundImage::Compressed),
+            "basis" => basis::load(Path::new(path), transcode_to).map(GroundImage::Compressed),
+            _ => {
+                let image = image::open(path).map_err(|e| e.to_string())?;
+                Ok(GroundImage::Rgb(image.flipv().into_rgb8()))
+            }
+        }
+    }
+
+    pub fn upload(&self) -> (Texture, MemoryHandle) {
+        let texture = Texture::new(gl::TEXTURE_2D, "Terrain detail");
+        texture.set_wrap_and_filter(gl::REPEAT, gl::LINEAR_MIPMAP_LINEAR, gl::NEAREST);
+        unsafe {
+            gl::TextureParameterf(
+                texture.id(),
+                gl::TEXTURE_MAX_ANISOTROPY,
+                get_max_anisotropy(),
+            );
+        }
+        let bytes = match self {
+            GroundImage::Compressed(image) => {
+                let format = compressed_format(image.format, image.srgb);
+                unsafe {
+                    gl::TextureStorage2D(
+                        texture.id(),
+                        image.levels.len() as i32,
+                        format,
+                        image.width as i32,
+                        image.height as i32,
+                    );
+                    for (level, data) in image.levels.iter().enumerate() {
+                        gl::CompressedTextureSubImage2D(
+                            texture.id(),
+                            level as i32,
+                            0,
+                            0,
+                            (image.width >> level).max(1) as i32,
+                            (image.height >> level).max(1) as i32,
+                            format,
+                            data.len() as i32,
+                            data.as_ptr() as *const _,
+                        );
+                    }
+                }
+                image.levels.iter().map(Vec::len).sum()
+            }
+            GroundImage::Rgb(image) => {
+                let (width, height) = (image.width() as usize, image.height() as usize);
+                let levels = calculate_mip_levels(width, height);
+                unsafe {
+                    gl::TextureStorage2D(
+                        texture.id(),
+                        levels,
+                        gl::SRGB8,
+                        width as i32,
+                        height as i32,
+                    );
+                    gl::TextureSubImage2D(
+                        texture.id(),
+                        0,
+                        0,
+                        0,
+                        width as i32,
+                        height as i32,
+                        gl::RGB,
+                        gl::UNSIGNED_BYTE,
+                        image.as_raw().as_ptr() as *const _,
+                    );
+                    gl::GenerateTextureMipmap(texture.id());
+                }
+                texture_bytes(gl::SRGB8, width, height, levels)
+            }
+        };
+        let memory = resources::track(ResourceKind::Texture, bytes);
+        (texture, memory)
+    }
+}
+
+/// S3TC is an extension, if a universal one, RGTC and BPTC are core
+fn compressed_format(format: BlockFormat, srgb: bool) -> GLenum {
+    match (format, srgb) {
+        (BlockFormat::Bc1, false) => gl::COMPRESSED_RGB_S3TC_DXT1_EXT,
+        (BlockFormat::Bc1, true) => gl::COMPRESSED_SRGB_S3TC_DXT1_EXT,
+        (BlockFormat::Bc3, false) => gl::COMPRESSED_RGBA_S3TC_DXT5_EXT,
+        (BlockFormat::Bc3, true) => gl::COMPRESSED_SRGB_ALPHA_S3TC_DXT5_EXT,
+        (BlockFormat::Bc4, _) => gl::COMPRESSED_RED_RGTC1,
+        (BlockFormat::Bc5, _) => gl::COMPRESSED_RG_RGTC2,
+        (BlockFormat::Bc7, false) => gl::COMPRESSED_RGBA_BPTC_UNORM,
+        (BlockFormat::Bc7, true) => gl::COMPRESSED_SRGB_ALPHA_BPTC_UNORM,
+    }
+}
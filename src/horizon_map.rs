@@ -0,0 +1,117 @@
+//! Terrain self-shadowing from precomputed horizons. Every texel knows how high the
+//! terrain around it rises in a few directions, so the sun is hidden when it's below
+//! that and the sky is partly hidden in valleys. It costs two texture reads, unlike
+//! the shadow map which redraws the terrain every frame.
+
+use terrain_core::horizon::{horizon_angles, HORIZON_DIRECTIONS};
+
+use crate::derived_cache::{self, ContentHash};
+use crate::heightfield::HeightField;
+use crate::opengl::objects::Texture;
+use crate::resources::{self, texture_bytes, MemoryHandle, ResourceKind};
+
+/// Horizons don't need much detail, and it keeps baking quick enough to do after every edit
+pub const MAX_RESOLUTION: usize = 512;
+
+/// Four directions per RGBA layer
+const LAYERS: usize = HORIZON_DIRECTIONS / 4;
+
+const CACHE_KIND: &str = "horizons";
+/// Goes up whenever baking changes, so older horizons aren't read back
+const CACHE_VERSION: u64 = 1;
+
+pub struct HorizonSettings {
+    /// Shadows from the horizons, rebaked when the terrain changes
+    pub enabled: bool,
+    /// The full shadow map, needed for anything other than the terrain to cast shadows
+    pub shadow_map: bool,
+    /// How much valleys darken the sky light
+    pub sky_occlusion: f32,
+    /// Sine of the angle over which the sun fades out behind the horizon
+    pub softness: f32,
+}
+
+impl Default for HorizonSettings {
+    fn default() -> Self {
+        HorizonSettings {
+            enabled: true,
+            shadow_map: true,
+            sky_occlusion: 0.7,
+            softness: 0.05,
+        }
+    }
+}
+
+pub struct HorizonMap {
+    pub texture: Texture,
+    _memory: MemoryHandle,
+}
+
+impl HorizonMap {
+    /// Takes a moment, the terrain only rebakes once an edit is finished. Heights that
+    /// have been baked before are read back from the derived data cache.
+    pub fn bake(heights: &HeightField, world_size: f32, max_height: f32) -> Self {
+        let size = heights.size().min(MAX_RESOLUTION);
+        let texture = Texture::new(gl::TEXTURE_2D_ARRAY, "Horizon map");
+        texture.set_wrap_and_filter(gl::CLAMP_TO_EDGE, gl::LINEAR, gl::LINEAR);
+        unsafe {
+            gl::TextureStorage3D(
+                texture.id(),
+                1,
+                gl::RGBA8,
+                size as i32,
+                size as i32,
+                LAYERS as i32,
+            );
+        }
+
+        let layer_bytes = size * size * 4;
+        let hash = ContentHash::new()
+            .with_u64(CACHE_VERSION)
+            .with_u64(size as u64)
+            .with_f32(world_size)
+            .with_f32(max_height)
+            .with_floats(heights.data());
+        let layers = match derived_cache::get(CACHE_KIND, hash) {
+            Some(layers) if layers.len() == layer_bytes * LAYERS => layers,
+            _ => {
+                let angles = horizon_angles(heights, size, world_size, max_height);
+                let to_byte = |sine: f32| (sine * 255.0).round() as u8;
+                let layers: Vec<u8> = (0..LAYERS)
+                    .flat_map(|layer| {
+                        angles.iter().flat_map(move |texel| {
+                            texel[layer * 4..layer * 4 + 4].iter().map(|&s| to_byte(s))
+                        })
+                    })
+                    .collect();
+                derived_cache::put(CACHE_KIND, hash, &layers);
+                layers
+            }
+        };
+        for (layer, pixels) in layers.chunks_exact(layer_bytes).enumerate() {
+            unsafe {
+                gl::TextureSubImage3D(
+                    texture.id(),
+                    0,
+                    0,
+                    0,
+                    layer as i32,
+                    size as i32,
+                    size as i32,
+                    1,
+                    gl::RGBA,
+                    gl::UNSIGNED_BYTE,
+                    pixels.as_ptr() as *const _,
+                );
+            }
+        }
+
+        HorizonMap {
+            texture,
+            _memory: resources::track(
+                ResourceKind::Texture,
+                texture_bytes(gl::RGBA8, size, size, 1) * LAYERS,
+            ),
+        }
+    }
+}
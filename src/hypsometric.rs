@@ -0,0 +1,39 @@
+//! Hypsometric tints: the terrain colored by elevation through an editable gradient,
+//! to read heights at a glance. The same colors can be saved as a texture for
+//! stylised maps.
+
+use crate::gradient::{ColorGradient, MAX_GRADIENT_STOPS};
+use crate::opengl::shader::Program;
+use crate::Result;
+
+pub struct HypsometricSettings {
+    pub enabled: bool,
+    /// How much of the terrain's own color the tints cover
+    pub opacity: f32,
+    pub gradient: ColorGradient,
+}
+
+impl Default for HypsometricSettings {
+    fn default() -> Self {
+        HypsometricSettings {
+            enabled: false,
+            opacity: 1.0,
+            gradient: ColorGradient::default(),
+        }
+    }
+}
+
+impl HypsometricSettings {
+    pub fn set_uniforms(&self, shader: &Program) -> Result<()> {
+        let opacity = if self.enabled { self.opacity } else { 0.0 };
+        shader.set_f32("hypsometric_opacity", opacity)?;
+        let stops = self.gradient.stops();
+        debug_assert!(stops.len() <= MAX_GRADIENT_STOPS);
+        shader.set_i32("gradient_stops", stops.len() as i32)?;
+        for (i, stop) in stops.iter().enumerate() {
+            shader.set_f32(&format!("gradient_heights[{}]", i), stop.height)?;
+            shader.set_float3(&format!("gradient_colors[{}]", i), &stop.color)?;
+        }
+        Ok(())
+    }
+}
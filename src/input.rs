@@ -1,7 +1,8 @@
 use glam::Vec2;
 use glutin::event::VirtualKeyCode;
+use serde::{Deserialize, Serialize};
 
-#[derive(Default, Clone)]
+#[derive(Default, Clone, Serialize, Deserialize)]
 pub struct Input {
     // Raw
     pub pointer: Vec2,
@@ -41,14 +42,14 @@ impl Input {
     }
 }
 
-#[derive(Clone, Copy, Debug, Default)]
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
 pub struct MouseButtons {
     pub primary: bool,
     pub middle: bool,
     pub secondary: bool,
 }
 
-#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
 pub struct Modifiers {
     pub alt: bool,
     pub ctrl: bool,
@@ -0,0 +1,173 @@
+//! One model drawn many times in a single call, with the per-instance transforms
+//! in a storage buffer, or read through a buffer texture on contexts without
+//! storage buffers. Used for anything scattered over the terrain.
+
+use glam::{Mat4, Quat, Vec3};
+
+use crate::model::Model;
+use crate::opengl::caps;
+use crate::opengl::objects::{Buffer, Texture};
+use crate::opengl::shader::Program;
+use crate::resources::{self, MemoryHandle, ResourceKind};
+use crate::scatter::Placement;
+use crate::texture::unit_to_gl_const;
+use crate::utils::size_of_slice;
+use crate::Result;
+
+pub struct InstancedModel {
+    model: Model,
+    /// Box around the model in model space
+    bounds: (Vec3, Vec3),
+    transforms: Buffer,
+    /// Over `transforms`, only when there are no storage buffers
+    transforms_texture: Option<Texture>,
+    /// Transforms the buffer has room for
+    capacity: usize,
+    count: usize,
+    _memory: Option<MemoryHandle>,
+}
+
+impl InstancedModel {
+    pub fn new(model: Model) -> Self {
+        InstancedModel {
+            bounds: model.bounds(),
+            model,
+            transforms: Buffer::new("Instance transforms"),
+            transforms_texture: None,
+            capacity: 0,
+            count: 0,
+            _memory: None,
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Self> {
+        Ok(InstancedModel::new(Model::load(path)?))
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Replaces all the instances. The buffer only ever grows.
+    pub fn set_transforms(&mut self, transforms: &[Mat4]) {
+        if transforms.len() > self.capacity {
+            // Buffer storage can't be resized, so make a new one with some room to grow
+            let capacity = transforms.len().next_power_of_two();
+            let bytes = capacity * std::mem::size_of::<Mat4>();
+            self.transforms = Buffer::new("Instance transforms");
+            unsafe {
+                gl::NamedBufferStorage(
+                    self.transforms.id(),
+                    bytes as isize,
+                    std::ptr::null(),
+                    gl::DYNAMIC_STORAGE_BIT,
+                );
+            }
+            if !caps::get().storage_buffers {
+                let texture = Texture::new(gl::TEXTURE_BUFFER, "Instance transforms");
+                unsafe {
+                    gl::TextureBuffer(texture.id(), gl::RGBA32F, self.transforms.id());
+                }
+                self.transforms_texture = Some(texture);
+            }
+            self.capacity = capacity;
+            self._memory = Some(resources::track(ResourceKind::Mesh, bytes));
+        }
+        if !transforms.is_empty() {
+            unsafe {
+                gl::NamedBufferSubData(
+                    self.transforms.id(),
+                    0,
+                    size_of_slice(transforms) as isize,
+                    transforms.as_ptr() as *const _,
+                );
+            }
+        }
+        self.count = transforms.len();
+    }
+
+    /// Where instances.glsl reads them from, storage buffer 0 or texture unit 13
+    fn bind_transforms(&self) {
+        unsafe {
+            match &self.transforms_texture {
+                Some(texture) => {
+                    gl::ActiveTexture(unit_to_gl_const(13));
+                    gl::BindTexture(gl::TEXTURE_BUFFER, texture.id());
+                }
+                None => gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, 0, self.transforms.id()),
+            }
+        }
+    }
+
+    /// Only the instances between `distances` from the camera are drawn. `shader` reads
+    /// the instance transforms with instances.glsl and needs the camera position set.
+    pub fn draw(&self, shader: &Program, distances: (f32, f32)) -> Result<()> {
+        if self.count == 0 || distances.0 >= distances.1 {
+            return Ok(());
+        }
+        shader.set_used();
+        shader.set_f32("min_distance", distances.0)?;
+        shader.set_f32("max_distance", distances.1)?;
+        self.bind_transforms();
+        unsafe {
+            gl::BindVertexArray(self.model.vao.id());
+        }
+        for node in &self.model.drawable_nodes {
+            shader.set_mat4("model", &node.transform)?;
+            for primitive in &node.primitives {
+                let material = &self.model.materials[primitive.material_index];
+                unsafe {
+                    gl::ActiveTexture(unit_to_gl_const(0));
+                    gl::BindTexture(gl::TEXTURE_2D, material.base_color_texture);
+
+                    gl::DrawElementsInstanced(
+                        gl::TRIANGLES,
+                        primitive.index_count as i32,
+                        gl::UNSIGNED_INT,
+                        primitive.first_index as *const _,
+                        self.count as i32,
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// A single textured quad per instance, for far away instances
+    pub fn draw_imposters(&self, shader: &Program, distances: (f32, f32)) -> Result<()> {
+        let material = match self.model.materials.first() {
+            Some(material) => material,
+            None => return Ok(()),
+        };
+        if self.count == 0 || distances.0 >= distances.1 {
+            return Ok(());
+        }
+        shader.set_used();
+        shader.set_f32("min_distance", distances.0)?;
+        shader.set_f32("max_distance", distances.1)?;
+        shader.set_vec3("bounds_min", &self.bounds.0)?;
+        shader.set_vec3("bounds_max", &self.bounds.1)?;
+        self.bind_transforms();
+        unsafe {
+            // Nothing is read from it, but something has to be bound
+            gl::BindVertexArray(self.model.vao.id());
+            gl::ActiveTexture(unit_to_gl_const(0));
+            gl::BindTexture(gl::TEXTURE_2D, material.base_color_texture);
+            gl::DrawArraysInstanced(gl::TRIANGLE_STRIP, 0, 4, self.count as i32);
+        }
+        Ok(())
+    }
+}
+
+/// `alignment` goes from standing straight up at 0 to following the surface normal at 1
+pub fn placement_transform(placement: &Placement, alignment: f32) -> Mat4 {
+    let up = Vec3::Y
+        .lerp(placement.normal, alignment.clamp(0.0, 1.0))
+        .normalize();
+    let rotation = Quat::from_rotation_arc(Vec3::Y, up) * Quat::from_rotation_y(placement.angle);
+    Mat4::from_scale_rotation_translation(
+        Vec3::splat(placement.scale),
+        rotation,
+        placement.position,
+    )
+}
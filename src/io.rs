@@ -0,0 +1,485 @@
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread::JoinHandle;
+
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView, ImageBuffer, Luma, Rgb32FImage, RgbaImage};
+
+use crate::block_texture::{self, BlockFormat};
+use crate::compare;
+use crate::config::Config;
+use crate::dem;
+use crate::derived_cache::{self, ContentHash};
+use crate::drape::MAX_DRAPE_SIZE;
+use crate::export_hooks::{self, ExportProfile};
+use crate::external::{self, ExternalBuild, ExternalTool};
+use crate::georef::{GeoExtent, Georeference};
+use crate::ground_texture::GroundImage;
+use crate::heightfield::HeightField;
+use crate::osm::{self, OsmData};
+use crate::postprocess::Lut;
+use crate::prefab::{self, Prefab};
+use crate::project::{self, PackReport, PackSettings, ProjectError};
+use crate::stamp::{self, Stamp};
+use crate::storage::StorageSettings;
+use crate::voids::VoidFill;
+
+/// Goes up whenever block compression changes, so older results aren't read back
+const COMPRESSION_CACHE_VERSION: u64 = 1;
+
+/// What a saved file is, so that the editor knows what to do once it's written
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SavePurpose {
+    Heightmap,
+    NormalMap,
+    SplatMap,
+    AmbientOcclusionMap,
+    HypsometricMap,
+    BuildableMask,
+    Screenshot,
+    Stamp,
+    Prefab,
+}
+
+/// Slow file work which shouldn't block the render loop.
+/// Anything touching GL stays on the main thread: jobs only get CPU data.
+pub enum IoJob {
+    SaveImage {
+        image: DynamicImage,
+        path: PathBuf,
+        flip_vertical: bool,
+        purpose: SavePurpose,
+    },
+    /// BC compressed with mipmaps into a KTX2 file, which takes a while
+    SaveCompressed {
+        image: RgbaImage,
+        format: BlockFormat,
+        path: PathBuf,
+        purpose: SavePurpose,
+    },
+    LoadLut {
+        path: String,
+    },
+    SaveStamp {
+        name: String,
+        stamp: Stamp,
+    },
+    SavePrefab {
+        name: String,
+        prefab: Prefab,
+    },
+    /// 16-bit grayscale, georeferenced the way it came in if it was
+    SaveHeightmap {
+        image: ImageBuffer<Luma<u16>, Vec<u16>>,
+        path: PathBuf,
+        georeference: Option<Georeference>,
+    },
+    /// A heightmap to compare the terrain with
+    LoadReference {
+        path: String,
+    },
+    PackProject {
+        config: Config,
+        storage: StorageSettings,
+        archive: String,
+        settings: PackSettings,
+    },
+    /// Into the current directory project, replacing it
+    UnpackProject {
+        storage: StorageSettings,
+        archive: String,
+    },
+    /// Heights to replace the terrain's with, voids filled with `fill` if there are any
+    ImportHeightmap {
+        path: String,
+        fill: Option<VoidFill>,
+    },
+    /// A World Machine or Gaea build folder to replace the terrain with
+    ImportBuild {
+        folder: String,
+        tool: ExternalTool,
+    },
+    /// OpenStreetMap roads, water and forests, placed with the terrain's georeference
+    LoadOsm {
+        path: String,
+        georeference: Option<Georeference>,
+    },
+    /// An HDR panorama for the skybox
+    LoadSky {
+        path: String,
+    },
+    /// A photo to drape over the terrain
+    LoadDrape {
+        path: String,
+    },
+    /// A texture to tile over the terrain, KTX2 and DDS kept compressed
+    LoadGroundTexture {
+        path: String,
+        /// What Basis Universal textures become
+        transcode_to: BlockFormat,
+    },
+    /// Queued after the export, so it only runs once that's written
+    RunExportHook {
+        profile: ExportProfile,
+        command: String,
+        path: PathBuf,
+    },
+}
+
+pub enum IoEvent {
+    Saved {
+        path: PathBuf,
+        purpose: SavePurpose,
+    },
+    SaveFailed {
+        path: PathBuf,
+        error: String,
+    },
+    LutLoaded {
+        path: String,
+        lut: Lut,
+    },
+    LutFailed {
+        path: String,
+        error: String,
+    },
+    ReferenceLoaded {
+        path: String,
+        heights: HeightField,
+    },
+    ReferenceFailed {
+        path: String,
+        error: String,
+    },
+    ProjectPacked {
+        archive: String,
+        report: PackReport,
+    },
+    ProjectUnpacked {
+        archive: String,
+        config: Config,
+    },
+    ProjectFailed {
+        archive: String,
+        error: String,
+    },
+    /// `georeference` says where the heights are on the ground if the file is a GeoTIFF,
+    /// `voids` is 1 where it had no data
+    HeightmapImported {
+        path: String,
+        heights: HeightField,
+        voids: Option<HeightField>,
+        georeference: Option<Georeference>,
+    },
+    BuildImported {
+        folder: String,
+        build: ExternalBuild,
+    },
+    OsmLoaded {
+        path: String,
+        data: OsmData,
+    },
+    SkyLoaded {
+        path: String,
+        panorama: Rgb32FImage,
+    },
+    DrapeLoaded {
+        path: String,
+        photo: RgbaImage,
+        extent: Option<GeoExtent>,
+    },
+    GroundTextureLoaded {
+        path: String,
+        image: GroundImage,
+    },
+    ImportFailed {
+        path: String,
+        error: String,
+    },
+    ExportHookRan {
+        profile: ExportProfile,
+        path: PathBuf,
+    },
+    ExportHookFailed {
+        profile: ExportProfile,
+        error: String,
+    },
+}
+
+/// A single worker thread taking jobs in order
+pub struct IoWorker {
+    jobs: Option<Sender<IoJob>>,
+    events: Receiver<IoEvent>,
+    pending: usize,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl IoWorker {
+    pub fn new() -> Self {
+        let (job_sender, job_receiver) = channel::<IoJob>();
+        let (event_sender, event_receiver) = channel();
+        let thread = std::thread::Builder::new()
+            .name("io".to_owned())
+            .spawn(move || {
+                for job in job_receiver {
+                    if event_sender.send(run_job(job)).is_err() {
+                        break; // nobody's listening anymore
+                    }
+                }
+            })
+            .expect("Couldn't start the IO thread");
+
+        IoWorker {
+            jobs: Some(job_sender),
+            events: event_receiver,
+            pending: 0,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn submit(&mut self, job: IoJob) {
+        if let Some(jobs) = &self.jobs {
+            jobs.send(job).expect("IO thread has died");
+            self.pending += 1;
+        }
+    }
+
+    /// Jobs finished since the last call, doesn't block
+    pub fn poll(&mut self) -> Vec<IoEvent> {
+        let events: Vec<IoEvent> = self.events.try_iter().collect();
+        self.pending -= events.len();
+        events
+    }
+
+    /// How many jobs are queued or running
+    pub fn pending(&self) -> usize {
+        self.pending
+    }
+}
+
+impl Drop for IoWorker {
+    /// Lets queued saves finish before exiting
+    fn drop(&mut self) {
+        self.jobs.take();
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+fn run_job(job: IoJob) -> IoEvent {
+    match job {
+        IoJob::SaveImage {
+            image,
+            path,
+            flip_vertical,
+            purpose,
+        } => {
+            let image = if flip_vertical { image.flipv() } else { image };
+            let result = path
+                .parent()
+                .map_or(Ok(()), std::fs::create_dir_all)
+                .map_err(|e| e.to_string())
+                .and_then(|_| image.save(&path).map_err(|e| e.to_string()));
+            match result {
+                Ok(()) => IoEvent::Saved { path, purpose },
+                Err(error) => IoEvent::SaveFailed { path, error },
+            }
+        }
+        IoJob::SaveCompressed {
+            image,
+            format,
+            path,
+            purpose,
+        } => {
+            let ktx2 = compress_cached(&image, format);
+            let result = path
+                .parent()
+                .map_or(Ok(()), std::fs::create_dir_all)
+                .and_then(|_| std::fs::write(&path, ktx2));
+            match result {
+                Ok(()) => IoEvent::Saved { path, purpose },
+                Err(error) => IoEvent::SaveFailed {
+                    path,
+                    error: error.to_string(),
+                },
+            }
+        }
+        IoJob::SaveHeightmap {
+            image,
+            path,
+            georeference,
+        } => match save_heightmap(&image, &path, georeference.as_ref()) {
+            Ok(()) => IoEvent::Saved {
+                path,
+                purpose: SavePurpose::Heightmap,
+            },
+            Err(error) => IoEvent::SaveFailed { path, error },
+        },
+        IoJob::LoadLut { path } => match Lut::load(&path) {
+            Ok(lut) => IoEvent::LutLoaded { path, lut },
+            Err(error) => IoEvent::LutFailed {
+                path,
+                error: error.to_string(),
+            },
+        },
+        IoJob::SaveStamp { name, stamp } => match stamp::save(&name, &stamp) {
+            Ok(path) => IoEvent::Saved {
+                path,
+                purpose: SavePurpose::Stamp,
+            },
+            Err(error) => IoEvent::SaveFailed {
+                path: PathBuf::from(name),
+                error: error.to_string(),
+            },
+        },
+        IoJob::SavePrefab { name, prefab } => match prefab::save(&name, &prefab) {
+            Ok(path) => IoEvent::Saved {
+                path,
+                purpose: SavePurpose::Prefab,
+            },
+            Err(error) => IoEvent::SaveFailed {
+                path: PathBuf::from(name),
+                error: error.to_string(),
+            },
+        },
+        IoJob::LoadReference { path } => match compare::load_heights(&path) {
+            Ok(heights) => IoEvent::ReferenceLoaded { path, heights },
+            Err(error) => IoEvent::ReferenceFailed { path, error },
+        },
+        IoJob::PackProject {
+            config,
+            storage,
+            archive,
+            settings,
+        } => match storage
+            .open()
+            .map_err(ProjectError::from)
+            .and_then(|storage| {
+                project::pack(Path::new("."), &config, &*storage, &archive, settings)
+            }) {
+            Ok(report) => IoEvent::ProjectPacked { archive, report },
+            Err(error) => IoEvent::ProjectFailed {
+                archive,
+                error: error.to_string(),
+            },
+        },
+        IoJob::UnpackProject { storage, archive } => match storage
+            .open()
+            .map_err(ProjectError::from)
+            .and_then(|storage| project::unpack(&*storage, &archive, Path::new(".")))
+        {
+            Ok(config) => IoEvent::ProjectUnpacked { archive, config },
+            Err(error) => IoEvent::ProjectFailed {
+                archive,
+                error: error.to_string(),
+            },
+        },
+        IoJob::ImportHeightmap { path, fill } => match dem::load(&path, fill) {
+            Ok(dem) => IoEvent::HeightmapImported {
+                georeference: Georeference::read(&path),
+                path,
+                heights: dem.heights,
+                voids: dem.voids,
+            },
+            Err(error) => IoEvent::ImportFailed { path, error },
+        },
+        IoJob::ImportBuild { folder, tool } => match external::load(&folder, tool) {
+            Ok(build) => IoEvent::BuildImported { folder, build },
+            Err(error) => IoEvent::ImportFailed {
+                path: folder,
+                error,
+            },
+        },
+        IoJob::LoadOsm { path, georeference } => match osm::load(&path, georeference.as_ref()) {
+            Ok(data) => IoEvent::OsmLoaded { path, data },
+            Err(error) => IoEvent::ImportFailed { path, error },
+        },
+        IoJob::LoadDrape { path } => match image::open(&path) {
+            Ok(mut photo) => {
+                let (width, height) = photo.dimensions();
+                if width.max(height) > MAX_DRAPE_SIZE {
+                    // Keeps the aspect ratio
+                    photo = photo.resize(MAX_DRAPE_SIZE, MAX_DRAPE_SIZE, FilterType::Triangle);
+                }
+                IoEvent::DrapeLoaded {
+                    extent: Georeference::read(&path).map(|g| g.extent),
+                    path,
+                    photo: photo.into_rgba8(),
+                }
+            }
+            Err(error) => IoEvent::ImportFailed {
+                path,
+                error: error.to_string(),
+            },
+        },
+        IoJob::RunExportHook {
+            profile,
+            command,
+            path,
+        } => match export_hooks::run(&command, &path) {
+            Ok(()) => IoEvent::ExportHookRan { profile, path },
+            Err(error) => IoEvent::ExportHookFailed { profile, error },
+        },
+        IoJob::LoadGroundTexture { path, transcode_to } => {
+            match GroundImage::load(&path, transcode_to) {
+                Ok(image) => IoEvent::GroundTextureLoaded { path, image },
+                Err(error) => IoEvent::ImportFailed { path, error },
+            }
+        }
+        IoJob::LoadSky { path } => match image::open(&path) {
+            Ok(image) => IoEvent::SkyLoaded {
+                path,
+                panorama: image.into_rgb32f(),
+            },
+            Err(error) => IoEvent::ImportFailed {
+                path,
+                error: error.to_string(),
+            },
+        },
+    }
+}
+
+/// GeoTIFFs keep their georeferencing inside, other formats get a world file next to them
+fn save_heightmap(
+    image: &ImageBuffer<Luma<u16>, Vec<u16>>,
+    path: &Path,
+    georeference: Option<&Georeference>,
+) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default()
+        .to_lowercase();
+    match georeference {
+        Some(georeference) if extension == "tif" || extension == "tiff" => {
+            georeference.write_geotiff(path, image.as_raw(), image.width())
+        }
+        Some(georeference) => {
+            image.save(path).map_err(|e| e.to_string())?;
+            georeference.write_world_file(path, image.width())
+        }
+        None => image.save(path).map_err(|e| e.to_string()),
+    }
+}
+
+/// A KTX2 file with the image compressed, or the one made last time from the same
+/// texels, which takes seconds less for a big map
+fn compress_cached(image: &RgbaImage, format: BlockFormat) -> Vec<u8> {
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let hash = ContentHash::new()
+        .with_u64(COMPRESSION_CACHE_VERSION)
+        .with_bytes(format.name().as_bytes())
+        .with_u64(width as u64)
+        .with_bytes(image.as_raw());
+    if let Some(ktx2) = derived_cache::get("block_compressed", hash) {
+        return ktx2;
+    }
+    let compressed = block_texture::compress(image.as_raw(), width, height, format);
+    let ktx2 = block_texture::to_ktx2(&compressed);
+    derived_cache::put("block_compressed", hash, &ktx2);
+    ktx2
+}
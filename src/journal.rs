@@ -0,0 +1,163 @@
+//! Editor operations recorded as they happen. Replaying a journal on the terrain
+//! a session started from gives the same result, and because brush strokes are
+//! normalised to the terrain size it can also be re-applied after changing
+//! the project parameters.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use glam::{Mat4, Vec2};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::erosion::{DropletSettings, ErosionSettings};
+use crate::filters::HeightFilter;
+use crate::prop_paint::GroundBlend;
+use crate::splat::PaintSettings;
+use crate::terrain::{AlongStrokeSmoothing, ErosionSplat, FitSettings, Stroke};
+use crate::tint::TintSettings;
+use crate::weathering::WeatheringSettings;
+
+pub const JOURNAL_PATH: &str = "journal.json";
+
+#[derive(Debug, Error)]
+pub enum JournalError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Not a valid journal: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Entry {
+    Sculpt {
+        stroke: Stroke,
+        raise: bool,
+    },
+    /// Into the detail layer instead of the heightmap
+    CarveDetail {
+        stroke: Stroke,
+        raise: bool,
+    },
+    /// `direction` is the way the stroke was heading, in uv
+    SmoothAlong {
+        stroke: Stroke,
+        direction: Vec2,
+        settings: AlongStrokeSmoothing,
+    },
+    /// `seed` picks the droplets, so that replaying erodes the same way
+    ErodeUnderBrush {
+        stroke: Stroke,
+        settings: DropletSettings,
+        #[serde(default)]
+        splat: ErosionSplat,
+        seed: u32,
+    },
+    Paint {
+        stroke: Stroke,
+        settings: PaintSettings,
+    },
+    Tint {
+        stroke: Stroke,
+        settings: TintSettings,
+        erase: bool,
+    },
+    Erode {
+        settings: ErosionSettings,
+        #[serde(default)]
+        splat: ErosionSplat,
+    },
+    Filter(HeightFilter),
+    Weather(WeatheringSettings),
+    MoveObject {
+        index: usize,
+        transform: Mat4,
+    },
+    DrapeObject {
+        index: usize,
+        offset: f32,
+    },
+    RestoreObjectShape {
+        index: usize,
+    },
+    FitTerrain {
+        index: usize,
+        settings: FitSettings,
+    },
+    /// The texture brush on the placed objects, every object it was over
+    PaintObjects {
+        stroke: Stroke,
+        settings: PaintSettings,
+    },
+    BlendObjectIntoGround {
+        index: usize,
+        blend: GroundBlend,
+    },
+    ClearObjectPaint {
+        index: usize,
+    },
+}
+
+impl Entry {
+    /// Changes the heights a bit at a time, what undoing a stroke takes back
+    pub fn shapes_heights(&self) -> bool {
+        matches!(
+            self,
+            Entry::Sculpt { .. }
+                | Entry::SmoothAlong { .. }
+                | Entry::ErodeUnderBrush { .. }
+                | Entry::FitTerrain { .. }
+        )
+    }
+}
+
+#[derive(Default)]
+pub struct Journal {
+    entries: Vec<Entry>,
+}
+
+impl Journal {
+    pub fn push(&mut self, entry: Entry) {
+        self.entries.push(entry);
+    }
+
+    pub fn pop(&mut self) -> Option<Entry> {
+        self.entries.pop()
+    }
+
+    /// Drops the entries from `start` on that `remove` picks, the rest stay in order
+    pub fn remove_since(&mut self, start: usize, remove: impl Fn(&Entry) -> bool) {
+        let mut index = 0;
+        self.entries.retain(|entry| {
+            index += 1;
+            index <= start || !remove(entry)
+        });
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), JournalError> {
+        fs::write(path, serde_json::to_string(&self.entries)?)?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, JournalError> {
+        let entries = serde_json::from_str(&fs::read_to_string(path)?)?;
+        Ok(Journal { entries })
+    }
+}
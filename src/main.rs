@@ -1,43 +1,150 @@
 // #![allow(dead_code)]
 // #![allow(unused)]
 
+mod audio;
+mod basis;
+mod benchmark;
+mod biome;
 mod camera;
+mod cliffs;
+mod compare;
 mod config;
+mod daylight;
+mod dem;
+mod derived_cache;
+mod detail_map;
+mod drape;
 mod editor;
+mod export_hooks;
+mod external;
+mod frame_pacing;
+mod georef;
+mod ground_texture;
+mod horizon_map;
+mod hypsometric;
 mod input;
+mod instances;
+mod io;
+mod journal;
 mod model;
 mod opengl;
+mod osm;
+mod overlay;
+mod path_check;
+mod postprocess;
+mod prefab;
+mod project;
+mod prop_paint;
 mod ray;
+mod readback;
+mod recording;
+mod resources;
+mod scatter_types;
+mod season;
+mod session_stats;
+mod sky_light;
 mod skybox;
+mod splat;
+mod stamp;
+mod storage;
+mod sun;
+mod sync;
 mod terrain;
 mod texture;
+mod tint;
+mod tool_memory;
+mod undo;
 mod utils;
+mod viewport;
+mod virtual_texture;
+mod wind;
+
+pub use terrain_core::{
+    block_texture, buildable, codec, detail, erosion, export, filters, flood, gradient,
+    heightfield, insolation, noise, path, scatter, viewshed, voids, weathering,
+};
 
 use std::error::Error;
+use std::path::{Path, PathBuf};
 use std::time::Instant;
 
 use egui::{Event as GuiEvent, Pos2, RawInput as EguiInput, Rect};
 use egui_winit::State as EguiState;
-use gl::types::GLuint;
-use glam::{Mat4, Quat, Vec2, Vec3};
+use glam::{Mat4, Quat, Vec2, Vec3, Vec3Swizzles};
 use glutin::event::{
     DeviceEvent, ElementState, Event, KeyboardInput, MouseButton, MouseScrollDelta, VirtualKeyCode,
     WindowEvent,
 };
-use glutin::event_loop::{ControlFlow, EventLoop};
+use glutin::event_loop::{ControlFlow, EventLoop, EventLoopWindowTarget};
 use glutin::window::WindowBuilder;
-use glutin::{Api, GlProfile, GlRequest};
-use glutin::{PossiblyCurrent, WindowedContext};
-
+use glutin::{Api, ContextError, GlProfile, GlRequest, Robustness};
+use image::{DynamicImage, ImageBuffer, Rgb32FImage};
+use memoffset::offset_of;
+use serde::{Deserialize, Serialize};
+
+use audio::{Audio, AudioSettings};
+use benchmark::{Benchmark, Pass};
+use biome::BiomeSettings;
+use block_texture::BlockFormat;
+use buildable::{BuildableAreas, BuildableSettings};
 use camera::Camera;
+use cliffs::CliffSettings;
+use compare::CompareSettings;
 use config::Config;
-use editor::gui::{Action, Gui};
+use daylight::{DayCycle, Sunlight};
+use dem::{VoidRepairSettings, VOID_OVERLAY_OPACITY};
+use detail_map::DetailSettings;
+use drape::{Drape, DrapeSettings};
+use editor::curve_editor::CurveEditor;
+use editor::detached::{ContextSlot, DetachedPanel};
+use editor::gui::{Action, Gui, Panel, ViewInfo};
+use erosion::{DropletSettings, ErosionSettings};
+use export_hooks::{ExportHooks, ExportProfile};
+use external::{ExternalImportSettings, FLOW_OVERLAY_OPACITY};
+use filters::FilterSettings;
+use flood::FloodSettings;
+use frame_pacing::{FramePacing, FrameSettings};
+use heightfield::{DirtyRect, EdgeMode, HeightField};
+use horizon_map::HorizonSettings;
+use hypsometric::HypsometricSettings;
 use input::{vec2_to_egui_pos2, vec2_to_egui_vec2, vkeycode_to_egui_key, Input, Modifiers};
+use instances::InstancedModel;
+use io::{IoEvent, IoJob, IoWorker, SavePurpose};
+use journal::{Entry, Journal};
 use model::Model;
+use osm::{OsmData, OsmSettings};
+use path_check::PathCheck;
+use postprocess::{
+    ColorGrading, PhotoSettings, PostProcess, ReflectionSettings, ResolutionSettings,
+};
+use prefab::PrefabSettings;
+use project::{Changes, PackReport, PackSettings};
+use prop_paint::{GroundBlend, IntersectionBlend, PropPaintSettings};
+use recording::{Frame, Playback, Recorder, Recording, RecordingError, StartWith};
+use resources::MemoryBudget;
+use scatter_types::{ScatterLayer, ScatterSettings};
+use season::SeasonSettings;
+use session_stats::{SessionStats, STATS_PATH};
+use sky_light::{SkyLight, SkyLightSettings};
 use skybox::Skybox;
-use terrain::Terrain;
+use splat::{PaintBlendMode, PaintSettings};
+use stamp::StampSettings;
+use storage::StorageSettings;
+use sun::{LensFlare, SunSettings};
+use sync::{SyncSession, SyncedMap};
+use terrain::{
+    AlongStrokeSmoothing, BoundsChange, ErosionSplat, FitSettings, Footprint, SnapSettings,
+    SnapTarget, Stroke, StrokeHeading, Terrain, TerrainMesh, TerrainParams,
+};
+use tint::TintSettings;
+use viewport::{ViewKind, ViewportLayout, Viewports};
+use viewshed::ViewshedSettings;
+use weathering::WeatheringSettings;
+use wind::WindSettings;
 
+use crate::opengl::caps;
 use crate::opengl::shader::Program;
+use crate::opengl::uniform::{BlockLayout, Std140Type, UniformBlock, UniformBuffer};
 use crate::texture::unit_to_gl_const;
 
 type Result<T> = std::result::Result<T, Box<dyn Error>>;
@@ -46,21 +153,61 @@ type Result<T> = std::result::Result<T, Box<dyn Error>>;
 
 fn main() {
     let event_loop = EventLoop::new();
-    let mut game = Game::new(&event_loop).unwrap_or_else(|error| {
+    let mut game = start(&event_loop).unwrap_or_else(|error| {
         eprintln!("{}", error);
         std::process::exit(1);
     });
 
-    event_loop.run(move |event, _, control_flow| {
-        if let Err(error) = game.process_event(event, control_flow) {
-            eprint!("{}", error);
-            std::process::exit(1);
+    event_loop.run(move |event, event_loop, control_flow| {
+        if let Err(error) = game.process_event(event, event_loop, control_flow) {
+            if let Err(error) = game.recover(error, event_loop) {
+                eprintln!("{}", error);
+                std::process::exit(1);
+            }
         };
     });
 }
 
+/// Loads the config and starts the way the command line says
+fn start(event_loop: &EventLoop<()>) -> Result<Game> {
+    let config = Config::load_or_default()?;
+    let mut game = Game::new(event_loop, config)?;
+    match StartWith::from_args(std::env::args().skip(1)) {
+        Some(StartWith::Record(path)) => game.start_recording(path),
+        Some(StartWith::Play(path)) => game.start_playback(&path, true)?,
+        Some(StartWith::Benchmark(path)) => game.start_benchmark(path)?,
+        None => {}
+    }
+    Ok(game)
+}
+
+/// The context is gone and nothing can be drawn with it anymore, e.g. after a driver
+/// reset. Anything else is shown to the user and the editor carries on.
+fn is_context_lost(error: &(dyn Error + 'static)) -> bool {
+    let mut error = Some(error);
+    while let Some(current) = error {
+        if let Some(ContextError::ContextLost) = current.downcast_ref::<ContextError>() {
+            return true;
+        }
+        error = current.source();
+    }
+    false
+}
+
 // ==================================== Game ======================================================
 
+const WINDOW_TITLE: &str = "Мёртвый трилистник";
+/// The brush and the camera move in steps this long, so that they behave the
+/// same at any frame rate
+const FIXED_DELTA_TIME: f32 = 1.0 / 60.0;
+/// Only what can be seen is shaded
+const VIEWSHED_OVERLAY_OPACITY: f32 = 0.5;
+const SUN_HOURS_OVERLAY_OPACITY: f32 = 0.6;
+const BUILDABLE_OVERLAY_OPACITY: f32 = 0.5;
+const FLOOD_OVERLAY_OPACITY: f32 = 0.7;
+/// Where the sun is looked for over the day, every 15 minutes
+const SUN_PATH_SAMPLES: usize = 96;
+
 static mut WINDOW_WIDTH: usize = 0;
 static mut WINDOW_HEIGHT: usize = 0;
 
@@ -80,16 +227,168 @@ enum EditorMode {
     Terrain { tool: TerrainTool },
 }
 
-struct EditorState {}
+/// Maps shown over the terrain until they're dismissed
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OverlayMap {
+    /// Where the imported heightmap had no data
+    Voids,
+    /// Where water runs on an imported build
+    Flow,
+    /// What can be seen from the observer
+    Viewshed,
+    SunHours,
+    Buildable,
+    Flood,
+}
+
+/// Ground that can be seen from an observer, shown over the terrain until it's cleared
+struct Viewshed {
+    /// World space, where the observer's eyes are
+    eye: Vec3,
+    visible: HeightField,
+    /// Of the whole terrain
+    fraction: f32,
+}
+
+/// How long the sun shines on the terrain over a day, shown until it's hidden
+struct SunHours {
+    /// Share of the daylight hours every texel of the horizon map gets
+    share: HeightField,
+    /// Hours the sun is up
+    daylight: f32,
+}
+
+/// Water that would stand on the terrain, shown until it's hidden
+struct Flood {
+    /// 0 where it's dry, from a little over 0 in the shallows to 1 in the deepest water
+    shown: HeightField,
+    /// Of the whole terrain
+    share: f32,
+    /// Meters
+    deepest: f32,
+}
+
+pub struct EditorState {
+    pub photo: PhotoSettings,
+    pub reflections: ReflectionSettings,
+    pub resolution: ResolutionSettings,
+    pub terrain_mesh: TerrainMesh,
+    pub tiling_preview: bool,
+    pub grading: ColorGrading,
+    pub paint: PaintSettings,
+    pub tint: TintSettings,
+    pub screenshot_requested: bool,
+    /// Parameters being edited in the new project dialog, if it's open
+    pub new_project: Option<TerrainParams>,
+    /// Crop / extend dialog, if it's open
+    pub bounds_change: Option<BoundsChange>,
+    /// Dropped onto the window and waiting to be confirmed
+    pub dropped_heightmap: Option<String>,
+    pub void_repair: VoidRepairSettings,
+    /// World Machine / Gaea build to import
+    pub external: ExternalImportSettings,
+    pub osm: OsmSettings,
+    /// Resolution of the copy to sculpt on instead of the full heightmap
+    pub proxy_resolution: u32,
+    /// Closing with unsaved changes waits for save / discard / cancel
+    pub confirm_exit: bool,
+    /// What went wrong since they were last dismissed, the editor carried on
+    pub errors: Vec<String>,
+    /// Where projects are exported to and imported from
+    pub storage: StorageSettings,
+    /// Relative to the storage
+    pub archive_path: String,
+    pub recent_projects: Vec<String>,
+    pub pack: PackSettings,
+    /// Sizes of the last archive exported
+    pub last_export: Option<PackReport>,
+    pub erosion: ErosionSettings,
+    pub erosion_brush: DropletSettings,
+    pub erosion_splat: ErosionSplat,
+    pub filters: FilterSettings,
+    /// What brushes and filters find past the border of the terrain
+    pub edges: EdgeMode,
+    pub curve_editor: CurveEditor,
+    pub weathering: WeatheringSettings,
+    pub hypsometric: HypsometricSettings,
+    pub memory_budget: MemoryBudget,
+    pub frames: FrameSettings,
+    /// 1 is the OS scale factor, saved to the config once it's changed
+    pub ui_scale: f32,
+    /// Saved to the config once it's changed
+    pub session_stats: bool,
+    /// Saved to the config when asked to
+    pub export_hooks: ExportHooks,
+    /// Where to host or join a shared editing session
+    pub sync_address: String,
+    pub record_journal: bool,
+    /// Where input recordings are saved and played back from
+    pub input_recording_path: String,
+    pub compare: CompareSettings,
+    pub drape: DrapeSettings,
+    /// What's tiled over the terrain, see `ground_texture`
+    pub ground_texture_path: String,
+    /// Baked maps are saved BC compressed as KTX2 rather than as PNGs
+    pub compressed_maps: bool,
+    pub detail: DetailSettings,
+    pub snap: SnapSettings,
+    pub stamps: StampSettings,
+    pub prefabs: PrefabSettings,
+    pub cliffs: CliffSettings,
+    pub scatter: ScatterSettings,
+    /// Meters between a draped object and the terrain
+    pub drape_offset: f32,
+    pub fit: FitSettings,
+    pub prop_paint: PropPaintSettings,
+    pub intersection_blend: IntersectionBlend,
+    pub along_stroke: AlongStrokeSmoothing,
+    pub wind: WindSettings,
+    /// Walkable path between two points, for checking routes
+    pub paths: PathCheck,
+    pub viewshed: ViewshedSettings,
+    pub buildable: BuildableSettings,
+    pub flood: FloodSettings,
+    pub seasons: SeasonSettings,
+    pub biomes: BiomeSettings,
+    pub audio: AudioSettings,
+    pub day: DayCycle,
+    pub sun: SunSettings,
+    pub sky_light: SkyLightSettings,
+    pub horizon: HorizonSettings,
+}
 
-enum TerrainTool {
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum TerrainTool {
     Sculpt,
+    /// Smooths only along the stroke, for riverbeds and ridgelines
+    SmoothAlong,
+    /// Runs hydraulic erosion under the brush
+    ErodeUnderBrush,
     PaintTextures,
+    /// Brings back the heights of the heightmap being compared with
+    Revert,
+    /// Saves the terrain under the brush as a stamp
+    CaptureStamp,
+    /// Saves the objects under the brush, and optionally the terrain, as a prefab
+    CapturePrefab,
+    PlacePrefab,
     PaintTrees,
+    /// Paints instances of the selected scatter type
     PaintVegetation,
+    /// Places wind zones
+    Wind,
+    /// Paints where each biome is
+    PaintBiomes,
+    /// Paints a color multiplied into the terrain
+    PaintTint,
+    /// Places ambient sound emitters
+    PlaceSound,
+    /// Finds a walkable path between two clicked points
+    FindPath,
+    /// Shades what can be seen from a clicked point
+    Viewshed,
 }
 
-// NOTE: no need to worry about std140 because Mat4's are aligned properly and with no gaps
 #[repr(C)]
 pub struct CameraTransforms {
     mvp: Mat4,
@@ -99,6 +398,28 @@ pub struct CameraTransforms {
     sun_vp: Mat4,
 }
 
+impl CameraTransforms {
+    fn update(&mut self, camera: &Camera) {
+        self.view = camera.get_view_matrix();
+        self.proj = camera.get_projection_matrix();
+        self.mvp = self.proj * self.view * self.model;
+    }
+}
+
+impl UniformBlock for CameraTransforms {
+    fn layout() -> BlockLayout {
+        BlockLayout::new("UTransforms")
+            .member("mvp", Std140Type::Mat4, offset_of!(Self, mvp))
+            .member("proj", Std140Type::Mat4, offset_of!(Self, proj))
+            .member("view", Std140Type::Mat4, offset_of!(Self, view))
+            .member("model", Std140Type::Mat4, offset_of!(Self, model))
+            .member("sun_vp", Std140Type::Mat4, offset_of!(Self, sun_vp))
+    }
+}
+
+/// The one the gizmo moves and object operations apply to
+const ACTIVE_GAME_OBJECT: usize = 1;
+
 // Intentionally dumb
 struct GameObject {
     pos: Vec3,
@@ -121,24 +442,51 @@ impl GameObject {
 struct Game {
     config: Config,
 
-    windowed_context: WindowedContext<PossiblyCurrent>,
+    windowed_context: ContextSlot,
     in_focus: bool,
 
     game_start: Instant,
     frame_start: Instant,
+    /// Time not yet simulated, less than a step
+    step_time: f32,
+    frame_pacing: FramePacing,
 
     scale_factor: f32,
 
     old_input: Input,
+    /// Where the current stroke is going, for tools that follow it
+    heading: StrokeHeading,
+    /// Every frame of the erosion brush releases different droplets
+    droplet_seed: u32,
     input: Input,
 
     gui: Gui,
     gui_state: EguiState,
+    detached_panels: Vec<DetachedPanel>,
+    /// Windows are opened once the frame is done
+    panels_to_detach: Vec<Panel>,
 
-    camera: Camera,
+    viewports: Viewports,
 
     terrain: Terrain,
     skybox: Skybox,
+    lens_flare: LensFlare,
+    sky_light: SkyLight,
+    post_process: PostProcess,
+
+    io: IoWorker,
+    sync: Option<SyncSession>,
+    journal: Journal,
+    /// Journal length before the brush last went down, where its stroke starts
+    stroke_start: usize,
+    /// Input being recorded or played back, never both
+    recorder: Option<Recorder>,
+    playback: Option<Playback>,
+    benchmark: Option<Benchmark>,
+    changes: Changes,
+    session_stats: SessionStats,
+    /// Exit as soon as the heightmap is saved
+    exit_after_save: bool,
 
     mode: GameMode,
 
@@ -146,18 +494,35 @@ struct Game {
     editor_mode: EditorMode,
 
     // tmp
-    camera_transforms_ubo: GLuint,
+    camera_transforms_ubo: UniformBuffer<CameraTransforms>,
     camera_transforms: CameraTransforms,
 
     model_shader: Program,
     game_objects: Vec<GameObject>,
+    instance_shader: Program,
+    instance_shadow_shader: Program,
+    imposter_shader: Program,
+    cliff_rocks: Option<InstancedModel>,
+    /// Texels the imported heightmap had no data for, until it's dismissed
+    import_voids: Option<HeightField>,
+    /// Where water runs on an imported World Machine / Gaea build, until it's dismissed
+    import_flow: Option<HeightField>,
+    /// Which is in the overlay already, it's only uploaded again after something else
+    map_in_overlay: Option<OverlayMap>,
+    viewshed: Option<Viewshed>,
+    sun_hours: Option<SunHours>,
+    /// Flat enough regions big enough to build on, shown until they're hidden
+    buildable: Option<BuildableAreas>,
+    flood: Option<Flood>,
+    /// One per scatter type
+    scatter_layers: Vec<ScatterLayer>,
+    /// None when there's no audio device
+    audio: Option<Audio>,
 }
 
 impl Game {
     /// Creates a window and inits a new game
-    fn new(event_loop: &EventLoop<()>) -> Result<Self> {
-        let config = Config::load_or_default()?;
-
+    fn new(event_loop: &EventLoopWindowTarget<()>, config: Config) -> Result<Self> {
         // Create window
         #[cfg(all(windows))]
         let window_builder = {
@@ -173,7 +538,7 @@ impl Game {
             );
 
             WindowBuilder::new()
-                .with_title("Мёртвый трилистник")
+                .with_title(WINDOW_TITLE)
                 .with_resizable(false)
                 .with_position(glutin::dpi::LogicalPosition::new(70, 10))
                 .with_inner_size(inner_size)
@@ -181,7 +546,7 @@ impl Game {
 
         #[cfg(not(windows))]
         let window_builder = WindowBuilder::new()
-            .with_title("Мёртвый трилистник")
+            .with_title(WINDOW_TITLE)
             .with_position(glutin::dpi::LogicalPosition::new(70, 10))
             // .with_fullscreen(Some(glutin::window::Fullscreen::Borderless(
             //     event_loop.primary_monitor(),
@@ -189,20 +554,51 @@ impl Game {
             .with_inner_size(glutin::dpi::LogicalSize::new(1920, 1080))
             .with_resizable(false);
 
-        let gl_request = GlRequest::Specific(Api::OpenGl, (4, 5));
-        let gl_profile = GlProfile::Core;
-        let windowed_context = glutin::ContextBuilder::new()
-            .with_gl(gl_request)
-            .with_gl_profile(gl_profile)
-            .with_srgb(true)
-            .with_double_buffer(Some(true))
-            .with_depth_buffer(16)
-            .with_vsync(true)
-            .build_windowed(window_builder, event_loop)?;
+        // Older versions are tried when the newest isn't there, `--safe-mode` goes
+        // straight to the oldest one
+        let safe_mode = std::env::args().any(|arg| arg == "--safe-mode");
+        let versions = if safe_mode {
+            &caps::VERSIONS[caps::VERSIONS.len() - 1..]
+        } else {
+            &caps::VERSIONS[..]
+        };
+        let mut windowed_context = None;
+        for (i, &version) in versions.iter().enumerate() {
+            let built = glutin::ContextBuilder::new()
+                .with_gl(GlRequest::Specific(Api::OpenGl, version))
+                .with_gl_profile(GlProfile::Core)
+                // A driver reset loses the context instead of leaving it hung,
+                // see `Game::recreate`
+                .with_gl_robustness(Robustness::TryRobustLoseContextOnReset)
+                .with_srgb(true)
+                .with_double_buffer(Some(true))
+                .with_depth_buffer(16)
+                .with_vsync(true)
+                .build_windowed(window_builder.clone(), event_loop);
+            match built {
+                Ok(context) => {
+                    windowed_context = Some(context);
+                    break;
+                }
+                Err(error) if i + 1 < versions.len() => {
+                    eprintln!("No OpenGL {}.{}: {}", version.0, version.1, error);
+                }
+                Err(error) => return Err(error.into()),
+            }
+        }
+        let windowed_context = windowed_context.expect("There's always a version to try");
 
         // Set up OpenGL
         let windowed_context = unsafe { windowed_context.make_current().unwrap() };
         gl::load_with(|s| windowed_context.get_proc_address(s) as *const _);
+        let capabilities = caps::init();
+        if capabilities.version < caps::VERSIONS[0] {
+            let (major, minor) = capabilities.version;
+            eprintln!("Running on OpenGL {}.{}, so", major, minor);
+            for limitation in capabilities.limitations() {
+                eprintln!("  {}", limitation);
+            }
+        }
         let window = windowed_context.window();
         // window.set_cursor_grab(true)?;
         // window.set_cursor_visible(false);
@@ -218,8 +614,10 @@ impl Game {
             gl::Enable(gl::FRAMEBUFFER_SRGB);
             gl::Enable(gl::CULL_FACE);
 
-            gl::Enable(gl::DEBUG_OUTPUT);
-            gl::DebugMessageCallback(Some(opengl::debug_callback), std::ptr::null());
+            if capabilities.debug_output {
+                gl::Enable(gl::DEBUG_OUTPUT);
+                gl::DebugMessageCallback(Some(opengl::debug_callback), std::ptr::null());
+            }
         }
 
         // // Directional light
@@ -240,42 +638,35 @@ impl Game {
         let camera = Camera::new(position, target, window_size.width, window_size.height);
 
         // Set up camera transforms uniform buffer
-        let mut transforms_ubo: GLuint = 0;
-        unsafe {
-            gl::CreateBuffers(1, &mut transforms_ubo);
-            gl::NamedBufferStorage(
-                transforms_ubo,
-                std::mem::size_of::<CameraTransforms>() as isize,
-                std::ptr::null(),
-                gl::DYNAMIC_STORAGE_BIT,
-            );
-            gl::BindBufferBase(gl::UNIFORM_BUFFER, 1, transforms_ubo);
-        }
+        // Updated once per view: enough slots for four views and two frames in flight
+        let transforms_ubo = UniformBuffer::new(1, 8, "Camera transforms");
+        let terrain = Terrain::new(
+            Vec2::new(0.0, 0.0),
+            config.terrain,
+            config.start_with_flat_terrain,
+            &config.heightmap_path,
+        )?;
+
         let transforms_data = {
             let proj = camera.get_projection_matrix();
             let view = camera.get_view_matrix();
             let model = Mat4::IDENTITY;
-            let sun_proj = Mat4::orthographic_rh_gl(-600.0, 600.0, -600.0, 600.0, 1.0, 1200.0);
-            let sun_view = Mat4::look_at_rh(
-                Vec3::new(0.0, 200.0, 500.0),
-                Vec3::new(0.0, 0.0, 0.0),
-                Vec3::new(0.0, 1.0, 0.0),
-            );
 
             CameraTransforms {
                 mvp: proj * view * model,
                 proj,
                 view,
                 model,
-                sun_vp: sun_proj * sun_view,
+                sun_vp: terrain.sun_view_projection(),
             }
         };
 
-        let terrain = Terrain::new(
-            Vec2::new(0.0, 0.0),
-            config.start_with_flat_terrain,
-            &config.heightmap_path,
-        )?;
+        let viewports = Viewports::new(
+            camera,
+            window_size.width,
+            window_size.height,
+            terrain.size(),
+        );
 
         let skybox = Skybox::from([
             "textures/skybox/default/right.png",
@@ -285,6 +676,7 @@ impl Game {
             "textures/skybox/default/front.png",
             "textures/skybox/default/back.png",
         ])?;
+        let sky_light = SkyLight::new(&skybox)?;
 
         let game_objects = vec![
             GameObject {
@@ -304,16 +696,44 @@ impl Game {
             },
         ];
 
+        let post_process =
+            PostProcess::new(window_size.width as usize, window_size.height as usize)?;
+
         let model_shader = Program::new()
+            .define("VERTEX_PAINT", "1")
+            .define("GROUND_BLEND", "1")
             .vertex_shader(include_str!("shaders/simple/simple.vert"))?
             .fragment_shader(include_str!("shaders/simple/simple.frag"))?
             .link()?;
+        transforms_ubo.check_program(&model_shader)?;
+        let instance_shader = Program::new()
+            .define("SEASON_TINT", "1")
+            .define("GROUND_BLEND", "1")
+            .vertex_shader(include_str!("shaders/simple/instanced.vert"))?
+            .fragment_shader(include_str!("shaders/simple/simple.frag"))?
+            .link()?;
+        transforms_ubo.check_program(&instance_shader)?;
+        let instance_shadow_shader = Program::new()
+            .define("SHADOW", "1")
+            .vertex_shader(include_str!("shaders/simple/instanced.vert"))?
+            .fragment_shader(include_str!("shaders/editor/terrain/shadow.frag.glsl"))?
+            .link()?;
+        transforms_ubo.check_program(&instance_shadow_shader)?;
+        let imposter_shader = Program::new()
+            .vertex_shader(include_str!("shaders/simple/imposter.vert"))?
+            .fragment_shader(include_str!("shaders/simple/imposter.frag"))?
+            .link()?;
+        transforms_ubo.check_program(&imposter_shader)?;
 
         let screen_size_physical = Vec2::new(window_size.width as f32, window_size.height as f32);
 
         // Gui and its initial input
-        let gui = Gui::new(screen_size_physical)?;
-        let gui_state = EguiState::new(window);
+        let mut gui = Gui::new(screen_size_physical)?;
+        if !config.tutorial_seen {
+            gui.start_tutorial();
+        }
+        let mut gui_state = EguiState::new(window);
+        gui_state.set_pixels_per_point(window.scale_factor() as f32 * config.ui_scale);
 
         let now = Instant::now();
         let input = Input {
@@ -321,31 +741,122 @@ impl Game {
             ..Default::default()
         };
 
-        Ok(Game {
+        let compare = CompareSettings::new(&config.heightmap_path);
+
+        let config_tool = config.tools.tool;
+
+        let mut game = Game {
             config,
 
             scale_factor: window.scale_factor() as f32,
-            windowed_context,
+            windowed_context: ContextSlot::new(windowed_context),
 
             game_start: now,
             frame_start: now,
+            step_time: 0.0,
+            frame_pacing: FramePacing::new(),
 
             old_input: Input::default(),
+            heading: StrokeHeading::default(),
+            droplet_seed: 0,
             input,
 
             gui,
             gui_state,
+            detached_panels: vec![],
+            panels_to_detach: vec![],
 
-            camera,
+            viewports,
             in_focus: true,
 
             terrain,
             skybox,
+            lens_flare: LensFlare::new()?,
+            sky_light,
+            post_process,
+
+            io: IoWorker::new(),
+            sync: None,
+            journal: Journal::default(),
+            stroke_start: 0,
+            recorder: None,
+            playback: None,
+            benchmark: None,
+            session_stats: SessionStats::new(),
+            changes: Changes::default(),
+            exit_after_save: false,
 
             mode: GameMode::Editor,
-            editor_state: EditorState {},
+            editor_state: EditorState {
+                photo: PhotoSettings::default(),
+                reflections: ReflectionSettings::default(),
+                resolution: ResolutionSettings::default(),
+                terrain_mesh: TerrainMesh::Patches,
+                tiling_preview: false,
+                grading: ColorGrading::default(),
+                paint: PaintSettings::default(),
+                tint: TintSettings::default(),
+                screenshot_requested: false,
+                new_project: None,
+                bounds_change: None,
+                dropped_heightmap: None,
+                void_repair: VoidRepairSettings::default(),
+                external: ExternalImportSettings::default(),
+                osm: OsmSettings::default(),
+                proxy_resolution: 1024,
+                confirm_exit: false,
+                errors: vec![],
+                storage: StorageSettings::load(),
+                archive_path: project::ARCHIVE_PATH.to_owned(),
+                recent_projects: project::recent(),
+                pack: PackSettings::default(),
+                last_export: None,
+                erosion: ErosionSettings::default(),
+                erosion_brush: DropletSettings::default(),
+                erosion_splat: ErosionSplat::default(),
+                filters: FilterSettings::default(),
+                edges: EdgeMode::Clamp,
+                curve_editor: CurveEditor::default(),
+                weathering: WeatheringSettings::default(),
+                hypsometric: HypsometricSettings::default(),
+                memory_budget: MemoryBudget::default(),
+                frames: FrameSettings::default(),
+                ui_scale: config.ui_scale,
+                session_stats: config.session_stats,
+                export_hooks: config.export_hooks.clone(),
+                sync_address: sync::DEFAULT_ADDRESS.to_owned(),
+                record_journal: false,
+                input_recording_path: "input.json".to_owned(),
+                compare,
+                drape: DrapeSettings::default(),
+                ground_texture_path: ground_texture::DEFAULT_PATH.to_owned(),
+                compressed_maps: false,
+                detail: DetailSettings::default(),
+                snap: SnapSettings::default(),
+                stamps: StampSettings::default(),
+                prefabs: PrefabSettings::default(),
+                cliffs: CliffSettings::default(),
+                scatter: ScatterSettings::default(),
+                drape_offset: 0.1,
+                fit: FitSettings::default(),
+                prop_paint: PropPaintSettings::default(),
+                intersection_blend: IntersectionBlend::default(),
+                along_stroke: AlongStrokeSmoothing::default(),
+                wind: WindSettings::default(),
+                paths: PathCheck::default(),
+                viewshed: ViewshedSettings::default(),
+                buildable: BuildableSettings::default(),
+                flood: FloodSettings::default(),
+                seasons: SeasonSettings::default(),
+                biomes: BiomeSettings::default(),
+                audio: AudioSettings::default(),
+                day: DayCycle::default(),
+                sun: SunSettings::default(),
+                sky_light: SkyLightSettings::default(),
+                horizon: HorizonSettings::default(),
+            },
             editor_mode: EditorMode::Terrain {
-                tool: TerrainTool::Sculpt,
+                tool: config_tool.unwrap_or(TerrainTool::Sculpt),
             },
 
             camera_transforms_ubo: transforms_ubo,
@@ -353,12 +864,52 @@ impl Game {
 
             game_objects,
             model_shader,
-        })
+            instance_shader,
+            instance_shadow_shader,
+            imposter_shader,
+            cliff_rocks: None,
+            import_voids: None,
+            import_flow: None,
+            map_in_overlay: None,
+            viewshed: None,
+            sun_hours: None,
+            buildable: None,
+            flood: None,
+            scatter_layers: vec![],
+            audio: match Audio::new() {
+                Ok(audio) => Some(audio),
+                Err(error) => {
+                    eprintln!("Sound is off: {}", error);
+                    None
+                }
+            },
+        };
+        game.config.tools.restore(&mut game.editor_state);
+        game.restore_brush_size();
+        Ok(game)
     }
 
-    fn process_event(&mut self, event: Event<()>, control_flow: &mut ControlFlow) -> Result<()> {
+    fn process_event(
+        &mut self,
+        event: Event<()>,
+        event_loop: &EventLoopWindowTarget<()>,
+        control_flow: &mut ControlFlow,
+    ) -> Result<()> {
         match event {
-            Event::WindowEvent { event, .. } => {
+            Event::WindowEvent { event, window_id } => {
+                self.frame_pacing.input_received();
+
+                if window_id != self.windowed_context.window().id() {
+                    let detached = self
+                        .detached_panels
+                        .iter_mut()
+                        .find(|detached| detached.window_id() == window_id);
+                    if let Some(detached) = detached {
+                        detached.on_event(&event);
+                    }
+                    return Ok(());
+                }
+
                 // Let egui know about the event
                 let captured = self.gui_state.on_event(self.gui.ctx(), &event);
                 if captured {
@@ -368,12 +919,15 @@ impl Game {
 
                 // Process window event
                 match event {
-                    WindowEvent::CloseRequested => self.input.should_exit = true,
+                    WindowEvent::CloseRequested => self.request_exit(),
+                    WindowEvent::DroppedFile(path) => self.open_dropped_file(&path),
                     WindowEvent::ScaleFactorChanged {
                         scale_factor,
                         new_inner_size: _,
                     } => {
                         self.scale_factor = scale_factor as f32;
+                        // egui went back to the OS scale
+                        self.apply_ui_scale();
                     }
                     WindowEvent::ModifiersChanged(state) => {
                         self.input.modifiers = Modifiers {
@@ -425,26 +979,46 @@ impl Game {
                     _ => {}
                 }
             }
-            Event::DeviceEvent { event, .. } => match event {
-                DeviceEvent::MouseMotion { delta } if self.in_focus => {
-                    let (x, y) = delta;
-                    let delta = Vec2::new(x as f32, y as f32) / self.scale_factor;
-                    self.input.pointer_delta += delta;
-                    self.input.pointer_moved = true;
+            Event::DeviceEvent { event, .. } => {
+                if self.in_focus {
+                    self.frame_pacing.input_received();
                 }
-                DeviceEvent::MouseWheel {
-                    delta: MouseScrollDelta::LineDelta(x, y),
-                } => {
-                    let scroll_delta = Vec2::new(x, y) / self.scale_factor;
-                    self.input.scroll_delta += scroll_delta;
-                    self.input.scrolled = true;
+                match event {
+                    DeviceEvent::MouseMotion { delta } if self.in_focus => {
+                        let (x, y) = delta;
+                        let delta = Vec2::new(x as f32, y as f32) / self.scale_factor;
+                        self.input.pointer_delta += delta;
+                        self.input.pointer_moved = true;
+                    }
+                    DeviceEvent::MouseWheel {
+                        delta: MouseScrollDelta::LineDelta(x, y),
+                    } => {
+                        let scroll_delta = Vec2::new(x, y) / self.scale_factor;
+                        self.input.scroll_delta += scroll_delta;
+                        self.input.scrolled = true;
+                    }
+                    _ => {}
                 }
-                _ => {}
-            },
+            }
             Event::MainEventsCleared => {
                 if !self.input.should_exit {
-                    self.update_and_render()?;
+                    let animating = self.is_animating();
+                    let frames = &self.editor_state.frames;
+                    if self
+                        .frame_pacing
+                        .should_draw(frames, animating, control_flow)
+                    {
+                        self.update_and_render()?;
+                        self.open_detached_panels(event_loop)?;
+                    }
                 } else {
+                    self.save_tool_memory();
+                    self.stop_recording();
+                    if self.config.session_stats {
+                        if let Err(error) = self.session_stats.save() {
+                            eprintln!("Couldn't save session stats to {}: {}", STATS_PATH, error);
+                        }
+                    }
                     *control_flow = ControlFlow::Exit;
                 }
             }
@@ -454,181 +1028,2231 @@ impl Game {
     }
 
     fn update_and_render(&mut self) -> Result<()> {
+        // Caught before anything is drawn into a context that's gone
+        if caps::get().reset_status && unsafe { gl::GetGraphicsResetStatus() } != gl::NO_ERROR {
+            return Err(ContextError::ContextLost.into());
+        }
+
         let now = Instant::now();
-        let delta_time = now.duration_since(self.frame_start).as_secs_f32();
+        // Nothing should jump ahead by however long the loop slept for
+        let delta_time = now.duration_since(self.frame_start).as_secs_f32().min(0.1);
         self.frame_start = now;
         let time = now.duration_since(self.game_start).as_secs_f64();
         self.input.time = time as f32;
 
+        // The brush and the camera move in fixed steps, however long the frame took
+        self.step_time += delta_time;
+        let mut steps = (self.step_time / FIXED_DELTA_TIME) as u32;
+        self.step_time -= steps as f32 * FIXED_DELTA_TIME;
+        if let Some(playback) = &mut self.playback {
+            match playback.next_frame() {
+                Some(frame) => {
+                    self.input = frame.input;
+                    steps = frame.steps;
+                }
+                None => self.finish_playback(),
+            }
+        }
+        if let Some(benchmark) = &mut self.benchmark {
+            match benchmark.begin_frame() {
+                Some((position, direction)) => {
+                    self.viewports.main_camera_mut().place(position, direction);
+                    self.input.camera_moved = true;
+                }
+                None => self.finish_benchmark(),
+            }
+        }
+
+        self.process_io_events();
+        self.apply_memory_budget();
+        self.frame_pacing
+            .apply_vsync(&self.editor_state.frames, &self.windowed_context);
+
         let new_mode = match self.mode {
             GameMode::Menu => unimplemented!("Menu is not implemented"),
             GameMode::Game => unimplemented!("Game mode is not implemented"),
-            GameMode::Editor => self.draw_editor(delta_time)?,
+            GameMode::Editor => self.draw_editor(steps, delta_time)?,
         };
 
         self.mode = new_mode;
 
+        if let Some(audio) = &mut self.audio {
+            let listener = self.viewports.main_camera().position;
+            audio.update(&self.editor_state.audio, listener);
+        }
+
+        let tool = self.current_tool();
+        self.session_stats.frame(tool, delta_time, now.elapsed());
+
         Ok(())
     }
 
-    fn draw_editor(&mut self, delta_time: f32) -> Result<GameMode> {
-        let active_game_object = 1;
-        let mut model_matrix = self.game_objects[active_game_object].get_model_matrix();
-
-        let actions = self.gui.layout_and_interact(
-            &mut self.gui_state,
-            self.windowed_context.window(),
-            &self.camera_transforms.view,
-            &self.camera_transforms.proj,
-            &mut model_matrix,
-        );
-        self.game_objects[active_game_object].set_model_matrix(&model_matrix);
-        self.process_gui_actions(actions)?;
+    /// Swaps in a new sky and relights everything with it
+    fn replace_sky(&mut self, panorama: &Rgb32FImage) -> Result<()> {
+        let (width, height) = panorama.dimensions();
+        let skybox = Skybox::from_equirect(width, height, panorama.as_raw())?;
+        self.sky_light = SkyLight::new(&skybox)?;
+        self.skybox = skybox;
+        Ok(())
+    }
 
-        if self.gui.wants_input() {
-            // Pointer over UI or currently interacting with it
-            self.terrain.hide_cursor();
-            self.windowed_context.window().set_cursor_visible(true); // we always want cursor with UI
-        } else {
-            // Process input
-            self.camera.speed_boost = self.input.modifiers.shift;
-
-            // Move camera
-            if self.input.mouse_buttons.secondary {
-                use camera::Movement::*;
-                if self.input.forward {
-                    self.camera.go(Forward, delta_time);
-                    self.input.camera_moved = true;
-                }
-                if self.input.left {
-                    self.camera.go(Left, delta_time);
-                    self.input.camera_moved = true;
-                }
-                if self.input.back {
-                    self.camera.go(Backward, delta_time);
-                    self.input.camera_moved = true;
-                }
-                if self.input.right {
-                    self.camera.go(Right, delta_time);
-                    self.input.camera_moved = true;
+    /// What a file dropped onto the window is taken for depends on its extension
+    fn open_dropped_file(&mut self, path: &Path) {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or_default()
+            .to_lowercase();
+        let path_string = path.to_string_lossy().into_owned();
+        if path.is_dir() {
+            // Most likely a World Machine or Gaea build, which replaces the terrain too
+            self.editor_state.external.path = path_string;
+            return;
+        }
+        match extension.as_str() {
+            // Replacing the terrain can't be undone, so it waits for a confirmation
+            "png" | "tif" | "tiff" => self.editor_state.dropped_heightmap = Some(path_string),
+            "gltf" | "glb" => {
+                let settings = &mut self.editor_state.scatter;
+                settings.model_path = path_string;
+                if let Err(error) = scatter_types::add_type(settings, &mut self.scatter_layers) {
+                    let message = format!("Couldn't load {}: {}", settings.model_path, error);
+                    self.show_error(message);
                 }
+            }
+            "hdr" | "exr" => self.io.submit(IoJob::LoadSky { path: path_string }),
+            _ => self.show_error(format!("Don't know what to do with {}", path.display())),
+        }
+    }
 
-                // Rotate camera
-                if self.input.pointer_moved {
-                    let delta = self.input.pointer_delta;
-                    self.camera.rotate(delta.x, delta.y);
-                    self.input.camera_moved = true;
-                }
+    fn open_detached_panels(&mut self, event_loop: &EventLoopWindowTarget<()>) -> Result<()> {
+        for panel in std::mem::take(&mut self.panels_to_detach) {
+            let ui_scale = self.config.ui_scale;
+            match DetachedPanel::open(panel, event_loop, &self.windowed_context, ui_scale) {
+                Ok(detached) => self.detached_panels.push(detached),
+                Err(error) => self.show_error(format!(
+                    "Couldn't open a window for {}: {}",
+                    panel.name(),
+                    error
+                )),
             }
+            self.windowed_context.make_current()?;
+        }
+        Ok(())
+    }
+
+    /// Tells the user in a dialog. A failing draw fails every frame, so it's only
+    /// shown once until dismissed.
+    fn show_error(&mut self, message: String) {
+        eprintln!("{}", message);
+        let errors = &mut self.editor_state.errors;
+        if !errors.contains(&message) {
+            errors.push(message);
+        }
+    }
+
+    /// Picks up after an event failed, e.g. halfway through a frame. A lost context
+    /// is made anew, the error is only given back if that fails too.
+    fn recover(
+        &mut self,
+        error: Box<dyn Error>,
+        event_loop: &EventLoopWindowTarget<()>,
+    ) -> Result<()> {
+        if is_context_lost(&*error) {
+            eprintln!("{}", error);
+            return self.recreate(event_loop);
+        }
+        // A detached window's context could still be current
+        self.windowed_context.make_current()?;
+        unsafe {
+            gl::Disable(gl::SCISSOR_TEST);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+            gl::Viewport(0, 0, WINDOW_WIDTH as i32, WINDOW_HEIGHT as i32);
+        }
+        self.show_error(error.to_string());
+        Ok(())
+    }
 
-            if self.input.camera_moved {
-                // Update camera tranforms uniform buffer
-                self.camera_transforms.view = self.camera.get_view_matrix();
-                self.camera_transforms.proj = self.camera.get_projection_matrix();
-                self.camera_transforms.mvp = self.camera_transforms.proj
-                    * self.camera_transforms.view
-                    * self.camera_transforms.model;
-                let data = &self.camera_transforms as *const CameraTransforms;
-                unsafe {
-                    gl::NamedBufferSubData(
-                        self.camera_transforms_ubo,
-                        0,
-                        std::mem::size_of::<CameraTransforms>() as isize,
-                        data as *const _,
-                    )
+    /// After a driver reset: opens a new window with a new context and moves over
+    /// everything that isn't only on the GPU. The terrain and the scattered instances
+    /// are uploaded again from their CPU copies.
+    fn recreate(&mut self, event_loop: &EventLoopWindowTarget<()>) -> Result<()> {
+        let backup = self.terrain.backup();
+        let mut config = self.config.clone();
+        // The terrain comes from the backup, there's nothing to load
+        config.terrain = backup.params;
+        config.start_with_flat_terrain = true;
+        opengl::forget_context();
+        let fresh = Game::new(event_loop, config)?;
+        let mut old = std::mem::replace(self, fresh);
+        self.terrain.restore(&backup)?;
+
+        macro_rules! carry_over {
+            ($($field:ident),* $(,)?) => {
+                $(std::mem::swap(&mut self.$field, &mut old.$field);)*
+            };
+        }
+        carry_over!(
+            config,
+            game_start,
+            heading,
+            droplet_seed,
+            viewports,
+            io,
+            sync,
+            journal,
+            stroke_start,
+            recorder,
+            playback,
+            changes,
+            session_stats,
+            exit_after_save,
+            mode,
+            editor_state,
+            editor_mode,
+            import_voids,
+            import_flow,
+            audio,
+        );
+        self.panels_to_detach = old.detached_panels.iter().map(|d| d.panel).collect();
+        self.panels_to_detach.append(&mut old.panels_to_detach);
+
+        let types = std::mem::take(&mut self.editor_state.scatter.types);
+        for (scatter_type, layer) in types.into_iter().zip(&old.scatter_layers) {
+            match layer.reload(&scatter_type.model_path) {
+                Ok(layer) => {
+                    self.editor_state.scatter.types.push(scatter_type);
+                    self.scatter_layers.push(layer);
+                }
+                Err(error) => {
+                    self.editor_state.scatter.selected = None;
+                    let path = &scatter_type.model_path;
+                    self.show_error(format!("Couldn't load {} again: {}", path, error));
                 }
             }
+        }
+        opengl::drop_lost(old);
 
-            if self.input.pointer_moved || self.input.camera_moved {
-                let ray = self.camera.get_ray_through_pixel(self.input.pointer);
-                let cursor_active = self.terrain.move_cursor(&ray);
-                self.windowed_context
-                    .window()
-                    .set_cursor_visible(!cursor_active);
-            }
+        self.update_title();
+        self.show_error(
+            "The graphics driver was reset, so the editor moved to a new window. \
+             Cliff rocks, the drape and a loaded sky have to be made again."
+                .to_owned(),
+        );
+        Ok(())
+    }
+
+    /// Asks first if there's anything to lose
+    fn request_exit(&mut self) {
+        if self.changes.unsaved() {
+            self.editor_state.confirm_exit = true;
+        } else {
+            self.input.should_exit = true;
+        }
+    }
+
+    /// The title shows whether there are unsaved changes
+    fn update_title(&self) {
+        let title = if self.changes.unsaved() {
+            format!("{}*", WINDOW_TITLE)
+        } else {
+            WINDOW_TITLE.to_owned()
+        };
+        self.windowed_context.window().set_title(&title);
+    }
+
+    fn edited(&mut self) {
+        let was_unsaved = self.changes.unsaved();
+        self.changes.edited();
+        self.session_stats.edited();
+        if !was_unsaved {
+            self.update_title();
+        }
+    }
+
+    /// After creating or loading a project
+    fn reset_changes(&mut self) {
+        self.changes.reset();
+        self.update_title();
+    }
+
+    fn apply_ui_scale(&mut self) {
+        let ui_scale = self.config.ui_scale;
+        self.gui_state
+            .set_pixels_per_point(self.scale_factor * ui_scale);
+        for detached in &mut self.detached_panels {
+            detached.set_ui_scale(ui_scale);
+        }
+    }
+
+    /// Closes the panel's window and puts it back into the main one
+    fn attach_panel(&mut self, panel: Panel) -> Result<()> {
+        let index = self.detached_panels.iter().position(|d| d.panel == panel);
+        if let Some(index) = index {
+            self.detached_panels.remove(index).close()?;
+            self.windowed_context.make_current()?;
+        }
+        Ok(())
+    }
+
+    /// Whether frames are needed even without any input
+    fn is_animating(&self) -> bool {
+        let input = &self.input;
+        let buttons = &input.mouse_buttons;
+        self.editor_state.day.playing
+            || input.forward
+            || input.back
+            || input.left
+            || input.right
+            || buttons.primary
+            || buttons.middle
+            || buttons.secondary
+            || self.io.pending() > 0
+            || self.sync.is_some()
+            || self.playback.is_some()
+            || self.benchmark.is_some()
+            || self.terrain.horizons_stale()
+    }
 
-            if self.input.scrolled {
-                let y = self.input.scroll_delta.y;
-                self.terrain.brush.size = (self.terrain.brush.size - y * 5.5).clamp(0.1, 800.0);
-                // self.terrain.tess_level = (self.terrain.tess_level - y * 0.2).clamp(1.0, 16.0);
+    /// `steps` is how many fixed timesteps to simulate before drawing, `delta_time`
+    /// is for what only moves on screen
+    fn draw_editor(&mut self, steps: u32, delta_time: f32) -> Result<GameMode> {
+        let mut model_matrix = self.game_objects[ACTIVE_GAME_OBJECT].get_model_matrix();
+
+        let main_view = self.viewports.main();
+        let gizmo_clip_rect = {
+            let pixels_per_point = self.gui.ctx().pixels_per_point();
+            let min = main_view.rect.min() / pixels_per_point;
+            let max = min + main_view.rect.size() / pixels_per_point;
+            Rect::from_min_max(Pos2::new(min.x, min.y), Pos2::new(max.x, max.y))
+        };
+        let cursor = self.terrain.cursor;
+        let cursor_position = if cursor.is_finite() {
+            Some(Vec3::new(
+                cursor.x,
+                self.terrain.surface_height_at(cursor),
+                cursor.y,
+            ))
+        } else {
+            None
+        };
+        let (cursor_geo, cursor_lat_long) = match (&self.config.georeference, cursor_position) {
+            (Some(georeference), Some(_)) => {
+                let (x, y) = georeference.extent.at_uv(self.terrain.uv_at(cursor));
+                let projected = if georeference.is_geographic() {
+                    None // the same as latitude and longitude
+                } else {
+                    Some((x, y))
+                };
+                (projected, georeference.lat_long(x, y))
+            }
+            _ => (None, None),
+        };
+        let view = ViewInfo {
+            view_matrix: main_view.camera.get_view_matrix(),
+            projection_matrix: main_view.camera.get_projection_matrix(),
+            gizmo_clip_rect,
+            viewport_layout: self.viewports.layout(),
+            tool: self.current_tool(),
+            terrain: self.terrain.params(),
+            io_pending: self.io.pending(),
+            vsync_supported: self.frame_pacing.vsync_supported(),
+            detached_panels: self.detached_panels.iter().map(|d| d.panel).collect(),
+            sync_status: self.sync.as_ref().map(SyncSession::status),
+            journal_len: self.journal.len(),
+            recording_input: self.recorder.is_some(),
+            playing_input: self.playback.is_some(),
+            unsaved_changes: self.changes.unsaved(),
+            edits: self.changes.edits(),
+            filter_preview: self.terrain.filter_preview(),
+            can_undo_filter: self.terrain.can_undo_filter(),
+            can_undo_stroke: self.terrain.can_undo_stroke(),
+            has_reference: self.terrain.has_reference(),
+            has_drape: self.terrain.has_drape(),
+            proxy_resolution: self.terrain.proxy_resolution(),
+            detail_tiles: self.terrain.detail_tiles(),
+            brush_fits_detail: self.terrain.brush_fits_detail(),
+            import_voids: self.import_voids.as_ref().map(dem::void_fraction),
+            import_flow: self.import_flow.is_some(),
+            georeferenced: self.config.georeference.is_some(),
+            cursor: cursor_position,
+            cursor_geo,
+            cursor_lat_long,
+            cliff_rocks: self.cliff_rocks.as_ref().map(InstancedModel::count),
+            scatter_counts: self
+                .scatter_layers
+                .iter()
+                .map(|layer| layer.instances().count())
+                .collect(),
+            viewshed: self
+                .viewshed
+                .as_ref()
+                .map(|viewshed| (viewshed.eye, viewshed.fraction)),
+            buildable: self.buildable.as_ref().map(|buildable| {
+                let total = buildable.regions.iter().sum();
+                let largest = buildable.regions.first().copied().unwrap_or(0.0);
+                (buildable.regions.len(), total, largest)
+            }),
+            flood: self
+                .flood
+                .as_ref()
+                .map(|flood| (flood.share, flood.deepest)),
+            sun_hours_shown: self.sun_hours.is_some(),
+            sun_hours_at_cursor: match &self.sun_hours {
+                Some(sun_hours) if cursor_position.is_some() => {
+                    let uv = self.terrain.uv_at(cursor);
+                    Some(sun_hours.share.sample(uv.x, uv.y) * sun_hours.daylight)
+                }
+                _ => None,
+            },
+            wind_arrows: if self.editor_state.wind.show_arrows {
+                self.editor_state.wind.arrows(&self.terrain)
+            } else {
+                vec![]
+            },
+        };
+        let mut actions = self.gui.layout_and_interact(
+            &mut self.gui_state,
+            self.windowed_context.window(),
+            &mut model_matrix,
+            &view,
+            &mut self.editor_state,
+        );
+        if !self.detached_panels.is_empty() {
+            for detached in &mut self.detached_panels {
+                actions.extend(detached.update(&view, &mut self.editor_state)?);
+            }
+            self.windowed_context.make_current()?;
+            let closed: Vec<Panel> = self
+                .detached_panels
+                .iter()
+                .filter(|d| d.closed)
+                .map(|d| d.panel)
+                .collect();
+            for panel in closed {
+                self.attach_panel(panel)?;
             }
+        }
+        let moved_object =
+            if model_matrix != self.game_objects[ACTIVE_GAME_OBJECT].get_model_matrix() {
+                if let Some(target) = self.editor_state.snap.active() {
+                    model_matrix = self.snap_to_terrain(&model_matrix, target);
+                }
+                Some((ACTIVE_GAME_OBJECT, model_matrix))
+            } else {
+                None
+            };
+        self.game_objects[ACTIVE_GAME_OBJECT].set_model_matrix(&model_matrix);
+        if let Some((index, transform)) = moved_object {
+            self.record(Entry::MoveObject { index, transform });
+        }
+        self.process_gui_actions(actions);
 
-            if self.input.mouse_buttons.primary && self.terrain.cursor.is_finite() {
-                self.terrain
-                    .shape_terrain(delta_time, !self.input.modifiers.ctrl);
+        let mut edited = None;
+
+        if self.gui_wants_input(steps) {
+            // Pointer over UI or currently interacting with it
+            self.terrain.hide_cursor();
+            self.windowed_context.window().set_cursor_visible(true); // we always want cursor with UI
+        } else {
+            self.handle_pointer();
+            for step in 0..steps {
+                if step > 0 {
+                    // Clicks only happen on the first step of a frame
+                    self.old_input.mouse_buttons = self.input.mouse_buttons;
+                }
+                edited = match (edited, self.step(FIXED_DELTA_TIME)) {
+                    (Some((map, rect)), Some((step_map, step_rect))) if map == step_map => {
+                        Some((map, rect.union(&step_rect)))
+                    }
+                    (edited, None) => edited,
+                    (_, step_edited) => step_edited,
+                };
             }
         }
+        if !self.input.mouse_buttons.primary {
+            self.terrain.end_stroke(self.stroke_start);
+            self.stroke_start = self.journal.len();
+        }
+        self.terrain.sync_dirty_region_async();
+        self.session_stats
+            .undo_captured(self.terrain.take_undo_capture_time());
+        // Baking takes a moment, so not while a stroke is still going
+        if self.editor_state.horizon.enabled
+            && self.terrain.horizons_stale()
+            && !self.input.mouse_buttons.primary
+        {
+            self.terrain.bake_horizons();
+        }
+        self.update_sync(edited, moved_object);
+        self.update_overlay();
+        self.terrain
+            .set_drape_opacity(self.editor_state.drape.opacity);
+        self.terrain.stream_pages(self.viewports.main_camera());
+        self.terrain.mesh = self.editor_state.terrain_mesh;
+        self.terrain.edges = self.editor_state.edges;
+        self.terrain.tiling_preview = self.editor_state.tiling_preview;
+        self.update_sun(delta_time);
+        benchmark::mark(&mut self.benchmark, Pass::Editing);
 
         // Draw
         unsafe {
             gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
         }
-        self.terrain.draw(self.input.time)?;
+        let camera_position = self.viewports.main_camera().position;
+        let shadow_shader = &self.instance_shadow_shader;
+        let scatter_layers = &self.scatter_layers;
+        let scatter_types = &self.editor_state.scatter.types;
+        let wind = &self.editor_state.wind;
+        let time = self.input.time;
+        if self.editor_state.horizon.shadow_map {
+            self.terrain.draw_shadow_map(|| {
+                wind.set_uniforms(shadow_shader, time)?;
+                shadow_shader.set_vec3("camera_position", &camera_position)?;
+                for (layer, scatter_type) in scatter_layers.iter().zip(scatter_types) {
+                    let lod = &scatter_type.lod;
+                    if lod.cast_shadows {
+                        // Imposters don't cast shadows, but the model does as far as
+                        // anything's drawn
+                        layer
+                            .instances()
+                            .draw(shadow_shader, (0.0, lod.max_distance))?;
+                    }
+                }
+                Ok(())
+            })?;
+        }
+        benchmark::mark(&mut self.benchmark, Pass::Shadows);
+
+        let sky = self.editor_state.day.sky();
+        self.post_process
+            .set_render_scale(self.editor_state.resolution.scale);
+        let post_process_active = self.post_process.is_active(
+            &self.editor_state.photo,
+            &self.editor_state.grading,
+            &self.editor_state.reflections,
+        );
+        if post_process_active {
+            self.post_process.begin();
+        }
 
-        // Draw objects
-        self.model_shader.set_used();
-        for obj in &self.game_objects {
-            let transform = obj.get_model_matrix();
+        // Every view gets its own part of the window and its own camera,
+        // the scene is shared
+        let (target_height, scale) = if post_process_active {
+            let post_process = &self.post_process;
+            (post_process.target_height(), post_process.render_scale())
+        } else {
+            (unsafe { WINDOW_HEIGHT } as u32, 1.0)
+        };
+        unsafe {
+            gl::Enable(gl::SCISSOR_TEST);
+        }
+        for (i, viewport) in self.viewports.iter().enumerate() {
+            let rect = viewport.rect.scaled(scale);
+            rect.set_gl_viewport(target_height);
             unsafe {
-                gl::BindVertexArray(obj.model.vao);
+                gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
             }
-            for node in &obj.model.drawable_nodes {
-                let transform = transform * node.transform;
-                self.model_shader.set_mat4("model", &transform)?;
-
-                for primitive in &node.primitives {
-                    let material = &obj.model.materials[primitive.material_index];
-                    unsafe {
-                        gl::ActiveTexture(unit_to_gl_const(0));
-                        gl::BindTexture(gl::TEXTURE_2D, material.base_color_texture);
+            self.camera_transforms.update(&viewport.camera);
+            self.camera_transforms_ubo.update(&self.camera_transforms);
 
-                        gl::DrawElements(
-                            gl::TRIANGLES,
-                            primitive.index_count as i32,
-                            gl::UNSIGNED_INT,
-                            primitive.first_index as *const _,
-                        );
-                    }
-                }
+            if viewport.kind == ViewKind::Heightmap {
+                let main_camera = self.viewports.main_camera().position;
+                self.terrain
+                    .draw_flat(Vec2::new(main_camera.x, main_camera.z))?;
+                benchmark::mark(&mut self.benchmark, Pass::Terrain);
+                continue;
             }
+            self.terrain.draw(
+                self.input.time,
+                &self.editor_state.compare,
+                &self.editor_state.seasons,
+                &self.editor_state.biomes,
+                &self.sky_light,
+                &self.editor_state.horizon,
+                &self.editor_state.hypsometric,
+            )?;
+            benchmark::mark(&mut self.benchmark, Pass::Terrain);
+            self.draw_game_objects()?;
+            self.draw_instances(viewport.camera.position)?;
+            benchmark::mark(&mut self.benchmark, Pass::Objects);
+            let sun = &self.terrain.sun;
+            let disk_radius = self.editor_state.sun.disk_radius();
+            self.skybox.draw(&sky, sun, disk_radius)?;
+            // One occlusion query is enough, so only the main view gets a flare
+            if i == 0 {
+                self.lens_flare.draw(
+                    &self.editor_state.sun,
+                    sun,
+                    &viewport.camera.get_view_matrix(),
+                    &viewport.camera.get_projection_matrix(),
+                    rect.size(),
+                )?;
+            }
+            benchmark::mark(&mut self.benchmark, Pass::Sky);
+        }
+        unsafe {
+            gl::Disable(gl::SCISSOR_TEST);
+            gl::Viewport(0, 0, WINDOW_WIDTH as i32, WINDOW_HEIGHT as i32);
         }
 
-        self.skybox.draw();
+        if post_process_active {
+            let camera = self.viewports.main_camera();
+            self.post_process.present(
+                &self.editor_state.photo,
+                &self.editor_state.grading,
+                &self.editor_state.reflections,
+                &self.editor_state.resolution,
+                &camera.get_projection_matrix(),
+                &camera.get_view_matrix(),
+            )?;
+        }
+        benchmark::mark(&mut self.benchmark, Pass::PostProcess);
+
+        if self.editor_state.screenshot_requested {
+            self.editor_state.screenshot_requested = false;
+            let image = unsafe { postprocess::read_screen(WINDOW_WIDTH, WINDOW_HEIGHT) };
+            self.io.submit(IoJob::SaveImage {
+                image: DynamicImage::ImageRgb8(image),
+                path: postprocess::screenshot_path(),
+                flip_vertical: true, // GL has the origin at the bottom
+                purpose: SavePurpose::Screenshot,
+            });
+        }
 
         self.gui.draw();
+        benchmark::mark(&mut self.benchmark, Pass::Gui);
 
         self.windowed_context.swap_buffers()?;
 
         // Clear old input
+        let stepped_buttons = self.old_input.mouse_buttons;
         self.old_input = self.input.renew();
+        if steps == 0 {
+            // No step has seen these buttons yet, so a press still has to look like one
+            self.old_input.mouse_buttons = stepped_buttons;
+        }
 
         Ok(GameMode::Editor)
     }
 
-    fn process_gui_actions(&mut self, actions: Vec<Action>) -> Result<()> {
-        for action in actions {
-            match action {
-                Action::SaveTerrain => {
-                    let (pixels, size) = self.terrain.get_heightmap_pixels();
-                    image::save_buffer(
-                        self.config.heightmap_path.clone(),
-                        &pixels,
-                        size as u32,
-                        size as u32,
-                        image::ColorType::L16,
-                    )?;
-                    self.config.start_with_flat_terrain = false;
-                    self.config.save();
-                }
-                Action::SaveCamera => {
-                    self.config.camera_position = Some(self.camera.position);
-                    self.config.camera_direction = Some(self.camera.direction);
-                    self.config.save();
-                }
-                Action::Quit => {
-                    self.input.should_exit = true;
+    /// Everything the pointer does once a frame rather than once a step
+    fn handle_pointer(&mut self) {
+        let pointer = self.input.pointer * self.scale_factor;
+        let dragging = self.input.mouse_buttons.primary || self.input.mouse_buttons.secondary;
+        self.viewports.update_active(pointer, dragging);
+
+        let camera = self.viewports.active_camera_mut();
+        camera.speed_boost = self.input.modifiers.shift;
+
+        // Rotate camera
+        if self.input.mouse_buttons.secondary && self.input.pointer_moved {
+            let delta = self.input.pointer_delta;
+            camera.rotate(delta.x, delta.y);
+            self.input.camera_moved = true;
+        }
+
+        if self.input.pointer_moved || self.input.camera_moved {
+            self.update_cursor();
+        }
+
+        if self.editor_state.photo.enabled {
+            // No brush in photo mode, clicking picks what to focus on instead
+            self.terrain.hide_cursor();
+            if self.input.mouse_buttons.primary {
+                let window_size = unsafe { Vec2::new(WINDOW_WIDTH as f32, WINDOW_HEIGHT as f32) };
+                self.editor_state.photo.focus_uv =
+                    Vec2::new(pointer.x / window_size.x, 1.0 - pointer.y / window_size.y);
+            }
+        } else if self.input.scrolled {
+            let y = self.input.scroll_delta.y;
+            self.terrain.brush.size = (self.terrain.brush.size - y * 5.5).clamp(0.1, 800.0);
+            if let Some(tool) = self.current_tool() {
+                self.config
+                    .tools
+                    .set_brush_size(tool, self.terrain.brush.size);
+            }
+            // self.terrain.tess_level = (self.terrain.tess_level - y * 0.2).clamp(1.0, 16.0);
+        }
+    }
+
+    /// Puts the brush where the pointer is on the terrain
+    fn update_cursor(&mut self) {
+        let pointer = self
+            .viewports
+            .pointer_in_active(self.input.pointer * self.scale_factor);
+        let ray = self
+            .viewports
+            .active()
+            .camera
+            .get_ray_through_pixel(pointer);
+        let snap = self.editor_state.snap.active();
+        let cursor_active = self.terrain.move_cursor(&ray, snap);
+        self.windowed_context
+            .window()
+            .set_cursor_visible(!cursor_active || self.editor_state.photo.enabled);
+        if self.editor_state.photo.enabled {
+            self.terrain.hide_cursor();
+        }
+    }
+
+    /// Flies the camera and applies the brush for one fixed timestep, returning what
+    /// the brush changed
+    fn step(&mut self, delta_time: f32) -> Option<(SyncedMap, DirtyRect)> {
+        if self.input.mouse_buttons.secondary {
+            use camera::Movement::*;
+            let camera = self.viewports.active_camera_mut();
+            let mut moved = false;
+            for (pressed, movement) in [
+                (self.input.forward, Forward),
+                (self.input.left, Left),
+                (self.input.back, Backward),
+                (self.input.right, Right),
+            ] {
+                if pressed {
+                    camera.go(movement, delta_time);
+                    moved = true;
                 }
             }
+            if moved {
+                self.input.camera_moved = true;
+                self.update_cursor();
+            }
+        }
+
+        if self.editor_state.photo.enabled
+            || !self.input.mouse_buttons.primary
+            || !self.terrain.cursor.is_finite()
+        {
+            return None;
+        }
+        // Painting over a preview would be confusing, it's either or
+        if self.editor_state.filters.preview {
+            self.editor_state.filters.preview = false;
+            self.terrain.end_filter_preview();
+        }
+        let stroke = self.terrain.stroke(delta_time);
+        match self.current_tool() {
+            Some(TerrainTool::Sculpt) => {
+                let raise = !self.input.modifiers.ctrl;
+                // Not synced, peers only see it once it's baked
+                if self.editor_state.detail.enabled && self.terrain.brush_fits_detail() {
+                    if !self.terrain.carve_detail(&stroke, raise) {
+                        self.show_error(
+                            "No room for more detail tiles, bake them into the heightmap \
+                             to carve more"
+                                .to_owned(),
+                        );
+                    }
+                    self.record(Entry::CarveDetail { stroke, raise });
+                    return None;
+                }
+                let rect = self.terrain.shape_terrain(&stroke, raise);
+                self.record(Entry::Sculpt { stroke, raise });
+                Some((SyncedMap::Heights, rect))
+            }
+            Some(TerrainTool::SmoothAlong) => {
+                if !self.old_input.mouse_buttons.primary {
+                    self.heading.end();
+                }
+                let settings = self.editor_state.along_stroke;
+                self.heading.follow(&stroke).map(|direction| {
+                    let rect = self.terrain.smooth_along(&stroke, direction, &settings);
+                    self.record(Entry::SmoothAlong {
+                        stroke,
+                        direction,
+                        settings,
+                    });
+                    (SyncedMap::Heights, rect)
+                })
+            }
+            Some(TerrainTool::ErodeUnderBrush) => {
+                let settings = self.editor_state.erosion_brush;
+                let splat = self.editor_state.erosion_splat;
+                let seed = self.droplet_seed;
+                self.droplet_seed = seed.wrapping_add(1);
+                let rect = self
+                    .terrain
+                    .erode_under_brush(&stroke, &settings, &splat, seed);
+                self.record(Entry::ErodeUnderBrush {
+                    stroke,
+                    settings,
+                    splat,
+                    seed,
+                });
+                Some((SyncedMap::Heights, rect))
+            }
+            Some(TerrainTool::PaintTextures) => {
+                let settings = self.editor_state.paint.clone();
+                let rect = self.terrain.paint_textures(&stroke, &settings);
+                if self.editor_state.prop_paint.with_brush {
+                    self.paint_objects(&stroke, &settings);
+                    self.record(Entry::PaintObjects {
+                        stroke,
+                        settings: settings.clone(),
+                    });
+                }
+                self.record(Entry::Paint { stroke, settings });
+                Some((SyncedMap::Splat, rect))
+            }
+            Some(TerrainTool::CaptureStamp) => {
+                // Once per click
+                if !self.old_input.mouse_buttons.primary {
+                    let brush_size = self.terrain.brush.size;
+                    let stamp = stamp::capture(&self.terrain, self.terrain.cursor, brush_size);
+                    self.io.submit(IoJob::SaveStamp {
+                        name: self.editor_state.stamps.name.clone(),
+                        stamp,
+                    });
+                }
+                None
+            }
+            Some(TerrainTool::CapturePrefab) => {
+                if !self.old_input.mouse_buttons.primary {
+                    let objects = self
+                        .game_objects
+                        .iter()
+                        .map(|object| (object.model.path.as_str(), object.get_model_matrix()));
+                    let prefab = prefab::capture(
+                        &self.terrain,
+                        self.terrain.cursor,
+                        self.terrain.brush.size,
+                        objects,
+                        &self.editor_state.prefabs,
+                    );
+                    self.io.submit(IoJob::SavePrefab {
+                        name: self.editor_state.prefabs.name.clone(),
+                        prefab,
+                    });
+                }
+                None
+            }
+            Some(TerrainTool::PlacePrefab) => {
+                if !self.old_input.mouse_buttons.primary {
+                    self.place_prefab(self.terrain.cursor)
+                } else {
+                    None
+                }
+            }
+            Some(TerrainTool::PaintVegetation) => {
+                let scatter = &self.editor_state.scatter;
+                if let Some(index) = scatter.selected {
+                    let erase = self.input.modifiers.ctrl;
+                    self.scatter_layers[index].paint(&self.terrain, &scatter.types[index], erase);
+                }
+                None
+            }
+            Some(TerrainTool::PaintTint) => {
+                let settings = &self.editor_state.tint;
+                let erase = self.input.modifiers.ctrl;
+                self.terrain.paint_tint(&stroke, settings, erase);
+                self.record(Entry::Tint {
+                    stroke,
+                    settings: settings.clone(),
+                    erase,
+                });
+                None
+            }
+            Some(TerrainTool::PaintBiomes) => {
+                let settings = &self.editor_state.biomes.paint;
+                self.terrain.paint_biomes(&stroke, settings);
+                None
+            }
+            Some(TerrainTool::PlaceSound) => {
+                if !self.old_input.mouse_buttons.primary {
+                    let audio = &mut self.editor_state.audio;
+                    let cursor = self.terrain.cursor;
+                    if self.input.modifiers.ctrl {
+                        audio.remove_emitter_at(cursor);
+                    } else {
+                        let ground = self.terrain.height_at(cursor);
+                        let position = Vec3::new(cursor.x, ground + 1.0, cursor.y);
+                        audio.add_emitter(position, self.terrain.brush.size / 2.0);
+                    }
+                }
+                None
+            }
+            Some(TerrainTool::Wind) => {
+                if !self.old_input.mouse_buttons.primary {
+                    let wind = &mut self.editor_state.wind;
+                    if self.input.modifiers.ctrl {
+                        wind.remove_zone_at(self.terrain.cursor);
+                    } else {
+                        wind.add_zone(self.terrain.cursor, self.terrain.brush.size / 2.0);
+                    }
+                }
+                None
+            }
+            Some(TerrainTool::FindPath) => {
+                if !self.old_input.mouse_buttons.primary {
+                    let paths = &mut self.editor_state.paths;
+                    if self.input.modifiers.ctrl {
+                        paths.clear();
+                    } else {
+                        paths.click(&self.terrain, self.terrain.cursor);
+                    }
+                }
+                None
+            }
+            Some(TerrainTool::Viewshed) => {
+                if !self.old_input.mouse_buttons.primary {
+                    if self.input.modifiers.ctrl {
+                        self.viewshed = None;
+                    } else {
+                        self.look_from(self.terrain.cursor);
+                    }
+                }
+                None
+            }
+            Some(TerrainTool::Revert) => {
+                let rate = self.editor_state.compare.blend;
+                let rect = self.terrain.revert_to_reference(&stroke, rate);
+                rect.map(|rect| (SyncedMap::Heights, rect))
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether the pointer is over the GUI, or was when playing back. The frame is
+    /// recorded here, as the viewports are about to get its input.
+    fn gui_wants_input(&mut self, steps: u32) -> bool {
+        let wants_input = match &self.playback {
+            Some(playback) => playback.gui_wants_input(),
+            None => self.gui.wants_input(),
+        };
+        if let Some(recorder) = &mut self.recorder {
+            recorder.recording.frames.push(Frame {
+                input: self.input.clone(),
+                gui_wants_input: wants_input,
+                steps,
+            });
+        }
+        wants_input
+    }
+
+    fn start_recording(&mut self, path: PathBuf) {
+        self.playback = None;
+        let camera = self.viewports.main_camera();
+        let size = self.windowed_context.window().inner_size();
+        self.recorder = Some(Recorder {
+            path,
+            recording: Recording {
+                window_size: (size.width, size.height),
+                layout: self.viewports.layout(),
+                camera_position: camera.position,
+                camera_direction: camera.direction,
+                tool: self.current_tool(),
+                brush_size: self.terrain.brush.size,
+                frames: vec![],
+                checksum: 0,
+            },
+        });
+    }
+
+    fn stop_recording(&mut self) {
+        let mut recorder = match self.recorder.take() {
+            Some(recorder) => recorder,
+            None => return,
+        };
+        recorder.recording.checksum = self.terrain.checksum();
+        let path = recorder.path.display();
+        match recorder.recording.save(&recorder.path) {
+            Ok(()) => println!(
+                "Recorded {} frames to {}",
+                recorder.recording.frames.len(),
+                path
+            ),
+            Err(error) => self.show_error(format!(
+                "Couldn't save the recording to {}: {}",
+                path, error
+            )),
+        }
+    }
+
+    /// Puts the camera and the tool back where they were when recording started
+    fn start_playback(
+        &mut self,
+        path: &Path,
+        exit_when_done: bool,
+    ) -> std::result::Result<(), RecordingError> {
+        let recording = Recording::load(path)?;
+        self.stop_recording();
+        let size = self.windowed_context.window().inner_size();
+        if (size.width, size.height) != recording.window_size {
+            let (width, height) = recording.window_size;
+            eprintln!(
+                "Recorded in a {}x{} window, this one is {}x{}, the pointer won't line up",
+                width, height, size.width, size.height
+            );
+        }
+        self.viewports.set_layout(recording.layout);
+        self.viewports
+            .main_camera_mut()
+            .place(recording.camera_position, recording.camera_direction);
+        self.editor_mode = match recording.tool {
+            Some(tool) => EditorMode::Terrain { tool },
+            None => EditorMode::General,
+        };
+        self.terrain.brush.size = recording.brush_size;
+        self.input = Input {
+            camera_moved: true,
+            ..Default::default()
+        };
+        self.old_input = Input::default();
+        self.playback = Some(Playback::new(recording, exit_when_done));
+        Ok(())
+    }
+
+    /// Tells whether the terrain came out the same as when it was recorded
+    fn finish_playback(&mut self) {
+        let playback = match self.playback.take() {
+            Some(playback) => playback,
+            None => return,
+        };
+        let recording = playback.recording();
+        let same = self.terrain.checksum() == recording.checksum;
+        let frames = recording.frames.len();
+        if same {
+            println!("Played back {} frames, the terrain is as recorded", frames);
+        } else {
+            eprintln!(
+                "Played back {} frames, the terrain came out different",
+                frames
+            );
+        }
+        if playback.exit_when_done {
+            std::process::exit(if same { 0 } else { 1 });
+        }
+    }
+
+    /// Replaces the scene with the benchmark's, which is only kept in memory
+    fn start_benchmark(&mut self, path: PathBuf) -> Result<()> {
+        let params = benchmark::scene_params();
+        self.terrain = Terrain::new(Vec2::ZERO, params, true, &self.config.heightmap_path)?;
+        self.terrain
+            .import_heights(&benchmark::scene_heights(&params));
+        self.on_terrain_replaced();
+        self.editor_state.biomes = BiomeSettings::default();
+        self.terrain.auto_texture(&self.editor_state.biomes);
+
+        self.editor_state.scatter = ScatterSettings::default();
+        self.scatter_layers.clear();
+        scatter_types::add_type(&mut self.editor_state.scatter, &mut self.scatter_layers)?;
+        self.scatter_layers[0].fill(
+            &self.terrain,
+            &self.editor_state.scatter.types[0],
+            benchmark::SCATTER_DENSITY,
+            |_, _| true,
+        );
+
+        self.viewports.set_layout(ViewportLayout::Single);
+        self.editor_mode = EditorMode::General;
+        self.editor_state.frames = FrameSettings {
+            vsync: false,
+            fps_cap: false,
+            save_power: false,
+            ..FrameSettings::default()
+        };
+        self.benchmark = Some(Benchmark::new(path, params));
+        Ok(())
+    }
+
+    /// Writes the report and quits
+    fn finish_benchmark(&mut self) {
+        let benchmark = match self.benchmark.take() {
+            Some(benchmark) => benchmark,
+            None => return,
+        };
+        let size = self.windowed_context.window().inner_size();
+        let instances = self
+            .scatter_layers
+            .iter()
+            .map(|l| l.instances().count())
+            .sum();
+        match benchmark.save((size.width, size.height), instances) {
+            Ok(()) => {
+                println!("Benchmark results saved to {}", benchmark.path().display());
+                std::process::exit(0);
+            }
+            Err(error) => {
+                eprintln!("Couldn't save the benchmark results: {}", error);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    /// Each tool has its own brush size, which the shared brush is set to
+    fn restore_brush_size(&mut self) {
+        let size = self
+            .current_tool()
+            .and_then(|tool| self.config.tools.brush_size(tool));
+        if let Some(size) = size {
+            self.terrain.brush.size = size;
+        }
+    }
+
+    /// Saved on exit rather than on every change, the painting settings change a lot
+    fn save_tool_memory(&mut self) {
+        let tools = &mut self.config.tools;
+        tools.tool = self.current_tool().or(tools.tool);
+        tools.store(&self.editor_state);
+        self.config.save();
+    }
+
+    fn current_tool(&self) -> Option<TerrainTool> {
+        match self.editor_mode {
+            EditorMode::Terrain { tool } => Some(tool),
+            EditorMode::General => None,
+        }
+    }
+
+    fn draw_game_objects(&self) -> Result<()> {
+        self.sky_light
+            .set_uniforms(&self.model_shader, &self.terrain.sun)?;
+        self.set_ground_blend_uniforms(&self.model_shader)?;
+        for obj in &self.game_objects {
+            let transform = obj.get_model_matrix();
+            unsafe {
+                gl::BindVertexArray(obj.model.vao.id());
+            }
+            for node in &obj.model.drawable_nodes {
+                let transform = transform * node.transform;
+                self.model_shader.set_mat4("model", &transform)?;
+
+                for primitive in &node.primitives {
+                    let material = &obj.model.materials[primitive.material_index];
+                    unsafe {
+                        gl::ActiveTexture(unit_to_gl_const(0));
+                        gl::BindTexture(gl::TEXTURE_2D, material.base_color_texture);
+
+                        gl::DrawElements(
+                            gl::TRIANGLES,
+                            primitive.index_count as i32,
+                            gl::UNSIGNED_INT,
+                            primitive.first_index as *const _,
+                        );
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn set_ground_blend_uniforms(&self, shader: &Program) -> Result<()> {
+        let editor_state = &self.editor_state;
+        self.terrain
+            .set_ground_uniforms(shader, &editor_state.seasons, &editor_state.biomes)?;
+        editor_state.intersection_blend.set_uniforms(shader)
+    }
+
+    /// Counted again every frame, it's cheap compared to drawing the instances
+    /// The scatter density heatmap, or else the viewshed, the sun hours, the buildable
+    /// areas, the flood, or the voids or the flow of the last import
+    fn update_overlay(&mut self) {
+        let scatter = &self.editor_state.scatter;
+        if let Some(index) = scatter.selected.filter(|_| scatter.heatmap.enabled) {
+            let density = self.scatter_layers[index].density(&self.terrain, &scatter.heatmap);
+            self.terrain.set_overlay(&density, scatter.heatmap.opacity);
+            self.map_in_overlay = None;
+            return;
+        }
+        let show_voids = self.editor_state.void_repair.show;
+        let show_flow = self.editor_state.external.show_flow;
+        let viewshed = self.viewshed.as_ref().map(|viewshed| {
+            (
+                OverlayMap::Viewshed,
+                &viewshed.visible,
+                VIEWSHED_OVERLAY_OPACITY,
+            )
+        });
+        let import = match (&self.import_voids, &self.import_flow) {
+            (Some(voids), _) if show_voids => {
+                Some((OverlayMap::Voids, voids, VOID_OVERLAY_OPACITY))
+            }
+            (_, Some(flow)) if show_flow => Some((OverlayMap::Flow, flow, FLOW_OVERLAY_OPACITY)),
+            _ => None,
+        };
+        let sun_hours = self.sun_hours.as_ref().map(|sun_hours| {
+            (
+                OverlayMap::SunHours,
+                &sun_hours.share,
+                SUN_HOURS_OVERLAY_OPACITY,
+            )
+        });
+        let buildable = self.buildable.as_ref().map(|buildable| {
+            (
+                OverlayMap::Buildable,
+                &buildable.mask,
+                BUILDABLE_OVERLAY_OPACITY,
+            )
+        });
+        let flood = self
+            .flood
+            .as_ref()
+            .map(|flood| (OverlayMap::Flood, &flood.shown, FLOOD_OVERLAY_OPACITY));
+        match viewshed.or(sun_hours).or(buildable).or(flood).or(import) {
+            Some((map, _, _)) if self.map_in_overlay == Some(map) => {}
+            Some((map, values, opacity)) => {
+                self.terrain.set_overlay(values, opacity);
+                self.map_in_overlay = Some(map);
+            }
+            None => {
+                self.terrain.clear_overlay();
+                self.map_in_overlay = None;
+            }
+        }
+    }
+
+    /// Works out what can be seen from a world space point and shows it over the terrain
+    fn look_from(&mut self, point: Vec2) {
+        let settings = &self.editor_state.viewshed;
+        let visible = self.terrain.viewshed(point, settings);
+        let fraction = visible.data().iter().sum::<f32>() / visible.data().len() as f32;
+        let eye_height = self.terrain.height_at(point) + settings.observer_height;
+        self.viewshed = Some(Viewshed {
+            eye: Vec3::new(point.x, eye_height, point.y),
+            visible,
+            fraction,
+        });
+        // Shown again even if there was one before
+        self.map_in_overlay = None;
+    }
+
+    /// Works out how long the sun shines on the terrain and shows it over the terrain
+    fn show_sun_hours(&mut self) {
+        let sun_path = daylight::sun_path(SUN_PATH_SAMPLES);
+        let daylight = insolation::daylight_hours(&sun_path);
+        let mut share = self.terrain.sun_hours(&sun_path);
+        for hours in share.data_mut() {
+            *hours /= daylight.max(f32::EPSILON);
+        }
+        self.sun_hours = Some(SunHours { share, daylight });
+        // Shown again even if they were before
+        self.map_in_overlay = None;
+    }
+
+    /// Works out where water would stand and shows it over the terrain
+    fn show_flood(&mut self) {
+        let depth = self.terrain.flood_depth(&self.editor_state.flood);
+        let deepest = depth.data().iter().copied().fold(0.0, f32::max);
+        let wet = depth.data().iter().filter(|&&depth| depth > 0.0).count();
+        let shown = depth
+            .data()
+            .iter()
+            .map(|&depth| {
+                if depth > 0.0 {
+                    // The shallows would hardly show otherwise
+                    0.1 + 0.9 * depth / deepest
+                } else {
+                    0.0
+                }
+            })
+            .collect();
+        self.flood = Some(Flood {
+            shown: HeightField::from_data(depth.size(), shown),
+            share: wet as f32 / depth.data().len() as f32,
+            deepest,
+        });
+        // Shown again even if it was before
+        self.map_in_overlay = None;
+    }
+
+    /// Shadows follow the sun since the shadow map is drawn every frame
+    fn update_sun(&mut self, delta_time: f32) {
+        let day = &mut self.editor_state.day;
+        day.advance(delta_time);
+        self.terrain.sun = Sunlight {
+            size: self.editor_state.sun.shadow_size(),
+            ..day.sunlight()
+        };
+        self.sky_light
+            .update(&self.editor_state.sky_light, &day.sky());
+        self.camera_transforms.sun_vp = self.terrain.sun_view_projection();
+    }
+
+    fn draw_instances(&self, camera_position: Vec3) -> Result<()> {
+        for shader in [&self.instance_shader, &self.imposter_shader] {
+            self.sky_light.set_uniforms(shader, &self.terrain.sun)?;
+            self.editor_state
+                .wind
+                .set_uniforms(shader, self.input.time)?;
+            shader.set_vec3("camera_position", &camera_position)?;
+        }
+        self.set_ground_blend_uniforms(&self.instance_shader)?;
+        let season = self.editor_state.seasons.season;
+        if let Some(rocks) = &self.cliff_rocks {
+            self.instance_shader.set_used();
+            self.instance_shader.set_float3("season_tint", &[1.0; 3])?;
+            rocks.draw(&self.instance_shader, (0.0, f32::MAX))?;
+        }
+        let scatter_types = &self.editor_state.scatter.types;
+        for (layer, scatter_type) in self.scatter_layers.iter().zip(scatter_types) {
+            let tint = scatter_type.seasons.apply([1.0; 3], season);
+            for shader in [&self.instance_shader, &self.imposter_shader] {
+                shader.set_used();
+                shader.set_float3("season_tint", &tint)?;
+            }
+            let lod = &scatter_type.lod;
+            let instances = layer.instances();
+            instances.draw(&self.instance_shader, lod.model_range())?;
+            instances.draw_imposters(&self.imposter_shader, lod.imposter_range())?;
+        }
+        Ok(())
+    }
+
+    /// Lowers texture resolution of the models furthest away while over the memory budget,
+    /// and brings back the closest ones once there's room again. One step per frame.
+    fn apply_memory_budget(&mut self) {
+        let budget = &self.editor_state.memory_budget;
+        let used = resources::usage().total();
+        let camera_position = self.viewports.main_camera().position;
+        let mut by_distance: Vec<usize> = (0..self.game_objects.len()).collect();
+        by_distance.sort_by(|&a, &b| {
+            let distance_a = self.game_objects[a].pos.distance_squared(camera_position);
+            let distance_b = self.game_objects[b].pos.distance_squared(camera_position);
+            distance_a.total_cmp(&distance_b)
+        });
+
+        if used > budget.bytes() {
+            let furthest = by_distance
+                .iter()
+                .rev()
+                .find(|&&i| self.game_objects[i].model.downres() < budget.max_downres);
+            if let Some(&i) = furthest {
+                let model = &mut self.game_objects[i].model;
+                model.set_downres(model.downres() + 1);
+            }
+        } else {
+            let closest = by_distance
+                .iter()
+                .find(|&&i| self.game_objects[i].model.downres() > 0);
+            if let Some(&i) = closest {
+                let model = &mut self.game_objects[i].model;
+                let levels = model.downres() - 1;
+                let extra = (-model.downres_savings(levels)) as usize;
+                if used + extra <= budget.bytes() {
+                    model.set_downres(levels);
+                }
+            }
+        }
+    }
+
+    /// Next to the heightmap and named after it, as a PNG or compressed to `format`
+    fn save_map(
+        &mut self,
+        image: DynamicImage,
+        suffix: &str,
+        format: BlockFormat,
+        purpose: SavePurpose,
+    ) {
+        let path = Path::new(&self.config.heightmap_path);
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        if self.editor_state.compressed_maps {
+            self.io.submit(IoJob::SaveCompressed {
+                image: image.into_rgba8(),
+                format,
+                path: path.with_file_name(format!("{}_{}.ktx2", stem, suffix)),
+                purpose,
+            });
+        } else {
+            self.io.submit(IoJob::SaveImage {
+                image,
+                path: path.with_file_name(format!("{}_{}.png", stem, suffix)),
+                flip_vertical: false,
+                purpose,
+            });
+        }
+    }
+
+    /// Queued behind whatever else is being written, so it doesn't hold up the editor
+    fn run_export_hook(&mut self, profile: ExportProfile, path: PathBuf) {
+        if let Some(command) = self.editor_state.export_hooks.command(profile) {
+            self.io.submit(IoJob::RunExportHook {
+                profile,
+                command: command.to_owned(),
+                path,
+            });
+        }
+    }
+
+    fn process_io_events(&mut self) {
+        for event in self.io.poll() {
+            match event {
+                IoEvent::Saved { path, purpose } => {
+                    println!("Saved {}", path.display());
+                    if let Some(profile) = ExportProfile::of_save(purpose) {
+                        self.run_export_hook(profile, path.clone());
+                    }
+                    match purpose {
+                        SavePurpose::Heightmap => {
+                            // Only now it's safe to load the heightmap on the next start
+                            self.config.start_with_flat_terrain = false;
+                            self.config.save();
+                            self.changes.save_finished();
+                            self.update_title();
+                            if self.exit_after_save {
+                                self.input.should_exit = true;
+                            }
+                        }
+                        SavePurpose::Stamp => {
+                            self.editor_state.stamps.library = stamp::list();
+                        }
+                        SavePurpose::Prefab => {
+                            self.editor_state.prefabs.library = prefab::list();
+                        }
+                        SavePurpose::NormalMap
+                        | SavePurpose::SplatMap
+                        | SavePurpose::AmbientOcclusionMap
+                        | SavePurpose::HypsometricMap
+                        | SavePurpose::BuildableMask
+                        | SavePurpose::Screenshot => {}
+                    }
+                }
+                IoEvent::SaveFailed { path, error } => {
+                    self.show_error(format!("Couldn't save {}: {}", path.display(), error));
+                    if path == Path::new(&self.config.heightmap_path) {
+                        self.changes.save_failed();
+                        self.exit_after_save = false;
+                    }
+                }
+                IoEvent::LutLoaded { path, lut } => {
+                    // Ignore LUTs that were deselected while loading
+                    if self.editor_state.grading.lut_path.as_deref() == Some(path.as_str()) {
+                        self.post_process.set_lut(&lut);
+                    }
+                }
+                IoEvent::LutFailed { path, error } => {
+                    self.show_error(format!("Couldn't load {}: {}", path, error));
+                    if self.editor_state.grading.lut_path.as_deref() == Some(path.as_str()) {
+                        self.editor_state.grading.lut_path = None;
+                    }
+                }
+                IoEvent::ReferenceLoaded { path, heights } => {
+                    // Ignore it if another one was asked for in the meantime
+                    if self.editor_state.compare.path == path {
+                        self.terrain.set_reference(&heights);
+                    }
+                }
+                IoEvent::ReferenceFailed { path, error } => {
+                    self.show_error(format!("Couldn't load {}: {}", path, error));
+                }
+                IoEvent::ProjectPacked { archive, report } => {
+                    println!("Exported {}", archive);
+                    self.run_export_hook(ExportProfile::Archive, PathBuf::from(&archive));
+                    self.editor_state.recent_projects = project::remember(&archive);
+                    self.editor_state.last_export = Some(report);
+                }
+                IoEvent::ProjectUnpacked { archive, config } => {
+                    self.editor_state.recent_projects = project::remember(&archive);
+                    self.editor_state.export_hooks = config.export_hooks.clone();
+                    self.config = config;
+                    if let Err(error) = self.reload_terrain() {
+                        self.show_error(format!("Couldn't load the imported terrain: {}", error));
+                    }
+                    self.reset_changes();
+                }
+                IoEvent::ProjectFailed { archive, error } => {
+                    self.show_error(format!("Project archive {}: {}", archive, error));
+                }
+                IoEvent::HeightmapImported {
+                    path: _,
+                    heights,
+                    voids,
+                    georeference,
+                } => {
+                    self.terrain.import_heights(&heights);
+                    self.import_voids = voids;
+                    self.import_flow = None;
+                    self.map_in_overlay = None;
+                    self.config.georeference = georeference;
+                    self.edited();
+                }
+                IoEvent::BuildImported { folder, build } => {
+                    let (columns, rows) = build.tiles;
+                    println!("Imported {} from {}x{} tile(s)", folder, columns, rows);
+                    self.terrain.import_heights(&build.heights);
+                    if let Some(layers) = &build.splat {
+                        self.terrain.import_splat(layers);
+                    }
+                    self.import_voids = None;
+                    self.import_flow = build.flow;
+                    self.map_in_overlay = None;
+                    self.config.georeference = build.georeference;
+                    self.edited();
+                }
+                IoEvent::DrapeLoaded {
+                    path,
+                    photo,
+                    extent,
+                } => {
+                    // Ignore it if another one was asked for in the meantime
+                    if self.editor_state.drape.path == path {
+                        let terrain_extent = self.config.georeference.as_ref().map(|g| g.extent);
+                        if extent.is_none() || terrain_extent.is_none() {
+                            eprintln!("{} isn't lined up, it's stretched over the terrain", path);
+                        }
+                        let drape = Drape::new(&photo, extent, terrain_extent);
+                        self.terrain.set_drape(drape);
+                    }
+                }
+                IoEvent::GroundTextureLoaded { path, image } => {
+                    // Ignore it if another one was asked for in the meantime
+                    if self.editor_state.ground_texture_path == path {
+                        self.terrain.set_ground_texture(&image);
+                    }
+                }
+                IoEvent::OsmLoaded { path, data } => {
+                    if data.stretched {
+                        eprintln!("{} isn't lined up, it's stretched over the terrain", path);
+                    }
+                    self.apply_osm(&data);
+                }
+                IoEvent::SkyLoaded { path, panorama } => {
+                    if let Err(error) = self.replace_sky(&panorama) {
+                        self.show_error(format!("Couldn't use {} as the sky: {}", path, error));
+                    }
+                }
+                IoEvent::ImportFailed { path, error } => {
+                    self.show_error(format!("Couldn't import {}: {}", path, error));
+                }
+                IoEvent::ExportHookRan { profile, path } => {
+                    println!("Ran the {} hook on {}", profile.name(), path.display());
+                }
+                IoEvent::ExportHookFailed { profile, error } => {
+                    let name = profile.name().to_lowercase();
+                    self.show_error(format!("The {} export hook failed: {}", name, error));
+                }
+            }
+        }
+    }
+
+    /// Sends this frame's edits and merges everyone else's
+    fn update_sync(
+        &mut self,
+        edited: Option<(SyncedMap, DirtyRect)>,
+        moved_object: Option<(usize, Mat4)>,
+    ) {
+        let session = match &mut self.sync {
+            Some(session) => session,
+            None => return,
+        };
+        let mut result = Ok(());
+        if let Some((map, rect)) = edited {
+            result = session.publish(&self.terrain, map, &rect);
+        }
+        if let (Ok(()), Some((index, transform))) = (&result, moved_object) {
+            result = session.publish_object(index, transform);
+        }
+        let moved = match result {
+            Ok(()) => session.update(&mut self.terrain),
+            Err(error) => Err(error),
+        };
+        match moved {
+            Ok(moved) => {
+                for (index, transform) in moved {
+                    if let Some(object) = self.game_objects.get_mut(index) {
+                        object.set_model_matrix(&transform);
+                    }
+                }
+            }
+            Err(error) => {
+                self.show_error(format!("Left the shared session: {}", error));
+                self.sync = None;
+            }
+        }
+    }
+
+    /// Moves an object's origin onto the snap target, resting on the ground
+    fn snap_to_terrain(&self, model_matrix: &Mat4, target: SnapTarget) -> Mat4 {
+        let (scale, rotation, translation) = model_matrix.to_scale_rotation_translation();
+        let point = self
+            .terrain
+            .snap(Vec2::new(translation.x, translation.z), target);
+        let translation = Vec3::new(point.x, self.terrain.height_at(point), point.y);
+        Mat4::from_scale_rotation_translation(scale, rotation, translation)
+    }
+
+    /// Puts the selected prefab down centered on a world space point
+    fn place_prefab(&mut self, center: Vec2) -> Option<(SyncedMap, DirtyRect)> {
+        let name = self.editor_state.prefabs.selected.as_ref()?;
+        let prefab = match prefab::load(name) {
+            Ok(prefab) => prefab,
+            Err(error) => {
+                let message = format!("Couldn't load prefab {}: {}", name, error);
+                self.show_error(message);
+                return None;
+            }
+        };
+
+        let ground = self.terrain.height_at(center);
+        let mut edited = None;
+        if let Some(patch) = &prefab.terrain {
+            if let Some(splat) = &patch.splat {
+                let rect = self.terrain.paste_splat(
+                    center,
+                    patch.world_size,
+                    prefab::PATCH_FEATHER,
+                    |uv| patch.weights_at(splat, uv),
+                );
+                edited = Some((SyncedMap::Splat, rect));
+            }
+            if let Some(heights) = patch.height_field() {
+                let rect = self.terrain.paste_heights(
+                    center,
+                    patch.world_size,
+                    prefab::PATCH_FEATHER,
+                    |uv| heights.sample(uv.x, uv.y) + ground,
+                );
+                edited = Some((SyncedMap::Heights, rect));
+            }
+        }
+
+        let origin = Mat4::from_translation(Vec3::new(center.x, ground, center.y));
+        for object in &prefab.objects {
+            match Model::load(&object.model) {
+                Ok(model) => {
+                    let mut game_object = GameObject {
+                        pos: Vec3::ZERO,
+                        orientation: Quat::default(),
+                        model,
+                    };
+                    game_object.set_model_matrix(&(origin * object.transform));
+                    self.game_objects.push(game_object);
+                }
+                Err(error) => self.show_error(format!("Couldn't load {}: {}", object.model, error)),
+            }
+        }
+        edited
+    }
+
+    /// Bakes the terrain shape under an object into its mesh
+    fn drape_object(&self, index: usize, offset: f32) {
+        if let Some(object) = self.game_objects.get(index) {
+            object.model.drape(
+                &object.get_model_matrix(),
+                |point| self.terrain.height_at(point),
+                offset,
+            );
+        }
+    }
+
+    /// Flattens the ground under an object so that it sits on it
+    /// Flattens roads, paints roads and water and fills forests, whichever is set up
+    fn apply_osm(&mut self, data: &OsmData) {
+        let settings = &self.editor_state.osm;
+        let terrain_size = self.terrain.size();
+        let resolution = self.terrain.resolution();
+        if settings.flatten_roads && !data.roads.is_empty() {
+            let (pixels, size) = self.terrain.get_heightmap_pixels();
+            let heights = HeightField::from_u16(size, &pixels);
+            let flattened = osm::flatten_roads(&heights, &data.roads, terrain_size);
+            self.terrain.import_heights(&flattened);
+        }
+        if let Some(layer) = settings.road_layer {
+            let roads = osm::coverage(&data.roads, &[], resolution, terrain_size);
+            self.terrain.cover_with_layer(layer, &roads);
+        }
+        if let Some(layer) = settings.water_layer {
+            let water = osm::coverage(&data.waterways, &data.water, resolution, terrain_size);
+            self.terrain.cover_with_layer(layer, &water);
+        }
+        let types = &self.editor_state.scatter.types;
+        if let Some(index) = settings.forest_type.filter(|&i| i < types.len()) {
+            let forests = osm::coverage(&[], &data.forests, resolution, terrain_size);
+            let min = Vec2::new(self.terrain.aabb.min.x, self.terrain.aabb.min.z);
+            let keep = |position: Vec2, _| {
+                let uv = (position - min) / terrain_size;
+                forests.sample(uv.x, uv.y) > 0.5
+            };
+            let scatter_type = &types[index];
+            self.scatter_layers[index].fill(
+                &self.terrain,
+                scatter_type,
+                scatter_type.density,
+                keep,
+            );
+        }
+        self.edited();
+    }
+
+    fn fit_terrain_to_object(&mut self, index: usize, settings: &FitSettings) {
+        if let Some(object) = self.game_objects.get(index) {
+            let (min, max) = object.model.bounds();
+            let footprint = Footprint::new(&object.get_model_matrix(), min, max);
+            self.terrain.fit_to_footprint(&footprint, settings);
+        }
+    }
+
+    fn restore_object_shape(&self, index: usize) {
+        if let Some(object) = self.game_objects.get(index) {
+            object.model.restore_shape();
+        }
+    }
+
+    /// Paints the layer of the texture brush onto the objects under it, or takes the
+    /// paint off them when it subtracts
+    fn paint_objects(&mut self, stroke: &Stroke, settings: &PaintSettings) {
+        let color = Vec3::from(self.terrain.layers[settings.layer].color);
+        let rate = settings.strength * stroke.delta_time;
+        let erase = settings.blend_mode == PaintBlendMode::Subtract;
+        let terrain = &self.terrain;
+        for object in &mut self.game_objects {
+            let transform = object.get_model_matrix();
+            object.model.paint(&transform, |position, paint| {
+                let amount = terrain.brush_weight(stroke, position.xz()) * rate;
+                if amount <= 0.0 {
+                    return;
+                }
+                if erase {
+                    prop_paint::erase(paint, amount);
+                } else {
+                    prop_paint::paint(paint, color, settings.opacity(), amount);
+                }
+            });
+        }
+    }
+
+    /// Gives the bottom of an object the color of the ground it stands on
+    fn blend_object_into_ground(&mut self, index: usize, blend: &GroundBlend) {
+        let terrain = &self.terrain;
+        if let Some(object) = self.game_objects.get_mut(index) {
+            let transform = object.get_model_matrix();
+            object.model.paint(&transform, |position, paint| {
+                let ground = terrain.height_at(position.xz());
+                let amount = blend.amount(position.y - ground);
+                if amount > 0.0 {
+                    let color = terrain.ground_color_at(position.xz());
+                    prop_paint::paint(paint, color, 1.0, amount);
+                }
+            });
+        }
+    }
+
+    fn clear_object_paint(&mut self, index: usize) {
+        if let Some(object) = self.game_objects.get_mut(index) {
+            object.model.clear_paint();
+        }
+    }
+
+    fn record(&mut self, entry: Entry) {
+        self.edited();
+        if self.editor_state.record_journal {
+            self.journal.push(entry);
+        }
+    }
+
+    /// Rebuilds the terrain the project starts from and runs the journal on it
+    fn replay_journal(&mut self) -> Result<()> {
+        self.reload_terrain()?;
+        // Some entries need the whole game, not just the terrain
+        let entries = self.journal.entries().to_vec();
+        for entry in &entries {
+            match entry {
+                Entry::Sculpt { stroke, raise } => {
+                    self.terrain.shape_terrain(stroke, *raise);
+                }
+                Entry::CarveDetail { stroke, raise } => {
+                    self.terrain.carve_detail(stroke, *raise);
+                }
+                Entry::SmoothAlong {
+                    stroke,
+                    direction,
+                    settings,
+                } => {
+                    self.terrain.smooth_along(stroke, *direction, settings);
+                }
+                Entry::ErodeUnderBrush {
+                    stroke,
+                    settings,
+                    splat,
+                    seed,
+                } => {
+                    self.terrain
+                        .erode_under_brush(stroke, settings, splat, *seed);
+                }
+                Entry::Paint { stroke, settings } => {
+                    self.terrain.paint_textures(stroke, settings);
+                }
+                Entry::Tint {
+                    stroke,
+                    settings,
+                    erase,
+                } => self.terrain.paint_tint(stroke, settings, *erase),
+                Entry::Erode { settings, splat } => self.terrain.erode(settings, splat),
+                Entry::Filter(filter) => self.terrain.apply_filter(filter),
+                Entry::Weather(settings) => self.terrain.weather(settings),
+                Entry::MoveObject { index, transform } => {
+                    if let Some(object) = self.game_objects.get_mut(*index) {
+                        object.set_model_matrix(transform);
+                    }
+                }
+                Entry::DrapeObject { index, offset } => self.drape_object(*index, *offset),
+                Entry::RestoreObjectShape { index } => self.restore_object_shape(*index),
+                Entry::FitTerrain { index, settings } => {
+                    self.fit_terrain_to_object(*index, settings);
+                }
+                Entry::PaintObjects { stroke, settings } => self.paint_objects(stroke, settings),
+                Entry::BlendObjectIntoGround { index, blend } => {
+                    self.blend_object_into_ground(*index, blend);
+                }
+                Entry::ClearObjectPaint { index } => self.clear_object_paint(*index),
+            }
+        }
+        self.terrain.sync_dirty_region();
+        Ok(())
+    }
+
+    fn reload_terrain(&mut self) -> Result<()> {
+        self.terrain = Terrain::new(
+            Vec2::ZERO,
+            self.config.terrain,
+            self.config.start_with_flat_terrain,
+            &self.config.heightmap_path,
+        )?;
+        self.on_terrain_replaced();
+        Ok(())
+    }
+
+    /// Everything that depends on the terrain size
+    fn on_terrain_replaced(&mut self) {
+        self.camera_transforms.sun_vp = self.terrain.sun_view_projection();
+        // The new terrain comes with a new brush
+        self.restore_brush_size();
+        self.viewports.set_world_extent(self.terrain.size());
+        self.input.camera_moved = true;
+        // Placed for the old terrain
+        self.cliff_rocks = None;
+        self.import_voids = None;
+        self.import_flow = None;
+        for layer in &mut self.scatter_layers {
+            layer.clear();
+        }
+    }
+
+    fn save_terrain(&mut self) {
+        self.terrain.sync_dirty_region();
+        let (pixels, size) = self.terrain.get_heightmap_pixels();
+        let image = ImageBuffer::from_raw(size as u32, size as u32, pixels)
+            .expect("Heightmap buffer has the wrong size");
+        self.io.submit(IoJob::SaveHeightmap {
+            image,
+            path: PathBuf::from(&self.config.heightmap_path),
+            georeference: self.config.georeference.clone(),
+        });
+        self.changes.save_started();
+    }
+
+    /// One action failing doesn't stop the others
+    fn process_gui_actions(&mut self, actions: Vec<Action>) {
+        // Whatever they do, they should see the last strokes
+        if !actions.is_empty() {
+            self.terrain.sync_dirty_region();
+        }
+        for action in actions {
+            if let Err(error) = self.process_gui_action(action) {
+                self.show_error(error.to_string());
+            }
+        }
+    }
+
+    fn process_gui_action(&mut self, action: Action) -> Result<()> {
+        match action {
+            Action::DetachPanel(panel) => {
+                if !self.panels_to_detach.contains(&panel) {
+                    self.panels_to_detach.push(panel);
+                }
+            }
+            Action::AttachPanel(panel) => self.attach_panel(panel)?,
+            Action::ImportHeightmap(path, fill) => {
+                self.io.submit(IoJob::ImportHeightmap { path, fill });
+            }
+            Action::ToggleSnap => {
+                let snap = &mut self.editor_state.snap;
+                snap.enabled = !snap.enabled;
+                self.input.camera_moved = true; // re-snap the cursor
+            }
+            Action::StartRecordingInput(path) => self.start_recording(path.into()),
+            Action::StopRecordingInput => self.stop_recording(),
+            Action::PlayInput(path) => {
+                if let Err(error) = self.start_playback(Path::new(&path), false) {
+                    self.show_error(format!("Couldn't play back {}: {}", path, error));
+                }
+            }
+            Action::StopPlayingInput => self.playback = None,
+            Action::ShowTutorial => self.gui.start_tutorial(),
+            Action::FinishTutorial => {
+                self.config.tutorial_seen = true;
+                self.config.save();
+            }
+            Action::SetUiScale(ui_scale) => {
+                self.config.ui_scale = ui_scale;
+                self.config.save();
+                self.apply_ui_scale();
+            }
+            Action::SetSessionStats(enabled) => {
+                self.config.session_stats = enabled;
+                self.config.save();
+            }
+            Action::SaveTerrain => self.save_terrain(),
+            Action::SaveNormalMap => {
+                let (pixels, size) = self.terrain.get_normal_map_pixels();
+                let image = ImageBuffer::from_raw(size as u32, size as u32, pixels)
+                    .expect("Normal map buffer has the wrong size");
+                // BC5 keeps the two horizontal axes, engines work out the up one
+                let image = DynamicImage::ImageRgb8(image);
+                self.save_map(image, "normals", BlockFormat::Bc5, SavePurpose::NormalMap);
+            }
+            Action::SaveSplatMap => {
+                let (pixels, size) = self.terrain.get_splat_pixels();
+                let image = ImageBuffer::from_raw(size as u32, size as u32, pixels)
+                    .expect("Splat map buffer has the wrong size");
+                let image = DynamicImage::ImageRgba8(image);
+                self.save_map(image, "splat", BlockFormat::Bc3, SavePurpose::SplatMap);
+            }
+            Action::SaveAmbientOcclusionMap => {
+                let (pixels, size) = self.terrain.get_ambient_occlusion_pixels();
+                let image = ImageBuffer::from_raw(size as u32, size as u32, pixels)
+                    .expect("AO map buffer has the wrong size");
+                let image = DynamicImage::ImageLuma8(image);
+                let purpose = SavePurpose::AmbientOcclusionMap;
+                self.save_map(image, "ao", BlockFormat::Bc4, purpose);
+            }
+            Action::LoadGroundTexture(path) => {
+                self.io.submit(IoJob::LoadGroundTexture {
+                    path,
+                    transcode_to: basis::target_format(),
+                });
+            }
+            Action::SaveHypsometricMap => {
+                let gradient = &self.editor_state.hypsometric.gradient;
+                let (pixels, size) = self.terrain.get_hypsometric_pixels(gradient);
+                let image = ImageBuffer::from_raw(size as u32, size as u32, pixels)
+                    .expect("Hypsometric map buffer has the wrong size");
+                let path = Path::new(&self.config.heightmap_path);
+                let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+                self.io.submit(IoJob::SaveImage {
+                    image: DynamicImage::ImageRgb8(image),
+                    path: path.with_file_name(format!("{}_hypsometric.png", stem)),
+                    flip_vertical: false,
+                    purpose: SavePurpose::HypsometricMap,
+                });
+            }
+            Action::SaveCamera => {
+                let camera = self.viewports.main_camera();
+                self.config.camera_position = Some(camera.position);
+                self.config.camera_direction = Some(camera.direction);
+                self.config.save();
+            }
+            Action::LoadLut(path) => {
+                self.io.submit(IoJob::LoadLut { path });
+            }
+            Action::NewProject(params) => {
+                self.terrain = Terrain::new(Vec2::ZERO, params, true, &self.config.heightmap_path)?;
+                self.on_terrain_replaced();
+
+                self.config.terrain = params;
+                self.config.start_with_flat_terrain = true;
+                self.config.georeference = None;
+                self.config.save();
+                self.reset_changes();
+            }
+            Action::ResampleTerrain(resolution) => {
+                self.terrain.resample(resolution)?;
+                self.config.terrain.resolution = resolution;
+                self.edited();
+            }
+            Action::BeginProxy(resolution) => {
+                self.terrain.begin_proxy(resolution as usize)?;
+            }
+            Action::EndProxy => {
+                self.terrain.end_proxy();
+            }
+            Action::BakeDetail => {
+                self.terrain.bake_detail();
+                self.edited();
+            }
+            Action::ClearDetail => {
+                self.terrain.clear_detail();
+            }
+            Action::ChangeBounds(change) => {
+                let world_size = self.terrain.size();
+                self.terrain.change_bounds(&change)?;
+                if let Some(georeference) = &mut self.config.georeference {
+                    georeference.extent = georeference.extent.changed(&change, world_size);
+                }
+                self.on_terrain_replaced();
+                self.config.terrain = self.terrain.params();
+                self.edited();
+            }
+            Action::Erode(settings) => {
+                let splat = self.editor_state.erosion_splat;
+                self.terrain.erode(&settings, &splat);
+                self.record(Entry::Erode { settings, splat });
+            }
+            Action::Weather(settings) => {
+                self.terrain.weather(&settings);
+                self.record(Entry::Weather(settings));
+            }
+            Action::ApplyFilter(filter) => {
+                self.terrain.apply_filter(&filter);
+                self.record(Entry::Filter(filter));
+            }
+            Action::PreviewFilter(filter) => {
+                self.terrain.preview_filter(&filter);
+            }
+            Action::EndFilterPreview => {
+                self.terrain.end_filter_preview();
+            }
+            Action::UndoFilter => {
+                if self.terrain.undo_filter() {
+                    if let Some(Entry::Filter(_)) = self.journal.entries().last() {
+                        self.journal.pop();
+                    }
+                    self.edited();
+                    self.session_stats.undone();
+                }
+            }
+            Action::UndoStroke => {
+                // Anything changed by the actions before this one counts as a stroke
+                self.terrain.end_stroke(self.stroke_start);
+                if let Some((start, rect)) = self.terrain.undo_stroke() {
+                    self.journal.remove_since(start, Entry::shapes_heights);
+                    self.stroke_start = self.journal.len();
+                    self.edited();
+                    self.session_stats.undone();
+                    self.update_sync(Some((SyncedMap::Heights, rect)), None);
+                }
+            }
+            Action::LoadReference(path) => {
+                self.io.submit(IoJob::LoadReference { path });
+            }
+            Action::ClearReference => {
+                self.terrain.clear_reference();
+            }
+            Action::LoadDrape(path) => {
+                self.io.submit(IoJob::LoadDrape { path });
+            }
+            Action::ClearDrape => {
+                self.terrain.clear_drape();
+            }
+            Action::DismissImportVoids => {
+                self.import_voids = None;
+            }
+            Action::ImportOsm(path) => {
+                self.io.submit(IoJob::LoadOsm {
+                    path,
+                    georeference: self.config.georeference.clone(),
+                });
+            }
+            Action::ImportBuild(folder, tool) => {
+                self.io.submit(IoJob::ImportBuild { folder, tool });
+            }
+            Action::DismissImportFlow => {
+                self.import_flow = None;
+            }
+            Action::BlendWithReference(amount) => {
+                self.terrain.blend_with_reference(amount);
+                self.edited();
+            }
+            Action::ScatterCliffRocks => {
+                match cliffs::scatter(&self.terrain, &self.editor_state.cliffs) {
+                    Ok(rocks) => self.cliff_rocks = Some(rocks),
+                    Err(error) => self.show_error(format!("Couldn't scatter rocks: {}", error)),
+                }
+            }
+            Action::ClearCliffRocks => {
+                self.cliff_rocks = None;
+            }
+            Action::FindPath => {
+                self.editor_state.paths.find(&self.terrain);
+            }
+            Action::UpdateViewshed => {
+                if let Some(eye) = self.viewshed.as_ref().map(|viewshed| viewshed.eye) {
+                    self.look_from(Vec2::new(eye.x, eye.z));
+                }
+            }
+            Action::ClearViewshed => {
+                self.viewshed = None;
+            }
+            Action::ShowSunHours => self.show_sun_hours(),
+            Action::HideSunHours => {
+                self.sun_hours = None;
+            }
+            Action::ShowFlood => self.show_flood(),
+            Action::HideFlood => {
+                self.flood = None;
+            }
+            Action::FindBuildable => {
+                let settings = &self.editor_state.buildable;
+                self.buildable = Some(self.terrain.buildable_areas(settings));
+                // Shown again even if they were before
+                self.map_in_overlay = None;
+            }
+            Action::HideBuildable => {
+                self.buildable = None;
+            }
+            Action::SaveBuildableMask => {
+                if let Some(buildable) = &self.buildable {
+                    let size = buildable.mask.size() as u32;
+                    let pixels = buildable
+                        .mask
+                        .data()
+                        .iter()
+                        .map(|&value| (value * 255.0) as u8)
+                        .collect();
+                    let image = ImageBuffer::from_raw(size, size, pixels)
+                        .expect("Buildable mask buffer has the wrong size");
+                    let path = Path::new(&self.config.heightmap_path);
+                    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+                    self.io.submit(IoJob::SaveImage {
+                        image: DynamicImage::ImageLuma8(image),
+                        path: path.with_file_name(format!("{}_buildable.png", stem)),
+                        flip_vertical: false,
+                        purpose: SavePurpose::BuildableMask,
+                    });
+                }
+            }
+            Action::AddScatterType => {
+                let settings = &mut self.editor_state.scatter;
+                if let Err(error) = scatter_types::add_type(settings, &mut self.scatter_layers) {
+                    let message = format!("Couldn't load {}: {}", settings.model_path, error);
+                    self.show_error(message);
+                }
+            }
+            Action::RemoveScatterType(index) => {
+                let settings = &mut self.editor_state.scatter;
+                scatter_types::remove_type(settings, &mut self.scatter_layers, index);
+                self.editor_state.biomes.forget_scatter_type(index);
+                self.editor_state.osm.forget_scatter_type(index);
+            }
+            Action::AutoTexture => {
+                self.terrain.auto_texture(&self.editor_state.biomes);
+            }
+            Action::AutoScatter => {
+                let types = &self.editor_state.scatter.types;
+                self.editor_state.biomes.auto_scatter(
+                    &self.terrain,
+                    types,
+                    &mut self.scatter_layers,
+                );
+            }
+            Action::ClearScatterType(index) => {
+                self.scatter_layers[index].clear();
+            }
+            Action::DrapeObject(offset) => {
+                self.drape_object(ACTIVE_GAME_OBJECT, offset);
+                self.record(Entry::DrapeObject {
+                    index: ACTIVE_GAME_OBJECT,
+                    offset,
+                });
+            }
+            Action::FitTerrain(settings) => {
+                self.fit_terrain_to_object(ACTIVE_GAME_OBJECT, &settings);
+                self.record(Entry::FitTerrain {
+                    index: ACTIVE_GAME_OBJECT,
+                    settings,
+                });
+            }
+            Action::RestoreObjectShape => {
+                self.restore_object_shape(ACTIVE_GAME_OBJECT);
+                self.record(Entry::RestoreObjectShape {
+                    index: ACTIVE_GAME_OBJECT,
+                });
+            }
+            Action::BlendObjectIntoGround(blend) => {
+                self.blend_object_into_ground(ACTIVE_GAME_OBJECT, &blend);
+                self.record(Entry::BlendObjectIntoGround {
+                    index: ACTIVE_GAME_OBJECT,
+                    blend,
+                });
+            }
+            Action::ClearObjectPaint => {
+                self.clear_object_paint(ACTIVE_GAME_OBJECT);
+                self.record(Entry::ClearObjectPaint {
+                    index: ACTIVE_GAME_OBJECT,
+                });
+            }
+            Action::ReplayJournal => {
+                self.replay_journal()?;
+            }
+            Action::ClearJournal => {
+                self.journal.clear();
+            }
+            Action::SaveJournal => {
+                if let Err(error) = self.journal.save(Path::new(journal::JOURNAL_PATH)) {
+                    self.show_error(format!("Couldn't save the journal: {}", error));
+                }
+            }
+            Action::LoadJournal => match Journal::load(Path::new(journal::JOURNAL_PATH)) {
+                Ok(journal) => self.journal = journal,
+                Err(error) => self.show_error(format!("Couldn't load the journal: {}", error)),
+            },
+            Action::SelectTool(tool) => {
+                self.editor_mode = EditorMode::Terrain { tool };
+                self.restore_brush_size();
+            }
+            Action::TakeScreenshot => {
+                self.editor_state.screenshot_requested = true;
+            }
+            Action::SetViewportLayout(layout) => {
+                self.viewports.set_layout(layout);
+                self.input.camera_moved = true;
+            }
+            Action::ExportArchive(path) => {
+                self.io.submit(IoJob::PackProject {
+                    config: self.config.clone(),
+                    storage: self.editor_state.storage.clone(),
+                    archive: path,
+                    settings: self.editor_state.pack,
+                });
+            }
+            Action::ImportArchive(path) => {
+                self.io.submit(IoJob::UnpackProject {
+                    storage: self.editor_state.storage.clone(),
+                    archive: path,
+                });
+            }
+            Action::SaveExportHooks => {
+                self.config.export_hooks = self.editor_state.export_hooks.clone();
+                self.config.save();
+            }
+            Action::ClearDerivedCache => {
+                if let Err(error) = derived_cache::clear() {
+                    self.show_error(format!("Couldn't clear the derived data cache: {}", error));
+                }
+            }
+            Action::SaveStorage => {
+                if let Err(error) = self.editor_state.storage.save() {
+                    self.show_error(format!("Couldn't save the storage settings: {}", error));
+                }
+            }
+            Action::HostSession => {
+                // Others get the full resolution heights
+                self.terrain.end_proxy();
+                match SyncSession::host(&self.editor_state.sync_address, &self.terrain) {
+                    Ok(session) => self.sync = Some(session),
+                    Err(error) => self.show_error(format!("Couldn't host a session: {}", error)),
+                }
+            }
+            Action::JoinSession => {
+                self.terrain.end_proxy();
+                match SyncSession::join(&self.editor_state.sync_address, &mut self.terrain) {
+                    Ok(session) => self.sync = Some(session),
+                    Err(error) => self.show_error(format!("Couldn't join the session: {}", error)),
+                }
+            }
+            Action::LeaveSession => {
+                self.sync = None;
+            }
+            Action::Quit => self.request_exit(),
+            Action::SaveAndQuit => {
+                self.exit_after_save = true;
+                self.save_terrain();
+            }
+            Action::DiscardAndQuit => {
+                self.input.should_exit = true;
+            }
         }
         Ok(())
     }
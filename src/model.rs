@@ -1,23 +1,41 @@
 use std::mem::size_of;
+use std::ops::Range;
 
 use gl::types::*;
-use glam::{Mat4, Vec2, Vec3, Vec4};
+use glam::{Mat4, Vec2, Vec3, Vec3Swizzles, Vec4};
 use gltf::accessor::DataType;
 use gltf::accessor::Dimensions;
 use gltf::image::Format;
 use gltf::Document;
 use memoffset::offset_of;
 
+use crate::opengl::objects::{self, Buffer, VertexArray};
+use crate::prop_paint::NO_PAINT;
+use crate::resources::{self, texture_bytes, MemoryHandle, ResourceKind};
 use crate::texture::calculate_mip_levels;
 use crate::utils::size_of_slice;
 use crate::Result;
 
 #[derive(Debug)]
 pub struct Model {
-    pub vao: GLuint,
-    vbo: GLuint,
-    ebo: GLuint,
-    texture_ids: Vec<GLuint>,
+    /// Where the model was loaded from
+    pub path: String,
+    pub vao: VertexArray,
+    vbo: Buffer,
+    _ebo: Buffer,
+    /// Vertices as loaded, the GPU copy may have been reshaped since
+    vertices: Vec<Vertex>,
+    /// Color painted over the textures, one per vertex in its own buffer
+    paint: Vec<[f32; 4]>,
+    paint_vbo: Buffer,
+    /// One per glTF image
+    image_textures: Vec<objects::Texture>,
+    texture_sizes: Vec<(usize, usize)>,
+
+    /// How many of the top mip levels are skipped to save memory
+    downres: u32,
+    _mesh_memory: MemoryHandle,
+    texture_memory: MemoryHandle,
 
     pub drawable_nodes: Vec<DrawableNode>,
     pub materials: Vec<Material>,
@@ -35,6 +53,7 @@ impl Model {
         for (node, transform) in
             NodesWithTransforms::from(&gltf).filter(|(node, _)| node.mesh().is_some())
         {
+            let node_vertex_start = vertices.len();
             let mut primitives = vec![];
             for primitive in node.mesh().unwrap().primitives() {
                 let first_index = indices.len();
@@ -153,25 +172,31 @@ impl Model {
             drawable_nodes.push(DrawableNode {
                 primitives,
                 transform,
+                vertices: node_vertex_start..vertices.len(),
             });
         }
 
         // Send the vertex and index buffers to GPU
-        let mut vao: GLuint = 0;
-        let mut vbo: GLuint = 0;
-        let mut ebo: GLuint = 0;
+        let vao = VertexArray::new(path);
+        let vbo = Buffer::new(&format!("{} vertices", path));
+        let ebo = Buffer::new(&format!("{} indices", path));
+        let paint_vbo = Buffer::new(&format!("{} paint", path));
+        let paint = vec![NO_PAINT; vertices.len()];
         unsafe {
-            gl::CreateVertexArrays(1, &mut vao);
-            gl::CreateBuffers(1, &mut vbo);
-            gl::CreateBuffers(1, &mut ebo);
-
             // Attach buffers to vao
-            gl::VertexArrayVertexBuffer(vao, 0, vbo, 0, size_of::<Vertex>() as i32);
-            gl::VertexArrayElementBuffer(vao, ebo);
+            gl::VertexArrayVertexBuffer(vao.id(), 0, vbo.id(), 0, size_of::<Vertex>() as i32);
+            gl::VertexArrayVertexBuffer(
+                vao.id(),
+                1,
+                paint_vbo.id(),
+                0,
+                size_of::<[f32; 4]>() as i32,
+            );
+            gl::VertexArrayElementBuffer(vao.id(), ebo.id());
 
             // Position
             gl::VertexArrayAttribFormat(
-                vao,
+                vao.id(),
                 0,
                 3,
                 gl::FLOAT,
@@ -181,7 +206,7 @@ impl Model {
 
             // Normal
             gl::VertexArrayAttribFormat(
-                vao,
+                vao.id(),
                 1,
                 3,
                 gl::FLOAT,
@@ -191,7 +216,7 @@ impl Model {
 
             // UV
             gl::VertexArrayAttribFormat(
-                vao,
+                vao.id(),
                 2,
                 2,
                 gl::FLOAT,
@@ -199,25 +224,38 @@ impl Model {
                 offset_of!(Vertex, uv) as u32,
             );
 
-            gl::EnableVertexArrayAttrib(vao, 0);
-            gl::EnableVertexArrayAttrib(vao, 1);
-            gl::EnableVertexArrayAttrib(vao, 2);
+            // Paint
+            gl::VertexArrayAttribFormat(vao.id(), 3, 4, gl::FLOAT, gl::FALSE, 0);
 
-            gl::VertexArrayAttribBinding(vao, 0, 0);
-            gl::VertexArrayAttribBinding(vao, 1, 0);
-            gl::VertexArrayAttribBinding(vao, 2, 0);
+            gl::EnableVertexArrayAttrib(vao.id(), 0);
+            gl::EnableVertexArrayAttrib(vao.id(), 1);
+            gl::EnableVertexArrayAttrib(vao.id(), 2);
+            gl::EnableVertexArrayAttrib(vao.id(), 3);
 
-            // Vertex data
+            gl::VertexArrayAttribBinding(vao.id(), 0, 0);
+            gl::VertexArrayAttribBinding(vao.id(), 1, 0);
+            gl::VertexArrayAttribBinding(vao.id(), 2, 0);
+            gl::VertexArrayAttribBinding(vao.id(), 3, 1);
+
+            // Vertex data, can be reshaped later
             gl::NamedBufferStorage(
-                vbo,
+                vbo.id(),
                 size_of_slice(&vertices) as isize,
                 vertices.as_ptr() as *const _,
-                0,
+                gl::DYNAMIC_STORAGE_BIT,
+            );
+
+            // Nothing painted yet
+            gl::NamedBufferStorage(
+                paint_vbo.id(),
+                size_of_slice(&paint) as isize,
+                paint.as_ptr() as *const _,
+                gl::DYNAMIC_STORAGE_BIT,
             );
 
             // Index data
             gl::NamedBufferStorage(
-                ebo,
+                ebo.id(),
                 size_of_slice(&indices) as isize,
                 indices.as_ptr() as *const _,
                 0,
@@ -225,29 +263,21 @@ impl Model {
         }
 
         // Load textures
-        let num_textures = images.len();
-        let mut texture_ids = Vec::with_capacity(num_textures);
-        unsafe {
-            texture_ids.set_len(num_textures);
-            gl::CreateTextures(
-                gl::TEXTURE_2D,
-                num_textures as i32,
-                texture_ids.as_mut_ptr(),
-            );
-            for &texture in &texture_ids {
+        let image_textures: Vec<objects::Texture> = (0..images.len())
+            .map(|i| {
+                let texture =
+                    objects::Texture::new(gl::TEXTURE_2D, &format!("{} image {}", path, i));
                 // Default sampler parameters
-                gl::TextureParameteri(texture, gl::TEXTURE_WRAP_S, gl::REPEAT as GLint);
-                gl::TextureParameteri(texture, gl::TEXTURE_WRAP_T, gl::REPEAT as GLint);
-                gl::TextureParameteri(
-                    texture,
-                    gl::TEXTURE_MIN_FILTER,
-                    gl::LINEAR_MIPMAP_LINEAR as GLint,
-                );
-                gl::TextureParameteri(texture, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
-            }
-        }
-        for (image, texture_id) in images.into_iter().zip(&texture_ids) {
-            let texture = *texture_id;
+                texture.set_wrap_and_filter(gl::REPEAT, gl::LINEAR_MIPMAP_LINEAR, gl::LINEAR);
+                texture
+            })
+            .collect();
+        let texture_sizes: Vec<(usize, usize)> = images
+            .iter()
+            .map(|image| (image.width as usize, image.height as usize))
+            .collect();
+        for (image, texture) in images.into_iter().zip(&image_textures) {
+            let texture = texture.id();
             unsafe {
                 gl::TextureStorage2D(
                     texture,
@@ -287,7 +317,7 @@ impl Model {
         // Fill in textures
         let textures = gltf
             .textures()
-            .map(|texture| texture_ids[texture.source().index()])
+            .map(|texture| image_textures[texture.source().index()].id())
             .collect::<Vec<_>>();
 
         // Load materials
@@ -303,11 +333,27 @@ impl Model {
             })
             .collect::<Vec<_>>();
 
+        let mesh_memory = resources::track(
+            ResourceKind::Mesh,
+            size_of_slice(&vertices) + size_of_slice(&paint) + size_of_slice(&indices),
+        );
+        let texture_memory =
+            resources::track(ResourceKind::Texture, textures_bytes(&texture_sizes, 0));
+
         Ok(Model {
+            path: path.to_owned(),
             vao,
             vbo,
-            ebo,
-            texture_ids,
+            _ebo: ebo,
+            vertices,
+            paint,
+            paint_vbo,
+            image_textures,
+            texture_sizes,
+
+            downres: 0,
+            _mesh_memory: mesh_memory,
+            texture_memory,
 
             drawable_nodes,
             materials,
@@ -315,19 +361,136 @@ impl Model {
     }
 }
 
-impl Drop for Model {
-    fn drop(&mut self) {
+impl Model {
+    pub fn downres(&self) -> u32 {
+        self.downres
+    }
+
+    /// Skips the top `levels` mips of every texture. GL may or may not free the memory,
+    /// but the textures are at least no longer sampled at full resolution.
+    pub fn set_downres(&mut self, levels: u32) {
+        if levels == self.downres {
+            return;
+        }
+        self.downres = levels;
+        for (texture, &(width, height)) in self.image_textures.iter().zip(&self.texture_sizes) {
+            let max_level = calculate_mip_levels(width, height) - 1;
+            let base_level = (levels as i32).min(max_level);
+            unsafe {
+                gl::TextureParameteri(texture.id(), gl::TEXTURE_BASE_LEVEL, base_level);
+            }
+        }
+        self.texture_memory
+            .resize(textures_bytes(&self.texture_sizes, levels));
+    }
+
+    /// Moves every vertex vertically so that the model follows `surface`, a world space
+    /// height function. Vertices keep their height above the lowest point of the model,
+    /// plus `offset`. Always starts from the shape as loaded, so it can be repeated
+    /// after moving the model. Normals are left as they are.
+    pub fn drape(&self, transform: &Mat4, surface: impl Fn(Vec2) -> f32, offset: f32) {
+        let mut vertices = self.vertices.clone();
+        let mut bottom = f32::MAX;
+        for node in &self.drawable_nodes {
+            let to_world = *transform * node.transform;
+            for vertex in &mut vertices[node.vertices.clone()] {
+                vertex.pos = to_world.transform_point3(vertex.pos);
+                bottom = bottom.min(vertex.pos.y);
+            }
+        }
+        for node in &self.drawable_nodes {
+            let to_local = (*transform * node.transform).inverse();
+            for vertex in &mut vertices[node.vertices.clone()] {
+                let pos = vertex.pos;
+                let height = surface(pos.xz()) + offset + (pos.y - bottom);
+                vertex.pos = to_local.transform_point3(Vec3::new(pos.x, height, pos.z));
+            }
+        }
+        self.upload_vertices(&vertices);
+    }
+
+    /// Corners of the box around the model as loaded, in model space
+    pub fn bounds(&self) -> (Vec3, Vec3) {
+        let mut min = Vec3::splat(f32::MAX);
+        let mut max = Vec3::splat(f32::MIN);
+        for node in &self.drawable_nodes {
+            for vertex in &self.vertices[node.vertices.clone()] {
+                let pos = node.transform.transform_point3(vertex.pos);
+                min = min.min(pos);
+                max = max.max(pos);
+            }
+        }
+        (min, max)
+    }
+
+    /// Undoes `drape`
+    pub fn restore_shape(&self) {
+        self.upload_vertices(&self.vertices);
+    }
+
+    /// Lets `paint` change the paint of every vertex, given where the vertex is in world
+    /// space. Positions are the ones loaded, a draped model is painted as if it wasn't.
+    pub fn paint(&mut self, transform: &Mat4, mut paint: impl FnMut(Vec3, &mut [f32; 4])) {
+        for node in &self.drawable_nodes {
+            let to_world = *transform * node.transform;
+            for i in node.vertices.clone() {
+                paint(
+                    to_world.transform_point3(self.vertices[i].pos),
+                    &mut self.paint[i],
+                );
+            }
+        }
+        self.upload_paint();
+    }
+
+    pub fn clear_paint(&mut self) {
+        self.paint.fill(NO_PAINT);
+        self.upload_paint();
+    }
+
+    fn upload_paint(&self) {
         unsafe {
-            gl::DeleteBuffers(1, &self.vbo);
-            gl::DeleteBuffers(1, &self.ebo);
-            gl::DeleteVertexArrays(1, &self.vao);
-            gl::DeleteTextures(self.texture_ids.len() as i32, self.texture_ids.as_ptr());
+            gl::NamedBufferSubData(
+                self.paint_vbo.id(),
+                0,
+                size_of_slice(&self.paint) as isize,
+                self.paint.as_ptr() as *const _,
+            );
         }
     }
+
+    fn upload_vertices(&self, vertices: &[Vertex]) {
+        unsafe {
+            gl::NamedBufferSubData(
+                self.vbo.id(),
+                0,
+                size_of_slice(vertices) as isize,
+                vertices.as_ptr() as *const _,
+            );
+        }
+    }
+
+    /// How much memory going down to `levels` would save
+    pub fn downres_savings(&self, levels: u32) -> isize {
+        textures_bytes(&self.texture_sizes, self.downres) as isize
+            - textures_bytes(&self.texture_sizes, levels) as isize
+    }
+}
+
+fn textures_bytes(sizes: &[(usize, usize)], skipped_levels: u32) -> usize {
+    sizes
+        .iter()
+        .map(|&(width, height)| {
+            let levels = calculate_mip_levels(width, height);
+            let skipped = (skipped_levels as i32).min(levels - 1);
+            let (width, height) = ((width >> skipped).max(1), (height >> skipped).max(1));
+            texture_bytes(gl::SRGB8, width, height, levels - skipped)
+        })
+        .sum()
 }
 
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct Vertex {
     pos: Vec3,
     normal: Vec3,
@@ -356,6 +519,8 @@ pub struct Primitive {
 pub struct DrawableNode {
     pub primitives: Vec<Primitive>,
     pub transform: Mat4,
+    /// Where the vertices of all the primitives are in the vertex buffer
+    pub vertices: Range<usize>,
 }
 
 impl DrawableNode {
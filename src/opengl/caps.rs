@@ -0,0 +1,121 @@
+//! What the context can do. The editor is written against GL 4.5, but it asks for
+//! older contexts when that fails (macOS stops at 4.1) and makes do: `fallback`
+//! stands in for direct state access, shaders are rewritten for the older GLSL and
+//! anything that needs more than the context has is drawn a simpler way.
+
+use std::cell::Cell;
+use std::collections::HashSet;
+use std::ffi::CStr;
+
+use gl::types::*;
+
+use super::fallback;
+
+/// Asked for in turn until one works
+pub const VERSIONS: [(u8, u8); 3] = [(4, 5), (4, 1), (3, 3)];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Capabilities {
+    pub version: (u8, u8),
+    /// `glCreate*` and the functions that take object names instead of binding them
+    pub direct_state_access: bool,
+    /// Immutable texture storage with `glTexStorage*`
+    pub texture_storage: bool,
+    /// BC7 textures, which Basis Universal ones are transcoded to when it's there
+    pub texture_bptc: bool,
+    pub buffer_storage: bool,
+    /// Without it the terrain is drawn as a fixed grid
+    pub tessellation: bool,
+    /// Without them instance transforms are read from a buffer texture
+    pub storage_buffers: bool,
+    /// `layout(binding = N)` in shaders, set after linking when missing
+    pub binding_qualifiers: bool,
+    pub debug_output: bool,
+    /// Linked programs can be cached between runs
+    pub program_binary: bool,
+    /// `glGetGraphicsResetStatus`, which notices a driver reset before the next
+    /// swap fails
+    pub reset_status: bool,
+    /// Not used for drawing, so the fallbacks can bind textures on it freely
+    pub scratch_texture_unit: u32,
+}
+
+thread_local! {
+    static CAPABILITIES: Cell<Option<Capabilities>> = Cell::new(None);
+}
+
+impl Capabilities {
+    /// Asks the current context, the GL functions have to be loaded already
+    fn detect() -> Self {
+        let (mut major, mut minor, mut extension_count, mut texture_units) = (0, 0, 0, 0);
+        unsafe {
+            gl::GetIntegerv(gl::MAJOR_VERSION, &mut major);
+            gl::GetIntegerv(gl::MINOR_VERSION, &mut minor);
+            gl::GetIntegerv(gl::NUM_EXTENSIONS, &mut extension_count);
+            gl::GetIntegerv(gl::MAX_COMBINED_TEXTURE_IMAGE_UNITS, &mut texture_units);
+        }
+        let extensions: HashSet<String> = (0..extension_count as GLuint)
+            .map(|i| unsafe {
+                let name = gl::GetStringi(gl::EXTENSIONS, i);
+                CStr::from_ptr(name as *const _)
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect();
+        let version = (major as u8, minor as u8);
+        let has =
+            |since: (u8, u8), extension: &str| version >= since || extensions.contains(extension);
+        Capabilities {
+            version,
+            direct_state_access: version >= (4, 5),
+            texture_storage: has((4, 2), "GL_ARB_texture_storage"),
+            texture_bptc: has((4, 2), "GL_ARB_texture_compression_bptc"),
+            buffer_storage: has((4, 4), "GL_ARB_buffer_storage"),
+            tessellation: version >= (4, 0),
+            storage_buffers: version >= (4, 3),
+            binding_qualifiers: version >= (4, 2),
+            debug_output: has((4, 3), "GL_KHR_debug"),
+            program_binary: has((4, 1), "GL_ARB_get_program_binary"),
+            reset_status: has((4, 5), "GL_KHR_robustness"),
+            scratch_texture_unit: texture_units as u32 - 1,
+        }
+    }
+
+    /// The `#version` shaders are compiled with
+    pub fn glsl_version(&self) -> u32 {
+        match self.version {
+            (3, _) => 330,
+            (major, minor) => major as u32 * 100 + minor as u32 * 10,
+        }
+    }
+
+    /// What doesn't work as well as on 4.5, for telling the user
+    pub fn limitations(&self) -> Vec<&'static str> {
+        let mut limitations = vec![];
+        if !self.tessellation {
+            limitations.push("the terrain has a fixed level of detail");
+        }
+        if !self.storage_buffers {
+            limitations.push("scattered instances are slower to draw");
+        }
+        if !self.program_binary {
+            limitations.push("shaders are compiled on every start");
+        }
+        limitations
+    }
+}
+
+/// Detects what the current context can do and fills in for what it can't.
+/// Called right after the GL functions are loaded, again if the context is made anew.
+pub fn init() -> Capabilities {
+    let capabilities = Capabilities::detect();
+    CAPABILITIES.with(|cell| cell.set(Some(capabilities)));
+    fallback::install(&capabilities);
+    capabilities
+}
+
+pub fn get() -> Capabilities {
+    CAPABILITIES
+        .with(|cell| cell.get())
+        .expect("GL capabilities are detected when the context is created")
+}
@@ -0,0 +1,635 @@
+//! Direct state access on contexts that don't have it. The GL 4.5 functions the
+//! editor calls are pointed at these instead, which bind the object, make the old
+//! call and put the binding back, so none of the calling code has to know.
+//!
+//! Textures are bound on a unit nothing draws with, buffers on the copy target and
+//! framebuffers and vertex arrays only for as long as the call takes.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::c_void;
+use std::ptr;
+
+use gl::types::*;
+
+use super::caps::{self, Capabilities};
+
+const CUBE_FACES: [GLenum; 6] = [
+    gl::TEXTURE_CUBE_MAP_POSITIVE_X,
+    gl::TEXTURE_CUBE_MAP_NEGATIVE_X,
+    gl::TEXTURE_CUBE_MAP_POSITIVE_Y,
+    gl::TEXTURE_CUBE_MAP_NEGATIVE_Y,
+    gl::TEXTURE_CUBE_MAP_POSITIVE_Z,
+    gl::TEXTURE_CUBE_MAP_NEGATIVE_Z,
+];
+
+/// What a vertex array has been told, it's all specified again with
+/// `glVertexAttribPointer` whenever any of it changes
+#[derive(Default)]
+struct VertexArrayState {
+    /// Size, type, normalized and relative offset by attribute
+    formats: HashMap<GLuint, (GLint, GLenum, GLboolean, GLuint)>,
+    /// Binding by attribute, the same index unless set
+    bindings: HashMap<GLuint, GLuint>,
+    /// Buffer, offset and stride by binding
+    buffers: HashMap<GLuint, (GLuint, GLintptr, GLsizei)>,
+}
+
+thread_local! {
+    /// Old style texture calls need the target, which is only known at creation
+    static TEXTURE_TARGETS: RefCell<HashMap<GLuint, GLenum>> = RefCell::new(HashMap::new());
+    // Vertex arrays aren't shared between contexts, so a name can be in use in the
+    // main window and a detached one. Those only make GUI vertex arrays, which are
+    // set up just like the main window's, so the state isn't kept apart.
+    static VERTEX_ARRAYS: RefCell<HashMap<GLuint, VertexArrayState>> =
+        RefCell::new(HashMap::new());
+}
+
+/// Points the functions the context is missing at the fallbacks
+pub fn install(capabilities: &Capabilities) {
+    macro_rules! replace {
+        ($($function:ident => $fallback:expr),* $(,)?) => {
+            $(gl::$function::load_with(|_| $fallback as *const c_void);)*
+        };
+    }
+    if !capabilities.direct_state_access {
+        replace!(
+            CreateTextures => create_textures,
+            CreateBuffers => create_buffers,
+            CreateVertexArrays => create_vertex_arrays,
+            CreateFramebuffers => create_framebuffers,
+            CreateQueries => create_queries,
+            TextureStorage2D => texture_storage_2d,
+            TextureStorage3D => texture_storage_3d,
+            TextureSubImage2D => texture_sub_image_2d,
+            TextureSubImage3D => texture_sub_image_3d,
+            CompressedTextureSubImage2D => compressed_texture_sub_image_2d,
+            TextureParameteri => texture_parameter_i,
+            TextureParameterf => texture_parameter_f,
+            TextureBuffer => texture_buffer,
+            GenerateTextureMipmap => generate_texture_mipmap,
+            GetTextureImage => get_texture_image,
+            GetTextureSubImage => get_texture_sub_image,
+            NamedBufferStorage => named_buffer_storage,
+            NamedBufferSubData => named_buffer_sub_data,
+            VertexArrayVertexBuffer => vertex_array_vertex_buffer,
+            VertexArrayElementBuffer => vertex_array_element_buffer,
+            VertexArrayAttribFormat => vertex_array_attrib_format,
+            VertexArrayAttribBinding => vertex_array_attrib_binding,
+            EnableVertexArrayAttrib => enable_vertex_array_attrib,
+            NamedFramebufferTexture => named_framebuffer_texture,
+            NamedFramebufferTextureLayer => named_framebuffer_texture_layer,
+            NamedFramebufferDrawBuffer => named_framebuffer_draw_buffer,
+            NamedFramebufferDrawBuffers => named_framebuffer_draw_buffers,
+            NamedFramebufferReadBuffer => named_framebuffer_read_buffer,
+            CheckNamedFramebufferStatus => check_named_framebuffer_status,
+        );
+    }
+    // Nothing to fall back to, these just do nothing
+    if !capabilities.debug_output {
+        replace!(ObjectLabel => object_label);
+    }
+    if capabilities.version < (4, 2) {
+        replace!(MemoryBarrier => memory_barrier);
+    }
+    if !capabilities.program_binary {
+        replace!(ProgramParameteri => program_parameter_i);
+    }
+}
+
+/// Names from a lost context mean nothing in the next one
+pub fn forget() {
+    TEXTURE_TARGETS.with(|targets| targets.borrow_mut().clear());
+    VERTEX_ARRAYS.with(|vertex_arrays| vertex_arrays.borrow_mut().clear());
+}
+
+fn texture_target(texture: GLuint) -> GLenum {
+    TEXTURE_TARGETS.with(|targets| {
+        targets
+            .borrow()
+            .get(&texture)
+            .copied()
+            .unwrap_or(gl::TEXTURE_2D)
+    })
+}
+
+/// Runs `f` with `texture` bound on the scratch unit
+unsafe fn with_texture(texture: GLuint, f: impl FnOnce(GLenum)) {
+    let target = texture_target(texture);
+    let mut active = 0;
+    gl::GetIntegerv(gl::ACTIVE_TEXTURE, &mut active);
+    gl::ActiveTexture(gl::TEXTURE0 + caps::get().scratch_texture_unit);
+    gl::BindTexture(target, texture);
+    f(target);
+    gl::ActiveTexture(active as GLenum);
+}
+
+unsafe fn with_buffer(buffer: GLuint, f: impl FnOnce()) {
+    let mut previous = 0;
+    gl::GetIntegerv(gl::COPY_WRITE_BUFFER_BINDING, &mut previous);
+    gl::BindBuffer(gl::COPY_WRITE_BUFFER, buffer);
+    f();
+    gl::BindBuffer(gl::COPY_WRITE_BUFFER, previous as GLuint);
+}
+
+unsafe fn with_framebuffer<T>(framebuffer: GLuint, f: impl FnOnce() -> T) -> T {
+    let (mut draw, mut read) = (0, 0);
+    gl::GetIntegerv(gl::DRAW_FRAMEBUFFER_BINDING, &mut draw);
+    gl::GetIntegerv(gl::READ_FRAMEBUFFER_BINDING, &mut read);
+    gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer);
+    let result = f();
+    gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, draw as GLuint);
+    gl::BindFramebuffer(gl::READ_FRAMEBUFFER, read as GLuint);
+    result
+}
+
+unsafe fn with_vertex_array(vertex_array: GLuint, f: impl FnOnce()) {
+    let mut previous = 0;
+    gl::GetIntegerv(gl::VERTEX_ARRAY_BINDING, &mut previous);
+    gl::BindVertexArray(vertex_array);
+    f();
+    gl::BindVertexArray(previous as GLuint);
+}
+
+/// Format and type that go with an internal format, for allocating storage the
+/// old way without any data
+fn pixel_format(internal_format: GLenum) -> (GLenum, GLenum) {
+    match internal_format {
+        gl::R8 => (gl::RED, gl::UNSIGNED_BYTE),
+        gl::R16 => (gl::RED, gl::UNSIGNED_SHORT),
+        gl::R16F | gl::R32F => (gl::RED, gl::FLOAT),
+        gl::RGB8 | gl::SRGB8 => (gl::RGB, gl::UNSIGNED_BYTE),
+        gl::RGB16F | gl::RGB32F => (gl::RGB, gl::FLOAT),
+        gl::RGBA16F | gl::RGBA32F => (gl::RGBA, gl::FLOAT),
+        gl::DEPTH_COMPONENT16 | gl::DEPTH_COMPONENT24 | gl::DEPTH_COMPONENT32F => {
+            (gl::DEPTH_COMPONENT, gl::FLOAT)
+        }
+        _ => (gl::RGBA, gl::UNSIGNED_BYTE),
+    }
+}
+
+/// Bytes of a 4x4 block of the compressed formats `ground_texture` uploads
+fn block_bytes(internal_format: GLenum) -> Option<GLsizei> {
+    match internal_format {
+        gl::COMPRESSED_RGB_S3TC_DXT1_EXT
+        | gl::COMPRESSED_SRGB_S3TC_DXT1_EXT
+        | gl::COMPRESSED_RED_RGTC1 => Some(8),
+        gl::COMPRESSED_RGBA_S3TC_DXT5_EXT
+        | gl::COMPRESSED_SRGB_ALPHA_S3TC_DXT5_EXT
+        | gl::COMPRESSED_RG_RGTC2
+        | gl::COMPRESSED_RGBA_BPTC_UNORM
+        | gl::COMPRESSED_SRGB_ALPHA_BPTC_UNORM => Some(16),
+        _ => None,
+    }
+}
+
+extern "system" fn create_textures(target: GLenum, n: GLsizei, textures: *mut GLuint) {
+    unsafe {
+        gl::GenTextures(n, textures);
+        for &texture in std::slice::from_raw_parts(textures, n as usize) {
+            TEXTURE_TARGETS.with(|targets| targets.borrow_mut().insert(texture, target));
+            // The name is only a texture once it's been bound
+            with_texture(texture, |_| {});
+        }
+    }
+}
+
+extern "system" fn create_buffers(n: GLsizei, buffers: *mut GLuint) {
+    unsafe {
+        gl::GenBuffers(n, buffers);
+        for &buffer in std::slice::from_raw_parts(buffers, n as usize) {
+            with_buffer(buffer, || {});
+        }
+    }
+}
+
+extern "system" fn create_vertex_arrays(n: GLsizei, arrays: *mut GLuint) {
+    unsafe {
+        gl::GenVertexArrays(n, arrays);
+        for &array in std::slice::from_raw_parts(arrays, n as usize) {
+            with_vertex_array(array, || {});
+        }
+    }
+}
+
+extern "system" fn create_framebuffers(n: GLsizei, framebuffers: *mut GLuint) {
+    unsafe {
+        gl::GenFramebuffers(n, framebuffers);
+        for &framebuffer in std::slice::from_raw_parts(framebuffers, n as usize) {
+            with_framebuffer(framebuffer, || {});
+        }
+    }
+}
+
+/// Queries only become objects once they're used, which is soon enough
+extern "system" fn create_queries(_target: GLenum, n: GLsizei, ids: *mut GLuint) {
+    unsafe {
+        gl::GenQueries(n, ids);
+    }
+}
+
+extern "system" fn texture_storage_2d(
+    texture: GLuint,
+    levels: GLsizei,
+    internal_format: GLenum,
+    width: GLsizei,
+    height: GLsizei,
+) {
+    unsafe {
+        with_texture(texture, |target| {
+            if caps::get().texture_storage {
+                gl::TexStorage2D(target, levels, internal_format, width, height);
+                return;
+            }
+            let (format, type_) = pixel_format(internal_format);
+            let faces = if target == gl::TEXTURE_CUBE_MAP {
+                &CUBE_FACES[..]
+            } else {
+                std::slice::from_ref(&target)
+            };
+            for level in 0..levels {
+                let (width, height) = ((width >> level).max(1), (height >> level).max(1));
+                for &face in faces {
+                    // Compressed formats have their own call, which also takes no data
+                    if let Some(block_bytes) = block_bytes(internal_format) {
+                        let blocks = ((width + 3) / 4) * ((height + 3) / 4);
+                        gl::CompressedTexImage2D(
+                            face,
+                            level,
+                            internal_format,
+                            width,
+                            height,
+                            0,
+                            blocks * block_bytes,
+                            ptr::null(),
+                        );
+                        continue;
+                    }
+                    gl::TexImage2D(
+                        face,
+                        level,
+                        internal_format as GLint,
+                        width,
+                        height,
+                        0,
+                        format,
+                        type_,
+                        ptr::null(),
+                    );
+                }
+            }
+            gl::TexParameteri(target, gl::TEXTURE_MAX_LEVEL, levels - 1);
+        });
+    }
+}
+
+extern "system" fn texture_storage_3d(
+    texture: GLuint,
+    levels: GLsizei,
+    internal_format: GLenum,
+    width: GLsizei,
+    height: GLsizei,
+    depth: GLsizei,
+) {
+    unsafe {
+        with_texture(texture, |target| {
+            if caps::get().texture_storage {
+                gl::TexStorage3D(target, levels, internal_format, width, height, depth);
+                return;
+            }
+            let (format, type_) = pixel_format(internal_format);
+            for level in 0..levels {
+                // Array layers don't get fewer with every level
+                let depth = if target == gl::TEXTURE_3D {
+                    (depth >> level).max(1)
+                } else {
+                    depth
+                };
+                gl::TexImage3D(
+                    target,
+                    level,
+                    internal_format as GLint,
+                    (width >> level).max(1),
+                    (height >> level).max(1),
+                    depth,
+                    0,
+                    format,
+                    type_,
+                    ptr::null(),
+                );
+            }
+            gl::TexParameteri(target, gl::TEXTURE_MAX_LEVEL, levels - 1);
+        });
+    }
+}
+
+extern "system" fn texture_sub_image_2d(
+    texture: GLuint,
+    level: GLint,
+    x: GLint,
+    y: GLint,
+    width: GLsizei,
+    height: GLsizei,
+    format: GLenum,
+    type_: GLenum,
+    pixels: *const c_void,
+) {
+    unsafe {
+        with_texture(texture, |target| {
+            gl::TexSubImage2D(target, level, x, y, width, height, format, type_, pixels);
+        });
+    }
+}
+
+extern "system" fn compressed_texture_sub_image_2d(
+    texture: GLuint,
+    level: GLint,
+    x: GLint,
+    y: GLint,
+    width: GLsizei,
+    height: GLsizei,
+    format: GLenum,
+    size: GLsizei,
+    data: *const c_void,
+) {
+    unsafe {
+        with_texture(texture, |target| {
+            gl::CompressedTexSubImage2D(target, level, x, y, width, height, format, size, data);
+        });
+    }
+}
+
+extern "system" fn texture_sub_image_3d(
+    texture: GLuint,
+    level: GLint,
+    x: GLint,
+    y: GLint,
+    z: GLint,
+    width: GLsizei,
+    height: GLsizei,
+    depth: GLsizei,
+    format: GLenum,
+    type_: GLenum,
+    pixels: *const c_void,
+) {
+    unsafe {
+        with_texture(texture, |target| {
+            gl::TexSubImage3D(
+                target, level, x, y, z, width, height, depth, format, type_, pixels,
+            );
+        });
+    }
+}
+
+extern "system" fn texture_parameter_i(texture: GLuint, name: GLenum, value: GLint) {
+    unsafe {
+        with_texture(texture, |target| gl::TexParameteri(target, name, value));
+    }
+}
+
+extern "system" fn texture_parameter_f(texture: GLuint, name: GLenum, value: GLfloat) {
+    unsafe {
+        with_texture(texture, |target| gl::TexParameterf(target, name, value));
+    }
+}
+
+extern "system" fn texture_buffer(texture: GLuint, internal_format: GLenum, buffer: GLuint) {
+    unsafe {
+        with_texture(texture, |target| {
+            gl::TexBuffer(target, internal_format, buffer)
+        });
+    }
+}
+
+extern "system" fn generate_texture_mipmap(texture: GLuint) {
+    unsafe {
+        with_texture(texture, |target| gl::GenerateMipmap(target));
+    }
+}
+
+extern "system" fn get_texture_image(
+    texture: GLuint,
+    level: GLint,
+    format: GLenum,
+    type_: GLenum,
+    _buffer_size: GLsizei,
+    pixels: *mut c_void,
+) {
+    unsafe {
+        with_texture(texture, |target| {
+            gl::GetTexImage(target, level, format, type_, pixels)
+        });
+    }
+}
+
+/// Read through a framebuffer, so only for 2D textures that can be rendered to
+extern "system" fn get_texture_sub_image(
+    texture: GLuint,
+    level: GLint,
+    x: GLint,
+    y: GLint,
+    _z: GLint,
+    width: GLsizei,
+    height: GLsizei,
+    _depth: GLsizei,
+    format: GLenum,
+    type_: GLenum,
+    _buffer_size: GLsizei,
+    pixels: *mut c_void,
+) {
+    unsafe {
+        let mut framebuffer = 0;
+        gl::GenFramebuffers(1, &mut framebuffer);
+        with_framebuffer(framebuffer, || {
+            let target = texture_target(texture);
+            let attachment = gl::COLOR_ATTACHMENT0;
+            gl::FramebufferTexture2D(gl::FRAMEBUFFER, attachment, target, texture, level);
+            gl::ReadBuffer(attachment);
+            gl::ReadPixels(x, y, width, height, format, type_, pixels);
+        });
+        gl::DeleteFramebuffers(1, &framebuffer);
+    }
+}
+
+extern "system" fn named_buffer_storage(
+    buffer: GLuint,
+    size: GLsizeiptr,
+    data: *const c_void,
+    flags: GLbitfield,
+) {
+    unsafe {
+        with_buffer(buffer, || {
+            if caps::get().buffer_storage {
+                gl::BufferStorage(gl::COPY_WRITE_BUFFER, size, data, flags);
+            } else {
+                let usage = if flags & gl::DYNAMIC_STORAGE_BIT != 0 {
+                    gl::DYNAMIC_DRAW
+                } else {
+                    gl::STATIC_DRAW
+                };
+                gl::BufferData(gl::COPY_WRITE_BUFFER, size, data, usage);
+            }
+        });
+    }
+}
+
+extern "system" fn named_buffer_sub_data(
+    buffer: GLuint,
+    offset: GLintptr,
+    size: GLsizeiptr,
+    data: *const c_void,
+) {
+    unsafe {
+        with_buffer(buffer, || {
+            gl::BufferSubData(gl::COPY_WRITE_BUFFER, offset, size, data)
+        });
+    }
+}
+
+/// Specifies every attribute that has a format and a buffer again
+fn update_vertex_array(vertex_array: GLuint, update: impl FnOnce(&mut VertexArrayState)) {
+    VERTEX_ARRAYS.with(|arrays| {
+        let mut arrays = arrays.borrow_mut();
+        let state = arrays.entry(vertex_array).or_default();
+        update(state);
+        unsafe {
+            let mut previous_buffer = 0;
+            gl::GetIntegerv(gl::ARRAY_BUFFER_BINDING, &mut previous_buffer);
+            with_vertex_array(vertex_array, || {
+                for (&attribute, &(size, type_, normalized, relative_offset)) in &state.formats {
+                    let binding = state.bindings.get(&attribute).unwrap_or(&attribute);
+                    if let Some(&(buffer, offset, stride)) = state.buffers.get(binding) {
+                        gl::BindBuffer(gl::ARRAY_BUFFER, buffer);
+                        let pointer = (offset + relative_offset as GLintptr) as *const c_void;
+                        gl::VertexAttribPointer(
+                            attribute, size, type_, normalized, stride, pointer,
+                        );
+                    }
+                }
+            });
+            gl::BindBuffer(gl::ARRAY_BUFFER, previous_buffer as GLuint);
+        }
+    });
+}
+
+extern "system" fn vertex_array_vertex_buffer(
+    vertex_array: GLuint,
+    binding: GLuint,
+    buffer: GLuint,
+    offset: GLintptr,
+    stride: GLsizei,
+) {
+    update_vertex_array(vertex_array, |state| {
+        state.buffers.insert(binding, (buffer, offset, stride));
+    });
+}
+
+extern "system" fn vertex_array_attrib_format(
+    vertex_array: GLuint,
+    attribute: GLuint,
+    size: GLint,
+    type_: GLenum,
+    normalized: GLboolean,
+    relative_offset: GLuint,
+) {
+    update_vertex_array(vertex_array, |state| {
+        let format = (size, type_, normalized, relative_offset);
+        state.formats.insert(attribute, format);
+    });
+}
+
+extern "system" fn vertex_array_attrib_binding(
+    vertex_array: GLuint,
+    attribute: GLuint,
+    binding: GLuint,
+) {
+    update_vertex_array(vertex_array, |state| {
+        state.bindings.insert(attribute, binding);
+    });
+}
+
+extern "system" fn enable_vertex_array_attrib(vertex_array: GLuint, attribute: GLuint) {
+    unsafe {
+        with_vertex_array(vertex_array, || gl::EnableVertexAttribArray(attribute));
+    }
+}
+
+extern "system" fn vertex_array_element_buffer(vertex_array: GLuint, buffer: GLuint) {
+    unsafe {
+        // The element buffer binding is part of the vertex array
+        with_vertex_array(vertex_array, || {
+            gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, buffer)
+        });
+    }
+}
+
+extern "system" fn named_framebuffer_texture(
+    framebuffer: GLuint,
+    attachment: GLenum,
+    texture: GLuint,
+    level: GLint,
+) {
+    unsafe {
+        with_framebuffer(framebuffer, || {
+            gl::FramebufferTexture(gl::FRAMEBUFFER, attachment, texture, level)
+        });
+    }
+}
+
+extern "system" fn named_framebuffer_texture_layer(
+    framebuffer: GLuint,
+    attachment: GLenum,
+    texture: GLuint,
+    level: GLint,
+    layer: GLint,
+) {
+    unsafe {
+        with_framebuffer(framebuffer, || {
+            // Cube map faces only count as layers since 4.5
+            if texture_target(texture) == gl::TEXTURE_CUBE_MAP {
+                let face = CUBE_FACES[layer as usize];
+                gl::FramebufferTexture2D(gl::FRAMEBUFFER, attachment, face, texture, level);
+            } else {
+                gl::FramebufferTextureLayer(gl::FRAMEBUFFER, attachment, texture, level, layer);
+            }
+        });
+    }
+}
+
+extern "system" fn named_framebuffer_draw_buffer(framebuffer: GLuint, buffer: GLenum) {
+    unsafe {
+        with_framebuffer(framebuffer, || gl::DrawBuffer(buffer));
+    }
+}
+
+extern "system" fn named_framebuffer_draw_buffers(
+    framebuffer: GLuint,
+    n: GLsizei,
+    buffers: *const GLenum,
+) {
+    unsafe {
+        with_framebuffer(framebuffer, || gl::DrawBuffers(n, buffers));
+    }
+}
+
+extern "system" fn named_framebuffer_read_buffer(framebuffer: GLuint, buffer: GLenum) {
+    unsafe {
+        with_framebuffer(framebuffer, || gl::ReadBuffer(buffer));
+    }
+}
+
+extern "system" fn check_named_framebuffer_status(framebuffer: GLuint, target: GLenum) -> GLenum {
+    unsafe { with_framebuffer(framebuffer, || gl::CheckFramebufferStatus(target)) }
+}
+
+extern "system" fn object_label(
+    _identifier: GLenum,
+    _name: GLuint,
+    _length: GLsizei,
+    _label: *const GLchar,
+) {
+}
+
+extern "system" fn memory_barrier(_barriers: GLbitfield) {}
+
+extern "system" fn program_parameter_i(_program: GLuint, _name: GLenum, _value: GLint) {}
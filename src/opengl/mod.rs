@@ -1,11 +1,41 @@
 #![macro_use]
 #![allow(dead_code)]
 
+use std::cell::Cell;
 use std::ffi::CStr;
 
 use gl::types::*;
 
+pub mod caps;
+pub mod fallback;
+pub mod objects;
 pub mod shader;
+pub mod uniform;
+
+thread_local! {
+    /// Set while dropping the objects of a context that's gone
+    static DROPPING_LOST: Cell<bool> = Cell::new(false);
+}
+
+/// Before a new context is made after the old one was lost. Nothing compiled or
+/// recorded for the old one applies to the new one.
+pub fn forget_context() {
+    shader::forget_compiled();
+    fallback::forget();
+}
+
+/// Drops whatever was made in a lost context. Its objects went with it and the
+/// names may belong to new objects by now, so nothing is deleted.
+pub fn drop_lost<T>(value: T) {
+    DROPPING_LOST.with(|dropping| dropping.set(true));
+    drop(value);
+    DROPPING_LOST.with(|dropping| dropping.set(false));
+}
+
+/// Checked by everything that deletes GL objects when dropped
+fn dropping_lost() -> bool {
+    DROPPING_LOST.with(|dropping| dropping.get())
+}
 
 pub fn gl_check_error(file: &str, line: u32) {
     let error_code = unsafe { gl::GetError() };
@@ -0,0 +1,236 @@
+//! Owning wrappers around GL object names. Each one deletes its object when dropped,
+//! so early returns and errors don't leak anything.
+//! Labels show up in debug callback messages and in tools like RenderDoc.
+
+use gl::types::*;
+
+fn set_label(identifier: GLenum, id: GLuint, label: &str) {
+    unsafe {
+        gl::ObjectLabel(
+            identifier,
+            id,
+            label.len() as GLsizei,
+            label.as_ptr() as *const GLchar,
+        );
+    }
+}
+
+#[derive(Debug)]
+pub struct Buffer {
+    id: GLuint,
+}
+
+impl Buffer {
+    pub fn new(label: &str) -> Self {
+        let mut id: GLuint = 0;
+        unsafe {
+            gl::CreateBuffers(1, &mut id);
+        }
+        set_label(gl::BUFFER, id, label);
+        Buffer { id }
+    }
+
+    pub fn id(&self) -> GLuint {
+        self.id
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        if super::dropping_lost() {
+            return;
+        }
+        unsafe {
+            gl::DeleteBuffers(1, &self.id);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct VertexArray {
+    id: GLuint,
+}
+
+impl VertexArray {
+    pub fn new(label: &str) -> Self {
+        let mut id: GLuint = 0;
+        unsafe {
+            gl::CreateVertexArrays(1, &mut id);
+        }
+        set_label(gl::VERTEX_ARRAY, id, label);
+        VertexArray { id }
+    }
+
+    pub fn id(&self) -> GLuint {
+        self.id
+    }
+}
+
+impl Drop for VertexArray {
+    fn drop(&mut self) {
+        if super::dropping_lost() {
+            return;
+        }
+        unsafe {
+            gl::DeleteVertexArrays(1, &self.id);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Texture {
+    id: GLuint,
+}
+
+impl Texture {
+    /// `target` is fixed for the lifetime of the texture, e.g. TEXTURE_2D
+    pub fn new(target: GLenum, label: &str) -> Self {
+        let mut id: GLuint = 0;
+        unsafe {
+            gl::CreateTextures(target, 1, &mut id);
+        }
+        set_label(gl::TEXTURE, id, label);
+        Texture { id }
+    }
+
+    pub fn id(&self) -> GLuint {
+        self.id
+    }
+
+    pub fn set_parameter(&self, name: GLenum, value: GLenum) {
+        unsafe {
+            gl::TextureParameteri(self.id, name, value as GLint);
+        }
+    }
+
+    /// Same wrapping on every axis plus min / mag filters
+    pub fn set_wrap_and_filter(&self, wrap: GLenum, min_filter: GLenum, mag_filter: GLenum) {
+        self.set_parameter(gl::TEXTURE_WRAP_S, wrap);
+        self.set_parameter(gl::TEXTURE_WRAP_T, wrap);
+        self.set_parameter(gl::TEXTURE_WRAP_R, wrap);
+        self.set_parameter(gl::TEXTURE_MIN_FILTER, min_filter);
+        self.set_parameter(gl::TEXTURE_MAG_FILTER, mag_filter);
+    }
+}
+
+impl Drop for Texture {
+    fn drop(&mut self) {
+        if super::dropping_lost() {
+            return;
+        }
+        unsafe {
+            gl::DeleteTextures(1, &self.id);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Framebuffer {
+    id: GLuint,
+}
+
+impl Framebuffer {
+    pub fn new(label: &str) -> Self {
+        let mut id: GLuint = 0;
+        unsafe {
+            gl::CreateFramebuffers(1, &mut id);
+        }
+        set_label(gl::FRAMEBUFFER, id, label);
+        Framebuffer { id }
+    }
+
+    pub fn id(&self) -> GLuint {
+        self.id
+    }
+
+    pub fn attach_texture(&self, attachment: GLenum, texture: &Texture) {
+        unsafe {
+            gl::NamedFramebufferTexture(self.id, attachment, texture.id(), 0);
+        }
+    }
+
+    /// One face of a cube map or layer of an array texture
+    pub fn attach_texture_layer(
+        &self,
+        attachment: GLenum,
+        texture: &Texture,
+        level: i32,
+        layer: i32,
+    ) {
+        unsafe {
+            gl::NamedFramebufferTextureLayer(self.id, attachment, texture.id(), level, layer);
+        }
+    }
+}
+
+impl Drop for Framebuffer {
+    fn drop(&mut self) {
+        if super::dropping_lost() {
+            return;
+        }
+        unsafe {
+            gl::DeleteFramebuffers(1, &self.id);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Query {
+    id: GLuint,
+}
+
+impl Query {
+    pub fn new(target: GLenum, label: &str) -> Self {
+        let mut id: GLuint = 0;
+        unsafe {
+            gl::CreateQueries(target, 1, &mut id);
+        }
+        set_label(gl::QUERY, id, label);
+        Query { id }
+    }
+
+    pub fn id(&self) -> GLuint {
+        self.id
+    }
+}
+
+impl Drop for Query {
+    fn drop(&mut self) {
+        if super::dropping_lost() {
+            return;
+        }
+        unsafe {
+            gl::DeleteQueries(1, &self.id);
+        }
+    }
+}
+
+/// Signalled once the GPU is done with everything issued before it
+#[derive(Debug)]
+pub struct Fence {
+    sync: GLsync,
+}
+
+impl Fence {
+    pub fn insert() -> Self {
+        let sync = unsafe { gl::FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0) };
+        Fence { sync }
+    }
+
+    /// `timeout` in nanoseconds, 0 only asks. A lost context counts as done.
+    pub fn wait(&self, timeout: u64) -> bool {
+        let status = unsafe { gl::ClientWaitSync(self.sync, gl::SYNC_FLUSH_COMMANDS_BIT, timeout) };
+        status != gl::TIMEOUT_EXPIRED
+    }
+}
+
+impl Drop for Fence {
+    fn drop(&mut self) {
+        if super::dropping_lost() {
+            return;
+        }
+        unsafe {
+            gl::DeleteSync(self.sync);
+        }
+    }
+}
@@ -1,71 +1,160 @@
-use std::ffi::CString;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::ffi::{CStr, CString};
 use std::fs;
 use std::io;
+use std::path::{Path, PathBuf};
 
 use gl::types::*;
 use glam::Vec2;
 use glam::{Mat4, Vec3};
 use thiserror::Error;
 
+use crate::derived_cache::ContentHash;
+
+use super::caps;
+
 #[derive(Debug, Error)]
 pub enum ShaderError {
     #[error("Failed to compile {name}: {message}")]
     CompileError { name: String, message: String },
+    #[error("Shader includes unknown file '{0}'")]
+    IncludeNotFound(String),
     #[error("Failed to link program: {0}")]
     LinkError(String),
     #[error("Couldn't get uniform location for '{name}'")]
     UniformLocationNotFound { name: String },
     #[error("Couldn't get uniform block index for '{name}'")]
     UniformBlockIndexNotFound { name: String },
+    #[error("Uniform block '{name}' doesn't match its Rust layout: {message}")]
+    UniformBlockMismatch { name: String, message: String },
 }
 
 pub type Result<T> = std::result::Result<T, ShaderError>;
 
+/// Files that can be pulled in with `#include "name"`
+const INCLUDES: &[(&str, &str)] = &[
+    (
+        "transforms.glsl",
+        include_str!("../shaders/include/transforms.glsl"),
+    ),
+    ("wind.glsl", include_str!("../shaders/include/wind.glsl")),
+    (
+        "sky_light.glsl",
+        include_str!("../shaders/include/sky_light.glsl"),
+    ),
+    (
+        "instances.glsl",
+        include_str!("../shaders/include/instances.glsl"),
+    ),
+    (
+        "heightmap.glsl",
+        include_str!("../shaders/include/heightmap.glsl"),
+    ),
+    (
+        "painted_maps.glsl",
+        include_str!("../shaders/include/painted_maps.glsl"),
+    ),
+];
+
+thread_local! {
+    /// Compiled shader objects by stage and preprocessed source. Programs sharing
+    /// a stage (e.g. the terrain and shadow passes) compile it once. The objects
+    /// are kept for as long as the context lives, there's only a handful of variants.
+    static SHADER_CACHE: RefCell<HashMap<(GLenum, String), GLuint>> = RefCell::new(HashMap::new());
+}
+
+/// Linked program binaries are stored here between runs
+const BINARY_CACHE_DIR: &str = "shader_cache";
+
+/// A `layout(binding = N)` taken out of a shader for a context too old for it,
+/// it's set on the linked program instead
+#[derive(Debug, Clone, PartialEq)]
+enum Binding {
+    Sampler(String, GLint),
+    UniformBlock(String, GLuint),
+}
+
 pub struct Program {
     id: GLuint,
+    defines: Vec<(String, String)>,
+    // Preprocessed sources, compiled on link unless there's a cached binary
+    stages: Vec<(GLenum, String)>,
+    bindings: Vec<Binding>,
 }
 
 impl Program {
     pub fn new() -> Self {
         let id = unsafe { gl::CreateProgram() };
-        Program { id }
+        Program {
+            id,
+            defines: vec![],
+            stages: vec![],
+            bindings: vec![],
+        }
     }
 
-    fn attach_shader(&self, code: &str, kind: GLenum) -> Result<()> {
-        let shader = Shader::new(kind, code)?;
-        unsafe {
-            gl::AttachShader(self.id, shader.id());
+    /// Adds `#define name value` to every stage attached after this call
+    pub fn define(mut self, name: &str, value: &str) -> Self {
+        self.defines.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    fn attach_shader(&mut self, code: &str, kind: GLenum) -> Result<()> {
+        let mut source = preprocess(code, &self.defines)?;
+        if !caps::get().binding_qualifiers {
+            source = take_bindings(&source, &mut self.bindings);
         }
+        self.stages.push((kind, source));
         Ok(())
     }
 
-    pub fn vertex_shader(self, code: &str) -> Result<Self> {
+    pub fn vertex_shader(mut self, code: &str) -> Result<Self> {
         self.attach_shader(code, gl::VERTEX_SHADER)?;
         Ok(self)
     }
 
-    pub fn fragment_shader(self, code: &str) -> Result<Self> {
+    pub fn fragment_shader(mut self, code: &str) -> Result<Self> {
         self.attach_shader(code, gl::FRAGMENT_SHADER)?;
         Ok(self)
     }
 
-    pub fn tess_control_shader(self, code: &str) -> Result<Self> {
+    pub fn tess_control_shader(mut self, code: &str) -> Result<Self> {
         self.attach_shader(code, gl::TESS_CONTROL_SHADER)?;
         Ok(self)
     }
 
-    pub fn tess_evaluation_shader(self, code: &str) -> Result<Self> {
+    pub fn tess_evaluation_shader(mut self, code: &str) -> Result<Self> {
         self.attach_shader(code, gl::TESS_EVALUATION_SHADER)?;
         Ok(self)
     }
 
-    pub fn geometry_shader(self, code: &str) -> Result<Self> {
+    pub fn geometry_shader(mut self, code: &str) -> Result<Self> {
         self.attach_shader(code, gl::GEOMETRY_SHADER)?;
         Ok(self)
     }
 
     pub fn link(self) -> Result<Self> {
+        let cache_path = binary_cache_path(&self.stages);
+        if let Some(path) = &cache_path {
+            if self.load_binary(path) {
+                self.apply_bindings();
+                return Ok(self);
+            }
+        }
+
+        for (kind, source) in &self.stages {
+            let shader = compile_cached(*kind, source.clone())?;
+            unsafe {
+                gl::AttachShader(self.id, shader);
+            }
+        }
         unsafe {
+            gl::ProgramParameteri(
+                self.id,
+                gl::PROGRAM_BINARY_RETRIEVABLE_HINT,
+                gl::TRUE as GLint,
+            );
             gl::LinkProgram(self.id);
         }
         let mut success: GLint = 1;
@@ -88,10 +177,100 @@ impl Program {
             }
             return Err(ShaderError::LinkError(error.to_string_lossy().into_owned()));
         }
+        self.apply_bindings();
+
+        if let Some(path) = &cache_path {
+            if let Err(error) = self.save_binary(path) {
+                eprintln!(
+                    "Couldn't cache program binary {}: {}",
+                    path.display(),
+                    error
+                );
+            }
+        }
 
         Ok(self)
     }
 
+    /// Sets what `take_bindings` took out of the shaders, uniforms the compiler
+    /// optimized away are skipped
+    fn apply_bindings(&self) {
+        if self.bindings.is_empty() {
+            return;
+        }
+        let mut previous: GLint = 0;
+        unsafe {
+            gl::GetIntegerv(gl::CURRENT_PROGRAM, &mut previous);
+            gl::UseProgram(self.id);
+        }
+        for binding in &self.bindings {
+            match binding {
+                Binding::Sampler(name, unit) => {
+                    if let Ok(location) = self.get_uniform_location(name) {
+                        unsafe { gl::Uniform1i(location, *unit) };
+                    }
+                }
+                Binding::UniformBlock(name, binding) => {
+                    let _ = self.bind_uniform_block(name, *binding);
+                }
+            }
+        }
+        unsafe {
+            gl::UseProgram(previous as GLuint);
+        }
+    }
+
+    /// False if there's no binary or the driver refuses it, e.g. after an update
+    fn load_binary(&self, path: &Path) -> bool {
+        let bytes = match fs::read(path) {
+            Ok(bytes) if bytes.len() > 4 => bytes,
+            _ => return false,
+        };
+        let format = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        let binary = &bytes[4..];
+        let mut success: GLint = 0;
+        unsafe {
+            gl::ProgramBinary(
+                self.id,
+                format,
+                binary.as_ptr() as *const _,
+                binary.len() as GLsizei,
+            );
+            gl::GetProgramiv(self.id, gl::LINK_STATUS, &mut success);
+        }
+        success != 0
+    }
+
+    /// Stored as the binary format followed by the binary itself
+    fn save_binary(&self, path: &Path) -> io::Result<()> {
+        let mut len: GLint = 0;
+        unsafe {
+            gl::GetProgramiv(self.id, gl::PROGRAM_BINARY_LENGTH, &mut len);
+        }
+        if len <= 0 {
+            return Ok(());
+        }
+        let mut binary = vec![0u8; len as usize];
+        let mut format: GLenum = 0;
+        unsafe {
+            gl::GetProgramBinary(
+                self.id,
+                len,
+                std::ptr::null_mut(),
+                &mut format,
+                binary.as_mut_ptr() as *mut _,
+            );
+        }
+        let mut bytes = format.to_le_bytes().to_vec();
+        bytes.extend_from_slice(&binary);
+        fs::create_dir_all(BINARY_CACHE_DIR)?;
+        fs::write(path, bytes)
+    }
+
+    pub fn id(&self) -> GLuint {
+        self.id
+    }
+
     pub fn set_used(&self) {
         unsafe {
             gl::UseProgram(self.id);
@@ -189,64 +368,209 @@ impl Program {
 
 impl Drop for Program {
     fn drop(&mut self) {
+        if super::dropping_lost() {
+            return;
+        }
         unsafe {
             gl::DeleteProgram(self.id);
         }
     }
 }
 
-struct Shader {
-    id: GLuint,
+/// Expands `#include "name"` lines and puts the defines right after `#version`.
+/// `#line` directives keep the compiler's line numbers pointing at the original file.
+/// The version is replaced with what the context has, shaders are written for 4.5.
+fn preprocess(code: &str, defines: &[(String, String)]) -> Result<String> {
+    let mut output = String::with_capacity(code.len());
+    let mut lines = code.lines().enumerate().peekable();
+    if let Some((_, first)) = lines.peek() {
+        if first.trim_start().starts_with("#version") {
+            let version = caps::get().glsl_version();
+            output.push_str(&format!("#version {} core\n", version));
+            // Matching stage outputs and inputs by location
+            if version < 410 {
+                output.push_str("#extension GL_ARB_separate_shader_objects : enable\n");
+            }
+            lines.next();
+        }
+    }
+    for (name, value) in defines {
+        output.push_str(&format!("#define {} {}\n", name, value));
+    }
+    if let Some(&(number, _)) = lines.peek() {
+        output.push_str(&format!("#line {}\n", number + 1));
+    }
+    let mut included = HashSet::new();
+    for (number, line) in lines {
+        expand_line(line, number + 1, &mut included, &mut output)?;
+    }
+    Ok(output)
 }
 
-impl Shader {
-    pub fn new(kind: GLenum, code: &str) -> Result<Self> {
-        let source = CString::new(code).unwrap();
-        let id = unsafe { gl::CreateShader(kind) };
-        unsafe {
-            gl::ShaderSource(id, 1, &source.as_ptr(), std::ptr::null());
-            gl::CompileShader(id);
+fn expand_line(
+    line: &str,
+    number: usize,
+    included: &mut HashSet<&'static str>,
+    output: &mut String,
+) -> Result<()> {
+    let name = match parse_include(line) {
+        Some(name) => name,
+        None => {
+            output.push_str(line);
+            output.push('\n');
+            return Ok(());
         }
-        let mut success: GLint = 1;
-        unsafe {
-            gl::GetShaderiv(id, gl::COMPILE_STATUS, &mut success);
+    };
+    let &(name, contents) = INCLUDES
+        .iter()
+        .find(|(include, _)| *include == name)
+        .ok_or_else(|| ShaderError::IncludeNotFound(name.to_owned()))?;
+    // Every file is only included once, so there's no need for include guards
+    if included.insert(name) {
+        output.push_str("#line 1\n");
+        for (number, line) in contents.lines().enumerate() {
+            expand_line(line, number + 1, included, output)?;
         }
-        if success == 0 {
-            let mut len: GLint = 0;
-            unsafe {
-                gl::GetShaderiv(id, gl::INFO_LOG_LENGTH, &mut len);
-            }
-            let error = new_cstring(len as usize);
-            unsafe {
-                gl::GetShaderInfoLog(id, len, std::ptr::null_mut(), error.as_ptr() as *mut GLchar);
+        output.push_str(&format!("#line {}\n", number + 1));
+    }
+    Ok(())
+}
+
+fn parse_include(line: &str) -> Option<&str> {
+    let rest = line.trim().strip_prefix("#include")?.trim();
+    rest.strip_prefix('"')?.strip_suffix('"')
+}
+
+/// Removes `binding = N` from the layouts of samplers and uniform blocks and
+/// adds them to `bindings`
+fn take_bindings(source: &str, bindings: &mut Vec<Binding>) -> String {
+    let mut output = String::with_capacity(source.len());
+    for line in source.lines() {
+        match take_binding(line) {
+            Some((line, binding)) => {
+                output.push_str(&line);
+                if !bindings.contains(&binding) {
+                    bindings.push(binding);
+                }
             }
-            let name = match kind {
-                gl::VERTEX_SHADER => "vertex shader",
-                gl::FRAGMENT_SHADER => "fragment shader",
-                gl::TESS_CONTROL_SHADER => "tessellation control shader",
-                gl::TESS_EVALUATION_SHADER => "tessellation evaluation shader",
-                gl::GEOMETRY_SHADER => "geometry shader",
-                _ => panic!("Unknown shader type, can't get error message"),
-            };
-            return Err(ShaderError::CompileError {
-                name: name.to_owned(),
-                message: error.to_string_lossy().into_owned(),
-            });
+            None => output.push_str(line),
         }
-        Ok(Shader { id })
+        output.push('\n');
     }
+    output
+}
 
-    pub fn id(&self) -> GLuint {
-        self.id
+/// `layout(std140, binding = 1) uniform Block {` becomes `layout(std140) uniform Block {`
+/// and `layout(binding = 0) uniform sampler2D name;` becomes `uniform sampler2D name;`
+fn take_binding(line: &str) -> Option<(String, Binding)> {
+    let rest = line.trim().strip_prefix("layout")?.trim_start();
+    let (qualifiers, declaration) = rest.strip_prefix('(')?.split_once(')')?;
+    let mut value = None;
+    let mut kept = vec![];
+    for qualifier in qualifiers.split(',') {
+        match qualifier.trim().strip_prefix("binding") {
+            Some(binding) => value = binding.trim().strip_prefix('=')?.trim().parse().ok(),
+            None => kept.push(qualifier.trim()),
+        }
     }
+    let value: u32 = value?;
+    let mut words = declaration.split_whitespace();
+    if words.next()? != "uniform" {
+        return None;
+    }
+    let binding = match words.next()? {
+        kind if kind.starts_with("sampler") => {
+            let name = words.next()?.trim_end_matches(';');
+            Binding::Sampler(name.to_owned(), value as GLint)
+        }
+        block => Binding::UniformBlock(block.trim_end_matches('{').to_owned(), value),
+    };
+    let line = if kept.is_empty() {
+        declaration.trim_start().to_owned()
+    } else {
+        format!("layout({}){}", kept.join(", "), declaration)
+    };
+    Some((line, binding))
 }
 
-impl Drop for Shader {
-    fn drop(&mut self) {
+/// The key covers every stage and the driver, so a driver update or any shader
+/// change just misses the cache. None if the driver can't save binaries at all.
+fn binary_cache_path(stages: &[(GLenum, String)]) -> Option<PathBuf> {
+    if !caps::get().program_binary {
+        return None;
+    }
+    let mut num_formats: GLint = 0;
+    unsafe {
+        gl::GetIntegerv(gl::NUM_PROGRAM_BINARY_FORMATS, &mut num_formats);
+    }
+    if num_formats == 0 {
+        return None;
+    }
+
+    // Stable across Rust releases, the binaries outlive the build that saved them
+    let mut hash = ContentHash::new();
+    for name in [gl::VENDOR, gl::RENDERER, gl::VERSION] {
+        let string = unsafe { CStr::from_ptr(gl::GetString(name) as *const _) };
+        hash = hash.with_bytes(string.to_bytes());
+    }
+    for (kind, source) in stages {
+        hash = hash.with_u64(*kind as u64).with_bytes(source.as_bytes());
+    }
+    let file_name = format!("{:016x}.bin", hash);
+    Some(Path::new(BINARY_CACHE_DIR).join(file_name))
+}
+
+fn compile_cached(kind: GLenum, source: String) -> Result<GLuint> {
+    let key = (kind, source);
+    let cached = SHADER_CACHE.with(|cache| cache.borrow().get(&key).copied());
+    if let Some(shader) = cached {
+        return Ok(shader);
+    }
+    let shader = compile(kind, &key.1)?;
+    SHADER_CACHE.with(|cache| cache.borrow_mut().insert(key, shader));
+    Ok(shader)
+}
+
+/// The cached shaders went with the context they were compiled in
+pub fn forget_compiled() {
+    SHADER_CACHE.with(|cache| cache.borrow_mut().clear());
+}
+
+fn compile(kind: GLenum, code: &str) -> Result<GLuint> {
+    let source = CString::new(code).unwrap();
+    let id = unsafe { gl::CreateShader(kind) };
+    unsafe {
+        gl::ShaderSource(id, 1, &source.as_ptr(), std::ptr::null());
+        gl::CompileShader(id);
+    }
+    let mut success: GLint = 1;
+    unsafe {
+        gl::GetShaderiv(id, gl::COMPILE_STATUS, &mut success);
+    }
+    if success == 0 {
+        let mut len: GLint = 0;
+        unsafe {
+            gl::GetShaderiv(id, gl::INFO_LOG_LENGTH, &mut len);
+        }
+        let error = new_cstring(len as usize);
         unsafe {
-            gl::DeleteShader(self.id);
+            gl::GetShaderInfoLog(id, len, std::ptr::null_mut(), error.as_ptr() as *mut GLchar);
+            gl::DeleteShader(id);
         }
+        let name = match kind {
+            gl::VERTEX_SHADER => "vertex shader",
+            gl::FRAGMENT_SHADER => "fragment shader",
+            gl::TESS_CONTROL_SHADER => "tessellation control shader",
+            gl::TESS_EVALUATION_SHADER => "tessellation evaluation shader",
+            gl::GEOMETRY_SHADER => "geometry shader",
+            _ => panic!("Unknown shader type, can't get error message"),
+        };
+        return Err(ShaderError::CompileError {
+            name: name.to_owned(),
+            message: error.to_string_lossy().into_owned(),
+        });
     }
+    Ok(id)
 }
 
 fn new_cstring(len: usize) -> CString {
@@ -0,0 +1,249 @@
+//! Typed uniform buffers. The Rust side describes its std140 layout once,
+//! the layout is then checked both against the struct and against the linked programs.
+
+use std::ffi::CString;
+use std::marker::PhantomData;
+use std::mem::size_of;
+
+use gl::types::*;
+
+use super::objects::Buffer;
+use super::shader::{Program, Result, ShaderError};
+
+/// The subset of GLSL types that can go into a block
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Std140Type {
+    Float,
+    Int,
+    Vec2,
+    Vec3,
+    Vec4,
+    Mat4,
+}
+
+impl Std140Type {
+    fn size(self) -> usize {
+        match self {
+            Std140Type::Float | Std140Type::Int => 4,
+            Std140Type::Vec2 => 8,
+            Std140Type::Vec3 => 12,
+            Std140Type::Vec4 => 16,
+            Std140Type::Mat4 => 64,
+        }
+    }
+
+    fn alignment(self) -> usize {
+        match self {
+            Std140Type::Float | Std140Type::Int => 4,
+            Std140Type::Vec2 => 8,
+            // vec3 is aligned like a vec4 but a float can still follow it
+            Std140Type::Vec3 | Std140Type::Vec4 | Std140Type::Mat4 => 16,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BlockMember {
+    pub name: &'static str,
+    pub ty: Std140Type,
+    pub offset: usize,
+}
+
+/// std140 offsets of a block's members, built in declaration order
+#[derive(Debug, Clone)]
+pub struct BlockLayout {
+    name: &'static str,
+    members: Vec<BlockMember>,
+    end: usize,
+}
+
+impl BlockLayout {
+    /// `name` is the block name in GLSL, not the instance name
+    pub fn new(name: &'static str) -> Self {
+        BlockLayout {
+            name,
+            members: vec![],
+            end: 0,
+        }
+    }
+
+    /// `rust_offset` is where the field actually is in the Rust struct (`offset_of!`),
+    /// panics if it's not where std140 puts it
+    pub fn member(mut self, name: &'static str, ty: Std140Type, rust_offset: usize) -> Self {
+        let offset = align_up(self.end, ty.alignment());
+        assert_eq!(
+            offset, rust_offset,
+            "Uniform block {}: member '{}' is at {} in Rust but at {} in std140",
+            self.name, name, rust_offset, offset
+        );
+        self.members.push(BlockMember { name, ty, offset });
+        self.end = offset + ty.size();
+        self
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn members(&self) -> &[BlockMember] {
+        &self.members
+    }
+
+    /// The whole block is padded to a multiple of a vec4
+    pub fn size(&self) -> usize {
+        align_up(self.end, 16)
+    }
+}
+
+/// Implemented by `#[repr(C)]` structs that are uploaded to a uniform block as is
+pub trait UniformBlock: Sized {
+    fn layout() -> BlockLayout;
+}
+
+impl Program {
+    /// Compares the block in the linked program with the Rust layout.
+    /// Blocks the program doesn't use are not an error.
+    pub fn check_uniform_block(&self, layout: &BlockLayout) -> Result<()> {
+        let block_name = CString::new(layout.name()).unwrap();
+        let index = unsafe { gl::GetUniformBlockIndex(self.id(), block_name.as_ptr()) };
+        if index == gl::INVALID_INDEX {
+            return Ok(());
+        }
+        let mismatch = |message: String| ShaderError::UniformBlockMismatch {
+            name: layout.name().to_owned(),
+            message,
+        };
+
+        let mut data_size: GLint = 0;
+        unsafe {
+            gl::GetActiveUniformBlockiv(
+                self.id(),
+                index,
+                gl::UNIFORM_BLOCK_DATA_SIZE,
+                &mut data_size,
+            );
+        }
+        if data_size as usize != layout.size() {
+            return Err(mismatch(format!(
+                "size is {} in the shader but {} in Rust",
+                data_size,
+                layout.size()
+            )));
+        }
+
+        for member in layout.members() {
+            let name = format!("{}.{}", layout.name(), member.name);
+            let name_cstr = CString::new(name.as_str()).unwrap();
+            let names = [name_cstr.as_ptr()];
+            let mut uniform_index: GLuint = gl::INVALID_INDEX;
+            let mut offset: GLint = -1;
+            unsafe {
+                gl::GetUniformIndices(self.id(), 1, names.as_ptr(), &mut uniform_index);
+                if uniform_index == gl::INVALID_INDEX {
+                    // Members unused by every stage may be optimised out
+                    continue;
+                }
+                gl::GetActiveUniformsiv(
+                    self.id(),
+                    1,
+                    &uniform_index,
+                    gl::UNIFORM_OFFSET,
+                    &mut offset,
+                );
+            }
+            if offset as usize != member.offset {
+                return Err(mismatch(format!(
+                    "'{}' is at {} in the shader but at {} in Rust",
+                    member.name, offset, member.offset
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A block-sized ring in a single buffer. Each update goes to the next slot,
+/// so the driver doesn't have to wait for draws still reading the previous one.
+pub struct UniformBuffer<T: UniformBlock> {
+    buffer: Buffer,
+    binding: GLuint,
+    slot_size: usize,
+    slots: usize,
+    current: usize,
+    _data: PhantomData<T>,
+}
+
+impl<T: UniformBlock> UniformBuffer<T> {
+    pub fn new(binding: GLuint, slots: usize, label: &str) -> Self {
+        let layout = T::layout();
+        assert_eq!(
+            size_of::<T>(),
+            layout.size(),
+            "Uniform block {} doesn't cover the whole struct",
+            layout.name()
+        );
+        assert!(slots > 0);
+
+        let mut alignment: GLint = 0;
+        unsafe {
+            gl::GetIntegerv(gl::UNIFORM_BUFFER_OFFSET_ALIGNMENT, &mut alignment);
+        }
+        let slot_size = align_up(layout.size(), alignment.max(1) as usize);
+
+        let buffer = Buffer::new(label);
+        unsafe {
+            gl::NamedBufferStorage(
+                buffer.id(),
+                (slot_size * slots) as isize,
+                std::ptr::null(),
+                gl::DYNAMIC_STORAGE_BIT,
+            );
+        }
+
+        let uniform_buffer = UniformBuffer {
+            buffer,
+            binding,
+            slot_size,
+            slots,
+            current: 0,
+            _data: PhantomData,
+        };
+        uniform_buffer.bind();
+        uniform_buffer
+    }
+
+    /// Writes `data` into the next slot and binds it
+    pub fn update(&mut self, data: &T) {
+        self.current = (self.current + 1) % self.slots;
+        unsafe {
+            gl::NamedBufferSubData(
+                self.buffer.id(),
+                (self.current * self.slot_size) as isize,
+                size_of::<T>() as isize,
+                data as *const T as *const _,
+            );
+        }
+        self.bind();
+    }
+
+    fn bind(&self) {
+        unsafe {
+            gl::BindBufferRange(
+                gl::UNIFORM_BUFFER,
+                self.binding,
+                self.buffer.id(),
+                (self.current * self.slot_size) as isize,
+                size_of::<T>() as isize,
+            );
+        }
+    }
+
+    pub fn check_program(&self, program: &Program) -> Result<()> {
+        program.check_uniform_block(&T::layout())
+    }
+}
+
+fn align_up(value: usize, alignment: usize) -> usize {
+    value.next_multiple_of(alignment)
+}
@@ -0,0 +1,460 @@
+//! Roads, water and forests from an OpenStreetMap extract (.osm XML, as exported from
+//! openstreetmap.org or cut with osmium). Ways are placed with the terrain's
+//! georeference; without one the extract's bounds are stretched over the terrain.
+//!
+//! There's no road or water geometry to feed, so roads and water are painted onto
+//! terrain layers and roads can flatten the ground under them. Forests are filled
+//! with a scatter type. Only ways are read: multipolygon relations, which big lakes
+//! and forests are often mapped as, are skipped.
+
+use std::collections::HashMap;
+use std::fs;
+
+use glam::Vec2;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+
+use crate::georef::Georeference;
+use crate::heightfield::HeightField;
+use crate::noise::smoothstep;
+
+/// Roads blend back into the terrain over this many times their half width
+const ROAD_SHOULDER: f32 = 1.0;
+
+pub struct OsmSettings {
+    pub path: String,
+    /// Terrain layers painted under roads and water, none leaves the texturing as it is
+    pub road_layer: Option<usize>,
+    pub water_layer: Option<usize>,
+    pub flatten_roads: bool,
+    /// Scatter type filling forests
+    pub forest_type: Option<usize>,
+}
+
+impl Default for OsmSettings {
+    fn default() -> Self {
+        OsmSettings {
+            path: String::new(),
+            road_layer: None,
+            water_layer: None,
+            flatten_roads: true,
+            forest_type: None,
+        }
+    }
+}
+
+impl OsmSettings {
+    /// Keeps the forest type pointing at the right one after a type is removed
+    pub fn forget_scatter_type(&mut self, index: usize) {
+        self.forest_type = match self.forest_type {
+            Some(forest) if forest == index => None,
+            Some(forest) if forest > index => Some(forest - 1),
+            forest => forest,
+        };
+    }
+}
+
+/// A road or a river, `points` in terrain uv with v = 0 along the northern edge
+pub struct OsmLine {
+    pub points: Vec<Vec2>,
+    /// Meters
+    pub width: f32,
+}
+
+/// Everything in terrain uv
+pub struct OsmData {
+    pub roads: Vec<OsmLine>,
+    /// Rivers and streams
+    pub waterways: Vec<OsmLine>,
+    /// Lakes, ponds and riverbanks
+    pub water: Vec<Vec<Vec2>>,
+    pub forests: Vec<Vec<Vec2>>,
+    /// Not lined up with the terrain, the extract was stretched over it
+    pub stretched: bool,
+}
+
+#[derive(Default)]
+struct Way {
+    nodes: Vec<u64>,
+    tags: HashMap<String, String>,
+}
+
+/// What's read from the XML, coordinates still in degrees
+#[derive(Default)]
+struct Extract {
+    nodes: HashMap<u64, (f64, f64)>,
+    ways: Vec<Way>,
+    /// min lat, min lon, max lat, max lon
+    bounds: Option<(f64, f64, f64, f64)>,
+}
+
+/// Parses an extract on any thread, `georeference` being the terrain's
+pub fn load(path: &str, georeference: Option<&Georeference>) -> Result<OsmData, String> {
+    let xml = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let Extract {
+        nodes,
+        ways,
+        bounds,
+    } = parse(&xml).map_err(|e| format!("{}: {}", path, e))?;
+    if nodes.is_empty() {
+        return Err(format!("{} has no OpenStreetMap nodes", path));
+    }
+
+    // Degrees to terrain uv
+    let extent = georeference.map(|g| g.extent);
+    let bounds = bounds.unwrap_or_else(|| {
+        nodes.values().fold(
+            (f64::MAX, f64::MAX, f64::MIN, f64::MIN),
+            |(min_lat, min_lon, max_lat, max_lon), &(lat, lon)| {
+                (
+                    min_lat.min(lat),
+                    min_lon.min(lon),
+                    max_lat.max(lat),
+                    max_lon.max(lon),
+                )
+            },
+        )
+    });
+    let stretched = extent.is_none()
+        || georeference
+            .and_then(|g| g.from_lat_long(bounds.0, bounds.1))
+            .is_none();
+    let to_uv = |&(lat, lon): &(f64, f64)| -> Vec2 {
+        let projected = georeference.and_then(|g| g.from_lat_long(lat, lon));
+        match (extent, projected) {
+            (Some(extent), Some((x, y))) => Vec2::new(
+                ((x - extent.min_x) / extent.width()) as f32,
+                ((extent.max_y - y) / extent.height()) as f32,
+            ),
+            _ => {
+                let (min_lat, min_lon, max_lat, max_lon) = bounds;
+                Vec2::new(
+                    ((lon - min_lon) / (max_lon - min_lon).max(f64::EPSILON)) as f32,
+                    ((max_lat - lat) / (max_lat - min_lat).max(f64::EPSILON)) as f32,
+                )
+            }
+        }
+    };
+
+    let mut data = OsmData {
+        roads: vec![],
+        waterways: vec![],
+        water: vec![],
+        forests: vec![],
+        stretched,
+    };
+    for way in ways {
+        let points: Vec<Vec2> = way
+            .nodes
+            .iter()
+            .filter_map(|id| nodes.get(id))
+            .map(to_uv)
+            .collect();
+        if points.len() < 2 {
+            continue;
+        }
+        let closed = way.nodes.len() >= 4 && way.nodes.first() == way.nodes.last();
+        let tag = |key: &str| way.tags.get(key).map(String::as_str);
+        let width = tag("width").and_then(|w| w.trim_end_matches(" m").parse().ok());
+
+        if let Some(highway) = tag("highway") {
+            if let Some(default_width) = road_width(highway) {
+                data.roads.push(OsmLine {
+                    points,
+                    width: width.unwrap_or(default_width),
+                });
+            }
+        } else if closed
+            && (tag("natural") == Some("water")
+                || tag("waterway") == Some("riverbank")
+                || matches!(tag("landuse"), Some("reservoir") | Some("basin")))
+        {
+            data.water.push(points);
+        } else if let Some(waterway) = tag("waterway") {
+            if let Some(default_width) = waterway_width(waterway) {
+                data.waterways.push(OsmLine {
+                    points,
+                    width: width.unwrap_or(default_width),
+                });
+            }
+        } else if closed && (tag("landuse") == Some("forest") || tag("natural") == Some("wood")) {
+            data.forests.push(points);
+        }
+    }
+    Ok(data)
+}
+
+fn parse(xml: &str) -> Result<Extract, String> {
+    let mut reader = Reader::from_str(xml);
+    let mut extract = Extract::default();
+    let mut way: Option<Way> = None;
+    loop {
+        let event = reader
+            .read_event()
+            .map_err(|e| format!("{} at byte {}", e, reader.error_position()))?;
+        let (element, open) = match event {
+            Event::Start(element) => (element, true),
+            Event::Empty(element) => (element, false),
+            Event::End(element) => {
+                if element.name().as_ref() == b"way" {
+                    extract.ways.extend(way.take());
+                }
+                continue;
+            }
+            Event::Eof => return Ok(extract),
+            _ => continue,
+        };
+        let value = |key| attribute(&element, key);
+        let number = |key| value(key).and_then(|v| v.parse::<f64>().ok());
+        match element.name().as_ref() {
+            b"node" => {
+                let id = value("id").and_then(|v| v.parse().ok());
+                if let (Some(id), Some(lat), Some(lon)) = (id, number("lat"), number("lon")) {
+                    extract.nodes.insert(id, (lat, lon));
+                }
+            }
+            b"way" if open => way = Some(Way::default()),
+            b"nd" => {
+                let id = value("ref").and_then(|v| v.parse().ok());
+                if let (Some(way), Some(id)) = (&mut way, id) {
+                    way.nodes.push(id);
+                }
+            }
+            b"tag" => {
+                if let (Some(way), Some(k), Some(v)) = (&mut way, value("k"), value("v")) {
+                    way.tags.insert(k, v);
+                }
+            }
+            b"bounds" => {
+                if let (Some(min_lat), Some(min_lon), Some(max_lat), Some(max_lon)) = (
+                    number("minlat"),
+                    number("minlon"),
+                    number("maxlat"),
+                    number("maxlon"),
+                ) {
+                    extract.bounds = Some((min_lat, min_lon, max_lat, max_lon));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Meters, none for highways that aren't built yet
+fn road_width(highway: &str) -> Option<f32> {
+    Some(match highway {
+        "proposed" | "construction" | "platform" | "elevator" => return None,
+        "motorway" | "trunk" => 14.0,
+        "primary" => 10.0,
+        "secondary" => 8.0,
+        "tertiary" => 7.0,
+        "residential" | "unclassified" | "living_street" => 6.0,
+        "service" => 4.0,
+        "track" => 3.0,
+        "path" | "footway" | "cycleway" | "bridleway" | "steps" | "pedestrian" => 1.5,
+        _ => 5.0,
+    })
+}
+
+fn waterway_width(waterway: &str) -> Option<f32> {
+    Some(match waterway {
+        "river" => 15.0,
+        "canal" => 10.0,
+        "stream" => 3.0,
+        "ditch" | "drain" => 1.5,
+        _ => return None,
+    })
+}
+
+/// Unescaped value of an attribute, none if it's missing or malformed
+fn attribute(element: &BytesStart, key: &str) -> Option<String> {
+    let attribute = element.try_get_attribute(key).ok()??;
+    attribute
+        .unescape_value()
+        .ok()
+        .map(|value| value.into_owned())
+}
+
+/// 1 under the lines and inside the areas, at `size` texels per side.
+/// Line widths are in meters, `terrain_size` says how many the terrain is across.
+pub fn coverage(
+    lines: &[OsmLine],
+    areas: &[Vec<Vec2>],
+    size: usize,
+    terrain_size: f32,
+) -> HeightField {
+    let mut data = vec![0.0f32; size * size];
+    let texels_per_meter = size as f32 / terrain_size;
+    for line in lines {
+        let half_width = (line.width * texels_per_meter / 2.0).max(0.5);
+        for (a, b) in segments(&line.points, size) {
+            for_texels_near(a, b, half_width + 1.0, size, |i, point| {
+                let (distance, _) = segment_distance(point, a, b);
+                let covered = (half_width + 0.5 - distance).clamp(0.0, 1.0);
+                data[i] = data[i].max(covered);
+            });
+        }
+    }
+    for area in areas {
+        fill_polygon(area, size, &mut data);
+    }
+    HeightField::from_data(size, data)
+}
+
+/// Levels the ground across every road. Along it the road follows the terrain from
+/// node to node, at the edges it blends back into it.
+pub fn flatten_roads(heights: &HeightField, roads: &[OsmLine], terrain_size: f32) -> HeightField {
+    let size = heights.size();
+    let texels_per_meter = size as f32 / terrain_size;
+    // Strongest road at every texel and the height it wants there
+    let mut weights = vec![0.0f32; size * size];
+    let mut targets = vec![0.0f32; size * size];
+    for road in roads {
+        let half_width = (road.width * texels_per_meter / 2.0).max(0.5);
+        let reach = half_width * (1.0 + ROAD_SHOULDER);
+        for (a, b) in segments(&road.points, size) {
+            let height_at = |p: Vec2| heights.sample(p.x / size as f32, p.y / size as f32);
+            let (height_a, height_b) = (height_at(a), height_at(b));
+            for_texels_near(a, b, reach, size, |i, point| {
+                let (distance, t) = segment_distance(point, a, b);
+                let weight = 1.0 - smoothstep((distance - half_width) / (reach - half_width));
+                if weight > weights[i] {
+                    weights[i] = weight;
+                    targets[i] = height_a + (height_b - height_a) * t;
+                }
+            });
+        }
+    }
+    let data = heights
+        .data()
+        .iter()
+        .zip(weights.iter().zip(&targets))
+        .map(|(&h, (&weight, &target))| h + (target - h) * weight)
+        .collect();
+    HeightField::from_data(size, data)
+}
+
+/// Consecutive points in texels
+fn segments(points: &[Vec2], size: usize) -> impl Iterator<Item = (Vec2, Vec2)> + '_ {
+    let scale = size as f32;
+    points.windows(2).map(move |w| (w[0] * scale, w[1] * scale))
+}
+
+/// Calls `f` with the index and center of every texel within `reach` of the bounding
+/// box of a and b
+fn for_texels_near(a: Vec2, b: Vec2, reach: f32, size: usize, mut f: impl FnMut(usize, Vec2)) {
+    let max = size as f32 - 1.0;
+    let min = (a.min(b) - Vec2::splat(reach)).floor().max(Vec2::ZERO);
+    let high = (a.max(b) + Vec2::splat(reach)).ceil().min(Vec2::splat(max));
+    if min.x > max || min.y > max || high.x < 0.0 || high.y < 0.0 {
+        return;
+    }
+    for y in min.y as usize..=high.y as usize {
+        for x in min.x as usize..=high.x as usize {
+            f(y * size + x, Vec2::new(x as f32 + 0.5, y as f32 + 0.5));
+        }
+    }
+}
+
+/// Distance from `point` to the segment, and how far along it the closest point is
+fn segment_distance(point: Vec2, a: Vec2, b: Vec2) -> (f32, f32) {
+    let ab = b - a;
+    let t = if ab.length_squared() > 0.0 {
+        ((point - a).dot(ab) / ab.length_squared()).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    ((a + ab * t).distance(point), t)
+}
+
+/// Even-odd scanline fill of texels whose centers are inside
+fn fill_polygon(polygon: &[Vec2], size: usize, data: &mut [f32]) {
+    let scale = size as f32;
+    let points: Vec<Vec2> = polygon.iter().map(|&p| p * scale).collect();
+    let (top, bottom) = points
+        .iter()
+        .fold((f32::MAX, f32::MIN), |(top, bottom), p| {
+            (top.min(p.y), bottom.max(p.y))
+        });
+    let first_row = top.floor().max(0.0) as usize;
+    let last_row = (bottom.ceil().max(0.0) as usize).min(size);
+    let mut crossings = Vec::new();
+    for y in first_row..last_row {
+        let center = y as f32 + 0.5;
+        crossings.clear();
+        for (a, b) in points.iter().zip(points.iter().cycle().skip(1)) {
+            if (a.y <= center) != (b.y <= center) {
+                crossings.push(a.x + (center - a.y) / (b.y - a.y) * (b.x - a.x));
+            }
+        }
+        crossings.sort_by(f32::total_cmp);
+        for pair in crossings.chunks_exact(2) {
+            let start = (pair[0] - 0.5).ceil().clamp(0.0, scale) as usize;
+            let end = (pair[1] - 0.5).ceil().clamp(0.0, scale) as usize;
+            for texel in &mut data[y * size + start..y * size + end] {
+                *texel = 1.0;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quoted_brackets_and_slashes_stay_in_the_value() {
+        let extract = parse(
+            r#"<osm><way id="1">
+                <tag k="name" v="A > B / C"/>
+                <tag k='note' v='say "hi" &amp; go/>'/>
+            </way></osm>"#,
+        )
+        .unwrap();
+        let tags = &extract.ways[0].tags;
+        assert_eq!(tags["name"], "A > B / C");
+        assert_eq!(tags["note"], "say \"hi\" & go/>");
+    }
+
+    #[test]
+    fn utf8_names_are_read_whole() {
+        let extract = parse(
+            r#"<osm><way id="1">
+                <tag k="name" v="Ñandú"/>
+                <tag k="名前" v="東京"/>
+                <tag k="name:el" v='Αθήνα'/>
+            </way></osm>"#,
+        )
+        .unwrap();
+        let tags = &extract.ways[0].tags;
+        assert_eq!(tags["name"], "Ñandú");
+        assert_eq!(tags["名前"], "東京");
+        assert_eq!(tags["name:el"], "Αθήνα");
+    }
+
+    #[test]
+    fn self_closing_and_open_elements_are_both_read() {
+        let extract = parse(
+            r#"<osm>
+                <bounds minlat="1" minlon="2" maxlat="3" maxlon="4"/>
+                <node id="1" lat="1.5" lon="2.5"/>
+                <node id="2" lat="2.5" lon="3.5"><tag k="natural" v="tree"/></node>
+                <way id="10"/>
+                <way id="11"><nd ref="1"/><nd ref="2"></nd><tag k="highway" v="track"/></way>
+            </osm>"#,
+        )
+        .unwrap();
+        assert_eq!(extract.bounds, Some((1.0, 2.0, 3.0, 4.0)));
+        assert_eq!(extract.nodes[&1], (1.5, 2.5));
+        assert_eq!(extract.nodes[&2], (2.5, 3.5));
+        // The empty way has nothing to give, the node's tag isn't a way's
+        assert_eq!(extract.ways.len(), 1);
+        assert_eq!(extract.ways[0].nodes, [1, 2]);
+        assert_eq!(extract.ways[0].tags.len(), 1);
+    }
+
+    #[test]
+    fn broken_xml_is_an_error() {
+        assert!(parse(r#"<osm><way id="1"><nd ref="1"/></node></osm>"#).is_err());
+    }
+}
@@ -0,0 +1,52 @@
+//! A heat map drawn over the terrain, e.g. to show how densely something is scattered.
+//! It covers the whole terrain at any resolution and is filtered linearly.
+
+use crate::heightfield::HeightField;
+use crate::opengl::objects::Texture;
+use crate::resources::{self, texture_bytes, MemoryHandle, ResourceKind};
+
+pub struct HeatOverlay {
+    pub texture: Texture,
+    size: usize,
+    pub opacity: f32,
+    _memory: MemoryHandle,
+}
+
+impl HeatOverlay {
+    pub fn new(size: usize) -> Self {
+        let texture = Texture::new(gl::TEXTURE_2D, "Heat overlay");
+        texture.set_wrap_and_filter(gl::CLAMP_TO_EDGE, gl::LINEAR, gl::LINEAR);
+        unsafe {
+            gl::TextureStorage2D(texture.id(), 1, gl::R16, size as i32, size as i32);
+        }
+        HeatOverlay {
+            texture,
+            size,
+            opacity: 0.0,
+            _memory: resources::track(ResourceKind::Texture, texture_bytes(gl::R16, size, size, 1)),
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// `values` in [0, 1] must be the same size as the overlay
+    pub fn upload(&self, values: &HeightField) {
+        let size = values.size();
+        debug_assert_eq!(size, self.size);
+        unsafe {
+            gl::TextureSubImage2D(
+                self.texture.id(),
+                0,
+                0,
+                0,
+                size as i32,
+                size as i32,
+                gl::RED,
+                gl::UNSIGNED_SHORT,
+                values.to_u16().as_ptr() as *const _,
+            );
+        }
+    }
+}
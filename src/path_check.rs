@@ -0,0 +1,63 @@
+//! Checking that a route can be walked: click two points and the shortest path
+//! between them that isn't too steep is drawn over the terrain, if there is one.
+
+use glam::{Vec2, Vec3};
+
+use crate::path::{PathSettings, WalkPath};
+use crate::terrain::Terrain;
+
+/// Meters above the terrain the path is drawn at, so it isn't hidden in the ground
+const LINE_HEIGHT: f32 = 1.0;
+
+#[derive(Default)]
+pub struct PathCheck {
+    pub settings: PathSettings,
+    /// World space, clicked and waiting for the other end
+    pub start: Option<Vec3>,
+    /// World space ends of the last path looked for
+    pub ends: Option<(Vec2, Vec2)>,
+    /// Between `ends` in world space, None if it couldn't be walked
+    pub path: Option<WalkPath>,
+}
+
+impl PathCheck {
+    /// The first click starts a path, the second one finds it
+    pub fn click(&mut self, terrain: &Terrain, point: Vec2) {
+        match self.start.take() {
+            None => {
+                self.start = Some(Vec3::new(point.x, terrain.height_at(point), point.y));
+                self.ends = None;
+                self.path = None;
+            }
+            Some(start) => {
+                self.ends = Some((Vec2::new(start.x, start.z), point));
+                self.find(terrain);
+            }
+        }
+    }
+
+    /// Looks again between the same ends, once the terrain or the settings have changed
+    pub fn find(&mut self, terrain: &Terrain) {
+        self.path = self
+            .ends
+            .and_then(|(start, goal)| terrain.find_path(start, goal, &self.settings));
+    }
+
+    pub fn clear(&mut self) {
+        self.start = None;
+        self.ends = None;
+        self.path = None;
+    }
+
+    /// World space points of the path to draw, just above the terrain
+    pub fn line(&self) -> Vec<Vec3> {
+        match &self.path {
+            Some(path) => path
+                .points
+                .iter()
+                .map(|&point| point + Vec3::Y * LINE_HEIGHT)
+                .collect(),
+            None => vec![],
+        }
+    }
+}
@@ -0,0 +1,543 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use glam::{Mat4, Vec2, Vec3};
+use thiserror::Error;
+
+use crate::opengl::objects::{Framebuffer, Texture, VertexArray};
+use crate::opengl::shader::Program;
+use crate::resources::{self, texture_bytes, MemoryHandle, ResourceKind};
+use crate::texture::unit_to_gl_const;
+use crate::Result;
+
+/// Effects that only make sense for taking pretty pictures
+pub struct PhotoSettings {
+    pub enabled: bool,
+
+    pub dof_enabled: bool,
+    /// Where to take the focal distance from, in window uv (origin at the bottom left)
+    pub focus_uv: Vec2,
+    pub aperture: f32,
+    pub max_blur_radius: f32,
+
+    pub vignette_enabled: bool,
+    pub vignette_strength: f32,
+    pub vignette_radius: f32,
+}
+
+impl Default for PhotoSettings {
+    fn default() -> Self {
+        PhotoSettings {
+            enabled: false,
+
+            dof_enabled: true,
+            focus_uv: Vec2::new(0.5, 0.5),
+            aperture: 0.5,
+            max_blur_radius: 12.0,
+
+            vignette_enabled: true,
+            vignette_strength: 0.6,
+            vignette_radius: 0.4,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReflectionQuality {
+    Low,
+    Medium,
+    High,
+}
+
+impl ReflectionQuality {
+    pub const ALL: [ReflectionQuality; 3] = [
+        ReflectionQuality::Low,
+        ReflectionQuality::Medium,
+        ReflectionQuality::High,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ReflectionQuality::Low => "Low",
+            ReflectionQuality::Medium => "Medium",
+            ReflectionQuality::High => "High",
+        }
+    }
+
+    /// Ray march steps, each hit is refined further with a few more
+    fn steps(&self) -> i32 {
+        match self {
+            ReflectionQuality::Low => 16,
+            ReflectionQuality::Medium => 32,
+            ReflectionQuality::High => 64,
+        }
+    }
+}
+
+/// Screen-space reflections, traced against the depth buffer of the main view. There's
+/// no water yet, so for now they show up on wet ground, where puddles would collect.
+pub struct ReflectionSettings {
+    pub enabled: bool,
+    pub quality: ReflectionQuality,
+    /// 0 is dry, 1 has puddles on everything flat
+    pub wetness: f32,
+    /// How far a ray is traced, in meters
+    pub max_distance: f32,
+    /// How far behind the depth buffer a ray still counts as a hit, in meters
+    pub thickness: f32,
+}
+
+impl Default for ReflectionSettings {
+    fn default() -> Self {
+        ReflectionSettings {
+            enabled: false,
+            quality: ReflectionQuality::Medium,
+            wetness: 0.5,
+            max_distance: 300.0,
+            thickness: 3.0,
+        }
+    }
+}
+
+/// Renders the scene at a different resolution than the window and scales it to fit
+pub struct ResolutionSettings {
+    /// 0.5 to 2, 1 is the window's resolution
+    pub scale: f32,
+    /// Contrast adaptive sharpening applied when upscaling, 0 to 1
+    pub sharpening: f32,
+}
+
+impl Default for ResolutionSettings {
+    fn default() -> Self {
+        ResolutionSettings {
+            scale: 1.0,
+            sharpening: 0.5,
+        }
+    }
+}
+
+pub const LUT_DIRECTORY: &str = "textures/luts";
+
+pub struct ColorGrading {
+    pub enabled: bool,
+    pub intensity: f32,
+    /// Currently selected LUT file, if any
+    pub lut_path: Option<String>,
+    pub available_luts: Vec<String>,
+}
+
+impl Default for ColorGrading {
+    fn default() -> Self {
+        ColorGrading {
+            enabled: false,
+            intensity: 1.0,
+            lut_path: None,
+            available_luts: list_luts(),
+        }
+    }
+}
+
+/// All the .cube files and png strips in the LUT directory
+pub fn list_luts() -> Vec<String> {
+    let mut luts: Vec<String> = fs::read_dir(LUT_DIRECTORY)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| {
+                    let extension = path.extension().and_then(|e| e.to_str());
+                    matches!(extension, Some("cube") | Some("png"))
+                })
+                .map(|path| path.to_string_lossy().into_owned())
+                .collect()
+        })
+        .unwrap_or_default();
+    luts.sort();
+    luts
+}
+
+#[derive(Debug, Error)]
+pub enum LutError {
+    #[error("Couldn't read LUT file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Couldn't load LUT image: {0}")]
+    Image(#[from] image::ImageError),
+    #[error("Unsupported LUT format: {0}")]
+    Unsupported(String),
+    #[error("Malformed .cube file, line {line}: {message}")]
+    Malformed { line: usize, message: String },
+}
+
+/// 3D colour lookup table, red changes fastest, then green, then blue
+pub struct Lut {
+    pub size: usize,
+    pub data: Vec<[f32; 3]>,
+}
+
+impl Lut {
+    pub fn load(path: &str) -> std::result::Result<Self, LutError> {
+        match Path::new(path).extension().and_then(|e| e.to_str()) {
+            Some("cube") => Lut::from_cube(&fs::read_to_string(path)?),
+            Some("png") => Lut::from_strip(path),
+            _ => Err(LutError::Unsupported(path.to_owned())),
+        }
+    }
+
+    /// Parses the Adobe/Resolve .cube format (3D tables only)
+    pub fn from_cube(text: &str) -> std::result::Result<Self, LutError> {
+        let mut size = 0;
+        let mut domain_min = [0.0f32; 3];
+        let mut domain_max = [1.0f32; 3];
+        let mut data = vec![];
+
+        let parse_triple = |parts: &[&str], line: usize| {
+            let mut triple = [0.0f32; 3];
+            if parts.len() != 3 {
+                return Err(LutError::Malformed {
+                    line,
+                    message: "expected 3 values".to_owned(),
+                });
+            }
+            for (value, part) in triple.iter_mut().zip(parts) {
+                *value = part.parse().map_err(|_| LutError::Malformed {
+                    line,
+                    message: format!("'{}' is not a number", part),
+                })?;
+            }
+            Ok(triple)
+        };
+
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            match parts[0] {
+                "TITLE" => {}
+                "LUT_1D_SIZE" => {
+                    return Err(LutError::Unsupported("1D .cube LUTs".to_owned()));
+                }
+                "LUT_3D_SIZE" => {
+                    size = parts
+                        .get(1)
+                        .and_then(|s| s.parse().ok())
+                        .filter(|&size| size >= 2)
+                        .ok_or_else(|| LutError::Malformed {
+                            line: line_number,
+                            message: "invalid LUT_3D_SIZE".to_owned(),
+                        })?;
+                }
+                "DOMAIN_MIN" => domain_min = parse_triple(&parts[1..], line_number)?,
+                "DOMAIN_MAX" => domain_max = parse_triple(&parts[1..], line_number)?,
+                _ => data.push(parse_triple(&parts, line_number)?),
+            }
+        }
+
+        if size == 0 {
+            return Err(LutError::Malformed {
+                line: 0,
+                message: "LUT_3D_SIZE is missing".to_owned(),
+            });
+        }
+        if data.len() != size * size * size {
+            return Err(LutError::Malformed {
+                line: 0,
+                message: format!(
+                    "expected {} entries, got {}",
+                    size * size * size,
+                    data.len()
+                ),
+            });
+        }
+
+        // We always sample in [0, 1]
+        for entry in data.iter_mut() {
+            for c in 0..3 {
+                entry[c] = (entry[c] - domain_min[c]) / (domain_max[c] - domain_min[c]);
+            }
+        }
+
+        Ok(Lut { size, data })
+    }
+
+    /// Loads a horizontal strip of `size` slices, each `size` x `size` pixels.
+    /// Red goes along x inside each slice, green goes down, blue changes from slice to slice.
+    pub fn from_strip(path: &str) -> std::result::Result<Self, LutError> {
+        let img = image::open(path)?.into_rgb8();
+        let (width, height) = img.dimensions();
+        let size = height as usize;
+        if size < 2 || width as usize != size * size {
+            return Err(LutError::Unsupported(format!(
+                "{}x{} strip, expected {}x{}",
+                width,
+                height,
+                size * size,
+                size
+            )));
+        }
+
+        let mut data = Vec::with_capacity(size * size * size);
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    let pixel = img.get_pixel((b * size + r) as u32, g as u32);
+                    data.push([
+                        pixel[0] as f32 / 255.0,
+                        pixel[1] as f32 / 255.0,
+                        pixel[2] as f32 / 255.0,
+                    ]);
+                }
+            }
+        }
+
+        Ok(Lut { size, data })
+    }
+
+    fn upload(&self) -> Texture {
+        let size = self.size as i32;
+        let texture = Texture::new(gl::TEXTURE_3D, "Color grading LUT");
+        texture.set_wrap_and_filter(gl::CLAMP_TO_EDGE, gl::LINEAR, gl::LINEAR);
+        unsafe {
+            gl::TextureStorage3D(texture.id(), 1, gl::RGB16F, size, size, size);
+            gl::TextureSubImage3D(
+                texture.id(),
+                0,
+                0,
+                0,
+                0,
+                size,
+                size,
+                size,
+                gl::RGB,
+                gl::FLOAT,
+                self.data.as_ptr() as *const _,
+            );
+        }
+        texture
+    }
+}
+
+/// Offscreen target the scene is rendered into when there are post effects to apply
+pub struct PostProcess {
+    target: RenderTarget,
+    window_width: i32,
+    window_height: i32,
+    scale: f32,
+
+    // Attributeless fullscreen triangle still needs a vao bound
+    vao: VertexArray,
+    shader: Program,
+
+    // Currently loaded colour grading table
+    lut_texture: Option<(Texture, MemoryHandle)>,
+}
+
+struct RenderTarget {
+    fbo: Framebuffer,
+    color_texture: Texture,
+    depth_texture: Texture,
+    height: i32,
+    _memory: MemoryHandle,
+}
+
+impl RenderTarget {
+    fn new(width: i32, height: i32) -> Self {
+        let color_texture = Texture::new(gl::TEXTURE_2D, "Post process color");
+        color_texture.set_wrap_and_filter(gl::CLAMP_TO_EDGE, gl::LINEAR, gl::LINEAR);
+        let depth_texture = Texture::new(gl::TEXTURE_2D, "Post process depth");
+        depth_texture.set_wrap_and_filter(gl::CLAMP_TO_EDGE, gl::NEAREST, gl::NEAREST);
+        let fbo = Framebuffer::new("Post process");
+        unsafe {
+            gl::TextureStorage2D(color_texture.id(), 1, gl::RGBA16F, width, height);
+            gl::TextureStorage2D(depth_texture.id(), 1, gl::DEPTH_COMPONENT24, width, height);
+
+            fbo.attach_texture(gl::COLOR_ATTACHMENT0, &color_texture);
+            fbo.attach_texture(gl::DEPTH_ATTACHMENT, &depth_texture);
+            let draw_buffers = [gl::COLOR_ATTACHMENT0];
+            gl::NamedFramebufferDrawBuffers(fbo.id(), 1, draw_buffers.as_ptr() as *const _);
+            assert_eq!(
+                gl::CheckNamedFramebufferStatus(fbo.id(), gl::FRAMEBUFFER),
+                gl::FRAMEBUFFER_COMPLETE,
+                "Post process framebuffer is incomplete",
+            );
+        }
+        RenderTarget {
+            fbo,
+            color_texture,
+            depth_texture,
+            height,
+            _memory: resources::track(
+                ResourceKind::RenderTarget,
+                texture_bytes(gl::RGBA16F, width as usize, height as usize, 1)
+                    + texture_bytes(gl::DEPTH_COMPONENT24, width as usize, height as usize, 1),
+            ),
+        }
+    }
+}
+
+impl PostProcess {
+    pub fn new(width: usize, height: usize) -> Result<Self> {
+        let (width, height) = (width as i32, height as i32);
+        let vao = VertexArray::new("Fullscreen triangle");
+
+        let shader = Program::new()
+            .vertex_shader(include_str!("shaders/post/fullscreen.vert"))?
+            .fragment_shader(include_str!("shaders/post/composite.frag"))?
+            .link()?;
+
+        Ok(PostProcess {
+            target: RenderTarget::new(width, height),
+            window_width: width,
+            window_height: height,
+            scale: 1.0,
+
+            vao,
+            shader,
+
+            lut_texture: None,
+        })
+    }
+
+    /// Whether there's anything to do at all, otherwise the scene can go straight to the window
+    pub fn is_active(
+        &self,
+        photo: &PhotoSettings,
+        grading: &ColorGrading,
+        reflections: &ReflectionSettings,
+    ) -> bool {
+        photo.enabled
+            || (grading.enabled && self.lut_texture.is_some())
+            || reflections.enabled
+            || self.scale != 1.0
+    }
+
+    /// Resizes the offscreen target if the scale changed
+    pub fn set_render_scale(&mut self, scale: f32) {
+        if scale != self.scale {
+            let size = |window_size: i32| ((window_size as f32 * scale).round() as i32).max(1);
+            self.target = RenderTarget::new(size(self.window_width), size(self.window_height));
+            self.scale = scale;
+        }
+    }
+
+    /// How much larger the offscreen target is than the window
+    pub fn render_scale(&self) -> f32 {
+        self.scale
+    }
+
+    pub fn target_height(&self) -> u32 {
+        self.target.height as u32
+    }
+
+    /// LUTs are parsed on the IO thread, only the upload happens here
+    pub fn set_lut(&mut self, lut: &Lut) {
+        let size = lut.size;
+        let memory = resources::track(ResourceKind::Texture, size * size * size * 8);
+        self.lut_texture = Some((lut.upload(), memory));
+    }
+
+    /// Everything drawn after this goes into the offscreen target
+    pub fn begin(&self) {
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.target.fbo.id());
+            gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+        }
+    }
+
+    /// Draws the offscreen target to the window applying the effects.
+    /// `proj` and `view` are of the view the focus point and reflections belong to.
+    pub fn present(
+        &self,
+        photo: &PhotoSettings,
+        grading: &ColorGrading,
+        reflections: &ReflectionSettings,
+        resolution: &ResolutionSettings,
+        proj: &Mat4,
+        view: &Mat4,
+    ) -> Result<()> {
+        let shader = &self.shader;
+        shader.set_used();
+        shader.set_mat4("proj", proj)?;
+        shader.set_mat4("inv_proj", &proj.inverse())?;
+        shader.set_i32("ssr_enabled", reflections.enabled as i32)?;
+        shader.set_i32("ssr_steps", reflections.quality.steps())?;
+        shader.set_f32("ssr_max_distance", reflections.max_distance)?;
+        shader.set_f32("ssr_thickness", reflections.thickness)?;
+        shader.set_f32("wetness", reflections.wetness)?;
+        shader.set_vec3("view_up", &view.transform_vector3(Vec3::Y))?;
+        shader.set_i32("dof_enabled", (photo.enabled && photo.dof_enabled) as i32)?;
+        shader.set_vec2("focus_uv", &photo.focus_uv)?;
+        shader.set_f32("aperture", photo.aperture)?;
+        shader.set_f32("max_blur_radius", photo.max_blur_radius)?;
+        shader.set_i32(
+            "vignette_enabled",
+            (photo.enabled && photo.vignette_enabled) as i32,
+        )?;
+        shader.set_f32("vignette_strength", photo.vignette_strength)?;
+        shader.set_f32("vignette_radius", photo.vignette_radius)?;
+        let grading_enabled = grading.enabled && self.lut_texture.is_some();
+        shader.set_i32("grading_enabled", grading_enabled as i32)?;
+        shader.set_f32("grading_intensity", grading.intensity)?;
+        // Only worth it when there are fewer pixels than in the window
+        let sharpening = if self.scale < 1.0 {
+            resolution.sharpening
+        } else {
+            0.0
+        };
+        shader.set_f32("sharpening", sharpening)?;
+
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+            gl::Viewport(0, 0, self.window_width, self.window_height);
+            gl::Disable(gl::DEPTH_TEST);
+
+            gl::ActiveTexture(unit_to_gl_const(0));
+            gl::BindTexture(gl::TEXTURE_2D, self.target.color_texture.id());
+            gl::ActiveTexture(unit_to_gl_const(1));
+            gl::BindTexture(gl::TEXTURE_2D, self.target.depth_texture.id());
+            if let Some((lut_texture, _)) = &self.lut_texture {
+                gl::ActiveTexture(unit_to_gl_const(2));
+                gl::BindTexture(gl::TEXTURE_3D, lut_texture.id());
+            }
+
+            gl::BindVertexArray(self.vao.id());
+            gl::DrawArrays(gl::TRIANGLES, 0, 3);
+
+            gl::Enable(gl::DEPTH_TEST);
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads back the window contents. Rows are bottom to top the way GL stores them.
+/// Should be called before the GUI is drawn so it doesn't end up in the picture.
+pub fn read_screen(width: usize, height: usize) -> image::RgbImage {
+    let mut pixels = vec![0u8; width * height * 3];
+    unsafe {
+        gl::BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
+        gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+        gl::ReadPixels(
+            0,
+            0,
+            width as i32,
+            height as i32,
+            gl::RGB,
+            gl::UNSIGNED_BYTE,
+            pixels.as_mut_ptr() as *mut _,
+        );
+        gl::PixelStorei(gl::PACK_ALIGNMENT, 4);
+    }
+    image::RgbImage::from_raw(width as u32, height as u32, pixels)
+        .expect("Screenshot buffer has the wrong size")
+}
+
+pub fn screenshot_path() -> PathBuf {
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    PathBuf::from(format!("screenshots/screenshot_{}.png", timestamp))
+}
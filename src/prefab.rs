@@ -0,0 +1,181 @@
+//! Groups of placed objects saved together, optionally with the terrain and
+//! splat weights under them, so that a village or a camp can be placed again
+//! anywhere with one click.
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use glam::{Mat4, Vec2, Vec3};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::heightfield::HeightField;
+use crate::splat::NUM_LAYERS;
+use crate::stamp::file_stem;
+use crate::terrain::Terrain;
+
+pub const PREFAB_DIRECTORY: &str = "prefabs";
+
+/// Fraction of a pasted patch that blends into the terrain around it
+pub const PATCH_FEATHER: f32 = 0.2;
+
+#[derive(Debug, Error)]
+pub enum PrefabError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Not a valid prefab: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PrefabObject {
+    /// glTF file the object's model is loaded from
+    pub model: String,
+    /// Relative to the ground at the prefab center
+    pub transform: Mat4,
+}
+
+/// A square of terrain centered on the prefab
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TerrainPatch {
+    /// Side of the square in meters
+    pub world_size: f32,
+    /// Texels per side
+    pub size: usize,
+    /// Meters relative to the ground at the center, row by row
+    pub heights: Option<Vec<f32>>,
+    /// Layer weights, row by row
+    pub splat: Option<Vec<[f32; NUM_LAYERS]>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Prefab {
+    pub objects: Vec<PrefabObject>,
+    pub terrain: Option<TerrainPatch>,
+}
+
+pub struct PrefabSettings {
+    /// What the next captured prefab will be called
+    pub name: String,
+    pub include_heights: bool,
+    pub include_splat: bool,
+    /// Names of the prefabs already saved
+    pub library: Vec<String>,
+    /// What the place tool puts down
+    pub selected: Option<String>,
+}
+
+impl Default for PrefabSettings {
+    fn default() -> Self {
+        PrefabSettings {
+            name: "prefab".to_owned(),
+            include_heights: true,
+            include_splat: true,
+            library: list(),
+            selected: None,
+        }
+    }
+}
+
+/// Everything in the square `size` meters across centered on a world space point.
+/// `objects` are the model paths and transforms of the placed objects.
+pub fn capture<'a>(
+    terrain: &Terrain,
+    center: Vec2,
+    size: f32,
+    objects: impl Iterator<Item = (&'a str, Mat4)>,
+    settings: &PrefabSettings,
+) -> Prefab {
+    let ground = terrain.height_at(center);
+    let origin = Mat4::from_translation(Vec3::new(center.x, ground, center.y)).inverse();
+    let half_size = size / 2.0;
+    let objects = objects
+        .filter(|(_, transform)| {
+            let position = transform.w_axis;
+            (position.x - center.x).abs() <= half_size && (position.z - center.y).abs() <= half_size
+        })
+        .map(|(model, transform)| PrefabObject {
+            model: model.to_owned(),
+            transform: origin * transform,
+        })
+        .collect();
+
+    let terrain = if settings.include_heights || settings.include_splat {
+        let heights = terrain.heights_around(center, size);
+        let max_height = terrain.max_height();
+        Some(TerrainPatch {
+            world_size: size,
+            size: heights.size(),
+            heights: if settings.include_heights {
+                Some(
+                    heights
+                        .data()
+                        .iter()
+                        .map(|&h| h * max_height - ground)
+                        .collect(),
+                )
+            } else {
+                None
+            },
+            splat: if settings.include_splat {
+                Some(terrain.splat_around(center, size).1)
+            } else {
+                None
+            },
+        })
+    } else {
+        None
+    };
+
+    Prefab { objects, terrain }
+}
+
+impl TerrainPatch {
+    /// The heights in meters as a field that can be sampled
+    pub fn height_field(&self) -> Option<HeightField> {
+        let heights = self.heights.as_ref()?;
+        Some(HeightField::from_data(self.size, heights.clone()))
+    }
+
+    /// Closest texel of `splat`, `uv` is in [0, 1] over the patch
+    pub fn weights_at(&self, splat: &[[f32; NUM_LAYERS]], uv: Vec2) -> [f32; NUM_LAYERS] {
+        let max = self.size - 1;
+        let x = ((uv.x * self.size as f32) as usize).min(max);
+        let y = ((uv.y * self.size as f32) as usize).min(max);
+        splat[y * self.size + x]
+    }
+}
+
+pub fn save(name: &str, prefab: &Prefab) -> Result<PathBuf, PrefabError> {
+    fs::create_dir_all(PREFAB_DIRECTORY)?;
+    let path = path(name);
+    fs::write(&path, serde_json::to_string(prefab)?)?;
+    Ok(path)
+}
+
+pub fn load(name: &str) -> Result<Prefab, PrefabError> {
+    Ok(serde_json::from_str(&fs::read_to_string(path(name))?)?)
+}
+
+/// Names of all the saved prefabs
+pub fn list() -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(PREFAB_DIRECTORY)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| path.extension().and_then(|e| e.to_str()) == Some("json"))
+                .filter_map(|path| Some(path.file_stem()?.to_string_lossy().into_owned()))
+                .collect()
+        })
+        .unwrap_or_default();
+    names.sort();
+    names
+}
+
+fn path(name: &str) -> PathBuf {
+    PathBuf::from(PREFAB_DIRECTORY)
+        .join(file_stem(name))
+        .with_extension("json")
+}
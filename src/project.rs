@@ -0,0 +1,334 @@
+//! Projects come in two styles. A directory project is config.json plus assets
+//! referenced by paths relative to it: that's what the editor works on, and it
+//! plays well with version control. An archive is a single zip with the config
+//! and every asset embedded, for passing a project around.
+//!
+//! Archives are read from and written to a `Storage`, which can be shared by a team.
+//!
+//! Entries are compressed with zstd. PNG heightmaps are stored through `codec`, and the
+//! splat and buildable mask PNGs saved next to them as raw pixels, both of which zstd
+//! gets a lot smaller than PNG's own deflate. They're turned back into PNGs when unpacked.
+
+use std::fs;
+use std::io::{self, Cursor, Read, Write};
+use std::path::{Component, Path, PathBuf};
+
+use image::{DynamicImage, ImageBuffer, ImageError, Luma, Rgba};
+use thiserror::Error;
+use zip::result::ZipError;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::codec::{self, LOSSLESS_BITS};
+use crate::config::{Config, ConfigError};
+use crate::storage::{Storage, StorageError};
+
+pub const ARCHIVE_PATH: &str = "project.terrain";
+
+/// Archives recently exported or imported, most recent first, whatever storage they're in
+const RECENT_PATH: &str = "recent_projects.json";
+const MAX_RECENT: usize = 8;
+
+const CONFIG_ENTRY: &str = "config.json";
+
+/// Added to the name of a heightmap entry packed with `codec`
+const CODEC_EXTENSION: &str = "thm";
+
+/// Added to the name of a layer entry stored as raw pixels
+const RAW_EXTENSION: &str = "raw";
+const RAW_MAGIC: &[u8; 4] = b"TLR1";
+/// Magic, width, height and channels
+const RAW_HEADER_SIZE: usize = 13;
+
+/// Maps saved next to the heightmap as `<heightmap>_<suffix>.png` that go into archives
+const LAYER_SUFFIXES: [&str; 2] = ["splat", "buildable"];
+
+#[derive(Debug, Error)]
+pub enum ProjectError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Broken archive: {0}")]
+    Zip(#[from] ZipError),
+    #[error("{0}")]
+    Config(#[from] ConfigError),
+    #[error("{0}")]
+    Storage(#[from] StorageError),
+    #[error("Archive has no {0}")]
+    MissingConfig(&'static str),
+    #[error("Archive entry '{0}' points outside the project")]
+    UnsafePath(String),
+    #[error("Heightmap: {0}")]
+    Image(#[from] ImageError),
+    #[error("Archive entry '{0}' isn't a packed heightmap")]
+    BrokenHeightmap(String),
+    #[error("Archive entry '{0}' isn't a packed layer")]
+    BrokenLayer(String),
+    #[error("Heightmap is {0}x{1}, only square ones can be packed")]
+    NotSquare(u32, u32),
+}
+
+/// How a heightmap goes into an archive
+#[derive(Debug, Clone, Copy)]
+pub struct PackSettings {
+    /// Precision kept, 16 is lossless
+    pub heightmap_bits: u8,
+}
+
+impl Default for PackSettings {
+    fn default() -> Self {
+        PackSettings {
+            heightmap_bits: LOSSLESS_BITS,
+        }
+    }
+}
+
+/// What an export came to
+#[derive(Debug, Clone, Copy)]
+pub struct PackReport {
+    /// The heightmap as plain 16-bit texels, none if there wasn't one
+    pub heightmap_bytes: Option<u64>,
+    /// The splat and mask layers as plain pixels
+    pub layer_bytes: u64,
+    pub archive_bytes: u64,
+    pub heightmap_bits: u8,
+}
+
+/// Writes the config and every asset it references into a single archive.
+/// Assets outside the project directory are embedded under assets/
+/// and the archived config points at the copies, layers go next to the heightmap.
+pub fn pack(
+    project_dir: &Path,
+    config: &Config,
+    storage: &dyn Storage,
+    archive: &str,
+    settings: PackSettings,
+) -> Result<PackReport, ProjectError> {
+    let mut config = config.clone();
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = FileOptions::default().compression_method(CompressionMethod::Zstd);
+    let mut heightmap_bytes = None;
+    let mut layer_bytes = 0;
+
+    let heightmap = project_dir.join(&config.heightmap_path);
+    // A flat terrain that's never been saved has no heightmap yet
+    if !config.start_with_flat_terrain && heightmap.exists() {
+        let entry = embedded_name(&config.heightmap_path);
+        let is_png = heightmap
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("png"));
+        if is_png {
+            let pixels = image::open(&heightmap)?.into_luma16();
+            let (width, height) = pixels.dimensions();
+            if width != height {
+                return Err(ProjectError::NotSquare(width, height));
+            }
+            let size = width as usize;
+            heightmap_bytes = Some((size * size * 2) as u64);
+            let packed = codec::encode(pixels.as_raw(), size, settings.heightmap_bits);
+            zip.start_file(format!("{}.{}", entry, CODEC_EXTENSION), options)?;
+            zip.write_all(&packed)?;
+        } else {
+            // GeoTIFFs go in as they are, decoding them would lose their tags
+            zip.start_file(entry.as_str(), options)?;
+            zip.write_all(&fs::read(&heightmap)?)?;
+        }
+
+        let stem = heightmap.file_stem().unwrap_or_default().to_string_lossy();
+        for suffix in &LAYER_SUFFIXES {
+            let name = format!("{}_{}.png", stem, suffix);
+            let layer = heightmap.with_file_name(&name);
+            if !layer.exists() {
+                continue;
+            }
+            let (raw, bytes) = encode_raw(image::open(&layer)?);
+            layer_bytes += bytes;
+            zip.start_file(
+                format!("{}.{}", sibling_entry(&entry, &name), RAW_EXTENSION),
+                options,
+            )?;
+            zip.write_all(&raw)?;
+        }
+        config.heightmap_path = entry;
+    }
+
+    zip.start_file(CONFIG_ENTRY, options)?;
+    zip.write_all(config.to_json().as_bytes())?;
+    let bytes = zip.finish()?.into_inner();
+    storage.write(archive, &bytes)?;
+    Ok(PackReport {
+        heightmap_bytes,
+        layer_bytes,
+        archive_bytes: bytes.len() as u64,
+        heightmap_bits: settings.heightmap_bits,
+    })
+}
+
+/// Extracts an archive into a directory project, overwriting what's there
+pub fn unpack(
+    storage: &dyn Storage,
+    archive: &str,
+    project_dir: &Path,
+) -> Result<Config, ProjectError> {
+    let mut zip = ZipArchive::new(Cursor::new(storage.read(archive)?))?;
+    let mut config = None;
+    for i in 0..zip.len() {
+        let mut entry = zip.by_index(i)?;
+        if entry.is_dir() {
+            continue;
+        }
+        let name = entry
+            .enclosed_name()
+            .map(Path::to_path_buf)
+            .ok_or_else(|| ProjectError::UnsafePath(entry.name().to_owned()))?;
+        let mut bytes = Vec::with_capacity(entry.size() as usize);
+        entry.read_to_end(&mut bytes)?;
+
+        if name == Path::new(CONFIG_ENTRY) {
+            config = Some(Config::from_json(&String::from_utf8_lossy(&bytes))?);
+        }
+        let path = project_dir.join(&name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        if name.extension().is_some_and(|e| e == CODEC_EXTENSION) {
+            let broken = || ProjectError::BrokenHeightmap(entry.name().to_owned());
+            let (pixels, size) = codec::decode(&bytes).ok_or_else(broken)?;
+            let image: ImageBuffer<Luma<u16>, Vec<u16>> =
+                ImageBuffer::from_raw(size as u32, size as u32, pixels).ok_or_else(broken)?;
+            // Without the codec extension, which is where the config points
+            image.save(path.with_extension(""))?;
+        } else if name.extension().is_some_and(|e| e == RAW_EXTENSION) {
+            let broken = || ProjectError::BrokenLayer(entry.name().to_owned());
+            decode_raw(&bytes)
+                .ok_or_else(broken)?
+                .save(path.with_extension(""))?;
+        } else {
+            fs::write(path, bytes)?;
+        }
+    }
+    config.ok_or(ProjectError::MissingConfig(CONFIG_ENTRY))
+}
+
+/// Empty if the list was never saved or can't be read, it's only a convenience
+pub fn recent() -> Vec<String> {
+    fs::read_to_string(RECENT_PATH)
+        .ok()
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
+
+/// Moves `archive` to the top of the recent list and returns the new list
+pub fn remember(archive: &str) -> Vec<String> {
+    let archive = archive.to_owned();
+    let mut recent = recent();
+    recent.retain(|path| *path != archive);
+    recent.insert(0, archive);
+    recent.truncate(MAX_RECENT);
+    if let Ok(json) = serde_json::to_string_pretty(&recent) {
+        fs::write(RECENT_PATH, json).ok();
+    }
+    recent
+}
+
+/// Whether the terrain has edits that aren't on disk yet. Edits are counted, so that
+/// one made while a save is still being written keeps the project unsaved.
+#[derive(Default)]
+pub struct Changes {
+    edits: u64,
+    saved: u64,
+    /// Edits the save being written includes
+    saving: Option<u64>,
+}
+
+impl Changes {
+    pub fn edited(&mut self) {
+        self.edits += 1;
+    }
+
+    pub fn save_started(&mut self) {
+        self.saving = Some(self.edits);
+    }
+
+    pub fn save_finished(&mut self) {
+        if let Some(saving) = self.saving.take() {
+            self.saved = saving;
+        }
+    }
+
+    pub fn save_failed(&mut self) {
+        self.saving = None;
+    }
+
+    /// A project was just created or loaded
+    pub fn reset(&mut self) {
+        *self = Changes::default();
+    }
+
+    /// Every edit made since the editor started, saved or not
+    pub fn edits(&self) -> u64 {
+        self.edits
+    }
+
+    pub fn unsaved(&self) -> bool {
+        self.edits != self.saved
+    }
+}
+
+/// Pixels with a small header, and how many bytes the pixels alone take. Splats keep
+/// all four channels, masks and anything else without colour only one.
+fn encode_raw(image: DynamicImage) -> (Vec<u8>, u64) {
+    let color = image.color();
+    let (width, height) = (image.width(), image.height());
+    let (channels, pixels) = if color.has_color() || color.has_alpha() {
+        (4, image.into_rgba8().into_raw())
+    } else {
+        (1, image.into_luma8().into_raw())
+    };
+    let mut bytes = Vec::with_capacity(RAW_HEADER_SIZE + pixels.len());
+    bytes.extend_from_slice(RAW_MAGIC);
+    bytes.extend_from_slice(&width.to_le_bytes());
+    bytes.extend_from_slice(&height.to_le_bytes());
+    bytes.push(channels);
+    bytes.extend_from_slice(&pixels);
+    (bytes, pixels.len() as u64)
+}
+
+/// None if the bytes aren't from `encode_raw`
+fn decode_raw(bytes: &[u8]) -> Option<DynamicImage> {
+    if bytes.len() < RAW_HEADER_SIZE || &bytes[..4] != RAW_MAGIC {
+        return None;
+    }
+    let width = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+    let height = u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]);
+    let pixels = bytes[RAW_HEADER_SIZE..].to_vec();
+    match bytes[12] {
+        1 => ImageBuffer::<Luma<u8>, _>::from_raw(width, height, pixels)
+            .map(DynamicImage::ImageLuma8),
+        4 => ImageBuffer::<Rgba<u8>, _>::from_raw(width, height, pixels)
+            .map(DynamicImage::ImageRgba8),
+        _ => None,
+    }
+}
+
+/// An entry in the same directory as `entry`
+fn sibling_entry(entry: &str, name: &str) -> String {
+    match entry.rfind('/') {
+        Some(slash) => format!("{}/{}", &entry[..slash], name),
+        None => name.to_owned(),
+    }
+}
+
+/// Relative paths inside the project stay as they are, anything else goes to assets/
+fn embedded_name(path: &str) -> String {
+    let path = Path::new(path);
+    let inside = path
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+    let name = if inside {
+        path.to_path_buf()
+    } else {
+        PathBuf::from("assets").join(path.file_name().unwrap_or_default())
+    };
+    // Zip entries always use forward slashes
+    name.to_string_lossy().replace('\\', "/")
+}
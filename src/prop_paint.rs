@@ -0,0 +1,110 @@
+//! Paint on placed objects, so that rocks and ruins pick up the same dirt and moss as
+//! the ground around them. Every vertex has a color laid over the object's texture,
+//! its alpha being how much of the texture it covers. The texture brush can paint its
+//! layer onto the objects under it along with the terrain, and blending into the
+//! ground gives the bottom of an object the color of the terrain it stands on.
+//!
+//! The intersection blend does the same in the shader for every object and rock as
+//! it's drawn, so it follows the terrain through sculpting and painting.
+
+use glam::Vec3;
+use serde::{Deserialize, Serialize};
+
+use crate::opengl::shader::Program;
+use crate::Result;
+
+/// Nothing painted, the texture shows as it is
+pub const NO_PAINT: [f32; 4] = [0.0; 4];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct GroundBlend {
+    /// Meters above the ground that the ground color reaches up to
+    pub height: f32,
+    /// How much of the texture the ground color covers at the ground itself
+    pub strength: f32,
+}
+
+impl Default for GroundBlend {
+    fn default() -> Self {
+        GroundBlend {
+            height: 1.5,
+            strength: 0.8,
+        }
+    }
+}
+
+impl GroundBlend {
+    /// How far towards the ground color a vertex `height` meters above the ground goes
+    pub fn amount(&self, height: f32) -> f32 {
+        let t = (height / self.height.max(0.01)).clamp(0.0, 1.0);
+        // Eased, so that there's no line where it stops
+        self.strength * (1.0 - t * t * (3.0 - 2.0 * t))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PropPaintSettings {
+    /// The texture brush paints the placed objects under it too
+    pub with_brush: bool,
+    pub ground_blend: GroundBlend,
+}
+
+impl Default for PropPaintSettings {
+    fn default() -> Self {
+        PropPaintSettings {
+            with_brush: false,
+            ground_blend: GroundBlend::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IntersectionBlend {
+    pub enabled: bool,
+    /// Meters up an object that the ground color reaches
+    pub height: f32,
+    /// How much of the ground's shading comes with its color, at 1 the bottom of an
+    /// object is lit as the terrain is
+    pub shading: f32,
+}
+
+impl Default for IntersectionBlend {
+    fn default() -> Self {
+        IntersectionBlend {
+            enabled: true,
+            height: 0.4,
+            shading: 0.7,
+        }
+    }
+}
+
+impl IntersectionBlend {
+    /// `shader` has to be built with `GROUND_BLEND`, see simple.frag
+    pub fn set_uniforms(&self, shader: &Program) -> Result<()> {
+        shader.set_used();
+        let height = if self.enabled { self.height } else { 0.0 };
+        shader.set_f32("ground_blend_height", height)?;
+        shader.set_f32("ground_blend_shading", self.shading)?;
+        Ok(())
+    }
+}
+
+/// Takes a vertex's paint `amount` of the way to `color` covering `opacity` of the
+/// texture
+pub fn paint(paint: &mut [f32; 4], color: Vec3, opacity: f32, amount: f32) {
+    let amount = amount.clamp(0.0, 1.0);
+    let old = Vec3::new(paint[0], paint[1], paint[2]);
+    // The color of paint that's all been erased doesn't count
+    let color = if paint[3] > 0.0 {
+        old.lerp(color, amount)
+    } else {
+        color
+    };
+    let alpha = paint[3] + (opacity - paint[3]) * amount;
+    *paint = [color.x, color.y, color.z, alpha];
+}
+
+/// Takes the paint `amount` of the way to not covering the texture at all
+pub fn erase(paint: &mut [f32; 4], amount: f32) {
+    paint[3] *= 1.0 - amount.clamp(0.0, 1.0);
+}
@@ -1,6 +1,6 @@
 use std::ops::Index;
 
-use glam::Vec3;
+use glam::{Vec2, Vec3, Vec3Swizzles};
 
 const EPSILON: f32 = 0.00001;
 
@@ -96,6 +96,54 @@ impl Ray {
         })
     }
 
+    /// Where the ray first goes below `height`, a function of the horizontal position,
+    /// within `bounds`. It's marched in steps of half of `spacing` horizontally, so no
+    /// bump that wide is stepped over, then bisected. A ray that's already below the
+    /// surface where it enters `bounds` hits it right there.
+    pub fn hits_height(
+        &self,
+        bounds: &AABB,
+        spacing: f32,
+        height: impl Fn(Vec2) -> f32,
+    ) -> Option<Vec3> {
+        const MIN_HORIZONTAL: f32 = 0.001;
+        let hit = self.hits_aabb(bounds)?;
+
+        // Straight down a step would be endless, the bounds stop it anyway
+        let horizontal = self.direction.xz().length().max(MIN_HORIZONTAL);
+        let step = 0.5 * spacing / horizontal;
+        let above = |t: f32| {
+            let point = self.get_point_at(t);
+            point.y - height(point.xz())
+        };
+        let (mut t0, mut t1) = (hit.t_min, hit.t_min);
+        if above(t0) > 0.0 {
+            loop {
+                if t1 >= hit.t_max {
+                    return None;
+                }
+                t0 = t1;
+                t1 = (t1 + step).min(hit.t_max);
+                if above(t1) <= 0.0 {
+                    break;
+                }
+            }
+            for _ in 0..16 {
+                let t = (t0 + t1) / 2.0;
+                if above(t) > 0.0 {
+                    t0 = t;
+                } else {
+                    t1 = t;
+                }
+            }
+        }
+        Some(self.get_point_at(t1))
+    }
+
+    pub fn direction(&self) -> Vec3 {
+        self.direction
+    }
+
     pub fn get_point_at(&self, t: f32) -> Vec3 {
         self.origin + self.direction * t
     }
@@ -166,3 +214,89 @@ impl Default for AABB {
         AABB::empty()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bounds() -> AABB {
+        AABB::new(Vec3::new(0.0, -1.0, 0.0), Vec3::new(100.0, 10.0, 100.0))
+    }
+
+    fn flat(_: Vec2) -> f32 {
+        0.0
+    }
+
+    fn assert_near(a: Vec3, b: Vec3, tolerance: f32) {
+        assert!(
+            (a - b).abs().max_element() <= tolerance,
+            "{} isn't {}",
+            a,
+            b
+        );
+    }
+
+    #[test]
+    fn hits_flat_ground() {
+        let ray = Ray::new(Vec3::new(10.0, 5.0, 10.0), Vec3::new(1.0, -1.0, 0.0));
+        let hit = ray.hits_height(&bounds(), 1.0, flat).unwrap();
+        assert_near(hit, Vec3::new(15.0, 0.0, 10.0), 1e-3);
+    }
+
+    #[test]
+    fn straight_down_hits_right_below() {
+        let ray = Ray::new(Vec3::new(20.0, 5.0, 30.0), Vec3::new(0.0, -1.0, 0.0));
+        let hit = ray.hits_height(&bounds(), 1.0, |_| 2.0).unwrap();
+        assert_near(hit, Vec3::new(20.0, 2.0, 30.0), 1e-3);
+    }
+
+    #[test]
+    fn grazing_rays_hit_far_away() {
+        let direction = Vec3::new(1.0, -0.001, 0.0);
+        let ray = Ray::new(Vec3::new(0.0, 0.05, 50.0), direction);
+        let hit = ray.hits_height(&bounds(), 1.0, flat).unwrap();
+        assert_near(hit, Vec3::new(50.0, 0.0, 50.0), 1e-3);
+    }
+
+    #[test]
+    fn grazing_rays_dont_step_over_narrow_bumps() {
+        let bump = |p: Vec2| {
+            if (50.0..51.0).contains(&p.x) {
+                1.0
+            } else {
+                0.0
+            }
+        };
+        let ray = Ray::new(Vec3::new(0.0, 0.5, 50.0), Vec3::new(1.0, -0.0001, 0.0));
+        let hit = ray.hits_height(&bounds(), 1.0, bump).unwrap();
+        assert!((hit.x - 50.0).abs() < 1e-3, "{}", hit);
+    }
+
+    #[test]
+    fn rays_from_below_hit_where_they_start() {
+        let origin = Vec3::new(10.0, -0.5, 10.0);
+        let ray = Ray::new(origin, Vec3::new(0.3, 1.0, 0.2));
+        assert_eq!(ray.hits_height(&bounds(), 1.0, flat), Some(origin));
+    }
+
+    #[test]
+    fn rays_from_below_outside_hit_where_they_enter() {
+        let ray = Ray::new(Vec3::new(-10.0, -0.5, 10.0), Vec3::new(1.0, 0.0, 0.0));
+        let hit = ray.hits_height(&bounds(), 1.0, flat).unwrap();
+        assert_near(hit, Vec3::new(0.0, -0.5, 10.0), 1e-4);
+    }
+
+    #[test]
+    fn rays_going_away_miss() {
+        let up = Ray::new(Vec3::new(10.0, 5.0, 10.0), Vec3::new(0.2, 1.0, 0.0));
+        assert_eq!(up.hits_height(&bounds(), 1.0, flat), None);
+        let outside = Ray::new(Vec3::new(-10.0, 5.0, 10.0), Vec3::new(-1.0, -1.0, 0.0));
+        assert_eq!(outside.hits_height(&bounds(), 1.0, flat), None);
+    }
+
+    #[test]
+    fn rays_level_above_the_ground_miss() {
+        let ray = Ray::new(Vec3::new(0.0, 0.5, 50.0), Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!(ray.hits_height(&bounds(), 1.0, flat), None);
+    }
+}
@@ -0,0 +1,155 @@
+//! Reads texture regions back without stalling the pipeline. The copy goes into a
+//! pixel buffer and is only fetched once a fence says the GPU is done with it, or
+//! after a few frames at most, so the CPU side never lags far behind.
+
+use std::collections::VecDeque;
+use std::marker::PhantomData;
+use std::mem::size_of;
+use std::ptr;
+
+use gl::types::*;
+
+use crate::heightfield::DirtyRect;
+use crate::opengl::objects::{Buffer, Fence, Texture};
+
+/// Polls a readback may wait for its fence before it's waited for
+pub const MAX_LATENCY: u32 = 3;
+
+struct Pending {
+    rect: DirtyRect,
+    buffer: Buffer,
+    capacity: usize,
+    fence: Fence,
+    polls: u32,
+}
+
+/// Readbacks of one texture format, `P` is a texel
+pub struct Readback<P> {
+    format: GLenum,
+    type_: GLenum,
+    /// Oldest first, they're handed out in the order they were started
+    pending: VecDeque<Pending>,
+    /// Buffers of finished readbacks and their size in bytes, for the next ones
+    spare: Vec<(Buffer, usize)>,
+    texel: PhantomData<P>,
+}
+
+impl<P: Copy + Default> Readback<P> {
+    /// `format` and `type_` as for `glGetTextureSubImage`, matching `P`
+    pub fn new(format: GLenum, type_: GLenum) -> Self {
+        Readback {
+            format,
+            type_,
+            pending: VecDeque::new(),
+            spare: vec![],
+            texel: PhantomData,
+        }
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Copies `rect` of the texture into a pixel buffer on the GPU
+    pub fn start(&mut self, texture: &Texture, rect: DirtyRect) {
+        let bytes = rect.width() * rect.height() * size_of::<P>();
+        let (buffer, capacity) = match self.spare.iter().position(|&(_, size)| size >= bytes) {
+            Some(index) => self.spare.swap_remove(index),
+            None => (Buffer::new("Readback"), 0),
+        };
+        unsafe {
+            gl::BindBuffer(gl::PIXEL_PACK_BUFFER, buffer.id());
+            if capacity < bytes {
+                gl::BufferData(
+                    gl::PIXEL_PACK_BUFFER,
+                    bytes as GLsizeiptr,
+                    ptr::null(),
+                    gl::STREAM_READ,
+                );
+            }
+            // Rows are packed tightly, whatever their width. With a pack buffer
+            // bound the pointer is an offset into it.
+            gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+            gl::GetTextureSubImage(
+                texture.id(),
+                0,
+                rect.x0 as i32,
+                rect.y0 as i32,
+                0,
+                rect.width() as i32,
+                rect.height() as i32,
+                1,
+                self.format,
+                self.type_,
+                bytes as i32,
+                ptr::null_mut(),
+            );
+            gl::PixelStorei(gl::PACK_ALIGNMENT, 4);
+            gl::BindBuffer(gl::PIXEL_PACK_BUFFER, 0);
+        }
+        self.pending.push_back(Pending {
+            rect,
+            buffer,
+            capacity: capacity.max(bytes),
+            fence: Fence::insert(),
+            polls: 0,
+        });
+    }
+
+    /// The readbacks that are done, oldest first. One that's been waited on for
+    /// `MAX_LATENCY` polls is waited for, and so is everything before it.
+    pub fn poll(&mut self) -> Vec<(DirtyRect, Vec<P>)> {
+        for pending in &mut self.pending {
+            pending.polls += 1;
+        }
+        let overdue = self
+            .pending
+            .iter()
+            .rposition(|pending| pending.polls >= MAX_LATENCY);
+        let mut done = vec![];
+        while let Some(pending) = self.pending.front() {
+            let must_wait = overdue.is_some_and(|overdue| done.len() <= overdue);
+            if !must_wait && !pending.fence.wait(0) {
+                break;
+            }
+            let pending = self.pending.pop_front().unwrap();
+            done.push(self.fetch(pending));
+        }
+        done
+    }
+
+    /// Waits for everything that's been started
+    pub fn finish(&mut self) -> Vec<(DirtyRect, Vec<P>)> {
+        let pending: Vec<Pending> = self.pending.drain(..).collect();
+        pending
+            .into_iter()
+            .map(|pending| self.fetch(pending))
+            .collect()
+    }
+
+    /// Forgets the readbacks in flight, e.g. when what they read has been replaced
+    pub fn cancel(&mut self) {
+        for pending in self.pending.drain(..) {
+            self.spare.push((pending.buffer, pending.capacity));
+        }
+    }
+
+    fn fetch(&mut self, pending: Pending) -> (DirtyRect, Vec<P>) {
+        // Returns as soon as the copy is done, which is right away for most
+        while !pending.fence.wait(1_000_000) {}
+        let rect = pending.rect;
+        let mut pixels = vec![P::default(); rect.width() * rect.height()];
+        unsafe {
+            gl::BindBuffer(gl::PIXEL_PACK_BUFFER, pending.buffer.id());
+            gl::GetBufferSubData(
+                gl::PIXEL_PACK_BUFFER,
+                0,
+                (pixels.len() * size_of::<P>()) as GLsizeiptr,
+                pixels.as_mut_ptr() as *mut _,
+            );
+            gl::BindBuffer(gl::PIXEL_PACK_BUFFER, 0);
+        }
+        self.spare.push((pending.buffer, pending.capacity));
+        (rect, pixels)
+    }
+}
@@ -0,0 +1,130 @@
+//! Records the input the viewports get, frame by frame, and plays it back with the
+//! same number of fixed timesteps every frame, so a recording played on the project
+//! it was made on gives the same terrain, which is checked against the checksum
+//! saved with it. That way editor interactions can be tested from a script and bugs
+//! come with a recording.
+//!
+//! GUI clicks aren't part of it, only what the tools and the camera see, so the tool
+//! a recording starts with is the one it uses throughout. Sculpting runs on the GPU,
+//! checksums are only expected to match on the machine they were recorded on.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use glam::Vec3;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::input::Input;
+use crate::viewport::ViewportLayout;
+use crate::TerrainTool;
+
+#[derive(Debug, Error)]
+pub enum RecordingError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Not a valid recording: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Frame {
+    pub input: Input,
+    /// The pointer was over the GUI, so the viewports left it alone
+    pub gui_wants_input: bool,
+    /// Simulation steps the frame took
+    pub steps: u32,
+}
+
+/// Everything the viewports need to start where the recording started
+#[derive(Serialize, Deserialize)]
+pub struct Recording {
+    /// Pointer positions only mean the same thing in a window this size
+    pub window_size: (u32, u32),
+    pub layout: ViewportLayout,
+    pub camera_position: Vec3,
+    pub camera_direction: Vec3,
+    pub tool: Option<TerrainTool>,
+    pub brush_size: f32,
+    pub frames: Vec<Frame>,
+    /// Of the heights after the last frame
+    pub checksum: u64,
+}
+
+impl Recording {
+    pub fn save(&self, path: &Path) -> Result<(), RecordingError> {
+        fs::write(path, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, RecordingError> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+}
+
+/// A recording being made, saved when it's stopped
+pub struct Recorder {
+    pub path: PathBuf,
+    pub recording: Recording,
+}
+
+pub struct Playback {
+    recording: Recording,
+    next: usize,
+    /// The frame being played
+    current: Option<Frame>,
+    /// Quits once it's done, with a failure if the terrain came out different
+    pub exit_when_done: bool,
+}
+
+impl Playback {
+    pub fn new(recording: Recording, exit_when_done: bool) -> Self {
+        Playback {
+            recording,
+            next: 0,
+            current: None,
+            exit_when_done,
+        }
+    }
+
+    pub fn recording(&self) -> &Recording {
+        &self.recording
+    }
+
+    /// None once every frame has been played
+    pub fn next_frame(&mut self) -> Option<Frame> {
+        self.current = self.recording.frames.get(self.next).cloned();
+        self.next += 1;
+        self.current.clone()
+    }
+
+    pub fn gui_wants_input(&self) -> bool {
+        self.current
+            .as_ref()
+            .is_some_and(|frame| frame.gui_wants_input)
+    }
+}
+
+/// What the command line asked for, `--record <file>`, `--play <file>` or
+/// `--benchmark <file>`
+pub enum StartWith {
+    Record(PathBuf),
+    Play(PathBuf),
+    /// Where the results go
+    Benchmark(PathBuf),
+}
+
+impl StartWith {
+    pub fn from_args(mut args: impl Iterator<Item = String>) -> Option<StartWith> {
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--record" => return args.next().map(|path| StartWith::Record(path.into())),
+                "--play" => return args.next().map(|path| StartWith::Play(path.into())),
+                "--benchmark" => return args.next().map(|path| StartWith::Benchmark(path.into())),
+                _ => {}
+            }
+        }
+        None
+    }
+}
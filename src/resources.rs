@@ -0,0 +1,159 @@
+//! Rough bookkeeping of how much video memory we're using.
+//! Drivers don't tell us, so sizes are estimated from formats and dimensions.
+
+use std::sync::Mutex;
+
+use gl::types::GLenum;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResourceKind {
+    Heightmap,
+    SplatMap,
+    Texture,
+    Mesh,
+    RenderTarget,
+}
+
+impl ResourceKind {
+    pub const ALL: [ResourceKind; 5] = [
+        ResourceKind::Heightmap,
+        ResourceKind::SplatMap,
+        ResourceKind::Texture,
+        ResourceKind::Mesh,
+        ResourceKind::RenderTarget,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ResourceKind::Heightmap => "Heightmaps",
+            ResourceKind::SplatMap => "Splat maps",
+            ResourceKind::Texture => "Textures",
+            ResourceKind::Mesh => "Meshes",
+            ResourceKind::RenderTarget => "Render targets",
+        }
+    }
+}
+
+struct Entry {
+    kind: ResourceKind,
+    bytes: usize,
+}
+
+static REGISTRY: Mutex<Vec<Option<Entry>>> = Mutex::new(Vec::new());
+
+/// Keeps an allocation counted for as long as it's alive
+#[derive(Debug)]
+pub struct MemoryHandle {
+    slot: usize,
+}
+
+impl MemoryHandle {
+    pub fn resize(&self, bytes: usize) {
+        let mut registry = REGISTRY.lock().unwrap();
+        if let Some(entry) = registry[self.slot].as_mut() {
+            entry.bytes = bytes;
+        }
+    }
+}
+
+impl Drop for MemoryHandle {
+    fn drop(&mut self) {
+        REGISTRY.lock().unwrap()[self.slot] = None;
+    }
+}
+
+pub fn track(kind: ResourceKind, bytes: usize) -> MemoryHandle {
+    let mut registry = REGISTRY.lock().unwrap();
+    let entry = Some(Entry { kind, bytes });
+    let slot = match registry.iter().position(|e| e.is_none()) {
+        Some(slot) => {
+            registry[slot] = entry;
+            slot
+        }
+        None => {
+            registry.push(entry);
+            registry.len() - 1
+        }
+    };
+    MemoryHandle { slot }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MemoryUsage {
+    pub by_kind: [usize; ResourceKind::ALL.len()],
+}
+
+impl MemoryUsage {
+    pub fn total(&self) -> usize {
+        self.by_kind.iter().sum()
+    }
+
+    pub fn of(&self, kind: ResourceKind) -> usize {
+        self.by_kind[kind as usize]
+    }
+}
+
+pub fn usage() -> MemoryUsage {
+    let registry = REGISTRY.lock().unwrap();
+    let mut usage = MemoryUsage::default();
+    for entry in registry.iter().flatten() {
+        usage.by_kind[entry.kind as usize] += entry.bytes;
+    }
+    usage
+}
+
+/// Estimated size of a 2D texture. Drivers pad 3 channel formats to 4.
+pub fn texture_bytes(
+    internal_format: GLenum,
+    width: usize,
+    height: usize,
+    mip_levels: i32,
+) -> usize {
+    let bytes_per_texel = match internal_format {
+        gl::R8 => 1,
+        gl::R16 | gl::R16F | gl::DEPTH_COMPONENT16 => 2,
+        gl::RGB8 | gl::SRGB8 | gl::RGBA8 | gl::SRGB8_ALPHA8 | gl::DEPTH_COMPONENT24 => 4,
+        gl::RGB16F | gl::RGBA16F => 8,
+        gl::RGBA32F => 16,
+        _ => 4,
+    };
+    let mut total = 0;
+    let (mut w, mut h) = (width, height);
+    for _ in 0..mip_levels.max(1) {
+        total += w * h * bytes_per_texel;
+        w = (w / 2).max(1);
+        h = (h / 2).max(1);
+    }
+    total
+}
+
+pub fn format_bytes(bytes: usize) -> String {
+    const MB: f64 = 1024.0 * 1024.0;
+    if bytes as f64 >= 1024.0 * MB {
+        format!("{:.2} GB", bytes as f64 / (1024.0 * MB))
+    } else {
+        format!("{:.1} MB", bytes as f64 / MB)
+    }
+}
+
+/// Video memory we'd like to stay under, textures far away get lower resolution when over it
+pub struct MemoryBudget {
+    pub budget_mb: usize,
+    /// Highest number of mip levels that may be dropped
+    pub max_downres: u32,
+}
+
+impl Default for MemoryBudget {
+    fn default() -> Self {
+        MemoryBudget {
+            budget_mb: 1024,
+            max_downres: 3,
+        }
+    }
+}
+
+impl MemoryBudget {
+    pub fn bytes(&self) -> usize {
+        self.budget_mb * 1024 * 1024
+    }
+}
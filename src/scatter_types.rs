@@ -0,0 +1,273 @@
+//! Any model can be registered as a scatter type and painted onto the terrain, each
+//! type with its own density, scale and alignment. Instances sit on a jittered grid,
+//! so brushing over the same spot again doesn't pile them up.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use glam::{Mat4, Vec2, Vec3Swizzles};
+
+use crate::heightfield::HeightField;
+use crate::instances::{placement_transform, InstancedModel};
+use crate::scatter;
+use crate::season::SeasonalVariants;
+use crate::terrain::Terrain;
+use crate::Result;
+
+pub struct ScatterType {
+    pub name: String,
+    pub model_path: String,
+    /// Instances per 100 square meters
+    pub density: f32,
+    pub min_scale: f32,
+    pub max_scale: f32,
+    /// 0 stands straight up, 1 follows the surface normal
+    pub alignment: f32,
+    /// Gives every type its own layout
+    pub seed: u32,
+    pub lod: LodSettings,
+    /// Tints for the texture in other seasons
+    pub seasons: SeasonalVariants,
+}
+
+/// How far away instances are drawn and how
+#[derive(Debug, Clone, Copy)]
+pub struct LodSettings {
+    /// Meters from the camera after which nothing is drawn
+    pub max_distance: f32,
+    /// Meters from the camera after which a flat quad is drawn instead of the model
+    pub imposter_distance: f32,
+    pub cast_shadows: bool,
+}
+
+impl Default for LodSettings {
+    fn default() -> Self {
+        LodSettings {
+            max_distance: 1000.0,
+            imposter_distance: 300.0,
+            cast_shadows: true,
+        }
+    }
+}
+
+impl LodSettings {
+    /// Distances the full model is drawn at
+    pub fn model_range(&self) -> (f32, f32) {
+        (0.0, self.imposter_distance.min(self.max_distance))
+    }
+
+    pub fn imposter_range(&self) -> (f32, f32) {
+        (self.imposter_distance, self.max_distance)
+    }
+}
+
+impl ScatterType {
+    fn new(model_path: &str, seed: u32) -> Self {
+        let name = Path::new(model_path)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| model_path.to_owned());
+        ScatterType {
+            name,
+            model_path: model_path.to_owned(),
+            density: 5.0,
+            min_scale: 0.8,
+            max_scale: 1.2,
+            alignment: 0.0,
+            seed,
+            lod: LodSettings::default(),
+            seasons: SeasonalVariants::DEFAULT_VEGETATION,
+        }
+    }
+}
+
+/// Shows how densely the selected type is scattered
+pub struct DensityHeatmap {
+    pub enabled: bool,
+    /// Side of the area instances are counted over, in meters
+    pub cell_size: f32,
+    /// Instances per 100 square meters that show at full heat
+    pub range: f32,
+    pub opacity: f32,
+}
+
+impl Default for DensityHeatmap {
+    fn default() -> Self {
+        DensityHeatmap {
+            enabled: false,
+            cell_size: 10.0,
+            range: 20.0,
+            opacity: 0.7,
+        }
+    }
+}
+
+pub struct ScatterSettings {
+    pub types: Vec<ScatterType>,
+    /// Which type the brush paints
+    pub selected: Option<usize>,
+    /// glTF model of the next type to add
+    pub model_path: String,
+    pub heatmap: DensityHeatmap,
+}
+
+impl Default for ScatterSettings {
+    fn default() -> Self {
+        ScatterSettings {
+            types: vec![],
+            selected: None,
+            model_path: "models/box/box.gltf".to_owned(),
+            heatmap: DensityHeatmap::default(),
+        }
+    }
+}
+
+/// Jittered grid spacing and cell, spacing changes with density
+type CellKey = (u32, i32, i32);
+
+/// The painted instances of one scatter type
+pub struct ScatterLayer {
+    instances: InstancedModel,
+    cells: BTreeMap<CellKey, Mat4>,
+}
+
+impl ScatterLayer {
+    pub fn load(model_path: &str) -> Result<Self> {
+        Ok(ScatterLayer {
+            instances: InstancedModel::load(model_path)?,
+            cells: BTreeMap::new(),
+        })
+    }
+
+    /// The same instances on the model loaded again, after the context was lost
+    pub fn reload(&self, model_path: &str) -> Result<Self> {
+        let mut layer = ScatterLayer::load(model_path)?;
+        layer.cells = self.cells.clone();
+        layer.upload();
+        Ok(layer)
+    }
+
+    pub fn instances(&self) -> &InstancedModel {
+        &self.instances
+    }
+
+    pub fn clear(&mut self) {
+        self.cells.clear();
+        self.instances.set_transforms(&[]);
+    }
+
+    /// Instances per 100 square meters over the whole terrain divided by `heatmap.range`,
+    /// one texel per cell
+    pub fn density(&self, terrain: &Terrain, heatmap: &DensityHeatmap) -> HeightField {
+        let terrain_size = terrain.size();
+        let cells = ((terrain_size / heatmap.cell_size).ceil() as usize).clamp(1, 1024);
+        let cell_size = terrain_size / cells as f32;
+        let min = terrain.aabb.min.xz();
+        let mut counts = vec![0u32; cells * cells];
+        for transform in self.cells.values() {
+            let cell = (transform.w_axis.truncate().xz() - min) / cell_size;
+            if cell.min_element() >= 0.0 {
+                let (x, y) = (cell.x as usize, cell.y as usize);
+                if x < cells && y < cells {
+                    counts[y * cells + x] += 1;
+                }
+            }
+        }
+        let per_100_m2 = 100.0 / (cell_size * cell_size);
+        let data = counts
+            .iter()
+            .map(|&count| (count as f32 * per_100_m2 / heatmap.range).min(1.0))
+            .collect();
+        HeightField::from_data(cells, data)
+    }
+
+    /// Fills the grid cells under the brush, or empties them when erasing
+    pub fn paint(&mut self, terrain: &Terrain, scatter_type: &ScatterType, erase: bool) {
+        let brush = &terrain.brush;
+        let in_brush = |point: Vec2| {
+            let brush_uv = Vec2::splat(0.5) + (point - terrain.cursor) / brush.size;
+            // Same edge as the brush outline on the terrain
+            brush.sample(brush_uv) > 0.1
+        };
+        let old_count = self.cells.len();
+
+        if erase {
+            self.cells
+                .retain(|_, transform| !in_brush(transform.w_axis.truncate().xz()));
+        } else {
+            let spacing = scatter::spacing(scatter_type.density);
+            let placements = terrain.scatter_in_square(
+                terrain.cursor,
+                brush.size,
+                scatter_type.seed,
+                spacing,
+                (scatter_type.min_scale, scatter_type.max_scale),
+            );
+            for ((x, y), placement) in placements {
+                if in_brush(placement.position.xz()) {
+                    self.cells
+                        .entry((spacing.to_bits(), x, y))
+                        .or_insert_with(|| placement_transform(&placement, scatter_type.alignment));
+                }
+            }
+        }
+
+        if self.cells.len() != old_count {
+            self.upload();
+        }
+    }
+
+    /// Adds instances all over the terrain at `density` wherever `keep` agrees.
+    /// `keep` gets the world space position and the grid cell.
+    pub fn fill(
+        &mut self,
+        terrain: &Terrain,
+        scatter_type: &ScatterType,
+        density: f32,
+        keep: impl Fn(Vec2, (i32, i32)) -> bool,
+    ) {
+        let spacing = scatter::spacing(density);
+        let center = (terrain.aabb.min.xz() + terrain.aabb.max.xz()) / 2.0;
+        let placements = terrain.scatter_in_square(
+            center,
+            terrain.size(),
+            scatter_type.seed,
+            spacing,
+            (scatter_type.min_scale, scatter_type.max_scale),
+        );
+        for ((x, y), placement) in placements {
+            if self.cells.len() >= scatter::MAX_PLACEMENTS {
+                break;
+            }
+            if keep(placement.position.xz(), (x, y)) {
+                self.cells
+                    .entry((spacing.to_bits(), x, y))
+                    .or_insert_with(|| placement_transform(&placement, scatter_type.alignment));
+            }
+        }
+        self.upload();
+    }
+
+    fn upload(&mut self) {
+        let transforms: Vec<Mat4> = self.cells.values().copied().collect();
+        self.instances.set_transforms(&transforms);
+    }
+}
+
+/// Adds a type together with its layer
+pub fn add_type(settings: &mut ScatterSettings, layers: &mut Vec<ScatterLayer>) -> Result<()> {
+    let layer = ScatterLayer::load(&settings.model_path)?;
+    let seed = settings.types.len() as u32 * 7919;
+    settings
+        .types
+        .push(ScatterType::new(&settings.model_path, seed));
+    layers.push(layer);
+    settings.selected = Some(settings.types.len() - 1);
+    Ok(())
+}
+
+pub fn remove_type(settings: &mut ScatterSettings, layers: &mut Vec<ScatterLayer>, index: usize) {
+    settings.types.remove(index);
+    layers.remove(index);
+    settings.selected = None;
+}
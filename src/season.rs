@@ -0,0 +1,121 @@
+//! The same project in a different season. Summer is what everything is authored
+//! as; every terrain layer and scatter type says how it changes in the others,
+//! either taking on a new color or being tinted.
+
+use serde::{Deserialize, Serialize};
+
+use crate::splat::NUM_LAYERS;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Season {
+    Summer,
+    Autumn,
+    Winter,
+}
+
+impl Season {
+    pub const ALL: [Season; 3] = [Season::Summer, Season::Autumn, Season::Winter];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Season::Summer => "Summer",
+            Season::Autumn => "Autumn",
+            Season::Winter => "Winter",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum SeasonalColor {
+    /// Multiplies the summer color, linear RGB
+    Tint([f32; 3]),
+    /// Replaces the summer color, linear RGB
+    Swap([f32; 3]),
+}
+
+impl SeasonalColor {
+    pub const UNCHANGED: SeasonalColor = SeasonalColor::Tint([1.0; 3]);
+
+    pub fn apply(&self, color: [f32; 3]) -> [f32; 3] {
+        match self {
+            SeasonalColor::Tint(tint) => {
+                [color[0] * tint[0], color[1] * tint[1], color[2] * tint[2]]
+            }
+            SeasonalColor::Swap(new_color) => *new_color,
+        }
+    }
+}
+
+/// How one material looks outside of summer
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SeasonalVariants {
+    pub autumn: SeasonalColor,
+    pub winter: SeasonalColor,
+}
+
+impl SeasonalVariants {
+    /// Colors a model's texture gets multiplied by
+    pub const DEFAULT_VEGETATION: SeasonalVariants = SeasonalVariants {
+        autumn: SeasonalColor::Tint([1.3, 0.8, 0.35]),
+        winter: SeasonalColor::Tint([0.8, 0.8, 0.85]),
+    };
+
+    pub fn apply(&self, color: [f32; 3], season: Season) -> [f32; 3] {
+        match season {
+            Season::Summer => color,
+            Season::Autumn => self.autumn.apply(color),
+            Season::Winter => self.winter.apply(color),
+        }
+    }
+
+    pub fn get_mut(&mut self, season: Season) -> Option<&mut SeasonalColor> {
+        match season {
+            Season::Summer => None,
+            Season::Autumn => Some(&mut self.autumn),
+            Season::Winter => Some(&mut self.winter),
+        }
+    }
+}
+
+pub struct SeasonSettings {
+    pub season: Season,
+    /// Same order as the terrain layers
+    pub layers: [SeasonalVariants; NUM_LAYERS],
+}
+
+impl Default for SeasonSettings {
+    fn default() -> Self {
+        SeasonSettings {
+            season: Season::Summer,
+            layers: [
+                // Grass
+                SeasonalVariants {
+                    autumn: SeasonalColor::Swap([0.36, 0.26, 0.08]),
+                    winter: SeasonalColor::Swap([0.75, 0.78, 0.82]),
+                },
+                // Dirt
+                SeasonalVariants {
+                    autumn: SeasonalColor::Tint([0.9, 0.85, 0.8]),
+                    winter: SeasonalColor::Tint([0.8, 0.8, 0.85]),
+                },
+                // Rock
+                SeasonalVariants {
+                    autumn: SeasonalColor::UNCHANGED,
+                    winter: SeasonalColor::Tint([1.1, 1.1, 1.15]),
+                },
+                // Snow
+                SeasonalVariants {
+                    autumn: SeasonalColor::UNCHANGED,
+                    winter: SeasonalColor::UNCHANGED,
+                },
+            ],
+        }
+    }
+}
+
+impl SeasonSettings {
+    /// Color of a terrain layer in the current season
+    pub fn layer_color(&self, layer: usize, summer_color: [f32; 3]) -> [f32; 3] {
+        self.layers[layer].apply(summer_color, self.season)
+    }
+}
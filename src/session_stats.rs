@@ -0,0 +1,84 @@
+//! A summary of the editing session: how long each tool was in use, how often
+//! something was undone and the longest the editor froze for. It's only written when
+//! turned on, and only ever to a local file, one line per session appended on exit.
+
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::TerrainTool;
+
+pub const STATS_PATH: &str = "session_stats.jsonl";
+
+#[derive(Serialize)]
+pub struct SessionStats {
+    /// Seconds since the Unix epoch
+    started: u64,
+    /// Seconds the editor was drawing frames, it sleeps when nothing happens
+    active_seconds: f32,
+    /// Active seconds with each tool selected
+    tool_seconds: BTreeMap<TerrainTool, f32>,
+    edits: u64,
+    undos: u32,
+    /// Longest a frame took to update and draw, in milliseconds
+    biggest_stall_ms: f32,
+    /// Most a frame spent capturing strokes for undo, in milliseconds
+    biggest_undo_capture_ms: f32,
+}
+
+impl SessionStats {
+    pub fn new() -> Self {
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        SessionStats {
+            started,
+            active_seconds: 0.0,
+            tool_seconds: BTreeMap::new(),
+            edits: 0,
+            undos: 0,
+            biggest_stall_ms: 0.0,
+            biggest_undo_capture_ms: 0.0,
+        }
+    }
+
+    /// `took` is how long the frame took to update and draw, not the time since the
+    /// last one
+    pub fn frame(&mut self, tool: Option<TerrainTool>, delta_time: f32, took: Duration) {
+        self.active_seconds += delta_time;
+        if let Some(tool) = tool {
+            *self.tool_seconds.entry(tool).or_insert(0.0) += delta_time;
+        }
+        let took = took.as_secs_f32() * 1000.0;
+        self.biggest_stall_ms = self.biggest_stall_ms.max(took);
+    }
+
+    /// `took` is what capturing strokes for undo cost the frame
+    pub fn undo_captured(&mut self, took: Duration) {
+        let took = took.as_secs_f32() * 1000.0;
+        self.biggest_undo_capture_ms = self.biggest_undo_capture_ms.max(took);
+    }
+
+    pub fn edited(&mut self) {
+        self.edits += 1;
+    }
+
+    pub fn undone(&mut self) {
+        self.undos += 1;
+    }
+
+    /// Appends the session to `STATS_PATH`
+    pub fn save(&self) -> io::Result<()> {
+        let mut line = serde_json::to_string(self)?;
+        line.push('\n');
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(STATS_PATH)?
+            .write_all(line.as_bytes())
+    }
+}
@@ -3,14 +3,7 @@
 uniform vec3 aabb_min;
 uniform vec3 aabb_max;
 
-layout(std140, binding = 1) uniform UTransforms {
-    mat4 mvp;
-    mat4 proj;
-    mat4 view;
-    mat4 model;
-    mat4 sun_vp;
-}
-uTransforms;
+#include "transforms.glsl"
 
 vec3 VERTICES[] = vec3[](
     aabb_min,
@@ -4,13 +4,7 @@ layout(location = 1) in vec3 inColor;
 
 out vec3 Color;
 
-layout(std140, binding = 1) uniform UTransforms {
-    mat4 mvp;
-    mat4 proj;
-    mat4 view;
-    mat4 model;
-}
-uTransforms;
+#include "transforms.glsl"
 
 void main() {
     gl_Position = uTransforms.mvp * vec4(inPosition, 1.0);
@@ -0,0 +1,102 @@
+#version 450 core
+
+// The terrain as nested square rings around the camera, every level twice as coarse
+// as the one inside it and the finest one at the heightmap's resolution. A level is
+// 4 x 4 blocks of quads, one instance each, and the quads the finer level covers
+// collapse to nothing. Towards its outer edge a level morphs into the next one, so
+// their edges meet without cracks. Drawn with BLOCK * BLOCK * 6 vertices per instance.
+
+#include "transforms.glsl"
+#include "heightmap.glsl"
+
+const int BLOCK = 16;
+const int BLOCKS = 4;
+const int LEVEL_QUADS = BLOCK * BLOCKS;
+// Quads over which a level morphs into the next one
+const float MORPH_QUADS = 8.0;
+// Two triangles per quad, counter-clockwise seen from above
+const ivec2 CORNERS[] = ivec2[](
+    ivec2(0, 0), ivec2(0, 1), ivec2(1, 0),
+    ivec2(1, 0), ivec2(0, 1), ivec2(1, 1)
+);
+
+uniform vec2 terrain_center;
+
+out TES_OUT {
+    vec4 frag_pos_sun_space;
+    vec3 frag_pos;
+    vec3 normal;
+    vec2 tile_uv;
+}
+tes_out;
+
+vec2 terrain_min() {
+    return terrain_center - terrain_size / 2.0;
+}
+
+// `vertex` counts vertices of a level `spacing` meters apart from the terrain corner
+vec2 vertex_uv(ivec2 vertex, float spacing) {
+    return clamp(vec2(vertex) * spacing / terrain_size, 0.0, 1.0);
+}
+
+// The camera's vertex on a level, rounded down to an even one so the level's edges
+// are on the next level's vertices
+ivec2 level_center(vec2 camera, float spacing) {
+    return 2 * ivec2(floor((camera - terrain_min()) / (2.0 * spacing)));
+}
+
+void main() {
+    int level = gl_InstanceID / (BLOCKS * BLOCKS);
+    int block = gl_InstanceID % (BLOCKS * BLOCKS);
+    int quad_index = gl_VertexID / 6;
+    ivec2 quad = ivec2(block % BLOCKS, block / BLOCKS) * BLOCK +
+                 ivec2(quad_index % BLOCK, quad_index / BLOCK);
+
+    // The view matrix is rigid, so the camera is its translation rotated back
+    vec2 camera = (-transpose(mat3(uTransforms.view)) * uTransforms.view[3].xyz).xz;
+    float spacing = terrain_size / float(textureSize(heightmap, 0).x) * exp2(float(level));
+    ivec2 center = level_center(camera, spacing);
+    ivec2 first = center - LEVEL_QUADS / 2;
+
+    if (level > 0) {
+        // Where the finer level is, in this level's vertices
+        ivec2 finer = level_center(camera, spacing / 2.0);
+        ivec2 hole_min = (finer - LEVEL_QUADS / 2) / 2;
+        ivec2 hole_max = (finer + LEVEL_QUADS / 2) / 2;
+        ivec2 corner = first + quad;
+        if (all(greaterThanEqual(corner, hole_min)) && all(lessThan(corner, hole_max))) {
+            // Every vertex of the quad in the same place, outside the view
+            gl_Position = vec4(2.0, 2.0, 2.0, 1.0);
+            return;
+        }
+    }
+
+    ivec2 vertex = first + quad + CORNERS[gl_VertexID % 6];
+    vec2 tile_uv = vertex_uv(vertex, spacing);
+    float height = sample_height(tile_uv);
+
+    // 0 inside, 1 on the outer edge where the next level has every other vertex
+    ivec2 from_center = abs(vertex - center);
+    float to_edge = float(LEVEL_QUADS / 2 - max(from_center.x, from_center.y));
+    float morph = clamp(1.0 - to_edge / MORPH_QUADS, 0.0, 1.0);
+    if (morph > 0.0) {
+        // Between the next level's vertices around this one
+        ivec2 even = (vertex >> 1) << 1;
+        vec2 t = vec2(vertex - even) / 2.0;
+        float h00 = sample_height(vertex_uv(even, spacing));
+        float h10 = sample_height(vertex_uv(even + ivec2(2, 0), spacing));
+        float h01 = sample_height(vertex_uv(even + ivec2(0, 2), spacing));
+        float h11 = sample_height(vertex_uv(even + ivec2(2, 2), spacing));
+        float coarse = mix(mix(h00, h10, t.x), mix(h01, h11, t.x), t.y);
+        height = mix(height, coarse, morph);
+    }
+
+    vec2 position = terrain_min() + tile_uv * terrain_size;
+    vec4 p = vec4(position.x, height, position.y, 1.0);
+    gl_Position = uTransforms.mvp * p;
+    tes_out.tile_uv = tile_uv;
+    tes_out.frag_pos = p.xyz;
+    tes_out.frag_pos_sun_space = uTransforms.sun_vp * vec4(tes_out.frag_pos, 1.0);
+    // Note: we're assuming the model matrix is identity here
+    tes_out.normal = calc_normal(tile_uv);
+}
@@ -0,0 +1,40 @@
+#version 450 core
+
+in VS_OUT {
+    vec2 world_pos;
+    vec2 tile_uv;
+}
+fs_in;
+
+out vec4 Color;
+
+uniform vec2 cursor;
+uniform float brush_size;
+// Where the main camera is, on the ground
+uniform vec2 camera_marker;
+
+layout(binding = 1) uniform sampler2D heightmap;
+layout(binding = 2) uniform sampler2D brush_texture;
+
+// Screen pixels
+const float MARKER_RADIUS = 5.0;
+
+void main() {
+    // Gray levels as an image editor would show them, the framebuffer is sRGB
+    float height = texture(heightmap, fs_in.tile_uv).r;
+    vec3 color = vec3(pow(height, 2.2));
+
+    vec2 brush_uv = vec2(0.5, 0.5) + (fs_in.world_pos - cursor) / brush_size;
+    const vec3 brush_color = vec3(0.75, 0.45, 0.92);
+    const vec3 brush_border_color = vec3(0.69, 0.67, 0.91);
+    float brush_value = texture(brush_texture, brush_uv).r;
+    color = mix(color, brush_color, clamp(brush_value * 1.5, 0.0, 1.0) * 0.5);
+    float t = smoothstep(0.1, 0.11, brush_value) - smoothstep(0.11, 0.12, brush_value);
+    color = mix(color, brush_border_color, t);
+
+    float marker_distance = length(fs_in.world_pos - camera_marker) / fwidth(fs_in.world_pos.x);
+    float marker = 1.0 - smoothstep(MARKER_RADIUS - 1.0, MARKER_RADIUS, marker_distance);
+    color = mix(color, vec3(1.0, 0.15, 0.05), marker);
+
+    Color = vec4(color, 1.0);
+}
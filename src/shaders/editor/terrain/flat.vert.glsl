@@ -0,0 +1,22 @@
+#version 450 core
+
+#include "transforms.glsl"
+
+const vec2 VERTICES[] = vec2[](vec2(0.0, 0.0), vec2(1.0, 0.0), vec2(0.0, 1.0), vec2(1.0, 1.0));
+
+uniform vec2 terrain_center;
+uniform float terrain_size;
+
+out VS_OUT {
+    vec2 world_pos;
+    vec2 tile_uv;
+}
+vs_out;
+
+// One quad over the whole terrain at sea level, the heights are only shown as colors
+void main() {
+    vec2 vertex = VERTICES[gl_VertexID];
+    vs_out.tile_uv = vertex;
+    vs_out.world_pos = (vertex - 0.5) * terrain_size + terrain_center;
+    gl_Position = uTransforms.mvp * vec4(vs_out.world_pos.x, 0.0, vs_out.world_pos.y, 1.0);
+}
@@ -6,15 +6,43 @@ fs_in;
 uniform vec2 cursor;       // normalised [0:1]
 uniform float brush_size;  // normalised [0:1]
 uniform float delta_time;
+uniform float texel_size;  // normalised, one heightmap texel
 
 layout(binding = 0) uniform sampler2D brush_texture;
 
 layout(location = 0) out vec4 Color;
 
-void main() {
+// Brush samples along each side of the area that lands on a texel
+const int SAMPLES = 4;
+
+float brush_at(vec2 uv) {
     // Note that brush_size is actually more like brush radius (i.e. half brush real size)
-    vec2 brush_uv = vec2(0.5, 0.5) + (fs_in.uv - cursor) / brush_size;
-    vec3 brush_value = texture(brush_texture, brush_uv).rrr * delta_time;  // TODO: sensitivity
+    vec2 brush_uv = vec2(0.5, 0.5) + (uv - cursor) / brush_size;
+    return texture(brush_texture, brush_uv).r;
+}
+
+void main() {
+    // Nothing lands this far from the brush
+    if (any(greaterThan(abs(fs_in.uv - cursor), vec2(brush_size / 2.0 + texel_size)))) {
+        discard;
+    }
+
+    // Every point of the brush is shared between the four texels around it, weighed
+    // the way bilinear filtering reads them back. The cursor can sit between texels
+    // and a brush only a few texels wide doesn't jump from one to the next.
+    float value = 0.0;
+    float total_weight = 0.0;
+    for (int y = 0; y < SAMPLES; y++) {
+        for (int x = 0; x < SAMPLES; x++) {
+            // Within a texel of this one's center
+            vec2 offset = (vec2(x, y) + 0.5) / float(SAMPLES) * 2.0 - 1.0;
+            vec2 weights = 1.0 - abs(offset);
+            float weight = weights.x * weights.y;
+            value += brush_at(fs_in.uv + offset * texel_size) * weight;
+            total_weight += weight;
+        }
+    }
+    vec3 brush_value = vec3(value / total_weight) * delta_time;  // TODO: sensitivity
 
     // Will be blended with what's currently in the heightmap
     Color = vec4(brush_value, 1.0);
@@ -1,5 +1,9 @@
 #version 450 core
 
+#include "transforms.glsl"
+#include "sky_light.glsl"
+#include "painted_maps.glsl"
+
 in TES_OUT {
     vec4 frag_pos_sun_space;
     vec3 frag_pos;
@@ -12,10 +16,98 @@ out vec4 Color;
 
 uniform vec2 cursor;
 uniform float brush_size;
+uniform float detail_tiling;
+// Zero unless there's a heightmap to compare with
+uniform float compare_opacity;
+// Normalised height difference that shows at full heat
+uniform float compare_range;
+// Zero unless there's a heat map to show
+uniform float overlay_opacity;
+// Zero unless there's a photo draped over the terrain
+uniform float drape_opacity;
+// Terrain uv to photo uv, the photo may cover more or less than the terrain
+uniform vec2 drape_uv_scale;
+uniform vec2 drape_uv_offset;
 
 layout(binding = 0) uniform sampler2D terrain_texture;
+layout(binding = 1) uniform sampler2D heightmap;
 layout(binding = 2) uniform sampler2D brush_texture;
 layout(binding = 3) uniform sampler2D shadow_map;
+layout(binding = 5) uniform sampler2D reference_heightmap;
+layout(binding = 6) uniform sampler2D overlay_map;
+layout(binding = 12) uniform sampler2D drape_map;
+// Sine of the horizon's elevation in 8 directions from +X towards +Z, 4 per layer
+layout(binding = 10) uniform sampler2DArray horizon_map;
+
+uniform bool shadow_map_enabled;
+uniform bool horizon_shadows;
+// How much valleys darken the sky light, 0 unless the horizons are used
+uniform float sky_occlusion;
+// Sine of the angle over which the sun fades out behind the horizon
+uniform float horizon_softness;
+
+// Shown over the terrain where each biome is painted
+uniform vec3 biome_colors[4];
+// Zero unless the biome regions are shown
+uniform float biome_opacity;
+
+// Zero unless the terrain is colored by elevation
+uniform float hypsometric_opacity;
+// Normalised heights, lowest first, and the linear colors at them
+uniform int gradient_stops;
+uniform float gradient_heights[8];
+uniform vec3 gradient_colors[8];
+
+// Angular diameter of the sun in radians, zero for hard shadows
+uniform float sun_size;
+// Meters across the shadow map and from its near to its far plane
+uniform float shadow_map_extent;
+uniform float shadow_depth_range;
+
+const int NUM_TAPS = 16;
+const vec2 POISSON_DISK[NUM_TAPS] = vec2[](
+    vec2(-0.94201624, -0.39906216), vec2(0.94558609, -0.76890725), vec2(-0.09418410, -0.92938870),
+    vec2(0.34495938, 0.29387760), vec2(-0.91588581, 0.45771432), vec2(-0.81544232, -0.87912464),
+    vec2(-0.38277543, 0.27676845), vec2(0.97484398, 0.75648379), vec2(0.44323325, -0.97511554),
+    vec2(0.53742981, -0.47373420), vec2(-0.26496911, -0.41893023), vec2(0.79197514, 0.19090188),
+    vec2(-0.24188840, 0.99706507), vec2(-0.81409955, 0.91437590), vec2(0.19984126, 0.78641367),
+    vec2(0.14383161, -0.14100790));
+// Widest penumbra, in texels, so a huge sun doesn't turn every shadow into a smudge
+const float MAX_PENUMBRA = 32.0;
+
+// Percentage-closer soft shadows: the further the blockers are from the receiver,
+// the wider the penumbra, so peaks cast soft shadows while small things stay crisp
+float calc_soft_shadow(vec3 proj_coords, float bias) {
+    float frag_depth = proj_coords.z;
+    vec2 texel_size = 1.0 / textureSize(shadow_map, 0);
+    // Meters of penumbra per meter between a blocker and the receiver, in uv
+    float spread = 2.0 * tan(sun_size * 0.5) * shadow_depth_range / shadow_map_extent;
+
+    // Blockers can be anywhere between the receiver and the light
+    float search_radius = min(spread * frag_depth, MAX_PENUMBRA * texel_size.x) + texel_size.x;
+    float blocker_depth = 0.0;
+    float blockers = 0.0;
+    for (int i = 0; i < NUM_TAPS; ++i) {
+        float depth = texture(shadow_map, proj_coords.xy + POISSON_DISK[i] * search_radius).r;
+        if (frag_depth - bias > depth) {
+            blocker_depth += depth;
+            blockers += 1.0;
+        }
+    }
+    if (blockers == 0.0) {
+        return 0.0;
+    }
+    blocker_depth /= blockers;
+
+    float penumbra =
+        clamp(spread * (frag_depth - blocker_depth), texel_size.x, MAX_PENUMBRA * texel_size.x);
+    float shadow = 0.0;
+    for (int i = 0; i < NUM_TAPS; ++i) {
+        float depth = texture(shadow_map, proj_coords.xy + POISSON_DISK[i] * penumbra).r;
+        shadow += frag_depth - bias > depth ? 1.0 : 0.0;
+    }
+    return shadow / float(NUM_TAPS);
+}
 
 float calc_shadow(vec4 frag_pos) {
     vec3 proj_coords = frag_pos.xyz / frag_pos.w;
@@ -23,6 +115,9 @@ float calc_shadow(vec4 frag_pos) {
     float frag_depth = proj_coords.z;
     // TODO: adjust bias based on the angle
     float bias = 0.003;
+    if (sun_size > 0.0) {
+        return calc_soft_shadow(proj_coords, bias);
+    }
     float shadow = 0.0;
     vec2 texel_size = 1.0 / textureSize(shadow_map, 0);
     for (int x = -1; x <= 1; ++x) {
@@ -34,11 +129,91 @@ float calc_shadow(vec4 frag_pos) {
     return shadow / 9.0;
 }
 
-const float ENABLE_SHADOWS = 1.0;
+const float TERRAIN_ROUGHNESS = 0.8;
+const float PI = 3.14159265359;
+
+float[8] read_horizons(vec2 uv) {
+    vec4 first = texture(horizon_map, vec3(uv, 0.0));
+    vec4 second = texture(horizon_map, vec3(uv, 1.0));
+    return float[8](first.r, first.g, first.b, first.a, second.r, second.g, second.b, second.a);
+}
+
+// 1 where the sun is behind the horizon in its direction
+float horizon_shadow(float horizons[8]) {
+    float azimuth = atan(sun_direction.z, sun_direction.x);
+    float index = mod(azimuth / (2.0 * PI) * 8.0, 8.0);
+    int i = int(index);
+    float horizon = mix(horizons[i], horizons[(i + 1) % 8], fract(index));
+    float softness = horizon_softness;
+    return 1.0 - smoothstep(horizon - softness, horizon + softness, sun_direction.y);
+}
+
+// How much of the sky is open, cosine weighted
+float sky_visibility(float horizons[8]) {
+    float open = 0.0;
+    for (int i = 0; i < 8; ++i) {
+        open += 1.0 - horizons[i] * horizons[i];
+    }
+    return open / 8.0;
+}
+
+// Flat below the first stop and above the last one
+vec3 gradient_color(float height) {
+    vec3 color = gradient_colors[0];
+    for (int i = 1; i < gradient_stops; ++i) {
+        float low = gradient_heights[i - 1];
+        float t = clamp((height - low) / max(gradient_heights[i] - low, 1e-5), 0.0, 1.0);
+        color = mix(color, gradient_colors[i], t);
+    }
+    return color;
+}
 
 void main() {
-    vec2 patch_uv = fs_in.tile_uv * 64.0;
-    vec4 terrain_color = texture(terrain_texture, patch_uv);
+    vec2 patch_uv = fs_in.tile_uv * detail_tiling;
+    vec4 detail = texture(terrain_texture, patch_uv);
+    vec4 splat = sample_painted(splat_map, 0, fs_in.tile_uv);
+    vec3 layers_color = splat_color(splat);
+    vec4 biome = sample_painted(biome_map, 1, fs_in.tile_uv);
+    layers_color *= biome_tint(biome);
+    if (drape_opacity > 0.0) {
+        vec2 drape_uv = fs_in.tile_uv * drape_uv_scale + drape_uv_offset;
+        // The layers show wherever the photo doesn't reach
+        bool covered = all(greaterThanEqual(drape_uv, vec2(0.0))) &&
+                       all(lessThanEqual(drape_uv, vec2(1.0)));
+        if (covered) {
+            layers_color = mix(layers_color, texture(drape_map, drape_uv).rgb, drape_opacity);
+        }
+    }
+    layers_color *= sample_painted(tint_map, 2, fs_in.tile_uv).rgb;
+    vec4 terrain_color = vec4(layers_color * mix(vec3(1.0), detail.rgb, 0.3), 1.0);
+    if (biome_opacity > 0.0) {
+        vec3 biome_color = biome.r * biome_colors[0] + biome.g * biome_colors[1] +
+                           biome.b * biome_colors[2] + biome.a * biome_colors[3];
+        terrain_color.rgb = mix(terrain_color.rgb, biome_color, biome_opacity);
+    }
+    if (hypsometric_opacity > 0.0) {
+        float height = texture(heightmap, fs_in.tile_uv).r;
+        terrain_color.rgb = mix(terrain_color.rgb, gradient_color(height), hypsometric_opacity);
+    }
+    if (compare_opacity > 0.0) {
+        // Red where the terrain is higher than the reference, blue where it's lower
+        float diff = texture(heightmap, fs_in.tile_uv).r -
+                     texture(reference_heightmap, fs_in.tile_uv).r;
+        float heat = clamp(abs(diff) / compare_range, 0.0, 1.0);
+        vec3 heat_color = diff > 0.0 ? vec3(1.0, 0.15, 0.05) : vec3(0.05, 0.35, 1.0);
+        terrain_color.rgb = mix(terrain_color.rgb, heat_color, heat * compare_opacity);
+    }
+    if (overlay_opacity > 0.0) {
+        // Blue through green to red, nothing where it's empty
+        float heat = texture(overlay_map, fs_in.tile_uv).r;
+        vec3 cold = vec3(0.1, 0.2, 1.0);
+        vec3 warm = vec3(0.1, 0.9, 0.2);
+        vec3 hot = vec3(1.0, 0.1, 0.05);
+        vec3 heat_color =
+            heat < 0.5 ? mix(cold, warm, heat * 2.0) : mix(warm, hot, heat * 2.0 - 1.0);
+        float alpha = smoothstep(0.0, 0.05, heat) * overlay_opacity;
+        terrain_color.rgb = mix(terrain_color.rgb, heat_color, alpha);
+    }
     vec2 brush_uv = vec2(0.5, 0.5) + (fs_in.frag_pos.xz - cursor) / brush_size;
     const vec4 brush_color = vec4(0.75, 0.45, 0.92, 1.0);
     const vec3 brush_border_color = vec3(0.69, 0.67, 0.91);
@@ -48,16 +223,21 @@ void main() {
 
     base_color = mix(base_color, brush_border_color, t);
 
-    vec3 ambient = 0.35 * base_color;
     vec3 normal = normalize(fs_in.normal);
-    vec3 light_color = vec3(1.0);
-    vec3 light_dir = normalize(vec3(0.0, 200.0, 500.0));  // @hardcoded
-    float diff = max(dot(light_dir, normal), 0.0);
-    vec3 diffuse = diff * light_color;
+    float horizons[8] = read_horizons(fs_in.tile_uv);
+    float sky_open = mix(1.0, sky_visibility(horizons), sky_occlusion);
+    vec3 ambient_color = sky_ambient(normal) * sky_open * base_color;
+    float diff = max(dot(sun_direction, normal), 0.0);
+    vec3 diffuse = diff * sun_color;
 
-    float shadow = calc_shadow(fs_in.frag_pos_sun_space);
+    float shadow = shadow_map_enabled ? calc_shadow(fs_in.frag_pos_sun_space) : 0.0;
+    if (horizon_shadows) {
+        shadow = max(shadow, horizon_shadow(horizons));
+    }
 
-    vec3 lighting = (ambient + (1.0 - shadow * ENABLE_SHADOWS) * diffuse) * base_color;
+    vec3 lighting = (ambient_color + (1.0 - shadow) * diffuse) * base_color;
+    // Ground is rough, it only catches the sky at grazing angles
+    lighting += sky_reflection(normal, fs_in.frag_pos, TERRAIN_ROUGHNESS) * sky_open;
 
     Color = vec4(lighting, 1.0);
 }
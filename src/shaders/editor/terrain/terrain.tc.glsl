@@ -8,16 +8,19 @@ tcs_in[];
 out TCS_OUT { vec2 tile_uv; }
 tcs_out[];
 
-layout(std140, binding = 1) uniform UTransforms {
-    mat4 mvp;
-    mat4 proj;
-    mat4 view;
-    mat4 model;
-    mat4 sun_vp;
-}
-uTransforms;
+#include "transforms.glsl"
 
 uniform float tess_level;
+// Where there's carved detail, see heightmap.glsl
+layout(binding = 15) uniform sampler2D detail_index;
+
+// The most every GL implementation has to support
+const float DETAIL_TESS_LEVEL = 64.0;
+
+// Neighbouring patches look at the same point on a shared edge, so the levels match
+float edge_level(vec2 uv) {
+    return texture(detail_index, uv).r > 0.5 ? DETAIL_TESS_LEVEL : tess_level;
+}
 
 void main() {
     if (gl_InvocationID == 0) {
@@ -38,18 +41,20 @@ void main() {
             // float l1 = length(p3.xy - p2.xy) + 1.0;
             // float l2 = length(p3.xy - p1.xy) + 1.0;
             // float l3 = length(p1.xy - p0.xy) + 1.0;
-            float l0 = tess_level;
-            float l1 = tess_level;
-            float l2 = tess_level;
-            float l3 = tess_level;
+            // Edges at u = 0, v = 0, u = 1 and v = 1
+            float l0 = edge_level(mix(tcs_in[0].tile_uv, tcs_in[2].tile_uv, 0.5));
+            float l1 = edge_level(mix(tcs_in[2].tile_uv, tcs_in[3].tile_uv, 0.5));
+            float l2 = edge_level(mix(tcs_in[1].tile_uv, tcs_in[3].tile_uv, 0.5));
+            float l3 = edge_level(mix(tcs_in[0].tile_uv, tcs_in[1].tile_uv, 0.5));
+            float inner = edge_level(mix(tcs_in[0].tile_uv, tcs_in[3].tile_uv, 0.5));
 
             gl_TessLevelOuter[0] = l0;
             gl_TessLevelOuter[1] = l1;
             gl_TessLevelOuter[2] = l2;
             gl_TessLevelOuter[3] = l3;
 
-            gl_TessLevelInner[0] = min(l1, l3);
-            gl_TessLevelInner[1] = min(l0, l2);
+            gl_TessLevelInner[0] = max(min(l1, l3), inner);
+            gl_TessLevelInner[1] = max(min(l0, l2), inner);
         }
     }
 
@@ -2,23 +2,15 @@
 
 layout(quads, fractional_odd_spacing) in;
 
-layout(std140, binding = 1) uniform UTransforms {
-    mat4 mvp;
-    mat4 proj;
-    mat4 view;
-    mat4 model;
-    mat4 sun_vp;
-}
-uTransforms;
-
-layout(binding = 1) uniform sampler2D heightmap;
-
-uniform float terrain_max_height;
-uniform float terrain_size;
+#include "transforms.glsl"
+#include "heightmap.glsl"
 
 in TCS_OUT { vec2 tile_uv; }
 tes_in[];
 
+// See terrain.vert.glsl
+uniform vec2 tile_offset;
+
 out TES_OUT {
     vec4 frag_pos_sun_space;
     vec3 frag_pos;
@@ -27,26 +19,6 @@ out TES_OUT {
 }
 tes_out;
 
-float sample_height(vec2 uv) { return texture(heightmap, uv).r * terrain_max_height; }
-
-vec3 calc_normal(vec2 uv) {
-    // @speed: maybe pass texture size in the uniform
-    // or maybe build a normal map while drawing on heightmap
-    vec2 heightmap_size = textureSize(heightmap, 0);
-    vec2 texel_size = 1.0 / heightmap_size;
-    float L = sample_height(uv - vec2(texel_size.x, 0));
-    float R = sample_height(uv + vec2(texel_size.x, 0));
-    float T = sample_height(uv - vec2(0, texel_size.y));
-    float B = sample_height(uv + vec2(0, texel_size.y));
-
-    vec2 texel_size_world = terrain_size / heightmap_size;
-    vec3 horizontal = vec3(2.0 * texel_size_world.x, R - L, 0.0);
-    vec3 vertical = vec3(0.0, B - T, 2.0 * texel_size_world.y);
-
-    vec3 normal = normalize(cross(vertical, horizontal));
-    return normal;
-}
-
 void main() {
     vec2 uv1 = mix(tes_in[0].tile_uv, tes_in[1].tile_uv, gl_TessCoord.x);
     vec2 uv2 = mix(tes_in[2].tile_uv, tes_in[3].tile_uv, gl_TessCoord.x);
@@ -60,7 +32,9 @@ void main() {
     gl_Position = uTransforms.mvp * p;
     tes_out.tile_uv = tile_uv;
     tes_out.frag_pos = p.xyz;
-    tes_out.frag_pos_sun_space = uTransforms.sun_vp * vec4(tes_out.frag_pos, 1.0);
+    // Copies get the shadows of the original, the shadow map only covers that
+    vec3 original = p.xyz - vec3(tile_offset.x, 0.0, tile_offset.y);
+    tes_out.frag_pos_sun_space = uTransforms.sun_vp * vec4(original, 1.0);
 
     // Note: we're assuming the model matrix is identity here
     tes_out.normal = calc_normal(tile_uv);
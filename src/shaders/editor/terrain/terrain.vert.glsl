@@ -5,6 +5,8 @@ const vec2 VERTICES[] = vec2[](vec2(0.0, 0.0), vec2(1.0, 0.0), vec2(0.0, 1.0), v
 uniform vec2 terrain_center;
 uniform int num_patches;
 uniform float patch_size;
+// Where a copy of the terrain goes when previewing it tiled, zero otherwise
+uniform vec2 tile_offset;
 
 out VS_OUT { vec2 tile_uv; }
 vs_out;
@@ -22,6 +24,7 @@ void main() {
     // Position
     float half_num = float(num_patches) / 2.0;
     vec2 position = (vertex + vec2(offset.x - half_num, offset.y - half_num)) * patch_size + terrain_center;
+    position += tile_offset;
 
     // TODO: displace height here?
     float height = 0.0;
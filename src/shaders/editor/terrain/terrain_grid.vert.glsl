@@ -0,0 +1,59 @@
+#version 450 core
+
+// The terrain without tessellation: every patch is split into the same grid of
+// quads and the heights are sampled here. Patches are laid out like in
+// terrain.vert.glsl. Drawn with GRID * GRID * 6 vertices per instance.
+
+#include "transforms.glsl"
+#include "heightmap.glsl"
+
+const int GRID = 8;
+// Two triangles per quad, counter-clockwise seen from above
+const ivec2 CORNERS[] = ivec2[](
+    ivec2(0, 0), ivec2(0, 1), ivec2(1, 0),
+    ivec2(1, 0), ivec2(0, 1), ivec2(1, 1)
+);
+
+uniform vec2 terrain_center;
+uniform int num_patches;
+uniform float patch_size;
+// See terrain.vert.glsl
+uniform vec2 tile_offset;
+
+#ifndef SHADOW
+out TES_OUT {
+    vec4 frag_pos_sun_space;
+    vec3 frag_pos;
+    vec3 normal;
+    vec2 tile_uv;
+}
+tes_out;
+#endif
+
+void main() {
+    int quad = gl_VertexID / 6;
+    ivec2 cell = ivec2(quad % GRID, quad / GRID) + CORNERS[gl_VertexID % 6];
+    vec2 vertex = vec2(cell) / float(GRID);
+
+    int x = gl_InstanceID % num_patches;
+    int y = gl_InstanceID / num_patches;
+    vec2 offset = vec2(x, y);
+
+    vec2 tile_uv = (vertex + offset) / float(num_patches);
+    float half_num = float(num_patches) / 2.0;
+    vec2 position = (vertex + offset - half_num) * patch_size + terrain_center;
+    vec4 p = vec4(position.x, sample_height(tile_uv), position.y, 1.0);
+
+#ifdef SHADOW
+    gl_Position = uTransforms.sun_vp * uTransforms.model * p;
+#else
+    // Copies get the shadows of the original, the shadow map only covers that
+    tes_out.frag_pos_sun_space = uTransforms.sun_vp * p;
+    p.xz += tile_offset;
+    gl_Position = uTransforms.mvp * p;
+    tes_out.tile_uv = tile_uv;
+    tes_out.frag_pos = p.xyz;
+    // Note: we're assuming the model matrix is identity here
+    tes_out.normal = calc_normal(tile_uv);
+#endif
+}
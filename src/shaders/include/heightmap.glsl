@@ -0,0 +1,49 @@
+// Heights and normals of the terrain, sampled from the heightmap
+layout(binding = 1) uniform sampler2D heightmap;
+// Sparse detail finer than the heightmap, see detail_map.rs. The index has a texel
+// per tile with its layer in detail_tiles plus one, 0 where nothing is carved.
+layout(binding = 14) uniform sampler2DArray detail_tiles;
+layout(binding = 15) uniform sampler2D detail_index;
+
+uniform float terrain_max_height;
+uniform float terrain_size;
+
+// Detail texels per heightmap texel, as in terrain_core::detail
+const float DETAIL_SCALE = 8.0;
+
+bool has_detail(vec2 uv) { return texture(detail_index, uv).r > 0.5; }
+
+float detail_offset(vec2 uv) {
+    float layer = texture(detail_index, uv).r;
+    if (layer < 0.5) {
+        return 0.0;
+    }
+    vec2 tiles = vec2(textureSize(detail_index, 0));
+    return texture(detail_tiles, vec3(fract(uv * tiles), layer - 1.0)).r;
+}
+
+float sample_height(vec2 uv) {
+    return (texture(heightmap, uv).r + detail_offset(uv)) * terrain_max_height;
+}
+
+vec3 calc_normal(vec2 uv) {
+    // @speed: maybe pass texture size in the uniform
+    // or maybe build a normal map while drawing on heightmap
+    vec2 heightmap_size = textureSize(heightmap, 0);
+    // Carved detail is only seen with the finer step
+    if (has_detail(uv)) {
+        heightmap_size *= DETAIL_SCALE;
+    }
+    vec2 texel_size = 1.0 / heightmap_size;
+    float L = sample_height(uv - vec2(texel_size.x, 0));
+    float R = sample_height(uv + vec2(texel_size.x, 0));
+    float T = sample_height(uv - vec2(0, texel_size.y));
+    float B = sample_height(uv + vec2(0, texel_size.y));
+
+    vec2 texel_size_world = terrain_size / heightmap_size;
+    vec3 horizontal = vec3(2.0 * texel_size_world.x, R - L, 0.0);
+    vec3 vertical = vec3(0.0, B - T, 2.0 * texel_size_world.y);
+
+    vec3 normal = normalize(cross(vertical, horizontal));
+    return normal;
+}
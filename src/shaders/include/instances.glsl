@@ -0,0 +1,23 @@
+// Set by InstancedModel::draw, from a storage buffer where the context has them
+// and from a buffer texture with a column per texel where it doesn't
+#if __VERSION__ >= 430
+layout(std430, binding = 0) readonly buffer Instances {
+    mat4 instance_transforms[];
+};
+
+mat4 instance_transform() {
+    return instance_transforms[gl_InstanceID];
+}
+#else
+layout(binding = 13) uniform samplerBuffer instance_texture;
+
+mat4 instance_transform() {
+    int texel = gl_InstanceID * 4;
+    return mat4(
+        texelFetch(instance_texture, texel),
+        texelFetch(instance_texture, texel + 1),
+        texelFetch(instance_texture, texel + 2),
+        texelFetch(instance_texture, texel + 3)
+    );
+}
+#endif
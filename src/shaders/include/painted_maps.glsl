@@ -0,0 +1,44 @@
+// The painted maps of the terrain and the layer colors they make, shared by the
+// terrain and the models blended into it
+
+layout(binding = 4) uniform sampler2D splat_map;
+layout(binding = 7) uniform sampler2D biome_map;
+// Painted color the layers are multiplied by, white where nothing is painted
+layout(binding = 11) uniform sampler2D tint_map;
+// Full resolution pages of the painted maps near the camera when the maps are too big
+// to send whole, their own textures are averaged down for the distance
+layout(binding = 16) uniform sampler2DArray virtual_pages;
+// Layer of a resident page's splat map plus one, its biome and tint maps follow
+layout(binding = 17) uniform sampler2D page_table;
+// Texels along a side of the painted maps, zero if they're sent whole
+uniform float virtual_size;
+const float PAGE_SIZE = 256.0;
+const float PAGE_BORDER = 1.0;
+
+uniform vec3 layer_colors[4];
+uniform vec3 biome_tints[4];
+
+// `map` is 0 for the splat map, 1 for the biomes and 2 for the tint
+vec4 sample_painted(sampler2D map_texture, int map, vec2 uv) {
+    if (virtual_size > 0.0) {
+        vec2 texel = clamp(uv * virtual_size, vec2(0.0), vec2(virtual_size - 0.5));
+        vec2 page = floor(texel / PAGE_SIZE);
+        float layer = texelFetch(page_table, ivec2(page), 0).r;
+        if (layer > 0.0) {
+            vec2 page_uv = (texel - page * PAGE_SIZE + PAGE_BORDER) /
+                           (PAGE_SIZE + 2.0 * PAGE_BORDER);
+            return texture(virtual_pages, vec3(page_uv, layer - 1.0 + float(map)));
+        }
+    }
+    return texture(map_texture, uv);
+}
+
+vec3 splat_color(vec4 splat) {
+    return splat.r * layer_colors[0] + splat.g * layer_colors[1] + splat.b * layer_colors[2] +
+           splat.a * layer_colors[3];
+}
+
+vec3 biome_tint(vec4 biome) {
+    return biome.r * biome_tints[0] + biome.g * biome_tints[1] + biome.b * biome_tints[2] +
+           biome.a * biome_tints[3];
+}
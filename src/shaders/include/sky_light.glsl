@@ -0,0 +1,50 @@
+// Sun and sky lighting shared by the terrain and the models. Needs transforms.glsl.
+
+// Towards the sun, normalised
+uniform vec3 sun_direction;
+uniform vec3 sun_color;
+// How much light there is even in shadow
+uniform float ambient;
+// 0 for a flat ambient, 1 for ambient coloured by the sky
+uniform float sky_diffuse;
+// How strongly the sky is reflected
+uniform float sky_specular;
+// 1 for the day sky, 0 for the night sky
+uniform float sky_day;
+
+layout(binding = 8) uniform samplerCube irradiance_map;
+layout(binding = 9) uniform samplerCube specular_map;
+
+// Has to match SPECULAR_LEVELS in sky_light.rs
+const float SPECULAR_MAX_LOD = 4.0;
+// Moonlight is bluish, how dim it is comes from the ambient
+const vec3 NIGHT_TINT = vec3(0.5, 0.6, 1.0);
+
+vec3 sky_color(vec3 day_color) {
+    float luminance = dot(day_color, vec3(0.2126, 0.7152, 0.0722));
+    return mix(NIGHT_TINT * luminance, day_color, sky_day);
+}
+
+vec3 sky_ambient(vec3 normal) {
+    // A clear sky is around half as bright as white, so it's doubled to keep `ambient` as is
+    vec3 sky = sky_color(texture(irradiance_map, normal).rgb * 2.0);
+    return ambient * mix(vec3(1.0), sky, sky_diffuse);
+}
+
+vec3 sky_reflection(vec3 normal, vec3 frag_pos, float roughness) {
+    // The camera is where the view matrix takes the origin from
+    mat3 rotation = mat3(uTransforms.view);
+    vec3 camera_position = -transpose(rotation) * uTransforms.view[3].xyz;
+    vec3 view_dir = normalize(camera_position - frag_pos);
+    vec3 reflected = reflect(-view_dir, normal);
+    vec3 sky = sky_color(textureLod(specular_map, reflected, roughness * SPECULAR_MAX_LOD).rgb);
+    // Schlick's approximation for a dielectric
+    float fresnel = 0.04 + 0.96 * pow(1.0 - max(dot(normal, view_dir), 0.0), 5.0);
+    return sky * fresnel * sky_specular;
+}
+
+// Everything lit without shadows
+vec3 sky_lit(vec3 albedo, vec3 normal, vec3 frag_pos, float roughness) {
+    vec3 diffuse = max(dot(sun_direction, normal), 0.0) * sun_color;
+    return (sky_ambient(normal) + diffuse) * albedo + sky_reflection(normal, frag_pos, roughness);
+}
@@ -0,0 +1,8 @@
+layout(std140, binding = 1) uniform UTransforms {
+    mat4 mvp;
+    mat4 proj;
+    mat4 view;
+    mat4 model;
+    mat4 sun_vp;
+}
+uTransforms;
@@ -0,0 +1,31 @@
+// Set from WindSettings::set_uniforms
+uniform float wind_time;
+// Direction scaled by strength, on the ground plane
+uniform vec2 global_wind;
+uniform int wind_zone_count;
+// Center on the ground plane and radius
+uniform vec3 wind_zones[8];
+uniform vec2 zone_winds[8];
+
+// Wind at a point on the ground plane. Zones take over from the global wind
+// towards their centers.
+vec2 wind_at(vec2 point) {
+    vec2 wind = global_wind;
+    for (int i = 0; i < wind_zone_count; i++) {
+        float distance = length(point - wind_zones[i].xy);
+        float weight = 1.0 - smoothstep(0.5, 1.0, distance / wind_zones[i].z);
+        wind = mix(wind, zone_winds[i], weight);
+    }
+    return wind;
+}
+
+// How far a point `height` meters above the root of a model moves. Models
+// sway out of step with each other so the wind looks like it moves in gusts.
+vec3 wind_sway(vec3 root, float height) {
+    vec2 wind = wind_at(root.xz);
+    float phase = dot(root.xz, vec2(0.13, 0.07));
+    float gust = 0.7 + 0.3 * sin(wind_time * (1.0 + length(wind)) + phase);
+    vec2 offset = wind * gust * 0.05 * max(height, 0.0);
+    // Bending over a bit lowers the top
+    return vec3(offset.x, -0.5 * dot(offset, offset) / max(height, 0.01), offset.y);
+}
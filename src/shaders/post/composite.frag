@@ -0,0 +1,195 @@
+#version 450 core
+
+in VS_OUT { vec2 uv; }
+fs_in;
+
+layout(binding = 0) uniform sampler2D scene_color;
+layout(binding = 1) uniform sampler2D scene_depth;
+layout(binding = 2) uniform sampler3D grading_lut;
+
+uniform mat4 proj;
+uniform mat4 inv_proj;
+
+uniform bool ssr_enabled;
+uniform int ssr_steps;
+uniform float ssr_max_distance;  // in meters
+uniform float ssr_thickness;     // in meters
+uniform float wetness;
+// World up in view space
+uniform vec3 view_up;
+
+uniform bool dof_enabled;
+uniform vec2 focus_uv;
+uniform float aperture;
+uniform float max_blur_radius;  // in pixels
+
+uniform bool vignette_enabled;
+uniform float vignette_strength;
+uniform float vignette_radius;
+
+uniform bool grading_enabled;
+uniform float grading_intensity;
+
+// Zero unless the scene is upscaled
+uniform float sharpening;
+
+layout(location = 0) out vec4 Color;
+
+const int NUM_TAPS = 16;
+const vec2 POISSON_DISK[NUM_TAPS] = vec2[](
+    vec2(-0.94201624, -0.39906216), vec2(0.94558609, -0.76890725), vec2(-0.09418410, -0.92938870),
+    vec2(0.34495938, 0.29387760), vec2(-0.91588581, 0.45771432), vec2(-0.81544232, -0.87912464),
+    vec2(-0.38277543, 0.27676845), vec2(0.97484398, 0.75648379), vec2(0.44323325, -0.97511554),
+    vec2(0.53742981, -0.47373420), vec2(-0.26496911, -0.41893023), vec2(0.79197514, 0.19090188),
+    vec2(-0.24188840, 0.99706507), vec2(-0.81409955, 0.91437590), vec2(0.19984126, 0.78641367),
+    vec2(0.14383161, -0.14100790));
+
+vec3 linear_to_srgb(vec3 color) {
+    vec3 low = color * 12.92;
+    vec3 high = 1.055 * pow(color, vec3(1.0 / 2.4)) - 0.055;
+    return mix(low, high, step(vec3(0.0031308), color));
+}
+
+vec3 srgb_to_linear(vec3 color) {
+    vec3 low = color / 12.92;
+    vec3 high = pow((color + 0.055) / 1.055, vec3(2.4));
+    return mix(low, high, step(vec3(0.04045), color));
+}
+
+// LUTs are authored for display values, so do the lookup in sRGB
+vec3 grade(vec3 color) {
+    vec3 srgb = clamp(linear_to_srgb(color), 0.0, 1.0);
+    float size = float(textureSize(grading_lut, 0).x);
+    // Sample texel centers so that 0 and 1 map exactly onto the first and last entries
+    vec3 lut_uv = srgb * ((size - 1.0) / size) + 0.5 / size;
+    vec3 graded = texture(grading_lut, lut_uv).rgb;
+    return srgb_to_linear(mix(srgb, graded, grading_intensity));
+}
+
+vec3 view_position(vec2 uv) {
+    float depth = texture(scene_depth, uv).r;
+    vec4 ndc = vec4(uv * 2.0 - 1.0, depth * 2.0 - 1.0, 1.0);
+    vec4 view_pos = inv_proj * ndc;
+    return view_pos.xyz / view_pos.w;
+}
+
+float linear_depth(vec2 uv) {
+    return -view_position(uv).z;
+}
+
+// Puddles collect on flat ground and reflect more at grazing angles
+float reflectivity(vec3 position, vec3 normal) {
+    float puddle = smoothstep(0.9, 0.97, dot(normal, view_up)) * wetness;
+    float cos_theta = max(dot(normal, -normalize(position)), 0.0);
+    float fresnel = 0.02 + 0.98 * pow(1.0 - cos_theta, 5.0);
+    return puddle * fresnel;
+}
+
+// Marches the reflected ray through view space until it goes behind the depth buffer,
+// then narrows the hit down. Alpha is how much to trust it.
+vec4 trace_reflection(vec3 position, vec3 normal) {
+    vec3 direction = reflect(normalize(position), normal);
+    vec3 ray_step = direction * ssr_max_distance / float(ssr_steps);
+    vec3 ray = position;
+    for (int i = 0; i < ssr_steps; ++i) {
+        ray += ray_step;
+        vec4 clip = proj * vec4(ray, 1.0);
+        if (clip.w <= 0.0) {
+            break;
+        }
+        vec2 uv = clip.xy / clip.w * 0.5 + 0.5;
+        if (any(lessThan(uv, vec2(0.0))) || any(greaterThan(uv, vec2(1.0)))) {
+            break;
+        }
+        float behind = view_position(uv).z - ray.z;
+        if (behind > 0.0 && behind < ssr_thickness) {
+            vec3 refine_step = ray_step;
+            for (int j = 0; j < 5; ++j) {
+                refine_step *= 0.5;
+                ray += view_position(uv).z > ray.z ? -refine_step : refine_step;
+                clip = proj * vec4(ray, 1.0);
+                uv = clip.xy / clip.w * 0.5 + 0.5;
+            }
+            // The sky is already reflected through the sky light
+            if (texture(scene_depth, uv).r >= 1.0) {
+                break;
+            }
+            vec2 edge = min(uv, 1.0 - uv);
+            float fade = smoothstep(0.0, 0.1, min(edge.x, edge.y)) *
+                         (1.0 - float(i) / float(ssr_steps));
+            return vec4(texture(scene_color, uv).rgb, fade);
+        }
+    }
+    return vec4(0.0);
+}
+
+// Contrast adaptive sharpening in the spirit of FSR1's RCAS: the negative lobe
+// shrinks where there's already a lot of local contrast, so edges don't ring
+vec3 sharpen(vec3 color) {
+    vec2 texel_size = 1.0 / textureSize(scene_color, 0);
+    vec3 north = texture(scene_color, fs_in.uv + vec2(0.0, texel_size.y)).rgb;
+    vec3 south = texture(scene_color, fs_in.uv - vec2(0.0, texel_size.y)).rgb;
+    vec3 east = texture(scene_color, fs_in.uv + vec2(texel_size.x, 0.0)).rgb;
+    vec3 west = texture(scene_color, fs_in.uv - vec2(texel_size.x, 0.0)).rgb;
+    vec3 low = min(color, min(min(north, south), min(east, west)));
+    vec3 high = max(color, max(max(north, south), max(east, west)));
+    vec3 headroom = clamp(min(low, 1.0 - high) / max(high, 0.0001), 0.0, 1.0);
+    vec3 lobe = -sqrt(headroom) * mix(0.125, 0.2, sharpening);
+    return (color + (north + south + east + west) * lobe) / (1.0 + 4.0 * lobe);
+}
+
+float circle_of_confusion(float depth, float focal_distance) {
+    float coc = aperture * abs(depth - focal_distance) / max(depth, 0.001);
+    return clamp(coc, 0.0, 1.0) * max_blur_radius;
+}
+
+void main() {
+    vec3 color = texture(scene_color, fs_in.uv).rgb;
+    if (sharpening > 0.0) {
+        color = sharpen(color);
+    }
+
+    if (ssr_enabled && wetness > 0.0 && texture(scene_depth, fs_in.uv).r < 1.0) {
+        vec2 texel_size = 1.0 / textureSize(scene_depth, 0);
+        vec3 position = view_position(fs_in.uv);
+        vec3 right = view_position(fs_in.uv + vec2(texel_size.x, 0.0)) - position;
+        vec3 up = view_position(fs_in.uv + vec2(0.0, texel_size.y)) - position;
+        vec3 normal = normalize(cross(right, up));
+        float strength = reflectivity(position, normal);
+        if (strength > 0.01) {
+            vec4 reflection = trace_reflection(position, normal);
+            color = mix(color, reflection.rgb, strength * reflection.a);
+        }
+    }
+
+    if (dof_enabled) {
+        float focal_distance = linear_depth(focus_uv);
+        float coc = circle_of_confusion(linear_depth(fs_in.uv), focal_distance);
+        if (coc > 0.5) {
+            vec2 texel_size = 1.0 / textureSize(scene_color, 0);
+            vec3 sum = color;
+            float total = 1.0;
+            for (int i = 0; i < NUM_TAPS; ++i) {
+                vec2 tap_uv = fs_in.uv + POISSON_DISK[i] * coc * texel_size;
+                // Don't let sharp foreground bleed into the blurry background
+                float tap_coc = circle_of_confusion(linear_depth(tap_uv), focal_distance);
+                float weight = smoothstep(0.0, coc, tap_coc + 0.5);
+                sum += texture(scene_color, tap_uv).rgb * weight;
+                total += weight;
+            }
+            color = sum / total;
+        }
+    }
+
+    if (vignette_enabled) {
+        float dist = length(fs_in.uv - vec2(0.5)) * 1.4142;
+        float vignette = 1.0 - smoothstep(vignette_radius, 1.0, dist) * vignette_strength;
+        color *= vignette;
+    }
+
+    if (grading_enabled) {
+        color = grade(color);
+    }
+
+    Color = vec4(color, 1.0);
+}
@@ -0,0 +1,20 @@
+#version 450 core
+
+#include "transforms.glsl"
+#include "sky_light.glsl"
+
+layout(binding = 0) uniform sampler2D texSampler;
+
+layout(location = 0) in vec2 inUV;
+layout(location = 2) in vec3 inWorldPos;
+
+layout(location = 0) out vec4 outColor;
+
+uniform vec3 season_tint;
+
+void main() {
+    // The smallest mip is the average color of the whole texture
+    vec3 albedo = textureLod(texSampler, inUV, 16.0).rgb * season_tint;
+    // A flat card has no useful normal, lit as if facing up like the canopy would
+    outColor = vec4(sky_lit(albedo, vec3(0.0, 1.0, 0.0), inWorldPos, 1.0), 1.0);
+}
@@ -0,0 +1,38 @@
+#version 450 core
+
+#include "transforms.glsl"
+#include "instances.glsl"
+#include "wind.glsl"
+
+layout(location = 0) out vec2 outUV;
+layout(location = 2) out vec3 outWorldPos;
+
+// Box around the model in model space
+uniform vec3 bounds_min;
+uniform vec3 bounds_max;
+uniform vec3 camera_position;
+uniform float min_distance;
+uniform float max_distance;
+
+// A quad the size of the model which turns around its vertical axis to face the camera
+void main() {
+    mat4 instance = instance_transform();
+    float distance = distance(camera_position, instance[3].xyz);
+    if (distance < min_distance || distance >= max_distance) {
+        gl_Position = vec4(2.0, 2.0, 2.0, 1.0);
+        return;
+    }
+    vec2 corner = vec2(gl_VertexID & 1, gl_VertexID >> 1);
+    vec3 center = (instance * vec4((bounds_min + bounds_max) * 0.5, 1.0)).xyz;
+    float scale = length(instance[0].xyz);
+    vec3 half_size = (bounds_max - bounds_min) * 0.5 * scale;
+    vec3 to_camera = camera_position - center;
+    vec3 right = normalize(cross(vec3(0.0, 1.0, 0.0), to_camera));
+    vec3 world_pos = center + right * (corner.x * 2.0 - 1.0) * max(half_size.x, half_size.z) +
+                     vec3(0.0, corner.y * 2.0 - 1.0, 0.0) * half_size.y;
+    vec3 root = instance[3].xyz;
+    world_pos += wind_sway(root, world_pos.y - root.y);
+    gl_Position = uTransforms.proj * uTransforms.view * vec4(world_pos, 1.0);
+    outUV = corner;
+    outWorldPos = world_pos;
+}
@@ -0,0 +1,41 @@
+#version 450 core
+
+#include "transforms.glsl"
+#include "instances.glsl"
+#include "wind.glsl"
+
+layout(location = 0) in vec3 inPosition;
+layout(location = 1) in vec3 inNormal;
+layout(location = 2) in vec2 inUV;
+
+layout(location = 0) out vec2 outUV;
+layout(location = 1) out vec3 outNormal;
+layout(location = 2) out vec3 outWorldPos;
+
+// Node transform within the model
+uniform mat4 model;
+// Instances are only drawn between these distances from the camera
+uniform vec3 camera_position;
+uniform float min_distance;
+uniform float max_distance;
+
+void main() {
+    mat4 instance = instance_transform();
+    float distance = distance(camera_position, instance[3].xyz);
+    if (distance < min_distance || distance >= max_distance) {
+        // Outside the clip volume, so the whole instance is clipped
+        gl_Position = vec4(2.0, 2.0, 2.0, 1.0);
+        return;
+    }
+    vec4 world_pos = instance * model * vec4(inPosition, 1.0);
+    vec3 root = instance[3].xyz;
+    world_pos.xyz += wind_sway(root, world_pos.y - root.y);
+#ifdef SHADOW
+    gl_Position = uTransforms.sun_vp * world_pos;
+#else
+    gl_Position = uTransforms.proj * uTransforms.view * world_pos;
+#endif
+    outUV = inUV;
+    outNormal = mat3(instance * model) * inNormal;
+    outWorldPos = world_pos.xyz;
+}
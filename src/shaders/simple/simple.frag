@@ -1,11 +1,74 @@
 #version 450 core
 
+#include "transforms.glsl"
+#include "sky_light.glsl"
+#ifdef GROUND_BLEND
+#include "heightmap.glsl"
+#include "painted_maps.glsl"
+#endif
+
 layout(binding = 0) uniform sampler2D texSampler;
 
 layout(location = 0) in vec2 inUV;
+layout(location = 1) in vec3 inNormal;
+layout(location = 2) in vec3 inWorldPos;
+#ifdef VERTEX_PAINT
+// Linear RGB over the texture, alpha is how much of it
+layout(location = 3) in vec4 inPaint;
+#endif
 
 layout(location = 0) out vec4 outColor;
 
+#ifdef SEASON_TINT
+uniform vec3 season_tint;
+#endif
+#ifdef GROUND_BLEND
+uniform vec2 terrain_center;
+// Meters up the object that the ground's color reaches, 0 for no blending
+uniform float ground_blend_height;
+// How much of the ground's shading comes along with its color
+uniform float ground_blend_shading;
+#endif
+
+const float ROUGHNESS = 0.6;
+
+#ifdef GROUND_BLEND
+// How much of the ground shows at a point, all of it where the object meets the
+// terrain and none `ground_blend_height` above it, so there's no line between them
+float ground_blend(vec3 world_pos, vec2 uv) {
+    bool on_terrain = all(greaterThanEqual(uv, vec2(0.0))) && all(lessThanEqual(uv, vec2(1.0)));
+    if (ground_blend_height <= 0.0 || !on_terrain) {
+        return 0.0;
+    }
+    float above = world_pos.y - sample_height(uv);
+    return 1.0 - smoothstep(0.0, ground_blend_height, above);
+}
+
+// The layers as the terrain mixes them, without its detail texture
+vec3 ground_color(vec2 uv) {
+    vec3 color = splat_color(sample_painted(splat_map, 0, uv));
+    color *= biome_tint(sample_painted(biome_map, 1, uv));
+    return color * sample_painted(tint_map, 2, uv).rgb;
+}
+#endif
+
 void main() {
-    outColor = vec4(texture(texSampler, inUV).rgb, 1.0);
+    vec3 albedo = texture(texSampler, inUV).rgb;
+#ifdef SEASON_TINT
+    albedo *= season_tint;
+#endif
+#ifdef VERTEX_PAINT
+    albedo = mix(albedo, inPaint.rgb, inPaint.a);
+#endif
+    vec3 normal = normalize(inNormal);
+#ifdef GROUND_BLEND
+    vec2 ground_uv = (inWorldPos.xz - terrain_center) / terrain_size + 0.5;
+    float blend = ground_blend(inWorldPos, ground_uv);
+    if (blend > 0.0) {
+        albedo = mix(albedo, ground_color(ground_uv), blend);
+        // Lit as the ground is, the seam shows as much in the shading as in the color
+        normal = normalize(mix(normal, calc_normal(ground_uv), blend * ground_blend_shading));
+    }
+#endif
+    outColor = vec4(sky_lit(albedo, normal, inWorldPos, ROUGHNESS), 1.0);
 }
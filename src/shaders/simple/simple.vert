@@ -1,23 +1,30 @@
 #version 450 core
 
-layout(std140, binding = 1) uniform UTransforms {
-    mat4 mvp;
-    mat4 proj;
-    mat4 view;
-    mat4 model;
-    mat4 sun_vp;
-}
-uTransforms;
+#include "transforms.glsl"
 
 layout(location = 0) in vec3 inPosition;
-layout(location = 1) in vec3 inNormal;  // ignored for now
+layout(location = 1) in vec3 inNormal;
 layout(location = 2) in vec2 inUV;
+#ifdef VERTEX_PAINT
+layout(location = 3) in vec4 inPaint;
+#endif
 
 layout(location = 0) out vec2 outUV;
+layout(location = 1) out vec3 outNormal;
+layout(location = 2) out vec3 outWorldPos;
+#ifdef VERTEX_PAINT
+layout(location = 3) out vec4 outPaint;
+#endif
 
 uniform mat4 model;
 
 void main() {
-    gl_Position = uTransforms.mvp * model * vec4(inPosition, 1.0);
+    vec4 world_pos = model * vec4(inPosition, 1.0);
+    gl_Position = uTransforms.mvp * world_pos;
     outUV = inUV;
+    outNormal = mat3(model) * inNormal;
+    outWorldPos = world_pos.xyz;
+#ifdef VERTEX_PAINT
+    outPaint = inPaint;
+#endif
 }
@@ -0,0 +1,99 @@
+#version 450 core
+
+in VS_OUT { vec2 uv; }
+fs_in;
+
+out vec4 Color;
+
+layout(binding = 0) uniform samplerCube skybox;
+
+// Which cube face is being rendered, in GL order
+uniform int face;
+#ifndef IRRADIANCE
+uniform float roughness;
+#endif
+
+const float PI = 3.14159265359;
+
+vec3 face_direction(vec2 uv) {
+    vec2 st = uv * 2.0 - 1.0;
+    switch (face) {
+        case 0: return vec3(1.0, -st.y, -st.x);
+        case 1: return vec3(-1.0, -st.y, st.x);
+        case 2: return vec3(st.x, 1.0, st.y);
+        case 3: return vec3(st.x, -1.0, -st.y);
+        case 4: return vec3(st.x, -st.y, 1.0);
+        default: return vec3(-st.x, -st.y, -1.0);
+    }
+}
+
+// Any basis will do as long as it's orthonormal
+mat3 tangent_basis(vec3 normal) {
+    vec3 up = abs(normal.y) < 0.999 ? vec3(0.0, 1.0, 0.0) : vec3(1.0, 0.0, 0.0);
+    vec3 tangent = normalize(cross(up, normal));
+    return mat3(tangent, cross(normal, tangent), normal);
+}
+
+#ifdef IRRADIANCE
+
+// Cosine weighted average over the hemisphere. The skybox is sampled from a small mip,
+// which is blurry already, so few samples are enough.
+vec3 prefilter(vec3 normal) {
+    mat3 basis = tangent_basis(normal);
+    vec3 sum = vec3(0.0);
+    float count = 0.0;
+    for (float phi = 0.0; phi < 2.0 * PI; phi += 0.1) {
+        for (float theta = 0.0; theta < 0.5 * PI; theta += 0.1) {
+            vec3 local = vec3(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta));
+            sum += textureLod(skybox, basis * local, 5.0).rgb * cos(theta) * sin(theta);
+            count += 1.0;
+        }
+    }
+    return PI * sum / count;
+}
+
+#else
+
+const uint SAMPLES = 128u;
+
+vec2 hammersley(uint i) {
+    uint bits = i;
+    bits = (bits << 16u) | (bits >> 16u);
+    bits = ((bits & 0x55555555u) << 1u) | ((bits & 0xAAAAAAAAu) >> 1u);
+    bits = ((bits & 0x33333333u) << 2u) | ((bits & 0xCCCCCCCCu) >> 2u);
+    bits = ((bits & 0x0F0F0F0Fu) << 4u) | ((bits & 0xF0F0F0F0u) >> 4u);
+    bits = ((bits & 0x00FF00FFu) << 8u) | ((bits & 0xFF00FF00u) >> 8u);
+    return vec2(float(i) / float(SAMPLES), float(bits) * 2.3283064365386963e-10);
+}
+
+// GGX lobe around the normal, assuming the view is along it
+vec3 prefilter(vec3 normal) {
+    mat3 basis = tangent_basis(normal);
+    float a = roughness * roughness;
+    float source_size = float(textureSize(skybox, 0).x);
+    // Wider lobes read from smaller mips so the samples don't alias
+    float texels_per_sample = 6.0 * source_size * source_size / float(SAMPLES);
+    float lod = 0.5 * log2(texels_per_sample) * roughness;
+    vec3 sum = vec3(0.0);
+    float weight = 0.0;
+    for (uint i = 0u; i < SAMPLES; ++i) {
+        vec2 xi = hammersley(i);
+        float phi = 2.0 * PI * xi.x;
+        float cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+        float sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+        vec3 half_vector = basis * vec3(sin_theta * cos(phi), sin_theta * sin(phi), cos_theta);
+        vec3 light = reflect(-normal, half_vector);
+        float n_dot_l = dot(normal, light);
+        if (n_dot_l > 0.0) {
+            sum += textureLod(skybox, light, lod).rgb * n_dot_l;
+            weight += n_dot_l;
+        }
+    }
+    return sum / weight;
+}
+
+#endif
+
+void main() {
+    Color = vec4(prefilter(normalize(face_direction(fs_in.uv))), 1.0);
+}
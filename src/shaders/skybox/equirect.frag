@@ -0,0 +1,34 @@
+#version 450 core
+
+in VS_OUT { vec2 uv; }
+fs_in;
+
+out vec4 Color;
+
+layout(binding = 0) uniform sampler2D panorama;
+
+// Which cube face is being rendered, in GL order
+uniform int face;
+
+const float PI = 3.14159265359;
+
+// Same as in prefilter.frag
+vec3 face_direction(vec2 uv) {
+    vec2 st = uv * 2.0 - 1.0;
+    switch (face) {
+        case 0: return vec3(1.0, -st.y, -st.x);
+        case 1: return vec3(-1.0, -st.y, st.x);
+        case 2: return vec3(st.x, 1.0, st.y);
+        case 3: return vec3(st.x, -1.0, -st.y);
+        case 4: return vec3(st.x, -st.y, 1.0);
+        default: return vec3(-st.x, -st.y, -1.0);
+    }
+}
+
+void main() {
+    vec3 direction = normalize(face_direction(fs_in.uv));
+    // Panoramas have the zenith at the top row
+    vec2 uv = vec2(atan(direction.z, direction.x) / (2.0 * PI) + 0.5,
+                   acos(clamp(direction.y, -1.0, 1.0)) / PI);
+    Color = vec4(texture(panorama, uv).rgb, 1.0);
+}
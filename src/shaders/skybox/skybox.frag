@@ -5,6 +5,70 @@ in vec3 TexCoords;
 
 layout(binding = 0) uniform samplerCube skybox;
 
+// 1 for the day sky, 0 for the night sky
+uniform float day;
+// Towards the sun, the disk is drawn there
+uniform vec3 sun_direction;
+uniform vec3 sun_color;
+// Radians, zero for no disk
+uniform float sun_disk_radius;
+uniform vec3 moon_direction;
+// Radians
+uniform float moon_radius;
+uniform float star_density;
+
+const vec3 NIGHT_SKY = vec3(0.004, 0.006, 0.015);
+const vec3 MOON_COLOR = vec3(0.8, 0.8, 0.75);
+// Cells per unit of the direction, each one has at most one star
+const float STAR_GRID = 250.0;
+
+float hash(vec3 p) {
+    p = fract(p * vec3(443.897, 441.423, 437.195));
+    p += dot(p, p.yzx + 19.19);
+    return fract((p.x + p.y) * p.z);
+}
+
+vec3 stars(vec3 dir) {
+    vec3 cell = floor(dir * STAR_GRID);
+    if (hash(cell) > star_density * 0.1) {
+        return vec3(0.0);
+    }
+    vec3 center = (cell + 0.25 + 0.5 * vec3(hash(cell + 1.0), hash(cell + 2.0), hash(cell + 3.0)));
+    float d = length(dir * STAR_GRID - center);
+    float brightness = pow(hash(cell + 4.0), 4.0);
+    // Some are a bit blue, some a bit red
+    vec3 color = mix(vec3(1.0, 0.85, 0.7), vec3(0.75, 0.85, 1.0), hash(cell + 5.0));
+    return color * brightness * (1.0 - smoothstep(0.05, 0.25, d)) * 2.0;
+}
+
 void main() {
-    FragColor = texture(skybox, TexCoords);  // * vec4(1.0, 0.7, 0.7, 1.0);
+    vec3 dir = normalize(TexCoords);
+    vec3 color = mix(NIGHT_SKY, texture(skybox, TexCoords).rgb, day);
+
+    // Nothing from beyond the horizon
+    float above = smoothstep(-0.02, 0.05, dir.y);
+    float night = 1.0 - day;
+
+    // The moon is a sphere lit by the sun, which makes its phases
+    vec3 offset = (dir - moon_direction * dot(dir, moon_direction)) / sin(moon_radius);
+    float r2 = dot(offset, offset);
+    bool on_moon = dot(dir, moon_direction) > 0.0 && r2 < 1.0;
+    if (on_moon) {
+        vec3 normal = offset - moon_direction * sqrt(1.0 - r2);
+        float lit = smoothstep(-0.05, 0.1, dot(normal, sun_direction));
+        float edge = 1.0 - smoothstep(0.9, 1.0, r2);
+        // Washed out by the day sky, but still there
+        color = mix(color, MOON_COLOR * (0.02 + lit), edge * above * (0.3 + 0.7 * night));
+    } else {
+        color += stars(dir) * night * above;
+    }
+
+    if (sun_disk_radius > 0.0) {
+        float angle = acos(clamp(dot(dir, sun_direction), -1.0, 1.0));
+        float disk = 1.0 - smoothstep(sun_disk_radius * 0.8, sun_disk_radius, angle);
+        // A faint glow around it
+        float glow = exp(-angle / (sun_disk_radius * 4.0)) * 0.3;
+        color += sun_color * (disk * 4.0 + glow);
+    }
+    FragColor = vec4(color, 1.0);
 }
@@ -0,0 +1,15 @@
+#version 450 core
+
+layout(location = 0) in vec2 inUV;
+layout(location = 1) in vec3 inColor;
+
+layout(location = 0) out vec4 outColor;
+
+// How much of the sun can be seen times how strong the flare is
+uniform float intensity;
+
+void main() {
+    // Soft round sprite, added on top of the scene
+    float falloff = 1.0 - smoothstep(0.0, 1.0, length(inUV));
+    outColor = vec4(inColor * falloff * falloff * intensity, 1.0);
+}
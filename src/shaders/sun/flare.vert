@@ -0,0 +1,36 @@
+#version 450 core
+
+// Sun on the screen
+uniform vec2 sun_ndc;
+// Width over height of the view
+uniform float aspect;
+
+layout(location = 0) out vec2 outUV;
+layout(location = 1) out vec3 outColor;
+
+#ifdef OCCLUSION
+// Half size of the tested square in NDC
+uniform vec2 test_size;
+#else
+// Where along the line from the sun through the screen center each sprite sits,
+// 0 is the sun and 1 is mirrored on the other side of the center
+const float OFFSETS[6] = float[](0.0, 0.35, 0.55, 0.8, 1.1, 1.3);
+const float SIZES[6] = float[](0.25, 0.04, 0.08, 0.05, 0.12, 0.07);
+const vec3 COLORS[6] = vec3[](vec3(1.0, 0.9, 0.7), vec3(0.4, 0.8, 1.0), vec3(1.0, 0.6, 0.3),
+                              vec3(0.6, 1.0, 0.5), vec3(0.5, 0.5, 1.0), vec3(1.0, 0.4, 0.6));
+#endif
+
+void main() {
+    vec2 corner = vec2(gl_VertexID & 1, gl_VertexID >> 1) * 2.0 - 1.0;
+    outUV = corner;
+#ifdef OCCLUSION
+    outColor = vec3(0.0);
+    // On the far plane, so only the sky passes the depth test
+    gl_Position = vec4(sun_ndc + corner * test_size, 1.0, 1.0);
+#else
+    vec2 center = sun_ndc * (1.0 - 2.0 * OFFSETS[gl_InstanceID]);
+    vec2 size = SIZES[gl_InstanceID] * vec2(1.0 / aspect, 1.0);
+    outColor = COLORS[gl_InstanceID];
+    gl_Position = vec4(center + corner * size, 0.0, 1.0);
+#endif
+}
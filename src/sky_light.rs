@@ -0,0 +1,228 @@
+//! Image based lighting from the skybox. The sky is prefiltered once into a small
+//! irradiance cube map for diffuse ambient and a mip chain of increasingly blurry
+//! reflections for specular, so slopes facing the sky pick up its color.
+
+use crate::daylight::{Sky, Sunlight};
+use crate::derived_cache;
+use crate::opengl::caps;
+use crate::opengl::objects::{Framebuffer, Texture, VertexArray};
+use crate::opengl::shader::Program;
+use crate::resources::{self, texture_bytes, MemoryHandle, ResourceKind};
+use crate::skybox::Skybox;
+use crate::texture::unit_to_gl_const;
+use crate::Result;
+
+const IRRADIANCE_SIZE: i32 = 32;
+const SPECULAR_SIZE: i32 = 128;
+/// Roughness goes from 0 at the top level to 1 at the last one, has to match sky_light.glsl
+const SPECULAR_LEVELS: i32 = 5;
+/// RGBA16F
+const TEXEL_BYTES: usize = 8;
+
+const CACHE_KIND: &str = "sky_light";
+/// Goes up whenever prefiltering changes, so older maps aren't read back
+const CACHE_VERSION: u64 = 1;
+
+pub struct SkyLightSettings {
+    /// 0 for a flat ambient, 1 for ambient colored by the sky
+    pub diffuse: f32,
+    pub specular: f32,
+}
+
+impl Default for SkyLightSettings {
+    fn default() -> Self {
+        SkyLightSettings {
+            diffuse: 1.0,
+            specular: 0.3,
+        }
+    }
+}
+
+pub struct SkyLight {
+    irradiance: Texture,
+    specular: Texture,
+    _memory: MemoryHandle,
+
+    // Copied over every frame
+    diffuse_strength: f32,
+    specular_strength: f32,
+    day: f32,
+}
+
+impl SkyLight {
+    /// Renders both maps from the skybox, which takes a moment, so it's done once and
+    /// kept in the derived data cache for the next time the same sky is loaded
+    pub fn new(skybox: &Skybox) -> Result<Self> {
+        let irradiance = Texture::new(gl::TEXTURE_CUBE_MAP, "Sky irradiance");
+        irradiance.set_wrap_and_filter(gl::CLAMP_TO_EDGE, gl::LINEAR, gl::LINEAR);
+        let specular = Texture::new(gl::TEXTURE_CUBE_MAP, "Sky specular");
+        specular.set_wrap_and_filter(gl::CLAMP_TO_EDGE, gl::LINEAR_MIPMAP_LINEAR, gl::LINEAR);
+        unsafe {
+            gl::TextureStorage2D(
+                irradiance.id(),
+                1,
+                gl::RGBA16F,
+                IRRADIANCE_SIZE,
+                IRRADIANCE_SIZE,
+            );
+            gl::TextureStorage2D(
+                specular.id(),
+                SPECULAR_LEVELS,
+                gl::RGBA16F,
+                SPECULAR_SIZE,
+                SPECULAR_SIZE,
+            );
+            // Filtering across faces hides the seams in the blurry levels
+            gl::Enable(gl::TEXTURE_CUBE_MAP_SEAMLESS);
+        }
+
+        // All six faces of every level, the irradiance one first
+        let levels: Vec<(&Texture, i32, i32)> = std::iter::once((&irradiance, 0, IRRADIANCE_SIZE))
+            .chain((0..SPECULAR_LEVELS).map(|level| (&specular, level, SPECULAR_SIZE >> level)))
+            .collect();
+        let level_bytes = |size: i32| 6 * (size * size) as usize * TEXEL_BYTES;
+        let total_bytes: usize = levels.iter().map(|&(_, _, size)| level_bytes(size)).sum();
+        // The fallbacks can't get at all the faces of a cube map at once
+        let cacheable = caps::get().direct_state_access;
+        let hash = skybox.content().with_u64(CACHE_VERSION);
+        let cached = if cacheable {
+            derived_cache::get(CACHE_KIND, hash).filter(|bytes| bytes.len() == total_bytes)
+        } else {
+            None
+        };
+        match cached {
+            Some(bytes) => {
+                let mut offset = 0;
+                for &(texture, level, size) in &levels {
+                    unsafe {
+                        gl::TextureSubImage3D(
+                            texture.id(),
+                            level,
+                            0,
+                            0,
+                            0,
+                            size,
+                            size,
+                            6,
+                            gl::RGBA,
+                            gl::HALF_FLOAT,
+                            bytes[offset..].as_ptr() as *const _,
+                        );
+                    }
+                    offset += level_bytes(size);
+                }
+            }
+            None => {
+                prefilter(skybox, &irradiance, &specular)?;
+                if cacheable {
+                    let mut bytes = vec![0u8; total_bytes];
+                    let mut offset = 0;
+                    for &(texture, level, size) in &levels {
+                        let length = level_bytes(size);
+                        unsafe {
+                            gl::GetTextureImage(
+                                texture.id(),
+                                level,
+                                gl::RGBA,
+                                gl::HALF_FLOAT,
+                                length as i32,
+                                bytes[offset..].as_mut_ptr() as *mut _,
+                            );
+                        }
+                        offset += length;
+                    }
+                    derived_cache::put(CACHE_KIND, hash, &bytes);
+                }
+            }
+        }
+
+        let (irradiance_size, specular_size) = (IRRADIANCE_SIZE as usize, SPECULAR_SIZE as usize);
+        let memory = 6
+            * (texture_bytes(gl::RGBA16F, irradiance_size, irradiance_size, 1)
+                + texture_bytes(gl::RGBA16F, specular_size, specular_size, SPECULAR_LEVELS));
+        let defaults = SkyLightSettings::default();
+        Ok(SkyLight {
+            irradiance,
+            specular,
+            _memory: resources::track(ResourceKind::Texture, memory),
+
+            diffuse_strength: defaults.diffuse,
+            specular_strength: defaults.specular,
+            day: 1.0,
+        })
+    }
+
+    pub fn update(&mut self, settings: &SkyLightSettings, sky: &Sky) {
+        self.diffuse_strength = settings.diffuse;
+        self.specular_strength = settings.specular;
+        self.day = sky.day;
+    }
+
+    /// Binds the maps and sets everything sky_light.glsl declares
+    pub fn set_uniforms(&self, shader: &Program, sun: &Sunlight) -> Result<()> {
+        shader.set_used();
+        shader.set_vec3("sun_direction", &sun.direction)?;
+        shader.set_vec3("sun_color", &sun.color)?;
+        shader.set_f32("ambient", sun.ambient)?;
+        shader.set_f32("sky_diffuse", self.diffuse_strength)?;
+        shader.set_f32("sky_specular", self.specular_strength)?;
+        shader.set_f32("sky_day", self.day)?;
+        unsafe {
+            gl::ActiveTexture(unit_to_gl_const(8));
+            gl::BindTexture(gl::TEXTURE_CUBE_MAP, self.irradiance.id());
+            gl::ActiveTexture(unit_to_gl_const(9));
+            gl::BindTexture(gl::TEXTURE_CUBE_MAP, self.specular.id());
+        }
+        Ok(())
+    }
+}
+
+/// Renders both maps from the skybox's mip levels
+fn prefilter(skybox: &Skybox, irradiance: &Texture, specular: &Texture) -> Result<()> {
+    let irradiance_shader = Program::new()
+        .define("IRRADIANCE", "1")
+        .vertex_shader(include_str!("shaders/post/fullscreen.vert"))?
+        .fragment_shader(include_str!("shaders/sky_light/prefilter.frag"))?
+        .link()?;
+    let specular_shader = Program::new()
+        .vertex_shader(include_str!("shaders/post/fullscreen.vert"))?
+        .fragment_shader(include_str!("shaders/sky_light/prefilter.frag"))?
+        .link()?;
+    // Attributeless fullscreen triangle still needs a vao bound
+    let vao = VertexArray::new("Sky light prefilter");
+    let fbo = Framebuffer::new("Sky light prefilter");
+    unsafe {
+        gl::BindVertexArray(vao.id());
+        gl::BindFramebuffer(gl::FRAMEBUFFER, fbo.id());
+        gl::ActiveTexture(unit_to_gl_const(0));
+        gl::BindTexture(gl::TEXTURE_CUBE_MAP, skybox.texture().id());
+        gl::Disable(gl::DEPTH_TEST);
+        gl::Disable(gl::CULL_FACE);
+    }
+    for face in 0..6 {
+        irradiance_shader.set_used();
+        irradiance_shader.set_i32("face", face)?;
+        render_face(&fbo, irradiance, 0, face, IRRADIANCE_SIZE);
+        specular_shader.set_used();
+        specular_shader.set_i32("face", face)?;
+        for level in 0..SPECULAR_LEVELS {
+            let roughness = level as f32 / (SPECULAR_LEVELS - 1) as f32;
+            specular_shader.set_f32("roughness", roughness)?;
+            render_face(&fbo, specular, level, face, SPECULAR_SIZE >> level);
+        }
+    }
+    unsafe {
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        gl::Enable(gl::DEPTH_TEST);
+        gl::Enable(gl::CULL_FACE);
+    }
+    Ok(())
+}
+
+fn render_face(fbo: &Framebuffer, texture: &Texture, level: i32, face: i32, size: i32) {
+    fbo.attach_texture_layer(gl::COLOR_ATTACHMENT0, texture, level, face);
+    unsafe {
+        gl::Viewport(0, 0, size, size);
+        gl::DrawArrays(gl::TRIANGLES, 0, 3);
+    }
+}
@@ -3,8 +3,18 @@ use std::mem::size_of;
 use gl::types::*;
 use thiserror::Error;
 
+use crate::daylight::{Sky, Sunlight};
+use crate::derived_cache::ContentHash;
+use crate::opengl::objects::{Buffer, Framebuffer, Texture, VertexArray};
 use crate::opengl::shader::{Program, ShaderError};
+use crate::opengl::uniform::UniformBlock;
+use crate::resources::{self, MemoryHandle, ResourceKind};
+use crate::texture::{calculate_mip_levels, unit_to_gl_const};
 use crate::utils::size_of_slice;
+use crate::CameraTransforms;
+
+/// Sharper than this isn't worth the memory behind a blurry horizon
+const MAX_FACE_SIZE: usize = 2048;
 
 #[derive(Debug, Error)]
 pub enum SkyboxError {
@@ -13,54 +23,37 @@ pub enum SkyboxError {
 }
 
 pub struct Skybox {
-    id: GLuint,
+    texture: Texture,
     shader: Program,
-    vao: GLuint,
-    vbo: GLuint,
+    vao: VertexArray,
+    _vbo: Buffer,
+    _memory: MemoryHandle,
+    /// Of the images the sky was made from, for what's derived from it
+    content: ContentHash,
 }
 
 impl Skybox {
     /// right, left, top, bottom, front, back
     pub fn from(paths: [&str; 6]) -> Result<Self, SkyboxError> {
-        // Generate texture
-        let mut id: GLuint = 0;
+        let texture = Texture::new(gl::TEXTURE_CUBE_MAP, "Skybox");
+        // Mips are only read when prefiltering the sky light
+        texture.set_wrap_and_filter(gl::CLAMP_TO_EDGE, gl::LINEAR_MIPMAP_LINEAR, gl::LINEAR);
         unsafe {
-            gl::GenTextures(1, &mut id);
-            gl::BindTexture(gl::TEXTURE_CUBE_MAP, id);
-
-            gl::TexParameteri(
-                gl::TEXTURE_CUBE_MAP,
-                gl::TEXTURE_WRAP_S,
-                gl::CLAMP_TO_EDGE as GLint,
-            );
-            gl::TexParameteri(
-                gl::TEXTURE_CUBE_MAP,
-                gl::TEXTURE_WRAP_T,
-                gl::CLAMP_TO_EDGE as GLint,
-            );
-            gl::TexParameteri(
-                gl::TEXTURE_CUBE_MAP,
-                gl::TEXTURE_WRAP_R,
-                gl::CLAMP_TO_EDGE as GLint,
-            );
-            gl::TexParameteri(
-                gl::TEXTURE_CUBE_MAP,
-                gl::TEXTURE_MIN_FILTER,
-                gl::LINEAR as GLint,
-            );
-            gl::TexParameteri(
-                gl::TEXTURE_CUBE_MAP,
-                gl::TEXTURE_MAG_FILTER,
-                gl::LINEAR as GLint,
-            );
+            // Faces are uploaded one by one through the bind point
+            gl::BindTexture(gl::TEXTURE_CUBE_MAP, texture.id());
         }
 
         // Load images
+        let mut texture_bytes = 0;
+        let mut content = ContentHash::new();
         for (i, path) in paths.iter().enumerate() {
             let img = image::open(path)
                 .expect("Can't load skybox image")
                 .into_rgb8();
             let (width, height) = img.dimensions();
+            content = content.with_u64(width as u64).with_bytes(img.as_raw());
+            texture_bytes +=
+                resources::texture_bytes(gl::SRGB8, width as usize, height as usize, 1);
             unsafe {
                 // Send to GPU
                 gl::TexImage2D(
@@ -77,11 +70,99 @@ impl Skybox {
             }
         }
 
+        // A full mip chain adds a third
+        texture_bytes = texture_bytes * 4 / 3;
+        unsafe {
+            gl::GenerateTextureMipmap(texture.id());
+        }
+
+        Skybox::with_texture(texture, texture_bytes, content)
+    }
+
+    /// From an HDR panorama, as most downloadable skies come. Each face is a quarter
+    /// of the panorama's width.
+    pub fn from_equirect(width: u32, height: u32, pixels: &[f32]) -> Result<Self, SkyboxError> {
+        let panorama = Texture::new(gl::TEXTURE_2D, "Sky panorama");
+        panorama.set_wrap_and_filter(gl::REPEAT, gl::LINEAR, gl::LINEAR);
+        unsafe {
+            gl::TextureStorage2D(panorama.id(), 1, gl::RGB16F, width as i32, height as i32);
+            gl::TextureSubImage2D(
+                panorama.id(),
+                0,
+                0,
+                0,
+                width as i32,
+                height as i32,
+                gl::RGB,
+                gl::FLOAT,
+                pixels.as_ptr() as *const _,
+            );
+        }
+
+        let face_size = (width as usize / 4).clamp(16, MAX_FACE_SIZE);
+        let mip_levels = calculate_mip_levels(face_size, face_size);
+        let texture = Texture::new(gl::TEXTURE_CUBE_MAP, "Skybox");
+        texture.set_wrap_and_filter(gl::CLAMP_TO_EDGE, gl::LINEAR_MIPMAP_LINEAR, gl::LINEAR);
+        unsafe {
+            gl::TextureStorage2D(
+                texture.id(),
+                mip_levels,
+                gl::RGBA16F,
+                face_size as i32,
+                face_size as i32,
+            );
+        }
+
+        let shader = Program::new()
+            .vertex_shader(include_str!("shaders/post/fullscreen.vert"))?
+            .fragment_shader(include_str!("shaders/skybox/equirect.frag"))?
+            .link()?;
+        // Attributeless fullscreen triangle still needs a vao bound
+        let vao = VertexArray::new("Sky panorama");
+        let fbo = Framebuffer::new("Sky panorama");
+        shader.set_used();
+        unsafe {
+            gl::BindVertexArray(vao.id());
+            gl::BindFramebuffer(gl::FRAMEBUFFER, fbo.id());
+            gl::ActiveTexture(unit_to_gl_const(0));
+            gl::BindTexture(gl::TEXTURE_2D, panorama.id());
+            gl::Viewport(0, 0, face_size as i32, face_size as i32);
+            gl::Disable(gl::DEPTH_TEST);
+            gl::Disable(gl::CULL_FACE);
+        }
+        for face in 0..6 {
+            shader.set_i32("face", face)?;
+            fbo.attach_texture_layer(gl::COLOR_ATTACHMENT0, &texture, 0, face);
+            unsafe {
+                gl::DrawArrays(gl::TRIANGLES, 0, 3);
+            }
+        }
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+            gl::Enable(gl::DEPTH_TEST);
+            gl::Enable(gl::CULL_FACE);
+            gl::GenerateTextureMipmap(texture.id());
+        }
+
+        let texture_bytes =
+            6 * resources::texture_bytes(gl::RGBA16F, face_size, face_size, mip_levels);
+        let content = ContentHash::new()
+            .with_u64(width as u64)
+            .with_floats(pixels);
+        Skybox::with_texture(texture, texture_bytes, content)
+    }
+
+    fn with_texture(
+        texture: Texture,
+        texture_bytes: usize,
+        content: ContentHash,
+    ) -> Result<Self, SkyboxError> {
         // Create shader
         let shader = Program::new()
             .vertex_shader(include_str!("shaders/skybox/skybox.vert"))?
             .fragment_shader(include_str!("shaders/skybox/skybox.frag"))?
             .link()?;
+        shader.check_uniform_block(&CameraTransforms::layout())?;
         shader.set_used();
 
         #[rustfmt::skip]
@@ -131,54 +212,62 @@ impl Skybox {
         ];
 
         // Init buffers
-        let mut vao: GLuint = 0;
-        let mut vbo: GLuint = 0;
+        let vao = VertexArray::new("Skybox");
+        let vbo = Buffer::new("Skybox vertices");
         unsafe {
-            gl::CreateVertexArrays(1, &mut vao);
-            gl::CreateBuffers(1, &mut vbo);
-
             // Upload vertices
             gl::NamedBufferStorage(
-                vbo,
+                vbo.id(),
                 size_of_slice(&vertices) as isize,
                 vertices.as_ptr() as *const _,
                 0,
             );
 
             // Describe vertex buffer
-            gl::VertexArrayVertexBuffer(vao, 0, vbo, 0, (size_of::<f32>() * 3) as i32);
-            gl::VertexArrayAttribFormat(vao, 0, 3, gl::FLOAT, gl::FALSE, 0);
-            gl::EnableVertexArrayAttrib(vao, 0);
+            gl::VertexArrayVertexBuffer(vao.id(), 0, vbo.id(), 0, (size_of::<f32>() * 3) as i32);
+            gl::VertexArrayAttribFormat(vao.id(), 0, 3, gl::FLOAT, gl::FALSE, 0);
+            gl::EnableVertexArrayAttrib(vao.id(), 0);
         }
 
         Ok(Skybox {
-            id,
+            texture,
             shader,
             vao,
-            vbo,
+            _vbo: vbo,
+            _memory: resources::track(ResourceKind::Texture, texture_bytes),
+            content,
         })
     }
 
-    pub fn draw(&self) {
+    pub fn texture(&self) -> &Texture {
+        &self.texture
+    }
+
+    pub fn content(&self) -> ContentHash {
+        self.content
+    }
+
+    /// The sun disk is left out if its radius is 0
+    pub fn draw(&self, sky: &Sky, sun: &Sunlight, disk_radius: f32) -> Result<(), SkyboxError> {
         unsafe {
             gl::DepthFunc(gl::LEQUAL);
         }
         self.shader.set_used();
+        self.shader.set_f32("day", sky.day)?;
+        self.shader
+            .set_vec3("moon_direction", &sky.moon_direction)?;
+        self.shader.set_f32("moon_radius", sky.moon_radius)?;
+        self.shader.set_f32("star_density", sky.star_density)?;
+        self.shader.set_vec3("sun_direction", &sun.direction)?;
+        self.shader.set_vec3("sun_color", &sun.color)?;
+        self.shader.set_f32("sun_disk_radius", disk_radius)?;
 
         unsafe {
-            gl::BindVertexArray(self.vao);
-            gl::BindTexture(gl::TEXTURE_CUBE_MAP, self.id);
+            gl::BindVertexArray(self.vao.id());
+            gl::BindTexture(gl::TEXTURE_CUBE_MAP, self.texture.id());
             gl::DrawArrays(gl::TRIANGLES, 0, 36);
             gl::DepthFunc(gl::LESS);
         }
-    }
-}
-
-impl Drop for Skybox {
-    fn drop(&mut self) {
-        unsafe {
-            gl::DeleteBuffers(1, &self.vbo as *const _);
-            gl::DeleteVertexArrays(1, &self.vao as *const _);
-        }
+        Ok(())
     }
 }
@@ -0,0 +1,343 @@
+use glam::Vec2;
+use serde::{Deserialize, Serialize};
+
+use crate::heightfield::{DirtyRect, EdgeFill, HeightField};
+use crate::opengl::objects::Texture;
+use crate::terrain::Brush;
+use crate::utils::{brush_uv, texel_uv};
+use crate::virtual_texture::{MapTexture, PagedMap};
+
+pub const NUM_LAYERS: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum PaintBlendMode {
+    /// Gradually accumulates the layer
+    Add,
+    /// Gradually removes the layer, giving its weight back to the others
+    Subtract,
+    /// Sets the layer to the brush opacity
+    Replace,
+    /// Only ever raises the layer up to the brush opacity
+    Max,
+}
+
+impl PaintBlendMode {
+    pub const ALL: [PaintBlendMode; 4] = [
+        PaintBlendMode::Add,
+        PaintBlendMode::Subtract,
+        PaintBlendMode::Replace,
+        PaintBlendMode::Max,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PaintBlendMode::Add => "Add",
+            PaintBlendMode::Subtract => "Subtract",
+            PaintBlendMode::Replace => "Replace",
+            PaintBlendMode::Max => "Max",
+        }
+    }
+}
+
+pub struct TerrainLayer {
+    pub name: &'static str,
+    /// Linear RGB
+    pub color: [f32; 3],
+}
+
+pub fn default_layers() -> [TerrainLayer; NUM_LAYERS] {
+    [
+        TerrainLayer {
+            name: "Grass",
+            color: [0.17, 0.32, 0.08],
+        },
+        TerrainLayer {
+            name: "Dirt",
+            color: [0.30, 0.20, 0.11],
+        },
+        TerrainLayer {
+            name: "Rock",
+            color: [0.33, 0.32, 0.30],
+        },
+        TerrainLayer {
+            name: "Snow",
+            color: [0.90, 0.92, 0.95],
+        },
+    ]
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PaintSettings {
+    pub layer: usize,
+    pub blend_mode: PaintBlendMode,
+    /// How fast the brush builds up, per second
+    pub strength: f32,
+    /// Each layer remembers its own brush opacity
+    pub layer_opacity: [f32; NUM_LAYERS],
+}
+
+impl Default for PaintSettings {
+    fn default() -> Self {
+        PaintSettings {
+            layer: 1,
+            blend_mode: PaintBlendMode::Add,
+            strength: 2.0,
+            layer_opacity: [1.0; NUM_LAYERS],
+        }
+    }
+}
+
+impl PaintSettings {
+    pub fn opacity(&self) -> f32 {
+        self.layer_opacity[self.layer]
+    }
+}
+
+/// Per-texel layer weights. Kept on the CPU because painting needs
+/// to renormalise all the layers, which is awkward to do with blending.
+pub struct SplatMap {
+    texture: MapTexture,
+    size: usize,
+    weights: Vec<[f32; NUM_LAYERS]>,
+}
+
+impl SplatMap {
+    /// Everything starts out as the first layer
+    pub fn new(size: usize) -> Self {
+        let mut base = [0.0; NUM_LAYERS];
+        base[0] = 1.0;
+        let weights = vec![base; size * size];
+
+        let mut splat_map = SplatMap {
+            texture: MapTexture::new(size, "Splat map"),
+            size,
+            weights,
+        };
+        splat_map.upload_region(0, 0, size, size);
+        splat_map
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// The whole map, or its average if it's too big for that
+    pub fn texture(&self) -> &Texture {
+        &self.texture.texture
+    }
+
+    /// Texels changed since the last call, for the virtual pages
+    pub fn take_dirty(&mut self) -> Option<DirtyRect> {
+        self.texture.take_dirty()
+    }
+
+    /// Each layer is filtered separately, then the weights are renormalised
+    pub fn resampled(&self, new_size: usize) -> SplatMap {
+        let layers: Vec<HeightField> = self
+            .layers()
+            .iter()
+            .map(|layer| layer.resampled(new_size))
+            .collect();
+        SplatMap::from_layers(&layers)
+    }
+
+    /// Same as `HeightField::region`, the new texels take the weights of the closest edge
+    pub fn region(&self, x0: isize, y0: isize, size: usize) -> SplatMap {
+        let layers: Vec<HeightField> = self
+            .layers()
+            .iter()
+            .map(|layer| layer.region(x0, y0, size, EdgeFill::Clamp))
+            .collect();
+        SplatMap::from_layers(&layers)
+    }
+
+    /// Weights of a square region which may reach outside the map, edges repeat
+    pub fn weights_around(&self, x0: isize, y0: isize, size: usize) -> Vec<[f32; NUM_LAYERS]> {
+        let max = self.size as isize - 1;
+        let mut weights = Vec::with_capacity(size * size);
+        for y in y0..y0 + size as isize {
+            for x in x0..x0 + size as isize {
+                let (x, y) = (x.clamp(0, max) as usize, y.clamp(0, max) as usize);
+                weights.push(self.weights[y * self.size + x]);
+            }
+        }
+        weights
+    }
+
+    pub fn weights_at(&self, x: usize, y: usize) -> [f32; NUM_LAYERS] {
+        self.weights[y * self.size + x]
+    }
+
+    pub fn layers(&self) -> Vec<HeightField> {
+        (0..NUM_LAYERS)
+            .map(|layer| {
+                let data = self.weights.iter().map(|texel| texel[layer]).collect();
+                HeightField::from_data(self.size, data)
+            })
+            .collect()
+    }
+
+    /// Layers are weighed against each other, all zero is the first layer
+    pub fn from_layers(layers: &[HeightField]) -> SplatMap {
+        let size = layers[0].size();
+        let mut splat_map = SplatMap::new(size);
+        for (i, texel) in splat_map.weights.iter_mut().enumerate() {
+            for (layer, weights) in layers.iter().enumerate() {
+                texel[layer] = weights.data()[i];
+            }
+            let sum: f32 = texel.iter().sum();
+            if sum > 0.0 {
+                for w in texel.iter_mut() {
+                    *w /= sum;
+                }
+            } else {
+                *texel = [0.0; NUM_LAYERS];
+                texel[0] = 1.0;
+            }
+        }
+        splat_map.upload_region(0, 0, size, size);
+        splat_map
+    }
+
+    /// `cursor` and `brush_size` are normalised to the terrain size
+    pub fn paint(
+        &mut self,
+        cursor: Vec2,
+        brush: &Brush,
+        brush_size: f32,
+        settings: &PaintSettings,
+        delta_time: f32,
+    ) {
+        let size = self.size as f32;
+        let half_extent = brush_size / 2.0;
+        let to_texel = |uv: f32| (uv * size).floor().clamp(0.0, size - 1.0) as usize;
+        let (x_min, x_max) = (
+            to_texel(cursor.x - half_extent),
+            to_texel(cursor.x + half_extent),
+        );
+        let (y_min, y_max) = (
+            to_texel(cursor.y - half_extent),
+            to_texel(cursor.y + half_extent),
+        );
+
+        let layer = settings.layer;
+        let opacity = settings.opacity();
+        let rate = settings.strength * delta_time;
+
+        for y in y_min..=y_max {
+            for x in x_min..=x_max {
+                let uv = texel_uv(x, y, self.size);
+                let w = brush.sample(brush_uv(uv, cursor, brush_size));
+                if w <= 0.0 {
+                    continue;
+                }
+
+                let texel = &mut self.weights[y * self.size + x];
+                let old = texel[layer];
+                let new = match settings.blend_mode {
+                    PaintBlendMode::Add => (old + w * rate).min(opacity.max(old)),
+                    PaintBlendMode::Subtract => old - w * rate * opacity,
+                    PaintBlendMode::Replace => old + (opacity - old) * w,
+                    PaintBlendMode::Max => old.max(w * opacity),
+                };
+                set_layer_weight(texel, layer, new.clamp(0.0, 1.0));
+            }
+        }
+
+        self.upload_region(x_min, y_min, x_max + 1, y_max + 1);
+    }
+
+    /// Raises `layer` to at least `coverage` everywhere, which has to be the same size
+    pub fn cover(&mut self, layer: usize, coverage: &HeightField) {
+        debug_assert_eq!(coverage.size(), self.size);
+        for (texel, &covered) in self.weights.iter_mut().zip(coverage.data()) {
+            if covered > texel[layer] {
+                set_layer_weight(texel, layer, covered);
+            }
+        }
+        self.upload_region(0, 0, self.size, self.size);
+    }
+
+    /// Adds `amount` of `layer` to every texel in `rect`, taking it from the others
+    pub fn add_to_layer(
+        &mut self,
+        layer: usize,
+        rect: &DirtyRect,
+        amount: impl Fn(usize, usize) -> f32,
+    ) {
+        for y in rect.y0..rect.y1 {
+            for x in rect.x0..rect.x1 {
+                let amount = amount(x, y);
+                if amount > 0.0 {
+                    let texel = &mut self.weights[y * self.size + x];
+                    set_layer_weight(texel, layer, (texel[layer] + amount).min(1.0));
+                }
+            }
+        }
+        self.upload_region(rect.x0, rect.y0, rect.x1, rect.y1);
+    }
+
+    pub fn read_region(&self, rect: &DirtyRect) -> Vec<[f32; NUM_LAYERS]> {
+        let mut weights = Vec::with_capacity(rect.width() * rect.height());
+        for y in rect.y0..rect.y1 {
+            weights
+                .extend_from_slice(&self.weights[y * self.size + rect.x0..y * self.size + rect.x1]);
+        }
+        weights
+    }
+
+    pub fn write_region(&mut self, rect: &DirtyRect, weights: &[[f32; NUM_LAYERS]]) {
+        debug_assert_eq!(weights.len(), rect.width() * rect.height());
+        for (y, row) in (rect.y0..rect.y1).zip(weights.chunks_exact(rect.width())) {
+            self.weights[y * self.size + rect.x0..y * self.size + rect.x1].copy_from_slice(row);
+        }
+        self.upload_region(rect.x0, rect.y0, rect.x1, rect.y1);
+    }
+
+    /// Sends texels in [x0, x1) x [y0, y1) to the GPU
+    fn upload_region(&mut self, x0: usize, y0: usize, x1: usize, y1: usize) {
+        let rect = self.texture.covering(&DirtyRect { x0, y0, x1, y1 });
+        let pixels = self.pixels(&rect);
+        self.texture.upload(&rect, &pixels);
+    }
+}
+
+impl PagedMap for SplatMap {
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    fn pixels(&self, rect: &DirtyRect) -> Vec<u8> {
+        let mut pixels = Vec::with_capacity(rect.width() * rect.height() * NUM_LAYERS);
+        for y in rect.y0..rect.y1 {
+            for texel in &self.weights[y * self.size + rect.x0..y * self.size + rect.x1] {
+                pixels.extend(texel.iter().map(|&w| (w * 255.0).round() as u8));
+            }
+        }
+        pixels
+    }
+}
+
+/// Sets one layer and scales the rest so that all weights still sum up to 1
+fn set_layer_weight(texel: &mut [f32; NUM_LAYERS], layer: usize, value: f32) {
+    texel[layer] = value;
+    let rest: f32 = texel
+        .iter()
+        .enumerate()
+        .filter(|&(i, _)| i != layer)
+        .map(|(_, w)| w)
+        .sum();
+    let remaining = 1.0 - value;
+    if rest > 0.0 {
+        for (i, w) in texel.iter_mut().enumerate() {
+            if i != layer {
+                *w *= remaining / rest;
+            }
+        }
+    } else {
+        // Nothing to scale, the freed up weight goes to the base layer
+        // (or the next one when painting the base layer itself)
+        let fallback = if layer == 0 { 1 } else { 0 };
+        texel[fallback] = remaining;
+    }
+}
@@ -0,0 +1,135 @@
+//! Pieces of terrain saved for reuse. A stamp is a 16-bit heightmap image plus a JSON file
+//! next to it with the scale it was captured at, so that it can be shared as two files.
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use glam::Vec2;
+use image::{DynamicImage, ImageBuffer};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::heightfield::HeightField;
+use crate::terrain::Terrain;
+
+pub const STAMP_DIRECTORY: &str = "stamps";
+
+#[derive(Debug, Error)]
+pub enum StampError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Couldn't write the image: {0}")]
+    Image(#[from] image::ImageError),
+    #[error("Couldn't write the metadata: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// Stored next to the image
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StampInfo {
+    /// Side of the captured square in meters
+    pub world_size: f32,
+    /// Height in meters of black in the image
+    pub base_height: f32,
+    /// Meters between black and white. The captured range is stretched
+    /// over the whole image to keep as much precision as possible.
+    pub height_range: f32,
+}
+
+pub struct Stamp {
+    pub info: StampInfo,
+    pub heights: HeightField,
+}
+
+pub struct StampSettings {
+    /// What the next captured stamp will be called
+    pub name: String,
+    /// Names of the stamps already saved
+    pub library: Vec<String>,
+}
+
+impl Default for StampSettings {
+    fn default() -> Self {
+        StampSettings {
+            name: "stamp".to_owned(),
+            library: list(),
+        }
+    }
+}
+
+/// The square of terrain `size` meters across centered on a world space point
+pub fn capture(terrain: &Terrain, center: Vec2, size: f32) -> Stamp {
+    let field = terrain.heights_around(center, size);
+    let (min, max) = field
+        .data()
+        .iter()
+        .fold((f32::MAX, f32::MIN), |(min, max), &h| {
+            (min.min(h), max.max(h))
+        });
+    let range = (max - min).max(f32::EPSILON);
+    let data = field.data().iter().map(|&h| (h - min) / range).collect();
+
+    let max_height = terrain.max_height();
+    Stamp {
+        info: StampInfo {
+            world_size: size,
+            base_height: min * max_height,
+            height_range: (max - min) * max_height,
+        },
+        heights: HeightField::from_data(field.size(), data),
+    }
+}
+
+/// Writes `<name>.png` and `<name>.json` into the library, replacing a stamp with the same name
+pub fn save(name: &str, stamp: &Stamp) -> Result<PathBuf, StampError> {
+    fs::create_dir_all(STAMP_DIRECTORY)?;
+    let path = PathBuf::from(STAMP_DIRECTORY).join(file_stem(name));
+
+    let size = stamp.heights.size() as u32;
+    let image = ImageBuffer::from_raw(size, size, stamp.heights.to_u16())
+        .expect("Stamp buffer has the wrong size");
+    DynamicImage::ImageLuma16(image).save(path.with_extension("png"))?;
+    fs::write(
+        path.with_extension("json"),
+        serde_json::to_string_pretty(&stamp.info)?,
+    )?;
+    Ok(path.with_extension("png"))
+}
+
+/// Names of all the stamps that have both an image and metadata
+pub fn list() -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(STAMP_DIRECTORY)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| path.extension().and_then(|e| e.to_str()) == Some("png"))
+                .filter(|path| path.with_extension("json").exists())
+                .filter_map(|path| Some(path.file_stem()?.to_string_lossy().into_owned()))
+                .collect()
+        })
+        .unwrap_or_default();
+    names.sort();
+    names
+}
+
+/// Names come from the GUI, keep them to something every file system is happy with
+pub fn file_stem(name: &str) -> String {
+    let stem: String = name
+        .trim()
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if stem.is_empty() {
+        "stamp".to_owned()
+    } else {
+        stem
+    }
+}
@@ -0,0 +1,294 @@
+//! Where project archives are exported to and imported from. By default that's the
+//! working directory, but it can be a folder elsewhere (a network share, or a bucket
+//! mounted with rclone or s3fs) or a WebDAV server, so that a team can keep its
+//! projects in one place.
+//!
+//! WebDAV is spoken over plain HTTP only, there's no TLS here. S3 proper needs signed
+//! requests, so S3-compatible buckets go through a mounted folder for now; another
+//! backend only has to implement `Storage` and get a `StorageKind`.
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Storage settings are the editor's rather than a project's, so they sit next to it.
+/// The WebDAV password is kept in there as it is.
+const SETTINGS_PATH: &str = "storage.json";
+
+const HTTP_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_HTTP_PORT: u16 = 80;
+
+#[derive(Debug, Error)]
+pub enum StorageError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("'{0}' isn't an http:// URL")]
+    BadUrl(String),
+    #[error("Only plain http:// is supported, not HTTPS")]
+    Https,
+    #[error("The server sent something that isn't HTTP")]
+    BadResponse,
+    #[error("The server said {status} {reason}")]
+    Http { status: u16, reason: String },
+}
+
+/// Named files somewhere, names being relative paths with forward slashes
+pub trait Storage {
+    fn read(&self, name: &str) -> Result<Vec<u8>, StorageError>;
+    /// Replaces the file if it's already there
+    fn write(&self, name: &str, bytes: &[u8]) -> Result<(), StorageError>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum StorageKind {
+    /// The working directory
+    Local,
+    /// Any directory the OS can reach, including network shares
+    Folder,
+    WebDav,
+}
+
+impl StorageKind {
+    pub const ALL: [StorageKind; 3] =
+        [StorageKind::Local, StorageKind::Folder, StorageKind::WebDav];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            StorageKind::Local => "This computer",
+            StorageKind::Folder => "Shared folder",
+            StorageKind::WebDav => "WebDAV",
+        }
+    }
+}
+
+/// Settings for every kind are kept, so that switching back and forth doesn't lose them
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StorageSettings {
+    pub kind: StorageKind,
+    pub folder: String,
+    /// Collection the archives go in, e.g. http://nas.local/dav/terrain/
+    pub url: String,
+    /// No authentication if empty
+    pub username: String,
+    pub password: String,
+}
+
+impl Default for StorageSettings {
+    fn default() -> Self {
+        StorageSettings {
+            kind: StorageKind::Local,
+            folder: String::new(),
+            url: String::new(),
+            username: String::new(),
+            password: String::new(),
+        }
+    }
+}
+
+impl StorageSettings {
+    /// The defaults if nothing was saved or it can't be read
+    pub fn load() -> Self {
+        fs::read_to_string(SETTINGS_PATH)
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<(), StorageError> {
+        let json = serde_json::to_string_pretty(self).map_err(io::Error::from)?;
+        fs::write(SETTINGS_PATH, json)?;
+        Ok(())
+    }
+
+    pub fn open(&self) -> Result<Box<dyn Storage>, StorageError> {
+        Ok(match self.kind {
+            StorageKind::Local => Box::new(FolderStorage {
+                root: PathBuf::from("."),
+            }),
+            StorageKind::Folder => Box::new(FolderStorage {
+                root: PathBuf::from(&self.folder),
+            }),
+            StorageKind::WebDav => Box::new(WebDavStorage::new(
+                &self.url,
+                &self.username,
+                &self.password,
+            )?),
+        })
+    }
+}
+
+struct FolderStorage {
+    root: PathBuf,
+}
+
+impl Storage for FolderStorage {
+    fn read(&self, name: &str) -> Result<Vec<u8>, StorageError> {
+        Ok(fs::read(self.root.join(name))?)
+    }
+
+    /// Through a temporary file, so that nobody else on the share reads half an archive
+    fn write(&self, name: &str, bytes: &[u8]) -> Result<(), StorageError> {
+        let path = self.root.join(name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut partial = path.clone().into_os_string();
+        partial.push(".part");
+        fs::write(&partial, bytes)?;
+        fs::rename(&partial, &path)?;
+        Ok(())
+    }
+}
+
+struct WebDavStorage {
+    host: String,
+    port: u16,
+    /// Ends with a slash
+    path: String,
+    /// Basic credentials, if there are any
+    authorization: Option<String>,
+}
+
+impl WebDavStorage {
+    fn new(url: &str, username: &str, password: &str) -> Result<Self, StorageError> {
+        if url.starts_with("https://") {
+            return Err(StorageError::Https);
+        }
+        let bad_url = || StorageError::BadUrl(url.to_owned());
+        let rest = url.strip_prefix("http://").ok_or_else(bad_url)?;
+        let (authority, path) = match rest.find('/') {
+            Some(slash) => rest.split_at(slash),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| bad_url())?),
+            None => (authority, DEFAULT_HTTP_PORT),
+        };
+        if host.is_empty() {
+            return Err(bad_url());
+        }
+        let mut path = path.to_owned();
+        if !path.ends_with('/') {
+            path.push('/');
+        }
+        let authorization = if username.is_empty() {
+            None
+        } else {
+            Some(base64(format!("{}:{}", username, password).as_bytes()))
+        };
+        Ok(WebDavStorage {
+            host: host.to_owned(),
+            port,
+            path,
+            authorization,
+        })
+    }
+
+    /// One request per connection. HTTP/1.0 keeps the server from chunking the
+    /// response, so the body is whatever comes after the headers.
+    fn request(&self, method: &str, name: &str, body: &[u8]) -> Result<Vec<u8>, StorageError> {
+        let mut stream = TcpStream::connect((self.host.as_str(), self.port))?;
+        stream.set_read_timeout(Some(HTTP_TIMEOUT))?;
+        stream.set_write_timeout(Some(HTTP_TIMEOUT))?;
+
+        let mut head = format!(
+            "{} {}{} HTTP/1.0\r\nHost: {}\r\nContent-Length: {}\r\n",
+            method,
+            self.path,
+            encode_path(name),
+            self.host,
+            body.len()
+        );
+        if let Some(authorization) = &self.authorization {
+            head.push_str(&format!("Authorization: Basic {}\r\n", authorization));
+        }
+        head.push_str("\r\n");
+        stream.write_all(head.as_bytes())?;
+        stream.write_all(body)?;
+
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response)?;
+        let body_start = response
+            .windows(4)
+            .position(|w| w == b"\r\n\r\n")
+            .ok_or(StorageError::BadResponse)?;
+        let head = String::from_utf8_lossy(&response[..body_start]);
+        let status_line = head.lines().next().unwrap_or_default();
+        let mut parts = status_line.splitn(3, ' ');
+        if !parts.next().unwrap_or_default().starts_with("HTTP/") {
+            return Err(StorageError::BadResponse);
+        }
+        let status: u16 = parts
+            .next()
+            .and_then(|s| s.parse().ok())
+            .ok_or(StorageError::BadResponse)?;
+        if !(200..300).contains(&status) {
+            return Err(StorageError::Http {
+                status,
+                reason: parts.next().unwrap_or_default().to_owned(),
+            });
+        }
+        Ok(response.split_off(body_start + 4))
+    }
+}
+
+impl Storage for WebDavStorage {
+    fn read(&self, name: &str) -> Result<Vec<u8>, StorageError> {
+        self.request("GET", name, &[])
+    }
+
+    fn write(&self, name: &str, bytes: &[u8]) -> Result<(), StorageError> {
+        // PUT doesn't create missing collections. Ones that are already there
+        // answer MKCOL with an error, which is fine.
+        let name = name.trim_start_matches('/');
+        for (i, _) in name.match_indices('/') {
+            match self.request("MKCOL", &name[..=i], &[]) {
+                Ok(_) | Err(StorageError::Http { .. }) => {}
+                Err(error) => return Err(error),
+            }
+        }
+        self.request("PUT", name, bytes)?;
+        Ok(())
+    }
+}
+
+/// Percent-encodes everything but unreserved characters and slashes
+fn encode_path(name: &str) -> String {
+    let mut encoded = String::with_capacity(name.len());
+    for byte in name.trim_start_matches('/').bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity((bytes.len() + 2) / 3 * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let triple = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(triple >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
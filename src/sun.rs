@@ -0,0 +1,184 @@
+//! The sun as seen from the camera: a disk drawn by the skybox in the light's
+//! direction, and a chain of flare sprites that fades out as the sun gets hidden.
+//! How much of the sun is visible comes from an occlusion query against the depth
+//! buffer, read a frame later so the CPU never waits for it.
+
+use glam::{Mat3, Mat4, Vec2};
+
+use crate::daylight::Sunlight;
+use crate::opengl::objects::{Query, VertexArray};
+use crate::opengl::shader::Program;
+use crate::Result;
+
+/// Side of the square tested for visibility, in pixels
+const TEST_PIXELS: f32 = 16.0;
+
+/// Flare sprites, has to match the arrays in flare.vert
+const SPRITES: i32 = 6;
+
+pub struct SunSettings {
+    pub disk: bool,
+    /// Degrees across
+    pub disk_size: f32,
+    pub lens_flare: bool,
+    pub flare_intensity: f32,
+    /// Contact-hardening shadows, softer the further they're cast
+    pub soft_shadows: bool,
+    /// Degrees across as far as shadows are concerned, the real sun is about half a degree
+    pub light_size: f32,
+}
+
+impl Default for SunSettings {
+    fn default() -> Self {
+        SunSettings {
+            disk: true,
+            disk_size: 1.5,
+            lens_flare: true,
+            flare_intensity: 0.6,
+            soft_shadows: false,
+            light_size: 0.5,
+        }
+    }
+}
+
+impl SunSettings {
+    /// What the shadows take the sun's size to be
+    pub fn shadow_size(&self) -> f32 {
+        if self.soft_shadows {
+            self.light_size.to_radians()
+        } else {
+            0.0
+        }
+    }
+
+    /// Angular radius the skybox draws the disk at, 0 for none
+    pub fn disk_radius(&self) -> f32 {
+        if self.disk {
+            self.disk_size.to_radians() / 2.0
+        } else {
+            0.0
+        }
+    }
+}
+
+pub struct LensFlare {
+    shader: Program,
+    occlusion_shader: Program,
+    // Nothing in it, the sprites are made up from the vertex id
+    vao: VertexArray,
+    query: Query,
+    query_pending: bool,
+    /// Fraction of the test square that was sky last time it was read
+    visibility: f32,
+}
+
+impl LensFlare {
+    pub fn new() -> Result<Self> {
+        let shader = Program::new()
+            .vertex_shader(include_str!("shaders/sun/flare.vert"))?
+            .fragment_shader(include_str!("shaders/sun/flare.frag"))?
+            .link()?;
+        let occlusion_shader = Program::new()
+            .define("OCCLUSION", "1")
+            .vertex_shader(include_str!("shaders/sun/flare.vert"))?
+            .fragment_shader(include_str!("shaders/sun/flare.frag"))?
+            .link()?;
+        Ok(LensFlare {
+            shader,
+            occlusion_shader,
+            vao: VertexArray::new("Lens flare"),
+            query: Query::new(gl::SAMPLES_PASSED, "Sun visibility"),
+            query_pending: false,
+            visibility: 0.0,
+        })
+    }
+
+    /// Draws over the finished scene of one view. `view_size` is in pixels.
+    pub fn draw(
+        &mut self,
+        settings: &SunSettings,
+        sun: &Sunlight,
+        view: &Mat4,
+        proj: &Mat4,
+        view_size: Vec2,
+    ) -> Result<()> {
+        if !settings.lens_flare {
+            return Ok(());
+        }
+        // Only the rotation matters for something infinitely far away
+        let rotation = Mat4::from_mat3(Mat3::from_mat4(*view));
+        let clip = *proj * rotation * sun.direction.extend(1.0);
+        if clip.w <= 0.0 {
+            return Ok(()); // behind the camera
+        }
+        let sun_ndc = clip.truncate().truncate() / clip.w;
+        self.read_visibility();
+
+        unsafe {
+            gl::BindVertexArray(self.vao.id());
+            gl::Disable(gl::CULL_FACE);
+        }
+
+        // Test for next time
+        if !self.query_pending {
+            let test_size = Vec2::splat(TEST_PIXELS) / view_size;
+            self.occlusion_shader.set_used();
+            self.occlusion_shader.set_vec2("sun_ndc", &sun_ndc)?;
+            self.occlusion_shader.set_vec2("test_size", &test_size)?;
+            unsafe {
+                gl::ColorMask(gl::FALSE, gl::FALSE, gl::FALSE, gl::FALSE);
+                gl::DepthMask(gl::FALSE);
+                gl::DepthFunc(gl::LEQUAL);
+                gl::BeginQuery(gl::SAMPLES_PASSED, self.query.id());
+                gl::DrawArrays(gl::TRIANGLE_STRIP, 0, 4);
+                gl::EndQuery(gl::SAMPLES_PASSED);
+                gl::DepthFunc(gl::LESS);
+                gl::DepthMask(gl::TRUE);
+                gl::ColorMask(gl::TRUE, gl::TRUE, gl::TRUE, gl::TRUE);
+            }
+            self.query_pending = true;
+        }
+
+        // Sprites fade out towards the screen edges and as the sun sets
+        let edge_fade = (1.0 - sun_ndc.abs().max_element()).clamp(0.0, 1.0);
+        let brightness = sun.color.max_element();
+        let intensity = settings.flare_intensity * self.visibility * edge_fade * brightness;
+        if intensity > 0.0 {
+            self.shader.set_used();
+            self.shader.set_vec2("sun_ndc", &sun_ndc)?;
+            self.shader.set_f32("aspect", view_size.x / view_size.y)?;
+            self.shader.set_f32("intensity", intensity)?;
+            unsafe {
+                gl::Disable(gl::DEPTH_TEST);
+                gl::Enable(gl::BLEND);
+                gl::BlendFunc(gl::ONE, gl::ONE);
+                gl::DrawArraysInstanced(gl::TRIANGLE_STRIP, 0, 4, SPRITES);
+                gl::Disable(gl::BLEND);
+                gl::Enable(gl::DEPTH_TEST);
+            }
+        }
+        unsafe {
+            gl::Enable(gl::CULL_FACE);
+        }
+        Ok(())
+    }
+
+    /// Picks up the query result if the GPU has it ready
+    fn read_visibility(&mut self) {
+        if !self.query_pending {
+            return;
+        }
+        let mut available: i32 = 0;
+        unsafe {
+            gl::GetQueryObjectiv(self.query.id(), gl::QUERY_RESULT_AVAILABLE, &mut available);
+        }
+        if available != 0 {
+            let mut samples: u32 = 0;
+            unsafe {
+                gl::GetQueryObjectuiv(self.query.id(), gl::QUERY_RESULT, &mut samples);
+            }
+            self.visibility = (samples as f32 / (TEST_PIXELS * TEST_PIXELS)).min(1.0);
+            self.query_pending = false;
+        }
+    }
+}
@@ -0,0 +1,665 @@
+//! Experimental shared editing. One editor hosts, the others join it,
+//! and brush strokes and object moves are sent around as operations.
+//!
+//! An operation carries the texels a stroke produced rather than the stroke itself,
+//! so that everyone ends up with the same terrain whatever order things arrive in.
+//! The maps are split into cells, and every cell keeps the newest write it has seen
+//! (last writer wins, ordered by Lamport timestamps). The host keeps the stamps of
+//! everything edited during the session and sends those cells to late joiners.
+//!
+//! Peers have to start from the same project, e.g. one unpacked from the same archive.
+//! Filters, erosion and anything else that replaces whole maps is not shared.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, sync_channel, Receiver, Sender, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use glam::Mat4;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::heightfield::DirtyRect;
+use crate::splat::NUM_LAYERS;
+use crate::terrain::{Terrain, TerrainParams};
+
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:7878";
+
+/// Texels per side of a last-writer-wins cell
+const CELL_SIZE: usize = 16;
+
+const JOIN_TIMEOUT: Duration = Duration::from_secs(5);
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(100);
+/// Messages a peer can fall behind by before it's dropped
+const SEND_QUEUE: usize = 1024;
+
+#[derive(Debug, Error)]
+pub enum SyncError {
+    #[error("Network error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Bad message: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Couldn't resolve {0}")]
+    BadAddress(String),
+    #[error("The host didn't say hello")]
+    NotWelcomed,
+    #[error("The host is editing a different terrain")]
+    TerrainMismatch,
+    #[error("The terrain was replaced")]
+    TerrainReplaced,
+    #[error("Lost connection to the host")]
+    HostLeft,
+}
+
+/// Which map a brush edit went to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SyncedMap {
+    Heights,
+    Splat,
+}
+
+/// Lamport time, ties are broken by the peer id so that everyone picks the same winner
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+struct Stamp {
+    time: u64,
+    peer: u32,
+}
+
+/// Texels in [x0, x1) x [y0, y1)
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct Region {
+    x0: usize,
+    y0: usize,
+    x1: usize,
+    y1: usize,
+}
+
+impl From<Region> for DirtyRect {
+    fn from(r: Region) -> Self {
+        DirtyRect {
+            x0: r.x0,
+            y0: r.y0,
+            x1: r.x1,
+            y1: r.y1,
+        }
+    }
+}
+
+impl From<DirtyRect> for Region {
+    fn from(r: DirtyRect) -> Self {
+        Region {
+            x0: r.x0,
+            y0: r.y0,
+            x1: r.x1,
+            y1: r.y1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum Edit {
+    Heights {
+        region: Region,
+        pixels: Vec<u16>,
+    },
+    Splat {
+        region: Region,
+        weights: Vec<[f32; NUM_LAYERS]>,
+    },
+    Object {
+        index: usize,
+        transform: Mat4,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Operation {
+    stamp: Stamp,
+    edit: Edit,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum Message {
+    /// First thing the host sends, with everything edited so far
+    Welcome {
+        peer: u32,
+        terrain: TerrainParams,
+        resolution: usize,
+        state: Vec<Operation>,
+    },
+    Operation(Operation),
+}
+
+/// What the network threads tell the main thread
+enum NetEvent {
+    Connected { peer: u32, stream: TcpStream },
+    Received { peer: u32, message: Message },
+    Closed { peer: u32 },
+}
+
+enum Role {
+    Host {
+        address: SocketAddr,
+        peers: Vec<Peer>,
+        stop_accepting: Arc<AtomicBool>,
+    },
+    Client {
+        host: Peer,
+    },
+}
+
+/// A connection written to from its own thread, so that one which stops reading
+/// only holds up itself rather than the editor
+struct Peer {
+    id: u32,
+    /// To shut the connection down, the writer thread has its own handle
+    stream: TcpStream,
+    lines: SyncSender<Arc<[u8]>>,
+}
+
+impl Peer {
+    fn new(id: u32, stream: TcpStream) -> io::Result<Self> {
+        let (lines, queue) = sync_channel::<Arc<[u8]>>(SEND_QUEUE);
+        let writer = stream.try_clone()?;
+        thread::Builder::new()
+            .name(format!("sync send {}", id))
+            .spawn(move || {
+                for line in queue {
+                    if let Err(error) = (&writer).write_all(&line) {
+                        eprintln!("Couldn't write to peer {}: {}", id, error);
+                        // Stops the reader as well, which reports the peer gone
+                        writer.shutdown(Shutdown::Both).ok();
+                        return;
+                    }
+                }
+            })?;
+        Ok(Peer { id, stream, lines })
+    }
+
+    /// Queues a line without waiting for it to be written
+    fn send(&self, line: &Arc<[u8]>) -> Result<(), &'static str> {
+        match self.lines.try_send(Arc::clone(line)) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => Err("too far behind"),
+            Err(TrySendError::Disconnected(_)) => Err("connection lost"),
+        }
+    }
+}
+
+impl Drop for Peer {
+    /// Closing the socket is what stops the reader thread
+    fn drop(&mut self) {
+        self.stream.shutdown(Shutdown::Both).ok();
+    }
+}
+
+pub struct SyncSession {
+    role: Role,
+    /// 0 is the host
+    peer: u32,
+    clock: u64,
+    events: Receiver<NetEvent>,
+
+    terrain: TerrainParams,
+    resolution: usize,
+    cells_per_side: usize,
+    height_stamps: Vec<Option<Stamp>>,
+    splat_stamps: Vec<Option<Stamp>>,
+    objects: HashMap<usize, (Stamp, Mat4)>,
+}
+
+impl SyncSession {
+    pub fn host(address: &str, terrain: &Terrain) -> Result<Self, SyncError> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        let address = listener.local_addr()?;
+        let stop_accepting = Arc::new(AtomicBool::new(false));
+        let (sender, events) = channel();
+
+        let stop = Arc::clone(&stop_accepting);
+        thread::Builder::new()
+            .name("sync accept".to_owned())
+            .spawn(move || accept_peers(listener, sender, stop))?;
+
+        let role = Role::Host {
+            address,
+            peers: vec![],
+            stop_accepting,
+        };
+        Ok(SyncSession::new(role, 0, events, terrain))
+    }
+
+    /// Blocks until the host has sent everything edited so far, then applies it
+    pub fn join(address: &str, terrain: &mut Terrain) -> Result<Self, SyncError> {
+        let address = address
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| SyncError::BadAddress(address.to_owned()))?;
+        let stream = TcpStream::connect_timeout(&address, JOIN_TIMEOUT)?;
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(JOIN_TIMEOUT))?;
+
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let (peer, state) = match serde_json::from_str(&line)? {
+            Message::Welcome {
+                peer,
+                terrain: params,
+                resolution,
+                state,
+            } => {
+                if params != terrain.params() || resolution != terrain.resolution() {
+                    return Err(SyncError::TerrainMismatch);
+                }
+                (peer, state)
+            }
+            Message::Operation(_) => return Err(SyncError::NotWelcomed),
+        };
+        stream.set_read_timeout(None)?;
+
+        let (sender, events) = channel();
+        spawn_reader(0, reader, sender)?;
+
+        let host = Peer::new(0, stream)?;
+        let mut session = SyncSession::new(Role::Client { host }, peer, events, terrain);
+        for operation in state {
+            session.merge(&operation, terrain);
+        }
+        Ok(session)
+    }
+
+    fn new(role: Role, peer: u32, events: Receiver<NetEvent>, terrain: &Terrain) -> Self {
+        let resolution = terrain.resolution();
+        let cells_per_side = resolution.div_ceil(CELL_SIZE);
+        SyncSession {
+            role,
+            peer,
+            clock: 0,
+            events,
+            terrain: terrain.params(),
+            resolution,
+            cells_per_side,
+            height_stamps: vec![None; cells_per_side * cells_per_side],
+            splat_stamps: vec![None; cells_per_side * cells_per_side],
+            objects: HashMap::new(),
+        }
+    }
+
+    pub fn status(&self) -> String {
+        match &self.role {
+            Role::Host { address, peers, .. } => {
+                format!("Hosting on {}, {} peer(s)", address, peers.len())
+            }
+            Role::Client { .. } => format!("Joined as peer {}", self.peer),
+        }
+    }
+
+    /// Sends what a brush did to `rect` this frame
+    pub fn publish(
+        &mut self,
+        terrain: &Terrain,
+        map: SyncedMap,
+        rect: &DirtyRect,
+    ) -> Result<(), SyncError> {
+        self.check_terrain(terrain)?;
+        if rect.is_empty() {
+            return Ok(());
+        }
+        // Whole cells, so that a write always replaces everything a cell holds
+        let (cx0, cy0, cx1, cy1) = self.cells_in(rect);
+        let rect = DirtyRect {
+            x0: cx0 * CELL_SIZE,
+            y0: cy0 * CELL_SIZE,
+            x1: (cx1 * CELL_SIZE).min(self.resolution),
+            y1: (cy1 * CELL_SIZE).min(self.resolution),
+        };
+
+        let stamp = self.tick();
+        let stamps = match map {
+            SyncedMap::Heights => &mut self.height_stamps,
+            SyncedMap::Splat => &mut self.splat_stamps,
+        };
+        for cy in cy0..cy1 {
+            for cx in cx0..cx1 {
+                stamps[cy * self.cells_per_side + cx] = Some(stamp);
+            }
+        }
+
+        let region = Region::from(rect);
+        let edit = match map {
+            SyncedMap::Heights => Edit::Heights {
+                region,
+                pixels: terrain.read_heights(&rect),
+            },
+            SyncedMap::Splat => Edit::Splat {
+                region,
+                weights: terrain.read_splat(&rect),
+            },
+        };
+        self.send(&Message::Operation(Operation { stamp, edit }), None)
+    }
+
+    pub fn publish_object(&mut self, index: usize, transform: Mat4) -> Result<(), SyncError> {
+        let stamp = self.tick();
+        self.objects.insert(index, (stamp, transform));
+        let edit = Edit::Object { index, transform };
+        self.send(&Message::Operation(Operation { stamp, edit }), None)
+    }
+
+    /// Merges everything that arrived since the last call.
+    /// Returns the objects that someone else has moved.
+    pub fn update(&mut self, terrain: &mut Terrain) -> Result<Vec<(usize, Mat4)>, SyncError> {
+        self.check_terrain(terrain)?;
+        let mut moved = vec![];
+        while let Ok(event) = self.events.try_recv() {
+            match event {
+                NetEvent::Connected { peer, stream } => {
+                    let welcome = encode(&self.welcome(peer, terrain))?;
+                    let welcomed = Peer::new(peer, stream)
+                        .map_err(|error| error.to_string())
+                        .and_then(|joined| match joined.send(&welcome) {
+                            Ok(()) => Ok(joined),
+                            Err(error) => Err(error.to_owned()),
+                        });
+                    match welcomed {
+                        Ok(joined) => {
+                            if let Role::Host { peers, .. } = &mut self.role {
+                                peers.push(joined);
+                            }
+                        }
+                        Err(error) => eprintln!("Couldn't welcome peer {}: {}", peer, error),
+                    }
+                }
+                NetEvent::Received {
+                    peer,
+                    message: Message::Operation(operation),
+                } => {
+                    if let Some(object) = self.merge(&operation, terrain) {
+                        moved.push(object);
+                    }
+                    // Peers only talk to the host, it passes everything on
+                    if let Role::Host { .. } = self.role {
+                        self.send(&Message::Operation(operation), Some(peer))?;
+                    }
+                }
+                NetEvent::Received { peer, .. } => {
+                    eprintln!("Peer {} sent an unexpected welcome", peer);
+                }
+                NetEvent::Closed { peer } => match &mut self.role {
+                    Role::Host { peers, .. } => peers.retain(|p| p.id != peer),
+                    Role::Client { .. } => return Err(SyncError::HostLeft),
+                },
+            }
+        }
+        Ok(moved)
+    }
+
+    /// Applies the parts of a remote operation that are newer than what's here
+    fn merge(&mut self, operation: &Operation, terrain: &mut Terrain) -> Option<(usize, Mat4)> {
+        let stamp = operation.stamp;
+        self.clock = self.clock.max(stamp.time);
+
+        match &operation.edit {
+            Edit::Heights { region, pixels } => {
+                let region = DirtyRect::from(*region);
+                for cell in self.accept_cells(SyncedMap::Heights, &region, pixels.len(), stamp) {
+                    terrain.write_heights(&cell, &sub_region(pixels, &region, &cell));
+                }
+                None
+            }
+            Edit::Splat { region, weights } => {
+                let region = DirtyRect::from(*region);
+                for cell in self.accept_cells(SyncedMap::Splat, &region, weights.len(), stamp) {
+                    terrain.write_splat(&cell, &sub_region(weights, &region, &cell));
+                }
+                None
+            }
+            Edit::Object { index, transform } => {
+                let newer = match self.objects.get(index) {
+                    Some((current, _)) => stamp > *current,
+                    None => true,
+                };
+                if newer {
+                    self.objects.insert(*index, (stamp, *transform));
+                    Some((*index, *transform))
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    /// Restamps and returns the cells of `region` that `stamp` wins.
+    /// Nothing is accepted from a region that doesn't fit this terrain.
+    fn accept_cells(
+        &mut self,
+        map: SyncedMap,
+        region: &DirtyRect,
+        texels: usize,
+        stamp: Stamp,
+    ) -> Vec<DirtyRect> {
+        let resolution = self.resolution;
+        let aligned = |texel: usize| texel.is_multiple_of(CELL_SIZE) || texel == resolution;
+        let fits = region.x1 <= resolution
+            && region.y1 <= resolution
+            && region.x0.is_multiple_of(CELL_SIZE)
+            && region.y0.is_multiple_of(CELL_SIZE)
+            && aligned(region.x1)
+            && aligned(region.y1)
+            && texels == region.width() * region.height();
+        if !fits {
+            eprintln!(
+                "Ignoring an edit that doesn't fit the terrain: {:?}",
+                region
+            );
+            return vec![];
+        }
+
+        let (cx0, cy0, cx1, cy1) = self.cells_in(region);
+        let per_side = self.cells_per_side;
+        let stamps = match map {
+            SyncedMap::Heights => &mut self.height_stamps,
+            SyncedMap::Splat => &mut self.splat_stamps,
+        };
+        let mut cells = vec![];
+        for cy in cy0..cy1 {
+            for cx in cx0..cx1 {
+                let current = &mut stamps[cy * per_side + cx];
+                if current.is_none_or(|current| stamp > current) {
+                    *current = Some(stamp);
+                    cells.push(cell_rect(cx, cy, resolution));
+                }
+            }
+        }
+        cells
+    }
+
+    /// Everything stamped so far, as it is now
+    fn welcome(&self, peer: u32, terrain: &Terrain) -> Message {
+        let mut state = vec![];
+        for (cell, stamp) in self.height_stamps.iter().enumerate() {
+            if let Some(stamp) = *stamp {
+                let rect = self.cell(cell);
+                let pixels = terrain.read_heights(&rect);
+                let region = Region::from(rect);
+                let edit = Edit::Heights { region, pixels };
+                state.push(Operation { stamp, edit });
+            }
+        }
+        for (cell, stamp) in self.splat_stamps.iter().enumerate() {
+            if let Some(stamp) = *stamp {
+                let rect = self.cell(cell);
+                let weights = terrain.read_splat(&rect);
+                let region = Region::from(rect);
+                let edit = Edit::Splat { region, weights };
+                state.push(Operation { stamp, edit });
+            }
+        }
+        for (&index, &(stamp, transform)) in &self.objects {
+            let edit = Edit::Object { index, transform };
+            state.push(Operation { stamp, edit });
+        }
+        Message::Welcome {
+            peer,
+            terrain: self.terrain,
+            resolution: self.resolution,
+            state,
+        }
+    }
+
+    /// To every peer but `except`. The host gets it when this is a client.
+    /// Never waits on the network, a peer that can't keep up is dropped.
+    fn send(&mut self, message: &Message, except: Option<u32>) -> Result<(), SyncError> {
+        let line = encode(message)?;
+        match &mut self.role {
+            Role::Host { peers, .. } => {
+                peers.retain(|peer| {
+                    if Some(peer.id) == except {
+                        return true;
+                    }
+                    match peer.send(&line) {
+                        Ok(()) => true,
+                        Err(error) => {
+                            eprintln!("Dropping peer {}: {}", peer.id, error);
+                            false
+                        }
+                    }
+                });
+                Ok(())
+            }
+            Role::Client { host } => host.send(&line).map_err(|_| SyncError::HostLeft),
+        }
+    }
+
+    fn tick(&mut self) -> Stamp {
+        self.clock += 1;
+        Stamp {
+            time: self.clock,
+            peer: self.peer,
+        }
+    }
+
+    /// Sessions don't survive resampling, cropping or loading another project
+    fn check_terrain(&self, terrain: &Terrain) -> Result<(), SyncError> {
+        if terrain.params() != self.terrain || terrain.resolution() != self.resolution {
+            Err(SyncError::TerrainReplaced)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Cells [cx0, cx1) x [cy0, cy1) overlapping `rect`
+    fn cells_in(&self, rect: &DirtyRect) -> (usize, usize, usize, usize) {
+        let last = |texel: usize| texel.div_ceil(CELL_SIZE).min(self.cells_per_side);
+        (
+            rect.x0 / CELL_SIZE,
+            rect.y0 / CELL_SIZE,
+            last(rect.x1),
+            last(rect.y1),
+        )
+    }
+
+    fn cell(&self, index: usize) -> DirtyRect {
+        let (cx, cy) = (index % self.cells_per_side, index / self.cells_per_side);
+        cell_rect(cx, cy, self.resolution)
+    }
+}
+
+impl Drop for SyncSession {
+    /// The peers close their own connections
+    fn drop(&mut self) {
+        if let Role::Host { stop_accepting, .. } = &self.role {
+            stop_accepting.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+fn accept_peers(listener: TcpListener, events: Sender<NetEvent>, stop: Arc<AtomicBool>) {
+    let mut next_peer = 1;
+    while !stop.load(Ordering::Relaxed) {
+        let stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(error) if error.kind() == io::ErrorKind::WouldBlock => {
+                thread::sleep(ACCEPT_INTERVAL);
+                continue;
+            }
+            Err(error) => {
+                eprintln!("Stopped accepting peers: {}", error);
+                return;
+            }
+        };
+        let peer = next_peer;
+        next_peer += 1;
+
+        let started = stream
+            .set_nonblocking(false)
+            .and_then(|()| stream.set_nodelay(true))
+            .and_then(|()| stream.try_clone())
+            .and_then(|reader| spawn_reader(peer, BufReader::new(reader), events.clone()));
+        if let Err(error) = started {
+            eprintln!("Couldn't accept peer {}: {}", peer, error);
+            continue;
+        }
+        if events.send(NetEvent::Connected { peer, stream }).is_err() {
+            return; // the session is gone
+        }
+    }
+}
+
+fn spawn_reader(
+    peer: u32,
+    reader: BufReader<TcpStream>,
+    events: Sender<NetEvent>,
+) -> io::Result<()> {
+    thread::Builder::new()
+        .name(format!("sync peer {}", peer))
+        .spawn(move || {
+            for line in reader.lines() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(_) => break,
+                };
+                match serde_json::from_str(&line) {
+                    Ok(message) => {
+                        if events.send(NetEvent::Received { peer, message }).is_err() {
+                            return;
+                        }
+                    }
+                    Err(error) => eprintln!("Bad message from peer {}: {}", peer, error),
+                }
+            }
+            events.send(NetEvent::Closed { peer }).ok();
+        })?;
+    Ok(())
+}
+
+/// One message per line, shared by every peer it's sent to
+fn encode(message: &Message) -> Result<Arc<[u8]>, SyncError> {
+    let mut line = serde_json::to_vec(message)?;
+    line.push(b'\n');
+    Ok(line.into())
+}
+
+fn cell_rect(cx: usize, cy: usize, resolution: usize) -> DirtyRect {
+    DirtyRect {
+        x0: cx * CELL_SIZE,
+        y0: cy * CELL_SIZE,
+        x1: ((cx + 1) * CELL_SIZE).min(resolution),
+        y1: ((cy + 1) * CELL_SIZE).min(resolution),
+    }
+}
+
+/// Texels of `rect` out of row-major `data` covering `region`
+fn sub_region<T: Copy>(data: &[T], region: &DirtyRect, rect: &DirtyRect) -> Vec<T> {
+    let mut texels = Vec::with_capacity(rect.width() * rect.height());
+    for y in rect.y0..rect.y1 {
+        let start = (y - region.y0) * region.width() + rect.x0 - region.x0;
+        texels.extend_from_slice(&data[start..start + rect.width()]);
+    }
+    texels
+}
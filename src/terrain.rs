@@ -1,67 +1,97 @@
+use std::borrow::Cow;
+use std::collections::VecDeque;
 use std::ffi::c_void;
+use std::mem;
+use std::time::Duration;
 
-use gl::types::*;
 use glam::Vec3Swizzles;
-use glam::{Vec2, Vec3};
+use glam::{Mat4, Vec2, Vec3};
 use image::GenericImageView;
-
-use crate::texture::{calculate_mip_levels, get_max_anisotropy, unit_to_gl_const};
+use serde::{Deserialize, Serialize};
+
+use crate::basis;
+use crate::biome::{BiomeSettings, MAX_BIOMES};
+use crate::buildable::{self, BuildableAreas, BuildableSettings};
+use crate::camera::Camera;
+use crate::compare::{CompareSettings, Reference};
+use crate::daylight::Sunlight;
+use crate::derived_cache::{self, ContentHash};
+use crate::detail::DetailLayer;
+use crate::detail_map::{self, DetailMap};
+use crate::drape::Drape;
+use crate::erosion::{droplet_erosion, thermal_erosion, DropletSettings, ErosionSettings};
+use crate::export::{ambient_occlusion_gray, hypsometric_rgb, normal_map_rgb};
+use crate::filters::HeightFilter;
+use crate::flood::{self, FloodSettings};
+use crate::gradient::ColorGradient;
+use crate::ground_texture::{self, GroundImage};
+use crate::heightfield::{DirtyRect, EdgeFill, EdgeMode, HeightBounds, HeightField};
+use crate::horizon_map::{self, HorizonMap, HorizonSettings};
+use crate::hypsometric::HypsometricSettings;
+use crate::insolation;
+use crate::noise::{hash, smoothstep};
+use crate::opengl::caps;
+use crate::overlay::HeatOverlay;
+use crate::path::{self, PathSettings, WalkPath};
+use crate::readback::Readback;
+use crate::resources::{self, texture_bytes, MemoryHandle, ResourceKind};
+use crate::scatter::{self, Placement, SlopeScatter, Surface};
+use crate::season::SeasonSettings;
+use crate::sky_light::SkyLight;
+use crate::splat::{default_layers, PaintSettings, SplatMap, TerrainLayer, NUM_LAYERS};
+use crate::texture::{calculate_mip_levels, unit_to_gl_const};
+use crate::tint::{TintMap, TintSettings};
+use crate::undo::StrokeHistory;
+use crate::viewshed::{self, ViewshedSettings};
+use crate::virtual_texture::{self, PagedMap, VirtualPages};
+use crate::weathering::{weathering_tint, WeatheringSettings};
 use crate::{
+    opengl::objects::{Framebuffer, Texture, VertexArray},
     opengl::shader::Program,
+    opengl::uniform::UniformBlock,
     ray::{Ray, AABB},
-    utils::vec2_infinity,
+    utils::{brush_uv, quad_height, sample_bilinear, texel_uv, vec2_infinity},
     Result,
 };
-use crate::{WINDOW_HEIGHT, WINDOW_WIDTH};
+use crate::{CameraTransforms, WINDOW_HEIGHT, WINDOW_WIDTH};
 
 struct Heightmap {
-    texture: GLuint,
+    texture: Texture,
     texture_size: usize,
+    _memory: MemoryHandle,
 
     // For drawing on heightmap
-    fbo: GLuint,
+    fbo: Framebuffer,
     shader: Program,
 }
 
 impl Heightmap {
     pub fn flat(texture_size: usize) -> Result<Self> {
-        Heightmap::new(None, Some(texture_size))
+        Heightmap::from_pixels(&vec![0u16; texture_size * texture_size], texture_size)
     }
 
     pub fn from_image(path: &str) -> Result<Self> {
-        Heightmap::new(Some(path), None)
+        let img = image::open(path)?;
+        let (width, height) = img.dimensions();
+        assert_eq!(width, height, "Only square heightmaps are supported");
+        assert!(
+            width.is_power_of_two() && (MIN_RESOLUTION..=MAX_RESOLUTION).contains(&width),
+            "Only power of two heightmaps from {} to {} are supported",
+            MIN_RESOLUTION,
+            MAX_RESOLUTION
+        );
+
+        Heightmap::from_pixels(&img.into_luma16().into_raw(), width as usize)
     }
 
-    fn new(path: Option<&str>, texture_size: Option<usize>) -> Result<Self> {
-        debug_assert!(!(path.is_some() && texture_size.is_some()));
-        debug_assert!(!(path.is_none() && texture_size.is_none()));
-
-        let (pixels, texture_size) = if let Some(path) = path {
-            let img = image::open(path)?;
-            let (width, height) = img.dimensions();
-            assert_eq!(width, height, "Only square heightmaps are supported");
-            assert!(
-                width == 1024 || width == 2048 || width == 4096,
-                "Only heightmaps with sizes 1024, 2048 and 4096 are supported"
-            );
-
-            (img.into_luma16().into_raw(), width as usize)
-        } else {
-            let size = texture_size.unwrap();
-
-            (vec![0u16; size * size], size)
-        };
+    fn from_pixels(pixels: &[u16], texture_size: usize) -> Result<Self> {
+        debug_assert_eq!(pixels.len(), texture_size * texture_size);
 
-        let mut texture: GLuint = 0;
+        let texture = Texture::new(gl::TEXTURE_2D, "Heightmap");
+        texture.set_wrap_and_filter(gl::CLAMP_TO_EDGE, gl::LINEAR, gl::LINEAR);
         unsafe {
-            gl::CreateTextures(gl::TEXTURE_2D, 1, &mut texture);
-            gl::BindTexture(gl::TEXTURE_2D, texture);
-            gl::TextureParameteri(texture, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
-            gl::TextureParameteri(texture, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
-            gl::TextureParameteri(texture, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
-            gl::TextureParameteri(texture, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
             gl::TextureStorage2D(
-                texture,
+                texture.id(),
                 1,
                 gl::R16,
                 texture_size as i32,
@@ -69,7 +99,7 @@ impl Heightmap {
             );
 
             gl::TextureSubImage2D(
-                texture,
+                texture.id(),
                 0,
                 0,
                 0,
@@ -82,14 +112,13 @@ impl Heightmap {
         }
 
         // Framebuffer object for rendering to heightmap
-        let mut fbo: GLuint = 0;
+        let fbo = Framebuffer::new("Heightmap");
+        fbo.attach_texture(gl::COLOR_ATTACHMENT0, &texture);
         unsafe {
-            gl::CreateFramebuffers(1, &mut fbo);
-            gl::NamedFramebufferTexture(fbo, gl::COLOR_ATTACHMENT0, texture, 0);
             let draw_buffers = [gl::COLOR_ATTACHMENT0];
-            gl::NamedFramebufferDrawBuffers(fbo, 1, draw_buffers.as_ptr() as *const _);
+            gl::NamedFramebufferDrawBuffers(fbo.id(), 1, draw_buffers.as_ptr() as *const _);
             assert_eq!(
-                gl::CheckNamedFramebufferStatus(fbo, gl::FRAMEBUFFER),
+                gl::CheckNamedFramebufferStatus(fbo.id(), gl::FRAMEBUFFER),
                 gl::FRAMEBUFFER_COMPLETE,
                 "Heightmap texture framebuffer is incomplete",
             );
@@ -103,35 +132,93 @@ impl Heightmap {
         Ok(Heightmap {
             texture,
             texture_size,
+            _memory: resources::track(
+                ResourceKind::Heightmap,
+                texture_bytes(gl::R16, texture_size, texture_size, 1),
+            ),
 
             fbo,
             shader,
         })
     }
 
-    fn draw_on_heightmap(
-        &self,
-        cursor: Vec2,
-        brush: &Brush,
-        terrain_size: f32,
-        delta_time: f32,
-        raise: bool,
-    ) {
+    fn read_pixels(&self) -> Vec<u16> {
+        let mut pixels = vec![0u16; self.texture_size * self.texture_size];
+        unsafe {
+            gl::GetTextureImage(
+                self.texture.id(),
+                0,
+                gl::RED,
+                gl::UNSIGNED_SHORT,
+                (pixels.len() * 2) as i32,
+                pixels.as_mut_ptr() as *mut c_void,
+            );
+        }
+        pixels
+    }
+
+    fn read_region(&self, rect: &DirtyRect) -> Vec<u16> {
+        let mut pixels = vec![0u16; rect.width() * rect.height()];
+        unsafe {
+            gl::GetTextureSubImage(
+                self.texture.id(),
+                0,
+                rect.x0 as i32,
+                rect.y0 as i32,
+                0,
+                rect.width() as i32,
+                rect.height() as i32,
+                1,
+                gl::RED,
+                gl::UNSIGNED_SHORT,
+                (pixels.len() * 2) as i32,
+                pixels.as_mut_ptr() as *mut c_void,
+            );
+        }
+        pixels
+    }
+
+    fn write_region(&self, rect: &DirtyRect, pixels: &[u16]) {
+        debug_assert_eq!(pixels.len(), rect.width() * rect.height());
+        unsafe {
+            gl::TextureSubImage2D(
+                self.texture.id(),
+                0,
+                rect.x0 as i32,
+                rect.y0 as i32,
+                rect.width() as i32,
+                rect.height() as i32,
+                gl::RED,
+                gl::UNSIGNED_SHORT,
+                pixels.as_ptr() as *const _,
+            );
+        }
+    }
+
+    fn draw_on_heightmap(&self, stroke: &Stroke, brush: &Brush, raise: bool) {
         self.shader.set_used();
-        debug_assert!(cursor.x <= 1.0 && cursor.x >= 0.0);
-        debug_assert!(cursor.y <= 1.0 && cursor.y >= 0.0);
+        let Stroke {
+            cursor,
+            brush_size,
+            delta_time,
+            ..
+        } = *stroke;
+        // Past an edge for the copies of a brush that wraps around
+        debug_assert!(cursor.x <= 2.0 && cursor.x >= -1.0);
+        debug_assert!(cursor.y <= 2.0 && cursor.y >= -1.0);
         self.shader.set_vec2("cursor", &cursor).unwrap();
-        let brush_size = brush.size as f32 / terrain_size;
         self.shader.set_f32("brush_size", brush_size).unwrap();
         self.shader.set_f32("delta_time", delta_time).unwrap();
+        let texel_size = 1.0 / self.texture_size as f32;
+        self.shader.set_f32("texel_size", texel_size).unwrap();
 
         unsafe {
-            gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbo);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbo.id());
             gl::Disable(gl::FRAMEBUFFER_SRGB);
             gl::Viewport(0, 0, self.texture_size as i32, self.texture_size as i32);
 
             gl::ActiveTexture(unit_to_gl_const(0));
-            gl::BindTexture(gl::TEXTURE_2D, brush.texture);
+            gl::BindTexture(gl::TEXTURE_2D, brush.texture.id());
 
             gl::Enable(gl::BLEND);
             gl::Disable(gl::DEPTH_TEST);
@@ -157,10 +244,435 @@ impl Heightmap {
     }
 }
 
+/// The full resolution heights put aside while sculpting on a downsampled copy
+struct SculptProxy {
+    heightmap: Heightmap,
+    heights: HeightField,
+    /// The copy as it was made, what's been sculpted since is the difference from it
+    base: HeightField,
+}
+
+impl SculptProxy {
+    /// The full resolution heights plus what's been sculpted on the proxy. Only the
+    /// difference is upsampled, so detail finer than the proxy stays as it was.
+    fn merged(&self, sculpted: &HeightField) -> HeightField {
+        let delta: Vec<f32> = sculpted
+            .data()
+            .iter()
+            .zip(self.base.data())
+            .map(|(&h, &base)| h - base)
+            .collect();
+        let delta = HeightField::from_data(sculpted.size(), delta);
+        let size = self.heights.size();
+        let mut merged = self.heights.clone();
+        for (y, row) in merged.data_mut().chunks_mut(size).enumerate() {
+            let v = (y as f32 + 0.5) / size as f32;
+            for (x, h) in row.iter_mut().enumerate() {
+                let u = (x as f32 + 0.5) / size as f32;
+                *h = (*h + delta.sample(u, v)).clamp(0.0, 1.0);
+            }
+        }
+        merged
+    }
+}
+
+/// Texels per side of a min / max height cell
+const BOUNDS_CELL_SIZE: usize = 32;
+/// Quads along a patch side without tessellation, as in terrain_grid.vert.glsl
+const GRID_SIZE: usize = 8;
+const GRID_VERTICES: i32 = (GRID_SIZE * GRID_SIZE * 6) as i32;
+/// Quads along a block side and blocks along a level side, as in clipmap.vert.glsl
+const CLIPMAP_BLOCK: usize = 16;
+const CLIPMAP_BLOCKS: usize = 4;
+const CLIPMAP_VERTICES: i32 = (CLIPMAP_BLOCK * CLIPMAP_BLOCK * 6) as i32;
+/// Splat, biome and tint, in the order terrain.frag.glsl finds their pages
+const PAINTED_MAPS: usize = 3;
+/// Texels per side the flood is worked out at, coarse enough to keep up with a slider
+const FLOOD_RESOLUTION: usize = 512;
+/// Goes up whenever the ambient occlusion bake changes, so older bakes aren't read back
+const AMBIENT_OCCLUSION_CACHE_VERSION: u64 = 1;
+
+/// How the terrain is turned into triangles
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TerrainMesh {
+    /// Tessellated patches, or a fixed grid per patch without tessellation
+    Patches,
+    /// Rings around the camera that get coarser further out
+    Clipmap,
+}
+
+impl TerrainMesh {
+    pub const ALL: [TerrainMesh; 2] = [TerrainMesh::Patches, TerrainMesh::Clipmap];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TerrainMesh::Patches => "Patches",
+            TerrainMesh::Clipmap => "Clipmap",
+        }
+    }
+}
+
+pub const MIN_RESOLUTION: u32 = 256;
+pub const MAX_RESOLUTION: u32 = 8192;
+pub const RESOLUTIONS: [u32; 6] = [256, 512, 1024, 2048, 4096, 8192];
+
+/// Things that are decided when a project is created
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TerrainParams {
+    /// Length of a side in meters
+    pub world_size: f32,
+    /// Heightmap texels along a side. Ignored when the heightmap is loaded from an image.
+    pub resolution: u32,
+    pub max_height: f32,
+    /// Everything procedural is derived from this, so that the same project
+    /// comes out the same on every machine
+    #[serde(default)]
+    pub seed: u32,
+}
+
+impl Default for TerrainParams {
+    fn default() -> Self {
+        TerrainParams {
+            world_size: 1024.0,
+            resolution: 1024,
+            max_height: 200.0,
+            seed: 0,
+        }
+    }
+}
+
+impl TerrainParams {
+    pub fn is_valid(&self) -> bool {
+        self.world_size >= 64.0
+            && self.max_height > 0.0
+            && self.resolution.is_power_of_two()
+            && (MIN_RESOLUTION..=MAX_RESOLUTION).contains(&self.resolution)
+    }
+
+    /// Patches are what gets tessellated, so there should be more of them
+    /// when there's more detail in the heightmap
+    fn num_patches(resolution: usize) -> i32 {
+        (resolution as i32 / 16).clamp(16, 256)
+    }
+}
+
+/// Meters to add (positive) or remove (negative) on each side of the terrain.
+/// The terrain has to stay square.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundsChange {
+    pub neg_x: f32,
+    pub pos_x: f32,
+    pub neg_z: f32,
+    pub pos_z: f32,
+    pub fill: EdgeFill,
+}
+
+impl Default for BoundsChange {
+    fn default() -> Self {
+        BoundsChange {
+            neg_x: 0.0,
+            pos_x: 0.0,
+            neg_z: 0.0,
+            pos_z: 0.0,
+            fill: EdgeFill::Clamp,
+        }
+    }
+}
+
+impl BoundsChange {
+    pub fn new_size(&self, world_size: f32) -> f32 {
+        world_size + self.neg_x + self.pos_x
+    }
+
+    pub fn is_square(&self) -> bool {
+        (self.neg_x + self.pos_x - self.neg_z - self.pos_z).abs() < 0.01
+    }
+
+    pub fn is_valid(&self, world_size: f32) -> bool {
+        self.is_square() && self.new_size(world_size) >= 64.0
+    }
+}
+
+/// How much of a pasted square shows at `uv`: all of it in the middle,
+/// fading out over the outer `feather` fraction
+fn paste_weight(uv: Vec2, feather: f32) -> f32 {
+    let edge = uv.min(Vec2::ONE - uv).min_element();
+    if edge < 0.0 {
+        0.0
+    } else if feather > 0.0 {
+        smoothstep(edge / feather)
+    } else {
+        1.0
+    }
+}
+
+fn terrain_aabb(terrain_size: f32, max_height: f32) -> AABB {
+    let half_size = terrain_size / 2.0;
+    let min = Vec3::new(-half_size, 0.0, -half_size);
+    let max = Vec3::new(half_size, max_height, half_size);
+    AABB::new(min, max)
+}
+
+/// What the brush cursor and moved objects stick to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SnapTarget {
+    /// Heightmap texel centers
+    Vertices,
+    /// Edges of the tessellation patches
+    Chunks,
+}
+
+impl SnapTarget {
+    pub const ALL: [SnapTarget; 2] = [SnapTarget::Vertices, SnapTarget::Chunks];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SnapTarget::Vertices => "Vertices",
+            SnapTarget::Chunks => "Chunk edges",
+        }
+    }
+}
+
+pub struct SnapSettings {
+    pub enabled: bool,
+    pub target: SnapTarget,
+}
+
+impl Default for SnapSettings {
+    fn default() -> Self {
+        SnapSettings {
+            enabled: false,
+            target: SnapTarget::Vertices,
+        }
+    }
+}
+
+impl SnapSettings {
+    /// None when snapping is off
+    pub fn active(&self) -> Option<SnapTarget> {
+        if self.enabled {
+            Some(self.target)
+        } else {
+            None
+        }
+    }
+}
+
+/// How the ground is shaped to fit an object
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct FitSettings {
+    /// Meters around the footprint over which the terrain blends back into its old shape
+    pub feather: f32,
+    /// Meters to sink the object into the ground
+    pub depth: f32,
+}
+
+impl Default for FitSettings {
+    fn default() -> Self {
+        FitSettings {
+            feather: 5.0,
+            depth: 0.1,
+        }
+    }
+}
+
+/// Smoothing that only averages along the stroke, so that a riverbed or a ridge gets
+/// evened out lengthwise without being flattened across
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct AlongStrokeSmoothing {
+    /// How fast heights move to the average, per second
+    pub strength: f32,
+    /// Meters averaged over ahead of and behind every texel
+    pub reach: f32,
+}
+
+impl Default for AlongStrokeSmoothing {
+    fn default() -> Self {
+        AlongStrokeSmoothing {
+            strength: 2.0,
+            reach: 10.0,
+        }
+    }
+}
+
+/// Splat layers that erosion paints as it goes, so that the texturing follows it.
+/// Nothing is painted where the layer is None.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ErosionSplat {
+    /// Where sediment has been deposited
+    pub sediment_layer: Option<usize>,
+    /// Where the ground has been worn away
+    pub rock_layer: Option<usize>,
+    /// Meters of deposition or wear that cover a texel with its layer completely
+    pub depth: f32,
+}
+
+impl Default for ErosionSplat {
+    fn default() -> Self {
+        ErosionSplat {
+            sediment_layer: None,
+            rock_layer: None,
+            depth: 2.0,
+        }
+    }
+}
+
+/// Samples taken on either side of a texel by the along-stroke smoothing
+const ALONG_STROKE_TAPS: usize = 4;
+
+/// How far the cursor has to move, relative to the brush size, to change the heading
+const MIN_HEADING_MOVE: f32 = 0.05;
+
+/// How much of a new heading is taken each time, keeping mouse jitter out of it
+const HEADING_SMOOTHING: f32 = 0.3;
+
+/// Which way a stroke is going, from where its cursor has been
+#[derive(Default)]
+pub struct StrokeHeading {
+    last: Option<Vec2>,
+    direction: Option<Vec2>,
+}
+
+impl StrokeHeading {
+    /// None until the cursor has moved far enough to tell
+    pub fn follow(&mut self, stroke: &Stroke) -> Option<Vec2> {
+        let last = *self.last.get_or_insert(stroke.cursor);
+        let moved = stroke.cursor - last;
+        if moved.length() > stroke.brush_size * MIN_HEADING_MOVE {
+            let heading = moved.normalize();
+            let direction = match self.direction {
+                Some(old) => (old + (heading - old) * HEADING_SMOOTHING).normalize_or_zero(),
+                None => heading,
+            };
+            // Doubling back exactly cancels out, keep the new one then
+            self.direction = Some(if direction == Vec2::ZERO {
+                heading
+            } else {
+                direction
+            });
+            self.last = Some(stroke.cursor);
+        }
+        self.direction
+    }
+
+    pub fn end(&mut self) {
+        *self = StrokeHeading::default();
+    }
+}
+
+/// The ground under an object: a rectangle in the object's own XZ plane
+pub struct Footprint {
+    to_local: Mat4,
+    min: Vec2,
+    max: Vec2,
+    /// World space height of the lowest corner
+    bottom: f32,
+}
+
+impl Footprint {
+    /// From a box in model space and where the model is in the world
+    pub fn new(transform: &Mat4, min: Vec3, max: Vec3) -> Self {
+        let bottom = (0..8)
+            .map(|i| {
+                let corner = Vec3::new(
+                    if i & 1 == 0 { min.x } else { max.x },
+                    if i & 2 == 0 { min.y } else { max.y },
+                    if i & 4 == 0 { min.z } else { max.z },
+                );
+                transform.transform_point3(corner).y
+            })
+            .fold(f32::MAX, f32::min);
+        Footprint {
+            to_local: transform.inverse(),
+            min: min.xz(),
+            max: max.xz(),
+            bottom,
+        }
+    }
+
+    /// Center and the side of a world space square that the footprint fits in
+    fn bounds(&self) -> (Vec2, f32) {
+        let to_world = self.to_local.inverse();
+        let corners = [
+            self.min,
+            Vec2::new(self.max.x, self.min.y),
+            self.max,
+            Vec2::new(self.min.x, self.max.y),
+        ]
+        .map(|c| to_world.transform_point3(Vec3::new(c.x, 0.0, c.y)).xz());
+        let min = corners.iter().fold(Vec2::splat(f32::MAX), |a, &c| a.min(c));
+        let max = corners.iter().fold(Vec2::splat(f32::MIN), |a, &c| a.max(c));
+        ((min + max) / 2.0, (max - min).max_element())
+    }
+
+    /// Meters from a world space point to the footprint, zero inside
+    fn distance(&self, point: Vec2) -> f32 {
+        let local = self
+            .to_local
+            .transform_point3(Vec3::new(point.x, self.bottom, point.y))
+            .xz();
+        let outside = (self.min - local).max(local - self.max).max(Vec2::ZERO);
+        outside.length()
+    }
+}
+
+/// One frame of a brush, normalised to the terrain size
+/// so that it can be replayed on a terrain of any size
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Stroke {
+    pub cursor: Vec2,
+    pub brush_size: f32,
+    pub delta_time: f32,
+    #[serde(default)]
+    pub edges: EdgeMode,
+}
+
+impl Stroke {
+    /// A texel wider on each side, that's how far the brush is spread
+    fn rect(&self, resolution: usize) -> DirtyRect {
+        let extent = self.brush_size + 2.0 / resolution as f32;
+        DirtyRect::around((self.cursor.x, self.cursor.y), extent, resolution)
+    }
+
+    /// The stroke and, when the edges wrap, a copy of it across every edge the brush
+    /// reaches past
+    fn wrapped(&self) -> Vec<Stroke> {
+        if self.edges != EdgeMode::Wrap {
+            return vec![*self];
+        }
+        let half = self.brush_size / 2.0;
+        let shifts = |center: f32| {
+            let mut shifts = vec![0.0];
+            if center - half < 0.0 {
+                shifts.push(1.0);
+            }
+            if center + half > 1.0 {
+                shifts.push(-1.0);
+            }
+            shifts
+        };
+        let mut strokes = vec![];
+        for dy in shifts(self.cursor.y) {
+            for dx in shifts(self.cursor.x) {
+                strokes.push(Stroke {
+                    cursor: self.cursor + Vec2::new(dx, dy),
+                    ..*self
+                });
+            }
+        }
+        strokes
+    }
+}
+
 pub struct Brush {
-    texture: GLuint,
+    texture: Texture,
     texture_size: usize,
+    _memory: MemoryHandle,
     pub size: f32,
+
+    // CPU copy for tools which don't paint on the GPU
+    pixels: Vec<u16>,
 }
 
 impl Brush {
@@ -172,26 +684,18 @@ impl Brush {
         assert_eq!(width, height, "Only square brushes are supported");
         let texture_size = width as usize;
 
-        let mut texture: GLuint = 0;
+        let texture = Texture::new(gl::TEXTURE_2D, "Brush");
+        texture.set_wrap_and_filter(gl::CLAMP_TO_BORDER, gl::LINEAR_MIPMAP_LINEAR, gl::LINEAR);
         unsafe {
-            gl::CreateTextures(gl::TEXTURE_2D, 1, &mut texture);
-            gl::TextureParameteri(texture, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_BORDER as GLint);
-            gl::TextureParameteri(texture, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_BORDER as GLint);
-            gl::TextureParameteri(
-                texture,
-                gl::TEXTURE_MIN_FILTER,
-                gl::LINEAR_MIPMAP_LINEAR as GLint,
-            );
-            gl::TextureParameteri(texture, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
             gl::TextureStorage2D(
-                texture,
+                texture.id(),
                 calculate_mip_levels(texture_size, texture_size),
                 gl::R16,
                 texture_size as i32,
                 texture_size as i32,
             );
             gl::TextureSubImage2D(
-                texture,
+                texture.id(),
                 0,
                 0,
                 0,
@@ -201,40 +705,126 @@ impl Brush {
                 gl::UNSIGNED_SHORT,
                 img.as_raw().as_ptr() as *const _,
             );
-            gl::GenerateTextureMipmap(texture);
+            gl::GenerateTextureMipmap(texture.id());
         }
 
+        let mip_levels = calculate_mip_levels(texture_size, texture_size);
         Brush {
             texture,
             size,
             texture_size,
+            _memory: resources::track(
+                ResourceKind::Texture,
+                texture_bytes(gl::R16, texture_size, texture_size, mip_levels),
+            ),
+
+            pixels: img.into_raw(),
         }
     }
+
+    /// Bilinearly filtered brush value in [0, 1], zero outside the brush
+    pub fn sample(&self, uv: Vec2) -> f32 {
+        let size = self.texture_size;
+        sample_bilinear(uv, size, |x, y| {
+            self.pixels[y * size + x] as f32 / u16::MAX as f32
+        })
+    }
+}
+
+/// The terrain as the CPU has it, enough to build it again in a new context
+pub struct TerrainBackup {
+    /// At full resolution, a proxy is merged back in
+    pub params: TerrainParams,
+    heights: HeightField,
+    splat_layers: Vec<HeightField>,
+    biome_layers: Vec<HeightField>,
+    tint_channels: Vec<HeightField>,
+    detail: DetailLayer,
+    reference: Option<HeightField>,
 }
 
 pub struct Terrain {
     pub aabb: AABB,
 
-    vao: GLuint,
+    vao: VertexArray,
     shader: Program,
+    /// The heightmap in grayscale for the 2D view
+    flat_shader: Program,
+    /// Without tessellation the patches are drawn as a fixed grid
+    tessellated: bool,
     pub tess_level: f32,
-
-    texture: GLuint,
+    pub mesh: TerrainMesh,
+    clipmap_shader: Program,
+    /// What brushes find past the border, strokes take it with them
+    pub edges: EdgeMode,
+    /// Draws 3x3 copies of the terrain to look for seams when it's meant to tile
+    pub tiling_preview: bool,
+
+    texture: Texture,
+    _texture_memory: MemoryHandle,
     heightmap: Heightmap,
+    splat_map: SplatMap,
+    /// Biome weights, one biome per layer
+    biome_map: SplatMap,
+    /// Multiplied into the color of the layers
+    tint_map: TintMap,
+    /// The painted maps near the camera, when they're too big to send whole
+    virtual_pages: VirtualPages,
+    /// Sparse heights finer than the heightmap, added to it where there are any
+    detail: DetailLayer,
+    detail_map: DetailMap,
+
+    // CPU copy of the heightmap. Brush strokes happen on the GPU,
+    // the touched regions are read back once per frame.
+    heights: HeightField,
+    height_bounds: HeightBounds,
+    dirty: Option<DirtyRect>,
+    /// Dirty regions on their way back from the GPU
+    readback: Readback<u16>,
+    /// Counting the direct reads of `sync_dirty_region` too
+    readbacks_started: u64,
+    readbacks_taken: u64,
+    /// Brush strokes that have ended: how many readbacks they need and their tag,
+    /// they're undoable once those are taken in
+    stroke_ends: VecDeque<(u64, usize)>,
+    /// The heights changed since the last `end_stroke`
+    stroke_open: bool,
+    history: StrokeHistory,
+    /// Another heightmap to compare with
+    reference: Option<Reference>,
+    overlay: Option<HeatOverlay>,
+    /// A photo covering the painted layers
+    drape: Option<Drape>,
+    drape_opacity: f32,
+    horizon_map: HorizonMap,
+    /// The heights changed since the horizons were baked
+    horizons_stale: bool,
+    /// Heights from before the last filter, dropped once anything else changes them
+    filter_undo: Option<HeightField>,
+    /// Shown on the GPU heightmap while the CPU heights stay as they are
+    filter_preview: Option<HeightFilter>,
+    /// Set while the heights are a downsampled copy to sculpt on. Everything else keeps
+    /// its resolution, patches included.
+    proxy: Option<SculptProxy>,
+
+    pub layers: [TerrainLayer; NUM_LAYERS],
+    pub sun: Sunlight,
 
     pub cursor: Vec2,
     pub brush: Brush,
 
-    shadow_map_fbo: GLuint,
-    shadow_map: GLuint,
+    shadow_map_fbo: Framebuffer,
+    shadow_map: Texture,
     shadow_map_size: i32,
     shadow_map_shader: Program,
+    _shadow_map_memory: MemoryHandle,
 
     debug: TerrainDebug,
 
     // Main parameters
     center: Vec2,
     max_height: f32,
+    seed: u32,
     num_patches: i32,
     patch_size: f32,
 }
@@ -245,128 +835,131 @@ struct TerrainDebug {
 }
 
 impl Terrain {
-    pub fn new(center: Vec2, start_flat: bool, heightmap_path: &str) -> Result<Self> {
+    pub fn new(
+        center: Vec2,
+        params: TerrainParams,
+        start_flat: bool,
+        heightmap_path: &str,
+    ) -> Result<Self> {
         // TODO: support centers other than 0, 0
         // (currently hard-coded in terrain.vert.glsl)
         assert_eq!(center, Vec2::new(0.0, 0.0));
+        assert!(params.is_valid(), "Invalid terrain params: {:?}", params);
 
-        let max_height = 200.0;
-        let num_patches = 64;
-        let patch_size = 16.0;
-
-        let terrain_size = patch_size * num_patches as f32;
-        let aabb = {
-            let half_size = terrain_size / 2.0;
-            let min = Vec3::new(-half_size, 0.0, -half_size);
-            let max = Vec3::new(half_size, max_height, half_size);
-            AABB::new(min, max)
+        let heightmap = if start_flat {
+            Heightmap::flat(params.resolution as usize)?
+        } else {
+            Heightmap::from_image(heightmap_path)?
         };
 
-        let mut vao: GLuint = 0;
-        unsafe {
-            gl::CreateVertexArrays(1, &mut vao);
-        }
+        let heights = HeightField::from_u16(heightmap.texture_size, &heightmap.read_pixels());
+        let height_bounds = HeightBounds::new(&heights, BOUNDS_CELL_SIZE);
 
-        let texture = {
-            let img = image::open("textures/checkerboard.png")
-                .unwrap()
-                .flipv()
-                .into_rgb8();
-            let (width, height) = img.dimensions();
-            assert_eq!(width, height);
-            let size = width as usize;
-
-            let mut texture: GLuint = 0;
-            unsafe {
-                gl::CreateTextures(gl::TEXTURE_2D, 1, &mut texture);
-                gl::TextureParameteri(texture, gl::TEXTURE_WRAP_S, gl::REPEAT as GLint);
-                gl::TextureParameteri(texture, gl::TEXTURE_WRAP_T, gl::REPEAT as GLint);
-                gl::TextureParameteri(
-                    texture,
-                    gl::TEXTURE_MIN_FILTER,
-                    gl::LINEAR_MIPMAP_LINEAR as GLint,
-                );
-                gl::TextureParameteri(texture, gl::TEXTURE_MAG_FILTER, gl::NEAREST as GLint);
-                gl::TextureParameterf(texture, gl::TEXTURE_MAX_ANISOTROPY, get_max_anisotropy());
-                gl::TextureStorage2D(
-                    texture,
-                    calculate_mip_levels(size, size),
-                    gl::SRGB8,
-                    size as i32,
-                    size as i32,
-                );
-                gl::TextureSubImage2D(
-                    texture,
-                    0,
-                    0,
-                    0,
-                    size as i32,
-                    size as i32,
-                    gl::RGB,
-                    gl::UNSIGNED_BYTE,
-                    img.as_raw().as_ptr() as *const _,
-                );
-                gl::GenerateTextureMipmap(texture);
-            }
+        let max_height = params.max_height;
+        let terrain_size = params.world_size;
+        let num_patches = TerrainParams::num_patches(heightmap.texture_size);
+        let patch_size = terrain_size / num_patches as f32;
 
-            texture
-        };
+        let aabb = terrain_aabb(terrain_size, max_height);
+        let horizon_map = HorizonMap::bake(&heights, terrain_size, max_height);
+
+        // Patches are generated in the vertex shader, no buffers needed
+        let vao = VertexArray::new("Terrain");
+
+        let (texture, texture_memory) =
+            GroundImage::load(ground_texture::DEFAULT_PATH, basis::target_format())
+                .unwrap()
+                .upload();
 
         let cursor = vec2_infinity();
-        let heightmap = if start_flat {
-            Heightmap::flat(1024)?
+        let brush = Brush::new("textures/brushes/mountain05.tga", 100.0);
+        let splat_map = SplatMap::new(heightmap.texture_size);
+        let biome_map = SplatMap::new(heightmap.texture_size);
+        let tint_map = TintMap::new(heightmap.texture_size);
+        let virtual_pages = VirtualPages::new(heightmap.texture_size, PAINTED_MAPS);
+        let detail = DetailLayer::new(heightmap.texture_size);
+        let detail_map = DetailMap::new(detail.tiles_per_side());
+
+        let tessellated = caps::get().tessellation;
+        let shader = if tessellated {
+            Program::new()
+                .vertex_shader(include_str!("shaders/editor/terrain/terrain.vert.glsl"))?
+                .tess_control_shader(include_str!("shaders/editor/terrain/terrain.tc.glsl"))?
+                .tess_evaluation_shader(include_str!("shaders/editor/terrain/terrain.te.glsl"))?
         } else {
-            Heightmap::from_image(heightmap_path)?
+            Program::new().vertex_shader(include_str!(
+                "shaders/editor/terrain/terrain_grid.vert.glsl"
+            ))?
         };
-        let brush = Brush::new("textures/brushes/mountain05.tga", 100.0);
-
-        let shader = Program::new()
-            .vertex_shader(include_str!("shaders/editor/terrain/terrain.vert.glsl"))?
-            .tess_control_shader(include_str!("shaders/editor/terrain/terrain.tc.glsl"))?
-            .tess_evaluation_shader(include_str!("shaders/editor/terrain/terrain.te.glsl"))?
+        let shader = shader
             .fragment_shader(include_str!("shaders/editor/terrain/terrain.frag.glsl"))?
             .link()?;
+        let clipmap_shader = Program::new()
+            .vertex_shader(include_str!("shaders/editor/terrain/clipmap.vert.glsl"))?
+            .fragment_shader(include_str!("shaders/editor/terrain/terrain.frag.glsl"))?
+            .link()?;
+        for shader in [&shader, &clipmap_shader] {
+            shader.check_uniform_block(&CameraTransforms::layout())?;
+            shader.set_used();
+            shader.set_vec2("terrain_center", &center)?;
+            shader.set_f32("terrain_max_height", max_height)?;
+            shader.set_f32("terrain_size", terrain_size)?;
+            // One tile of the detail texture every 16 meters
+            shader.set_f32("detail_tiling", terrain_size / 16.0)?;
+        }
         shader.set_used();
-        shader.set_vec2("terrain_center", &center)?;
-        shader.set_f32("terrain_max_height", max_height)?;
-        shader.set_f32("terrain_size", terrain_size)?;
         shader.set_i32("num_patches", num_patches)?;
         shader.set_f32("patch_size", patch_size)?;
+        shader.set_vec2("tile_offset", &Vec2::ZERO)?;
+
+        let flat_shader = Program::new()
+            .vertex_shader(include_str!("shaders/editor/terrain/flat.vert.glsl"))?
+            .fragment_shader(include_str!("shaders/editor/terrain/flat.frag.glsl"))?
+            .link()?;
+        flat_shader.check_uniform_block(&CameraTransforms::layout())?;
+        flat_shader.set_used();
+        flat_shader.set_vec2("terrain_center", &center)?;
+        flat_shader.set_f32("terrain_size", terrain_size)?;
 
         // Shadow map
-        let mut shadow_map_fbo: GLuint = 0;
-        let mut shadow_map: GLuint = 0;
         let shadow_map_size = 2048;
+        let shadow_map = Texture::new(gl::TEXTURE_2D, "Shadow map");
+        shadow_map.set_wrap_and_filter(gl::REPEAT, gl::NEAREST, gl::NEAREST);
+        let shadow_map_fbo = Framebuffer::new("Shadow map");
         unsafe {
-            gl::CreateFramebuffers(1, &mut shadow_map_fbo);
-            gl::CreateTextures(gl::TEXTURE_2D, 1, &mut shadow_map);
-            gl::TextureParameteri(shadow_map, gl::TEXTURE_MIN_FILTER, gl::NEAREST as i32);
-            gl::TextureParameteri(shadow_map, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
-            gl::TextureParameteri(shadow_map, gl::TEXTURE_WRAP_S, gl::REPEAT as i32);
-            gl::TextureParameteri(shadow_map, gl::TEXTURE_WRAP_T, gl::REPEAT as i32);
             gl::TextureStorage2D(
-                shadow_map,
+                shadow_map.id(),
                 1,
                 gl::DEPTH_COMPONENT16,
                 shadow_map_size,
                 shadow_map_size,
             );
-            gl::NamedFramebufferTexture(shadow_map_fbo, gl::DEPTH_ATTACHMENT, shadow_map, 0);
-            gl::NamedFramebufferDrawBuffer(shadow_map_fbo, gl::NONE);
-            gl::NamedFramebufferReadBuffer(shadow_map_fbo, gl::NONE);
+            shadow_map_fbo.attach_texture(gl::DEPTH_ATTACHMENT, &shadow_map);
+            gl::NamedFramebufferDrawBuffer(shadow_map_fbo.id(), gl::NONE);
+            gl::NamedFramebufferReadBuffer(shadow_map_fbo.id(), gl::NONE);
 
             assert_eq!(
-                gl::CheckNamedFramebufferStatus(shadow_map_fbo, gl::FRAMEBUFFER),
+                gl::CheckNamedFramebufferStatus(shadow_map_fbo.id(), gl::FRAMEBUFFER),
                 gl::FRAMEBUFFER_COMPLETE,
                 "Shadow map framebuffer is incomplete",
             );
         }
-        let shadow_map_shader = Program::new()
-            .vertex_shader(include_str!("shaders/editor/terrain/terrain.vert.glsl"))?
-            .tess_control_shader(include_str!("shaders/editor/terrain/terrain.tc.glsl"))?
-            .tess_evaluation_shader(include_str!("shaders/editor/terrain/shadow.te.glsl"))?
+        let shadow_map_shader = if tessellated {
+            Program::new()
+                .vertex_shader(include_str!("shaders/editor/terrain/terrain.vert.glsl"))?
+                .tess_control_shader(include_str!("shaders/editor/terrain/terrain.tc.glsl"))?
+                .tess_evaluation_shader(include_str!("shaders/editor/terrain/shadow.te.glsl"))?
+        } else {
+            Program::new()
+                .define("SHADOW", "1")
+                .vertex_shader(include_str!(
+                    "shaders/editor/terrain/terrain_grid.vert.glsl"
+                ))?
+        };
+        let shadow_map_shader = shadow_map_shader
             .fragment_shader(include_str!("shaders/editor/terrain/shadow.frag.glsl"))?
             .link()?;
+        shadow_map_shader.check_uniform_block(&CameraTransforms::layout())?;
         shadow_map_shader.set_used();
         shadow_map_shader.set_vec2("terrain_center", &center)?;
         shadow_map_shader.set_f32("terrain_max_height", max_height)?;
@@ -382,10 +975,19 @@ impl Terrain {
             aabb_shader.set_vec3("aabb_min", &aabb.min)?;
             aabb_shader.set_vec3("aabb_max", &aabb.max)?;
 
-            let normal_shader = Program::new()
-                .vertex_shader(include_str!("shaders/editor/terrain/terrain.vert.glsl"))?
-                .tess_control_shader(include_str!("shaders/editor/terrain/terrain.tc.glsl"))?
-                .tess_evaluation_shader(include_str!("shaders/editor/terrain/terrain.te.glsl"))?
+            let normal_shader = if tessellated {
+                Program::new()
+                    .vertex_shader(include_str!("shaders/editor/terrain/terrain.vert.glsl"))?
+                    .tess_control_shader(include_str!("shaders/editor/terrain/terrain.tc.glsl"))?
+                    .tess_evaluation_shader(include_str!(
+                        "shaders/editor/terrain/terrain.te.glsl"
+                    ))?
+            } else {
+                Program::new().vertex_shader(include_str!(
+                    "shaders/editor/terrain/terrain_grid.vert.glsl"
+                ))?
+            };
+            let normal_shader = normal_shader
                 .geometry_shader(include_str!("shaders/debug/terrain/normals.geometry.glsl"))?
                 .fragment_shader(include_str!("shaders/debug/terrain/normals.frag.glsl"))?
                 .link()?;
@@ -402,10 +1004,43 @@ impl Terrain {
 
             vao,
             shader,
+            flat_shader,
+            tessellated,
             tess_level: 11.0,
+            mesh: TerrainMesh::Patches,
+            clipmap_shader,
+            edges: EdgeMode::Clamp,
+            tiling_preview: false,
 
             texture,
+            _texture_memory: texture_memory,
             heightmap,
+            splat_map,
+            biome_map,
+            tint_map,
+            virtual_pages,
+            detail,
+            detail_map,
+            heights,
+            height_bounds,
+            dirty: None,
+            readback: Readback::new(gl::RED, gl::UNSIGNED_SHORT),
+            readbacks_started: 0,
+            readbacks_taken: 0,
+            stroke_ends: VecDeque::new(),
+            stroke_open: false,
+            history: StrokeHistory::new(),
+            reference: None,
+            overlay: None,
+            drape: None,
+            drape_opacity: 1.0,
+            horizon_map,
+            horizons_stale: false,
+            filter_undo: None,
+            filter_preview: None,
+            proxy: None,
+            layers: default_layers(),
+            sun: Sunlight::default(),
 
             cursor,
             brush,
@@ -414,132 +1049,1443 @@ impl Terrain {
             shadow_map,
             shadow_map_size,
             shadow_map_shader,
+            _shadow_map_memory: resources::track(
+                ResourceKind::RenderTarget,
+                texture_bytes(
+                    gl::DEPTH_COMPONENT16,
+                    shadow_map_size as usize,
+                    shadow_map_size as usize,
+                    1,
+                ),
+            ),
 
             debug,
 
             center,
             max_height,
+            seed: params.seed,
             num_patches,
             patch_size,
         })
     }
 
-    // TODO: use a renderer
-    pub fn draw(&mut self, time: f32) -> Result<()> {
+    fn bind_common(&self) {
         // Set common stuff for shadow pass / render pass
         unsafe {
-            gl::PatchParameteri(gl::PATCH_VERTICES, 4);
-            gl::BindVertexArray(self.vao);
+            if self.tessellated {
+                gl::PatchParameteri(gl::PATCH_VERTICES, 4);
+            }
+            gl::BindVertexArray(self.vao.id());
 
             // Default texture
             gl::ActiveTexture(unit_to_gl_const(0));
-            gl::BindTexture(gl::TEXTURE_2D, self.texture);
-
-            // Heightmap
-            gl::ActiveTexture(unit_to_gl_const(1));
-            gl::BindTexture(gl::TEXTURE_2D, self.heightmap.texture);
+            gl::BindTexture(gl::TEXTURE_2D, self.texture.id());
 
             // Brush
             gl::ActiveTexture(unit_to_gl_const(2));
-            gl::BindTexture(gl::TEXTURE_2D, self.brush.texture);
+            gl::BindTexture(gl::TEXTURE_2D, self.brush.texture.id());
 
             // Shadow map
             gl::ActiveTexture(unit_to_gl_const(3));
-            gl::BindTexture(gl::TEXTURE_2D, self.shadow_map);
+            gl::BindTexture(gl::TEXTURE_2D, self.shadow_map.id());
         }
+        self.bind_ground_maps();
+    }
 
-        // Draw into shadow map
-        self.shadow_map_shader.set_used();
-        self.shadow_map_shader
-            .set_f32("tess_level", self.tess_level)?;
+    /// The heights and the painted maps, as in heightmap.glsl and painted_maps.glsl
+    fn bind_ground_maps(&self) {
         unsafe {
-            gl::BindFramebuffer(gl::FRAMEBUFFER, self.shadow_map_fbo);
-            gl::Viewport(0, 0, self.shadow_map_size, self.shadow_map_size);
-            gl::Clear(gl::DEPTH_BUFFER_BIT);
-
-            gl::DrawArraysInstanced(gl::PATCHES, 0, 4, 64 * 64);
+            // Heightmap
+            gl::ActiveTexture(unit_to_gl_const(1));
+            gl::BindTexture(gl::TEXTURE_2D, self.heightmap.texture.id());
 
-            gl::Viewport(0, 0, WINDOW_WIDTH as i32, WINDOW_HEIGHT as i32);
-            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
-        }
+            // Texture layer weights
+            gl::ActiveTexture(unit_to_gl_const(4));
+            gl::BindTexture(gl::TEXTURE_2D, self.splat_map.texture().id());
 
-        // Draw the scene
-        self.shader.set_used();
-        self.shader.set_vec2("cursor", &self.cursor)?;
-        self.shader.set_f32("brush_size", self.brush.size)?;
-        self.shader.set_f32("tess_level", self.tess_level)?;
+            gl::ActiveTexture(unit_to_gl_const(7));
+            gl::BindTexture(gl::TEXTURE_2D, self.biome_map.texture().id());
 
-        unsafe {
-            // gl::PolygonMode(gl::FRONT_AND_BACK, gl::LINE);
-            gl::DrawArraysInstanced(gl::PATCHES, 0, 4, 64 * 64);
-            // gl::PolygonMode(gl::FRONT_AND_BACK, gl::FILL);
-        }
+            gl::ActiveTexture(unit_to_gl_const(11));
+            gl::BindTexture(gl::TEXTURE_2D, self.tint_map.texture().id());
 
-        // // Draw debug stuff
-        // {
-        //     // Draw AABB
-        //     let debug = &mut self.debug;
-        //     debug.aabb_shader.set_used();
-        //     debug.aabb_shader.set_f32("time", time)?;
-        //     unsafe {
-        //         gl::DrawArrays(gl::LINE_STRIP, 0, 16);
-        //     }
+            // Full resolution pages of the painted maps
+            gl::ActiveTexture(unit_to_gl_const(virtual_texture::PAGES_UNIT));
+            gl::BindTexture(gl::TEXTURE_2D_ARRAY, self.virtual_pages.pages.id());
+            gl::ActiveTexture(unit_to_gl_const(virtual_texture::TABLE_UNIT));
+            gl::BindTexture(gl::TEXTURE_2D, self.virtual_pages.table.id());
 
-        //     // Draw normals
-        //     debug.normal_shader.set_used();
-        //     debug.normal_shader.set_f32("tess_level", self.tess_level)?;
-        //     unsafe {
-        //         gl::DrawArraysInstanced(gl::PATCHES, 0, 4, 64 * 64);
-        //     }
-        // }
+            // Carved detail
+            gl::ActiveTexture(unit_to_gl_const(detail_map::TILES_UNIT));
+            gl::BindTexture(gl::TEXTURE_2D_ARRAY, self.detail_map.tiles.id());
+            gl::ActiveTexture(unit_to_gl_const(detail_map::INDEX_UNIT));
+            gl::BindTexture(gl::TEXTURE_2D, self.detail_map.index.id());
+        }
+    }
 
-        Ok(())
+    /// One instance per patch, either tessellated or split into a fixed grid
+    fn draw_patches(&self) {
+        let instances = self.num_patches * self.num_patches;
+        unsafe {
+            if self.tessellated {
+                gl::DrawArraysInstanced(gl::PATCHES, 0, 4, instances);
+            } else {
+                gl::DrawArraysInstanced(gl::TRIANGLES, 0, GRID_VERTICES, instances);
+            }
+        }
     }
 
-    pub fn get_heightmap_pixels(&self) -> (Vec<u8>, usize) {
-        let buffer_size = self.heightmap.texture_size * self.heightmap.texture_size * 2;
-        let mut pixels = Vec::<u8>::with_capacity(buffer_size);
+    /// Every level a block per instance. The finest level has the heightmap's
+    /// resolution and the coarsest covers the terrain from anywhere on it.
+    fn draw_clipmap(&self) {
+        let level_quads = CLIPMAP_BLOCK * CLIPMAP_BLOCKS;
+        let resolution = self.params().resolution as usize;
+        let mut levels = 1;
+        while (level_quads / 2) << (levels - 1) < resolution {
+            levels += 1;
+        }
+        let instances = (levels * CLIPMAP_BLOCKS * CLIPMAP_BLOCKS) as i32;
         unsafe {
-            pixels.set_len(buffer_size);
-            gl::GetTextureImage(
-                self.heightmap.texture,
-                0,
-                gl::RED,
-                gl::UNSIGNED_SHORT,
-                buffer_size as i32,
-                pixels.as_mut_ptr() as *mut c_void,
-            );
+            gl::DrawArraysInstanced(gl::TRIANGLES, 0, CLIPMAP_VERTICES, instances);
         }
-        (pixels, self.heightmap.texture_size)
     }
 
-    pub fn size(&self) -> f32 {
+    /// Only needs to happen once per frame regardless of how many views there are
+    /// `draw_casters` draws anything else that should cast shadows on the terrain
+    pub fn draw_shadow_map(&mut self, draw_casters: impl FnOnce() -> Result<()>) -> Result<()> {
+        self.bind_common();
+
+        // Draw into shadow map
+        self.shadow_map_shader.set_used();
+        if self.tessellated {
+            self.shadow_map_shader
+                .set_f32("tess_level", self.tess_level)?;
+        }
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.shadow_map_fbo.id());
+            gl::Viewport(0, 0, self.shadow_map_size, self.shadow_map_size);
+            gl::Clear(gl::DEPTH_BUFFER_BIT);
+        }
+        self.draw_patches();
+        draw_casters()?;
+        unsafe {
+            gl::Viewport(0, 0, WINDOW_WIDTH as i32, WINDOW_HEIGHT as i32);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        }
+
+        Ok(())
+    }
+
+    /// The heightmap seen from above as a grayscale image, with the same brush as the
+    /// 3D terrain. `camera_marker` is where the main camera is on the ground.
+    pub fn draw_flat(&self, camera_marker: Vec2) -> Result<()> {
+        self.bind_common();
+        self.flat_shader.set_used();
+        self.flat_shader.set_vec2("cursor", &self.cursor)?;
+        self.flat_shader.set_f32("brush_size", self.brush.size)?;
+        self.flat_shader.set_vec2("camera_marker", &camera_marker)?;
+        unsafe {
+            gl::DrawArrays(gl::TRIANGLE_STRIP, 0, 4);
+        }
+        Ok(())
+    }
+
+    // TODO: use a renderer
+    pub fn draw(
+        &mut self,
+        time: f32,
+        compare: &CompareSettings,
+        seasons: &SeasonSettings,
+        biomes: &BiomeSettings,
+        sky_light: &SkyLight,
+        horizon: &HorizonSettings,
+        hypsometric: &HypsometricSettings,
+    ) -> Result<()> {
+        self.bind_common();
+
+        // Draw the scene. The clipmap only covers what's around the camera, so the
+        // copies for the tiling preview are drawn with patches, and so is the original.
+        let mesh = if self.tiling_preview {
+            TerrainMesh::Patches
+        } else {
+            self.mesh
+        };
+        let shader = match mesh {
+            TerrainMesh::Patches => &self.shader,
+            TerrainMesh::Clipmap => &self.clipmap_shader,
+        };
+        shader.set_used();
+        shader.set_vec2("cursor", &self.cursor)?;
+        shader.set_f32("brush_size", self.brush.size)?;
+        if self.tessellated && mesh == TerrainMesh::Patches {
+            shader.set_f32("tess_level", self.tess_level)?;
+        }
+        let compare_opacity = match &self.reference {
+            Some(reference) => {
+                unsafe {
+                    gl::ActiveTexture(unit_to_gl_const(5));
+                    gl::BindTexture(gl::TEXTURE_2D, reference.texture.id());
+                }
+                compare.overlay_opacity
+            }
+            None => 0.0,
+        };
+        shader.set_f32("compare_opacity", compare_opacity)?;
+        let overlay_opacity = match &self.overlay {
+            Some(overlay) => {
+                unsafe {
+                    gl::ActiveTexture(unit_to_gl_const(6));
+                    gl::BindTexture(gl::TEXTURE_2D, overlay.texture.id());
+                }
+                overlay.opacity
+            }
+            None => 0.0,
+        };
+        shader.set_f32("overlay_opacity", overlay_opacity)?;
+        let drape_opacity = match &self.drape {
+            Some(drape) => {
+                unsafe {
+                    gl::ActiveTexture(unit_to_gl_const(12));
+                    gl::BindTexture(gl::TEXTURE_2D, drape.texture.id());
+                }
+                shader.set_vec2("drape_uv_scale", &drape.uv_scale)?;
+                shader.set_vec2("drape_uv_offset", &drape.uv_offset)?;
+                self.drape_opacity
+            }
+            None => 0.0,
+        };
+        shader.set_f32("drape_opacity", drape_opacity)?;
+        shader.set_f32("compare_range", compare.range / self.max_height)?;
+        self.set_layer_uniforms(shader, seasons, biomes)?;
+        for (i, biome) in biomes.biomes.iter().enumerate() {
+            shader.set_float3(&format!("biome_colors[{}]", i), &biome.color)?;
+        }
+        sky_light.set_uniforms(shader, &self.sun)?;
+        unsafe {
+            gl::ActiveTexture(unit_to_gl_const(10));
+            gl::BindTexture(gl::TEXTURE_2D_ARRAY, self.horizon_map.texture.id());
+        }
+        shader.set_i32("horizon_shadows", horizon.enabled as i32)?;
+        shader.set_i32("shadow_map_enabled", horizon.shadow_map as i32)?;
+        let (half_extent, near, far) = self.sun_frustum();
+        shader.set_f32("sun_size", self.sun.size)?;
+        shader.set_f32("shadow_map_extent", half_extent * 2.0)?;
+        shader.set_f32("shadow_depth_range", far - near)?;
+        let sky_occlusion = if horizon.enabled {
+            horizon.sky_occlusion
+        } else {
+            0.0
+        };
+        shader.set_f32("sky_occlusion", sky_occlusion)?;
+        shader.set_f32("horizon_softness", horizon.softness.max(0.001))?;
+        let biome_opacity = if biomes.show_regions { 0.5 } else { 0.0 };
+        shader.set_f32("biome_opacity", biome_opacity)?;
+        hypsometric.set_uniforms(shader)?;
+
+        // unsafe { gl::PolygonMode(gl::FRONT_AND_BACK, gl::LINE) };
+        match mesh {
+            TerrainMesh::Patches => self.draw_patches(),
+            TerrainMesh::Clipmap => self.draw_clipmap(),
+        }
+        if self.tiling_preview {
+            let size = self.size();
+            for y in -1..=1 {
+                for x in -1..=1 {
+                    if x == 0 && y == 0 {
+                        continue;
+                    }
+                    let offset = Vec2::new(x as f32, y as f32) * size;
+                    self.shader.set_vec2("tile_offset", &offset)?;
+                    self.draw_patches();
+                }
+            }
+            self.shader.set_vec2("tile_offset", &Vec2::ZERO)?;
+        }
+        // unsafe { gl::PolygonMode(gl::FRONT_AND_BACK, gl::FILL) };
+
+        // // Draw debug stuff
+        // {
+        //     // Draw AABB
+        //     let debug = &mut self.debug;
+        //     debug.aabb_shader.set_used();
+        //     debug.aabb_shader.set_f32("time", time)?;
+        //     unsafe {
+        //         gl::DrawArrays(gl::LINE_STRIP, 0, 16);
+        //     }
+
+        //     // Draw normals
+        //     debug.normal_shader.set_used();
+        //     debug.normal_shader.set_f32("tess_level", self.tess_level)?;
+        //     unsafe {
+        //         gl::DrawArraysInstanced(gl::PATCHES, 0, 4, 64 * 64);
+        //     }
+        // }
+
+        Ok(())
+    }
+
+    /// The colors the painted maps mix, and how big the maps are if they're sent in pages
+    fn set_layer_uniforms(
+        &self,
+        shader: &Program,
+        seasons: &SeasonSettings,
+        biomes: &BiomeSettings,
+    ) -> Result<()> {
+        for (i, layer) in self.layers.iter().enumerate() {
+            let color = seasons.layer_color(i, layer.color);
+            shader.set_float3(&format!("layer_colors[{}]", i), &color)?;
+        }
+        for (i, biome) in biomes.biomes.iter().enumerate() {
+            shader.set_float3(&format!("biome_tints[{}]", i), &biome.tint)?;
+        }
+        shader.set_f32("virtual_size", self.virtual_pages.virtual_size() as f32)?;
+        Ok(())
+    }
+
+    /// For models blended into the ground, `shader` has to include heightmap.glsl and
+    /// painted_maps.glsl
+    pub fn set_ground_uniforms(
+        &self,
+        shader: &Program,
+        seasons: &SeasonSettings,
+        biomes: &BiomeSettings,
+    ) -> Result<()> {
+        self.bind_ground_maps();
+        shader.set_used();
+        let center = self.aabb.min.xz() + Vec2::splat(self.size() / 2.0);
+        shader.set_vec2("terrain_center", &center)?;
+        shader.set_f32("terrain_size", self.size())?;
+        shader.set_f32("terrain_max_height", self.max_height)?;
+        self.set_layer_uniforms(shader, seasons, biomes)
+    }
+
+    /// From the CPU copy, so it's only as fresh as the last sync
+    /// With the detail layer folded in
+    pub fn get_heightmap_pixels(&self) -> (Vec<u16>, usize) {
+        let mut heights = self.full_heights().into_owned();
+        self.detail.add_to(&mut heights);
+        (heights.to_u16(), heights.size())
+    }
+
+    pub fn size(&self) -> f32 {
         self.aabb.max.x - self.aabb.min.x
     }
 
-    pub fn shape_terrain(&mut self, delta_time: f32, raise: bool) {
+    pub fn max_height(&self) -> f32 {
+        self.max_height
+    }
+
+    /// The full resolution even while sculpting on a proxy
+    pub fn params(&self) -> TerrainParams {
+        let heightmap = match &self.proxy {
+            Some(proxy) => &proxy.heightmap,
+            None => &self.heightmap,
+        };
+        TerrainParams {
+            world_size: self.size(),
+            resolution: heightmap.texture_size as u32,
+            max_height: self.max_height,
+            seed: self.seed,
+        }
+    }
+
+    /// Changes the resolution of the heightmap and every map that goes with it,
+    /// keeping the world size and the shape of the terrain
+    pub fn resample(&mut self, resolution: u32) -> Result<()> {
+        self.end_proxy();
+        let params = TerrainParams {
+            resolution,
+            ..self.params()
+        };
+        assert!(params.is_valid(), "Invalid terrain params: {:?}", params);
+        let new_size = resolution as usize;
+        if new_size == self.heightmap.texture_size {
+            return Ok(());
+        }
+
+        let heights = self.heights.resampled(new_size);
+        let splat_map = self.splat_map.resampled(new_size);
+        let biome_map = self.biome_map.resampled(new_size);
+        let tint_map = self.tint_map.resampled(new_size);
+        let maps = (splat_map, biome_map, tint_map);
+        self.replace_maps(&heights, maps, params.world_size)
+    }
+
+    /// Crops or extends the terrain on each side, then re-centers it.
+    /// Keeps roughly the same meters per texel, rounding to a supported resolution.
+    pub fn change_bounds(&mut self, change: &BoundsChange) -> Result<()> {
+        // The detail tiles wouldn't line up anymore
+        self.bake_detail();
+        self.end_proxy();
+        let world_size = self.size();
+        let new_world_size = change.new_size(world_size);
+        assert!(change.is_valid(world_size), "Invalid bounds: {:?}", change);
+
+        let texel_size = world_size / self.heightmap.texture_size as f32;
+        let x0 = (-change.neg_x / texel_size).round() as isize;
+        let y0 = (-change.neg_z / texel_size).round() as isize;
+        let texels = (new_world_size / texel_size).round() as usize;
+
+        let heights = self.heights.region(x0, y0, texels, change.fill);
+        let splat_map = self.splat_map.region(x0, y0, texels);
+        let biome_map = self.biome_map.region(x0, y0, texels);
+        let tint_map = self.tint_map.region(x0, y0, texels);
+
+        let resolution = (texels.next_power_of_two() as u32).clamp(MIN_RESOLUTION, MAX_RESOLUTION);
+        let resolution = resolution as usize;
+        let heights = heights.resampled(resolution);
+        let splat_map = splat_map.resampled(resolution);
+        let biome_map = biome_map.resampled(resolution);
+        let tint_map = tint_map.resampled(resolution);
+        let maps = (splat_map, biome_map, tint_map);
+        self.replace_maps(&heights, maps, new_world_size)?;
+        // Lined up with the old bounds
+        self.drape = None;
+        Ok(())
+    }
+
+    /// Swaps the heights for a copy downsampled to `resolution`, which is a lot cheaper
+    /// to sculpt on when all that's needed is broad shaping. `end_proxy` brings the
+    /// changes back to full resolution.
+    pub fn begin_proxy(&mut self, resolution: usize) -> Result<()> {
+        if self.proxy.is_some() || resolution >= self.heights.size() {
+            return Ok(());
+        }
+        self.sync_dirty_region();
+        self.end_filter_preview();
+        let base = self.heights.resampled(resolution);
+        let heightmap = Heightmap::from_pixels(&base.to_u16(), resolution)?;
+        let proxy = SculptProxy {
+            heightmap: mem::replace(&mut self.heightmap, heightmap),
+            heights: mem::replace(&mut self.heights, base.clone()),
+            base,
+        };
+        self.proxy = Some(proxy);
+        self.heights_swapped();
+        Ok(())
+    }
+
+    /// Adds what's been sculpted on the proxy to the full resolution heights
+    pub fn end_proxy(&mut self) {
+        self.sync_dirty_region();
+        self.end_filter_preview();
+        let proxy = match self.proxy.take() {
+            Some(proxy) => proxy,
+            None => return,
+        };
+        let heights = proxy.merged(&self.heights);
+        self.heightmap = proxy.heightmap;
+        let rect = DirtyRect::full(heights.size());
+        self.heightmap.write_region(&rect, &heights.to_u16());
+        self.heights = heights;
+        self.heights_swapped();
+    }
+
+    /// Resolution of the proxy being sculpted on, if there is one
+    pub fn proxy_resolution(&self) -> Option<usize> {
+        self.proxy.as_ref().map(|_| self.heights.size())
+    }
+
+    /// Merged with the proxy if there's one
+    fn full_heights(&self) -> Cow<HeightField> {
+        match &self.proxy {
+            Some(proxy) => Cow::Owned(proxy.merged(&self.heights)),
+            None => Cow::Borrowed(&self.heights),
+        }
+    }
+
+    /// After the heights have changed resolution
+    fn heights_swapped(&mut self) {
+        self.height_bounds = HeightBounds::new(&self.heights, BOUNDS_CELL_SIZE);
+        self.forget_strokes();
+        self.horizons_stale = true;
+        self.filter_undo = None;
+        let resolution = self.heights.size();
+        if let Some(reference) = &mut self.reference {
+            *reference = Reference::new(reference.heights(), resolution);
+        }
+    }
+
+    pub fn erode(&mut self, settings: &ErosionSettings, splat: &ErosionSplat) {
+        self.sync_dirty_region();
+        let texel_size = self.size() / self.heightmap.texture_size as f32;
+        let heights = thermal_erosion(&self.heights, settings, texel_size, self.max_height);
+        let before = mem::replace(&mut self.heights, heights);
+        let rect = DirtyRect::full(before.size());
+        self.paint_erosion(before.data(), &rect, splat);
+        self.set_heights(self.heights.clone());
+    }
+
+    /// Paints the deposition and wear since `before`, the heights in `rect` as they were
+    fn paint_erosion(&mut self, before: &[f32], rect: &DirtyRect, splat: &ErosionSplat) {
+        let size = self.heights.size();
+        let resolution = self.splat_map.size();
+        let splat_rect = DirtyRect {
+            x0: rect.x0 * resolution / size,
+            y0: rect.y0 * resolution / size,
+            x1: (rect.x1 * resolution).div_ceil(size),
+            y1: (rect.y1 * resolution).div_ceil(size),
+        };
+        if rect.is_empty() {
+            return;
+        }
+        let heights = self.heights.data();
+        let scale = self.max_height / splat.depth.max(f32::EPSILON);
+        // Nearest heightmap texel, the maps don't have to be the same size
+        let change = |x: usize, y: usize| {
+            let hx = (x * size / resolution).clamp(rect.x0, rect.x1 - 1);
+            let hy = (y * size / resolution).clamp(rect.y0, rect.y1 - 1);
+            let old = before[(hy - rect.y0) * rect.width() + hx - rect.x0];
+            (heights[hy * size + hx] - old) * scale
+        };
+        if let Some(layer) = splat.sediment_layer {
+            self.splat_map
+                .add_to_layer(layer, &splat_rect, |x, y| change(x, y).max(0.0));
+        }
+        if let Some(layer) = splat.rock_layer {
+            self.splat_map
+                .add_to_layer(layer, &splat_rect, |x, y| (-change(x, y)).max(0.0));
+        }
+    }
+
+    /// Can be undone with `undo_filter` until the heights change some other way
+    pub fn apply_filter(&mut self, filter: &HeightFilter) {
+        self.sync_dirty_region();
+        let heights = filter.apply(&self.heights);
+        let previous = self.heights.clone();
+        self.set_heights(heights);
+        self.filter_undo = Some(previous);
+    }
+
+    /// False if there was nothing to undo
+    pub fn undo_filter(&mut self) -> bool {
+        match self.filter_undo.take() {
+            Some(heights) => {
+                self.set_heights(heights);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn can_undo_filter(&self) -> bool {
+        self.filter_undo.is_some()
+    }
+
+    /// Shows what `filter` would do without changing the heights anything else sees.
+    /// Sculpting in the meantime ends the preview.
+    pub fn preview_filter(&mut self, filter: &HeightFilter) {
+        self.sync_dirty_region();
+        let preview = filter.apply(&self.heights);
+        let rect = DirtyRect::full(preview.size());
+        self.heightmap.write_region(&rect, &preview.to_u16());
+        self.filter_preview = Some(*filter);
+    }
+
+    pub fn end_filter_preview(&mut self) {
+        if self.filter_preview.take().is_some() {
+            let rect = DirtyRect::full(self.heights.size());
+            self.heightmap.write_region(&rect, &self.heights.to_u16());
+        }
+    }
+
+    pub fn filter_preview(&self) -> Option<HeightFilter> {
+        self.filter_preview
+    }
+
+    /// Darkens crevices and colors the paths water takes, on top of the tint map
+    pub fn weather(&mut self, settings: &WeatheringSettings) {
+        self.sync_dirty_region();
+        let heights = self.full_heights();
+        let texel_size = self.size() / heights.size() as f32;
+        let tint = weathering_tint(&heights, settings, texel_size, self.max_height);
+        self.tint_map.multiply(&tint);
+    }
+
+    /// Tangent space style RGB8 normal map, +Z of the texture is world up
+    pub fn get_normal_map_pixels(&self) -> (Vec<u8>, usize) {
+        let heights = self.full_heights();
+        let size = heights.size();
+        let texel_size = self.size() / size as f32;
+        let pixels = normal_map_rgb(&heights, texel_size, self.max_height);
+        (pixels, size)
+    }
+
+    /// RGBA8, the weight of each layer in a channel
+    pub fn get_splat_pixels(&self) -> (Vec<u8>, usize) {
+        let to_byte = |weight: f32| (weight.clamp(0.0, 1.0) * 255.0).round() as u8;
+        let size = self.splat_map.size();
+        let pixels = (0..size * size)
+            .flat_map(|i| self.splat_map.weights_at(i % size, i / size).map(to_byte))
+            .collect();
+        (pixels, size)
+    }
+
+    /// R8, how much of the sky every texel sees the way the sky light is shaded, at
+    /// the resolution of the horizon map. Heights baked before come from the derived
+    /// data cache.
+    pub fn get_ambient_occlusion_pixels(&self) -> (Vec<u8>, usize) {
+        let heights = self.full_heights();
+        let size = heights.size().min(horizon_map::MAX_RESOLUTION);
+        let hash = ContentHash::new()
+            .with_u64(AMBIENT_OCCLUSION_CACHE_VERSION)
+            .with_u64(size as u64)
+            .with_f32(self.size())
+            .with_f32(self.max_height)
+            .with_floats(heights.data());
+        if let Some(pixels) = derived_cache::get("ambient_occlusion", hash) {
+            if pixels.len() == size * size {
+                return (pixels, size);
+            }
+        }
+        let pixels = ambient_occlusion_gray(&heights, size, self.size(), self.max_height);
+        derived_cache::put("ambient_occlusion", hash, &pixels);
+        (pixels, size)
+    }
+
+    /// RGB8, the terrain colored by height the way the hypsometric view shows it
+    pub fn get_hypsometric_pixels(&self, gradient: &ColorGradient) -> (Vec<u8>, usize) {
+        let heights = self.full_heights();
+        (hypsometric_rgb(&heights, gradient), heights.size())
+    }
+
+    /// Replaces all heights keeping the resolution
+    fn set_heights(&mut self, heights: HeightField) {
+        debug_assert_eq!(heights.size(), self.heights.size());
+        let rect = DirtyRect::full(heights.size());
+        self.heightmap.write_region(&rect, &heights.to_u16());
+        self.height_bounds = HeightBounds::new(&heights, BOUNDS_CELL_SIZE);
+        self.heights = heights;
+        self.forget_strokes();
+        self.horizons_stale = true;
+        self.filter_undo = None;
+        self.filter_preview = None;
+    }
+
+    /// Sends a region of the CPU heights to the GPU after changing them in place,
+    /// `before` is the region as it was for undoing it
+    fn upload_heights(&mut self, rect: &DirtyRect, before: &[u16]) {
+        // Would bring back the heights from before the change
+        debug_assert!(
+            self.readback.is_idle(),
+            "Heights changed without syncing first"
+        );
+        self.end_filter_preview();
+        let pixels = self.heights.read_region_u16(rect);
+        self.history.capture(rect, before, &pixels);
+        self.stroke_open = true;
+        self.heightmap.write_region(rect, &pixels);
+        self.height_bounds.update(&self.heights, rect);
+        self.horizons_stale = true;
+        self.filter_undo = None;
+    }
+
+    /// Brings the CPU copy up to date with what's been sculpted on the GPU.
+    /// Only the regions touched since the last call are read back.
+    pub fn sync_dirty_region(&mut self) {
+        self.finish_readbacks();
+        if let Some(rect) = self.dirty.take() {
+            let pixels = self.heightmap.read_region(&rect);
+            self.readbacks_started += 1;
+            self.take_readback(&rect, &pixels);
+        }
+    }
+
+    /// Like `sync_dirty_region` without waiting for the GPU: the regions touched
+    /// since the last call start on their way back and those that have arrived are
+    /// taken in. The CPU copy lags a few frames behind at most, which is fine for
+    /// picking, anything that needs it exact syncs first. Once a frame.
+    pub fn sync_dirty_region_async(&mut self) {
+        for (rect, pixels) in self.readback.poll() {
+            self.take_readback(&rect, &pixels);
+        }
+        if let Some(rect) = self.dirty.take() {
+            self.readback.start(&self.heightmap.texture, rect);
+            self.readbacks_started += 1;
+        }
+    }
+
+    fn finish_readbacks(&mut self) {
+        for (rect, pixels) in self.readback.finish() {
+            self.take_readback(&rect, &pixels);
+        }
+    }
+
+    fn take_readback(&mut self, rect: &DirtyRect, pixels: &[u16]) {
+        let before = self.heights.read_region_u16(rect);
+        self.history.capture(rect, &before, pixels);
+        self.heights.write_region_u16(rect, pixels);
+        self.height_bounds.update(&self.heights, rect);
+        self.horizons_stale = true;
+        self.filter_undo = None;
+        self.readbacks_taken += 1;
+        self.close_strokes();
+    }
+
+    /// Once the brush is lifted: everything the heights went through since the last
+    /// call is one step for `undo_stroke`, which gives back `tag` with it. Does nothing
+    /// if they haven't changed.
+    pub fn end_stroke(&mut self, tag: usize) {
+        if !self.stroke_open {
+            return;
+        }
+        self.stroke_open = false;
+        if let Some(rect) = self.dirty.take() {
+            self.readback.start(&self.heightmap.texture, rect);
+            self.readbacks_started += 1;
+        }
+        self.stroke_ends.push_back((self.readbacks_started, tag));
+        self.close_strokes();
+    }
+
+    /// Ends the strokes whose changes have all been read back
+    fn close_strokes(&mut self) {
+        while let Some(&(readbacks, tag)) = self.stroke_ends.front() {
+            if readbacks > self.readbacks_taken {
+                break;
+            }
+            self.stroke_ends.pop_front();
+            self.history.end_stroke(tag);
+        }
+    }
+
+    pub fn can_undo_stroke(&self) -> bool {
+        self.history.can_undo() || !self.stroke_ends.is_empty()
+    }
+
+    /// Puts back the heights from before the last stroke that's ended. Returns the
+    /// stroke's tag and the texels it changed, none if there's nothing to undo.
+    pub fn undo_stroke(&mut self) -> Option<(usize, DirtyRect)> {
+        self.sync_dirty_region();
+        let (tag, deltas) = self.history.undo()?;
+        self.end_filter_preview();
+        let mut changed: Option<DirtyRect> = None;
+        for (rect, difference) in deltas.iter().rev() {
+            let mut pixels = self.heights.read_region_u16(rect);
+            for (pixel, &difference) in pixels.iter_mut().zip(difference) {
+                *pixel = pixel.wrapping_add(difference);
+            }
+            self.heightmap.write_region(rect, &pixels);
+            self.heights.write_region_u16(rect, &pixels);
+            self.height_bounds.update(&self.heights, rect);
+            changed = Some(match changed {
+                Some(changed) => changed.union(rect),
+                None => *rect,
+            });
+        }
+        self.horizons_stale = true;
+        self.filter_undo = None;
+        changed.map(|changed| (tag, changed))
+    }
+
+    /// How long capturing strokes for undo took since the last call
+    pub fn take_undo_capture_time(&mut self) -> Duration {
+        self.history.take_capture_time()
+    }
+
+    /// Drops whatever's been sculpted and not read back yet, and every stroke that
+    /// could be undone. For when the heights have been replaced.
+    fn forget_strokes(&mut self) {
+        self.dirty = None;
+        self.readback.cancel();
+        self.readbacks_taken = self.readbacks_started;
+        self.stroke_ends.clear();
+        self.stroke_open = false;
+        self.history.clear();
+    }
+
+    pub fn horizons_stale(&self) -> bool {
+        self.horizons_stale
+    }
+
+    pub fn bake_horizons(&mut self) {
+        self.horizon_map = HorizonMap::bake(&self.heights, self.size(), self.max_height);
+        self.horizons_stale = false;
+    }
+
+    /// Flat enough regions big enough to build on, over the whole terrain
+    pub fn buildable_areas(&self, settings: &BuildableSettings) -> BuildableAreas {
+        buildable::buildable_areas(&self.surface(), settings)
+    }
+
+    /// Meters of water that would stand on the terrain, at a lower resolution
+    pub fn flood_depth(&self, settings: &FloodSettings) -> HeightField {
+        let heights = self
+            .heights
+            .resampled(self.heights.size().min(FLOOD_RESOLUTION));
+        flood::flood_depth(&heights, self.max_height, settings)
+    }
+
+    /// Hours of direct sun over a day with the sun going through `sun_path`, at the
+    /// resolution of the horizon map since that's what the shadows come from
+    pub fn sun_hours(&self, sun_path: &[Vec3]) -> HeightField {
+        let size = self.heights.size().min(horizon_map::MAX_RESOLUTION);
+        insolation::sun_hours(&self.heights, size, self.size(), self.max_height, sun_path)
+    }
+
+    /// Lowest and highest point of the terrain in meters
+    pub fn height_range(&self) -> (f32, f32) {
+        let (min, max) = self.height_bounds.range();
+        (min * self.max_height, max * self.max_height)
+    }
+
+    /// Swaps in new maps and updates everything that depends on the resolution or world size
+    fn replace_maps(
+        &mut self,
+        heights: &HeightField,
+        (splat_map, biome_map, tint_map): (SplatMap, SplatMap, TintMap),
+        world_size: f32,
+    ) -> Result<()> {
+        let resolution = heights.size();
+        self.heightmap = Heightmap::from_pixels(&heights.to_u16(), resolution)?;
+        self.splat_map = splat_map;
+        self.biome_map = biome_map;
+        self.tint_map = tint_map;
+        self.heights = heights.clone();
+        self.height_bounds = HeightBounds::new(heights, BOUNDS_CELL_SIZE);
+        self.forget_strokes();
+        self.horizons_stale = true;
+        self.filter_undo = None;
+        self.filter_preview = None;
+        // Wouldn't line up anymore
+        self.reference = None;
+
+        self.aabb = terrain_aabb(world_size, self.max_height);
+        self.num_patches = TerrainParams::num_patches(resolution);
+        self.patch_size = world_size / self.num_patches as f32;
+        for shader in [&self.shader, &self.shadow_map_shader] {
+            shader.set_used();
+            shader.set_i32("num_patches", self.num_patches)?;
+            shader.set_f32("patch_size", self.patch_size)?;
+        }
+        for shader in [&self.shader, &self.clipmap_shader] {
+            shader.set_used();
+            shader.set_f32("terrain_size", world_size)?;
+            shader.set_f32("detail_tiling", world_size / 16.0)?;
+        }
+
+        let aabb_shader = &self.debug.aabb_shader;
+        aabb_shader.set_used();
+        aabb_shader.set_vec3("aabb_min", &self.aabb.min)?;
+        aabb_shader.set_vec3("aabb_max", &self.aabb.max)?;
+
+        Ok(())
+    }
+
+    /// Only reads the CPU copies, whatever hadn't been read back from the GPU yet
+    /// is lost with the context
+    pub fn backup(&self) -> TerrainBackup {
+        let heights = self.full_heights().into_owned();
+        TerrainBackup {
+            params: TerrainParams {
+                resolution: heights.size() as u32,
+                ..self.params()
+            },
+            splat_layers: self.splat_map.layers(),
+            biome_layers: self.biome_map.layers(),
+            tint_channels: self.tint_map.channels(),
+            detail: self.detail.clone(),
+            reference: self.reference.as_ref().map(|r| r.heights().clone()),
+            heights,
+        }
+    }
+
+    /// Uploads a backup into a terrain made from its params
+    pub fn restore(&mut self, backup: &TerrainBackup) -> Result<()> {
+        let maps = (
+            SplatMap::from_layers(&backup.splat_layers),
+            SplatMap::from_layers(&backup.biome_layers),
+            TintMap::from_channels(&backup.tint_channels),
+        );
+        self.replace_maps(&backup.heights, maps, backup.params.world_size)?;
+        self.detail = backup.detail.clone();
+        self.detail_map = DetailMap::new(self.detail.tiles_per_side());
+        self.detail_map.upload_all(&self.detail);
+        if let Some(reference) = &backup.reference {
+            self.set_reference(reference);
+        }
+        Ok(())
+    }
+
+    /// The sun only needs to see the terrain, so fit its frustum around it
+    pub fn sun_view_projection(&self) -> Mat4 {
+        let terrain_size = self.size();
+        let (half_extent, near, far) = self.sun_frustum();
+        let sun_position = self.sun.direction * terrain_size;
+        let sun_proj = Mat4::orthographic_rh_gl(
+            -half_extent,
+            half_extent,
+            -half_extent,
+            half_extent,
+            near,
+            far,
+        );
+        let sun_view = Mat4::look_at_rh(sun_position, Vec3::ZERO, Vec3::Y);
+        sun_proj * sun_view
+    }
+
+    /// Half the width of the shadow map and its near and far planes, in meters
+    fn sun_frustum(&self) -> (f32, f32, f32) {
+        let terrain_size = self.size();
+        (
+            terrain_size * 0.6,
+            1.0,
+            terrain_size * 2.0 + self.max_height,
+        )
+    }
+
+    /// The brush at the cursor this frame
+    pub fn stroke(&self, delta_time: f32) -> Stroke {
+        let terrain_size = self.size();
+        Stroke {
+            cursor: (self.cursor - self.aabb.min.xz()) / terrain_size,
+            brush_size: self.brush.size / terrain_size,
+            delta_time,
+            edges: self.edges,
+        }
+    }
+
+    /// Returns the texels the brush could have touched
+    pub fn shape_terrain(&mut self, stroke: &Stroke, raise: bool) -> DirtyRect {
+        self.end_filter_preview();
+        let size = self.heights.size();
+        let mut rect = stroke.rect(size);
+        for stroke in stroke.wrapped() {
+            self.heightmap
+                .draw_on_heightmap(&stroke, &self.brush, raise);
+            rect = rect.union(&stroke.rect(size));
+        }
+        self.dirty = Some(match self.dirty {
+            Some(dirty) => dirty.union(&rect),
+            None => rect,
+        });
+        rect
+    }
+
+    /// Whether the brush is small enough for `carve_detail`
+    pub fn brush_fits_detail(&self) -> bool {
+        let texel_size = self.size() / self.heights.size() as f32;
+        self.brush.size <= texel_size * detail_map::MAX_BRUSH_TEXELS
+    }
+
+    /// Sculpts into the detail layer instead of the heightmap, for paths and trenches
+    /// finer than a texel. Returns false once there's no room for new tiles.
+    pub fn carve_detail(&mut self, stroke: &Stroke, raise: bool) -> bool {
+        let sign = if raise { 1.0 } else { -1.0 };
+        let brush = &self.brush;
+        let radius = stroke.brush_size / 2.0;
+        let changed = self.detail.carve(stroke.cursor, radius, |uv| {
+            brush.sample(brush_uv(uv, stroke.cursor, stroke.brush_size)) * stroke.delta_time * sign
+        });
+        self.detail_map.upload(&self.detail, &changed);
+        !self.detail.is_full()
+    }
+
+    /// Folds the detail layer into the heightmap. Whatever is finer than a texel
+    /// is averaged away.
+    pub fn bake_detail(&mut self) {
+        if self.detail.is_empty() {
+            return;
+        }
+        self.end_proxy();
+        self.sync_dirty_region();
+        let mut heights = self.heights.clone();
+        self.detail.add_to(&mut heights);
+        self.clear_detail();
+        self.set_heights(heights);
+    }
+
+    pub fn clear_detail(&mut self) {
+        self.detail.clear();
+        self.detail_map.clear();
+    }
+
+    pub fn detail_tiles(&self) -> usize {
+        self.detail.tile_count()
+    }
+
+    /// Brings in the pages of the painted maps closest to `camera` and sends again
+    /// what was painted on the resident ones. Once a frame, before drawing.
+    pub fn stream_pages(&mut self, camera: &Camera) {
+        let changed = [
+            self.splat_map.take_dirty(),
+            self.biome_map.take_dirty(),
+            self.tint_map.take_dirty(),
+        ];
+        let size = self.splat_map.size();
+        if self.virtual_pages.map_size() != size {
+            self.virtual_pages = VirtualPages::new(size, PAINTED_MAPS);
+        }
+        let ground = camera.position.xz();
+        let height = (camera.position.y - self.height_at(ground)).max(0.0) / self.size();
+        let maps: [&dyn PagedMap; PAINTED_MAPS] =
+            [&self.splat_map, &self.biome_map, &self.tint_map];
+        self.virtual_pages.update(
+            &maps,
+            &changed,
+            self.uv_at(ground),
+            height,
+            camera.direction.xz(),
+        );
+    }
+
+    /// Returns the splat map texels the brush could have touched
+    pub fn paint_textures(&mut self, stroke: &Stroke, settings: &PaintSettings) -> DirtyRect {
+        self.splat_map.paint(
+            stroke.cursor,
+            &self.brush,
+            stroke.brush_size,
+            settings,
+            stroke.delta_time,
+        );
+        stroke.rect(self.splat_map.size())
+    }
+
+    /// Same as `paint_textures` for the tint map, erasing brings back white
+    pub fn paint_tint(&mut self, stroke: &Stroke, settings: &TintSettings, erase: bool) {
+        self.tint_map.paint(
+            stroke.cursor,
+            &self.brush,
+            stroke.brush_size,
+            settings,
+            erase,
+            stroke.delta_time,
+        );
+    }
+
+    /// Same as `paint_textures` for the biome map
+    pub fn paint_biomes(&mut self, stroke: &Stroke, settings: &PaintSettings) {
+        self.biome_map.paint(
+            stroke.cursor,
+            &self.brush,
+            stroke.brush_size,
+            settings,
+            stroke.delta_time,
+        );
+    }
+
+    /// Biome weights at the closest texel to a world space point
+    pub fn biome_weights_at(&self, point: Vec2) -> [f32; MAX_BIOMES] {
+        let size = self.biome_map.size();
+        let texel = (point - self.aabb.min.xz()) / self.size() * size as f32;
+        let max = (size - 1) as f32;
+        let texel = texel.clamp(Vec2::ZERO, Vec2::splat(max));
+        self.biome_map
+            .weights_at(texel.x as usize, texel.y as usize)
+    }
+
+    /// Linear RGB of the painted layers with the tint at the closest texel to a world
+    /// space point. The draped photo and the ground texture are left out.
+    pub fn ground_color_at(&self, point: Vec2) -> Vec3 {
+        let size = self.splat_map.size();
+        let texel = (point - self.aabb.min.xz()) / self.size() * size as f32;
+        let max = (size - 1) as f32;
+        let texel = texel.clamp(Vec2::ZERO, Vec2::splat(max));
+        let weights = self
+            .splat_map
+            .weights_at(texel.x as usize, texel.y as usize);
+        let color = self
+            .layers
+            .iter()
+            .zip(weights)
+            .fold(Vec3::ZERO, |color, (layer, weight)| {
+                color + Vec3::from(layer.color) * weight
+            });
+        color * self.tint_map.color_at(self.uv_at(point))
+    }
+
+    /// How much of the brush a stroke puts on a world space point, 0 outside of it
+    pub fn brush_weight(&self, stroke: &Stroke, point: Vec2) -> f32 {
+        let uv = self.uv_at(point);
+        self.brush
+            .sample(brush_uv(uv, stroke.cursor, stroke.brush_size))
+    }
+
+    /// Textures the whole terrain by the rules of the biomes painted on it.
+    /// Returns the splat map texels that changed.
+    pub fn auto_texture(&mut self, biomes: &BiomeSettings) -> DirtyRect {
+        let full_heights = self.full_heights();
+        let size = full_heights.size();
+        let texel_size = self.size() / size as f32;
+        let rect = DirtyRect::full(size);
+        let heights = full_heights.data();
+        let height = |x: usize, y: usize| heights[y * size + x] * self.max_height;
+        let mut weights = self.splat_map.read_region(&rect);
+        for y in 0..size {
+            for x in 0..size {
+                let (left, right) = (x.saturating_sub(1), (x + 1).min(size - 1));
+                let (up, down) = (y.saturating_sub(1), (y + 1).min(size - 1));
+                let dx =
+                    (height(right, y) - height(left, y)) / ((right - left) as f32 * texel_size);
+                let dz = (height(x, down) - height(x, up)) / ((down - up) as f32 * texel_size);
+                let slope = dx.hypot(dz).atan().to_degrees();
+                let texel = &mut weights[y * size + x];
+                *texel = biomes.texel_weights(
+                    &self.biome_map.weights_at(x, y),
+                    height(x, y),
+                    slope,
+                    *texel,
+                );
+            }
+        }
+        self.splat_map.write_region(&rect, &weights);
+        rect
+    }
+
+    pub fn set_reference(&mut self, heights: &HeightField) {
+        self.reference = Some(Reference::new(heights, self.heights.size()));
+    }
+
+    pub fn clear_reference(&mut self) {
+        self.reference = None;
+    }
+
+    /// Shows `values` in [0, 1] over the whole terrain as a heat map
+    pub fn set_overlay(&mut self, values: &HeightField, opacity: f32) {
+        if self.overlay.as_ref().map(HeatOverlay::size) != Some(values.size()) {
+            self.overlay = Some(HeatOverlay::new(values.size()));
+        }
+        if let Some(overlay) = &mut self.overlay {
+            overlay.upload(values);
+            overlay.opacity = opacity;
+        }
+    }
+
+    pub fn clear_overlay(&mut self) {
+        self.overlay = None;
+    }
+
+    /// Replaces the texture tiled over the terrain
+    pub fn set_ground_texture(&mut self, image: &GroundImage) {
+        let (texture, memory) = image.upload();
+        self.texture = texture;
+        self._texture_memory = memory;
+    }
+
+    pub fn set_drape(&mut self, drape: Drape) {
+        self.drape = Some(drape);
+    }
+
+    pub fn clear_drape(&mut self) {
+        self.drape = None;
+    }
+
+    pub fn has_drape(&self) -> bool {
+        self.drape.is_some()
+    }
+
+    pub fn set_drape_opacity(&mut self, opacity: f32) {
+        self.drape_opacity = opacity;
+    }
+
+    pub fn has_reference(&self) -> bool {
+        self.reference.is_some()
+    }
+
+    /// Moves the heights under the brush towards the reference, at most `rate`
+    /// of the way per second. Returns the texels it could have touched.
+    pub fn revert_to_reference(&mut self, stroke: &Stroke, rate: f32) -> Option<DirtyRect> {
+        // Sculpting happens on the GPU, so get everything that's been sculpted first
+        self.sync_dirty_region();
+        let reference = self.reference.as_ref()?;
+
+        let size = self.heights.size();
+        let rect = stroke.rect(size);
+        let target = reference.heights().data();
+        let before = self.heights.read_region_u16(&rect);
+        let heights = self.heights.data_mut();
+        for y in rect.y0..rect.y1 {
+            for x in rect.x0..rect.x1 {
+                let uv = texel_uv(x, y, size);
+                let weight = self
+                    .brush
+                    .sample(brush_uv(uv, stroke.cursor, stroke.brush_size));
+                let t = (weight * rate * stroke.delta_time).min(1.0);
+                if t > 0.0 {
+                    let i = y * size + x;
+                    heights[i] += (target[i] - heights[i]) * t;
+                }
+            }
+        }
+
+        self.upload_heights(&rect, &before);
+        Some(rect)
+    }
+
+    /// Averages every texel under the brush with the heights before and after it along
+    /// `direction`, a unit vector in uv
+    pub fn smooth_along(
+        &mut self,
+        stroke: &Stroke,
+        direction: Vec2,
+        settings: &AlongStrokeSmoothing,
+    ) -> DirtyRect {
+        self.sync_dirty_region();
+        self.end_filter_preview();
+        let size = self.heights.size();
+        let rect = stroke.rect(size);
+        let step = direction * settings.reach / self.size() / ALONG_STROKE_TAPS as f32;
+        let rate = settings.strength * stroke.delta_time;
+
+        let mut smoothed = Vec::with_capacity(rect.width() * rect.height());
+        for y in rect.y0..rect.y1 {
+            for x in rect.x0..rect.x1 {
+                let uv = texel_uv(x, y, size);
+                let weight = self
+                    .brush
+                    .sample(brush_uv(uv, stroke.cursor, stroke.brush_size));
+                let t = (weight * rate).min(1.0);
+                let height = self.heights.data()[y * size + x];
+                if t <= 0.0 {
+                    smoothed.push(height);
+                    continue;
+                }
+                let taps = ALONG_STROKE_TAPS as i32;
+                let total: f32 = (-taps..=taps)
+                    .map(|i| {
+                        let p = uv + step * i as f32;
+                        self.heights.sample_with(p.x, p.y, stroke.edges)
+                    })
+                    .sum();
+                let average = total / (2 * taps + 1) as f32;
+                smoothed.push(height + (average - height) * t);
+            }
+        }
+        let before = self.heights.read_region_u16(&rect);
+        let heights = self.heights.data_mut();
+        for (y, row) in (rect.y0..rect.y1).zip(smoothed.chunks_exact(rect.width())) {
+            heights[y * size + rect.x0..y * size + rect.x1].copy_from_slice(row);
+        }
+        self.upload_heights(&rect, &before);
+        rect
+    }
+
+    /// Lets droplets run from under the brush, eroding only where the brush is.
+    /// The same `seed` releases the same droplets.
+    pub fn erode_under_brush(
+        &mut self,
+        stroke: &Stroke,
+        settings: &DropletSettings,
+        splat: &ErosionSplat,
+        seed: u32,
+    ) -> DirtyRect {
+        self.sync_dirty_region();
+        self.end_filter_preview();
+        let size = self.heights.size();
+        let rect = stroke.rect(size);
+        let mut mask = Vec::with_capacity(rect.width() * rect.height());
+        for y in rect.y0..rect.y1 {
+            for x in rect.x0..rect.x1 {
+                let uv = texel_uv(x, y, size);
+                mask.push(
+                    self.brush
+                        .sample(brush_uv(uv, stroke.cursor, stroke.brush_size)),
+                );
+            }
+        }
+        let mask_at = |x: usize, y: usize| {
+            if x < rect.x0 || y < rect.y0 || x >= rect.x1 || y >= rect.y1 {
+                return 0.0;
+            }
+            mask[(y - rect.y0) * rect.width() + (x - rect.x0)]
+        };
+
+        // Candidates all over the brush square, kept as often as the brush is strong
+        let candidates = (settings.rate * stroke.delta_time).ceil() as i32;
+        let starts: Vec<(f32, f32)> = (0..candidates)
+            .filter_map(|i| {
+                let x = rect.x0 as f32 + hash(seed, i, 0) * rect.width() as f32;
+                let y = rect.y0 as f32 + hash(seed, i, 1) * rect.height() as f32;
+                let strength = mask_at(x as usize, y as usize);
+                if hash(seed, i, 2) < strength {
+                    Some((x, y))
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        let texel_size = self.size() / size as f32;
+        let height_scale = self.max_height / texel_size;
+        let before: Vec<f32> = (rect.y0..rect.y1)
+            .flat_map(|y| &self.heights.data()[y * size + rect.x0..y * size + rect.x1])
+            .copied()
+            .collect();
+        let before_pixels = self.heights.read_region_u16(&rect);
+        droplet_erosion(&mut self.heights, &starts, settings, height_scale, mask_at);
+        self.paint_erosion(&before, &rect, splat);
+        self.upload_heights(&rect, &before_pixels);
+        rect
+    }
+
+    /// Flattens the ground under a footprint to its lowest point, blending into the
+    /// surrounding terrain over `feather` meters
+    pub fn fit_to_footprint(&mut self, footprint: &Footprint, settings: &FitSettings) -> DirtyRect {
+        self.sync_dirty_region();
         let terrain_size = self.size();
-        let cursor = (self.cursor - self.aabb.min.xz()) / terrain_size;
-        self.heightmap
-            .draw_on_heightmap(cursor, &self.brush, terrain_size, delta_time, raise);
+        let min = self.aabb.min.xz();
+        let (center, extent) = footprint.bounds();
+        let center = (center - min) / terrain_size;
+        let extent = (extent + 2.0 * settings.feather) / terrain_size;
+
+        let size = self.heights.size();
+        let rect = DirtyRect::around((center.x, center.y), extent, size);
+        let target = ((footprint.bottom - settings.depth) / self.max_height).clamp(0.0, 1.0);
+        let texel_size = terrain_size / size as f32;
+        let before = self.heights.read_region_u16(&rect);
+        let heights = self.heights.data_mut();
+        for y in rect.y0..rect.y1 {
+            for x in rect.x0..rect.x1 {
+                let point = min + (Vec2::new(x as f32, y as f32) + Vec2::splat(0.5)) * texel_size;
+                let distance = footprint.distance(point);
+                let t = if distance == 0.0 {
+                    1.0
+                } else if settings.feather > 0.0 {
+                    1.0 - smoothstep(distance / settings.feather)
+                } else {
+                    0.0
+                };
+                if t > 0.0 {
+                    let i = y * size + x;
+                    heights[i] += (target - heights[i]) * t;
+                }
+            }
+        }
+
+        self.upload_heights(&rect, &before);
+        rect
+    }
+
+    /// Replaces all the heights, resampled to the terrain's resolution
+    pub fn import_heights(&mut self, heights: &HeightField) {
+        self.end_proxy();
+        self.clear_detail();
+        self.set_heights(heights.resampled(self.heights.size()));
+    }
+
+    /// Replaces the layer weights, resampled to the splat map's resolution
+    pub fn import_splat(&mut self, layers: &[HeightField]) {
+        let size = self.splat_map.size();
+        let layers: Vec<HeightField> = layers.iter().map(|l| l.resampled(size)).collect();
+        self.splat_map = SplatMap::from_layers(&layers);
+    }
+
+    /// Paints `layer` wherever `coverage` is above 0, as strongly as it is
+    pub fn cover_with_layer(&mut self, layer: usize, coverage: &HeightField) {
+        self.splat_map
+            .cover(layer, &coverage.resampled(self.splat_map.size()));
+    }
+
+    /// Mixes `amount` of the reference into the whole terrain
+    pub fn blend_with_reference(&mut self, amount: f32) {
+        self.sync_dirty_region();
+        let reference = match &self.reference {
+            Some(reference) => reference,
+            None => return,
+        };
+        let data = self
+            .heights
+            .data()
+            .iter()
+            .zip(reference.heights().data())
+            .map(|(&h, &r)| h + (r - h) * amount)
+            .collect();
+        let heights = HeightField::from_data(self.heights.size(), data);
+        self.set_heights(heights);
+    }
+
+    /// Texels per side of the heights, the splat map has the same resolution unless
+    /// they're a proxy
+    pub fn resolution(&self) -> usize {
+        self.heights.size()
     }
 
-    /// Currently only intersects with the bottom plane of the AABB
+    /// Heightmap texels as stored on the GPU, up to date with any sculpting in progress
+    pub fn read_heights(&self, rect: &DirtyRect) -> Vec<u16> {
+        self.heightmap.read_region(rect)
+    }
+
+    pub fn write_heights(&mut self, rect: &DirtyRect, pixels: &[u16]) {
+        // Older than these, they'd overwrite them on the CPU
+        self.finish_readbacks();
+        self.end_filter_preview();
+        self.heightmap.write_region(rect, pixels);
+        self.heights.write_region_u16(rect, pixels);
+        self.height_bounds.update(&self.heights, rect);
+        self.horizons_stale = true;
+        self.filter_undo = None;
+        // Undoing a stroke would overwrite them
+        self.history.clear();
+    }
+
+    pub fn read_splat(&self, rect: &DirtyRect) -> Vec<[f32; NUM_LAYERS]> {
+        self.splat_map.read_region(rect)
+    }
+
+    pub fn write_splat(&mut self, rect: &DirtyRect, weights: &[[f32; NUM_LAYERS]]) {
+        self.splat_map.write_region(rect, weights);
+    }
+
+    /// Where the ray first meets the surface as it's drawn, so the brush sits on
+    /// what's visible at any tessellation level
     pub fn intersect_with_ray(&self, ray: &Ray) -> Option<Vec3> {
-        let hit = ray.hits_aabb(&self.aabb)?;
-        let point = ray.get_point_at(hit.t_max);
+        const MIN_THICKNESS: f32 = 0.01;
+        let (min_height, max_height) = self.height_range();
+        let mut aabb = self.aabb;
+        aabb.min.y = min_height;
+        aabb.max.y = max_height.max(min_height + MIN_THICKNESS);
+        ray.hits_height(&aabb, self.quad_size(), |point| {
+            self.surface_height_at(point)
+        })
+    }
 
-        const EPSILON: f32 = 0.001;
-        if (point.y - self.aabb.min.y) > EPSILON {
-            None // not hitting the bottom plane
-        } else {
-            Some(point)
+    /// Side of the quads the surface is drawn with. The clipmap only has them
+    /// around the camera, where the cursor usually is.
+    fn quad_size(&self) -> f32 {
+        if self.mesh == TerrainMesh::Clipmap {
+            return self.size() / self.params().resolution as f32;
         }
+        let quads = if self.tessellated {
+            // With fractional odd spacing the level is rounded up to an odd number of
+            // segments. At an odd level they're all the same length, otherwise two are
+            // shorter, which is close enough here.
+            let level = self.tess_level.ceil().max(1.0) as usize;
+            level + (level + 1) % 2
+        } else {
+            GRID_SIZE
+        };
+        self.patch_size / quads as f32
+    }
+
+    /// The height as drawn: sampled at the quad corners like the shaders do and
+    /// linear over the two triangles in between. Between the corners it can be well
+    /// off `height_at` when the tessellation level is low.
+    pub fn surface_height_at(&self, point: Vec2) -> f32 {
+        let quad_size = self.quad_size();
+        let last = (self.size() / quad_size).round() - 1.0;
+        let quad = (point - self.aabb.min.xz()) / quad_size;
+        let corner = quad.floor().clamp(Vec2::ZERO, Vec2::splat(last.max(0.0)));
+        let t = (quad - corner).clamp(Vec2::ZERO, Vec2::ONE);
+        let height = |dx: f32, dy: f32| {
+            let corner = self.aabb.min.xz() + (corner + Vec2::new(dx, dy)) * quad_size;
+            self.height_at(corner)
+        };
+        // Split along the same diagonal as in terrain_grid.vert.glsl
+        let corners = [
+            height(0.0, 0.0),
+            height(1.0, 0.0),
+            height(0.0, 1.0),
+            height(1.0, 1.0),
+        ];
+        quad_height(t, corners)
     }
 
-    pub fn move_cursor(&mut self, ray: &Ray) -> bool {
+    pub fn move_cursor(&mut self, ray: &Ray, snap: Option<SnapTarget>) -> bool {
         if let Some(point) = self.intersect_with_ray(ray) {
             self.cursor = Vec2::new(point.x, point.z).clamp(self.aabb.min.xz(), self.aabb.max.xz());
+            if let Some(target) = snap {
+                self.cursor = self.snap(self.cursor, target);
+            }
             true
         } else {
             self.hide_cursor();
@@ -547,16 +2493,229 @@ impl Terrain {
         }
     }
 
-    pub fn hide_cursor(&mut self) {
-        self.cursor = vec2_infinity();
+    /// The closest point of `target` to a world space point
+    pub fn snap(&self, point: Vec2, target: SnapTarget) -> Vec2 {
+        let min = self.aabb.min.xz();
+        let local = point - min;
+        let snapped = match target {
+            SnapTarget::Vertices => {
+                let texel_size = self.size() / self.heights.size() as f32;
+                ((local / texel_size - Vec2::splat(0.5)).round() + Vec2::splat(0.5)) * texel_size
+            }
+            SnapTarget::Chunks => {
+                // Only the closer edge, so the cursor can slide along it
+                let cell = local / self.patch_size;
+                let edge = cell.round();
+                if (cell.x - edge.x).abs() < (cell.y - edge.y).abs() {
+                    Vec2::new(edge.x, cell.y) * self.patch_size
+                } else {
+                    Vec2::new(cell.x, edge.y) * self.patch_size
+                }
+            }
+        };
+        (min + snapped).clamp(min, self.aabb.max.xz())
     }
-}
 
-impl Drop for Terrain {
-    fn drop(&mut self) {
-        unsafe {
-            gl::DeleteVertexArrays(1, &self.vao);
-            gl::DeleteTextures(1, &self.texture);
+    /// The square of normalised heights `size` meters across centered on a world space point.
+    /// Whatever falls outside the terrain repeats the edge.
+    /// FNV-1a of the heights, to tell whether two sessions ended up with the same terrain
+    pub fn checksum(&mut self) -> u64 {
+        self.sync_dirty_region();
+        let mut hash = 0xcbf2_9ce4_8422_2325u64;
+        for height in self.heights.data() {
+            for byte in height.to_bits().to_le_bytes() {
+                hash ^= byte as u64;
+                hash = hash.wrapping_mul(0x0100_0000_01b3);
+            }
+        }
+        hash
+    }
+
+    pub fn heights_around(&self, center: Vec2, size: f32) -> HeightField {
+        let (x0, y0, texels) = self.texels_around(center, size, self.heights.size());
+        self.heights.region(x0, y0, texels, EdgeFill::Clamp)
+    }
+
+    /// Same square as `heights_around` from the splat map, row by row
+    pub fn splat_around(&self, center: Vec2, size: f32) -> (usize, Vec<[f32; NUM_LAYERS]>) {
+        let (x0, y0, texels) = self.texels_around(center, size, self.splat_map.size());
+        (texels, self.splat_map.weights_around(x0, y0, texels))
+    }
+
+    /// First texel and texels per side of a square `size` meters across, in a map with
+    /// `resolution` texels per side
+    fn texels_around(&self, center: Vec2, size: f32, resolution: usize) -> (isize, isize, usize) {
+        let texel_size = self.size() / resolution as f32;
+        let texels = (size / texel_size).round().max(1.0);
+        let corner = (center - self.aabb.min.xz()) / texel_size - Vec2::splat(texels / 2.0);
+        (
+            corner.x.round() as isize,
+            corner.y.round() as isize,
+            texels as usize,
+        )
+    }
+
+    /// Blends the square `size` meters across centered on `center` towards `height`, which
+    /// gets a position inside the square in [0, 1] and returns meters. The outer `feather`
+    /// fraction of the square fades into the terrain that's already there.
+    pub fn paste_heights(
+        &mut self,
+        center: Vec2,
+        size: f32,
+        feather: f32,
+        height: impl Fn(Vec2) -> f32,
+    ) -> DirtyRect {
+        self.sync_dirty_region();
+        let resolution = self.heights.size();
+        let rect = self.rect_around(center, size, resolution);
+        let max_height = self.max_height;
+        let texel_uv = self.square_uv(center, size, resolution);
+        let before = self.heights.read_region_u16(&rect);
+        let heights = self.heights.data_mut();
+        for y in rect.y0..rect.y1 {
+            for x in rect.x0..rect.x1 {
+                let uv = texel_uv(x, y);
+                let t = paste_weight(uv, feather);
+                if t > 0.0 {
+                    let i = y * resolution + x;
+                    let target = (height(uv) / max_height).clamp(0.0, 1.0);
+                    heights[i] += (target - heights[i]) * t;
+                }
+            }
+        }
+        self.upload_heights(&rect, &before);
+        rect
+    }
+
+    /// Same as `paste_heights` for the splat map
+    pub fn paste_splat(
+        &mut self,
+        center: Vec2,
+        size: f32,
+        feather: f32,
+        weights: impl Fn(Vec2) -> [f32; NUM_LAYERS],
+    ) -> DirtyRect {
+        let resolution = self.splat_map.size();
+        let rect = self.rect_around(center, size, resolution);
+        let mut texels = self.splat_map.read_region(&rect);
+        let texel_uv = self.square_uv(center, size, resolution);
+        let rows = (rect.y0..rect.y1).flat_map(|y| (rect.x0..rect.x1).map(move |x| (x, y)));
+        for ((x, y), texel) in rows.zip(&mut texels) {
+            let uv = texel_uv(x, y);
+            let t = paste_weight(uv, feather);
+            if t > 0.0 {
+                for (w, target) in texel.iter_mut().zip(weights(uv)) {
+                    *w += (target - *w) * t;
+                }
+            }
+        }
+        self.splat_map.write_region(&rect, &texels);
+        rect
+    }
+
+    /// Texels of a map with `resolution` texels per side
+    fn rect_around(&self, center: Vec2, size: f32, resolution: usize) -> DirtyRect {
+        let uv = (center - self.aabb.min.xz()) / self.size();
+        DirtyRect::around((uv.x, uv.y), size / self.size(), resolution)
+    }
+
+    /// Maps texels to positions inside a world space square, in [0, 1] when inside
+    fn square_uv(
+        &self,
+        center: Vec2,
+        size: f32,
+        resolution: usize,
+    ) -> impl Fn(usize, usize) -> Vec2 {
+        let texel_size = self.size() / resolution as f32;
+        let corner = center - Vec2::splat(size / 2.0) - self.aabb.min.xz();
+        move |x, y| {
+            let point = (Vec2::new(x as f32, y as f32) + Vec2::splat(0.5)) * texel_size;
+            (point - corner) / size
+        }
+    }
+
+    /// 0 to 1 across the terrain for world space points on it
+    pub fn uv_at(&self, point: Vec2) -> Vec2 {
+        (point - self.aabb.min.xz()) / self.size()
+    }
+
+    /// Terrain height in meters under a world space point, bilinearly filtered
+    pub fn height_at(&self, point: Vec2) -> f32 {
+        let size = self.heights.size();
+        let uv = (point - self.aabb.min.xz()) / self.size();
+        let texel = uv * size as f32 - Vec2::splat(0.5);
+        let max = (size - 1) as f32;
+        let texel = texel.clamp(Vec2::ZERO, Vec2::splat(max));
+        let (x0, y0) = (texel.x.floor() as usize, texel.y.floor() as usize);
+        let (x1, y1) = ((x0 + 1).min(size - 1), (y0 + 1).min(size - 1));
+        let (tx, ty) = (texel.x.fract(), texel.y.fract());
+        let h = |x: usize, y: usize| self.heights.data()[y * size + x];
+        let top = h(x0, y0) * (1.0 - tx) + h(x1, y0) * tx;
+        let bottom = h(x0, y1) * (1.0 - tx) + h(x1, y1) * tx;
+        (top * (1.0 - ty) + bottom * ty + self.detail.offset_at(uv)) * self.max_height
+    }
+
+    fn surface(&self) -> Surface {
+        Surface {
+            field: &self.heights,
+            world_size: self.size(),
+            max_height: self.max_height,
+        }
+    }
+
+    /// Props on every face steeper than the settings allow, in world space
+    pub fn scatter_on_slopes(&self, settings: &SlopeScatter) -> Vec<Placement> {
+        let mut placements = scatter::on_slopes(&self.surface(), self.seed, settings);
+        for placement in &mut placements {
+            placement.position += self.aabb.min;
+        }
+        placements
+    }
+
+    /// The shortest route between two world space points that's nowhere too steep
+    /// to walk, in world space
+    pub fn find_path(&self, start: Vec2, goal: Vec2, settings: &PathSettings) -> Option<WalkPath> {
+        let corner = self.aabb.min.xz();
+        let mut path = path::find_path(&self.surface(), start - corner, goal - corner, settings)?;
+        for point in &mut path.points {
+            *point += self.aabb.min;
         }
+        Some(path)
+    }
+
+    /// 1 where the ground can be seen from above a world space point, 0 where it can't
+    pub fn viewshed(&self, observer: Vec2, settings: &ViewshedSettings) -> HeightField {
+        viewshed::viewshed(&self.surface(), observer - self.aabb.min.xz(), settings)
+    }
+
+    /// Props of the jittered grid cells under a world space square, in world space.
+    /// `seed` is mixed with the terrain seed so that every kind of prop gets its own layout.
+    pub fn scatter_in_square(
+        &self,
+        center: Vec2,
+        size: f32,
+        seed: u32,
+        spacing: f32,
+        scale: (f32, f32),
+    ) -> Vec<((i32, i32), Placement)> {
+        let surface = self.surface();
+        let seed = self.seed.wrapping_add(seed);
+        let local = center - self.aabb.min.xz();
+        let first = ((local - Vec2::splat(size / 2.0)) / spacing).floor();
+        let last = ((local + Vec2::splat(size / 2.0)) / spacing).floor();
+        let mut placements = vec![];
+        for y in first.y as i32..=last.y as i32 {
+            for x in first.x as i32..=last.x as i32 {
+                if let Some(mut placement) = surface.in_cell(seed, spacing, (x, y), scale) {
+                    placement.position += self.aabb.min;
+                    placements.push(((x, y), placement));
+                }
+            }
+        }
+        placements
+    }
+
+    pub fn hide_cursor(&mut self) {
+        self.cursor = vec2_infinity();
     }
 }
@@ -36,6 +36,8 @@ pub fn unit_to_gl_const(unit: i32) -> GLenum {
         13 => gl::TEXTURE13,
         14 => gl::TEXTURE14,
         15 => gl::TEXTURE15,
+        16 => gl::TEXTURE16,
+        17 => gl::TEXTURE17,
         _ => panic!("Unsupported texture unit"),
     }
 }
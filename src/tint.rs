@@ -0,0 +1,187 @@
+//! A color painted over the terrain by hand and multiplied into it, for touches the
+//! splat layers can't give: darker paths, mossy patches, a rusty streak down a cliff.
+//! White leaves the terrain as it is.
+
+use glam::{Vec2, Vec3};
+use serde::{Deserialize, Serialize};
+
+use crate::heightfield::{DirtyRect, EdgeFill, HeightField};
+use crate::opengl::objects::Texture;
+use crate::terrain::Brush;
+use crate::utils::{brush_uv, texel_uv};
+use crate::virtual_texture::{MapTexture, PagedMap};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TintSettings {
+    /// Linear RGB the terrain is multiplied by
+    pub color: [f32; 3],
+    /// How fast the brush builds up, per second
+    pub strength: f32,
+}
+
+impl Default for TintSettings {
+    fn default() -> Self {
+        TintSettings {
+            color: [0.6, 0.55, 0.5],
+            strength: 2.0,
+        }
+    }
+}
+
+/// Kept on the CPU like the splat map, so it can be resampled with the terrain
+pub struct TintMap {
+    texture: MapTexture,
+    size: usize,
+    colors: Vec<Vec3>,
+}
+
+impl TintMap {
+    /// White all over
+    pub fn new(size: usize) -> Self {
+        let mut tint_map = TintMap {
+            texture: MapTexture::new(size, "Tint map"),
+            size,
+            colors: vec![Vec3::ONE; size * size],
+        };
+        tint_map.upload_region(0, 0, size, size);
+        tint_map
+    }
+
+    /// The whole map, or its average if it's too big for that
+    pub fn texture(&self) -> &Texture {
+        &self.texture.texture
+    }
+
+    /// Texels changed since the last call, for the virtual pages
+    pub fn take_dirty(&mut self) -> Option<DirtyRect> {
+        self.texture.take_dirty()
+    }
+
+    pub fn resampled(&self, new_size: usize) -> TintMap {
+        let channels: Vec<HeightField> = self
+            .channels()
+            .iter()
+            .map(|channel| channel.resampled(new_size))
+            .collect();
+        TintMap::from_channels(&channels)
+    }
+
+    /// Same as `HeightField::region`, the new texels take the color of the closest edge
+    pub fn region(&self, x0: isize, y0: isize, size: usize) -> TintMap {
+        let channels: Vec<HeightField> = self
+            .channels()
+            .iter()
+            .map(|channel| channel.region(x0, y0, size, EdgeFill::Clamp))
+            .collect();
+        TintMap::from_channels(&channels)
+    }
+
+    pub fn channels(&self) -> Vec<HeightField> {
+        (0..3)
+            .map(|channel| {
+                let data = self.colors.iter().map(|color| color[channel]).collect();
+                HeightField::from_data(self.size, data)
+            })
+            .collect()
+    }
+
+    pub fn from_channels(channels: &[HeightField]) -> TintMap {
+        let size = channels[0].size();
+        let mut tint_map = TintMap::new(size);
+        for (i, color) in tint_map.colors.iter_mut().enumerate() {
+            let rgb = Vec3::new(
+                channels[0].data()[i],
+                channels[1].data()[i],
+                channels[2].data()[i],
+            );
+            *color = rgb.clamp(Vec3::ZERO, Vec3::ONE);
+        }
+        tint_map.upload_region(0, 0, size, size);
+        tint_map
+    }
+
+    /// Every texel times its factor, which has to be the same size as the map
+    pub fn multiply(&mut self, factors: &[Vec3]) {
+        assert_eq!(factors.len(), self.colors.len());
+        for (color, factor) in self.colors.iter_mut().zip(factors) {
+            *color = (*color * *factor).clamp(Vec3::ZERO, Vec3::ONE);
+        }
+        self.upload_region(0, 0, self.size, self.size);
+    }
+
+    /// `cursor` and `brush_size` are normalised to the terrain size.
+    /// Erasing fades back to white.
+    /// At the closest texel to `uv`
+    pub fn color_at(&self, uv: Vec2) -> Vec3 {
+        let max = (self.size - 1) as f32;
+        let texel = (uv * self.size as f32).clamp(Vec2::ZERO, Vec2::splat(max));
+        self.colors[texel.y as usize * self.size + texel.x as usize]
+    }
+
+    pub fn paint(
+        &mut self,
+        cursor: Vec2,
+        brush: &Brush,
+        brush_size: f32,
+        settings: &TintSettings,
+        erase: bool,
+        delta_time: f32,
+    ) {
+        let size = self.size as f32;
+        let half_extent = brush_size / 2.0;
+        let to_texel = |uv: f32| (uv * size).floor().clamp(0.0, size - 1.0) as usize;
+        let (x_min, x_max) = (
+            to_texel(cursor.x - half_extent),
+            to_texel(cursor.x + half_extent),
+        );
+        let (y_min, y_max) = (
+            to_texel(cursor.y - half_extent),
+            to_texel(cursor.y + half_extent),
+        );
+
+        let target = if erase {
+            Vec3::ONE
+        } else {
+            Vec3::from(settings.color)
+        };
+        let rate = settings.strength * delta_time;
+
+        for y in y_min..=y_max {
+            for x in x_min..=x_max {
+                let uv = texel_uv(x, y, self.size);
+                let w = brush.sample(brush_uv(uv, cursor, brush_size));
+                if w <= 0.0 {
+                    continue;
+                }
+                let color = &mut self.colors[y * self.size + x];
+                *color = color.lerp(target, (w * rate).min(1.0));
+            }
+        }
+
+        self.upload_region(x_min, y_min, x_max + 1, y_max + 1);
+    }
+
+    /// Sends texels in [x0, x1) x [y0, y1) to the GPU
+    fn upload_region(&mut self, x0: usize, y0: usize, x1: usize, y1: usize) {
+        let rect = self.texture.covering(&DirtyRect { x0, y0, x1, y1 });
+        let pixels = self.pixels(&rect);
+        self.texture.upload(&rect, &pixels);
+    }
+}
+
+impl PagedMap for TintMap {
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    fn pixels(&self, rect: &DirtyRect) -> Vec<u8> {
+        let mut pixels = Vec::with_capacity(rect.width() * rect.height() * 4);
+        for y in rect.y0..rect.y1 {
+            for color in &self.colors[y * self.size + rect.x0..y * self.size + rect.x1] {
+                pixels.extend(color.to_array().iter().map(|&c| (c * 255.0).round() as u8));
+                pixels.push(255);
+            }
+        }
+        pixels
+    }
+}
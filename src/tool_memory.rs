@@ -0,0 +1,63 @@
+//! What every tool was last used with. Each tool keeps its own brush size instead of
+//! sharing one, and the painting settings and the selected tool are saved with the
+//! config, so the next session starts where this one stopped.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::erosion::DropletSettings;
+use crate::splat::PaintSettings;
+use crate::terrain::AlongStrokeSmoothing;
+use crate::tint::TintSettings;
+use crate::{EditorState, TerrainTool};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct ToolMemory {
+    pub tool: Option<TerrainTool>,
+    /// Only tools that have been used, the others start with the terrain's default brush
+    brush_sizes: BTreeMap<TerrainTool, f32>,
+    paint: Option<PaintSettings>,
+    biome_paint: Option<PaintSettings>,
+    tint: Option<TintSettings>,
+    along_stroke: Option<AlongStrokeSmoothing>,
+    erosion_brush: Option<DropletSettings>,
+}
+
+impl ToolMemory {
+    pub fn brush_size(&self, tool: TerrainTool) -> Option<f32> {
+        self.brush_sizes.get(&tool).copied()
+    }
+
+    pub fn set_brush_size(&mut self, tool: TerrainTool, size: f32) {
+        self.brush_sizes.insert(tool, size);
+    }
+
+    /// Settings that live in the editor state, anything never saved keeps its default
+    pub fn restore(&self, editor_state: &mut EditorState) {
+        if let Some(paint) = &self.paint {
+            editor_state.paint = paint.clone();
+        }
+        if let Some(paint) = &self.biome_paint {
+            editor_state.biomes.paint = paint.clone();
+        }
+        if let Some(tint) = &self.tint {
+            editor_state.tint = tint.clone();
+        }
+        if let Some(along_stroke) = self.along_stroke {
+            editor_state.along_stroke = along_stroke;
+        }
+        if let Some(erosion_brush) = self.erosion_brush {
+            editor_state.erosion_brush = erosion_brush;
+        }
+    }
+
+    pub fn store(&mut self, editor_state: &EditorState) {
+        self.paint = Some(editor_state.paint.clone());
+        self.biome_paint = Some(editor_state.biomes.paint.clone());
+        self.tint = Some(editor_state.tint.clone());
+        self.along_stroke = Some(editor_state.along_stroke);
+        self.erosion_brush = Some(editor_state.erosion_brush);
+    }
+}
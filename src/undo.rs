@@ -0,0 +1,430 @@
+//! Undo for brush strokes. What a stroke changes is captured on the main thread as
+//! the difference to the heights before it, into one of two buffers that take turns:
+//! while one fills up, the other is with a worker thread which packs the differences
+//! and keeps them. Capturing costs the frame a subtraction per texel the stroke
+//! touched, however big the region and however many strokes are kept.
+
+use std::collections::VecDeque;
+use std::mem;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::heightfield::DirtyRect;
+
+/// Strokes that can be undone, older ones are forgotten
+pub const MAX_STROKES: usize = 64;
+
+/// Texels a capture buffer takes before it's handed over in the middle of a stroke
+const BUFFER_TEXELS: usize = 1 << 20;
+
+/// Regions and the differences in them, in the order they were captured
+type Deltas = Vec<(DirtyRect, Vec<u16>)>;
+
+/// Captured differences, one region after another
+#[derive(Default)]
+struct Chunk {
+    rects: Vec<DirtyRect>,
+    /// Height before minus height after for every texel of each region, wrapping
+    texels: Vec<u16>,
+}
+
+enum Job {
+    Capture(Chunk),
+    EndStroke,
+    /// Takes the last stroke
+    Undo(Sender<Deltas>),
+    Clear,
+}
+
+pub struct StrokeHistory {
+    jobs: Option<Sender<Job>>,
+    /// Buffers the worker is done with
+    spare: Receiver<Chunk>,
+    filling: Chunk,
+    /// Captured something since the last stroke ended
+    open: bool,
+    /// The tag of every stroke the worker keeps or is about to, oldest first
+    tags: VecDeque<usize>,
+    /// Spent capturing since the last `take_capture_time`
+    capture_time: Duration,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl StrokeHistory {
+    pub fn new() -> Self {
+        let (job_sender, job_receiver) = channel::<Job>();
+        let (spare_sender, spare_receiver) = channel();
+        // The second buffer, the first one is filling
+        spare_sender.send(Chunk::default()).ok();
+        let thread = std::thread::Builder::new()
+            .name("undo".to_owned())
+            .spawn(move || keep_strokes(job_receiver, spare_sender))
+            .expect("Couldn't start the undo thread");
+
+        StrokeHistory {
+            jobs: Some(job_sender),
+            spare: spare_receiver,
+            filling: Chunk::default(),
+            open: false,
+            tags: VecDeque::new(),
+            capture_time: Duration::ZERO,
+            thread: Some(thread),
+        }
+    }
+
+    /// Remembers what changed in `rect`, the texels as they were and as they are now
+    pub fn capture(&mut self, rect: &DirtyRect, before: &[u16], after: &[u16]) {
+        let start = Instant::now();
+        debug_assert_eq!(before.len(), rect.width() * rect.height());
+        debug_assert_eq!(before.len(), after.len());
+        if !self.filling.rects.is_empty()
+            && self.filling.texels.len() + before.len() > BUFFER_TEXELS
+        {
+            self.hand_over();
+        }
+        self.filling.rects.push(*rect);
+        self.filling.texels.extend(
+            before
+                .iter()
+                .zip(after)
+                .map(|(&before, &after)| before.wrapping_sub(after)),
+        );
+        self.open = true;
+        self.capture_time += start.elapsed();
+    }
+
+    /// Everything captured since the last call is one stroke, `tag` comes back with it
+    /// from `undo`. Nothing is kept if nothing was captured.
+    pub fn end_stroke(&mut self, tag: usize) {
+        if !self.open {
+            return;
+        }
+        self.open = false;
+        self.hand_over();
+        self.send(Job::EndStroke);
+        self.tags.push_back(tag);
+        if self.tags.len() > MAX_STROKES {
+            self.tags.pop_front();
+        }
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.tags.is_empty()
+    }
+
+    /// The last stroke's tag and the differences it made, which are to be added to the
+    /// heights in reverse order. Waits for the worker to get to it.
+    pub fn undo(&mut self) -> Option<(usize, Deltas)> {
+        debug_assert!(!self.open, "Undoing a stroke that's still going");
+        let tag = self.tags.pop_back()?;
+        let (reply_sender, reply_receiver) = channel();
+        self.send(Job::Undo(reply_sender));
+        let deltas = reply_receiver.recv().unwrap_or_default();
+        Some((tag, deltas))
+    }
+
+    /// Forgets every stroke, e.g. when the heights have been changed some other way
+    pub fn clear(&mut self) {
+        self.filling.rects.clear();
+        self.filling.texels.clear();
+        self.open = false;
+        self.tags.clear();
+        self.send(Job::Clear);
+    }
+
+    /// How long capturing took the main thread since the last call
+    pub fn take_capture_time(&mut self) -> Duration {
+        mem::take(&mut self.capture_time)
+    }
+
+    /// Sends the filling buffer to the worker and takes the one it's done with. If it
+    /// isn't done yet a new buffer is made rather than waiting for it.
+    fn hand_over(&mut self) {
+        if self.filling.rects.is_empty() {
+            return;
+        }
+        let next = self.spare.try_recv().unwrap_or_default();
+        let chunk = mem::replace(&mut self.filling, next);
+        self.send(Job::Capture(chunk));
+    }
+
+    fn send(&self, job: Job) {
+        if let Some(jobs) = &self.jobs {
+            jobs.send(job).expect("Undo thread has died");
+        }
+    }
+}
+
+impl Drop for StrokeHistory {
+    fn drop(&mut self) {
+        self.jobs.take();
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+/// A region of a stroke with its differences packed
+struct Packed {
+    rect: DirtyRect,
+    bytes: Vec<u8>,
+}
+
+/// The worker's side. Buffers go back to `spare` emptied once they're packed.
+fn keep_strokes(jobs: Receiver<Job>, spare: Sender<Chunk>) {
+    let mut strokes: VecDeque<Vec<Packed>> = VecDeque::new();
+    let mut current: Vec<Packed> = vec![];
+    for job in jobs {
+        match job {
+            Job::Capture(mut chunk) => {
+                let mut offset = 0;
+                for rect in &chunk.rects {
+                    let len = rect.width() * rect.height();
+                    let bytes = pack(&chunk.texels[offset..offset + len]);
+                    current.push(Packed { rect: *rect, bytes });
+                    offset += len;
+                }
+                chunk.rects.clear();
+                chunk.texels.clear();
+                // Gone if the editor is closing
+                spare.send(chunk).ok();
+            }
+            Job::EndStroke => {
+                strokes.push_back(mem::take(&mut current));
+                if strokes.len() > MAX_STROKES {
+                    strokes.pop_front();
+                }
+            }
+            Job::Undo(reply) => {
+                let stroke = strokes.pop_back().unwrap_or_default();
+                let deltas = stroke
+                    .iter()
+                    .map(|packed| {
+                        let len = packed.rect.width() * packed.rect.height();
+                        (packed.rect, unpack(&packed.bytes, len))
+                    })
+                    .collect();
+                reply.send(deltas).ok();
+            }
+            Job::Clear => {
+                strokes.clear();
+                current.clear();
+            }
+        }
+    }
+}
+
+/// Most of a stroke's region doesn't change at all, so runs of zeros are a zero
+/// followed by their length. Everything is little endian u16.
+fn pack(texels: &[u16]) -> Vec<u8> {
+    let mut bytes = vec![];
+    let mut i = 0;
+    while i < texels.len() {
+        let value = texels[i];
+        if value != 0 {
+            bytes.extend_from_slice(&value.to_le_bytes());
+            i += 1;
+            continue;
+        }
+        let run = texels[i..]
+            .iter()
+            .take(u16::MAX as usize)
+            .take_while(|&&t| t == 0)
+            .count();
+        bytes.extend_from_slice(&0u16.to_le_bytes());
+        bytes.extend_from_slice(&(run as u16).to_le_bytes());
+        i += run;
+    }
+    bytes
+}
+
+fn unpack(bytes: &[u8], len: usize) -> Vec<u16> {
+    let mut texels = Vec::with_capacity(len);
+    let mut values = bytes
+        .chunks_exact(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]));
+    while let Some(value) = values.next() {
+        if value == 0 {
+            let run = values.next().unwrap_or(0) as usize;
+            texels.resize(texels.len() + run, 0);
+        } else {
+            texels.push(value);
+        }
+    }
+    debug_assert_eq!(texels.len(), len);
+    texels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: usize = 64;
+
+    fn read(map: &[u16], rect: &DirtyRect) -> Vec<u16> {
+        (rect.y0..rect.y1)
+            .flat_map(|y| map[y * SIZE + rect.x0..y * SIZE + rect.x1].iter().copied())
+            .collect()
+    }
+
+    fn write(map: &mut [u16], rect: &DirtyRect, texels: &[u16]) {
+        for (row, y) in (rect.y0..rect.y1).enumerate() {
+            let texels = &texels[row * rect.width()..(row + 1) * rect.width()];
+            map[y * SIZE + rect.x0..y * SIZE + rect.x1].copy_from_slice(texels);
+        }
+    }
+
+    /// Changes the texels under `rect` the way a brush would and captures them.
+    /// Leaves some texels alone and wraps others past the top.
+    fn dab(history: &mut StrokeHistory, map: &mut [u16], rect: DirtyRect, amount: u16) {
+        let before = read(map, &rect);
+        let after: Vec<u16> = before
+            .iter()
+            .enumerate()
+            .map(|(i, &h)| {
+                if i % 3 == 0 {
+                    h
+                } else {
+                    h.wrapping_add(amount)
+                }
+            })
+            .collect();
+        write(map, &rect, &after);
+        history.capture(&rect, &before, &after);
+    }
+
+    fn undo(history: &mut StrokeHistory, map: &mut [u16]) -> usize {
+        let (tag, deltas) = history.undo().expect("Nothing to undo");
+        for (rect, deltas) in deltas.iter().rev() {
+            let texels: Vec<u16> = read(map, rect)
+                .iter()
+                .zip(deltas)
+                .map(|(&h, &delta)| h.wrapping_add(delta))
+                .collect();
+            write(map, rect, &texels);
+        }
+        tag
+    }
+
+    fn rect(x0: usize, y0: usize, x1: usize, y1: usize) -> DirtyRect {
+        DirtyRect { x0, y0, x1, y1 }
+    }
+
+    #[test]
+    fn undoing_strokes_restores_the_heights() {
+        let original: Vec<u16> = (0..SIZE * SIZE)
+            .map(|i| (i as u16).wrapping_mul(40_503))
+            .collect();
+        let mut map = original.clone();
+        let mut history = StrokeHistory::new();
+
+        dab(&mut history, &mut map, rect(0, 0, 20, 20), 1000);
+        dab(&mut history, &mut map, rect(10, 10, 40, 30), 60_000);
+        history.end_stroke(1);
+        let after_first = map.clone();
+        dab(&mut history, &mut map, rect(30, 5, 64, 64), 7);
+        dab(&mut history, &mut map, rect(0, 0, SIZE, SIZE), 12_345);
+        dab(&mut history, &mut map, rect(31, 6, 33, 8), u16::MAX);
+        history.end_stroke(2);
+
+        assert_eq!(undo(&mut history, &mut map), 2);
+        assert_eq!(map, after_first);
+        assert_eq!(undo(&mut history, &mut map), 1);
+        assert_eq!(map, original);
+        assert!(!history.can_undo());
+    }
+
+    #[test]
+    fn unpacking_gives_back_what_was_packed() {
+        let mut texels = vec![0u16; 70_000];
+        texels.extend_from_slice(&[1, u16::MAX, 0, 0, 42]);
+        texels.resize(texels.len() + u16::MAX as usize, 0);
+        texels.push(3);
+        assert_eq!(unpack(&pack(&texels), texels.len()), texels);
+        assert!(pack(&texels).len() < 32);
+        assert!(unpack(&pack(&[]), 0).is_empty());
+    }
+
+    #[test]
+    fn capturing_only_takes_the_dirty_rect() {
+        // A brush dab on an 8k map, only its own texels are ever touched
+        let mut history = StrokeHistory::new();
+        let dab = rect(4000, 4000, 4016, 4008);
+        let before = vec![100; 16 * 8];
+        let after = vec![200; 16 * 8];
+        history.capture(&dab, &before, &after);
+        history.capture(&dab, &after, &before);
+        assert_eq!(history.filling.rects, [dab, dab]);
+        assert_eq!(history.filling.texels.len(), 2 * 16 * 8);
+        let deltas = &history.filling.texels;
+        assert!(deltas
+            .iter()
+            .all(|&d| d == 100 || d == 100u16.wrapping_neg()));
+    }
+
+    #[test]
+    fn a_full_buffer_is_handed_over() {
+        let mut history = StrokeHistory::new();
+        let big = rect(0, 0, 1024, BUFFER_TEXELS / 1024);
+        let texels = vec![0; BUFFER_TEXELS];
+        history.capture(&big, &texels, &texels);
+        let small = rect(0, 0, 4, 4);
+        history.capture(&small, &[1; 16], &[2; 16]);
+        assert_eq!(history.filling.rects, [small]);
+        assert_eq!(history.filling.texels.len(), 16);
+        history.end_stroke(7);
+        let (tag, deltas) = history.undo().unwrap();
+        assert_eq!(tag, 7);
+        assert_eq!(deltas.len(), 2);
+    }
+
+    #[test]
+    fn capture_cost_follows_the_dirty_rect() {
+        // Texels and time on the main thread for one capture, the cheapest of a few
+        // so that the thread being scheduled out doesn't count
+        fn cost(history: &mut StrokeHistory, rect: DirtyRect, texels: &[u16]) -> (usize, Duration) {
+            let mut fastest = Duration::MAX;
+            let mut captured = 0;
+            for _ in 0..5 {
+                let before = history.filling.texels.len();
+                history.take_capture_time();
+                history.capture(&rect, texels, texels);
+                fastest = fastest.min(history.take_capture_time());
+                captured = history.filling.texels.len() - before;
+                history.end_stroke(0);
+            }
+            (captured, fastest)
+        }
+
+        const MAP: usize = 2048;
+        let full_map = vec![7u16; MAP * MAP];
+        let mut history = StrokeHistory::new();
+        let (one_texel, one_texel_time) = cost(&mut history, rect(5, 5, 6, 6), &[7]);
+        let (everything, everything_time) = cost(&mut history, DirtyRect::full(MAP), &full_map);
+        assert_eq!(one_texel, 1);
+        assert_eq!(everything, MAP * MAP);
+        assert!(
+            one_texel_time * 100 < everything_time,
+            "{:?} vs {:?}",
+            one_texel_time,
+            everything_time
+        );
+
+        // A full history doesn't make the next small stroke any dearer
+        let stroke = rect(0, 0, 256, 256);
+        for tag in 0..MAX_STROKES {
+            let after = vec![tag as u16; 256 * 256];
+            history.capture(&stroke, &full_map[..256 * 256], &after);
+            history.end_stroke(tag);
+        }
+        let (after_history, after_history_time) = cost(&mut history, rect(5, 5, 6, 6), &[7]);
+        assert_eq!(after_history, 1);
+        assert!(
+            after_history_time * 100 < everything_time,
+            "{:?} vs {:?}",
+            after_history_time,
+            everything_time
+        );
+    }
+}
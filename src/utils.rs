@@ -10,7 +10,124 @@ pub fn vec2_infinity() -> Vec2 {
     Vec2::new(f32::INFINITY, f32::INFINITY)
 }
 
+/// Normalised coordinates of the center of texel `(x, y)` of a square `size` texels wide
+#[inline(always)]
+pub fn texel_uv(x: usize, y: usize, size: usize) -> Vec2 {
+    Vec2::new(x as f32 + 0.5, y as f32 + 0.5) / size as f32
+}
+
+/// Where `uv` is on a brush `brush_size` wide centered on `cursor`, all in the same
+/// units. The brush covers [0, 1].
+#[inline(always)]
+pub fn brush_uv(uv: Vec2, cursor: Vec2, brush_size: f32) -> Vec2 {
+    Vec2::splat(0.5) + (uv - cursor) / brush_size
+}
+
+/// Bilinearly filtered value of a square `size` texels wide, zero outside [0, 1].
+/// Texel centers are at half texels like on the GPU, the edge texels reach the edges.
+pub fn sample_bilinear(uv: Vec2, size: usize, texel: impl Fn(usize, usize) -> f32) -> f32 {
+    if uv.x < 0.0 || uv.y < 0.0 || uv.x > 1.0 || uv.y > 1.0 {
+        return 0.0;
+    }
+    let last = size - 1;
+    let p = (uv * size as f32 - Vec2::splat(0.5)).max(Vec2::ZERO);
+    let (x, y) = (p.x as usize, p.y as usize);
+    let (tx, ty) = (p.x.fract(), p.y.fract());
+    let texel = |x: usize, y: usize| texel(x.min(last), y.min(last));
+    let top = texel(x, y) * (1.0 - tx) + texel(x + 1, y) * tx;
+    let bottom = texel(x, y + 1) * (1.0 - tx) + texel(x + 1, y + 1) * tx;
+    top * (1.0 - ty) + bottom * ty
+}
+
+/// Height inside a quad drawn as two triangles split from (1, 0) to (0, 1), the way
+/// the terrain is. `t` is the position in the quad, `corners` the heights at (0, 0),
+/// (1, 0), (0, 1) and (1, 1).
+pub fn quad_height(t: Vec2, corners: [f32; 4]) -> f32 {
+    let [h00, h10, h01, h11] = corners;
+    if t.x + t.y <= 1.0 {
+        h00 + (h10 - h00) * t.x + (h01 - h00) * t.y
+    } else {
+        h11 + (h01 - h11) * (1.0 - t.x) + (h10 - h11) * (1.0 - t.y)
+    }
+}
+
 #[inline(always)]
 pub fn size_of_slice<T>(slice: &[T]) -> usize {
     std::mem::size_of::<T>() * slice.len()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 4 by 4, each texel is its index
+    fn texel(x: usize, y: usize) -> f32 {
+        (y * 4 + x) as f32
+    }
+
+    #[test]
+    fn texel_uvs_are_at_the_centers() {
+        assert_eq!(texel_uv(0, 0, 4), Vec2::splat(0.125));
+        assert_eq!(texel_uv(3, 1, 4), Vec2::new(0.875, 0.375));
+        assert_eq!(texel_uv(0, 0, 1), Vec2::splat(0.5));
+    }
+
+    #[test]
+    fn brush_covers_zero_to_one_around_the_cursor() {
+        let cursor = Vec2::new(0.3, 0.6);
+        assert_eq!(brush_uv(cursor, cursor, 0.2), Vec2::splat(0.5));
+        let corner = brush_uv(cursor - Vec2::splat(0.1), cursor, 0.2);
+        assert!(corner.abs().max_element() < 1e-6);
+        let corner = brush_uv(cursor + Vec2::splat(0.1), cursor, 0.2);
+        assert!((corner - Vec2::ONE).abs().max_element() < 1e-6);
+    }
+
+    #[test]
+    fn bilinear_samples_texels_at_their_centers() {
+        for y in 0..4 {
+            for x in 0..4 {
+                assert_eq!(sample_bilinear(texel_uv(x, y, 4), 4, texel), texel(x, y));
+            }
+        }
+    }
+
+    #[test]
+    fn bilinear_blends_between_centers() {
+        let between = (texel_uv(1, 1, 4) + texel_uv(2, 2, 4)) / 2.0;
+        let expected = (texel(1, 1) + texel(2, 1) + texel(1, 2) + texel(2, 2)) / 4.0;
+        assert!((sample_bilinear(between, 4, texel) - expected).abs() < 1e-5);
+    }
+
+    #[test]
+    fn bilinear_edge_texels_reach_the_edges() {
+        assert_eq!(sample_bilinear(Vec2::ZERO, 4, texel), texel(0, 0));
+        assert_eq!(sample_bilinear(Vec2::ONE, 4, texel), texel(3, 3));
+        assert_eq!(sample_bilinear(Vec2::new(1.0, 0.0), 4, texel), texel(3, 0));
+        assert_eq!(sample_bilinear(Vec2::new(0.0, 1.0), 4, texel), texel(0, 3));
+    }
+
+    #[test]
+    fn bilinear_is_zero_outside() {
+        assert_eq!(sample_bilinear(Vec2::new(-0.01, 0.5), 4, texel), 0.0);
+        assert_eq!(sample_bilinear(Vec2::new(0.5, 1.01), 4, texel), 0.0);
+    }
+
+    #[test]
+    fn quad_height_matches_the_corners() {
+        let corners = [1.0, 2.0, 3.0, 5.0];
+        assert_eq!(quad_height(Vec2::new(0.0, 0.0), corners), 1.0);
+        assert_eq!(quad_height(Vec2::new(1.0, 0.0), corners), 2.0);
+        assert_eq!(quad_height(Vec2::new(0.0, 1.0), corners), 3.0);
+        assert_eq!(quad_height(Vec2::new(1.0, 1.0), corners), 5.0);
+    }
+
+    #[test]
+    fn quad_height_is_continuous_across_the_split() {
+        let corners = [1.0, 2.0, 3.0, 5.0];
+        let below = quad_height(Vec2::new(0.5, 0.5 - 1e-4), corners);
+        let above = quad_height(Vec2::new(0.5, 0.5 + 1e-4), corners);
+        assert!((below - above).abs() < 1e-3);
+        // Not the average of all four, the quad isn't bilinear
+        assert_eq!(quad_height(Vec2::splat(0.5), corners), 2.5);
+    }
+}
@@ -0,0 +1,264 @@
+use glam::{Vec2, Vec3};
+use serde::{Deserialize, Serialize};
+
+use crate::camera::Camera;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ViewportLayout {
+    Single,
+    /// Perspective on the left, top-down orthographic on the right
+    TwoPane,
+    /// Perspective, top-down and two more perspective views from the sides
+    FourPane,
+    /// Perspective on the left, the heightmap as a flat grayscale image on the right
+    Heightmap,
+}
+
+impl ViewportLayout {
+    pub const ALL: [ViewportLayout; 4] = [
+        ViewportLayout::Single,
+        ViewportLayout::TwoPane,
+        ViewportLayout::FourPane,
+        ViewportLayout::Heightmap,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ViewportLayout::Single => "Single",
+            ViewportLayout::TwoPane => "Two panes",
+            ViewportLayout::FourPane => "Four panes",
+            ViewportLayout::Heightmap => "3D and 2D heightmap",
+        }
+    }
+}
+
+/// Part of the window in physical pixels, origin at the top left corner
+#[derive(Debug, Clone, Copy)]
+pub struct ViewportRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl ViewportRect {
+    pub fn contains(&self, point: Vec2) -> bool {
+        point.x >= self.x as f32
+            && point.x < (self.x + self.width) as f32
+            && point.y >= self.y as f32
+            && point.y < (self.y + self.height) as f32
+    }
+
+    pub fn min(&self) -> Vec2 {
+        Vec2::new(self.x as f32, self.y as f32)
+    }
+
+    pub fn size(&self) -> Vec2 {
+        Vec2::new(self.width as f32, self.height as f32)
+    }
+
+    /// The same part of a render target `scale` times the size of the window
+    pub fn scaled(&self, scale: f32) -> ViewportRect {
+        let scale = |value: u32| (value as f32 * scale).round() as u32;
+        ViewportRect {
+            x: scale(self.x),
+            y: scale(self.y),
+            width: scale(self.width).max(1),
+            height: scale(self.height).max(1),
+        }
+    }
+
+    /// Sets the GL viewport and scissor box (GL wants the origin at the bottom left)
+    pub fn set_gl_viewport(&self, window_height: u32) {
+        let y = window_height as i32 - (self.y + self.height) as i32;
+        unsafe {
+            gl::Viewport(self.x as i32, y, self.width as i32, self.height as i32);
+            gl::Scissor(self.x as i32, y, self.width as i32, self.height as i32);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ViewKind {
+    Scene,
+    /// Heights as gray levels from above, edited with the same brush as the scene
+    Heightmap,
+}
+
+pub struct Viewport {
+    pub rect: ViewportRect,
+    pub camera: Camera,
+    pub kind: ViewKind,
+}
+
+/// All the views into the scene which are rendered each frame.
+/// The first one is always the main perspective view.
+pub struct Viewports {
+    layout: ViewportLayout,
+    views: Vec<Viewport>,
+    window_size: (u32, u32),
+    world_extent: f32,
+
+    /// The view the pointer was last over
+    active: usize,
+}
+
+impl Viewports {
+    pub fn new(
+        main_camera: Camera,
+        window_width: u32,
+        window_height: u32,
+        world_extent: f32,
+    ) -> Self {
+        let rect = ViewportRect {
+            x: 0,
+            y: 0,
+            width: window_width,
+            height: window_height,
+        };
+        Viewports {
+            layout: ViewportLayout::Single,
+            views: vec![Viewport {
+                rect,
+                camera: main_camera,
+                kind: ViewKind::Scene,
+            }],
+            window_size: (window_width, window_height),
+            world_extent,
+            active: 0,
+        }
+    }
+
+    /// Where the extra views are placed depends on how big the world is
+    pub fn set_world_extent(&mut self, world_extent: f32) {
+        self.world_extent = world_extent;
+        let layout = self.layout;
+        self.layout = ViewportLayout::Single;
+        self.set_layout(layout);
+    }
+
+    pub fn layout(&self) -> ViewportLayout {
+        self.layout
+    }
+
+    pub fn set_layout(&mut self, layout: ViewportLayout) {
+        if layout == self.layout {
+            return;
+        }
+        self.layout = layout;
+        self.views.truncate(1);
+        self.active = 0;
+
+        let extent = self.world_extent;
+        let make_camera = |position: Vec3| Camera::new(position, Vec3::ZERO, 1, 1);
+        match layout {
+            ViewportLayout::Single => {}
+            ViewportLayout::TwoPane => {
+                self.views.push(self.top_down_view());
+            }
+            ViewportLayout::FourPane => {
+                self.views.push(self.top_down_view());
+                self.views.push(Viewport {
+                    rect: self.views[0].rect,
+                    camera: make_camera(Vec3::new(0.0, extent * 0.4, extent * 0.8)),
+                    kind: ViewKind::Scene,
+                });
+                self.views.push(Viewport {
+                    rect: self.views[0].rect,
+                    camera: make_camera(Vec3::new(extent * 0.8, extent * 0.4, 0.0)),
+                    kind: ViewKind::Scene,
+                });
+            }
+            ViewportLayout::Heightmap => {
+                let mut view = self.top_down_view();
+                view.kind = ViewKind::Heightmap;
+                self.views.push(view);
+            }
+        }
+
+        let rects = self.split_window();
+        for (view, rect) in self.views.iter_mut().zip(rects) {
+            view.rect = rect;
+            view.camera.set_screen_dimensions(rect.width, rect.height);
+        }
+    }
+
+    fn top_down_view(&self) -> Viewport {
+        let rect = self.views[0].rect;
+        let center = Vec3::new(0.0, self.world_extent, 0.0);
+        Viewport {
+            rect,
+            camera: Camera::new_top_down(center, self.world_extent, rect.width, rect.height),
+            kind: ViewKind::Scene,
+        }
+    }
+
+    fn split_window(&self) -> Vec<ViewportRect> {
+        let (width, height) = self.window_size;
+        let rect = |x, y, width, height| ViewportRect {
+            x,
+            y,
+            width,
+            height,
+        };
+        match self.layout {
+            ViewportLayout::Single => vec![rect(0, 0, width, height)],
+            ViewportLayout::TwoPane | ViewportLayout::Heightmap => {
+                let half = width / 2;
+                vec![
+                    rect(0, 0, half, height),
+                    rect(half, 0, width - half, height),
+                ]
+            }
+            ViewportLayout::FourPane => {
+                let (half_w, half_h) = (width / 2, height / 2);
+                vec![
+                    rect(0, 0, half_w, half_h),
+                    rect(half_w, 0, width - half_w, half_h),
+                    rect(0, half_h, half_w, height - half_h),
+                    rect(half_w, half_h, width - half_w, height - half_h),
+                ]
+            }
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Viewport> {
+        self.views.iter()
+    }
+
+    pub fn main(&self) -> &Viewport {
+        &self.views[0]
+    }
+
+    pub fn main_camera(&self) -> &Camera {
+        &self.views[0].camera
+    }
+
+    pub fn main_camera_mut(&mut self) -> &mut Camera {
+        &mut self.views[0].camera
+    }
+
+    pub fn active(&self) -> &Viewport {
+        &self.views[self.active]
+    }
+
+    pub fn active_camera_mut(&mut self) -> &mut Camera {
+        &mut self.views[self.active].camera
+    }
+
+    /// Makes the view under the pointer active unless we're in the middle of dragging.
+    /// Pointer is in physical pixels.
+    pub fn update_active(&mut self, pointer: Vec2, dragging: bool) {
+        if dragging {
+            return;
+        }
+        if let Some(index) = self.views.iter().position(|v| v.rect.contains(pointer)) {
+            self.active = index;
+        }
+    }
+
+    /// Pointer position relative to the active view
+    pub fn pointer_in_active(&self, pointer: Vec2) -> Vec2 {
+        pointer - self.active().rect.min()
+    }
+}
@@ -0,0 +1,389 @@
+//! Painted maps too big to keep on the GPU whole. Their own textures are averaged
+//! down to `FALLBACK_SIZE` for the distance, and the pages closest to the camera are
+//! streamed in at full resolution from the CPU copies. A page table with one texel
+//! per page tells the shader which pages are resident.
+
+use std::collections::HashMap;
+
+use glam::Vec2;
+
+use crate::heightfield::DirtyRect;
+use crate::opengl::objects::Texture;
+use crate::resources::{self, texture_bytes, MemoryHandle, ResourceKind};
+
+/// Maps up to this size are sent whole
+pub const FALLBACK_SIZE: usize = 2048;
+/// Map texels along a page side
+pub const PAGE_SIZE: usize = 256;
+/// Copied from the neighbours on every side so pages filter across their edges
+pub const PAGE_BORDER: usize = 1;
+/// Pages resident at once, each has a layer per map
+pub const MAX_PAGES: usize = 128;
+/// More would make moving the camera stutter
+const PAGES_PER_FRAME: usize = 8;
+
+/// Where the terrain shaders read the pages and the page table from
+pub const PAGES_UNIT: i32 = 16;
+pub const TABLE_UNIT: i32 = 17;
+
+type PageKey = (usize, usize);
+
+/// A painted map `VirtualPages` can stream from
+pub trait PagedMap {
+    fn size(&self) -> usize;
+    /// RGBA8 texels within `rect`, row by row
+    fn pixels(&self, rect: &DirtyRect) -> Vec<u8>;
+}
+
+/// The GPU side of a painted map, either the whole map or its average for the
+/// distance
+pub struct MapTexture {
+    pub texture: Texture,
+    _memory: MemoryHandle,
+    size: usize,
+    /// Map texels per texel of `texture` along a side
+    ratio: usize,
+    /// Changed since the pages last looked
+    dirty: Option<DirtyRect>,
+}
+
+impl MapTexture {
+    pub fn new(size: usize, label: &str) -> Self {
+        let ratio = (size / FALLBACK_SIZE).max(1);
+        let texture_size = size.div_ceil(ratio);
+        let texture = Texture::new(gl::TEXTURE_2D, label);
+        texture.set_wrap_and_filter(gl::CLAMP_TO_EDGE, gl::LINEAR, gl::LINEAR);
+        unsafe {
+            gl::TextureStorage2D(
+                texture.id(),
+                1,
+                gl::RGBA8,
+                texture_size as i32,
+                texture_size as i32,
+            );
+        }
+        MapTexture {
+            texture,
+            _memory: resources::track(
+                ResourceKind::SplatMap,
+                texture_bytes(gl::RGBA8, texture_size, texture_size, 1),
+            ),
+            size,
+            ratio,
+            dirty: None,
+        }
+    }
+
+    /// `rect` grown to whole texels of the texture, what `upload` needs
+    pub fn covering(&self, rect: &DirtyRect) -> DirtyRect {
+        let ratio = self.ratio;
+        DirtyRect {
+            x0: rect.x0 / ratio * ratio,
+            y0: rect.y0 / ratio * ratio,
+            x1: (rect.x1.div_ceil(ratio) * ratio).min(self.size),
+            y1: (rect.y1.div_ceil(ratio) * ratio).min(self.size),
+        }
+    }
+
+    /// `pixels` are the RGBA8 texels of the map within `rect`, which has to come
+    /// from `covering`
+    pub fn upload(&mut self, rect: &DirtyRect, pixels: &[u8]) {
+        let (width, height) = (rect.width(), rect.height());
+        debug_assert_eq!(pixels.len(), width * height * 4);
+        self.dirty = Some(match &self.dirty {
+            Some(dirty) => dirty.union(rect),
+            None => *rect,
+        });
+
+        let ratio = self.ratio;
+        let (columns, rows) = (width.div_ceil(ratio), height.div_ceil(ratio));
+        let averaged = if ratio == 1 {
+            pixels.to_vec()
+        } else {
+            let mut sums = vec![0u32; columns * rows * 4];
+            let mut counts = vec![0u32; columns * rows];
+            for y in 0..height {
+                for x in 0..width {
+                    let block = (y / ratio) * columns + x / ratio;
+                    let texel = &pixels[(y * width + x) * 4..][..4];
+                    for (sum, &value) in sums[block * 4..][..4].iter_mut().zip(texel) {
+                        *sum += value as u32;
+                    }
+                    counts[block] += 1;
+                }
+            }
+            sums.iter()
+                .enumerate()
+                .map(|(i, &sum)| ((sum + counts[i / 4] / 2) / counts[i / 4]) as u8)
+                .collect()
+        };
+        unsafe {
+            gl::TextureSubImage2D(
+                self.texture.id(),
+                0,
+                (rect.x0 / ratio) as i32,
+                (rect.y0 / ratio) as i32,
+                columns as i32,
+                rows as i32,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                averaged.as_ptr() as *const _,
+            );
+        }
+    }
+
+    /// What changed since the last call
+    pub fn take_dirty(&mut self) -> Option<DirtyRect> {
+        self.dirty.take()
+    }
+}
+
+/// Full resolution pages of maps of the same size, all resident for the same part
+/// of the terrain
+pub struct VirtualPages {
+    /// Layer `slot * maps + map` holds a page of a map
+    pub pages: Texture,
+    /// First layer of every resident page plus one
+    pub table: Texture,
+    _memory: MemoryHandle,
+    map_size: usize,
+    maps: usize,
+    pages_per_side: usize,
+    /// Slot by page
+    resident: HashMap<PageKey, usize>,
+    free_slots: Vec<usize>,
+}
+
+impl VirtualPages {
+    /// For `maps` maps of `map_size`, there are no pages if they fit whole
+    pub fn new(map_size: usize, maps: usize) -> Self {
+        let pages_per_side = if map_size > FALLBACK_SIZE {
+            map_size.div_ceil(PAGE_SIZE)
+        } else {
+            0
+        };
+        // Empty maps still need something to bind
+        let (stored, layers) = if pages_per_side > 0 {
+            (PAGE_SIZE + 2 * PAGE_BORDER, MAX_PAGES * maps)
+        } else {
+            (1, 1)
+        };
+        let table_size = pages_per_side.max(1);
+
+        let pages = Texture::new(gl::TEXTURE_2D_ARRAY, "Virtual pages");
+        pages.set_wrap_and_filter(gl::CLAMP_TO_EDGE, gl::LINEAR, gl::LINEAR);
+        let table = Texture::new(gl::TEXTURE_2D, "Page table");
+        table.set_wrap_and_filter(gl::CLAMP_TO_EDGE, gl::NEAREST, gl::NEAREST);
+        unsafe {
+            gl::TextureStorage3D(
+                pages.id(),
+                1,
+                gl::RGBA8,
+                stored as i32,
+                stored as i32,
+                layers as i32,
+            );
+            gl::TextureStorage2D(
+                table.id(),
+                1,
+                gl::R32F,
+                table_size as i32,
+                table_size as i32,
+            );
+        }
+
+        let virtual_pages = VirtualPages {
+            pages,
+            table,
+            _memory: resources::track(
+                ResourceKind::SplatMap,
+                texture_bytes(gl::RGBA8, stored, stored, 1) * layers
+                    + texture_bytes(gl::R32F, table_size, table_size, 1),
+            ),
+            map_size,
+            maps,
+            pages_per_side,
+            resident: HashMap::new(),
+            free_slots: (0..MAX_PAGES).rev().collect(),
+        };
+        let zeros = vec![0.0f32; table_size * table_size];
+        unsafe {
+            gl::TextureSubImage2D(
+                virtual_pages.table.id(),
+                0,
+                0,
+                0,
+                table_size as i32,
+                table_size as i32,
+                gl::RED,
+                gl::FLOAT,
+                zeros.as_ptr() as *const _,
+            );
+        }
+        virtual_pages
+    }
+
+    pub fn map_size(&self) -> usize {
+        self.map_size
+    }
+
+    /// Texels along a side of the maps the shader reads through the pages,
+    /// 0 if the maps are sent whole
+    pub fn virtual_size(&self) -> usize {
+        if self.pages_per_side > 0 {
+            self.map_size
+        } else {
+            0
+        }
+    }
+
+    pub fn resident_pages(&self) -> usize {
+        self.resident.len()
+    }
+
+    /// Sends again what changed in the resident pages, then swaps the pages
+    /// furthest from the camera for closer ones. `changed` is a rect per map, the
+    /// camera is in uv with its height over the terrain in the same units and
+    /// `forward` is where it looks on the ground.
+    pub fn update(
+        &mut self,
+        maps: &[&dyn PagedMap],
+        changed: &[Option<DirtyRect>],
+        camera: Vec2,
+        height: f32,
+        forward: Vec2,
+    ) {
+        debug_assert_eq!(maps.len(), self.maps);
+        if self.pages_per_side == 0 {
+            return;
+        }
+
+        for (map, rect) in changed.iter().enumerate() {
+            let rect = match rect {
+                Some(rect) => rect,
+                None => continue,
+            };
+            for (&key, &slot) in &self.resident {
+                if overlaps(&self.page_rect(key), rect) {
+                    self.upload_page(maps[map], key, slot * self.maps + map);
+                }
+            }
+        }
+
+        // The closest pages, those behind the camera count as further away
+        let pages_per_side = self.pages_per_side;
+        let page_extent = 1.0 / pages_per_side as f32;
+        let forward = forward.normalize_or_zero();
+        let mut wanted: Vec<(f32, PageKey)> = (0..pages_per_side * pages_per_side)
+            .map(|i| {
+                let key = (i % pages_per_side, i / pages_per_side);
+                let center = (Vec2::new(key.0 as f32, key.1 as f32) + 0.5) * page_extent;
+                let offset = center - camera;
+                let mut distance = (offset.length_squared() + height * height).sqrt();
+                if offset.dot(forward) < 0.0 && offset.length() > page_extent {
+                    distance *= 2.0;
+                }
+                (distance, key)
+            })
+            .collect();
+        wanted.sort_by(|a, b| a.0.total_cmp(&b.0));
+        wanted.truncate(MAX_PAGES);
+
+        let evicted: Vec<PageKey> = self
+            .resident
+            .keys()
+            .filter(|key| !wanted.iter().any(|(_, wanted)| wanted == *key))
+            .copied()
+            .collect();
+        for key in evicted {
+            let slot = self.resident.remove(&key).unwrap();
+            self.free_slots.push(slot);
+            self.set_table(key, 0.0);
+        }
+
+        let missing = wanted
+            .iter()
+            .map(|&(_, key)| key)
+            .filter(|key| !self.resident.contains_key(key))
+            .take(PAGES_PER_FRAME)
+            .collect::<Vec<_>>();
+        for key in missing {
+            let slot = match self.free_slots.pop() {
+                Some(slot) => slot,
+                None => break,
+            };
+            for (map, paged) in maps.iter().enumerate() {
+                self.upload_page(*paged, key, slot * self.maps + map);
+            }
+            self.resident.insert(key, slot);
+            self.set_table(key, (slot * self.maps + 1) as f32);
+        }
+    }
+
+    /// Map texels a page covers, with its border
+    fn page_rect(&self, (x, y): PageKey) -> DirtyRect {
+        DirtyRect {
+            x0: (x * PAGE_SIZE).saturating_sub(PAGE_BORDER),
+            y0: (y * PAGE_SIZE).saturating_sub(PAGE_BORDER),
+            x1: ((x + 1) * PAGE_SIZE + PAGE_BORDER).min(self.map_size),
+            y1: ((y + 1) * PAGE_SIZE + PAGE_BORDER).min(self.map_size),
+        }
+    }
+
+    /// Past the edges of the map the border repeats the last texels
+    fn upload_page(&self, map: &dyn PagedMap, key: PageKey, layer: usize) {
+        debug_assert_eq!(map.size(), self.map_size);
+        let rect = self.page_rect(key);
+        let pixels = map.pixels(&rect);
+        let stored = PAGE_SIZE + 2 * PAGE_BORDER;
+        let to_map = |page_index: usize, texel: usize, low: usize, high: usize| {
+            (page_index * PAGE_SIZE + texel)
+                .saturating_sub(PAGE_BORDER)
+                .clamp(low, high - 1)
+        };
+        let mut page = Vec::with_capacity(stored * stored * 4);
+        for y in 0..stored {
+            let row = to_map(key.1, y, rect.y0, rect.y1) - rect.y0;
+            for x in 0..stored {
+                let column = to_map(key.0, x, rect.x0, rect.x1) - rect.x0;
+                let i = (row * rect.width() + column) * 4;
+                page.extend_from_slice(&pixels[i..i + 4]);
+            }
+        }
+        unsafe {
+            gl::TextureSubImage3D(
+                self.pages.id(),
+                0,
+                0,
+                0,
+                layer as i32,
+                stored as i32,
+                stored as i32,
+                1,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                page.as_ptr() as *const _,
+            );
+        }
+    }
+
+    fn set_table(&self, (x, y): PageKey, value: f32) {
+        unsafe {
+            gl::TextureSubImage2D(
+                self.table.id(),
+                0,
+                x as i32,
+                y as i32,
+                1,
+                1,
+                gl::RED,
+                gl::FLOAT,
+                &value as *const f32 as *const _,
+            );
+        }
+    }
+}
+
+fn overlaps(a: &DirtyRect, b: &DirtyRect) -> bool {
+    a.x0 < b.x1 && b.x0 < a.x1 && a.y0 < b.y1 && b.y0 < a.y1
+}
@@ -0,0 +1,127 @@
+//! Wind that sways scattered models: one global direction and strength, plus
+//! round zones with their own wind that fades into the global one at the edge.
+
+use glam::{Vec2, Vec3, Vec3Swizzles};
+
+use crate::opengl::shader::Program;
+use crate::terrain::Terrain;
+use crate::Result;
+
+/// Has to match the array size in wind.glsl
+pub const MAX_WIND_ZONES: usize = 8;
+
+/// Meters above the terrain the arrows are drawn at
+const ARROW_HEIGHT: f32 = 5.0;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Wind {
+    /// Degrees clockwise from +Z when looking down
+    pub direction: f32,
+    /// 0 is still, 1 is a fair breeze
+    pub strength: f32,
+}
+
+impl Wind {
+    /// Horizontal direction scaled by strength
+    pub fn vector(&self) -> Vec2 {
+        let angle = self.direction.to_radians();
+        Vec2::new(angle.sin(), angle.cos()) * self.strength
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct WindZone {
+    /// World space, on the ground plane
+    pub center: Vec2,
+    pub radius: f32,
+    pub wind: Wind,
+}
+
+pub struct WindSettings {
+    pub global: Wind,
+    pub zones: Vec<WindZone>,
+    /// The zone the sliders edit
+    pub selected: Option<usize>,
+    pub show_arrows: bool,
+}
+
+impl Default for WindSettings {
+    fn default() -> Self {
+        WindSettings {
+            global: Wind {
+                direction: 45.0,
+                strength: 0.5,
+            },
+            zones: vec![],
+            selected: None,
+            show_arrows: true,
+        }
+    }
+}
+
+impl WindSettings {
+    /// Adds a zone and selects it, unless there's no room for another one
+    pub fn add_zone(&mut self, center: Vec2, radius: f32) {
+        if self.zones.len() < MAX_WIND_ZONES {
+            self.zones.push(WindZone {
+                center,
+                radius,
+                wind: self.global,
+            });
+            self.selected = Some(self.zones.len() - 1);
+        }
+    }
+
+    /// Removes the zone whose center is closest to `point` if `point` is inside it
+    pub fn remove_zone_at(&mut self, point: Vec2) {
+        let closest = self
+            .zones
+            .iter()
+            .enumerate()
+            .filter(|(_, zone)| zone.center.distance(point) <= zone.radius)
+            .min_by(|(_, a), (_, b)| {
+                let distance_a = a.center.distance_squared(point);
+                let distance_b = b.center.distance_squared(point);
+                distance_a.total_cmp(&distance_b)
+            })
+            .map(|(i, _)| i);
+        if let Some(index) = closest {
+            self.remove_zone(index);
+        }
+    }
+
+    pub fn remove_zone(&mut self, index: usize) {
+        self.zones.remove(index);
+        self.selected = None;
+    }
+
+    /// `shader` has to include wind.glsl
+    pub fn set_uniforms(&self, shader: &Program, time: f32) -> Result<()> {
+        shader.set_used();
+        shader.set_f32("wind_time", time)?;
+        shader.set_vec2("global_wind", &self.global.vector())?;
+        shader.set_i32("wind_zone_count", self.zones.len() as i32)?;
+        for (i, zone) in self.zones.iter().enumerate() {
+            let area = Vec3::new(zone.center.x, zone.center.y, zone.radius);
+            shader.set_vec3(&format!("wind_zones[{}]", i), &area)?;
+            shader.set_vec2(&format!("zone_winds[{}]", i), &zone.wind.vector())?;
+        }
+        Ok(())
+    }
+
+    /// World space start and end of an arrow for the global wind and every zone.
+    /// The global one floats over the terrain center.
+    pub fn arrows(&self, terrain: &Terrain) -> Vec<(Vec3, Vec3)> {
+        let arrow = |center: Vec2, wind: &Wind, length: f32| {
+            let start = Vec3::new(center.x, terrain.height_at(center) + ARROW_HEIGHT, center.y);
+            let direction = wind.vector().normalize_or_zero() * length;
+            (start, start + Vec3::new(direction.x, 0.0, direction.y))
+        };
+        let terrain_center = (terrain.aabb.min.xz() + terrain.aabb.max.xz()) / 2.0;
+        let mut arrows = vec![arrow(terrain_center, &self.global, terrain.size() * 0.05)];
+        for zone in &self.zones {
+            arrows.push(arrow(zone.center, &zone.wind, zone.radius));
+        }
+        arrows
+    }
+}
@@ -0,0 +1,66 @@
+//! Shows how the CPU heightmap filters scale with the number of threads.
+//! Run with `cargo bench -p terrain_core`, compare the 1 thread numbers to the rest.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+use terrain_core::erosion::{thermal_erosion, ErosionSettings};
+use terrain_core::filters;
+use terrain_core::heightfield::{EdgeMode, HeightField};
+
+const THREADS: [usize; 4] = [1, 2, 4, 8];
+
+fn test_field(size: usize) -> HeightField {
+    let data = (0..size * size)
+        .map(|i| {
+            let (x, y) = (
+                (i % size) as f32 / size as f32,
+                (i / size) as f32 / size as f32,
+            );
+            let hills = (x * 17.0).sin() * (y * 13.0).cos() * 0.25 + 0.5;
+            let ridges = ((x + y) * 61.0).sin().abs() * 0.1;
+            hills + ridges
+        })
+        .collect();
+    HeightField::from_data(size, data)
+}
+
+fn bench_scaling<F>(c: &mut Criterion, name: &str, size: usize, op: F)
+where
+    F: Fn(&HeightField) + Sync,
+{
+    let field = test_field(size);
+    let mut group = c.benchmark_group(format!("{}_{}", name, size));
+    group.sample_size(10);
+    for threads in THREADS {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap();
+        group.bench_with_input(BenchmarkId::from_parameter(threads), &field, |b, field| {
+            pool.install(|| b.iter(|| op(field)));
+        });
+    }
+    group.finish();
+}
+
+fn filters(c: &mut Criterion) {
+    bench_scaling(c, "blur", 2048, |field| {
+        filters::blur(field, 8, EdgeMode::Clamp);
+    });
+    bench_scaling(c, "terrace", 2048, |field| {
+        filters::terrace(field, 8, 0.5);
+    });
+    bench_scaling(c, "normals", 2048, |field| {
+        filters::normals(field, 1.0, 200.0);
+    });
+    let settings = ErosionSettings {
+        iterations: 10,
+        ..ErosionSettings::default()
+    };
+    bench_scaling(c, "thermal_erosion", 2048, |field| {
+        thermal_erosion(field, &settings, 1.0, 200.0);
+    });
+}
+
+criterion_group!(benches, filters);
+criterion_main!(benches);
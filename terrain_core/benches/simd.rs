@@ -0,0 +1,38 @@
+//! Scalar vs SIMD versions of the heightmap kernels.
+//! The SIMD numbers fall back to scalar on CPUs without AVX2.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use glam::Vec3;
+use terrain_core::simd;
+
+const LEN: usize = 2048 * 2048;
+
+fn add_scaled(c: &mut Criterion) {
+    let src: Vec<f32> = (0..LEN).map(|i| (i as f32 * 0.01).sin()).collect();
+    let mut dst = vec![0.0f32; LEN];
+    let mut group = c.benchmark_group("add_scaled");
+    group.bench_function("scalar", |b| {
+        b.iter(|| simd::scalar::add_scaled(&mut dst, black_box(&src), 0.25))
+    });
+    group.bench_function("simd", |b| {
+        b.iter(|| simd::add_scaled(&mut dst, black_box(&src), 0.25))
+    });
+    group.finish();
+}
+
+fn normals_from_gradients(c: &mut Criterion) {
+    let dx: Vec<f32> = (0..LEN).map(|i| (i as f32 * 0.01).sin()).collect();
+    let dz: Vec<f32> = (0..LEN).map(|i| (i as f32 * 0.02).cos()).collect();
+    let mut out = vec![Vec3::Y; LEN];
+    let mut group = c.benchmark_group("normals_from_gradients");
+    group.bench_function("scalar", |b| {
+        b.iter(|| simd::scalar::normals_from_gradients(black_box(&dx), &dz, 0.01, &mut out))
+    });
+    group.bench_function("simd", |b| {
+        b.iter(|| simd::normals_from_gradients(black_box(&dx), &dz, 0.01, &mut out))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, add_scaled, normals_from_gradients);
+criterion_main!(benches);
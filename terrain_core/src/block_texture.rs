@@ -0,0 +1,649 @@
+//! GPU block compressed textures, which stay compressed in VRAM: BC1/BC3 for color,
+//! BC4 for single channel maps and BC5 for normal maps. Baked maps are compressed
+//! here and written as KTX2 with their mipmaps, and textures authored elsewhere are
+//! read from KTX2 or DDS as they are, ready to upload.
+//!
+//! The encoder fits the endpoints of every block along the main axis of its colors
+//! and is quick rather than the best there is. BC7 is only read.
+
+use std::path::Path;
+
+const KTX2_IDENTIFIER: [u8; 12] = [
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+/// Identifier, the fixed header fields and the index, up to the level index
+const KTX2_HEADER_SIZE: usize = 80;
+const KTX2_LEVEL_SIZE: usize = 24;
+
+const DDS_MAGIC: &[u8; 4] = b"DDS ";
+/// The magic and the header, where the data starts unless there's a DX10 header
+const DDS_HEADER_SIZE: usize = 128;
+const DDS_DX10_HEADER_SIZE: usize = 20;
+const DDS_CUBEMAP: u32 = 0x200;
+
+/// Wider or taller than any GPU takes, and than level sizes can be worked out for
+const MAX_SIZE: usize = 1 << 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockFormat {
+    /// RGB, 4 bits a texel
+    Bc1,
+    /// RGBA, the alpha kept apart from the color, 8 bits a texel
+    Bc3,
+    /// One channel, 4 bits a texel
+    Bc4,
+    /// Two channels, 8 bits a texel
+    Bc5,
+    /// RGBA at the best quality, 8 bits a texel, only read
+    Bc7,
+}
+
+impl BlockFormat {
+    pub fn name(&self) -> &'static str {
+        match self {
+            BlockFormat::Bc1 => "BC1",
+            BlockFormat::Bc3 => "BC3",
+            BlockFormat::Bc4 => "BC4",
+            BlockFormat::Bc5 => "BC5",
+            BlockFormat::Bc7 => "BC7",
+        }
+    }
+
+    /// Bytes of a 4x4 block
+    pub fn block_bytes(&self) -> usize {
+        match self {
+            BlockFormat::Bc1 | BlockFormat::Bc4 => 8,
+            BlockFormat::Bc3 | BlockFormat::Bc5 | BlockFormat::Bc7 => 16,
+        }
+    }
+
+    /// Bytes of a whole mip level, partial blocks at the edges taking a whole one
+    pub fn level_bytes(&self, width: usize, height: usize) -> usize {
+        let blocks = |texels: usize| texels.div_ceil(4);
+        blocks(width) * blocks(height) * self.block_bytes()
+    }
+
+    /// Vulkan's format, which KTX2 uses. Only BC1 and BC3 have sRGB variants here.
+    fn vk_format(&self, srgb: bool) -> u32 {
+        match (self, srgb) {
+            (BlockFormat::Bc1, false) => 131,
+            (BlockFormat::Bc1, true) => 132,
+            (BlockFormat::Bc3, false) => 137,
+            (BlockFormat::Bc3, true) => 138,
+            (BlockFormat::Bc4, _) => 139,
+            (BlockFormat::Bc5, _) => 141,
+            (BlockFormat::Bc7, false) => 145,
+            (BlockFormat::Bc7, true) => 146,
+        }
+    }
+
+    fn from_vk_format(format: u32) -> Option<(BlockFormat, bool)> {
+        Some(match format {
+            // Both BC1 with and without alpha
+            131 | 133 => (BlockFormat::Bc1, false),
+            132 | 134 => (BlockFormat::Bc1, true),
+            137 => (BlockFormat::Bc3, false),
+            138 => (BlockFormat::Bc3, true),
+            139 => (BlockFormat::Bc4, false),
+            141 => (BlockFormat::Bc5, false),
+            145 => (BlockFormat::Bc7, false),
+            146 => (BlockFormat::Bc7, true),
+            _ => return None,
+        })
+    }
+
+    fn from_dxgi_format(format: u32) -> Option<(BlockFormat, bool)> {
+        Some(match format {
+            71 => (BlockFormat::Bc1, false),
+            72 => (BlockFormat::Bc1, true),
+            77 => (BlockFormat::Bc3, false),
+            78 => (BlockFormat::Bc3, true),
+            80 => (BlockFormat::Bc4, false),
+            83 => (BlockFormat::Bc5, false),
+            98 => (BlockFormat::Bc7, false),
+            99 => (BlockFormat::Bc7, true),
+            _ => return None,
+        })
+    }
+
+    /// The Khronos data format descriptor's color model, and the channel and bit
+    /// offset of each half of a block
+    fn descriptor(&self) -> (u8, &'static [(u8, u16)]) {
+        match self {
+            BlockFormat::Bc1 => (128, &[(0, 0)]),
+            // Alpha, then color
+            BlockFormat::Bc3 => (130, &[(15, 0), (0, 64)]),
+            BlockFormat::Bc4 => (131, &[(0, 0)]),
+            // Red, then green
+            BlockFormat::Bc5 => (132, &[(0, 0), (1, 64)]),
+            BlockFormat::Bc7 => (134, &[(0, 0)]),
+        }
+    }
+}
+
+/// Compressed texels ready to upload, every mip level there is, the largest first
+#[derive(Debug, Clone)]
+pub struct CompressedImage {
+    pub format: BlockFormat,
+    /// Color stored in sRGB, to be decoded when sampled
+    pub srgb: bool,
+    pub width: usize,
+    pub height: usize,
+    pub levels: Vec<Vec<u8>>,
+}
+
+/// RGBA8 texels compressed with a full mip chain. Only the channels the format
+/// keeps are used, BC4 taking red and BC5 red and green. The texels are taken to be
+/// linear, which is what baked maps are.
+pub fn compress(rgba: &[u8], width: usize, height: usize, format: BlockFormat) -> CompressedImage {
+    assert_eq!(rgba.len(), width * height * 4);
+    assert!(format != BlockFormat::Bc7, "BC7 can only be read");
+    let mut levels = vec![];
+    let mut texels = rgba.to_vec();
+    let (mut w, mut h) = (width, height);
+    loop {
+        levels.push(compress_level(&texels, w, h, format));
+        if w == 1 && h == 1 {
+            break;
+        }
+        let (half_w, half_h) = ((w / 2).max(1), (h / 2).max(1));
+        texels = downsample(&texels, w, h, half_w, half_h);
+        w = half_w;
+        h = half_h;
+    }
+    CompressedImage {
+        format,
+        srgb: false,
+        width,
+        height,
+        levels,
+    }
+}
+
+/// Every 2x2 texels averaged, or every two when one side is down to a single texel
+fn downsample(rgba: &[u8], width: usize, height: usize, half_w: usize, half_h: usize) -> Vec<u8> {
+    let mut half = Vec::with_capacity(half_w * half_h * 4);
+    for y in 0..half_h {
+        for x in 0..half_w {
+            let (x0, y0) = (x * 2, y * 2);
+            let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+            for channel in 0..4 {
+                let texel = |x: usize, y: usize| rgba[(y * width + x) * 4 + channel] as u32;
+                let sum = texel(x0, y0) + texel(x1, y0) + texel(x0, y1) + texel(x1, y1);
+                half.push(((sum + 2) / 4) as u8);
+            }
+        }
+    }
+    half
+}
+
+fn compress_level(rgba: &[u8], width: usize, height: usize, format: BlockFormat) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(format.level_bytes(width, height));
+    for block_y in (0..height).step_by(4) {
+        for block_x in (0..width).step_by(4) {
+            // Blocks hanging over the edge repeat the last texels
+            let mut block = [[0u8; 4]; 16];
+            for (i, texel) in block.iter_mut().enumerate() {
+                let x = (block_x + i % 4).min(width - 1);
+                let y = (block_y + i / 4).min(height - 1);
+                let start = (y * width + x) * 4;
+                texel.copy_from_slice(&rgba[start..start + 4]);
+            }
+            let channel = |c: usize| {
+                let mut values = [0u8; 16];
+                for (value, texel) in values.iter_mut().zip(&block) {
+                    *value = texel[c];
+                }
+                values
+            };
+            match format {
+                BlockFormat::Bc1 => bytes.extend_from_slice(&encode_color(&block)),
+                BlockFormat::Bc3 => {
+                    bytes.extend_from_slice(&encode_channel(&channel(3)));
+                    bytes.extend_from_slice(&encode_color(&block));
+                }
+                BlockFormat::Bc4 => bytes.extend_from_slice(&encode_channel(&channel(0))),
+                BlockFormat::Bc5 => {
+                    bytes.extend_from_slice(&encode_channel(&channel(0)));
+                    bytes.extend_from_slice(&encode_channel(&channel(1)));
+                }
+                BlockFormat::Bc7 => unreachable!("BC7 is never encoded"),
+            }
+        }
+    }
+    bytes
+}
+
+/// A BC1 color block in four color mode, the alpha of the texels is left out
+fn encode_color(block: &[[u8; 4]; 16]) -> [u8; 8] {
+    let colors: Vec<[f32; 3]> = block
+        .iter()
+        .map(|t| [t[0] as f32, t[1] as f32, t[2] as f32])
+        .collect();
+    let mut mean = [0.0; 3];
+    for color in &colors {
+        for c in 0..3 {
+            mean[c] += color[c] / 16.0;
+        }
+    }
+    // The main axis by power iteration on the covariance
+    let mut covariance = [[0.0f32; 3]; 3];
+    for color in &colors {
+        for i in 0..3 {
+            for j in 0..3 {
+                covariance[i][j] += (color[i] - mean[i]) * (color[j] - mean[j]);
+            }
+        }
+    }
+    let mut axis = [1.0f32, 1.0, 1.0];
+    for _ in 0..8 {
+        let mut next = [0.0; 3];
+        for i in 0..3 {
+            next[i] = (0..3).map(|j| covariance[i][j] * axis[j]).sum();
+        }
+        let length = next.iter().map(|v| v * v).sum::<f32>().sqrt();
+        if length < 1e-6 {
+            break;
+        }
+        axis = next.map(|v| v / length);
+    }
+    let project = |color: &[f32; 3]| (0..3).map(|c| (color[c] - mean[c]) * axis[c]).sum::<f32>();
+    let (mut low, mut high) = (f32::INFINITY, f32::NEG_INFINITY);
+    for color in &colors {
+        low = low.min(project(color));
+        high = high.max(project(color));
+    }
+    let along = |t: f32| [0, 1, 2].map(|c| mean[c] + axis[c] * t);
+
+    let mut endpoints = [to_565(along(high)), to_565(along(low))];
+    if endpoints[0] < endpoints[1] {
+        endpoints.swap(0, 1);
+    }
+    let mut bytes = [0u8; 8];
+    bytes[0..2].copy_from_slice(&endpoints[0].to_le_bytes());
+    bytes[2..4].copy_from_slice(&endpoints[1].to_le_bytes());
+    if endpoints[0] == endpoints[1] {
+        // Every texel is the first endpoint
+        return bytes;
+    }
+    let (first, second) = (from_565(endpoints[0]), from_565(endpoints[1]));
+    let palette = [
+        first,
+        second,
+        [0, 1, 2].map(|c| (2.0 * first[c] + second[c]) / 3.0),
+        [0, 1, 2].map(|c| (first[c] + 2.0 * second[c]) / 3.0),
+    ];
+    let mut indices = 0u32;
+    for (i, color) in colors.iter().enumerate() {
+        let distance = |p: &[f32; 3]| (0..3).map(|c| (p[c] - color[c]).powi(2)).sum::<f32>();
+        let nearest = (0..4)
+            .min_by(|&a, &b| distance(&palette[a]).total_cmp(&distance(&palette[b])))
+            .unwrap_or(0);
+        indices |= (nearest as u32) << (i * 2);
+    }
+    bytes[4..8].copy_from_slice(&indices.to_le_bytes());
+    bytes
+}
+
+fn to_565(color: [f32; 3]) -> u16 {
+    let quantize = |v: f32, max: f32| (v.clamp(0.0, 255.0) / 255.0 * max).round() as u16;
+    quantize(color[0], 31.0) << 11 | quantize(color[1], 63.0) << 5 | quantize(color[2], 31.0)
+}
+
+fn from_565(color: u16) -> [f32; 3] {
+    let expand = |v: u16, max: f32| v as f32 / max * 255.0;
+    [
+        expand(color >> 11, 31.0),
+        expand((color >> 5) & 63, 63.0),
+        expand(color & 31, 31.0),
+    ]
+}
+
+/// A BC4 block, also the alpha of BC3 and either half of BC5, in eight value mode
+fn encode_channel(values: &[u8; 16]) -> [u8; 8] {
+    let high = *values.iter().max().unwrap_or(&0);
+    let low = *values.iter().min().unwrap_or(&0);
+    let mut bytes = [0u8; 8];
+    bytes[0] = high;
+    bytes[1] = low;
+    if high == low {
+        return bytes;
+    }
+    // Index 0 is the high end and 1 the low one, 2 to 7 step from high to low
+    let (high, low) = (high as f32, low as f32);
+    let palette: Vec<f32> = (0..8)
+        .map(|i| match i {
+            0 => high,
+            1 => low,
+            _ => ((8 - i) as f32 * high + (i - 1) as f32 * low) / 7.0,
+        })
+        .collect();
+    let mut indices = 0u64;
+    for (i, &value) in values.iter().enumerate() {
+        let distance = |p: f32| (p - value as f32).abs();
+        let nearest = (0..8)
+            .min_by(|&a, &b| distance(palette[a]).total_cmp(&distance(palette[b])))
+            .unwrap_or(0);
+        indices |= (nearest as u64) << (i * 3);
+    }
+    bytes[2..8].copy_from_slice(&indices.to_le_bytes()[..6]);
+    bytes
+}
+
+/// A KTX2 file with a single face and no supercompression
+pub fn to_ktx2(image: &CompressedImage) -> Vec<u8> {
+    let format = image.format;
+    let (color_model, samples) = format.descriptor();
+    let block_size = 24 + 16 * samples.len();
+    let mut descriptor = vec![];
+    descriptor.extend_from_slice(&((4 + block_size) as u32).to_le_bytes());
+    // Khronos' basic descriptor block, version 2
+    descriptor.extend_from_slice(&0u32.to_le_bytes());
+    descriptor.extend_from_slice(&2u16.to_le_bytes());
+    descriptor.extend_from_slice(&(block_size as u16).to_le_bytes());
+    // BT.709 primaries, sRGB or linear transfer, straight alpha
+    let transfer = if image.srgb { 2 } else { 1 };
+    descriptor.extend_from_slice(&[color_model, 1, transfer, 0]);
+    // 4x4 blocks, the dimensions being stored minus one
+    descriptor.extend_from_slice(&[3, 3, 0, 0]);
+    descriptor.extend_from_slice(&[format.block_bytes() as u8, 0, 0, 0, 0, 0, 0, 0]);
+    let bits = (format.block_bytes() * 8 / samples.len()) as u8;
+    for &(channel, offset) in samples {
+        descriptor.extend_from_slice(&offset.to_le_bytes());
+        descriptor.extend_from_slice(&[bits - 1, channel, 0, 0, 0, 0]);
+        descriptor.extend_from_slice(&0u32.to_le_bytes());
+        descriptor.extend_from_slice(&u32::MAX.to_le_bytes());
+    }
+
+    let level_count = image.levels.len();
+    let descriptor_offset = KTX2_HEADER_SIZE + KTX2_LEVEL_SIZE * level_count;
+    let mut bytes = vec![];
+    bytes.extend_from_slice(&KTX2_IDENTIFIER);
+    let header = [
+        format.vk_format(image.srgb),
+        // Type size, 1 for block compressed formats
+        1,
+        image.width as u32,
+        image.height as u32,
+        // Depth, layers, faces, levels, supercompression
+        0,
+        0,
+        1,
+        level_count as u32,
+        0,
+        descriptor_offset as u32,
+        descriptor.len() as u32,
+        // No key/value data
+        0,
+        0,
+    ];
+    for value in header {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    // No supercompression global data
+    bytes.extend_from_slice(&[0; 16]);
+
+    // Levels are stored smallest first, each aligned to a whole block
+    let align = format.block_bytes();
+    let mut offset = descriptor_offset + descriptor.len();
+    let mut offsets = vec![0; level_count];
+    for level in (0..level_count).rev() {
+        offset = offset.div_ceil(align) * align;
+        offsets[level] = offset;
+        offset += image.levels[level].len();
+    }
+    for (level, data) in image.levels.iter().enumerate() {
+        let length = data.len() as u64;
+        bytes.extend_from_slice(&(offsets[level] as u64).to_le_bytes());
+        bytes.extend_from_slice(&length.to_le_bytes());
+        bytes.extend_from_slice(&length.to_le_bytes());
+    }
+    bytes.extend_from_slice(&descriptor);
+    for level in (0..level_count).rev() {
+        bytes.resize(offsets[level], 0);
+        bytes.extend_from_slice(&image.levels[level]);
+    }
+    bytes
+}
+
+/// A KTX2 or a DDS file, told apart by their first bytes
+pub fn load(path: &Path) -> Result<CompressedImage, String> {
+    let bytes = std::fs::read(path).map_err(|e| e.to_string())?;
+    if bytes.starts_with(&KTX2_IDENTIFIER) {
+        from_ktx2(&bytes)
+    } else if bytes.starts_with(DDS_MAGIC) {
+        from_dds(&bytes)
+    } else {
+        Err("Neither a KTX2 nor a DDS file".to_owned())
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32, String> {
+    bytes
+        .get(offset..offset + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| "The file is cut short".to_owned())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Result<u64, String> {
+    Ok(read_u32(bytes, offset)? as u64 | (read_u32(bytes, offset + 4)? as u64) << 32)
+}
+
+/// Headers are only trusted as far as the texture could really be that size and
+/// have that many levels
+fn check_header(width: usize, height: usize, level_count: usize) -> Result<(), String> {
+    if width == 0 || height == 0 || width > MAX_SIZE || height > MAX_SIZE {
+        return Err(format!("A {}x{} texture can't be right", width, height));
+    }
+    let full_chain = (usize::BITS - width.max(height).leading_zeros()) as usize;
+    if level_count > full_chain {
+        return Err(format!(
+            "{} mip levels is more than a {}x{} texture has",
+            level_count, width, height
+        ));
+    }
+    Ok(())
+}
+
+/// Side of a mip level, never less than a texel
+fn level_side(side: usize, level: usize) -> usize {
+    side.checked_shr(level as u32).unwrap_or(0).max(1)
+}
+
+/// Mip levels of a flat 2D texture, the largest first
+pub fn from_ktx2(bytes: &[u8]) -> Result<CompressedImage, String> {
+    let field = |i: usize| read_u32(bytes, KTX2_IDENTIFIER.len() + i * 4);
+    let vk_format = field(0)?;
+    let (format, srgb) = BlockFormat::from_vk_format(vk_format)
+        .ok_or_else(|| format!("Vulkan format {} isn't a BC format read here", vk_format))?;
+    let (width, height) = (field(2)? as usize, field(3)? as usize);
+    if field(4)? > 0 || field(5)? > 1 || field(6)? != 1 {
+        return Err("Only flat 2D textures are supported, not 3D, arrays or cubemaps".to_owned());
+    }
+    if field(8)? != 0 {
+        return Err("Supercompressed KTX2 (Basis, zstd) isn't supported".to_owned());
+    }
+    let level_count = (field(7)? as usize).max(1);
+    check_header(width, height, level_count)?;
+    let levels = (0..level_count)
+        .map(|level| {
+            let entry = KTX2_HEADER_SIZE + level * KTX2_LEVEL_SIZE;
+            let offset = read_u64(bytes, entry)? as usize;
+            let length = read_u64(bytes, entry + 8)? as usize;
+            let expected = format.level_bytes(level_side(width, level), level_side(height, level));
+            match bytes.get(offset..offset.saturating_add(length)) {
+                Some(data) if length == expected => Ok(data.to_vec()),
+                _ => Err(format!("Mip level {} is broken", level)),
+            }
+        })
+        .collect::<Result<_, String>>()?;
+    Ok(CompressedImage {
+        format,
+        srgb,
+        width,
+        height,
+        levels,
+    })
+}
+
+/// Mip levels of a flat 2D texture, the largest first. Legacy files are never sRGB.
+pub fn from_dds(bytes: &[u8]) -> Result<CompressedImage, String> {
+    let height = read_u32(bytes, 12)? as usize;
+    let width = read_u32(bytes, 16)? as usize;
+    let level_count = (read_u32(bytes, 28)? as usize).max(1);
+    check_header(width, height, level_count)?;
+    if read_u32(bytes, 112)? & DDS_CUBEMAP != 0 {
+        return Err("Cubemaps aren't supported".to_owned());
+    }
+    let four_cc = bytes.get(84..88).ok_or("The file is cut short")?;
+    let (format, srgb, mut offset) = match four_cc {
+        b"DXT1" => (BlockFormat::Bc1, false, DDS_HEADER_SIZE),
+        b"DXT5" => (BlockFormat::Bc3, false, DDS_HEADER_SIZE),
+        b"ATI1" | b"BC4U" => (BlockFormat::Bc4, false, DDS_HEADER_SIZE),
+        b"ATI2" | b"BC5U" => (BlockFormat::Bc5, false, DDS_HEADER_SIZE),
+        b"DX10" => {
+            let dxgi_format = read_u32(bytes, DDS_HEADER_SIZE)?;
+            if read_u32(bytes, DDS_HEADER_SIZE + 12)? > 1 {
+                return Err("Texture arrays aren't supported".to_owned());
+            }
+            let (format, srgb) = BlockFormat::from_dxgi_format(dxgi_format).ok_or_else(|| {
+                format!("DXGI format {} isn't a BC format read here", dxgi_format)
+            })?;
+            (format, srgb, DDS_HEADER_SIZE + DDS_DX10_HEADER_SIZE)
+        }
+        _ => {
+            let name = String::from_utf8_lossy(four_cc).into_owned();
+            return Err(format!("'{}' isn't a BC format read here", name));
+        }
+    };
+    // Levels follow each other, the largest first
+    let mut levels = vec![];
+    for level in 0..level_count {
+        let length = format.level_bytes(level_side(width, level), level_side(height, level));
+        let data = bytes
+            .get(offset..offset + length)
+            .ok_or_else(|| format!("Mip level {} is cut short", level))?;
+        levels.push(data.to_vec());
+        offset += length;
+    }
+    Ok(CompressedImage {
+        format,
+        srgb,
+        width,
+        height,
+        levels,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FORMATS: [BlockFormat; 4] = [
+        BlockFormat::Bc1,
+        BlockFormat::Bc3,
+        BlockFormat::Bc4,
+        BlockFormat::Bc5,
+    ];
+
+    /// Not a multiple of the block size either way, so the edges are partial blocks
+    fn image(format: BlockFormat) -> CompressedImage {
+        let (width, height) = (21, 10);
+        let rgba: Vec<u8> = (0..width * height * 4)
+            .map(|i| (i * 37 % 251) as u8)
+            .collect();
+        compress(&rgba, width, height, format)
+    }
+
+    fn dds_header(width: u32, height: u32, level_count: u32, four_cc: &[u8; 4]) -> Vec<u8> {
+        let mut bytes = vec![0u8; DDS_HEADER_SIZE];
+        bytes[..4].copy_from_slice(DDS_MAGIC);
+        bytes[4..8].copy_from_slice(&124u32.to_le_bytes());
+        bytes[12..16].copy_from_slice(&height.to_le_bytes());
+        bytes[16..20].copy_from_slice(&width.to_le_bytes());
+        bytes[28..32].copy_from_slice(&level_count.to_le_bytes());
+        bytes[76..80].copy_from_slice(&32u32.to_le_bytes());
+        bytes[84..88].copy_from_slice(four_cc);
+        bytes
+    }
+
+    fn to_dds(image: &CompressedImage) -> Vec<u8> {
+        let four_cc = match image.format {
+            BlockFormat::Bc1 => b"DXT1",
+            BlockFormat::Bc3 => b"DXT5",
+            BlockFormat::Bc4 => b"ATI1",
+            BlockFormat::Bc5 => b"ATI2",
+            BlockFormat::Bc7 => unreachable!("BC7 is never encoded"),
+        };
+        let (width, height) = (image.width as u32, image.height as u32);
+        let mut bytes = dds_header(width, height, image.levels.len() as u32, four_cc);
+        for level in &image.levels {
+            bytes.extend_from_slice(level);
+        }
+        bytes
+    }
+
+    fn assert_same(read: &CompressedImage, written: &CompressedImage) {
+        assert_eq!(read.format, written.format);
+        assert_eq!(read.srgb, written.srgb);
+        assert_eq!((read.width, read.height), (written.width, written.height));
+        assert_eq!(read.levels, written.levels);
+    }
+
+    #[test]
+    fn full_mip_chains_go_down_to_a_texel() {
+        let image = image(BlockFormat::Bc1);
+        // 21 wide: 21, 10, 5, 2, 1
+        assert_eq!(image.levels.len(), 5);
+        assert_eq!(image.levels[4].len(), BlockFormat::Bc1.block_bytes());
+    }
+
+    #[test]
+    fn ktx2_round_trips() {
+        for format in FORMATS {
+            let image = image(format);
+            assert_same(&from_ktx2(&to_ktx2(&image)).unwrap(), &image);
+        }
+        let mut srgb = image(BlockFormat::Bc3);
+        srgb.srgb = true;
+        assert_same(&from_ktx2(&to_ktx2(&srgb)).unwrap(), &srgb);
+    }
+
+    #[test]
+    fn dds_round_trips() {
+        for format in FORMATS {
+            let image = image(format);
+            assert_same(&from_dds(&to_dds(&image)).unwrap(), &image);
+        }
+    }
+
+    #[test]
+    fn more_mips_than_the_texture_has_are_refused() {
+        let mut dds = dds_header(1, 1, 70, b"DXT1");
+        dds.extend_from_slice(&[0; 8 * 70]);
+        assert!(from_dds(&dds).is_err());
+
+        let mut ktx2 = to_ktx2(&compress(&[0; 4], 1, 1, BlockFormat::Bc1));
+        let level_count = KTX2_IDENTIFIER.len() + 7 * 4;
+        ktx2[level_count..level_count + 4].copy_from_slice(&70u32.to_le_bytes());
+        assert!(from_ktx2(&ktx2).is_err());
+    }
+
+    #[test]
+    fn malformed_headers_are_refused() {
+        assert!(from_dds(&dds_header(u32::MAX, u32::MAX, 1, b"DXT1")).is_err());
+        assert!(from_dds(&dds_header(0, 4, 1, b"DXT1")).is_err());
+        assert!(from_dds(&dds_header(8, 8, 1, b"RGBA")).is_err());
+        // Header only, no texels
+        assert!(from_dds(&dds_header(8, 8, 1, b"DXT1")).is_err());
+        assert!(from_dds(&DDS_MAGIC[..]).is_err());
+
+        let ktx2 = to_ktx2(&image(BlockFormat::Bc4));
+        assert!(from_ktx2(&ktx2[..ktx2.len() - 1]).is_err());
+        assert!(from_ktx2(&ktx2[..KTX2_HEADER_SIZE]).is_err());
+        let mut huge = ktx2.clone();
+        let width = KTX2_IDENTIFIER.len() + 2 * 4;
+        huge[width..width + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(from_ktx2(&huge).is_err());
+    }
+}
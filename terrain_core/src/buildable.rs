@@ -0,0 +1,98 @@
+//! Where there's room to build: ground that's flat enough, in patches big enough to
+//! put something on. For checking that a map has space for a city builder's towns.
+
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::heightfield::HeightField;
+use crate::math;
+use crate::scatter::Surface;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct BuildableSettings {
+    /// Degrees from horizontal, anything steeper can't be built on
+    pub max_slope: f32,
+    /// Square meters, smaller flat patches don't count
+    pub min_area: f32,
+}
+
+impl Default for BuildableSettings {
+    fn default() -> Self {
+        BuildableSettings {
+            max_slope: 8.0,
+            min_area: 10_000.0,
+        }
+    }
+}
+
+pub struct BuildableAreas {
+    /// 1 where it can be built on, 0 elsewhere, the size of the heightfield
+    pub mask: HeightField,
+    /// Square meters of every region that can be built on, the largest first
+    pub regions: Vec<f32>,
+}
+
+/// Texels no steeper than the settings allow, joined into regions along their sides.
+/// Only the regions with at least the minimum area are kept.
+pub fn buildable_areas(surface: &Surface, settings: &BuildableSettings) -> BuildableAreas {
+    let size = surface.field.size();
+    let texel_size = surface.world_size / size as f32;
+    let heights = surface.field.data();
+    let max_rise = math::tan(settings.max_slope.clamp(0.0, 89.0).to_radians());
+    let flat: Vec<bool> = (0..size * size)
+        .into_par_iter()
+        .map(|i| {
+            let (x, y) = (i % size, i / size);
+            let (left, right) = (x.saturating_sub(1), (x + 1).min(size - 1));
+            let (up, down) = (y.saturating_sub(1), (y + 1).min(size - 1));
+            let height = |x: usize, y: usize| heights[y * size + x] * surface.max_height;
+            let dx = (height(right, y) - height(left, y)) / ((right - left) as f32 * texel_size);
+            let dz = (height(x, down) - height(x, up)) / ((down - up) as f32 * texel_size);
+            dx.hypot(dz) <= max_rise
+        })
+        .collect();
+
+    let texel_area = texel_size * texel_size;
+    let min_texels = ((settings.min_area / texel_area).ceil() as usize).max(1);
+    let mut mask = vec![0.0; size * size];
+    let mut regions = vec![];
+    let mut seen = vec![false; size * size];
+    let mut stack = vec![];
+    let mut region = vec![];
+    for start in 0..size * size {
+        if !flat[start] || seen[start] {
+            continue;
+        }
+        region.clear();
+        seen[start] = true;
+        stack.push(start);
+        while let Some(i) = stack.pop() {
+            region.push(i);
+            let (x, y) = (i % size, i / size);
+            let neighbours = [
+                (x > 0).then(|| i - 1),
+                (x + 1 < size).then(|| i + 1),
+                (y > 0).then(|| i - size),
+                (y + 1 < size).then(|| i + size),
+            ];
+            for &n in neighbours.iter().flatten() {
+                if flat[n] && !seen[n] {
+                    seen[n] = true;
+                    stack.push(n);
+                }
+            }
+        }
+        if region.len() >= min_texels {
+            for &i in &region {
+                mask[i] = 1.0;
+            }
+            regions.push(region.len() as f32 * texel_area);
+        }
+    }
+    regions.sort_by(|a, b| b.total_cmp(a));
+
+    BuildableAreas {
+        mask: HeightField::from_data(size, mask),
+        regions,
+    }
+}
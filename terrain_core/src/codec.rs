@@ -0,0 +1,188 @@
+//! Packs 16-bit heightmaps so that a general purpose compressor (the zstd of a
+//! project archive) gets them small. Every texel is predicted from its neighbours the
+//! way lossless JPEG does and only the error is kept, which is close to zero almost
+//! everywhere on real terrain. High and low bytes go in separate planes, the high
+//! bytes then being nearly all zero.
+//!
+//! Heights can also be quantized to fewer bits first. That's lossy, but smooth
+//! terrain has little use for all 16 bits and the residuals get a lot smaller.
+
+const MAGIC: &[u8; 4] = b"THM1";
+const HEADER_SIZE: usize = 9;
+
+pub const LOSSLESS_BITS: u8 = 16;
+pub const MIN_BITS: u8 = 8;
+
+/// `bits` of precision are kept, 16 is lossless
+pub fn encode(pixels: &[u16], size: usize, bits: u8) -> Vec<u8> {
+    assert_eq!(pixels.len(), size * size);
+    let bits = bits.clamp(MIN_BITS, LOSSLESS_BITS);
+    let levels: Vec<u16> = pixels.iter().map(|&p| quantize(p, bits)).collect();
+
+    let mut bytes = Vec::with_capacity(HEADER_SIZE + pixels.len() * 2);
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&(size as u32).to_le_bytes());
+    bytes.push(bits);
+    let residuals: Vec<u16> = (0..size * size)
+        .map(|i| {
+            let predicted = predict(&levels, size, i);
+            zigzag(levels[i].wrapping_sub(predicted) as i16)
+        })
+        .collect();
+    bytes.extend(residuals.iter().map(|r| (r >> 8) as u8));
+    bytes.extend(residuals.iter().map(|&r| r as u8));
+    bytes
+}
+
+/// Pixels and texels per side, none if the bytes aren't a heightmap from `encode`
+pub fn decode(bytes: &[u8]) -> Option<(Vec<u16>, usize)> {
+    if bytes.len() < HEADER_SIZE || &bytes[..4] != MAGIC {
+        return None;
+    }
+    let size = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]) as usize;
+    let bits = bytes[8];
+    let texels = size.checked_mul(size)?;
+    let expected = texels.checked_mul(2)?.checked_add(HEADER_SIZE)?;
+    if !(MIN_BITS..=LOSSLESS_BITS).contains(&bits) || bytes.len() != expected {
+        return None;
+    }
+    let (high, low) = bytes[HEADER_SIZE..].split_at(texels);
+
+    let mut levels = vec![0u16; texels];
+    for i in 0..texels {
+        let residual = unzigzag(u16::from_be_bytes([high[i], low[i]]));
+        levels[i] = predict(&levels, size, i).wrapping_add(residual as u16);
+    }
+    let pixels = levels.iter().map(|&q| dequantize(q, bits)).collect();
+    Some((pixels, size))
+}
+
+/// Largest difference quantizing to `bits` makes, as a fraction of the max height
+pub fn max_error(bits: u8) -> f32 {
+    if bits >= LOSSLESS_BITS {
+        return 0.0;
+    }
+    0.5 / highest_level(bits) as f32
+}
+
+fn highest_level(bits: u8) -> u32 {
+    (1 << bits.clamp(MIN_BITS, LOSSLESS_BITS)) - 1
+}
+
+/// 0 to 65535 spread over 2^`bits` levels
+fn quantize(pixel: u16, bits: u8) -> u16 {
+    let levels = highest_level(bits);
+    ((pixel as u32 * levels + u16::MAX as u32 / 2) / u16::MAX as u32) as u16
+}
+
+fn dequantize(level: u16, bits: u8) -> u16 {
+    let levels = highest_level(bits);
+    ((level as u32 * u16::MAX as u32 + levels / 2) / levels) as u16
+}
+
+/// Median edge detector from LOCO-I: picks the left or upper neighbour next to an
+/// edge and the plane through all three otherwise
+fn predict(levels: &[u16], size: usize, i: usize) -> u16 {
+    let (x, y) = (i % size, i / size);
+    match (x, y) {
+        (0, 0) => 0,
+        (_, 0) => levels[i - 1],
+        (0, _) => levels[i - size],
+        _ => {
+            let left = levels[i - 1] as i32;
+            let up = levels[i - size] as i32;
+            let up_left = levels[i - size - 1] as i32;
+            let predicted = if up_left >= left.max(up) {
+                left.min(up)
+            } else if up_left <= left.min(up) {
+                left.max(up)
+            } else {
+                left + up - up_left
+            };
+            predicted as u16
+        }
+    }
+}
+
+/// Small residuals either side of zero become small numbers
+fn zigzag(value: i16) -> u16 {
+    ((value << 1) ^ (value >> 15)) as u16
+}
+
+fn unzigzag(value: u16) -> i16 {
+    ((value >> 1) as i16) ^ -((value & 1) as i16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Rolling terrain with a cliff and both extremes in it
+    fn heightmap(size: usize) -> Vec<u16> {
+        let mut pixels: Vec<u16> = (0..size * size)
+            .map(|i| {
+                let (x, y) = ((i % size) as f32, (i / size) as f32);
+                let hills = 20_000.0 + 9_000.0 * (x * 0.21).sin() + 7_000.0 * (y * 0.13).cos();
+                let cliff = if x > size as f32 / 2.0 { 15_000.0 } else { 0.0 };
+                (hills + cliff) as u16
+            })
+            .collect();
+        pixels[0] = 0;
+        pixels[1] = u16::MAX;
+        pixels
+    }
+
+    #[test]
+    fn sixteen_bits_is_lossless() {
+        let pixels = heightmap(37);
+        let packed = encode(&pixels, 37, LOSSLESS_BITS);
+        assert_eq!(decode(&packed), Some((pixels, 37)));
+    }
+
+    #[test]
+    fn fewer_bits_stay_within_the_max_error() {
+        let pixels = heightmap(37);
+        for bits in MIN_BITS..LOSSLESS_BITS {
+            let (decoded, size) = decode(&encode(&pixels, 37, bits)).unwrap();
+            assert_eq!(size, 37);
+            let worst = pixels
+                .iter()
+                .zip(&decoded)
+                .map(|(&a, &b)| (a as i32 - b as i32).unsigned_abs())
+                .max()
+                .unwrap();
+            // Plus rounding the level back to 16 bits
+            let allowed = max_error(bits) * u16::MAX as f32;
+            assert!(
+                worst as f32 <= allowed + 0.5,
+                "{} bits off by {}",
+                bits,
+                worst
+            );
+        }
+    }
+
+    #[test]
+    fn empty_and_single_texel_maps_round_trip() {
+        assert_eq!(decode(&encode(&[], 0, LOSSLESS_BITS)), Some((vec![], 0)));
+        assert_eq!(
+            decode(&encode(&[1234], 1, LOSSLESS_BITS)),
+            Some((vec![1234], 1))
+        );
+    }
+
+    #[test]
+    fn anything_else_isnt_a_heightmap() {
+        let packed = encode(&heightmap(8), 8, LOSSLESS_BITS);
+        assert_eq!(decode(&packed[..packed.len() - 1]), None);
+        assert_eq!(decode(&packed[..4]), None);
+        assert_eq!(decode(b"PNG!and some more bytes"), None);
+
+        let mut huge = packed.clone();
+        huge[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(decode(&huge), None);
+        let mut bad_bits = packed;
+        bad_bits[8] = 3;
+        assert_eq!(decode(&bad_bits), None);
+    }
+}
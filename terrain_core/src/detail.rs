@@ -0,0 +1,152 @@
+//! Sparse heights finer than the heightmap, for carving paths and trenches a texel
+//! is too coarse for. Only tiles that have been carved into exist, each one an
+//! offset from the heightmap under it. Tiles are laid out in uv, so the heightmap
+//! can be resampled under them.
+
+use std::collections::BTreeMap;
+
+use glam::Vec2;
+
+use crate::heightfield::HeightField;
+
+/// Detail texels per heightmap texel along a side
+pub const DETAIL_SCALE: usize = 8;
+/// Heightmap texels along a tile side, at the resolution the layer was made for
+pub const TILE_TEXELS: usize = 16;
+/// Detail texels along a tile side
+pub const TILE_SIZE: usize = TILE_TEXELS * DETAIL_SCALE;
+/// Carving stops making new tiles after this many
+pub const MAX_TILES: usize = 256;
+
+pub type TileKey = (usize, usize);
+
+#[derive(Debug, Clone)]
+pub struct DetailLayer {
+    tiles_per_side: usize,
+    /// Normalised height offsets by tile
+    tiles: BTreeMap<TileKey, HeightField>,
+}
+
+impl DetailLayer {
+    /// `resolution` is the heightmap's, it decides how fine the detail is
+    pub fn new(resolution: usize) -> Self {
+        DetailLayer {
+            tiles_per_side: (resolution / TILE_TEXELS).max(1),
+            tiles: BTreeMap::new(),
+        }
+    }
+
+    pub fn tiles_per_side(&self) -> usize {
+        self.tiles_per_side
+    }
+
+    pub fn tile_count(&self) -> usize {
+        self.tiles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tiles.is_empty()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.tiles.len() >= MAX_TILES
+    }
+
+    pub fn tile(&self, key: TileKey) -> Option<&HeightField> {
+        self.tiles.get(&key)
+    }
+
+    pub fn tiles(&self) -> impl Iterator<Item = (&TileKey, &HeightField)> {
+        self.tiles.iter()
+    }
+
+    pub fn clear(&mut self) {
+        self.tiles.clear();
+    }
+
+    /// Bilinear within a tile, 0 where nothing has been carved
+    pub fn offset_at(&self, uv: Vec2) -> f32 {
+        let tiles = self.tiles_per_side as f32;
+        let max = (self.tiles_per_side - 1) as f32;
+        let tile = (uv * tiles).floor().clamp(Vec2::ZERO, Vec2::splat(max));
+        match self.tiles.get(&(tile.x as usize, tile.y as usize)) {
+            Some(field) => {
+                let local = uv * tiles - tile;
+                field.sample(local.x, local.y)
+            }
+            None => 0.0,
+        }
+    }
+
+    /// Adds `amount(uv)` to every detail texel within `radius` of `center`, both in
+    /// uv. Tiles are made where the amount isn't 0, until there are `MAX_TILES`.
+    /// Returns the tiles that changed.
+    pub fn carve(
+        &mut self,
+        center: Vec2,
+        radius: f32,
+        amount: impl Fn(Vec2) -> f32,
+    ) -> Vec<TileKey> {
+        let size = self.tiles_per_side * TILE_SIZE;
+        let to_texel = |uv: f32| ((uv * size as f32).max(0.0) as usize).min(size);
+        let (x0, x1) = (to_texel(center.x - radius), to_texel(center.x + radius) + 1);
+        let (y0, y1) = (to_texel(center.y - radius), to_texel(center.y + radius) + 1);
+
+        let mut changed = vec![];
+        for y in y0..y1.min(size) {
+            for x in x0..x1.min(size) {
+                let uv = (Vec2::new(x as f32, y as f32) + 0.5) / size as f32;
+                let value = amount(uv);
+                if value == 0.0 {
+                    continue;
+                }
+                let key = (x / TILE_SIZE, y / TILE_SIZE);
+                if !self.tiles.contains_key(&key) {
+                    if self.is_full() {
+                        continue;
+                    }
+                    let empty = HeightField::from_data(TILE_SIZE, vec![0.0; TILE_SIZE * TILE_SIZE]);
+                    self.tiles.insert(key, empty);
+                }
+                let tile = self.tiles.get_mut(&key).unwrap();
+                tile.data_mut()[(y % TILE_SIZE) * TILE_SIZE + x % TILE_SIZE] += value;
+                if !changed.contains(&key) {
+                    changed.push(key);
+                }
+            }
+        }
+        changed
+    }
+
+    /// Adds the detail to heights of any resolution. Where a texel covers several
+    /// detail texels it gets their average, so a narrow trench still shows, only
+    /// shallower.
+    pub fn add_to(&self, heights: &mut HeightField) {
+        let size = heights.size();
+        let detail_size = self.tiles_per_side * TILE_SIZE;
+        if size >= detail_size {
+            let texels_per_tile = size / self.tiles_per_side;
+            for &(tile_x, tile_y) in self.tiles.keys() {
+                for y in tile_y * texels_per_tile..(tile_y + 1) * texels_per_tile {
+                    for x in tile_x * texels_per_tile..(tile_x + 1) * texels_per_tile {
+                        let uv = (Vec2::new(x as f32, y as f32) + 0.5) / size as f32;
+                        heights.data_mut()[y * size + x] += self.offset_at(uv);
+                    }
+                }
+            }
+        } else {
+            let ratio = detail_size / size;
+            let weight = 1.0 / (ratio * ratio) as f32;
+            for (&(tile_x, tile_y), tile) in &self.tiles {
+                for (i, &offset) in tile.data().iter().enumerate() {
+                    let x = (tile_x * TILE_SIZE + i % TILE_SIZE) / ratio;
+                    let y = (tile_y * TILE_SIZE + i / TILE_SIZE) / ratio;
+                    heights.data_mut()[y * size + x] += offset * weight;
+                }
+            }
+        }
+        for height in heights.data_mut() {
+            *height = height.clamp(0.0, 1.0);
+        }
+    }
+}
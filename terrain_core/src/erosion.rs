@@ -0,0 +1,360 @@
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::heightfield::HeightField;
+use crate::math;
+use crate::tiled::{process_tiled, TileSettings};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ErosionSettings {
+    pub iterations: u32,
+    /// Slopes steeper than this collapse, degrees
+    pub talus_angle: f32,
+    /// Fraction of the excess material that moves each iteration
+    pub strength: f32,
+    pub tiles: TileSettings,
+}
+
+impl Default for ErosionSettings {
+    fn default() -> Self {
+        ErosionSettings {
+            iterations: 30,
+            talus_angle: 35.0,
+            strength: 0.5,
+            tiles: TileSettings::default(),
+        }
+    }
+}
+
+/// Thermal erosion: material slides down from slopes steeper than the talus angle.
+/// `texel_size` and `max_height` are in meters and turn the angle into a height difference.
+pub fn thermal_erosion(
+    field: &HeightField,
+    settings: &ErosionSettings,
+    texel_size: f32,
+    max_height: f32,
+) -> HeightField {
+    let talus = math::tan(settings.talus_angle.to_radians()) * texel_size / max_height;
+    let tiles = TileSettings {
+        // Material can travel one texel per iteration
+        overlap: settings.tiles.overlap.max(settings.iterations as usize),
+        ..settings.tiles
+    };
+    process_tiled(field, tiles, |tile| {
+        let mut outflow = vec![[0.0; 4]; tile.size() * tile.size()];
+        for _ in 0..settings.iterations {
+            thermal_erosion_step(tile, &mut outflow, talus, settings.strength);
+        }
+    })
+}
+
+const NEIGHBOURS: [(isize, isize); 4] = [(-1, 0), (1, 0), (0, -1), (0, 1)];
+
+/// Two passes so that rows can be processed in parallel: first every texel
+/// works out how much it sends to each neighbour, then every texel
+/// gathers what its neighbours sent to it.
+fn thermal_erosion_step(
+    field: &mut HeightField,
+    outflow: &mut [[f32; 4]],
+    talus: f32,
+    strength: f32,
+) {
+    let size = field.size();
+    let max = size as isize - 1;
+
+    {
+        let heights = field.data();
+        outflow
+            .par_chunks_mut(size)
+            .enumerate()
+            .for_each(|(y, row)| {
+                let y = y as isize;
+                for (x, out) in row.iter_mut().enumerate() {
+                    let x = x as isize;
+                    let h = heights[y as usize * size + x as usize];
+                    let mut excess = [0.0f32; 4];
+                    for (i, &(dx, dy)) in NEIGHBOURS.iter().enumerate() {
+                        let (nx, ny) = (x + dx, y + dy);
+                        if nx < 0 || ny < 0 || nx > max || ny > max {
+                            continue;
+                        }
+                        excess[i] =
+                            (h - heights[ny as usize * size + nx as usize] - talus).max(0.0);
+                    }
+                    let total_excess: f32 = excess.iter().sum();
+                    let max_excess = excess.iter().cloned().fold(0.0, f32::max);
+                    // Move half of the biggest excess so that we never overshoot
+                    let moved = max_excess * 0.5 * strength;
+                    for (out, &excess) in out.iter_mut().zip(&excess) {
+                        *out = if total_excess > 0.0 {
+                            moved * excess / total_excess
+                        } else {
+                            0.0
+                        };
+                    }
+                }
+            });
+    }
+
+    let outflow = &*outflow;
+    field
+        .data_mut()
+        .par_chunks_mut(size)
+        .enumerate()
+        .for_each(|(y, row)| {
+            let y = y as isize;
+            for (x, h) in row.iter_mut().enumerate() {
+                let x = x as isize;
+                let out = &outflow[y as usize * size + x as usize];
+                let mut change = -out.iter().sum::<f32>();
+                for (i, &(dx, dy)) in NEIGHBOURS.iter().enumerate() {
+                    let (nx, ny) = (x + dx, y + dy);
+                    if nx < 0 || ny < 0 || nx > max || ny > max {
+                        continue;
+                    }
+                    // The neighbour sends to us in the opposite direction
+                    change += outflow[ny as usize * size + nx as usize][i ^ 1];
+                }
+                *h += change;
+            }
+        });
+}
+
+/// Hydraulic erosion by droplets that run downhill, picking up sediment where they
+/// speed up and dropping it where they slow down. Cheap enough to run under a brush
+/// while it's being dragged.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DropletSettings {
+    /// Droplets released per second where the brush is at full strength
+    pub rate: f32,
+    /// Steps a droplet takes before it's gone
+    pub lifetime: u32,
+    /// How much a droplet keeps going its own way rather than downhill, 0 to 1
+    pub inertia: f32,
+    /// Sediment a droplet can carry for its speed, water and the slope it's on
+    pub capacity: f32,
+    /// Fraction of the spare capacity taken from the ground every step
+    pub erosion: f32,
+    /// Fraction of the excess sediment dropped every step
+    pub deposition: f32,
+    /// Fraction of the water lost every step
+    pub evaporation: f32,
+}
+
+impl Default for DropletSettings {
+    fn default() -> Self {
+        DropletSettings {
+            rate: 2000.0,
+            lifetime: 30,
+            inertia: 0.05,
+            capacity: 1.0,
+            erosion: 0.1,
+            deposition: 0.3,
+            evaporation: 0.02,
+        }
+    }
+}
+
+/// Keeps droplets on flat ground carrying something
+const MIN_CAPACITY_SLOPE: f32 = 0.01;
+const GRAVITY: f32 = 4.0;
+
+/// Runs a droplet from every start, given in texels. `height_scale` turns heights
+/// into texels, so that slopes mean the same at any resolution. The ground only
+/// changes as much as `mask` (in [0, 1] for a texel) lets it, droplets that leave
+/// the mask keep flowing but stop carving.
+pub fn droplet_erosion(
+    field: &mut HeightField,
+    starts: &[(f32, f32)],
+    settings: &DropletSettings,
+    height_scale: f32,
+    mask: impl Fn(usize, usize) -> f32,
+) {
+    let size = field.size();
+    if size < 2 {
+        return;
+    }
+    // The cell a droplet is in has to have texels on all four corners
+    let end = (size - 1) as f32;
+    let inside = |x: f32, y: f32| x >= 0.0 && y >= 0.0 && x < end && y < end;
+
+    for &(mut x, mut y) in starts {
+        let (mut dx, mut dy) = (0.0f32, 0.0f32);
+        let mut speed = 1.0f32;
+        let mut water = 1.0f32;
+        let mut sediment = 0.0f32;
+
+        for _ in 0..settings.lifetime {
+            if !inside(x, y) {
+                break;
+            }
+            let (height, gx, gy) = height_and_gradient(field, x, y);
+            dx = dx * settings.inertia - gx * (1.0 - settings.inertia);
+            dy = dy * settings.inertia - gy * (1.0 - settings.inertia);
+            let length = (dx * dx + dy * dy).sqrt();
+            if length < f32::EPSILON {
+                // Stuck in a pit or on a flat
+                break;
+            }
+            dx /= length;
+            dy /= length;
+            let (old_x, old_y) = (x, y);
+            x += dx;
+            y += dy;
+            if !inside(x, y) {
+                break;
+            }
+
+            let drop = (height - height_and_gradient(field, x, y).0) * height_scale;
+            let capacity = drop.max(MIN_CAPACITY_SLOPE) * speed * water * settings.capacity;
+            if drop < 0.0 || sediment > capacity {
+                // Uphill fills the hole behind, as far as the sediment goes
+                let amount = if drop < 0.0 {
+                    sediment.min(-drop)
+                } else {
+                    (sediment - capacity) * settings.deposition
+                };
+                sediment -= amount;
+                add_bilinear(field, old_x, old_y, amount / height_scale, &mask);
+            } else {
+                // Never dig deeper than the drop, that leaves spikes behind
+                let amount = ((capacity - sediment) * settings.erosion).min(drop);
+                sediment -=
+                    add_bilinear(field, old_x, old_y, -amount / height_scale, &mask) * height_scale;
+            }
+
+            speed = (speed * speed + drop * GRAVITY).max(0.0).sqrt();
+            water *= 1.0 - settings.evaporation;
+        }
+    }
+}
+
+/// Bilinear height at a point in texels, and its slope in x and y
+fn height_and_gradient(field: &HeightField, x: f32, y: f32) -> (f32, f32, f32) {
+    let size = field.size();
+    let heights = field.data();
+    let (x0, y0) = (x as usize, y as usize);
+    let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+    let i = y0 * size + x0;
+    let (h00, h10) = (heights[i], heights[i + 1]);
+    let (h01, h11) = (heights[i + size], heights[i + size + 1]);
+    let height = (h00 * (1.0 - fx) + h10 * fx) * (1.0 - fy) + (h01 * (1.0 - fx) + h11 * fx) * fy;
+    let gx = (h10 - h00) * (1.0 - fy) + (h11 - h01) * fy;
+    let gy = (h01 - h00) * (1.0 - fx) + (h11 - h10) * fx;
+    (height, gx, gy)
+}
+
+/// Spreads `amount` over the four texels around a point, as much as the mask lets
+/// through, keeping heights in [0, 1]. Returns how much actually went on.
+fn add_bilinear(
+    field: &mut HeightField,
+    x: f32,
+    y: f32,
+    amount: f32,
+    mask: &impl Fn(usize, usize) -> f32,
+) -> f32 {
+    let size = field.size();
+    let (x0, y0) = (x as usize, y as usize);
+    let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+    let corners = [
+        (x0, y0, (1.0 - fx) * (1.0 - fy)),
+        (x0 + 1, y0, fx * (1.0 - fy)),
+        (x0, y0 + 1, (1.0 - fx) * fy),
+        (x0 + 1, y0 + 1, fx * fy),
+    ];
+    let heights = field.data_mut();
+    let mut added = 0.0;
+    for (cx, cy, weight) in corners {
+        let h = &mut heights[cy * size + cx];
+        let new = (*h + amount * weight * mask(cx, cy)).clamp(0.0, 1.0);
+        added += new - *h;
+        *h = new;
+    }
+    added
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::golden;
+    use crate::noise::fbm;
+
+    fn noise_field(size: usize) -> HeightField {
+        let data = (0..size * size)
+            .map(|i| fbm(11, (i % size) as f32 / 12.0, (i / size) as f32 / 12.0, 5))
+            .collect();
+        HeightField::from_data(size, data)
+    }
+
+    #[test]
+    fn thermal_erosion_is_the_same_for_the_same_input() {
+        let eroded = thermal_erosion(&noise_field(96), &ErosionSettings::default(), 1.0, 20.0);
+        assert_eq!(
+            golden::hash(eroded.data().iter().copied()),
+            2031945339651187236
+        );
+    }
+
+    #[test]
+    fn thermal_erosion_wears_cliffs_down() {
+        let size = 64;
+        let cliff = (0..size * size).map(|i| if i % size < size / 2 { 0.0 } else { 1.0 });
+        let field = HeightField::from_data(size, cliff.collect());
+        let eroded = thermal_erosion(&field, &ErosionSettings::default(), 1.0, 20.0);
+        let steepest = eroded
+            .data()
+            .chunks(size)
+            .flat_map(|row| row.windows(2).map(|pair| (pair[1] - pair[0]).abs()))
+            .fold(0.0, f32::max);
+        assert!(steepest < 0.5, "steepest step {}", steepest);
+    }
+
+    #[test]
+    fn droplet_erosion_is_the_same_for_the_same_starts() {
+        let mut field = noise_field(64);
+        let starts: Vec<_> = (0..500)
+            .map(|i| {
+                let t = i as f32;
+                ((t * 7.31) % 63.0, (t * 3.77) % 63.0)
+            })
+            .collect();
+        droplet_erosion(
+            &mut field,
+            &starts,
+            &DropletSettings::default(),
+            64.0,
+            |_, _| 1.0,
+        );
+        assert_eq!(
+            golden::hash(field.data().iter().copied()),
+            15714276514179541975
+        );
+    }
+
+    #[test]
+    fn droplet_erosion_leaves_masked_ground_alone() {
+        let field = noise_field(64);
+        let mut eroded = field.clone();
+        let starts: Vec<_> = (0..200)
+            .map(|i| ((i % 60) as f32 + 1.5, (i / 4) as f32 + 1.5))
+            .collect();
+        droplet_erosion(
+            &mut eroded,
+            &starts,
+            &DropletSettings::default(),
+            64.0,
+            |x, _| {
+                if x < 32 {
+                    0.0
+                } else {
+                    1.0
+                }
+            },
+        );
+        for y in 0..64 {
+            for x in 0..32 {
+                assert_eq!(eroded.data()[y * 64 + x], field.data()[y * 64 + x]);
+            }
+        }
+    }
+}
@@ -0,0 +1,46 @@
+use crate::filters::normals;
+use crate::gradient::ColorGradient;
+use crate::heightfield::HeightField;
+use crate::horizon::{horizon_angles, HORIZON_DIRECTIONS};
+
+/// Tangent space normals packed into RGB bytes, Y up in the blue channel
+/// the way most engines expect from a heightmap normal map
+pub fn normal_map_rgb(field: &HeightField, texel_size: f32, max_height: f32) -> Vec<u8> {
+    let to_byte = |v: f32| ((v * 0.5 + 0.5) * 255.0).round() as u8;
+    normals(field, texel_size, max_height)
+        .iter()
+        .flat_map(|n| [to_byte(n.x), to_byte(n.z), to_byte(n.y)])
+        .collect()
+}
+
+/// Every texel colored by its height, for stylised maps. sRGB bytes, the
+/// gradient itself is linear.
+pub fn hypsometric_rgb(field: &HeightField, gradient: &ColorGradient) -> Vec<u8> {
+    let to_byte = |v: f32| (v.clamp(0.0, 1.0).powf(1.0 / 2.2) * 255.0).round() as u8;
+    field
+        .data()
+        .iter()
+        .flat_map(|&h| {
+            let color = gradient.color_at(h);
+            [to_byte(color.x), to_byte(color.y), to_byte(color.z)]
+        })
+        .collect()
+}
+
+/// How much of the sky every texel of `field` resampled to `size` sees, white where
+/// nothing is in the way. The same as the editor's sky light: the sky seen past the
+/// horizon, averaged over every direction.
+pub fn ambient_occlusion_gray(
+    field: &HeightField,
+    size: usize,
+    world_size: f32,
+    max_height: f32,
+) -> Vec<u8> {
+    horizon_angles(field, size, world_size, max_height)
+        .iter()
+        .map(|horizon| {
+            let open: f32 = horizon.iter().map(|sine| 1.0 - sine * sine).sum();
+            (open / HORIZON_DIRECTIONS as f32 * 255.0).round() as u8
+        })
+        .collect()
+}
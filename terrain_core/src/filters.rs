@@ -0,0 +1,812 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use glam::Vec3;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::heightfield::{EdgeMode, HeightField};
+use crate::math;
+use crate::noise::smoothstep;
+use crate::simd;
+
+pub const MAX_CURVE_POINTS: usize = 16;
+
+/// Samples of the curve a heightmap is remapped through, heights in between are interpolated
+const CURVE_LUT_SIZE: usize = 1024;
+
+/// How much higher each texel of a filled sink is than the one it drains into, so
+/// that water crossing the flat still has somewhere to go. Just over the f32 precision
+/// of heights near 1.
+const SINK_SLOPE: f32 = 1e-7;
+
+/// Heights are normalised, so amounts and ranges are fractions of the max height.
+/// Results are clamped to 0..1. Filters that look at the texels around `edges` say
+/// what's past the border.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum HeightFilter {
+    Blur {
+        radius: usize,
+        #[serde(default)]
+        edges: EdgeMode,
+    },
+    /// Unsharp mask: adds back `amount` times what a blur of `radius` takes away
+    Sharpen {
+        radius: usize,
+        amount: f32,
+        #[serde(default)]
+        edges: EdgeMode,
+    },
+    Terrace {
+        steps: u32,
+        sharpness: f32,
+    },
+    Add {
+        amount: f32,
+    },
+    Multiply {
+        factor: f32,
+    },
+    Clamp {
+        min: f32,
+        max: f32,
+    },
+    /// Peaks become valleys
+    Invert,
+    /// Stretches the lowest point to `min` and the highest to `max`
+    Normalize {
+        min: f32,
+        max: f32,
+    },
+    /// Exponents over 1 flatten the lowlands and steepen the peaks
+    Power {
+        exponent: f32,
+    },
+    Curve(HeightCurve),
+    /// Median filter for spikes and pits a single texel or so wide. Only texels more than
+    /// `threshold` away from the median of their neighbourhood are replaced, so the
+    /// rest of the detail stays.
+    Despeckle {
+        radius: usize,
+        threshold: f32,
+        #[serde(default)]
+        edges: EdgeMode,
+    },
+    /// Raises every pit up to where it would overflow, so that water reaches the edge
+    /// of the terrain from everywhere
+    FillSinks,
+    /// Blends the heights within `width` of each edge, a fraction of the map, with
+    /// those across from them so that opposite edges meet and the map tiles
+    MakeTileable {
+        width: f32,
+    },
+}
+
+impl HeightFilter {
+    pub fn apply(&self, field: &HeightField) -> HeightField {
+        match *self {
+            HeightFilter::Blur { radius, edges } => blur(field, radius, edges),
+            HeightFilter::Sharpen {
+                radius,
+                amount,
+                edges,
+            } => sharpen(field, radius, amount, edges),
+            HeightFilter::Terrace { steps, sharpness } => terrace(field, steps, sharpness),
+            HeightFilter::Add { amount } => map(field, |h| h + amount),
+            HeightFilter::Multiply { factor } => map(field, |h| h * factor),
+            HeightFilter::Clamp { min, max } => map(field, |h| h.clamp(min, max.max(min))),
+            HeightFilter::Invert => map(field, |h| 1.0 - h),
+            HeightFilter::Normalize { min, max } => normalize(field, min, max),
+            HeightFilter::Power { exponent } => map(field, |h| math::powf(h, exponent)),
+            HeightFilter::Curve(curve) => {
+                let lut = curve.lookup_table(CURVE_LUT_SIZE);
+                let last = (CURVE_LUT_SIZE - 1) as f32;
+                map(field, |h| {
+                    let x = h.clamp(0.0, 1.0) * last;
+                    let i = (x as usize).min(CURVE_LUT_SIZE - 2);
+                    lut[i] + (lut[i + 1] - lut[i]) * (x - i as f32)
+                })
+            }
+            HeightFilter::Despeckle {
+                radius,
+                threshold,
+                edges,
+            } => despeckle(field, radius, threshold, edges),
+            HeightFilter::FillSinks => fill_sinks(field),
+            HeightFilter::MakeTileable { width } => make_tileable(field, width),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterKind {
+    Blur,
+    Sharpen,
+    Terrace,
+    Add,
+    Multiply,
+    Clamp,
+    Invert,
+    Normalize,
+    Power,
+    Curve,
+    Despeckle,
+    FillSinks,
+    MakeTileable,
+}
+
+impl FilterKind {
+    pub const ALL: [FilterKind; 13] = [
+        FilterKind::Blur,
+        FilterKind::Sharpen,
+        FilterKind::Terrace,
+        FilterKind::Add,
+        FilterKind::Multiply,
+        FilterKind::Clamp,
+        FilterKind::Invert,
+        FilterKind::Normalize,
+        FilterKind::Power,
+        FilterKind::Curve,
+        FilterKind::Despeckle,
+        FilterKind::FillSinks,
+        FilterKind::MakeTileable,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FilterKind::Blur => "Blur",
+            FilterKind::Sharpen => "Sharpen",
+            FilterKind::Terrace => "Terrace",
+            FilterKind::Add => "Add",
+            FilterKind::Multiply => "Multiply",
+            FilterKind::Clamp => "Clamp",
+            FilterKind::Invert => "Invert",
+            FilterKind::Normalize => "Normalize",
+            FilterKind::Power => "Power curve",
+            FilterKind::Curve => "Curves",
+            FilterKind::Despeckle => "Despeckle",
+            FilterKind::FillSinks => "Fill sinks",
+            FilterKind::MakeTileable => "Make tileable",
+        }
+    }
+}
+
+/// What the filter controls in the editor are set to
+pub struct FilterSettings {
+    pub kind: FilterKind,
+    /// Show the selected filter on the terrain before applying it
+    pub preview: bool,
+    pub blur_radius: usize,
+    pub sharpen_amount: f32,
+    pub terrace_steps: u32,
+    pub terrace_sharpness: f32,
+    pub add_amount: f32,
+    pub multiply_factor: f32,
+    /// Also the range for normalize
+    pub min: f32,
+    pub max: f32,
+    pub exponent: f32,
+    pub curve: HeightCurve,
+    pub despeckle_radius: usize,
+    pub despeckle_threshold: f32,
+    pub tileable_width: f32,
+}
+
+impl Default for FilterSettings {
+    fn default() -> Self {
+        FilterSettings {
+            kind: FilterKind::Blur,
+            preview: false,
+            blur_radius: 4,
+            sharpen_amount: 1.0,
+            terrace_steps: 8,
+            terrace_sharpness: 0.5,
+            add_amount: 0.05,
+            multiply_factor: 1.2,
+            min: 0.0,
+            max: 1.0,
+            exponent: 1.5,
+            curve: HeightCurve::default(),
+            despeckle_radius: 1,
+            despeckle_threshold: 0.005,
+            tileable_width: 0.1,
+        }
+    }
+}
+
+impl FilterSettings {
+    /// The selected filter with its parameters
+    pub fn filter(&self, edges: EdgeMode) -> HeightFilter {
+        match self.kind {
+            FilterKind::Blur => HeightFilter::Blur {
+                radius: self.blur_radius,
+                edges,
+            },
+            FilterKind::Sharpen => HeightFilter::Sharpen {
+                radius: self.blur_radius,
+                amount: self.sharpen_amount,
+                edges,
+            },
+            FilterKind::Terrace => HeightFilter::Terrace {
+                steps: self.terrace_steps,
+                sharpness: self.terrace_sharpness,
+            },
+            FilterKind::Add => HeightFilter::Add {
+                amount: self.add_amount,
+            },
+            FilterKind::Multiply => HeightFilter::Multiply {
+                factor: self.multiply_factor,
+            },
+            FilterKind::Clamp => HeightFilter::Clamp {
+                min: self.min,
+                max: self.max,
+            },
+            FilterKind::Invert => HeightFilter::Invert,
+            FilterKind::Normalize => HeightFilter::Normalize {
+                min: self.min,
+                max: self.max,
+            },
+            FilterKind::Power => HeightFilter::Power {
+                exponent: self.exponent,
+            },
+            FilterKind::Curve => HeightFilter::Curve(self.curve),
+            FilterKind::Despeckle => HeightFilter::Despeckle {
+                radius: self.despeckle_radius,
+                threshold: self.despeckle_threshold,
+                edges,
+            },
+            FilterKind::FillSinks => HeightFilter::FillSinks,
+            FilterKind::MakeTileable => HeightFilter::MakeTileable {
+                width: self.tileable_width,
+            },
+        }
+    }
+}
+
+/// Maps heights in to heights out through a smooth curve drawn through a few points,
+/// like the curves of an image editor. The curve never overshoots its points, so
+/// it goes up wherever the points do and never folds the terrain over.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct HeightCurve {
+    /// Sorted by x, the first one at x = 0 and the last one at x = 1
+    points: [(f32, f32); MAX_CURVE_POINTS],
+    len: usize,
+}
+
+impl Default for HeightCurve {
+    /// Leaves the heights as they are
+    fn default() -> Self {
+        let mut points = [(0.0, 0.0); MAX_CURVE_POINTS];
+        points[1] = (1.0, 1.0);
+        HeightCurve { points, len: 2 }
+    }
+}
+
+impl HeightCurve {
+    pub fn points(&self) -> &[(f32, f32)] {
+        &self.points[..self.len]
+    }
+
+    /// Returns the index of the new point, None if the curve is full or there's
+    /// already a point at `x`
+    pub fn insert(&mut self, x: f32, y: f32) -> Option<usize> {
+        if self.len == MAX_CURVE_POINTS {
+            return None;
+        }
+        let x = x.clamp(0.0, 1.0);
+        let index = self.points().iter().position(|&(px, _)| px >= x)?;
+        if self.points[index].0 == x || index == 0 {
+            return None;
+        }
+        self.points.copy_within(index..self.len, index + 1);
+        self.points[index] = (x, y.clamp(0.0, 1.0));
+        self.len += 1;
+        Some(index)
+    }
+
+    /// The end points stay
+    pub fn remove(&mut self, index: usize) {
+        if index > 0 && index + 1 < self.len {
+            self.points.copy_within(index + 1..self.len, index);
+            self.len -= 1;
+        }
+    }
+
+    /// Points can't pass their neighbours and the end points only move up and down
+    pub fn move_point(&mut self, index: usize, x: f32, y: f32) {
+        let x = if index == 0 {
+            0.0
+        } else if index + 1 == self.len {
+            1.0
+        } else {
+            // Keep a little gap so the curve stays a function
+            let gap = 1e-3;
+            x.clamp(
+                self.points[index - 1].0 + gap,
+                self.points[index + 1].0 - gap,
+            )
+        };
+        self.points[index] = (x, y.clamp(0.0, 1.0));
+    }
+
+    pub fn evaluate(&self, x: f32) -> f32 {
+        self.evaluate_with(&self.tangents(), x)
+    }
+
+    /// `size` evenly spaced samples from x = 0 to x = 1
+    pub fn lookup_table(&self, size: usize) -> Vec<f32> {
+        let tangents = self.tangents();
+        let last = (size - 1) as f32;
+        (0..size)
+            .map(|i| self.evaluate_with(&tangents, i as f32 / last))
+            .collect()
+    }
+
+    fn evaluate_with(&self, tangents: &[f32], x: f32) -> f32 {
+        let points = self.points();
+        let x = x.clamp(0.0, 1.0);
+        let k = points[1..]
+            .iter()
+            .position(|&(px, _)| x <= px)
+            .unwrap_or(self.len - 2);
+        let ((x0, y0), (x1, y1)) = (points[k], points[k + 1]);
+        let h = x1 - x0;
+        let t = (x - x0) / h;
+        let (t2, t3) = (t * t, t * t * t);
+        let y = (2.0 * t3 - 3.0 * t2 + 1.0) * y0
+            + (t3 - 2.0 * t2 + t) * h * tangents[k]
+            + (-2.0 * t3 + 3.0 * t2) * y1
+            + (t3 - t2) * h * tangents[k + 1];
+        y.clamp(0.0, 1.0)
+    }
+
+    /// Fritsch-Carlson: tangents of a cubic Hermite spline that's monotonic
+    /// wherever the points are
+    fn tangents(&self) -> [f32; MAX_CURVE_POINTS] {
+        let points = self.points();
+        let n = self.len;
+        let mut slopes = [0.0; MAX_CURVE_POINTS];
+        for k in 0..n - 1 {
+            let ((x0, y0), (x1, y1)) = (points[k], points[k + 1]);
+            slopes[k] = (y1 - y0) / (x1 - x0);
+        }
+        let mut tangents = [0.0; MAX_CURVE_POINTS];
+        tangents[0] = slopes[0];
+        tangents[n - 1] = slopes[n - 2];
+        for k in 1..n - 1 {
+            tangents[k] = if slopes[k - 1] * slopes[k] > 0.0 {
+                (slopes[k - 1] + slopes[k]) / 2.0
+            } else {
+                0.0
+            };
+        }
+        for k in 0..n - 1 {
+            if slopes[k] == 0.0 {
+                tangents[k] = 0.0;
+                tangents[k + 1] = 0.0;
+                continue;
+            }
+            let a = tangents[k] / slopes[k];
+            let b = tangents[k + 1] / slopes[k];
+            let s = a * a + b * b;
+            if s > 9.0 {
+                let t = 3.0 / s.sqrt();
+                tangents[k] = t * a * slopes[k];
+                tangents[k + 1] = t * b * slopes[k];
+            }
+        }
+        tangents
+    }
+}
+
+/// Every height through `f`, clamped to 0..1
+fn map(field: &HeightField, f: impl Fn(f32) -> f32 + Sync) -> HeightField {
+    let mut result = field.clone();
+    result
+        .data_mut()
+        .par_iter_mut()
+        .for_each(|h| *h = f(*h).clamp(0.0, 1.0));
+    result
+}
+
+/// Separable gaussian blur, `radius` in texels
+pub fn blur(field: &HeightField, radius: usize, edges: EdgeMode) -> HeightField {
+    if radius == 0 {
+        return field.clone();
+    }
+    let kernel = gaussian_kernel(radius);
+    let size = field.size();
+    let src = field.data();
+
+    let mut horizontal = vec![0.0; size * size];
+    horizontal
+        .par_chunks_mut(size)
+        .enumerate()
+        .for_each(|(y, row)| {
+            // Pad with what's past the edges so every tap is a plain shifted slice
+            let src_row = &src[y * size..(y + 1) * size];
+            let r = radius as isize;
+            let mut padded = Vec::with_capacity(size + 2 * radius);
+            padded.extend((-r..0).map(|x| src_row[edges.index(x, size)]));
+            padded.extend_from_slice(src_row);
+            padded.extend((0..r).map(|x| src_row[edges.index(size as isize + x, size)]));
+            for (k, &weight) in kernel.iter().enumerate() {
+                simd::add_scaled(row, &padded[k..k + size], weight);
+            }
+        });
+
+    let r = radius as isize;
+    let mut data = vec![0.0; size * size];
+    data.par_chunks_mut(size).enumerate().for_each(|(y, row)| {
+        for (k, &weight) in kernel.iter().enumerate() {
+            let sy = edges.index(y as isize + k as isize - r, size);
+            simd::add_scaled(row, &horizontal[sy * size..(sy + 1) * size], weight);
+        }
+    });
+
+    HeightField::from_data(size, data)
+}
+
+/// Exaggerates the detail a blur of `radius` texels would remove
+pub fn sharpen(field: &HeightField, radius: usize, amount: f32, edges: EdgeMode) -> HeightField {
+    let blurred = blur(field, radius, edges);
+    let mut result = field.clone();
+    result
+        .data_mut()
+        .par_iter_mut()
+        .zip(blurred.data())
+        .for_each(|(h, &smooth)| *h = (*h + (*h - smooth) * amount).clamp(0.0, 1.0));
+    result
+}
+
+/// Remaps the heights so the lowest becomes `min` and the highest `max`.
+/// A flat terrain ends up at `min`.
+pub fn normalize(field: &HeightField, min: f32, max: f32) -> HeightField {
+    let (lowest, highest) = field
+        .data()
+        .iter()
+        .fold((f32::MAX, f32::MIN), |(lo, hi), &h| (lo.min(h), hi.max(h)));
+    let range = highest - lowest;
+    let scale = if range > 0.0 {
+        (max - min) / range
+    } else {
+        0.0
+    };
+    map(field, |h| min + (h - lowest) * scale)
+}
+
+/// Median of the (2 * `radius` + 1)² texels around each texel
+pub fn despeckle(
+    field: &HeightField,
+    radius: usize,
+    threshold: f32,
+    edges: EdgeMode,
+) -> HeightField {
+    let size = field.size();
+    let src = field.data();
+    let r = radius as isize;
+    let mut data = src.to_vec();
+    data.par_chunks_mut(size).enumerate().for_each(|(y, row)| {
+        let mut window = Vec::with_capacity((2 * radius + 1).pow(2));
+        for (x, h) in row.iter_mut().enumerate() {
+            window.clear();
+            for dy in -r..=r {
+                let sy = edges.index(y as isize + dy, size);
+                for dx in -r..=r {
+                    let sx = edges.index(x as isize + dx, size);
+                    window.push(src[sy * size + sx]);
+                }
+            }
+            let middle = window.len() / 2;
+            let (_, &mut median, _) = window.select_nth_unstable_by(middle, f32::total_cmp);
+            if (*h - median).abs() > threshold {
+                *h = median;
+            }
+        }
+    });
+    HeightField::from_data(size, data)
+}
+
+/// A texel waiting to be flooded, the lowest comes out of the heap first
+struct FloodCell {
+    height: f32,
+    index: usize,
+}
+
+impl PartialEq for FloodCell {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for FloodCell {}
+
+impl PartialOrd for FloodCell {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for FloodCell {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.height.total_cmp(&self.height)
+    }
+}
+
+/// Priority flood (Barnes et al.): water comes in from the edges and rises from the
+/// lowest texel reached so far, so every texel it reaches from above is a sink to fill
+pub fn fill_sinks(field: &HeightField) -> HeightField {
+    let size = field.size();
+    let mut heights = field.data().to_vec();
+    let mut flooded = vec![false; size * size];
+    let mut queue = BinaryHeap::new();
+    for i in 0..size * size {
+        let (x, y) = (i % size, i / size);
+        if x == 0 || y == 0 || x == size - 1 || y == size - 1 {
+            flooded[i] = true;
+            queue.push(FloodCell {
+                height: heights[i],
+                index: i,
+            });
+        }
+    }
+    while let Some(FloodCell { height, index }) = queue.pop() {
+        let (x, y) = ((index % size) as isize, (index / size) as isize);
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                let (nx, ny) = (x + dx, y + dy);
+                if nx < 0 || ny < 0 || nx >= size as isize || ny >= size as isize {
+                    continue;
+                }
+                let n = ny as usize * size + nx as usize;
+                if flooded[n] {
+                    continue;
+                }
+                flooded[n] = true;
+                heights[n] = heights[n].max(height + SINK_SLOPE);
+                queue.push(FloodCell {
+                    height: heights[n],
+                    index: n,
+                });
+            }
+        }
+    }
+    let data = heights.into_iter().map(|h| h.clamp(0.0, 1.0)).collect();
+    HeightField::from_data(size, data)
+}
+
+/// Every texel within `width` of an edge, as a fraction of the size, is blended with
+/// the one mirrored across the map. Right at the edges it's half and half, so opposite
+/// edges end up with the same heights. Rows first, then columns.
+pub fn make_tileable(field: &HeightField, width: f32) -> HeightField {
+    let size = field.size();
+    let band = ((width.clamp(0.0, 0.5) * size as f32) as usize).max(1);
+    let weight = |i: usize| {
+        let distance = i.min(size - 1 - i);
+        0.5 * (1.0 - smoothstep(distance as f32 / band as f32))
+    };
+
+    let src = field.data();
+    let mut rows = src.to_vec();
+    rows.par_chunks_mut(size).enumerate().for_each(|(y, row)| {
+        let src_row = &src[y * size..(y + 1) * size];
+        for (x, h) in row.iter_mut().enumerate() {
+            *h += (src_row[size - 1 - x] - *h) * weight(x);
+        }
+    });
+
+    let mut data = rows.clone();
+    data.par_chunks_mut(size).enumerate().for_each(|(y, row)| {
+        let w = weight(y);
+        if w > 0.0 {
+            let opposite = &rows[(size - 1 - y) * size..(size - y) * size];
+            for (h, &across) in row.iter_mut().zip(opposite) {
+                *h += (across - *h) * w;
+            }
+        }
+    });
+    HeightField::from_data(size, data)
+}
+
+fn gaussian_kernel(radius: usize) -> Vec<f32> {
+    let sigma = radius as f32 / 2.0;
+    let r = radius as isize;
+    let kernel: Vec<f32> = (-r..=r)
+        .map(|i| math::exp(-(i * i) as f32 / (2.0 * sigma * sigma)))
+        .collect();
+    let sum: f32 = kernel.iter().sum();
+    kernel.into_iter().map(|w| w / sum).collect()
+}
+
+/// Quantises heights into `steps` flat terraces.
+/// `sharpness` of 0 leaves the terrain as is, 1 gives hard steps.
+pub fn terrace(field: &HeightField, steps: u32, sharpness: f32) -> HeightField {
+    let steps = steps.max(1) as f32;
+    let mut result = field.clone();
+    result.data_mut().par_iter_mut().for_each(|h| {
+        let scaled = *h * steps;
+        let step = scaled.floor();
+        let t = scaled - step;
+        // Flat on top of each step, steep in between
+        let shaped = math::powf(t, 1.0 + sharpness * 7.0);
+        let terraced = (step + shaped) / steps;
+        *h += (terraced - *h) * sharpness.clamp(0.0, 1.0);
+    });
+    result
+}
+
+/// World space normals from central differences, row by row from the top (min z)
+pub fn normals(field: &HeightField, texel_size: f32, max_height: f32) -> Vec<Vec3> {
+    let size = field.size();
+    let src = field.data();
+    let row_at = |y: isize| {
+        let y = y.clamp(0, size as isize - 1) as usize;
+        &src[y * size..(y + 1) * size]
+    };
+    // Heights are normalised, so scale the up component instead of the gradients
+    let up = 2.0 * texel_size / max_height;
+
+    let mut normals = vec![Vec3::Y; size * size];
+    normals
+        .par_chunks_mut(size)
+        .enumerate()
+        .for_each(|(y, row)| {
+            let y = y as isize;
+            let current = row_at(y);
+
+            let mut padded = Vec::with_capacity(size + 2);
+            padded.push(current[0]);
+            padded.extend_from_slice(current);
+            padded.push(current[size - 1]);
+            let mut dx = padded[2..].to_vec();
+            simd::add_scaled(&mut dx, &padded[..size], -1.0);
+
+            let mut dz = row_at(y + 1).to_vec();
+            simd::add_scaled(&mut dz, row_at(y - 1), -1.0);
+
+            simd::normals_from_gradients(&dx, &dz, up, row);
+        });
+    normals
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::noise::fbm;
+
+    fn noise_field(size: usize, seed: u32) -> HeightField {
+        let data = (0..size * size)
+            .map(|i| fbm(seed, (i % size) as f32 / 8.0, (i / size) as f32 / 8.0, 4))
+            .collect();
+        HeightField::from_data(size, data)
+    }
+
+    fn mean(field: &HeightField) -> f32 {
+        field.data().iter().sum::<f32>() / field.data().len() as f32
+    }
+
+    #[test]
+    fn blur_keeps_flat_ground_flat() {
+        let field = HeightField::from_data(16, vec![0.4; 256]);
+        for edges in EdgeMode::ALL {
+            let blurred = blur(&field, 3, edges);
+            assert!(blurred.data().iter().all(|&h| (h - 0.4).abs() < 1e-6));
+        }
+    }
+
+    #[test]
+    fn wrapped_blur_keeps_the_mean_and_smooths() {
+        let field = noise_field(32, 1);
+        let blurred = blur(&field, 4, EdgeMode::Wrap);
+        assert!((mean(&blurred) - mean(&field)).abs() < 1e-5);
+        let variance = |f: &HeightField| {
+            let m = mean(f);
+            f.data().iter().map(|h| (h - m) * (h - m)).sum::<f32>()
+        };
+        assert!(variance(&blurred) < variance(&field));
+    }
+
+    #[test]
+    fn sharpen_with_no_amount_changes_nothing() {
+        let field = noise_field(16, 2);
+        let sharpened = sharpen(&field, 2, 0.0, EdgeMode::Clamp);
+        assert_eq!(sharpened.data(), field.data());
+    }
+
+    #[test]
+    fn normalize_stretches_to_the_range() {
+        let field = noise_field(16, 3);
+        let normalized = normalize(&field, 0.2, 0.7);
+        let (lowest, highest) = normalized
+            .data()
+            .iter()
+            .fold((f32::MAX, f32::MIN), |(lo, hi), &h| (lo.min(h), hi.max(h)));
+        assert!((lowest - 0.2).abs() < 1e-6);
+        assert!((highest - 0.7).abs() < 1e-6);
+    }
+
+    #[test]
+    fn invert_twice_is_the_original() {
+        let field = noise_field(16, 4);
+        let twice = HeightFilter::Invert.apply(&HeightFilter::Invert.apply(&field));
+        for (a, b) in twice.data().iter().zip(field.data()) {
+            assert!((a - b).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn results_are_clamped() {
+        let field = noise_field(16, 5);
+        let raised = HeightFilter::Add { amount: 0.8 }.apply(&field);
+        assert!(raised.data().iter().all(|&h| (0.0..=1.0).contains(&h)));
+    }
+
+    #[test]
+    fn the_default_curve_leaves_heights_as_they_are() {
+        let field = noise_field(16, 6);
+        let curved = HeightFilter::Curve(HeightCurve::default()).apply(&field);
+        for (a, b) in curved.data().iter().zip(field.data()) {
+            assert!((a - b).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn curves_never_fold_the_terrain_over() {
+        let mut curve = HeightCurve::default();
+        curve.insert(0.3, 0.8);
+        curve.insert(0.6, 0.82);
+        let lut = curve.lookup_table(256);
+        assert!(lut.windows(2).all(|pair| pair[1] >= pair[0]));
+        assert_eq!(curve.evaluate(0.3), 0.8);
+    }
+
+    #[test]
+    fn terrace_without_sharpness_changes_nothing() {
+        let field = noise_field(16, 7);
+        assert_eq!(terrace(&field, 5, 0.0).data(), field.data());
+    }
+
+    #[test]
+    fn despeckle_removes_a_spike_and_keeps_the_rest() {
+        let mut field = HeightField::from_data(9, vec![0.3; 81]);
+        field.data_mut()[4 * 9 + 4] = 0.9;
+        field.data_mut()[0] = 0.31;
+        let cleaned = despeckle(&field, 1, 0.05, EdgeMode::Clamp);
+        assert_eq!(cleaned.data()[4 * 9 + 4], 0.3);
+        assert_eq!(cleaned.data()[0], 0.31);
+    }
+
+    #[test]
+    fn filled_sinks_drain_to_the_edge() {
+        // A bowl with a pit in the middle and a rim lower on one side
+        let size = 9;
+        let mut data = vec![0.5; size * size];
+        data[4 * size + 4] = 0.1;
+        data[4 * size] = 0.2;
+        let field = HeightField::from_data(size, data);
+        let filled = fill_sinks(&field);
+        for (filled, original) in filled.data().iter().zip(field.data()) {
+            assert!(filled >= original);
+        }
+        assert!(filled.data()[4 * size + 4] >= 0.5);
+        assert_eq!(filled.data()[4 * size], 0.2);
+    }
+
+    #[test]
+    fn tileable_maps_meet_at_opposite_edges() {
+        let field = noise_field(32, 8);
+        let tileable = make_tileable(&field, 0.25);
+        let data = tileable.data();
+        for i in 0..32 {
+            assert!((data[i * 32] - data[i * 32 + 31]).abs() < 1e-6);
+            assert!((data[i] - data[31 * 32 + i]).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn flat_ground_faces_up() {
+        let field = HeightField::from_data(8, vec![0.5; 64]);
+        let normals = normals(&field, 1.0, 100.0);
+        assert!(normals.iter().all(|n| (*n - Vec3::Y).length() < 1e-6));
+    }
+}
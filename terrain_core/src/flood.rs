@@ -0,0 +1,101 @@
+//! Where water would stand, for planning rather than for the water that's drawn:
+//! either the sea rising in from the edges of the map, or rain filling every hollow
+//! until it spills over.
+
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+use crate::filters::fill_sinks;
+use crate::heightfield::HeightField;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FloodSource {
+    /// Up to the sea level, from the edges inwards
+    Sea,
+    /// Lakes wherever rain can't run off the map
+    Rain,
+}
+
+impl FloodSource {
+    pub const ALL: [FloodSource; 2] = [FloodSource::Sea, FloodSource::Rain];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FloodSource::Sea => "Sea",
+            FloodSource::Rain => "Rain",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct FloodSettings {
+    pub source: FloodSource,
+    /// Meters above the lowest height the terrain can have
+    pub sea_level: f32,
+    /// Meters, shallower puddles of rain are left out
+    pub min_depth: f32,
+}
+
+impl Default for FloodSettings {
+    fn default() -> Self {
+        FloodSettings {
+            source: FloodSource::Sea,
+            sea_level: 10.0,
+            min_depth: 0.5,
+        }
+    }
+}
+
+/// Meters of water on every texel, 0 where it stays dry
+pub fn flood_depth(field: &HeightField, max_height: f32, settings: &FloodSettings) -> HeightField {
+    let size = field.size();
+    let heights = field.data();
+    let depth = match settings.source {
+        FloodSource::Sea => {
+            // The sea only gets past texels it's over, and not through diagonal gaps
+            let level = settings.sea_level / max_height;
+            let mut depth = vec![0.0; size * size];
+            let mut queue: VecDeque<usize> = (0..size * size)
+                .filter(|&i| {
+                    let (x, y) = (i % size, i / size);
+                    x == 0 || y == 0 || x == size - 1 || y == size - 1
+                })
+                .filter(|&i| heights[i] < level)
+                .collect();
+            for &i in &queue {
+                depth[i] = (level - heights[i]) * max_height;
+            }
+            while let Some(i) = queue.pop_front() {
+                let (x, y) = (i % size, i / size);
+                let neighbours = [
+                    (x > 0).then(|| i - 1),
+                    (x + 1 < size).then(|| i + 1),
+                    (y > 0).then(|| i - size),
+                    (y + 1 < size).then(|| i + size),
+                ];
+                for &n in neighbours.iter().flatten() {
+                    if depth[n] == 0.0 && heights[n] < level {
+                        depth[n] = (level - heights[n]) * max_height;
+                        queue.push_back(n);
+                    }
+                }
+            }
+            depth
+        }
+        FloodSource::Rain => fill_sinks(field)
+            .data()
+            .iter()
+            .zip(heights)
+            .map(|(&filled, &h)| {
+                let depth = (filled - h) * max_height;
+                if depth >= settings.min_depth {
+                    depth
+                } else {
+                    0.0
+                }
+            })
+            .collect(),
+    };
+    HeightField::from_data(size, depth)
+}
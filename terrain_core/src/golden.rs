@@ -0,0 +1,15 @@
+//! Fingerprints of generator output for the golden tests. The generators are
+//! meant to give the same bits on every machine, so a test only has to keep
+//! one hash instead of a whole heightfield.
+
+/// FNV-1a over the bit patterns of the values, in order
+pub fn hash(values: impl IntoIterator<Item = f32>) -> u64 {
+    let mut h = 0xcbf2_9ce4_8422_2325u64;
+    for value in values {
+        for byte in value.to_bits().to_le_bytes() {
+            h ^= byte as u64;
+            h = h.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    h
+}
@@ -0,0 +1,104 @@
+use glam::Vec3;
+use serde::{Deserialize, Serialize};
+
+/// As many as the terrain shader has room for
+pub const MAX_GRADIENT_STOPS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct GradientStop {
+    /// Normalised, 0 at the bottom of the terrain and 1 at its max height
+    pub height: f32,
+    /// Linear RGB
+    pub color: [f32; 3],
+}
+
+/// Colors by elevation, blended linearly between the stops
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ColorGradient {
+    stops: Vec<GradientStop>,
+}
+
+impl Default for ColorGradient {
+    /// The usual atlas tints: green lowlands, yellow and brown hills, snowy peaks
+    fn default() -> Self {
+        let stop = |height, color| GradientStop { height, color };
+        ColorGradient {
+            stops: vec![
+                stop(0.0, [0.05, 0.25, 0.1]),
+                stop(0.2, [0.3, 0.5, 0.15]),
+                stop(0.45, [0.75, 0.65, 0.3]),
+                stop(0.7, [0.45, 0.28, 0.15]),
+                stop(1.0, [0.95, 0.95, 0.95]),
+            ],
+        }
+    }
+}
+
+impl ColorGradient {
+    /// Lowest first
+    pub fn stops(&self) -> &[GradientStop] {
+        &self.stops
+    }
+
+    /// Heights may be edited out of order, `sort` puts them back
+    pub fn stops_mut(&mut self) -> &mut [GradientStop] {
+        &mut self.stops
+    }
+
+    pub fn sort(&mut self) {
+        self.stops.sort_by(|a, b| a.height.total_cmp(&b.height));
+    }
+
+    /// Halfway into the widest gap, in the color the gradient already has there.
+    /// False if there's no room for another stop.
+    pub fn add_stop(&mut self) -> bool {
+        if self.stops.len() >= MAX_GRADIENT_STOPS {
+            return false;
+        }
+        let mut bounds: Vec<f32> = self.stops.iter().map(|stop| stop.height).collect();
+        bounds.insert(0, 0.0);
+        bounds.push(1.0);
+        let (low, high) =
+            bounds
+                .windows(2)
+                .map(|pair| (pair[0], pair[1]))
+                .fold((0.0, 0.0), |widest, gap| {
+                    if gap.1 - gap.0 > widest.1 - widest.0 {
+                        gap
+                    } else {
+                        widest
+                    }
+                });
+        let height = (low + high) / 2.0;
+        let color = self.color_at(height).to_array();
+        self.stops.push(GradientStop { height, color });
+        self.sort();
+        true
+    }
+
+    /// The last stop stays, a gradient needs at least one color
+    pub fn remove_stop(&mut self, index: usize) {
+        if self.stops.len() > 1 && index < self.stops.len() {
+            self.stops.remove(index);
+        }
+    }
+
+    /// Below the first stop and above the last one the color stays flat
+    pub fn color_at(&self, height: f32) -> Vec3 {
+        let first = match self.stops.first() {
+            Some(stop) => stop,
+            None => return Vec3::ONE,
+        };
+        if height <= first.height {
+            return Vec3::from(first.color);
+        }
+        for pair in self.stops.windows(2) {
+            let (low, high) = (&pair[0], &pair[1]);
+            if height <= high.height {
+                let t = (height - low.height) / (high.height - low.height).max(f32::EPSILON);
+                return Vec3::from(low.color).lerp(Vec3::from(high.color), t);
+            }
+        }
+        Vec3::from(self.stops[self.stops.len() - 1].color)
+    }
+}
@@ -0,0 +1,503 @@
+use serde::{Deserialize, Serialize};
+
+use crate::noise::{fbm, smoothstep};
+
+/// How to fill texels that fall outside the source when extending a map
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EdgeFill {
+    /// Repeat the closest edge texel
+    Clamp,
+    /// Edge texel plus noise that fades in away from the edge
+    Noise { amplitude: f32, seed: u32 },
+}
+
+/// What's past the border of a map for filters and brushes that look around a texel
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EdgeMode {
+    /// The border texels go on
+    #[default]
+    Clamp,
+    /// The map reflected at its border
+    Mirror,
+    /// The opposite border, for maps that tile. Brushes reach over to it too.
+    Wrap,
+}
+
+impl EdgeMode {
+    pub const ALL: [EdgeMode; 3] = [EdgeMode::Clamp, EdgeMode::Mirror, EdgeMode::Wrap];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            EdgeMode::Clamp => "Clamp",
+            EdgeMode::Mirror => "Mirror",
+            EdgeMode::Wrap => "Wrap",
+        }
+    }
+
+    /// The texel that stands for `i` on a row `size` texels long
+    #[inline]
+    pub fn index(self, i: isize, size: usize) -> usize {
+        let size = size as isize;
+        let index = match self {
+            EdgeMode::Clamp => i.clamp(0, size - 1),
+            // The border texel comes twice, as with GL_MIRRORED_REPEAT
+            EdgeMode::Mirror => {
+                let period = 2 * size;
+                let i = i.rem_euclid(period);
+                if i < size {
+                    i
+                } else {
+                    period - 1 - i
+                }
+            }
+            EdgeMode::Wrap => i.rem_euclid(size),
+        };
+        index as usize
+    }
+}
+
+/// Texels in [x0, x1) x [y0, y1) that have changed since the last sync
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DirtyRect {
+    pub x0: usize,
+    pub y0: usize,
+    pub x1: usize,
+    pub y1: usize,
+}
+
+impl DirtyRect {
+    pub fn full(size: usize) -> Self {
+        DirtyRect {
+            x0: 0,
+            y0: 0,
+            x1: size,
+            y1: size,
+        }
+    }
+
+    /// Texels under a square brush. `center` and `extent` are normalised to the map size.
+    pub fn around(center: (f32, f32), extent: f32, size: usize) -> Self {
+        let to_texel = |uv: f32| ((uv * size as f32).floor().max(0.0) as usize).min(size);
+        let half = extent / 2.0;
+        DirtyRect {
+            x0: to_texel(center.0 - half),
+            y0: to_texel(center.1 - half),
+            x1: (to_texel(center.0 + half) + 1).min(size),
+            y1: (to_texel(center.1 + half) + 1).min(size),
+        }
+    }
+
+    pub fn union(&self, other: &DirtyRect) -> DirtyRect {
+        DirtyRect {
+            x0: self.x0.min(other.x0),
+            y0: self.y0.min(other.y0),
+            x1: self.x1.max(other.x1),
+            y1: self.y1.max(other.y1),
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.x1.saturating_sub(self.x0)
+    }
+
+    pub fn height(&self) -> usize {
+        self.y1.saturating_sub(self.y0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width() == 0 || self.height() == 0
+    }
+}
+
+/// Min and max height of every cell of a height field, so that picking
+/// and culling don't have to look at every texel
+pub struct HeightBounds {
+    cell_size: usize,
+    cells_per_side: usize,
+    min: Vec<f32>,
+    max: Vec<f32>,
+}
+
+impl HeightBounds {
+    pub fn new(field: &HeightField, cell_size: usize) -> Self {
+        let cells_per_side = field.size.div_ceil(cell_size);
+        let mut bounds = HeightBounds {
+            cell_size,
+            cells_per_side,
+            min: vec![0.0; cells_per_side * cells_per_side],
+            max: vec![0.0; cells_per_side * cells_per_side],
+        };
+        bounds.update(field, &DirtyRect::full(field.size));
+        bounds
+    }
+
+    /// Recomputes only the cells touching `rect`
+    pub fn update(&mut self, field: &HeightField, rect: &DirtyRect) {
+        if rect.is_empty() {
+            return;
+        }
+        let (cx0, cy0) = (rect.x0 / self.cell_size, rect.y0 / self.cell_size);
+        let cx1 = rect.x1.div_ceil(self.cell_size);
+        let cy1 = rect.y1.div_ceil(self.cell_size);
+        for cy in cy0..cy1.min(self.cells_per_side) {
+            for cx in cx0..cx1.min(self.cells_per_side) {
+                let (mut min, mut max) = (f32::INFINITY, f32::NEG_INFINITY);
+                let y_end = ((cy + 1) * self.cell_size).min(field.size);
+                let x_end = ((cx + 1) * self.cell_size).min(field.size);
+                for y in cy * self.cell_size..y_end {
+                    let row = &field.data[y * field.size..(y + 1) * field.size];
+                    for &h in &row[cx * self.cell_size..x_end] {
+                        min = min.min(h);
+                        max = max.max(h);
+                    }
+                }
+                let i = cy * self.cells_per_side + cx;
+                self.min[i] = min;
+                self.max[i] = max;
+            }
+        }
+    }
+
+    /// Lowest and highest point of the whole field
+    pub fn range(&self) -> (f32, f32) {
+        let min = self.min.iter().cloned().fold(f32::INFINITY, f32::min);
+        let max = self.max.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+        (min, max)
+    }
+}
+
+/// CPU copy of a square single channel map (heights, masks, layer weights).
+/// Values are normalised to [0, 1], texel centers are at (i + 0.5) / size.
+#[derive(Debug, Clone)]
+pub struct HeightField {
+    size: usize,
+    data: Vec<f32>,
+}
+
+impl HeightField {
+    pub fn from_data(size: usize, data: Vec<f32>) -> Self {
+        assert_eq!(data.len(), size * size);
+        HeightField { size, data }
+    }
+
+    pub fn from_u16(size: usize, pixels: &[u16]) -> Self {
+        let data = pixels.iter().map(|&p| p as f32 / u16::MAX as f32).collect();
+        HeightField::from_data(size, data)
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn data(&self) -> &[f32] {
+        &self.data
+    }
+
+    pub fn data_mut(&mut self) -> &mut [f32] {
+        &mut self.data
+    }
+
+    /// Overwrites `rect` with normalised u16 texels, row by row
+    pub fn write_region_u16(&mut self, rect: &DirtyRect, pixels: &[u16]) {
+        debug_assert_eq!(pixels.len(), rect.width() * rect.height());
+        for (row, y) in (rect.y0..rect.y1).enumerate() {
+            let src = &pixels[row * rect.width()..(row + 1) * rect.width()];
+            let dst = &mut self.data[y * self.size + rect.x0..y * self.size + rect.x1];
+            for (d, &p) in dst.iter_mut().zip(src) {
+                *d = p as f32 / u16::MAX as f32;
+            }
+        }
+    }
+
+    pub fn to_u16(&self) -> Vec<u16> {
+        self.data.iter().map(|&h| to_u16(h)).collect()
+    }
+
+    /// Texels of `rect` as normalised u16, row by row
+    pub fn read_region_u16(&self, rect: &DirtyRect) -> Vec<u16> {
+        let mut pixels = Vec::with_capacity(rect.width() * rect.height());
+        for y in rect.y0..rect.y1 {
+            let row = &self.data[y * self.size + rect.x0..y * self.size + rect.x1];
+            pixels.extend(row.iter().map(|&h| to_u16(h)));
+        }
+        pixels
+    }
+
+    /// Bilinearly filtered value at normalised coordinates, edges repeat
+    pub fn sample(&self, u: f32, v: f32) -> f32 {
+        let x = u * self.size as f32 - 0.5;
+        let y = v * self.size as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as isize, y0 as isize);
+        let top = self.get_clamped(x0, y0) * (1.0 - tx) + self.get_clamped(x0 + 1, y0) * tx;
+        let bottom =
+            self.get_clamped(x0, y0 + 1) * (1.0 - tx) + self.get_clamped(x0 + 1, y0 + 1) * tx;
+        top * (1.0 - ty) + bottom * ty
+    }
+
+    /// Like `sample`, with what's past the border up to `edges`
+    pub fn sample_with(&self, u: f32, v: f32, edges: EdgeMode) -> f32 {
+        let x = u * self.size as f32 - 0.5;
+        let y = v * self.size as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as isize, y0 as isize);
+        let get = |x: isize, y: isize| self.get_with(x, y, edges);
+        let top = get(x0, y0) * (1.0 - tx) + get(x0 + 1, y0) * tx;
+        let bottom = get(x0, y0 + 1) * (1.0 - tx) + get(x0 + 1, y0 + 1) * tx;
+        top * (1.0 - ty) + bottom * ty
+    }
+
+    /// The texel at (x, y), which may be past the border
+    #[inline]
+    pub fn get_with(&self, x: isize, y: isize, edges: EdgeMode) -> f32 {
+        self.data[edges.index(y, self.size) * self.size + edges.index(x, self.size)]
+    }
+
+    #[inline]
+    fn get_clamped(&self, x: isize, y: isize) -> f32 {
+        let max = self.size as isize - 1;
+        self.data[y.clamp(0, max) as usize * self.size + x.clamp(0, max) as usize]
+    }
+
+    /// A square region starting at (x0, y0) which may reach outside the field
+    /// on any side. Texels outside are filled according to `fill`.
+    pub fn region(&self, x0: isize, y0: isize, size: usize, fill: EdgeFill) -> HeightField {
+        let mut data = Vec::with_capacity(size * size);
+        for y in y0..y0 + size as isize {
+            for x in x0..x0 + size as isize {
+                let edge = self.get_clamped(x, y);
+                let value = match fill {
+                    EdgeFill::Clamp => edge,
+                    EdgeFill::Noise { amplitude, seed } => {
+                        let max = self.size as isize - 1;
+                        let dx = (x - x.clamp(0, max)).abs();
+                        let dy = (y - y.clamp(0, max)).abs();
+                        let distance = dx.max(dy);
+                        if distance == 0 {
+                            edge
+                        } else {
+                            // Full strength 64 texels away from the edge so there's no seam
+                            let fade = smoothstep(distance as f32 / 64.0);
+                            let noise =
+                                fbm(seed, x as f32 / 128.0, y as f32 / 128.0, 5) * 2.0 - 1.0;
+                            (edge + noise * amplitude * fade).clamp(0.0, 1.0)
+                        }
+                    }
+                };
+                data.push(value);
+            }
+        }
+        HeightField::from_data(size, data)
+    }
+
+    /// Box filters when shrinking so that no detail is skipped,
+    /// uses Catmull-Rom when growing so that slopes stay smooth.
+    pub fn resampled(&self, new_size: usize) -> HeightField {
+        assert!(new_size > 0);
+        if new_size == self.size {
+            return self.clone();
+        }
+
+        // Separable: rows first, then columns
+        let mut rows = vec![0.0; new_size * self.size];
+        for y in 0..self.size {
+            let src = &self.data[y * self.size..(y + 1) * self.size];
+            let dst = &mut rows[y * new_size..(y + 1) * new_size];
+            resample_1d(src, dst);
+        }
+
+        let mut data = vec![0.0; new_size * new_size];
+        let mut column = vec![0.0; self.size];
+        let mut resampled_column = vec![0.0; new_size];
+        for x in 0..new_size {
+            for (y, value) in column.iter_mut().enumerate() {
+                *value = rows[y * new_size + x];
+            }
+            resample_1d(&column, &mut resampled_column);
+            for (y, &value) in resampled_column.iter().enumerate() {
+                data[y * new_size + x] = value;
+            }
+        }
+
+        let mut field = HeightField::from_data(new_size, data);
+        if new_size > self.size {
+            // Cubic filters overshoot near sharp features
+            for h in field.data.iter_mut() {
+                *h = h.clamp(0.0, 1.0);
+            }
+        }
+        field
+    }
+}
+
+fn resample_1d(src: &[f32], dst: &mut [f32]) {
+    if dst.len() < src.len() {
+        downsample_1d(src, dst);
+    } else {
+        upsample_1d(src, dst);
+    }
+}
+
+/// Each destination texel is the average of the source texels it covers,
+/// partially covered ones contribute proportionally
+fn downsample_1d(src: &[f32], dst: &mut [f32]) {
+    let scale = src.len() as f32 / dst.len() as f32;
+    for (i, value) in dst.iter_mut().enumerate() {
+        let start = i as f32 * scale;
+        let end = start + scale;
+        let mut sum = 0.0;
+        let mut j = start.floor() as usize;
+        while (j as f32) < end && j < src.len() {
+            let coverage = (end.min(j as f32 + 1.0) - start.max(j as f32)).max(0.0);
+            sum += src[j] * coverage;
+            j += 1;
+        }
+        *value = sum / scale;
+    }
+}
+
+fn upsample_1d(src: &[f32], dst: &mut [f32]) {
+    let scale = src.len() as f32 / dst.len() as f32;
+    let last = src.len() as isize - 1;
+    let at = |i: isize| src[i.clamp(0, last) as usize];
+    for (i, value) in dst.iter_mut().enumerate() {
+        // Position of the destination texel center in source texel space
+        let x = (i as f32 + 0.5) * scale - 0.5;
+        let x0 = x.floor();
+        let t = x - x0;
+        let x0 = x0 as isize;
+        *value = catmull_rom(at(x0 - 1), at(x0), at(x0 + 1), at(x0 + 2), t);
+    }
+}
+
+fn catmull_rom(p0: f32, p1: f32, p2: f32, p3: f32, t: f32) -> f32 {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    0.5 * ((2.0 * p1)
+        + (-p0 + p2) * t
+        + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t2
+        + (-p0 + 3.0 * p1 - 3.0 * p2 + p3) * t3)
+}
+
+fn to_u16(height: f32) -> u16 {
+    (height.clamp(0.0, 1.0) * u16::MAX as f32).round() as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ramp(size: usize) -> HeightField {
+        let data = (0..size * size)
+            .map(|i| (i % size) as f32 / (size - 1) as f32)
+            .collect();
+        HeightField::from_data(size, data)
+    }
+
+    fn noise_field(size: usize, seed: u32) -> HeightField {
+        let data = (0..size * size)
+            .map(|i| fbm(seed, (i % size) as f32 / 8.0, (i / size) as f32 / 8.0, 4))
+            .collect();
+        HeightField::from_data(size, data)
+    }
+
+    #[test]
+    fn resampling_to_the_same_size_changes_nothing() {
+        let field = noise_field(32, 1);
+        assert_eq!(field.resampled(32).data(), field.data());
+    }
+
+    #[test]
+    fn shrinking_averages_the_texels_covered() {
+        let field = HeightField::from_data(4, (0..16).map(|i| i as f32 / 15.0).collect());
+        let half = field.resampled(2);
+        let average = |texels: [usize; 4]| texels.iter().sum::<usize>() as f32 / 4.0 / 15.0;
+        let expected = [
+            average([0, 1, 4, 5]),
+            average([2, 3, 6, 7]),
+            average([8, 9, 12, 13]),
+            average([10, 11, 14, 15]),
+        ];
+        for (&h, e) in half.data().iter().zip(expected) {
+            assert!((h - e).abs() < 1e-6, "{} != {}", h, e);
+        }
+    }
+
+    #[test]
+    fn shrinking_by_a_fraction_keeps_the_mean() {
+        let field = noise_field(30, 2);
+        let mean = |f: &HeightField| f.data().iter().sum::<f32>() / f.data().len() as f32;
+        assert!((mean(&field.resampled(17)) - mean(&field)).abs() < 1e-4);
+    }
+
+    #[test]
+    fn growing_keeps_flat_ground_flat() {
+        let field = HeightField::from_data(8, vec![0.25; 64]);
+        let grown = field.resampled(29);
+        assert!(grown.data().iter().all(|&h| (h - 0.25).abs() < 1e-6));
+    }
+
+    #[test]
+    fn growing_follows_slopes_and_stays_in_range() {
+        let field = ramp(16);
+        let grown = field.resampled(64);
+        for y in 0..64 {
+            let row = &grown.data()[y * 64..(y + 1) * 64];
+            assert!(row.windows(2).all(|pair| pair[1] >= pair[0] - 1e-6));
+        }
+        // A cliff makes Catmull-Rom overshoot, which is clamped away
+        let mut cliff = HeightField::from_data(8, vec![0.0; 64]);
+        for y in 0..8 {
+            for x in 4..8 {
+                cliff.data_mut()[y * 8 + x] = 1.0;
+            }
+        }
+        let grown = cliff.resampled(40);
+        assert!(grown.data().iter().all(|&h| (0.0..=1.0).contains(&h)));
+    }
+
+    #[test]
+    fn growing_then_shrinking_comes_back_close() {
+        let field = noise_field(32, 3);
+        let round_trip = field.resampled(128).resampled(32);
+        let worst = field
+            .data()
+            .iter()
+            .zip(round_trip.data())
+            .map(|(a, b)| (a - b).abs())
+            .fold(0.0, f32::max);
+        assert!(worst < 0.05, "worst difference {}", worst);
+    }
+
+    #[test]
+    fn edge_modes_index_past_the_border() {
+        let indices =
+            |edges: EdgeMode| -> Vec<usize> { (-3..7).map(|i| edges.index(i, 4)).collect() };
+        assert_eq!(indices(EdgeMode::Clamp), [0, 0, 0, 0, 1, 2, 3, 3, 3, 3]);
+        assert_eq!(indices(EdgeMode::Mirror), [2, 1, 0, 0, 1, 2, 3, 3, 2, 1]);
+        assert_eq!(indices(EdgeMode::Wrap), [1, 2, 3, 0, 1, 2, 3, 0, 1, 2]);
+    }
+
+    #[test]
+    fn region_repeats_the_edges_outside() {
+        let field = ramp(4);
+        let region = field.region(-2, 1, 8, EdgeFill::Clamp);
+        for y in 0..8 {
+            let row = &region.data()[y * 8..(y + 1) * 8];
+            let expected: Vec<f32> = (-2..6).map(|x: isize| x.clamp(0, 3) as f32 / 3.0).collect();
+            assert_eq!(row, expected.as_slice());
+        }
+    }
+
+    #[test]
+    fn bounds_follow_updates() {
+        let mut field = noise_field(64, 4);
+        let mut bounds = HeightBounds::new(&field, 16);
+        field.data_mut()[10 * 64 + 50] = 1.0;
+        let rect = DirtyRect::around((50.5 / 64.0, 10.5 / 64.0), 2.0 / 64.0, 64);
+        bounds.update(&field, &rect);
+        assert_eq!(bounds.range().1, 1.0);
+    }
+}
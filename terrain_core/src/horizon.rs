@@ -0,0 +1,62 @@
+use std::f32::consts::TAU;
+
+use rayon::prelude::*;
+
+use crate::heightfield::HeightField;
+
+/// Azimuths the horizon is found in, evenly spaced starting at +X and turning towards +Y
+pub const HORIZON_DIRECTIONS: usize = 8;
+
+/// Each step looks this much further than the last, so far away hills are found in few steps
+const STEP_GROWTH: f32 = 1.3;
+
+/// Sine of how high the horizon rises above the horizontal in every direction, for every
+/// texel of `field` resampled to `size`. Zero where nothing is higher than the texel.
+/// `world_size` and `max_height` give the slopes their real proportions.
+pub fn horizon_angles(
+    field: &HeightField,
+    size: usize,
+    world_size: f32,
+    max_height: f32,
+) -> Vec<[f32; HORIZON_DIRECTIONS]> {
+    let field = field.resampled(size);
+    let texel_size = world_size / size as f32;
+    let directions: Vec<(f32, f32)> = (0..HORIZON_DIRECTIONS)
+        .map(|i| {
+            let angle = i as f32 / HORIZON_DIRECTIONS as f32 * TAU;
+            (angle.cos(), angle.sin())
+        })
+        .collect();
+    // Half the terrain is far enough to find any horizon worth shading
+    let max_distance = size as f32 / 2.0;
+
+    let mut angles = vec![[0.0; HORIZON_DIRECTIONS]; size * size];
+    angles
+        .par_chunks_mut(size)
+        .enumerate()
+        .for_each(|(y, row)| {
+            for (x, texel) in row.iter_mut().enumerate() {
+                let u = (x as f32 + 0.5) / size as f32;
+                let v = (y as f32 + 0.5) / size as f32;
+                let height = field.sample(u, v);
+                for (sine, &(dx, dy)) in texel.iter_mut().zip(&directions) {
+                    let mut max_slope = 0.0f32;
+                    let mut distance = 1.0;
+                    while distance < max_distance {
+                        let (su, sv) = (
+                            u + dx * distance / size as f32,
+                            v + dy * distance / size as f32,
+                        );
+                        if !(0.0..=1.0).contains(&su) || !(0.0..=1.0).contains(&sv) {
+                            break;
+                        }
+                        let rise = (field.sample(su, sv) - height) * max_height;
+                        max_slope = max_slope.max(rise / (distance * texel_size));
+                        distance *= STEP_GROWTH;
+                    }
+                    *sine = max_slope / (1.0 + max_slope * max_slope).sqrt();
+                }
+            }
+        });
+    angles
+}
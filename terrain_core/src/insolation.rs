@@ -0,0 +1,59 @@
+//! How long the sun shines on every part of the terrain over a day, for placing snow,
+//! vegetation and settlements where they'd believably be. The sun is followed along
+//! its path and a texel gets sun whenever it's above the horizon there.
+
+use std::f32::consts::TAU;
+
+use glam::Vec3;
+use rayon::prelude::*;
+
+use crate::heightfield::HeightField;
+use crate::horizon::{horizon_angles, HORIZON_DIRECTIONS};
+
+/// Hours of direct sun for every texel of `field` resampled to `size`. `sun_path` is
+/// the direction towards the sun at evenly spaced times over a whole day, +Y up, and
+/// each sample is worth an equal share of the 24 hours.
+pub fn sun_hours(
+    field: &HeightField,
+    size: usize,
+    world_size: f32,
+    max_height: f32,
+    sun_path: &[Vec3],
+) -> HeightField {
+    let horizons = horizon_angles(field, size, world_size, max_height);
+    let hours_per_sample = 24.0 / sun_path.len().max(1) as f32;
+    // Where each sun is among the horizon directions, and the sine of its elevation.
+    // Suns below the horizontal never shine.
+    let suns: Vec<(f32, f32)> = sun_path
+        .iter()
+        .map(|direction| direction.normalize_or_zero())
+        .filter(|direction| direction.y > 0.0)
+        .map(|direction| {
+            let azimuth = direction.z.atan2(direction.x).rem_euclid(TAU);
+            (azimuth / TAU * HORIZON_DIRECTIONS as f32, direction.y)
+        })
+        .collect();
+    let hours = horizons
+        .par_iter()
+        .map(|horizon| {
+            let shining = suns.iter().filter(|&&(position, elevation)| {
+                // Between the two nearest directions, the same as the terrain shader
+                let i = position as usize % HORIZON_DIRECTIONS;
+                let t = position.fract();
+                let next = horizon[(i + 1) % HORIZON_DIRECTIONS];
+                elevation > horizon[i] * (1.0 - t) + next * t
+            });
+            shining.count() as f32 * hours_per_sample
+        })
+        .collect();
+    HeightField::from_data(size, hours)
+}
+
+/// Hours the sun is above the horizontal at all, what open flat ground gets
+pub fn daylight_hours(sun_path: &[Vec3]) -> f32 {
+    let up = sun_path
+        .iter()
+        .filter(|direction| direction.y > 0.0)
+        .count();
+    up as f32 * 24.0 / sun_path.len().max(1) as f32
+}
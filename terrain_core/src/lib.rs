@@ -0,0 +1,26 @@
+//! Heightmap processing shared by the editor: resampling, erosion, filters,
+//! prop placement and export. Nothing here needs a window or a GL context.
+
+pub mod block_texture;
+pub mod buildable;
+pub mod codec;
+pub mod detail;
+pub mod erosion;
+pub mod export;
+pub mod filters;
+pub mod flood;
+#[cfg(test)]
+mod golden;
+pub mod gradient;
+pub mod heightfield;
+pub mod horizon;
+pub mod insolation;
+pub mod math;
+pub mod noise;
+pub mod path;
+pub mod scatter;
+pub mod simd;
+pub mod tiled;
+pub mod viewshed;
+pub mod voids;
+pub mod weathering;
@@ -0,0 +1,136 @@
+//! Portable versions of the few transcendental functions the generators need.
+//! The std ones call into the platform's libm, which rounds differently on
+//! different systems. These only use basic IEEE arithmetic, which rounds the
+//! same everywhere, so results are bit for bit identical on every machine.
+//! Everything is computed in f64 so the series are well past f32 precision.
+
+use std::f64::consts::{FRAC_PI_2, LN_2};
+
+pub fn exp(x: f32) -> f32 {
+    let x = x as f64;
+    if x < -104.0 {
+        return 0.0;
+    }
+    if x > 89.0 {
+        return f32::INFINITY;
+    }
+    // x = k * ln(2) + r with |r| <= ln(2) / 2
+    let k = (x / LN_2).round();
+    let r = x - k * LN_2;
+    let mut term = 1.0;
+    let mut sum = 1.0;
+    for i in 1..=14 {
+        term *= r / i as f64;
+        sum += term;
+    }
+    (sum * pow2(k as i64)) as f32
+}
+
+/// Natural logarithm, NaN for negative numbers. Special values go the same
+/// way as std's, the loops below would never finish on infinity.
+pub fn ln(x: f32) -> f32 {
+    if x.is_nan() || x < 0.0 {
+        return f32::NAN;
+    }
+    if x == 0.0 {
+        return f32::NEG_INFINITY;
+    }
+    if x == f32::INFINITY {
+        return f32::INFINITY;
+    }
+    // x = m * 2^e with m in [sqrt(0.5), sqrt(2))
+    let mut m = x as f64;
+    let mut e = 0;
+    while m >= std::f64::consts::SQRT_2 {
+        m *= 0.5;
+        e += 1;
+    }
+    while m < std::f64::consts::FRAC_1_SQRT_2 {
+        m *= 2.0;
+        e -= 1;
+    }
+    // ln(m) = 2 * atanh(s), |s| < 0.172
+    let s = (m - 1.0) / (m + 1.0);
+    let s2 = s * s;
+    let mut power = s;
+    let mut sum = 0.0;
+    for i in 0..12 {
+        sum += power / (2 * i + 1) as f64;
+        power *= s2;
+    }
+    (2.0 * sum + e as f64 * LN_2) as f32
+}
+
+/// `base` must not be negative
+pub fn powf(base: f32, exponent: f32) -> f32 {
+    if base == 0.0 {
+        return if exponent > 0.0 { 0.0 } else { f32::INFINITY };
+    }
+    exp(exponent * ln(base))
+}
+
+/// Only for angles in (-pi/2, pi/2), which is all slopes need
+pub fn tan(x: f32) -> f32 {
+    let x = (x as f64).clamp(-FRAC_PI_2, FRAC_PI_2);
+    let x2 = x * x;
+    let (mut sin, mut cos) = (0.0, 0.0);
+    let (mut sin_term, mut cos_term) = (x, 1.0);
+    for i in 0..16 {
+        sin += sin_term;
+        cos += cos_term;
+        let n = (2 * i + 2) as f64;
+        sin_term *= -x2 / (n * (n + 1.0));
+        cos_term *= -x2 / ((n - 1.0) * n);
+    }
+    (sin / cos) as f32
+}
+
+/// 2^k built straight from the exponent bits
+fn pow2(k: i64) -> f64 {
+    f64::from_bits(((k + 1023) as u64) << 52)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ln_of_special_values_matches_std() {
+        assert_eq!(ln(f32::INFINITY), f32::INFINITY);
+        assert_eq!(ln(0.0), f32::NEG_INFINITY);
+        assert_eq!(ln(-0.0), f32::NEG_INFINITY);
+        assert!(ln(-1.0).is_nan());
+        assert!(ln(f32::NEG_INFINITY).is_nan());
+        assert!(ln(f32::NAN).is_nan());
+    }
+
+    #[test]
+    fn ln_matches_std_over_the_whole_range() {
+        for &x in &[
+            f32::MIN_POSITIVE,
+            1e-30,
+            0.001,
+            0.5,
+            1.0,
+            2.0,
+            10.0,
+            1e30,
+            f32::MAX,
+        ] {
+            let expected = x.ln();
+            assert!(
+                (ln(x) - expected).abs() <= expected.abs() * 1e-6 + 1e-6,
+                "ln({})",
+                x
+            );
+        }
+    }
+
+    #[test]
+    fn powf_and_tan_match_std() {
+        assert!((powf(2.0, 0.5) - std::f32::consts::SQRT_2).abs() < 1e-6);
+        assert_eq!(powf(0.0, 2.0), 0.0);
+        assert!((tan(0.5) - 0.5f32.tan()).abs() < 1e-6);
+        assert!((exp(1.0) - std::f32::consts::E).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,63 @@
+//! Seeded noise. Only integer hashing and basic arithmetic, so the same seed
+//! gives the same terrain on every machine.
+
+/// Hash of a lattice point in [0, 1]
+pub fn hash(seed: u32, x: i32, y: i32) -> f32 {
+    let mut h = (x as u32).wrapping_mul(0x8da6_b343)
+        ^ (y as u32).wrapping_mul(0xd816_3841)
+        ^ seed.wrapping_mul(0xcb1a_b31f);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0x5bd1_e995);
+    h ^= h >> 15;
+    h as f32 / u32::MAX as f32
+}
+
+pub fn smoothstep(t: f32) -> f32 {
+    let t = t.clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// Value noise in [0, 1]
+pub fn value_noise(seed: u32, x: f32, y: f32) -> f32 {
+    let (x0, y0) = (x.floor(), y.floor());
+    let (tx, ty) = (smoothstep(x - x0), smoothstep(y - y0));
+    let (x0, y0) = (x0 as i32, y0 as i32);
+    let corner = |dx, dy| hash(seed, x0 + dx, y0 + dy);
+    let top = corner(0, 0) + (corner(1, 0) - corner(0, 0)) * tx;
+    let bottom = corner(0, 1) + (corner(1, 1) - corner(0, 1)) * tx;
+    top + (bottom - top) * ty
+}
+
+/// A few octaves of value noise in [0, 1]. Every octave gets its own seed
+/// so the lattices don't line up.
+pub fn fbm(seed: u32, x: f32, y: f32, octaves: u32) -> f32 {
+    let mut sum = 0.0;
+    let mut amplitude = 0.5;
+    let mut frequency = 1.0;
+    for octave in 0..octaves {
+        sum += value_noise(seed.wrapping_add(octave), x * frequency, y * frequency) * amplitude;
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+    sum / (1.0 - amplitude * 2.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::golden;
+
+    #[test]
+    fn fbm_is_the_same_for_the_same_seed() {
+        let values = (0..64 * 64).map(|i| fbm(42, (i % 64) as f32 / 7.0, (i / 64) as f32 / 7.0, 6));
+        assert_eq!(golden::hash(values), 10114353438202781399);
+    }
+
+    #[test]
+    fn fbm_stays_in_range() {
+        for i in 0..1000 {
+            let value = fbm(3, i as f32 * 0.37, i as f32 * -0.53, 8);
+            assert!((0.0..=1.0).contains(&value));
+        }
+    }
+}
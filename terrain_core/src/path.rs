@@ -0,0 +1,190 @@
+//! Walkable routes, for checking that the places a map has to connect are connected
+//! before it's exported. A* over a grid laid on the heightfield, where a step
+//! steeper than the limit is a wall.
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use glam::{Vec2, Vec3};
+use serde::{Deserialize, Serialize};
+
+use crate::math;
+use crate::scatter::Surface;
+
+const SQRT_2: f32 = std::f32::consts::SQRT_2;
+
+const DIRECTIONS: [(isize, isize); 8] = [
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+    (-1, 0),
+    (1, 0),
+    (-1, 1),
+    (0, 1),
+    (1, 1),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PathSettings {
+    /// Degrees from horizontal, nothing steeper can be walked up or down
+    pub max_slope: f32,
+    /// Grid cells along a side of the terrain. Finer finds narrower passes but
+    /// takes longer, it's never finer than the heightfield.
+    pub resolution: usize,
+}
+
+impl Default for PathSettings {
+    fn default() -> Self {
+        PathSettings {
+            max_slope: 30.0,
+            resolution: 512,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WalkPath {
+    /// Centers of the grid cells walked through, meters from the minimum corner of
+    /// the terrain
+    pub points: Vec<Vec3>,
+    /// Meters, climbing included
+    pub length: f32,
+    /// Degrees from horizontal of the steepest step
+    pub steepest: f32,
+}
+
+/// The shortest route between two points on the surface that's nowhere steeper than
+/// the settings allow. Points are in meters from the minimum corner of the terrain,
+/// the ones off the surface are moved onto its edge. None if there's no such route.
+pub fn find_path(
+    surface: &Surface,
+    start: Vec2,
+    goal: Vec2,
+    settings: &PathSettings,
+) -> Option<WalkPath> {
+    let cells = settings.resolution.clamp(2, surface.field.size());
+    let cell_size = surface.world_size / cells as f32;
+    let heights = surface.grid_heights(cells);
+    let cell_at = |point: Vec2| {
+        let cell = (point / cell_size)
+            .floor()
+            .clamp(Vec2::ZERO, Vec2::splat((cells - 1) as f32));
+        cell.y as usize * cells + cell.x as usize
+    };
+    let center =
+        |i: usize| Vec2::new((i % cells) as f32 + 0.5, (i / cells) as f32 + 0.5) * cell_size;
+    let (start, goal) = (cell_at(start), cell_at(goal));
+    let goal_center = center(goal);
+    let max_rise = math::tan(settings.max_slope.clamp(0.0, 89.0).to_radians());
+
+    // Meters walked to get to each cell, and the cell it was reached from
+    let mut walked = vec![f32::INFINITY; cells * cells];
+    let mut came_from = vec![usize::MAX; cells * cells];
+    let mut queue = BinaryHeap::new();
+    walked[start] = 0.0;
+    queue.push(OpenCell {
+        estimate: center(start).distance(goal_center),
+        walked: 0.0,
+        index: start,
+    });
+    while let Some(open) = queue.pop() {
+        let (index, so_far) = (open.index, open.walked);
+        if index == goal {
+            break;
+        }
+        if so_far > walked[index] {
+            continue; // there's been a shorter way here since it was queued
+        }
+        let (x, y) = ((index % cells) as isize, (index / cells) as isize);
+        for (dx, dy) in DIRECTIONS {
+            let (nx, ny) = (x + dx, y + dy);
+            if nx < 0 || ny < 0 || nx >= cells as isize || ny >= cells as isize {
+                continue;
+            }
+            let next = ny as usize * cells + nx as usize;
+            let run = if dx != 0 && dy != 0 {
+                cell_size * SQRT_2
+            } else {
+                cell_size
+            };
+            let rise = (heights[next] - heights[index]).abs();
+            if rise > run * max_rise {
+                continue;
+            }
+            let total = so_far + run.hypot(rise);
+            if total < walked[next] {
+                walked[next] = total;
+                came_from[next] = index;
+                // The straight line is never longer than what's left to walk, so the
+                // goal comes out of the queue by the shortest way
+                let estimate = total + center(next).distance(goal_center);
+                queue.push(OpenCell {
+                    estimate,
+                    walked: total,
+                    index: next,
+                });
+            }
+        }
+    }
+    if !walked[goal].is_finite() {
+        return None;
+    }
+
+    let mut route = vec![goal];
+    while let Some(&last) = route.last() {
+        if last == start {
+            break;
+        }
+        route.push(came_from[last]);
+    }
+    route.reverse();
+    let points: Vec<Vec3> = route
+        .iter()
+        .map(|&i| {
+            let center = center(i);
+            Vec3::new(center.x, heights[i], center.y)
+        })
+        .collect();
+    let steepest = points
+        .windows(2)
+        .map(|step| {
+            let rise = (step[1].y - step[0].y).abs();
+            let run = (step[1].x - step[0].x).hypot(step[1].z - step[0].z);
+            rise.atan2(run).to_degrees()
+        })
+        .fold(0.0, f32::max);
+    Some(WalkPath {
+        points,
+        length: walked[goal],
+        steepest,
+    })
+}
+
+/// A cell waiting to be walked from, the one that looks closest to the goal comes out
+/// of the heap first
+struct OpenCell {
+    /// Meters walked to get here plus the straight line distance left
+    estimate: f32,
+    walked: f32,
+    index: usize,
+}
+
+impl PartialEq for OpenCell {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for OpenCell {}
+
+impl PartialOrd for OpenCell {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for OpenCell {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.estimate.total_cmp(&self.estimate)
+    }
+}
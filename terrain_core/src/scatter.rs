@@ -0,0 +1,205 @@
+//! Deterministic placement of props on a heightfield. Positions come from a
+//! jittered grid and seeded hashes, so the same terrain and settings always
+//! give the same layout.
+
+use glam::{Vec2, Vec3};
+use serde::{Deserialize, Serialize};
+
+use crate::heightfield::HeightField;
+use crate::math;
+use crate::noise;
+
+/// More than this and the props would cost more than the terrain
+pub const MAX_PLACEMENTS: usize = 100_000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SlopeScatter {
+    /// Degrees from horizontal, only steeper faces get props
+    pub min_slope: f32,
+    /// Props per 100 square meters of map area that is steep enough
+    pub density: f32,
+    pub min_scale: f32,
+    pub max_scale: f32,
+}
+
+impl Default for SlopeScatter {
+    fn default() -> Self {
+        SlopeScatter {
+            min_slope: 50.0,
+            density: 1.0,
+            min_scale: 1.0,
+            max_scale: 3.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Placement {
+    /// Meters from the minimum corner of the terrain
+    pub position: Vec3,
+    /// Surface normal at the position
+    pub normal: Vec3,
+    pub scale: f32,
+    /// Radians around the normal
+    pub angle: f32,
+}
+
+/// Normalised heights covering `world_size` meters, scaled by `max_height`
+pub struct Surface<'a> {
+    pub field: &'a HeightField,
+    pub world_size: f32,
+    pub max_height: f32,
+}
+
+/// Meters between props on the jittered grid for a density in props per 100 square meters
+pub fn spacing(density: f32) -> f32 {
+    10.0 / density.max(0.001).sqrt()
+}
+
+impl Surface<'_> {
+    fn texel_size(&self) -> f32 {
+        self.world_size / self.field.size() as f32
+    }
+
+    /// Heights in meters at the centers of a grid of `cells` by `cells` laid over the
+    /// surface, row by row
+    pub fn grid_heights(&self, cells: usize) -> Vec<f32> {
+        (0..cells * cells)
+            .map(|i| {
+                let (u, v) = (
+                    ((i % cells) as f32 + 0.5) / cells as f32,
+                    ((i / cells) as f32 + 0.5) / cells as f32,
+                );
+                self.field.sample(u, v) * self.max_height
+            })
+            .collect()
+    }
+
+    /// The prop of one cell of a jittered grid, every cell has at most one.
+    /// None when the jittered point falls off the surface.
+    pub fn in_cell(
+        &self,
+        seed: u32,
+        spacing: f32,
+        (x, y): (i32, i32),
+        (min_scale, max_scale): (f32, f32),
+    ) -> Option<Placement> {
+        // Separate hash streams for every random property of a cell
+        let jitter = Vec2::new(
+            noise::hash(seed, x, y),
+            noise::hash(seed.wrapping_add(1), x, y),
+        );
+        let point = (Vec2::new(x as f32, y as f32) + jitter) * spacing;
+        if point.min_element() < 0.0 || point.max_element() >= self.world_size {
+            return None;
+        }
+        let texel_size = self.texel_size();
+        let scale_t = noise::hash(seed.wrapping_add(2), x, y);
+        Some(Placement {
+            position: Vec3::new(
+                point.x,
+                height_at(self.field, point, texel_size) * self.max_height,
+                point.y,
+            ),
+            normal: normal_at(self.field, point, texel_size, self.max_height),
+            scale: min_scale + (max_scale - min_scale) * scale_t,
+            angle: noise::hash(seed.wrapping_add(3), x, y) * std::f32::consts::TAU,
+        })
+    }
+}
+
+/// Props on every face steeper than `min_slope`
+pub fn on_slopes(surface: &Surface, seed: u32, settings: &SlopeScatter) -> Vec<Placement> {
+    let spacing = spacing(settings.density);
+    let cells = (surface.world_size / spacing).ceil().max(1.0) as i32;
+    let slope = math::tan(settings.min_slope.clamp(0.0, 89.0).to_radians());
+    let max_normal_y = 1.0 / (1.0 + slope * slope).sqrt();
+    let scale = (settings.min_scale, settings.max_scale);
+
+    let mut placements = vec![];
+    for y in 0..cells {
+        for x in 0..cells {
+            let placement = match surface.in_cell(seed, spacing, (x, y), scale) {
+                Some(placement) if placement.normal.y <= max_normal_y => placement,
+                _ => continue,
+            };
+            placements.push(placement);
+            if placements.len() == MAX_PLACEMENTS {
+                return placements;
+            }
+        }
+    }
+    placements
+}
+
+/// Normalised height at a point in meters, bilinearly filtered
+fn height_at(field: &HeightField, point: Vec2, texel_size: f32) -> f32 {
+    let size = field.size();
+    let max = (size - 1) as f32;
+    let texel = (point / texel_size - Vec2::splat(0.5)).clamp(Vec2::ZERO, Vec2::splat(max));
+    let (x0, y0) = (texel.x.floor() as usize, texel.y.floor() as usize);
+    let (x1, y1) = ((x0 + 1).min(size - 1), (y0 + 1).min(size - 1));
+    let (tx, ty) = (texel.x.fract(), texel.y.fract());
+    let h = |x: usize, y: usize| field.data()[y * size + x];
+    let top = h(x0, y0) * (1.0 - tx) + h(x1, y0) * tx;
+    let bottom = h(x0, y1) * (1.0 - tx) + h(x1, y1) * tx;
+    top * (1.0 - ty) + bottom * ty
+}
+
+/// Central differences one texel apart, same as the normal map
+fn normal_at(field: &HeightField, point: Vec2, texel_size: f32, max_height: f32) -> Vec3 {
+    let sample = |dx: f32, dy: f32| {
+        let offset = Vec2::new(dx, dy) * texel_size;
+        height_at(field, point + offset, texel_size) * max_height
+    };
+    let dx = sample(1.0, 0.0) - sample(-1.0, 0.0);
+    let dz = sample(0.0, 1.0) - sample(0.0, -1.0);
+    Vec3::new(-dx, 2.0 * texel_size, -dz).normalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::golden;
+
+    fn hills(size: usize) -> HeightField {
+        let data = (0..size * size)
+            .map(|i| noise::fbm(5, (i % size) as f32 / 10.0, (i / size) as f32 / 10.0, 4))
+            .collect();
+        HeightField::from_data(size, data)
+    }
+
+    #[test]
+    fn slope_scatter_is_the_same_for_the_same_seed() {
+        let field = hills(128);
+        let surface = Surface {
+            field: &field,
+            world_size: 256.0,
+            max_height: 60.0,
+        };
+        let settings = SlopeScatter {
+            min_slope: 20.0,
+            ..SlopeScatter::default()
+        };
+        let placements = on_slopes(&surface, 9, &settings);
+        assert!(!placements.is_empty());
+        let mut values = vec![];
+        for p in &placements {
+            values.extend_from_slice(&p.position.to_array());
+            values.extend_from_slice(&p.normal.to_array());
+            values.extend_from_slice(&[p.scale, p.angle]);
+        }
+        assert_eq!(golden::hash(values), 1144087911354530499);
+    }
+
+    #[test]
+    fn flat_ground_gets_no_props() {
+        let field = HeightField::from_data(32, vec![0.5; 32 * 32]);
+        let surface = Surface {
+            field: &field,
+            world_size: 64.0,
+            max_height: 60.0,
+        };
+        assert!(on_slopes(&surface, 1, &SlopeScatter::default()).is_empty());
+    }
+}
@@ -0,0 +1,203 @@
+//! Vectorised inner loops of the CPU heightmap filters.
+//! AVX2 is picked at runtime when the CPU has it, otherwise the scalar versions run.
+//! Both do exactly the same operations in the same order (no FMA), so the results
+//! are identical whichever one runs.
+//! Brush strokes on the heightmap itself are drawn on the GPU so they're not here.
+
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use glam::Vec3;
+
+const UNKNOWN: u8 = 0;
+const UNSUPPORTED: u8 = 1;
+const SUPPORTED: u8 = 2;
+
+static AVX2_SUPPORT: AtomicU8 = AtomicU8::new(UNKNOWN);
+
+/// Feature detection is not free, so do it once
+pub fn avx2_supported() -> bool {
+    match AVX2_SUPPORT.load(Ordering::Relaxed) {
+        UNKNOWN => {
+            let supported = detect_avx2();
+            let value = if supported { SUPPORTED } else { UNSUPPORTED };
+            AVX2_SUPPORT.store(value, Ordering::Relaxed);
+            supported
+        }
+        value => value == SUPPORTED,
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+fn detect_avx2() -> bool {
+    is_x86_feature_detected!("avx2")
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn detect_avx2() -> bool {
+    false
+}
+
+/// dst += src * weight
+pub fn add_scaled(dst: &mut [f32], src: &[f32], weight: f32) {
+    debug_assert_eq!(dst.len(), src.len());
+    #[cfg(target_arch = "x86_64")]
+    if avx2_supported() {
+        // Safe because we've just checked the CPU supports it
+        unsafe { avx2::add_scaled(dst, src, weight) };
+        return;
+    }
+    scalar::add_scaled(dst, src, weight);
+}
+
+/// Normalises (-dx, up, -dz) for each pair of gradients
+pub fn normals_from_gradients(dx: &[f32], dz: &[f32], up: f32, out: &mut [Vec3]) {
+    debug_assert!(dx.len() == out.len() && dz.len() == out.len());
+    #[cfg(target_arch = "x86_64")]
+    if avx2_supported() {
+        unsafe { avx2::normals_from_gradients(dx, dz, up, out) };
+        return;
+    }
+    scalar::normals_from_gradients(dx, dz, up, out);
+}
+
+pub mod scalar {
+    use glam::Vec3;
+
+    pub fn add_scaled(dst: &mut [f32], src: &[f32], weight: f32) {
+        for (d, &s) in dst.iter_mut().zip(src) {
+            *d += s * weight;
+        }
+    }
+
+    pub fn normals_from_gradients(dx: &[f32], dz: &[f32], up: f32, out: &mut [Vec3]) {
+        for ((normal, &dx), &dz) in out.iter_mut().zip(dx).zip(dz) {
+            // Spelled out rather than normalize() to match the SIMD version exactly
+            let inv_length = 1.0 / ((dx * dx + up * up) + dz * dz).sqrt();
+            *normal = Vec3::new(-dx * inv_length, up * inv_length, -dz * inv_length);
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod avx2 {
+    use std::arch::x86_64::*;
+
+    use glam::Vec3;
+
+    const LANES: usize = 8;
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn add_scaled(dst: &mut [f32], src: &[f32], weight: f32) {
+        let n = dst.len().min(src.len());
+        let w = _mm256_set1_ps(weight);
+        let mut i = 0;
+        while i + LANES <= n {
+            let d = _mm256_loadu_ps(dst.as_ptr().add(i));
+            let s = _mm256_loadu_ps(src.as_ptr().add(i));
+            _mm256_storeu_ps(
+                dst.as_mut_ptr().add(i),
+                _mm256_add_ps(d, _mm256_mul_ps(s, w)),
+            );
+            i += LANES;
+        }
+        super::scalar::add_scaled(&mut dst[i..n], &src[i..n], weight);
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn normals_from_gradients(dx: &[f32], dz: &[f32], up: f32, out: &mut [Vec3]) {
+        let n = out.len();
+        let up_scalar = up;
+        let up = _mm256_set1_ps(up);
+        let up_squared = _mm256_mul_ps(up, up);
+        let one = _mm256_set1_ps(1.0);
+        let sign = _mm256_set1_ps(-0.0);
+
+        let mut x = [0.0f32; LANES];
+        let mut y = [0.0f32; LANES];
+        let mut z = [0.0f32; LANES];
+        let mut i = 0;
+        while i + LANES <= n {
+            let gx = _mm256_loadu_ps(dx.as_ptr().add(i));
+            let gz = _mm256_loadu_ps(dz.as_ptr().add(i));
+            let length_squared = _mm256_add_ps(
+                _mm256_add_ps(_mm256_mul_ps(gx, gx), up_squared),
+                _mm256_mul_ps(gz, gz),
+            );
+            let inv_length = _mm256_div_ps(one, _mm256_sqrt_ps(length_squared));
+            let neg_inv_length = _mm256_xor_ps(inv_length, sign);
+
+            _mm256_storeu_ps(x.as_mut_ptr(), _mm256_mul_ps(gx, neg_inv_length));
+            _mm256_storeu_ps(y.as_mut_ptr(), _mm256_mul_ps(up, inv_length));
+            _mm256_storeu_ps(z.as_mut_ptr(), _mm256_mul_ps(gz, neg_inv_length));
+            for (lane, normal) in out[i..i + LANES].iter_mut().enumerate() {
+                *normal = Vec3::new(x[lane], y[lane], z[lane]);
+            }
+            i += LANES;
+        }
+        super::scalar::normals_from_gradients(&dx[i..], &dz[i..], up_scalar, &mut out[i..]);
+    }
+}
+
+#[cfg(all(test, target_arch = "x86_64"))]
+mod tests {
+    use super::*;
+
+    /// xorshift, spread over -range..range
+    fn random_floats(seed: &mut u32, count: usize, range: f32) -> Vec<f32> {
+        (0..count)
+            .map(|_| {
+                *seed ^= *seed << 13;
+                *seed ^= *seed >> 17;
+                *seed ^= *seed << 5;
+                (*seed as f32 / u32::MAX as f32 * 2.0 - 1.0) * range
+            })
+            .collect()
+    }
+
+    fn lengths() -> impl Iterator<Item = usize> {
+        (0..=33).chain([255, 1001])
+    }
+
+    #[test]
+    fn avx2_add_scaled_matches_scalar_bitwise() {
+        if !is_x86_feature_detected!("avx2") {
+            return;
+        }
+        let mut seed = 0x2545_f491;
+        for n in lengths() {
+            let dst = random_floats(&mut seed, n, 1000.0);
+            let src = random_floats(&mut seed, n, 1000.0);
+            let weight = random_floats(&mut seed, 1, 10.0)[0];
+            let mut scalar = dst.clone();
+            scalar::add_scaled(&mut scalar, &src, weight);
+            let mut vector = dst;
+            unsafe { avx2::add_scaled(&mut vector, &src, weight) };
+            let bits = |v: &[f32]| v.iter().map(|f| f.to_bits()).collect::<Vec<_>>();
+            assert_eq!(bits(&scalar), bits(&vector), "length {}", n);
+        }
+    }
+
+    #[test]
+    fn avx2_normals_match_scalar_bitwise() {
+        if !is_x86_feature_detected!("avx2") {
+            return;
+        }
+        let mut seed = 0x9e37_79b9;
+        for n in lengths() {
+            let dx = random_floats(&mut seed, n, 100.0);
+            let dz = random_floats(&mut seed, n, 100.0);
+            let up = random_floats(&mut seed, 1, 10.0)[0].abs() + 0.01;
+            let mut scalar = vec![Vec3::ZERO; n];
+            scalar::normals_from_gradients(&dx, &dz, up, &mut scalar);
+            let mut vector = vec![Vec3::ZERO; n];
+            unsafe { avx2::normals_from_gradients(&dx, &dz, up, &mut vector) };
+            let bits = |v: &[Vec3]| {
+                v.iter()
+                    .flat_map(|n| n.to_array())
+                    .map(f32::to_bits)
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(bits(&scalar), bits(&vector), "length {}", n);
+        }
+    }
+}
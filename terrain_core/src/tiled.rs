@@ -0,0 +1,158 @@
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::heightfield::{EdgeFill, HeightField};
+
+/// Large maps are split into tiles which are processed in parallel.
+/// Tiles overlap so that operations which look at neighbours
+/// see the same data near the seams, and the overlaps are cross-faded.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TileSettings {
+    pub tile_size: usize,
+    /// Extra texels on each side of a tile. Should be at least
+    /// as far as the operation can move data, otherwise seams show up.
+    pub overlap: usize,
+}
+
+impl Default for TileSettings {
+    fn default() -> Self {
+        TileSettings {
+            tile_size: 512,
+            overlap: 32,
+        }
+    }
+}
+
+/// Runs `op` on every tile and stitches the results back together.
+/// Only one row of tiles is kept in memory at a time.
+pub fn process_tiled<F>(field: &HeightField, settings: TileSettings, op: F) -> HeightField
+where
+    F: Fn(&mut HeightField) + Sync,
+{
+    let size = field.size();
+    let tile_size = settings.tile_size.clamp(1, size);
+    let overlap = settings.overlap;
+    let extended_size = tile_size + overlap * 2;
+    let tiles_per_side = size.div_ceil(tile_size);
+
+    let mut sum = vec![0.0f32; size * size];
+    let mut total_weight = vec![0.0f32; size * size];
+
+    for tile_y in 0..tiles_per_side {
+        let row: Vec<(isize, isize, HeightField)> = (0..tiles_per_side)
+            .into_par_iter()
+            .map(|tile_x| {
+                let x0 = (tile_x * tile_size) as isize - overlap as isize;
+                let y0 = (tile_y * tile_size) as isize - overlap as isize;
+                let mut tile = field.region(x0, y0, extended_size, EdgeFill::Clamp);
+                op(&mut tile);
+                (x0, y0, tile)
+            })
+            .collect();
+
+        for (x0, y0, tile) in row {
+            for ty in 0..extended_size {
+                let y = y0 + ty as isize;
+                if y < 0 || y >= size as isize {
+                    continue;
+                }
+                for tx in 0..extended_size {
+                    let x = x0 + tx as isize;
+                    if x < 0 || x >= size as isize {
+                        continue;
+                    }
+                    let w =
+                        feather(tx, extended_size, overlap) * feather(ty, extended_size, overlap);
+                    let i = y as usize * size + x as usize;
+                    sum[i] += tile.data()[ty * extended_size + tx] * w;
+                    total_weight[i] += w;
+                }
+            }
+        }
+    }
+
+    let data = sum
+        .iter()
+        .zip(total_weight.iter())
+        .map(|(&s, &w)| s / w)
+        .collect();
+    HeightField::from_data(size, data)
+}
+
+/// Weight ramps up across the overlap and is 1 inside the tile proper,
+/// so every texel gets full weight from the tile which owns it
+fn feather(i: usize, extended_size: usize, overlap: usize) -> f32 {
+    let distance_to_edge = i.min(extended_size - 1 - i);
+    ((distance_to_edge + 1) as f32 / (overlap + 1) as f32).min(1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filters::blur;
+    use crate::heightfield::EdgeMode;
+    use crate::noise::fbm;
+
+    fn noise_field(size: usize) -> HeightField {
+        let data = (0..size * size)
+            .map(|i| fbm(7, (i % size) as f32 / 16.0, (i / size) as f32 / 16.0, 5))
+            .collect();
+        HeightField::from_data(size, data)
+    }
+
+    fn worst_difference(a: &HeightField, b: &HeightField) -> f32 {
+        a.data()
+            .iter()
+            .zip(b.data())
+            .map(|(a, b)| (a - b).abs())
+            .fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn tiles_that_change_nothing_stitch_back_to_the_original() {
+        let field = noise_field(100);
+        // Tiles that don't divide the map, so the last ones hang over the edge
+        let settings = TileSettings {
+            tile_size: 32,
+            overlap: 8,
+        };
+        let stitched = process_tiled(&field, settings, |_| {});
+        assert!(worst_difference(&stitched, &field) < 1e-6);
+    }
+
+    #[test]
+    fn per_texel_operations_have_no_seams() {
+        let field = noise_field(96);
+        let settings = TileSettings {
+            tile_size: 24,
+            overlap: 0,
+        };
+        let stitched = process_tiled(&field, settings, |tile| {
+            tile.data_mut().iter_mut().for_each(|h| *h = *h * 0.5 + 0.1);
+        });
+        for (&h, &original) in stitched.data().iter().zip(field.data()) {
+            assert!((h - (original * 0.5 + 0.1)).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn enough_overlap_hides_the_seams_of_a_blur() {
+        let field = noise_field(128);
+        let whole = blur(&field, 4, EdgeMode::Clamp);
+        let tiled = |overlap| {
+            let settings = TileSettings {
+                tile_size: 32,
+                overlap,
+            };
+            process_tiled(&field, settings, |tile| {
+                *tile = blur(tile, 4, EdgeMode::Clamp)
+            })
+        };
+        let without_overlap = worst_difference(&tiled(0), &whole);
+        let with_overlap = worst_difference(&tiled(16), &whole);
+        // Texels near the outside of a tile still see its clamped edge, but they fade
+        // out across the overlap
+        assert!(with_overlap < 5e-3, "{}", with_overlap);
+        assert!(with_overlap < without_overlap / 10.0);
+    }
+}
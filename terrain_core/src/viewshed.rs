@@ -0,0 +1,72 @@
+//! What can be seen from a point, for placing towers and lookouts or checking what a
+//! player sees from a spawn. Sight lines go from the observer to every cell on the
+//! border of a grid laid on the heightfield, and a cell along one is visible when
+//! nothing nearer on it rises higher above the horizontal (the R2 viewshed).
+
+use glam::Vec2;
+use serde::{Deserialize, Serialize};
+
+use crate::heightfield::HeightField;
+use crate::scatter::Surface;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ViewshedSettings {
+    /// Meters above the ground the observer's eyes are
+    pub observer_height: f32,
+    /// Grid cells along a side of the terrain, never finer than the heightfield
+    pub resolution: usize,
+}
+
+impl Default for ViewshedSettings {
+    fn default() -> Self {
+        ViewshedSettings {
+            observer_height: 2.0,
+            resolution: 1024,
+        }
+    }
+}
+
+/// 1 where the ground can be seen from `observer`, 0 where it can't. The observer is
+/// in meters from the minimum corner of the terrain and is moved onto it if it's off.
+pub fn viewshed(surface: &Surface, observer: Vec2, settings: &ViewshedSettings) -> HeightField {
+    let cells = settings.resolution.clamp(2, surface.field.size());
+    let cell_size = surface.world_size / cells as f32;
+    let heights = surface.grid_heights(cells);
+    let last = (cells - 1) as f32;
+    let cell_at = |point: Vec2| {
+        let cell = point.floor().clamp(Vec2::ZERO, Vec2::splat(last));
+        cell.y as usize * cells + cell.x as usize
+    };
+    // In cells from here on
+    let observer = (observer / cell_size).clamp(Vec2::ZERO, Vec2::splat(cells as f32));
+    let eye = heights[cell_at(observer)] + settings.observer_height;
+
+    let mut visible = vec![0.0; cells * cells];
+    visible[cell_at(observer)] = 1.0;
+    let border = (0..cells).flat_map(|i| {
+        let i = i as f32;
+        [
+            Vec2::new(i, 0.0),
+            Vec2::new(i, last),
+            Vec2::new(0.0, i),
+            Vec2::new(last, i),
+        ]
+    });
+    for target in border {
+        let to_target = target + Vec2::splat(0.5) - observer;
+        // One cell at a time along whichever axis the line goes further in
+        let steps = to_target.abs().max_element().ceil() as usize;
+        let mut highest = f32::NEG_INFINITY;
+        for step in 1..=steps {
+            let offset = to_target * (step as f32 / steps as f32);
+            let cell = cell_at(observer + offset);
+            // Rise over run of the line from the eye to the ground here
+            let angle = (heights[cell] - eye) / (offset.length() * cell_size);
+            if angle >= highest {
+                visible[cell] = 1.0;
+                highest = angle;
+            }
+        }
+    }
+    HeightField::from_data(cells, visible)
+}
@@ -0,0 +1,177 @@
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::heightfield::HeightField;
+
+const SQRT_2: f32 = std::f32::consts::SQRT_2;
+
+/// Texels searched in each direction for data to interpolate from. Cells further
+/// inside a void are filled on another pass from the ones around them.
+const MAX_SEARCH_DISTANCE: usize = 128;
+
+/// Smoothing steps at every level of the Laplacian fill
+const RELAX_ITERATIONS: usize = 32;
+
+const DIRECTIONS: [(isize, isize); 8] = [
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+    (-1, 0),
+    (1, 0),
+    (-1, 1),
+    (0, 1),
+    (1, 1),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum VoidFill {
+    /// Weighted by how close the nearest data is in 8 directions, like GDAL's
+    /// fill nodata. Keeps more of the surrounding slopes, can leave faint streaks.
+    InverseDistance,
+    /// The smoothest surface that meets the edges of the void, like a soap film
+    Laplacian,
+}
+
+impl VoidFill {
+    pub const ALL: [VoidFill; 2] = [VoidFill::InverseDistance, VoidFill::Laplacian];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            VoidFill::InverseDistance => "Inverse distance",
+            VoidFill::Laplacian => "Laplacian",
+        }
+    }
+}
+
+/// `voids` marks texels without data, whatever heights they have are ignored.
+/// With no data at all the result is flat at 0.
+pub fn fill_voids(field: &HeightField, voids: &[bool], method: VoidFill) -> HeightField {
+    let size = field.size();
+    assert_eq!(voids.len(), size * size);
+    let mut heights = field.data().to_vec();
+    let known: Vec<bool> = voids.iter().map(|&void| !void).collect();
+    if !known.contains(&true) {
+        return HeightField::from_data(size, vec![0.0; size * size]);
+    }
+    match method {
+        VoidFill::InverseDistance => inverse_distance_fill(&mut heights, known, size),
+        VoidFill::Laplacian => laplacian_fill(&mut heights, &known, size),
+    }
+    HeightField::from_data(size, heights)
+}
+
+/// 1 where a texel is a void, for showing what was filled
+pub fn void_mask(voids: &[bool]) -> HeightField {
+    let size = (voids.len() as f64).sqrt() as usize;
+    let data = voids
+        .iter()
+        .map(|&void| if void { 1.0 } else { 0.0 })
+        .collect();
+    HeightField::from_data(size, data)
+}
+
+fn inverse_distance_fill(heights: &mut [f32], mut known: Vec<bool>, size: usize) {
+    while known.contains(&false) {
+        let filled: Vec<Option<f32>> = (0..size * size)
+            .into_par_iter()
+            .map(|i| {
+                if known[i] {
+                    return None;
+                }
+                let (x, y) = ((i % size) as isize, (i / size) as isize);
+                let mut total = 0.0;
+                let mut weights = 0.0;
+                for (dx, dy) in DIRECTIONS {
+                    let step = if dx != 0 && dy != 0 { SQRT_2 } else { 1.0 };
+                    for distance in 1..=MAX_SEARCH_DISTANCE as isize {
+                        let (nx, ny) = (x + dx * distance, y + dy * distance);
+                        if nx < 0 || ny < 0 || nx >= size as isize || ny >= size as isize {
+                            break;
+                        }
+                        let n = ny as usize * size + nx as usize;
+                        if known[n] {
+                            let weight = 1.0 / (distance as f32 * step).powi(2);
+                            total += heights[n] * weight;
+                            weights += weight;
+                            break;
+                        }
+                    }
+                }
+                if weights > 0.0 {
+                    Some(total / weights)
+                } else {
+                    None
+                }
+            })
+            .collect();
+        for (i, value) in filled.into_iter().enumerate() {
+            if let Some(value) = value {
+                heights[i] = value;
+                known[i] = true;
+            }
+        }
+    }
+}
+
+/// Coarse to fine: the void is filled from a half resolution copy first, so big voids
+/// don't take thousands of smoothing steps to fill in
+fn laplacian_fill(heights: &mut [f32], known: &[bool], size: usize) {
+    if size > 1 {
+        let coarse_size = size.div_ceil(2);
+        let mut sums = vec![0.0f32; coarse_size * coarse_size];
+        let mut counts = vec![0u32; coarse_size * coarse_size];
+        for i in 0..size * size {
+            if known[i] {
+                let (x, y) = (i % size, i / size);
+                let coarse = (y / 2) * coarse_size + x / 2;
+                sums[coarse] += heights[i];
+                counts[coarse] += 1;
+            }
+        }
+        let coarse_known: Vec<bool> = counts.iter().map(|&count| count > 0).collect();
+        let mut coarse: Vec<f32> = sums
+            .iter()
+            .zip(&counts)
+            .map(|(&sum, &count)| if count > 0 { sum / count as f32 } else { 0.0 })
+            .collect();
+        laplacian_fill(&mut coarse, &coarse_known, coarse_size);
+        for i in 0..size * size {
+            if !known[i] {
+                let (x, y) = (i % size, i / size);
+                heights[i] = coarse[(y / 2) * coarse_size + x / 2];
+            }
+        }
+    }
+    relax(heights, known, size);
+}
+
+/// Gauss-Seidel: every void texel becomes the average of its neighbours
+fn relax(heights: &mut [f32], known: &[bool], size: usize) {
+    let voids: Vec<usize> = (0..size * size).filter(|&i| !known[i]).collect();
+    for _ in 0..RELAX_ITERATIONS {
+        for &i in &voids {
+            let (x, y) = (i % size, i / size);
+            let mut total = 0.0;
+            let mut count = 0.0;
+            if x > 0 {
+                total += heights[i - 1];
+                count += 1.0;
+            }
+            if x + 1 < size {
+                total += heights[i + 1];
+                count += 1.0;
+            }
+            if y > 0 {
+                total += heights[i - size];
+                count += 1.0;
+            }
+            if y + 1 < size {
+                total += heights[i + size];
+                count += 1.0;
+            }
+            if count > 0.0 {
+                heights[i] = total / count;
+            }
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use glam::Vec3;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::filters::blur;
+use crate::heightfield::{EdgeMode, HeightField};
+use crate::math;
+
+const SQRT_2: f32 = std::f32::consts::SQRT_2;
+
+const NEIGHBOURS: [(isize, isize); 8] = [
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+    (-1, 0),
+    (1, 0),
+    (-1, 1),
+    (0, 1),
+    (1, 1),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct WeatheringSettings {
+    /// Texels around a point that decide how sunken it is
+    pub cavity_radius: usize,
+    /// How much the deepest crevices are darkened, 0 to 1
+    pub cavity_darkening: f32,
+    /// Linear RGB of what water leaves behind where it gathers
+    pub sediment_color: [f32; 3],
+    /// 0 to 1
+    pub sediment_amount: f32,
+}
+
+impl Default for WeatheringSettings {
+    fn default() -> Self {
+        WeatheringSettings {
+            cavity_radius: 4,
+            cavity_darkening: 0.5,
+            sediment_color: [0.55, 0.45, 0.35],
+            sediment_amount: 0.6,
+        }
+    }
+}
+
+/// 0 on ridges and slopes, up to 1 in crevices as deep as they are wide.
+/// `texel_size` and `max_height` are in meters.
+pub fn cavity(field: &HeightField, radius: usize, texel_size: f32, max_height: f32) -> HeightField {
+    let radius = radius.max(1);
+    let surroundings = blur(field, radius, EdgeMode::Clamp);
+    // Meters sunken below the surroundings per meter of surroundings
+    let scale = max_height / (texel_size * radius as f32);
+    let data = field
+        .data()
+        .par_iter()
+        .zip(surroundings.data())
+        .map(|(&h, &around)| ((around - h) * scale).clamp(0.0, 1.0))
+        .collect();
+    HeightField::from_data(field.size(), data)
+}
+
+/// Where rain would gather: every texel sheds one unit of water, split between its
+/// lower neighbours by how much lower they are. Logarithmic, 0 on peaks and 1 where
+/// the most water ends up.
+pub fn flow_accumulation(field: &HeightField) -> HeightField {
+    let size = field.size();
+    let heights = field.data();
+    // Highest first, so all the water from above has arrived before a texel passes it on
+    let mut order: Vec<usize> = (0..size * size).collect();
+    order.par_sort_unstable_by(|&a, &b| heights[b].total_cmp(&heights[a]));
+
+    let mut water = vec![1.0f32; size * size];
+    for &i in &order {
+        let (x, y) = ((i % size) as isize, (i / size) as isize);
+        let mut drops = [(0, 0.0); 8];
+        let mut count = 0;
+        let mut total = 0.0;
+        for (dx, dy) in NEIGHBOURS {
+            let (nx, ny) = (x + dx, y + dy);
+            if nx < 0 || ny < 0 || nx >= size as isize || ny >= size as isize {
+                continue;
+            }
+            let n = ny as usize * size + nx as usize;
+            // Diagonal neighbours are further away
+            let distance = if dx != 0 && dy != 0 { SQRT_2 } else { 1.0 };
+            let drop = (heights[i] - heights[n]) / distance;
+            if drop > 0.0 {
+                drops[count] = (n, drop);
+                count += 1;
+                total += drop;
+            }
+        }
+        let amount = water[i];
+        for &(n, drop) in &drops[..count] {
+            water[n] += amount * drop / total;
+        }
+    }
+
+    let most = water.iter().copied().fold(1.0f32, f32::max);
+    let max_ln = math::ln(most).max(f32::EPSILON);
+    let data = water.par_iter().map(|&w| math::ln(w) / max_ln).collect();
+    HeightField::from_data(size, data)
+}
+
+/// What to multiply the terrain color by: crevices darkened and sediment along
+/// the paths water takes
+pub fn weathering_tint(
+    field: &HeightField,
+    settings: &WeatheringSettings,
+    texel_size: f32,
+    max_height: f32,
+) -> Vec<Vec3> {
+    let cavity = cavity(field, settings.cavity_radius, texel_size, max_height);
+    let flow = flow_accumulation(field);
+    let sediment = Vec3::from(settings.sediment_color);
+    let amount = settings.sediment_amount.clamp(0.0, 1.0);
+    let darkening = settings.cavity_darkening.clamp(0.0, 1.0);
+    cavity
+        .data()
+        .par_iter()
+        .zip(flow.data())
+        .map(|(&cavity, &flow)| {
+            let shade = 1.0 - darkening * cavity;
+            Vec3::ONE.lerp(sediment, flow * amount) * shade
+        })
+        .collect()
+}